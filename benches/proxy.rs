@@ -0,0 +1,98 @@
+//! Benchmarks for the per-request proxy path
+//!
+//! Run with `cargo bench`. The round-trip benchmark sends requests through
+//! the connection pool to an in-process backend on a loopback port.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use http_body_util::{BodyExt, Empty, Full};
+use hyper::body::{Bytes, Incoming};
+use hyper::service::service_fn;
+use hyper::{Request, Response};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto::Builder as AutoBuilder;
+use spawngate::config::{BackendConfig, BackendDefaults};
+use spawngate::pool::{backend_request, BackendProtocol, ConnectionPool, PoolConfig};
+use spawngate::process::ProcessManager;
+use std::collections::HashMap;
+use std::convert::Infallible;
+
+/// A request with headers typical for proxied browser traffic
+fn sample_request() -> Request<Empty<Bytes>> {
+    Request::builder()
+        .uri("/api/items?page=2&sort=name")
+        .header("host", "app.example.com")
+        .header("user-agent", "Mozilla/5.0 (X11; Linux x86_64; rv:131.0) Gecko/20100101 Firefox/131.0")
+        .header("accept", "application/json")
+        .header("accept-encoding", "gzip, deflate, br")
+        .header("accept-language", "en-US,en;q=0.5")
+        .header("cookie", "session=0123456789abcdef; theme=dark")
+        .header("x-request-id", "6f9c1a52-4e1b-4a8e-9a0e-2f4b7d3c8e11")
+        .header("x-forwarded-for", "203.0.113.7")
+        .header("x-forwarded-host", "app.example.com")
+        .header("x-forwarded-proto", "https")
+        .body(Empty::new())
+        .unwrap()
+}
+
+fn bench_backend_request(c: &mut Criterion) {
+    c.bench_function("backend_request", |b| {
+        b.iter_batched(
+            sample_request,
+            |req| backend_request(req, 3000).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn bench_upstream_target(c: &mut Criterion) {
+    let mut backend = BackendConfig::local("node", 3000);
+    backend.args = vec!["server.js".to_string()];
+    backend.env = HashMap::from([("NODE_ENV".to_string(), "production".to_string())]);
+    backend.labels = HashMap::from([("env".to_string(), "prod".to_string())]);
+    let defaults = BackendDefaults::default();
+    let manager = ProcessManager::new(
+        HashMap::from([("app.example.com".to_string(), backend)]),
+        defaults.clone(),
+        String::new(),
+    );
+
+    c.bench_function("upstream_target", |b| {
+        b.iter(|| manager.upstream_target("app.example.com", &defaults).unwrap())
+    });
+}
+
+fn bench_pool_round_trip(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let port = runtime.block_on(async {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let Ok((stream, _)) = listener.accept().await else { break };
+                tokio::spawn(async move {
+                    let service = service_fn(|_req: Request<Incoming>| async {
+                        Ok::<_, Infallible>(Response::new(Full::new(Bytes::from_static(b"ok"))))
+                    });
+                    let _ = AutoBuilder::new(TokioExecutor::new())
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+        port
+    });
+    let pool = ConnectionPool::new(PoolConfig::default());
+
+    c.bench_function("pool_round_trip", |b| {
+        b.iter(|| {
+            runtime.block_on(async {
+                let req = sample_request().map(|body| body.map_err(|never| match never {}).boxed());
+                let response = pool.send_request(req, port, BackendProtocol::Http1).await.unwrap();
+                response.into_body().collect().await.unwrap()
+            })
+        })
+    });
+}
+
+criterion_group!(benches, bench_backend_request, bench_upstream_target, bench_pool_round_trip);
+criterion_main!(benches);
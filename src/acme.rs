@@ -3,6 +3,8 @@
 //! Supports automatic certificate provisioning using:
 //! - HTTP-01 challenge (serves token at /.well-known/acme-challenge/)
 //! - TLS-ALPN-01 challenge (serves certificate with acme-tls/1 ALPN)
+//! - DNS-01 challenge (publishes a TXT record through a [`DnsProvider`]),
+//!   required for wildcard domains
 //!
 //! # Security Considerations
 //!
@@ -17,9 +19,11 @@
 //! - Back up the cache directory securely (it contains your ACME account key)
 
 use crate::config::{AcmeChallengeType, AcmeConfig};
+use crate::dns::{self, DnsProvider};
+use crate::preflight;
 use instant_acme::{
     Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, LetsEncrypt,
-    NewAccount, NewOrder, OrderStatus,
+    NewAccount, NewOrder, Order, OrderStatus,
 };
 use rcgen::{CertificateParams, DistinguishedName, DnType, KeyPair, PKCS_ECDSA_P256_SHA256};
 use rustls::pki_types::{CertificateDer, PrivateKeyDer};
@@ -31,9 +35,10 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::{watch, RwLock};
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
-const ACME_TLS_ALPN_NAME: &[u8] = b"acme-tls/1";
+/// ALPN protocol of TLS-ALPN-01 validation connections (RFC 8737)
+pub const ACME_TLS_ALPN_NAME: &[u8] = b"acme-tls/1";
 const ACME_ALPN_OID: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 1, 31];
 
 /// Pending ACME challenges for HTTP-01 validation
@@ -61,9 +66,12 @@ impl Http01Challenges {
 }
 
 /// TLS-ALPN-01 challenge certificate resolver
+///
+/// Uses synchronous locks because rustls resolves certificates from inside
+/// the handshake, where blocking on an async lock would panic.
 pub struct TlsAlpn01Resolver {
-    challenge_certs: Arc<RwLock<HashMap<String, Arc<CertifiedKey>>>>,
-    regular_cert: Arc<RwLock<Option<Arc<CertifiedKey>>>>,
+    challenge_certs: Arc<parking_lot::RwLock<HashMap<String, Arc<CertifiedKey>>>>,
+    regular_cert: Arc<parking_lot::RwLock<Option<Arc<CertifiedKey>>>>,
 }
 
 impl std::fmt::Debug for TlsAlpn01Resolver {
@@ -84,32 +92,29 @@ impl Default for TlsAlpn01Resolver {
 impl TlsAlpn01Resolver {
     pub fn new() -> Self {
         Self {
-            challenge_certs: Arc::new(RwLock::new(HashMap::new())),
-            regular_cert: Arc::new(RwLock::new(None)),
+            challenge_certs: Arc::new(parking_lot::RwLock::new(HashMap::new())),
+            regular_cert: Arc::new(parking_lot::RwLock::new(None)),
         }
     }
 
     pub async fn set_challenge_cert(&self, domain: &str, cert: Arc<CertifiedKey>) {
-        self.challenge_certs
-            .write()
-            .await
-            .insert(domain.to_string(), cert);
+        self.challenge_certs.write().insert(domain.to_string(), cert);
     }
 
     pub async fn remove_challenge_cert(&self, domain: &str) {
-        self.challenge_certs.write().await.remove(domain);
+        self.challenge_certs.write().remove(domain);
     }
 
     pub async fn set_regular_cert(&self, cert: Arc<CertifiedKey>) {
-        *self.regular_cert.write().await = Some(cert);
+        *self.regular_cert.write() = Some(cert);
     }
 
     fn get_challenge_cert_sync(&self, domain: &str) -> Option<Arc<CertifiedKey>> {
-        self.challenge_certs.blocking_read().get(domain).cloned()
+        self.challenge_certs.read().get(domain).cloned()
     }
 
     fn get_regular_cert_sync(&self) -> Option<Arc<CertifiedKey>> {
-        self.regular_cert.blocking_read().clone()
+        self.regular_cert.read().clone()
     }
 }
 
@@ -142,6 +147,7 @@ pub struct AcmeManager {
     cache_dir: PathBuf,
     http01_challenges: Http01Challenges,
     tls_alpn01_resolver: Arc<TlsAlpn01Resolver>,
+    dns_provider: Option<Arc<dyn DnsProvider>>,
     current_cert: Arc<RwLock<StoredCert>>,
     cert_tx: watch::Sender<Option<Arc<CertifiedKey>>>,
     cert_rx: watch::Receiver<Option<Arc<CertifiedKey>>>,
@@ -149,19 +155,31 @@ pub struct AcmeManager {
 
 impl AcmeManager {
     pub fn new(config: AcmeConfig) -> Result<Self, anyhow::Error> {
-        let cache_dir = validate_cache_dir(&config.cache_dir)?;
+        let mut cache_dir = validate_cache_dir(&config.cache_dir)?;
+        // Keep the staging account and untrusted certificates apart from production ones
+        if config.staging {
+            cache_dir = cache_dir.join("staging");
+        }
+        let dns_provider = config.dns.as_ref().map(dns::provider).transpose()?;
         let (cert_tx, cert_rx) = watch::channel(None);
         Ok(Self {
             config,
             cache_dir,
             http01_challenges: Http01Challenges::new(),
             tls_alpn01_resolver: Arc::new(TlsAlpn01Resolver::new()),
+            dns_provider,
             current_cert: Arc::new(RwLock::new(None)),
             cert_tx,
             cert_rx,
         })
     }
 
+    /// Publish DNS-01 records through `provider` instead of the configured one
+    pub fn with_dns_provider(mut self, provider: Arc<dyn DnsProvider>) -> Self {
+        self.dns_provider = Some(provider);
+        self
+    }
+
     pub fn http01_challenges(&self) -> Http01Challenges {
         self.http01_challenges.clone()
     }
@@ -187,23 +205,21 @@ impl AcmeManager {
         }
 
         info!("Creating new ACME account");
-        let email = self.config.email.as_ref().ok_or_else(|| {
-            anyhow::anyhow!("ACME email is required for account creation")
-        })?;
-
-        let directory_url = self
-            .config
-            .directory_url
-            .as_deref()
-            .unwrap_or(LetsEncrypt::Production.url());
+        // Staging accounts are throwaway, so a contact address is optional there
+        let contact = match &self.config.email {
+            Some(email) => vec![format!("mailto:{}", email)],
+            None if self.config.staging => Vec::new(),
+            None => anyhow::bail!("ACME email is required for account creation"),
+        };
+        let contact: Vec<&str> = contact.iter().map(String::as_str).collect();
 
         let (account, credentials) = Account::create(
             &NewAccount {
-                contact: &[&format!("mailto:{}", email)],
+                contact: &contact,
                 terms_of_service_agreed: true,
                 only_return_existing: false,
             },
-            directory_url,
+            directory_url(&self.config),
             None,
         )
         .await?;
@@ -282,6 +298,31 @@ impl AcmeManager {
         Ok(())
     }
 
+    /// Check every domain's DNS and CAA records, reporting all problems at once
+    ///
+    /// Catches misconfigurations before they surface as opaque challenge
+    /// failures that also count against the CA's failed-validation limit.
+    async fn preflight(&self) -> anyhow::Result<()> {
+        let identity = preflight::caa_identity(directory_url(&self.config));
+        let mut problems = Vec::new();
+        for domain in &self.config.domains {
+            let result = match (&self.config.challenge_type, identity) {
+                // The CA only queries DNS, so the domain needn't point at this server
+                (AcmeChallengeType::Dns01, Some(identity)) => preflight::check_caa(domain, identity).await,
+                (AcmeChallengeType::Dns01, None) => Ok(()),
+                _ => preflight::check_domain(domain, &self.config.expected_ips, identity).await,
+            };
+            if let Err(e) = result {
+                problems.push(e);
+            }
+        }
+        if !problems.is_empty() {
+            anyhow::bail!("ACME pre-flight checks failed: {}", problems.join("; "));
+        }
+        debug!(domains = ?self.config.domains, "ACME pre-flight checks passed");
+        Ok(())
+    }
+
     /// Obtain a new certificate via ACME
     async fn obtain_certificate(
         &self,
@@ -294,6 +335,10 @@ impl AcmeManager {
             .map(|d| Identifier::Dns(d.clone()))
             .collect();
 
+        if self.config.preflight {
+            self.preflight().await?;
+        }
+
         info!(domains = ?self.config.domains, "Requesting new certificate");
 
         let mut order = account
@@ -317,6 +362,7 @@ impl AcmeManager {
             let challenge_type = match self.config.challenge_type {
                 AcmeChallengeType::Http01 => ChallengeType::Http01,
                 AcmeChallengeType::TlsAlpn01 => ChallengeType::TlsAlpn01,
+                AcmeChallengeType::Dns01 => ChallengeType::Dns01,
             };
 
             let challenge = authz
@@ -334,6 +380,8 @@ impl AcmeManager {
             let key_auth = order.key_authorization(challenge);
             let key_auth_str = key_auth.as_str().to_string();
             let digest: Vec<u8> = key_auth.digest().as_ref().to_vec();
+            let dns_value = key_auth.dns_value();
+            let record_name = dns::challenge_record_name(&identifier);
 
             match self.config.challenge_type {
                 AcmeChallengeType::Http01 => {
@@ -349,48 +397,25 @@ impl AcmeManager {
                         .set_challenge_cert(&identifier, challenge_cert)
                         .await;
                 }
+                AcmeChallengeType::Dns01 => {
+                    debug!(domain = %identifier, record = %record_name, "Setting up DNS-01 challenge");
+                    self.dns_provider()?.add_txt(&record_name, &dns_value).await?;
+                }
             }
 
-            // Notify ACME server we're ready
-            order.set_challenge_ready(&challenge.url).await?;
-
-            // Wait for authorization to become valid
-            let mut attempts = 0;
-            loop {
-                tokio::time::sleep(Duration::from_secs(2)).await;
-
-                // Refresh the order and get authorizations again
-                order.refresh().await?;
-                let auths = order.authorizations().await?;
-                let current_auth = auths.iter().find(|a| {
-                    matches!(&a.identifier, Identifier::Dns(d) if d == &identifier)
-                });
-
-                match current_auth.map(|a| &a.status) {
-                    Some(AuthorizationStatus::Valid) => {
-                        info!(domain = %identifier, "Authorization valid");
-                        break;
-                    }
-                    Some(AuthorizationStatus::Pending) => {
-                        attempts += 1;
-                        if attempts > 30 {
-                            anyhow::bail!("Authorization timeout for {}", identifier);
-                        }
-                        debug!(domain = %identifier, attempt = attempts, "Waiting for authorization");
-                    }
-                    Some(AuthorizationStatus::Invalid) => {
-                        anyhow::bail!("Authorization failed for {}", identifier);
-                    }
-                    Some(status) => {
-                        debug!(domain = %identifier, status = ?status, "Authorization status");
-                    }
-                    None => {
-                        anyhow::bail!("Authorization not found for {}", identifier);
-                    }
+            // Notify ACME server we're ready, then wait for authorization to become valid
+            let result = async {
+                let dns = self.config.dns.as_ref().filter(|_| self.config.challenge_type == AcmeChallengeType::Dns01);
+                if let Some(dns) = dns {
+                    // Give the record time to reach all of the zone's name servers
+                    tokio::time::sleep(Duration::from_secs(dns.propagation_secs)).await;
                 }
+                order.set_challenge_ready(&challenge.url).await?;
+                wait_for_authorization(&mut order, &identifier).await
             }
+            .await;
 
-            // Clean up challenge
+            // Clean up challenge, also after a failure
             match self.config.challenge_type {
                 AcmeChallengeType::Http01 => {
                     self.http01_challenges.remove(&challenge.token).await;
@@ -398,7 +423,18 @@ impl AcmeManager {
                 AcmeChallengeType::TlsAlpn01 => {
                     self.tls_alpn01_resolver.remove_challenge_cert(&identifier).await;
                 }
+                AcmeChallengeType::Dns01 => {
+                    if let Err(e) = self.dns_provider()?.remove_txt(&record_name, &dns_value).await {
+                        warn!(
+                            domain = %identifier,
+                            record = %record_name,
+                            error = %e,
+                            "Failed to remove DNS-01 record"
+                        );
+                    }
+                }
             }
+            result?;
         }
 
         // Wait for order to be ready
@@ -478,6 +514,12 @@ impl AcmeManager {
         Ok((certs, key, cert_chain_pem, private_key_pem))
     }
 
+    fn dns_provider(&self) -> anyhow::Result<&Arc<dyn DnsProvider>> {
+        self.dns_provider
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("DNS-01 challenge requires a DNS provider"))
+    }
+
     /// Update the current certificate and notify watchers
     async fn update_cert(
         &self,
@@ -513,6 +555,16 @@ impl AcmeManager {
         })
     }
 
+    /// Run a complete order without caching or serving the certificate
+    ///
+    /// Challenges must be served as for `run`. Meant for dry runs against a
+    /// staging directory.
+    pub async fn test_issuance(&self) -> anyhow::Result<()> {
+        let account = self.get_or_create_account().await?;
+        self.obtain_certificate(&account).await?;
+        Ok(())
+    }
+
     /// Run the ACME manager - obtains and renews certificates
     pub async fn run(&self, mut shutdown_rx: watch::Receiver<bool>) -> anyhow::Result<()> {
         // Try to load cached certificate first
@@ -578,6 +630,61 @@ impl AcmeManager {
     }
 }
 
+/// Poll an order until the authorization of `identifier` is valid
+async fn wait_for_authorization(order: &mut Order, identifier: &str) -> anyhow::Result<()> {
+    let mut attempts = 0;
+    loop {
+        tokio::time::sleep(Duration::from_secs(2)).await;
+
+        // Refresh the order and get authorizations again
+        order.refresh().await?;
+        let auths = order.authorizations().await?;
+        let current_auth = auths.iter().find(|a| {
+            matches!(&a.identifier, Identifier::Dns(d) if d == identifier)
+        });
+
+        match current_auth.map(|a| &a.status) {
+            Some(AuthorizationStatus::Valid) => {
+                info!(domain = %identifier, "Authorization valid");
+                return Ok(());
+            }
+            Some(AuthorizationStatus::Pending) => {
+                attempts += 1;
+                if attempts > 30 {
+                    anyhow::bail!("Authorization timeout for {}", identifier);
+                }
+                debug!(domain = %identifier, attempt = attempts, "Waiting for authorization");
+            }
+            Some(AuthorizationStatus::Invalid) => {
+                let problem = current_auth
+                    .into_iter()
+                    .flat_map(|a| &a.challenges)
+                    .find_map(|c| c.error.as_ref());
+                match problem {
+                    Some(problem) => anyhow::bail!("Authorization failed for {}: {}", identifier, problem),
+                    None => anyhow::bail!("Authorization failed for {}", identifier),
+                }
+            }
+            Some(status) => {
+                debug!(domain = %identifier, status = ?status, "Authorization status");
+            }
+            None => {
+                anyhow::bail!("Authorization not found for {}", identifier);
+            }
+        }
+    }
+}
+
+/// Directory URL for a config: `directory_url` if set, else Let's Encrypt
+/// staging or production depending on `staging`
+pub fn directory_url(config: &AcmeConfig) -> &str {
+    match &config.directory_url {
+        Some(url) => url,
+        None if config.staging => LetsEncrypt::Staging.url(),
+        None => LetsEncrypt::Production.url(),
+    }
+}
+
 /// Create a TLS-ALPN-01 challenge certificate
 fn create_tls_alpn01_cert(domain: &str, digest: &[u8]) -> anyhow::Result<Arc<CertifiedKey>> {
     use rcgen::{CustomExtension, IsCa, KeyUsagePurpose};
@@ -747,14 +854,36 @@ mod tests {
             domains: vec!["example.com".to_string()],
             email: Some("admin@example.com".to_string()),
             directory_url: None,
+            staging: false,
             cache_dir: "/tmp/acme_test".to_string(),
             challenge_type: AcmeChallengeType::Http01,
+            dns: None,
+            preflight: true,
+            expected_ips: Vec::new(),
         };
 
         let manager = AcmeManager::new(config).unwrap();
         assert!(manager.http01_challenges.inner.try_read().is_ok());
     }
 
+    #[test]
+    fn test_staging_directory_and_cache() {
+        let mut config = AcmeConfig {
+            cache_dir: "/tmp/acme_test".to_string(),
+            ..AcmeConfig::default()
+        };
+        assert_eq!(directory_url(&config), LetsEncrypt::Production.url());
+
+        config.staging = true;
+        assert_eq!(directory_url(&config), LetsEncrypt::Staging.url());
+        let manager = AcmeManager::new(config.clone()).unwrap();
+        assert!(manager.cache_dir.ends_with("acme_test/staging"));
+
+        // An explicit directory takes precedence
+        config.directory_url = Some("https://acme.example.com/directory".to_string());
+        assert_eq!(directory_url(&config), "https://acme.example.com/directory");
+    }
+
     #[test]
     fn test_validate_cache_dir_rejects_traversal() {
         assert!(validate_cache_dir("../etc/passwd").is_err());
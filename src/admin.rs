@@ -1,13 +1,30 @@
-use crate::process::ProcessManager;
-use http_body_util::Full;
+use crate::apply::{diff_fields, redacted, Applier, ApplyError, FieldChange};
+use crate::certwatch::CertWatcher;
+use crate::config::{redact_secrets, restore_secrets, BackendConfig, Config};
+use crate::configvars::{ConfigVarsError, Vars};
+use crate::experiments::ExperimentMetrics;
+use crate::lockout::AuthLockout;
+use crate::metrics::TlsHandshakeMetrics;
+use crate::process::{BackendChangeError, Precondition, ProcessManager};
+use crate::profiling::{ProfileError, Profiler};
+use crate::registry::DebugRegistry;
+use crate::selector::{Selector, SelectorError};
+use crate::slo::ColdStartSlo;
+use crate::slowlog::SlowRequestLog;
+use crate::supervisor::Supervisor;
+use crate::throttle::ColdStartThrottle;
+use http_body_util::{BodyExt, Full, Limited};
 use hyper::body::Bytes;
-use hyper::header::AUTHORIZATION;
+use hyper::header::{AUTHORIZATION, ETAG, IF_MATCH, IF_NONE_MATCH};
 use hyper::service::service_fn;
 use hyper::{Method, Request, Response, StatusCode};
 use hyper_util::rt::{TokioExecutor, TokioIo};
 use hyper_util::server::conn::auto::Builder as AutoBuilder;
+use parking_lot::RwLock;
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpListener;
 use tokio::sync::watch;
@@ -18,6 +35,15 @@ use tracing::{debug, error, info, warn};
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 pub const PKG_NAME: &str = env!("CARGO_PKG_NAME");
 
+/// Largest accepted config vars update
+const MAX_CONFIG_VARS_BODY: usize = 64 * 1024;
+
+/// Largest backend definition accepted by `PUT /backends/{hostname}`
+const MAX_BACKEND_BODY: usize = 64 * 1024;
+
+/// Largest desired configuration accepted by `POST /apply`
+const MAX_APPLY_BODY: usize = 1024 * 1024;
+
 /// Helper to create a simple response - infallible with valid StatusCode
 fn response(status: StatusCode, body: impl Into<Bytes>) -> Response<Full<Bytes>> {
     Response::builder()
@@ -35,6 +61,23 @@ fn json_response(status: StatusCode, body: impl Into<Bytes>) -> Response<Full<By
         .expect("valid response with StatusCode enum and static header")
 }
 
+/// Optional subsystems whose state the admin API exposes
+#[derive(Clone, Default)]
+struct Subsystems {
+    cold_start_throttle: Option<Arc<ColdStartThrottle>>,
+    cert_watcher: Option<Arc<CertWatcher>>,
+    tls_metrics: Option<Arc<TlsHandshakeMetrics>>,
+    auth_lockout: Option<Arc<AuthLockout>>,
+    slow_log: Option<Arc<SlowRequestLog>>,
+    cold_start_slo: Option<Arc<ColdStartSlo>>,
+    experiment_metrics: Option<Arc<ExperimentMetrics>>,
+    debug_registry: Option<Arc<DebugRegistry>>,
+    supervisor: Option<Arc<Supervisor>>,
+    config: Option<Arc<RwLock<Config>>>,
+    applier: Option<Arc<Applier>>,
+    profiler: Option<Arc<Profiler>>,
+}
+
 /// Admin API server for backend callbacks
 pub struct AdminServer {
     bind_addr: SocketAddr,
@@ -42,6 +85,7 @@ pub struct AdminServer {
     shutdown_rx: watch::Receiver<bool>,
     tls_acceptor: Option<TlsAcceptor>,
     auth_token: Arc<String>,
+    subsystems: Subsystems,
 }
 
 impl AdminServer {
@@ -57,6 +101,7 @@ impl AdminServer {
             shutdown_rx,
             tls_acceptor: None,
             auth_token: Arc::new(auth_token),
+            subsystems: Subsystems::default(),
         }
     }
 
@@ -65,6 +110,83 @@ impl AdminServer {
         self
     }
 
+    /// Expose cold-start throttle counters at `GET /throttle`
+    pub fn with_cold_start_throttle(mut self, throttle: Arc<ColdStartThrottle>) -> Self {
+        self.subsystems.cold_start_throttle = Some(throttle);
+        self
+    }
+
+    /// Expose watched certificate status at `GET /certificates`
+    pub fn with_cert_watcher(mut self, watcher: Arc<CertWatcher>) -> Self {
+        self.subsystems.cert_watcher = Some(watcher);
+        self
+    }
+
+    /// Lock out clients that repeatedly fail authentication; the audit log
+    /// is exposed at `GET /auth/audit`
+    pub fn with_auth_lockout(mut self, lockout: Arc<AuthLockout>) -> Self {
+        self.subsystems.auth_lockout = Some(lockout);
+        self
+    }
+
+    /// Expose TLS handshake counters at `GET /tls/handshakes`
+    pub fn with_tls_metrics(mut self, metrics: Arc<TlsHandshakeMetrics>) -> Self {
+        self.subsystems.tls_metrics = Some(metrics);
+        self
+    }
+
+    /// Expose the slowest endpoints per backend at `GET /slow-requests`
+    pub fn with_slow_log(mut self, slow_log: Arc<SlowRequestLog>) -> Self {
+        self.subsystems.slow_log = Some(slow_log);
+        self
+    }
+
+    /// Expose cold-start SLO attainment and burn rates at `GET /slo`
+    pub fn with_cold_start_slo(mut self, slo: Arc<ColdStartSlo>) -> Self {
+        self.subsystems.cold_start_slo = Some(slo);
+        self
+    }
+
+    /// Expose per-variant metrics of A/B experiments at `GET /experiments`
+    pub fn with_experiment_metrics(mut self, metrics: Arc<ExperimentMetrics>) -> Self {
+        self.subsystems.experiment_metrics = Some(metrics);
+        self
+    }
+
+    /// Expose registered tasks and open connections at `GET /debug/tasks`
+    /// and `GET /debug/connections`
+    pub fn with_debug_registry(mut self, registry: Arc<DebugRegistry>) -> Self {
+        self.subsystems.debug_registry = Some(registry);
+        self
+    }
+
+    /// List restartable subsystems at `GET /subsystems` and restart them at
+    /// `POST /subsystems/{name}/restart` (`POST /acme/restart` for the ACME manager)
+    pub fn with_supervisor(mut self, supervisor: Arc<Supervisor>) -> Self {
+        self.subsystems.supervisor = Some(supervisor);
+        self
+    }
+
+    /// Expose the configuration with defaults and config vars resolved at
+    /// `GET /config/effective`
+    pub fn with_config(mut self, config: Arc<RwLock<Config>>) -> Self {
+        self.subsystems.config = Some(config);
+        self
+    }
+
+    /// Plan and apply desired backends at `POST /apply`
+    pub fn with_applier(mut self, applier: Arc<Applier>) -> Self {
+        self.subsystems.applier = Some(applier);
+        self
+    }
+
+    /// Serve CPU flamegraphs at `GET /debug/pprof/profile` and heap profiles
+    /// at `GET /debug/pprof/heap`
+    pub fn with_profiler(mut self, profiler: Arc<Profiler>) -> Self {
+        self.subsystems.profiler = Some(profiler);
+        self
+    }
+
     pub fn tls_enabled(&self) -> bool {
         self.tls_acceptor.is_some()
     }
@@ -75,12 +197,19 @@ impl AdminServer {
 
     pub async fn run(self) -> anyhow::Result<()> {
         let listener = TcpListener::bind(self.bind_addr).await?;
+        self.serve(listener).await
+    }
+
+    /// Serve connections from an already-bound listener
+    pub async fn serve(self, listener: TcpListener) -> anyhow::Result<()> {
+        let local_addr = listener.local_addr()?;
         let protocol = if self.tls_acceptor.is_some() { "HTTPS" } else { "HTTP" };
-        info!(addr = %self.bind_addr, protocol, "Admin API server listening (HTTP/1.1 and HTTP/2)");
+        info!(addr = %local_addr, protocol, "Admin API server listening (HTTP/1.1 and HTTP/2)");
 
         let mut shutdown_rx = self.shutdown_rx.clone();
         let tls_acceptor = self.tls_acceptor.clone();
         let auth_token = Arc::clone(&self.auth_token);
+        let subsystems = self.subsystems.clone();
 
         loop {
             tokio::select! {
@@ -90,12 +219,13 @@ impl AdminServer {
                             let process_manager = Arc::clone(&self.process_manager);
                             let tls_acceptor = tls_acceptor.clone();
                             let auth_token = Arc::clone(&auth_token);
+                            let subsystems = subsystems.clone();
 
                             tokio::spawn(async move {
                                 if let Some(acceptor) = tls_acceptor {
                                     match acceptor.accept(stream).await {
                                         Ok(tls_stream) => {
-                                            if let Err(e) = serve_admin_connection(tls_stream, addr, process_manager, auth_token).await {
+                                            if let Err(e) = serve_admin_connection(tls_stream, addr, process_manager, auth_token, subsystems).await {
                                                 debug!(addr = %addr, error = %e, "Admin TLS connection error");
                                             }
                                         }
@@ -103,7 +233,7 @@ impl AdminServer {
                                             debug!(addr = %addr, error = %e, "Admin TLS handshake failed");
                                         }
                                     }
-                                } else if let Err(e) = serve_admin_connection(stream, addr, process_manager, auth_token).await {
+                                } else if let Err(e) = serve_admin_connection(stream, addr, process_manager, auth_token, subsystems).await {
                                     debug!(addr = %addr, error = %e, "Admin connection error");
                                 }
                             });
@@ -128,9 +258,10 @@ impl AdminServer {
 
 async fn serve_admin_connection<S>(
     stream: S,
-    _addr: SocketAddr,
+    addr: SocketAddr,
     process_manager: Arc<ProcessManager>,
     auth_token: Arc<String>,
+    subsystems: Subsystems,
 ) -> anyhow::Result<()>
 where
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
@@ -139,7 +270,13 @@ where
     let service = service_fn(move |req| {
         let pm = Arc::clone(&process_manager);
         let token = Arc::clone(&auth_token);
-        async move { handle_admin_request(req, pm, token).await }
+        let subsystems = subsystems.clone();
+        async move {
+            let Some(lockout) = subsystems.auth_lockout.clone() else {
+                return handle_admin_request(req, pm, token, subsystems).await;
+            };
+            handle_with_lockout(req, addr, &lockout, pm, token, subsystems).await
+        }
     });
 
     AutoBuilder::new(TokioExecutor::new())
@@ -150,6 +287,55 @@ where
     Ok(())
 }
 
+/// Handle a request, refusing locked out clients and recording failed authentication
+///
+/// The unauthenticated `/health` and `/version` endpoints stay available, as
+/// do ready callbacks with a valid instance token: backends share their IP
+/// with other local clients, whose failures must not hold up their start.
+async fn handle_with_lockout(
+    req: Request<hyper::body::Incoming>,
+    addr: SocketAddr,
+    lockout: &AuthLockout,
+    process_manager: Arc<ProcessManager>,
+    auth_token: Arc<String>,
+    subsystems: Subsystems,
+) -> Result<Response<Full<Bytes>>, hyper::Error> {
+    let ip = addr.ip();
+    let (method, path) = (req.method().clone(), req.uri().path().to_string());
+    let exempt = matches!(path.as_str(), "/health" | "/version") || is_instance_callback(&req, &process_manager);
+
+    if !exempt {
+        if let Some(remaining) = lockout.locked_out(ip) {
+            debug!(client = %ip, %method, path, "Refusing locked out admin API client");
+            let mut response = response(StatusCode::TOO_MANY_REQUESTS, "too many failed authentication attempts");
+            response
+                .headers_mut()
+                .insert(hyper::header::RETRY_AFTER, (remaining.as_millis().div_ceil(1000) as u64).into());
+            return Ok(response);
+        }
+    }
+
+    let authenticated = check_auth(&req, &auth_token);
+    let response = handle_admin_request(req, process_manager, auth_token, subsystems).await?;
+    if response.status() == StatusCode::UNAUTHORIZED {
+        lockout.record_failure(ip, method.as_str(), &path);
+    } else if authenticated {
+        lockout.record_success(ip);
+    }
+    Ok(response)
+}
+
+/// Whether a request is a ready callback carrying the instance token of the backend
+fn is_instance_callback(req: &Request<hyper::body::Incoming>, process_manager: &ProcessManager) -> bool {
+    req.method() == Method::POST
+        && req
+            .uri()
+            .path()
+            .strip_prefix("/ready/")
+            .zip(request_token(req))
+            .is_some_and(|(hostname, token)| process_manager.verify_instance_token(hostname, &token))
+}
+
 fn check_auth(req: &Request<hyper::body::Incoming>, expected_token: &str) -> bool {
     req.headers()
         .get(AUTHORIZATION)
@@ -163,10 +349,288 @@ fn check_auth(req: &Request<hyper::body::Incoming>, expected_token: &str) -> boo
         .unwrap_or(false)
 }
 
+/// A finished profile, or the reason it could not be taken
+fn profile_response(
+    profile: Result<Vec<u8>, ProfileError>,
+    content_type: &'static str,
+) -> Response<Full<Bytes>> {
+    match profile {
+        Ok(profile) => Response::builder()
+            .status(StatusCode::OK)
+            .header("content-type", content_type)
+            .body(Full::new(Bytes::from(profile)))
+            .expect("valid response with StatusCode enum and static header"),
+        Err(e) => {
+            warn!(error = %e, "Profile failed");
+            response(e.status(), e.to_string())
+        }
+    }
+}
+
+/// Value of a query string parameter
+fn query_param(req: &Request<hyper::body::Incoming>, name: &str) -> Option<String> {
+    form_urlencoded::parse(req.uri().query()?.as_bytes())
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+}
+
+/// Token from the Authorization header or a `token` query parameter
+fn request_token(req: &Request<hyper::body::Incoming>) -> Option<String> {
+    req.headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .map(|auth| auth.strip_prefix("Bearer ").unwrap_or(auth).to_string())
+        .or_else(|| query_param(req, "token"))
+}
+
+/// Parse the `selector` query parameter (absent means all backends)
+fn request_selector(req: &Request<hyper::body::Incoming>) -> Result<Selector, SelectorError> {
+    query_param(req, "selector")
+        .map(|expr| Selector::parse(&expr))
+        .transpose()
+        .map(Option::unwrap_or_default)
+}
+
+/// Read, update or clear a backend's config vars
+///
+/// PUT takes a JSON object of vars to set, where `null` removes a var. With
+/// `?restart=true` a running backend is restarted when its vars changed.
+async fn handle_config_vars(
+    req: Request<hyper::body::Incoming>,
+    process_manager: &Arc<ProcessManager>,
+    hostname: &str,
+) -> Response<Full<Bytes>> {
+    let restart = query_param(&req, "restart").is_some_and(|v| v == "true" || v == "1");
+    let vars = process_manager.config_vars();
+
+    let result = match *req.method() {
+        Method::GET => Ok((vars.get(hostname), false)),
+        Method::PUT => {
+            let body = match Limited::new(req.into_body(), MAX_CONFIG_VARS_BODY).collect().await {
+                Ok(body) => body.to_bytes(),
+                Err(_) => return response(StatusCode::PAYLOAD_TOO_LARGE, "body too large"),
+            };
+            let changes: HashMap<String, Option<String>> = match serde_json::from_slice(&body) {
+                Ok(changes) => changes,
+                Err(e) => return response(StatusCode::BAD_REQUEST, format!("expected a JSON object of strings: {}", e)),
+            };
+            vars.update(hostname, changes)
+        }
+        _ => vars.clear(hostname).map(|changed| (Vars::new(), changed)),
+    };
+
+    match result {
+        Ok((vars, changed)) => {
+            if changed {
+                info!(hostname, count = vars.len(), "Config vars changed");
+                if restart {
+                    process_manager.restart_backend(hostname);
+                }
+            }
+            json_response(StatusCode::OK, serde_json::to_string(&vars).unwrap_or_default())
+        }
+        Err(e @ ConfigVarsError::Invalid(_)) => response(StatusCode::BAD_REQUEST, e.to_string()),
+        Err(e @ ConfigVarsError::Save(_)) => {
+            error!(hostname, error = %e, "Failed to save config vars");
+            response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        }
+    }
+}
+
+/// Result of changing a backend definition, with the new definition's ETag
+fn backend_change_response(
+    status: StatusCode,
+    hostname: &str,
+    action: &str,
+    diff: Vec<FieldChange>,
+    backend: Option<&BackendConfig>,
+) -> Response<Full<Bytes>> {
+    let etag = backend.map(BackendConfig::etag);
+    let definition = backend.map(redacted_definition);
+    let diff = redacted(diff);
+    let body = serde_json::json!({
+        "hostname": hostname,
+        "action": action,
+        "diff": diff,
+        "backend": definition,
+    });
+    let mut response = json_response(status, body.to_string());
+    if let Some(etag) = etag {
+        response.headers_mut().insert(ETAG, etag.parse().expect("hex ETag is a valid header"));
+    }
+    response
+}
+
+/// Backend definition as shown by the admin API, with secrets redacted
+fn redacted_definition(backend: &BackendConfig) -> serde_json::Value {
+    let mut definition = serde_json::to_value(backend).unwrap_or_default();
+    redact_secrets(&mut definition);
+    definition
+}
+
+/// Read, create, replace or remove a backend definition
+///
+/// PUT takes the backend as JSON, with the same settings as `[backends.*]`,
+/// and creates or replaces it; putting the same definition again changes
+/// nothing. Secrets are shown redacted, and a redacted secret sent back keeps
+/// its current value. `If-Match` and `If-None-Match` make a request conditional on the
+/// definition's ETag. Backends from the configuration can be read but not
+/// changed here.
+async fn handle_backend_definition(
+    req: Request<hyper::body::Incoming>,
+    process_manager: &Arc<ProcessManager>,
+    hostname: &str,
+) -> Response<Full<Bytes>> {
+    let current = process_manager.get_config(hostname);
+    let etag = current.as_ref().map(BackendConfig::etag);
+    let header = |name| req.headers().get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+    let precondition = Precondition {
+        if_match: header(IF_MATCH),
+        if_none_match: header(IF_NONE_MATCH),
+    };
+    let api_backend = process_manager.is_api_backend(hostname);
+
+    // Checked again when the change is made, in case the definition changed meanwhile
+    if !precondition.holds(etag.as_deref()) {
+        let if_match = Precondition {
+            if_none_match: None,
+            ..precondition.clone()
+        };
+        if req.method() == Method::GET && if_match.holds(etag.as_deref()) {
+            let mut not_modified = response(StatusCode::NOT_MODIFIED, "");
+            if let Some(etag) = &etag {
+                not_modified.headers_mut().insert(ETAG, etag.parse().expect("hex ETag is a valid header"));
+            }
+            return not_modified;
+        }
+        return response(StatusCode::PRECONDITION_FAILED, "precondition failed");
+    }
+
+    match (req.method().clone(), current) {
+        (_, None) if req.method() != Method::PUT => response(StatusCode::NOT_FOUND, "not found"),
+        (Method::GET, Some(backend)) => {
+            let body = serde_json::json!({
+                "hostname": hostname,
+                "source": if api_backend { "api" } else { "config" },
+                "backend": redacted_definition(&backend),
+            });
+            let mut response = json_response(StatusCode::OK, body.to_string());
+            if let Some(etag) = etag {
+                response.headers_mut().insert(ETAG, etag.parse().expect("hex ETag is a valid header"));
+            }
+            response
+        }
+        (_, Some(_)) if !api_backend => response(StatusCode::CONFLICT, "backend is defined by the configuration"),
+        (Method::DELETE, Some(_)) => match process_manager.remove_api_backend(hostname, &precondition).await {
+            Ok(Some(previous)) => {
+                let diff = diff_fields(&serde_json::to_value(previous).unwrap_or_default(), &serde_json::Value::Null);
+                backend_change_response(StatusCode::OK, hostname, "deleted", diff, None)
+            }
+            Ok(None) => response(StatusCode::NOT_FOUND, "not found"),
+            Err(e) => backend_change_error(e),
+        },
+        (_, current) => {
+            let body = match Limited::new(req.into_body(), MAX_BACKEND_BODY).collect().await {
+                Ok(body) => body.to_bytes(),
+                Err(_) => return response(StatusCode::PAYLOAD_TOO_LARGE, "body too large"),
+            };
+            let mut definition: serde_json::Value = match serde_json::from_slice(&body) {
+                Ok(definition) => definition,
+                Err(e) => return response(StatusCode::BAD_REQUEST, format!("invalid backend: {}", e)),
+            };
+            if let Some(current) = &current {
+                restore_secrets(&mut definition, &serde_json::to_value(current).unwrap_or_default());
+            }
+            let backend: BackendConfig = match serde_json::from_value(definition) {
+                Ok(backend) => backend,
+                Err(e) => return response(StatusCode::BAD_REQUEST, format!("invalid backend: {}", e)),
+            };
+
+            // Validated like a backend in the configuration file, without profiles
+            let mut config = Config {
+                defaults: process_manager.get_defaults(),
+                ..Default::default()
+            };
+            config.backends.insert(hostname.to_string(), backend);
+            if let Err(e) = config.resolve_profiles() {
+                return response(StatusCode::BAD_REQUEST, e.to_string());
+            }
+            // Alongside the running backends, which it may refer to and be referred to by
+            let mut backends = process_manager.get_configs();
+            backends.extend(std::mem::take(&mut config.backends));
+            config.backends = backends;
+            if let Err(e) = config.validate() {
+                return response(StatusCode::BAD_REQUEST, e.to_string());
+            }
+            let backend = config.backends.remove(hostname).expect("backend was just inserted");
+            let definition = serde_json::to_value(&backend).unwrap_or_default();
+
+            let previous = match process_manager.put_api_backend(hostname, backend.clone(), &precondition) {
+                Ok(previous) => previous,
+                Err(e) => return backend_change_error(e),
+            };
+            let previous = previous.map(|previous| serde_json::to_value(previous).unwrap_or_default());
+            let diff = diff_fields(previous.as_ref().unwrap_or(&serde_json::Value::Null), &definition);
+            let (status, action) = match previous {
+                None => (StatusCode::CREATED, "created"),
+                Some(_) if diff.is_empty() => (StatusCode::OK, "unchanged"),
+                Some(_) => (StatusCode::OK, "updated"),
+            };
+            backend_change_response(status, hostname, action, diff, Some(&backend))
+        }
+    }
+}
+
+/// Response for a backend definition the admin API didn't change
+fn backend_change_error(error: BackendChangeError) -> Response<Full<Bytes>> {
+    let status = match error {
+        BackendChangeError::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+        BackendChangeError::Configured => StatusCode::CONFLICT,
+    };
+    response(status, error.to_string())
+}
+
+/// Plan, or with `?dry_run=true` only show, the changes for a desired
+/// configuration
+///
+/// The body is a configuration file, whose `[defaults]` and `[backends]`
+/// replace the running ones. `If-Match` applies only if the plan still has
+/// the ETag returned by a dry run.
+async fn handle_apply(req: Request<hyper::body::Incoming>, applier: &Applier) -> Response<Full<Bytes>> {
+    let dry_run = query_param(&req, "dry_run").is_some_and(|v| v == "true" || v == "1");
+    let expected = req.headers().get(IF_MATCH).and_then(|v| v.to_str().ok()).map(str::to_string);
+    let body = match Limited::new(req.into_body(), MAX_APPLY_BODY).collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(_) => return response(StatusCode::PAYLOAD_TOO_LARGE, "body too large"),
+    };
+    let desired = match std::str::from_utf8(&body).map_err(anyhow::Error::from).and_then(Config::parse) {
+        Ok(desired) => desired,
+        Err(e) => return response(StatusCode::BAD_REQUEST, format!("invalid configuration: {}", e)),
+    };
+
+    let result = if dry_run {
+        applier.plan(&desired).map_err(ApplyError::Invalid)
+    } else {
+        applier.apply(&desired, expected.as_deref()).await
+    };
+    match result {
+        Ok(plan) => {
+            let etag = plan.etag();
+            let body = serde_json::json!({ "plan": plan, "applied": !dry_run });
+            let mut response = json_response(StatusCode::OK, body.to_string());
+            response.headers_mut().insert(ETAG, etag.parse().expect("hex ETag is a valid header"));
+            response
+        }
+        Err(e @ ApplyError::Invalid(_)) => response(StatusCode::BAD_REQUEST, e.to_string()),
+        Err(e @ ApplyError::PlanChanged(_)) => response(StatusCode::PRECONDITION_FAILED, e.to_string()),
+    }
+}
+
 async fn handle_admin_request(
     req: Request<hyper::body::Incoming>,
     process_manager: Arc<ProcessManager>,
     auth_token: Arc<String>,
+    subsystems: Subsystems,
 ) -> Result<Response<Full<Bytes>>, hyper::Error> {
     let path = req.uri().path();
     let method = req.method();
@@ -186,50 +650,560 @@ async fn handle_admin_request(
             json_response(StatusCode::OK, version_info.to_string())
         }
 
-        // Backend ready callback: POST /ready/{hostname} (auth required)
+        // Backend ready callback: POST /ready/{hostname}
+        // (admin token, or the instance token from ADMIN_CALLBACK_URL)
         (&Method::POST, path) if path.starts_with("/ready/") => {
+            let hostname = path.strip_prefix("/ready/").unwrap_or("");
+            let authorized = check_auth(&req, &auth_token)
+                || request_token(&req)
+                    .is_some_and(|token| process_manager.verify_instance_token(hostname, &token));
+            if !authorized {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else if hostname.is_empty() {
+                response(StatusCode::BAD_REQUEST, "missing hostname")
+            } else if let Some(gate) = process_manager.pending_gate(hostname) {
+                debug!(hostname, %gate, "Ready callback before readiness gates passed");
+                response(StatusCode::CONFLICT, format!("waiting for {}", gate))
+            } else if process_manager.mark_ready(hostname) {
+                info!(hostname, "Backend marked ready via callback");
+                response(StatusCode::OK, "ok")
+            } else {
+                response(StatusCode::NOT_FOUND, "backend not starting")
+            }
+        }
+
+        // Manual readiness gate: POST approves, DELETE revokes (auth required)
+        (&Method::POST, path) | (&Method::DELETE, path) if path.starts_with("/approve/") => {
             if !check_auth(&req, &auth_token) {
                 warn!(path, "Unauthorized admin API request");
                 response(StatusCode::UNAUTHORIZED, "unauthorized")
             } else {
-                let hostname = path.strip_prefix("/ready/").unwrap_or("");
-                if hostname.is_empty() {
-                    response(StatusCode::BAD_REQUEST, "missing hostname")
-                } else if process_manager.mark_ready(hostname) {
-                    info!(hostname, "Backend marked ready via callback");
+                let hostname = path.strip_prefix("/approve/").unwrap_or("");
+                if method == Method::POST && process_manager.approve(hostname) {
+                    info!(hostname, "Backend approved via admin API");
+                    response(StatusCode::OK, "ok")
+                } else if method == Method::DELETE && process_manager.revoke_approval(hostname) {
+                    info!(hostname, "Backend approval revoked via admin API");
                     response(StatusCode::OK, "ok")
                 } else {
-                    response(StatusCode::NOT_FOUND, "backend not starting")
+                    response(StatusCode::NOT_FOUND, "not found")
+                }
+            }
+        }
+
+        // Enabled faults: GET /faults (auth required)
+        (&Method::GET, "/faults") => {
+            if !check_auth(&req, &auth_token) {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else {
+                let faults = process_manager.faults().active();
+                let response_body = serde_json::json!({
+                    "faults": faults,
+                    "count": faults.len()
+                });
+                json_response(StatusCode::OK, response_body.to_string())
+            }
+        }
+
+        // Fault injection: POST /faults/{hostname}[?duration_secs=N] enables,
+        // DELETE disables (auth required)
+        (&Method::POST, path) | (&Method::DELETE, path) if path.starts_with("/faults/") => {
+            if !check_auth(&req, &auth_token) {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else {
+                let hostname = path.strip_prefix("/faults/").unwrap_or("");
+                if !process_manager.has_backend(hostname) {
+                    response(StatusCode::NOT_FOUND, "not found")
+                } else if method == Method::DELETE {
+                    if process_manager.faults().disable(hostname) {
+                        response(StatusCode::OK, "ok")
+                    } else {
+                        response(StatusCode::NOT_FOUND, "no faults enabled")
+                    }
+                } else {
+                    let duration = query_param(&req, "duration_secs")
+                        .map(|secs| secs.parse().map(Duration::from_secs))
+                        .transpose();
+                    match duration {
+                        Err(_) => response(StatusCode::BAD_REQUEST, "invalid duration_secs"),
+                        Ok(duration) => match process_manager.enable_faults(hostname, duration) {
+                            Ok(duration) => {
+                                let response_body = serde_json::json!({
+                                    "hostname": hostname,
+                                    "duration_secs": duration.as_secs()
+                                });
+                                json_response(StatusCode::OK, response_body.to_string())
+                            }
+                            Err(e) => response(StatusCode::BAD_REQUEST, e),
+                        },
+                    }
+                }
+            }
+        }
+
+        // Traffic quota usage: GET /quotas (auth required)
+        (&Method::GET, "/quotas") => {
+            if !check_auth(&req, &auth_token) {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else {
+                let quotas = process_manager.quota_status();
+                let response_body = serde_json::json!({
+                    "quotas": quotas,
+                    "count": quotas.len()
+                });
+                json_response(StatusCode::OK, response_body.to_string())
+            }
+        }
+
+        // Start a backend's quota window over: DELETE /quotas/{hostname} (auth required)
+        (&Method::DELETE, path) if path.starts_with("/quotas/") => {
+            if !check_auth(&req, &auth_token) {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else {
+                let hostname = path.strip_prefix("/quotas/").unwrap_or("");
+                if process_manager.quota(hostname).is_none() {
+                    response(StatusCode::NOT_FOUND, "no quota configured")
+                } else {
+                    process_manager.quotas().reset(hostname);
+                    response(StatusCode::OK, "ok")
                 }
             }
         }
 
-        // List backends and their status: GET /backends (auth required)
+        // Promotion review and apply: GET/POST /promote/{source}/{target} (auth required)
+        (&Method::GET, path) | (&Method::POST, path) if path.starts_with("/promote/") => {
+            if !check_auth(&req, &auth_token) {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else {
+                let route = path.strip_prefix("/promote/").unwrap_or("");
+                match route.split_once('/') {
+                    Some((source, target)) if !source.is_empty() && !target.is_empty() => {
+                        let result = if method == Method::GET {
+                            process_manager
+                                .review_promotion(source, target)
+                                .map(|review| serde_json::to_value(review).unwrap_or_default())
+                        } else {
+                            process_manager
+                                .promote(source, target)
+                                .await
+                                .map(|record| serde_json::to_value(record).unwrap_or_default())
+                        };
+                        match result {
+                            Ok(body) => json_response(StatusCode::OK, body.to_string()),
+                            Err(e) => response(StatusCode::BAD_REQUEST, e),
+                        }
+                    }
+                    _ => response(StatusCode::BAD_REQUEST, "expected /promote/{source}/{target}"),
+                }
+            }
+        }
+
+        // Config vars: GET/PUT/DELETE /apps/{hostname}/config[?restart=true] (auth required)
+        (&Method::GET, path) | (&Method::PUT, path) | (&Method::DELETE, path)
+            if path.starts_with("/apps/") && path.ends_with("/config") =>
+        {
+            if !check_auth(&req, &auth_token) {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else {
+                let hostname = path
+                    .strip_prefix("/apps/")
+                    .and_then(|p| p.strip_suffix("/config"))
+                    .unwrap_or("")
+                    .to_string();
+                if process_manager.has_backend(&hostname) {
+                    handle_config_vars(req, &process_manager, &hostname).await
+                } else {
+                    response(StatusCode::NOT_FOUND, "not found")
+                }
+            }
+        }
+
+        // Promotion history: GET /promotions (auth required)
+        (&Method::GET, "/promotions") => {
+            if !check_auth(&req, &auth_token) {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else {
+                let history = process_manager.promotion_history();
+                let response_body = serde_json::json!({
+                    "promotions": history,
+                    "count": history.len()
+                });
+                json_response(StatusCode::OK, response_body.to_string())
+            }
+        }
+
+        // List backends and their status: GET /backends[?selector=...][&resources=true]
+        // (auth required)
         (&Method::GET, "/backends") => {
             if !check_auth(&req, &auth_token) {
                 warn!(path, "Unauthorized admin API request");
                 response(StatusCode::UNAUTHORIZED, "unauthorized")
             } else {
-                let backends = process_manager.list_backends();
-                let backend_list: Vec<serde_json::Value> = backends
-                    .into_iter()
-                    .map(|b| {
-                        serde_json::json!({
-                            "hostname": b.hostname,
-                            "state": b.state,
-                            "port": b.port,
-                            "in_flight": b.in_flight
-                        })
-                    })
-                    .collect();
+                match request_selector(&req) {
+                    Err(e) => response(StatusCode::BAD_REQUEST, e.to_string()),
+                    Ok(selector) => {
+                        let backends: Vec<_> = process_manager
+                            .list_backends()
+                            .into_iter()
+                            .filter(|b| selector.matches(&b.labels))
+                            .collect();
+                        // Asks the container runtime, which may take seconds, so only on request
+                        let resources = if query_param(&req, "resources").is_some_and(|v| v == "true" || v == "1") {
+                            let stats = backends.iter().map(|b| process_manager.resource_stats(&b.hostname));
+                            futures::future::join_all(stats).await.into_iter().map(Some).collect()
+                        } else {
+                            vec![None; backends.len()]
+                        };
+                        let backend_list: Vec<serde_json::Value> = backends
+                            .into_iter()
+                            .zip(resources)
+                            .map(|(b, resources)| {
+                                let mut backend = serde_json::json!({
+                                    "hostname": b.hostname,
+                                    "state": b.state,
+                                    "port": b.port,
+                                    "in_flight": b.in_flight,
+                                    "queued": b.queued,
+                                    "queued_by_class": b.queued_by_class,
+                                    "max_queue_depth": b.max_queue_depth,
+                                    "cancelled": b.cancelled,
+                                    "labels": b.labels,
+                                });
+                                if let Some(resources) = resources {
+                                    backend["resources"] = serde_json::json!(resources);
+                                }
+                                backend
+                            })
+                            .collect();
+                        let response_body = serde_json::json!({
+                            "backends": backend_list,
+                            "count": backend_list.len()
+                        });
+                        json_response(StatusCode::OK, response_body.to_string())
+                    }
+                }
+            }
+        }
+
+        // Merged configuration with defaults resolved and secrets redacted:
+        // GET /config/effective (auth required)
+        (&Method::GET, "/config/effective") => {
+            if !check_auth(&req, &auth_token) {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else if let Some(config) = subsystems.config {
+                let mut config = config.read().clone();
+                config.backends = process_manager.get_configs();
+                config.defaults = process_manager.get_defaults();
+                let effective = config.effective(process_manager.config_vars());
+                json_response(StatusCode::OK, effective.to_string())
+            } else {
+                response(StatusCode::NOT_FOUND, "configuration not available")
+            }
+        }
+
+        // Declarative apply: POST /apply[?dry_run=true] (auth required)
+        (&Method::POST, "/apply") => {
+            if !check_auth(&req, &auth_token) {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else if let Some(applier) = subsystems.applier {
+                handle_apply(req, &applier).await
+            } else {
+                response(StatusCode::NOT_FOUND, "apply not enabled")
+            }
+        }
+
+        // Backend definitions: GET/PUT/DELETE /backends/{hostname} (auth required)
+        (&Method::GET, path) | (&Method::PUT, path) | (&Method::DELETE, path)
+            if path
+                .strip_prefix("/backends/")
+                .is_some_and(|hostname| !hostname.is_empty() && !hostname.contains('/')) =>
+        {
+            if !check_auth(&req, &auth_token) {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else {
+                let hostname = path.strip_prefix("/backends/").unwrap_or("").to_string();
+                handle_backend_definition(req, &process_manager, &hostname).await
+            }
+        }
+
+        // Bulk stop: POST /backends/stop?selector=... (auth required, selector required)
+        (&Method::POST, "/backends/stop") => {
+            if !check_auth(&req, &auth_token) {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else if query_param(&req, "selector").is_none() {
+                response(StatusCode::BAD_REQUEST, "missing selector")
+            } else {
+                match request_selector(&req) {
+                    Err(e) => response(StatusCode::BAD_REQUEST, e.to_string()),
+                    Ok(selector) => {
+                        let hostnames = process_manager.select_backends(&selector);
+                        for hostname in &hostnames {
+                            info!(hostname, "Stopping backend (bulk stop)");
+                            process_manager.stop_backend(hostname).await;
+                        }
+                        let response_body = serde_json::json!({
+                            "stopped": hostnames,
+                            "count": hostnames.len()
+                        });
+                        json_response(StatusCode::OK, response_body.to_string())
+                    }
+                }
+            }
+        }
+
+        // Recent backend output: GET /logs/{hostname}[?lines=N] (auth required)
+        (&Method::GET, path) if path.starts_with("/logs/") => {
+            if !check_auth(&req, &auth_token) {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else {
+                let hostname = path.strip_prefix("/logs/").unwrap_or("");
+                let lines = query_param(&req, "lines").map_or(Ok(100), |lines| lines.parse::<usize>());
+                match lines {
+                    _ if !process_manager.has_backend(hostname) => response(StatusCode::NOT_FOUND, "not found"),
+                    Err(_) => response(StatusCode::BAD_REQUEST, "invalid lines"),
+                    Ok(lines) => {
+                        let lines = process_manager.logs(hostname, lines);
+                        let response_body = serde_json::json!({
+                            "hostname": hostname,
+                            "lines": lines,
+                            "count": lines.len()
+                        });
+                        json_response(StatusCode::OK, response_body.to_string())
+                    }
+                }
+            }
+        }
+
+        // Configured and learned idle timeouts: GET /idle (auth required)
+        (&Method::GET, "/idle") => {
+            if !check_auth(&req, &auth_token) {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else {
                 let response_body = serde_json::json!({
-                    "backends": backend_list,
-                    "count": backend_list.len()
+                    "backends": process_manager.idle_timeouts(),
                 });
                 json_response(StatusCode::OK, response_body.to_string())
             }
         }
 
+        // Compute saved by scale-to-zero: GET /savings (auth required)
+        (&Method::GET, "/savings") => {
+            if !check_auth(&req, &auth_token) {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else {
+                let report = serde_json::to_value(process_manager.savings_report()).unwrap_or_default();
+                json_response(StatusCode::OK, report.to_string())
+            }
+        }
+
+        // Cold-start throttle counters: GET /throttle (auth required)
+        (&Method::GET, "/throttle") => {
+            if !check_auth(&req, &auth_token) {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else if let Some(throttle) = subsystems.cold_start_throttle {
+                let stats = serde_json::to_value(throttle.stats()).unwrap_or_default();
+                json_response(StatusCode::OK, stats.to_string())
+            } else {
+                response(StatusCode::NOT_FOUND, "cold-start throttle not enabled")
+            }
+        }
+
+        // Watched certificate files and their expiry: GET /certificates (auth required)
+        (&Method::GET, "/certificates") => {
+            if !check_auth(&req, &auth_token) {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else {
+                let statuses = subsystems.cert_watcher.map(|w| w.statuses()).unwrap_or_default();
+                let body = serde_json::json!({ "certificates": statuses });
+                json_response(StatusCode::OK, body.to_string())
+            }
+        }
+
+        // Failed authentication attempts and lockouts: GET /auth/audit (auth required)
+        (&Method::GET, "/auth/audit") => {
+            if !check_auth(&req, &auth_token) {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else if let Some(lockout) = subsystems.auth_lockout {
+                let body = serde_json::json!({
+                    "stats": lockout.stats(),
+                    "entries": lockout.audit_log(),
+                });
+                json_response(StatusCode::OK, body.to_string())
+            } else {
+                response(StatusCode::NOT_FOUND, "admin lockout not enabled")
+            }
+        }
+
+        // Proxy TLS handshake counters: GET /tls/handshakes (auth required)
+        (&Method::GET, "/tls/handshakes") => {
+            if !check_auth(&req, &auth_token) {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else if let Some(metrics) = subsystems.tls_metrics {
+                let stats = serde_json::to_value(metrics.stats()).unwrap_or_default();
+                json_response(StatusCode::OK, stats.to_string())
+            } else {
+                response(StatusCode::NOT_FOUND, "TLS not enabled")
+            }
+        }
+
+        // Slowest endpoints per backend within the window: GET /slow-requests (auth required)
+        (&Method::GET, "/slow-requests") => {
+            if !check_auth(&req, &auth_token) {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else if let Some(slow_log) = subsystems.slow_log {
+                let summary = serde_json::to_value(slow_log.summary()).unwrap_or_default();
+                json_response(StatusCode::OK, summary.to_string())
+            } else {
+                response(StatusCode::NOT_FOUND, "slow request log not enabled")
+            }
+        }
+
+        // Cold-start SLO attainment per backend: GET /slo (auth required)
+        (&Method::GET, "/slo") => {
+            if !check_auth(&req, &auth_token) {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else if let Some(slo) = subsystems.cold_start_slo {
+                let report = serde_json::to_value(slo.report()).unwrap_or_default();
+                json_response(StatusCode::OK, report.to_string())
+            } else {
+                response(StatusCode::NOT_FOUND, "cold-start SLO not enabled")
+            }
+        }
+
+        // Requests, errors and latency per experiment variant: GET /experiments (auth required)
+        (&Method::GET, "/experiments") => {
+            if !check_auth(&req, &auth_token) {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else if let Some(metrics) = subsystems.experiment_metrics {
+                let body = serde_json::json!({ "variants": metrics.report() });
+                json_response(StatusCode::OK, body.to_string())
+            } else {
+                response(StatusCode::NOT_FOUND, "experiment metrics not enabled")
+            }
+        }
+
+        // Registered tasks and backends being spawned: GET /debug/tasks (auth required)
+        (&Method::GET, "/debug/tasks") => {
+            if !check_auth(&req, &auth_token) {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else if let Some(registry) = subsystems.debug_registry {
+                let mut snapshot = serde_json::to_value(registry.tasks()).unwrap_or_default();
+                snapshot["spawning"] = serde_json::json!(process_manager.spawning());
+                json_response(StatusCode::OK, snapshot.to_string())
+            } else {
+                response(StatusCode::NOT_FOUND, "debug registry not enabled")
+            }
+        }
+
+        // Open client and backend connections: GET /debug/connections (auth required)
+        (&Method::GET, "/debug/connections") => {
+            if !check_auth(&req, &auth_token) {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else if let Some(registry) = subsystems.debug_registry {
+                let snapshot = serde_json::to_value(registry.connections()).unwrap_or_default();
+                json_response(StatusCode::OK, snapshot.to_string())
+            } else {
+                response(StatusCode::NOT_FOUND, "debug registry not enabled")
+            }
+        }
+
+        // CPU flamegraph over ?seconds=: GET /debug/pprof/profile (auth required)
+        (&Method::GET, "/debug/pprof/profile") => {
+            if !check_auth(&req, &auth_token) {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else if let Some(profiler) = subsystems.profiler {
+                let profile = match profiler.duration(query_param(&req, "seconds").as_deref()) {
+                    Ok(duration) => {
+                        info!(seconds = duration.as_secs(), "CPU profile requested via admin API");
+                        profiler.cpu(duration).await
+                    }
+                    Err(e) => Err(e),
+                };
+                profile_response(profile, "image/svg+xml")
+            } else {
+                response(StatusCode::NOT_FOUND, "profiling not enabled")
+            }
+        }
+
+        // jemalloc heap profile over ?seconds=: GET /debug/pprof/heap (auth required)
+        (&Method::GET, "/debug/pprof/heap") => {
+            if !check_auth(&req, &auth_token) {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else if let Some(profiler) = subsystems.profiler {
+                let profile = match profiler.duration(query_param(&req, "seconds").as_deref()) {
+                    Ok(duration) => {
+                        info!(seconds = duration.as_secs(), "Heap profile requested via admin API");
+                        profiler.heap(duration).await
+                    }
+                    Err(e) => Err(e),
+                };
+                profile_response(profile, "application/octet-stream")
+            } else {
+                response(StatusCode::NOT_FOUND, "profiling not enabled")
+            }
+        }
+
+        // Restartable subsystems: GET /subsystems (auth required)
+        (&Method::GET, "/subsystems") => {
+            if !check_auth(&req, &auth_token) {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else if let Some(supervisor) = subsystems.supervisor {
+                let response_body = serde_json::json!({ "subsystems": supervisor.list() });
+                json_response(StatusCode::OK, response_body.to_string())
+            } else {
+                response(StatusCode::NOT_FOUND, "subsystems not enabled")
+            }
+        }
+
+        // Restart a subsystem: POST /subsystems/{name}/restart, or POST /acme/restart (auth required)
+        (&Method::POST, path)
+            if path == "/acme/restart" || (path.starts_with("/subsystems/") && path.ends_with("/restart")) =>
+        {
+            let name = path
+                .strip_prefix("/subsystems/")
+                .and_then(|rest| rest.strip_suffix("/restart"))
+                .unwrap_or("acme");
+            if !check_auth(&req, &auth_token) {
+                warn!(path, "Unauthorized admin API request");
+                response(StatusCode::UNAUTHORIZED, "unauthorized")
+            } else {
+                match subsystems.supervisor.and_then(|supervisor| supervisor.restart(name)) {
+                    Some(status) => {
+                        info!(subsystem = name, "Subsystem restarted via admin API");
+                        json_response(StatusCode::OK, serde_json::to_string(&status).unwrap_or_default())
+                    }
+                    None => response(StatusCode::NOT_FOUND, format!("subsystem '{}' not running", name)),
+                }
+            }
+        }
+
         // 404 for everything else
         _ => response(StatusCode::NOT_FOUND, "not found"),
     };
@@ -0,0 +1,878 @@
+//! Running spawngate inside another binary
+//!
+//! [`Spawngate::builder`] wires up everything the shipped binary runs: the
+//! proxy listeners, admin API, TLS and ACME, and the background tasks. The
+//! configuration can come from a file, be built in code, or both:
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! use spawngate::config::BackendConfig;
+//! use spawngate::process::BackendEvent;
+//! use spawngate::Spawngate;
+//!
+//! let gate = Spawngate::builder()
+//!     .backend("app.local", BackendConfig::local("./app", 3000))
+//!     .on_event(|event| {
+//!         if let BackendEvent::Ready { hostname } = event {
+//!             println!("{} is ready", hostname);
+//!         }
+//!     })
+//!     .start()
+//!     .await?;
+//! // ...
+//! gate.stop().await;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Embedding applications install a rustls crypto provider and set up
+//! logging themselves.
+
+use crate::acme::{AcmeManager, Http01Challenges, ACME_TLS_ALPN_NAME};
+use crate::admin::AdminServer;
+use crate::apply::Applier;
+use crate::certwatch::{CertTarget, CertWatcher};
+use crate::config::{
+    AcmeChallengeType, BackendConfig, BackendDefaults, Config, EarlyDataConfig, PathNormalizationConfig, ServerConfig,
+    ShutdownConfig,
+};
+use crate::discovery::{Discovery, FileDiscovery, KvDiscovery};
+use crate::early_data;
+use crate::experiments::ExperimentMetrics;
+use crate::fingerprint::Fingerprinter;
+use crate::hashicorp::ConsulRegistrar;
+use crate::listeners::{ListenerSettings, Listeners, ProxyServers};
+use crate::lockout::AuthLockout;
+use crate::metrics::{self, RequestMetrics, TlsHandshakeMetrics};
+use crate::pool::{ConnectionPool, PoolConfig};
+use crate::process::{BackendEvent, EventHook, ProcessManager, ReloadResult, SharedDefaults, Spawner};
+use crate::profiling::Profiler;
+use crate::proxy::{HostRouter, ProxyServer, Router};
+use crate::registry::DebugRegistry;
+use crate::sampling::TraceSampler;
+use crate::shutdown;
+use crate::slo::ColdStartSlo;
+use crate::slowlog::SlowRequestLog;
+use crate::supervisor::Supervisor;
+use crate::throttle::ColdStartThrottle;
+use crate::tls::{
+    certified_key, configure_session_resumption, load_certified_key, load_certs, load_key, PinnedCertResolver,
+};
+use parking_lot::RwLock;
+use rcgen::{generate_simple_self_signed, CertifiedKey};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::ResolvesServerCert;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
+use tracing::{error, info, warn};
+
+/// How often idle backends are looked for, and warm backends restarted
+const IDLE_CLEANUP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long `stop` waits for the servers beyond the drain timeout, and for the
+/// final metrics export
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Configures a [`Spawngate`] before it starts
+pub struct SpawngateBuilder {
+    config: Config,
+    hooks: Vec<EventHook>,
+    spawners: Vec<(String, Arc<dyn Spawner>)>,
+    router: Option<MakeRouter>,
+}
+
+/// Builds the custom router from the default one
+type MakeRouter = Box<dyn FnOnce(HostRouter) -> Arc<dyn Router> + Send>;
+
+impl SpawngateBuilder {
+    /// Start from a loaded configuration, replacing anything set so far
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Set the `[server]` settings
+    pub fn server(mut self, server: ServerConfig) -> Self {
+        self.config.server = server;
+        self
+    }
+
+    /// Set the `[defaults]` applied to every backend
+    pub fn defaults(mut self, defaults: BackendDefaults) -> Self {
+        self.config.defaults = defaults;
+        self
+    }
+
+    /// Add a backend, replacing any backend with the same hostname
+    pub fn backend(mut self, hostname: impl Into<String>, backend: BackendConfig) -> Self {
+        self.config.backends.insert(hostname.into(), backend);
+        self
+    }
+
+    /// Call `hook` for every backend lifecycle event
+    pub fn on_event(mut self, hook: impl Fn(&BackendEvent) + Send + Sync + 'static) -> Self {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Launch backends whose `spawner` is `name` with `spawner`
+    pub fn spawner(mut self, name: impl Into<String>, spawner: Arc<dyn Spawner>) -> Self {
+        self.spawners.push((name.into(), spawner));
+        self
+    }
+
+    /// Pick backends with a custom router on both listeners
+    ///
+    /// `make` receives the default Host header router to delegate to.
+    pub fn router(mut self, make: impl FnOnce(HostRouter) -> Arc<dyn Router> + Send + 'static) -> Self {
+        self.router = Some(Box::new(make));
+        self
+    }
+
+    /// Validate the configuration and start serving
+    pub async fn start(self) -> anyhow::Result<Spawngate> {
+        let mut config = self.config;
+        config.resolve_profiles()?;
+        config.validate()?;
+        Spawngate::start(config, self.hooks, self.spawners, self.router).await
+    }
+}
+
+/// A running proxy with its admin API and background tasks
+pub struct Spawngate {
+    process_manager: Arc<ProcessManager>,
+    listeners: Listeners,
+    factory: ProxyFactory,
+    cert_resolver: Option<Arc<PinnedCertResolver>>,
+    watching_certs: bool,
+    debug_registry: Arc<DebugRegistry>,
+    admin_addr: SocketAddr,
+    admin_token: String,
+    shutdown_tx: watch::Sender<bool>,
+    supervisor: Arc<Supervisor>,
+    admin_task: JoinHandle<()>,
+    consul: Option<Arc<ConsulRegistrar>>,
+    discovery: Option<Arc<Discovery>>,
+    config: Arc<RwLock<Config>>,
+    shutdown: ShutdownConfig,
+}
+
+impl Spawngate {
+    pub fn builder() -> SpawngateBuilder {
+        SpawngateBuilder {
+            config: Config::default(),
+            hooks: Vec::new(),
+            spawners: Vec::new(),
+            router: None,
+        }
+    }
+
+    async fn start(
+        config: Config,
+        hooks: Vec<EventHook>,
+        spawners: Vec<(String, Arc<dyn Spawner>)>,
+        router: Option<MakeRouter>,
+    ) -> anyhow::Result<Self> {
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
+        // Backends from discovery files and KV stores are there from the start
+        let discovery = (config.server.file_sd.dir.is_some() || config.server.kv.enabled)
+            .then(|| Arc::new(Discovery::new(&config)));
+        let file_discovery = config.server.file_sd.dir.as_ref().zip(discovery.as_ref()).map(|(dir, discovery)| {
+            let files = Arc::new(FileDiscovery::new(Arc::clone(discovery), dir));
+            files.scan();
+            files
+        });
+        let kv_discovery = match &discovery {
+            Some(discovery) if config.server.kv.enabled => {
+                let kv = Arc::new(KvDiscovery::new(Arc::clone(discovery), &config.server.kv));
+                kv.poll().await;
+                Some(kv)
+            }
+            _ => None,
+        };
+        let config = match &discovery {
+            Some(discovery) => discovery.merge(&config)?,
+            None => config,
+        };
+
+        // Bound first so backends get the real address for ready callbacks
+        let admin_listener = TcpListener::bind(("127.0.0.1", config.server.admin_port))
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to bind admin API port {}: {}", config.server.admin_port, e))?;
+        let admin_addr = admin_listener.local_addr()?;
+
+        let process_manager = ProcessManager::new(
+            config.backends.clone(),
+            config.defaults.clone(),
+            format!("http://{}", admin_addr),
+        );
+        for hook in hooks {
+            process_manager.on_event(move |event| hook(event));
+        }
+        for (name, spawner) in spawners {
+            process_manager.register_spawner(name, spawner);
+        }
+        if let Some(path) = &config.server.config_vars_file {
+            process_manager.config_vars().persist_to(path)?;
+            info!(path = %path, "Config vars persisted to file");
+        }
+
+        let pool_config = PoolConfig {
+            max_idle_per_host: config.server.pool_max_idle_per_host,
+            idle_timeout: Duration::from_secs(config.server.pool_idle_timeout_secs),
+        };
+
+        info!(
+            max_idle = pool_config.max_idle_per_host,
+            idle_timeout_secs = pool_config.idle_timeout.as_secs(),
+            "Connection pool configured"
+        );
+
+        // Tasks and connections listed by the admin API's debug endpoints
+        let debug_registry = Arc::new(DebugRegistry::new());
+        let supervisor = Arc::new(Supervisor::new(Arc::clone(&debug_registry)));
+
+        // One pool for both listeners if configured, otherwise one each
+        let shared_pool = config.server.pool_shared.then(|| {
+            info!("Connection pool shared between HTTP and HTTPS listeners");
+            Arc::new(ConnectionPool::with_registry(pool_config.clone(), Arc::clone(&debug_registry)))
+        });
+
+        // PUBLIC_URL for backends: prefer HTTPS when it's the only or the forced listener
+        let (https_port, http_port) = (config.server.https_port(), config.server.http_port());
+        if https_port > 0 && (config.server.force_https || http_port == 0) {
+            process_manager.set_public_endpoint("https", https_port);
+        } else {
+            process_manager.set_public_endpoint("http", http_port);
+        }
+
+        // One throttle shared by the HTTP and HTTPS listeners so a client can't double its budget
+        let cold_start_throttle = config.server.cold_start_throttle.enabled.then(|| {
+            let throttle = &config.server.cold_start_throttle;
+            info!(
+                burst = throttle.burst,
+                window_secs = throttle.window_secs,
+                ban_secs = throttle.ban_secs,
+                "Cold-start throttle enabled"
+            );
+            Arc::new(ColdStartThrottle::new(throttle))
+        });
+
+        // Request metrics shared by both listeners, only collected when exported
+        let request_metrics = config
+            .server
+            .metrics_export
+            .enabled
+            .then(|| Arc::new(RequestMetrics::new()));
+
+        // Sampled request traces shared by both listeners
+        let trace_sampler = config.server.trace_sampling.enabled.then(|| {
+            let sampling = &config.server.trace_sampling;
+            info!(
+                force_header = %sampling.force_header,
+                rates = ?sampling.rates,
+                default_rate = sampling.default_rate,
+                "Request trace sampling enabled"
+            );
+            Arc::new(TraceSampler::new(sampling, Arc::clone(&process_manager)))
+        });
+
+        // Slow request log shared by both listeners and the admin API
+        let slow_log = config.server.slow_requests.enabled.then(|| {
+            let slow = &config.server.slow_requests;
+            info!(threshold_ms = slow.threshold_ms, window_secs = slow.window_secs, "Slow request log enabled");
+            Arc::new(SlowRequestLog::new(slow))
+        });
+
+        // Cold-start SLO shared by both listeners and the admin API
+        let cold_start_slo = config.server.cold_start_slo.enabled.then(|| {
+            let slo = &config.server.cold_start_slo;
+            info!(
+                threshold_ms = slo.threshold_ms,
+                target = slo.target,
+                window_secs = slo.window_secs,
+                "Cold-start SLO tracking enabled"
+            );
+            Arc::new(ColdStartSlo::new(slo, Arc::clone(&process_manager)))
+        });
+
+        // Per-variant experiment metrics; experiments can be added by a reload
+        let experiment_metrics = Arc::new(ExperimentMetrics::new());
+
+        let (tls_acceptor, acme_manager, cert_resolver) = tls_setup(&config)?;
+
+        // Watch certificate files so renewals by external tooling are picked up
+        let cert_watcher = cert_resolver
+            .clone()
+            .filter(|_| config.server.cert_watch.enabled)
+            .map(|resolver| {
+                let mut watcher = CertWatcher::new(resolver, &config.server.cert_watch);
+                if let (false, Some(cert), Some(key)) =
+                    (config.acme_enabled(), &config.server.tls_cert, &config.server.tls_key)
+                {
+                    watcher.watch(CertTarget::Default, cert, key);
+                }
+                for (hostname, cert, key) in config.pinned_certs() {
+                    watcher.watch(CertTarget::Host(hostname.to_string()), cert, key);
+                }
+                Arc::new(watcher)
+            })
+            .filter(|watcher| !watcher.is_empty());
+        let watching_certs = cert_watcher.is_some();
+        if let Some(watcher) = &cert_watcher {
+            let watcher = Arc::clone(watcher);
+            let shutdown_rx = shutdown_rx.clone();
+            supervisor.spawn("cert-watch", "certificate watcher", move || {
+                Arc::clone(&watcher).run(shutdown_rx.clone())
+            });
+        }
+
+        // Get ACME HTTP-01 challenges if using HTTP-01 challenge type
+        let acme_http01_challenges = acme_manager.as_ref().and_then(|m| {
+            if config.server.acme.challenge_type == AcmeChallengeType::Http01 {
+                Some(m.http01_challenges())
+            } else {
+                None
+            }
+        });
+
+        // Handshake counters for the HTTPS listener, exposed on the admin API
+        let tls_metrics = tls_acceptor.is_some().then(|| Arc::new(TlsHandshakeMetrics::new()));
+
+        // Listener-independent parts of the proxy servers, reused when the
+        // listeners are replaced on reload
+        let factory = ProxyFactory {
+            process_manager: Arc::clone(&process_manager),
+            shared_defaults: process_manager.shared_defaults(),
+            pool_config,
+            shared_pool,
+            debug_registry: Arc::clone(&debug_registry),
+            router: router.map(|make| make(HostRouter::new(Arc::clone(&process_manager)))),
+            cold_start_throttle: cold_start_throttle.clone(),
+            request_metrics: request_metrics.clone(),
+            trace_sampler,
+            slow_log: slow_log.clone(),
+            cold_start_slo: cold_start_slo.clone(),
+            experiment_metrics: Arc::clone(&experiment_metrics),
+            acme_http01_challenges,
+            tls_acceptor,
+            tls_metrics: tls_metrics.clone(),
+            early_data: config.server.early_data.clone(),
+            fingerprinter: config
+                .server
+                .tls_fingerprint
+                .enabled
+                .then(|| Arc::new(Fingerprinter::new(&config.server.tls_fingerprint))),
+            path_normalization: config.server.path_normalization.clone(),
+            drain_timeout: Duration::from_secs(config.server.listener_drain_timeout_secs),
+            drain_close_keep_alive: config.server.drain_close_keep_alive,
+        };
+        let listeners = Listeners::start(ListenerSettings::new(&config.server), |settings, shutdown_rx| {
+            factory.servers(settings, shutdown_rx)
+        })
+        .await?;
+
+        // Spawn ACME manager task if configured
+        if let Some(manager) = acme_manager {
+            let shutdown_rx = shutdown_rx.clone();
+            supervisor.spawn("acme", "ACME manager", move || {
+                let manager = Arc::clone(&manager);
+                let shutdown = shutdown_rx.clone();
+                async move {
+                    if let Err(e) = manager.run(shutdown).await {
+                        error!(error = %e, "ACME manager error");
+                    }
+                }
+            });
+        }
+
+        // Generate or use configured admin token
+        let admin_token = config.server.admin_token.clone().unwrap_or_else(|| {
+            let token = uuid::Uuid::new_v4().to_string();
+            info!(token = %token, "Generated admin API token (configure admin_token to set a fixed value)");
+            token
+        });
+
+        // Services point at the proxy, which starts the backends on demand
+        let proxy_port = listeners.http_addr().or(listeners.https_addr()).map(|addr| addr.port());
+        let consul = match proxy_port {
+            Some(port) if config.server.consul.enabled => {
+                let consul = ConsulRegistrar::new(&config.server.consul, port);
+                consul.sync(&config.backends).await;
+                info!(address = %config.server.consul.address, "Backends registered in Consul");
+                Some(Arc::new(consul))
+            }
+            _ => None,
+        };
+
+        // Admin server (always HTTP for internal use)
+        let mut admin_server =
+            AdminServer::new(admin_addr, Arc::clone(&process_manager), shutdown_rx.clone(), admin_token.clone());
+        if let Some(throttle) = cold_start_throttle {
+            admin_server = admin_server.with_cold_start_throttle(throttle);
+        }
+        if let Some(watcher) = cert_watcher {
+            admin_server = admin_server.with_cert_watcher(watcher);
+        }
+        if let Some(metrics) = tls_metrics {
+            admin_server = admin_server.with_tls_metrics(metrics);
+        }
+        if let Some(slow_log) = slow_log {
+            admin_server = admin_server.with_slow_log(slow_log);
+        }
+        if let Some(slo) = cold_start_slo {
+            admin_server = admin_server.with_cold_start_slo(slo);
+        }
+        admin_server = admin_server.with_experiment_metrics(experiment_metrics);
+        if config.server.admin_lockout.enabled {
+            admin_server = admin_server.with_auth_lockout(Arc::new(AuthLockout::new(&config.server.admin_lockout)));
+        }
+        let current_config = Arc::new(RwLock::new(config.clone()));
+        admin_server = admin_server
+            .with_debug_registry(Arc::clone(&debug_registry))
+            .with_supervisor(Arc::clone(&supervisor))
+            .with_config(Arc::clone(&current_config))
+            .with_applier(Arc::new(Applier::new(
+                Arc::clone(&process_manager),
+                Arc::clone(&current_config),
+                discovery.clone(),
+                consul.clone(),
+            )));
+        if config.server.profiling.enabled {
+            admin_server = admin_server.with_profiler(Arc::new(Profiler::new(&config.server.profiling)));
+        }
+
+        debug_registry.spawn(
+            "idle backend cleanup",
+            Arc::clone(&process_manager).run_idle_cleanup(IDLE_CLEANUP_INTERVAL, shutdown_rx.clone()),
+        );
+
+        if let Some(metrics) = request_metrics {
+            let export = config.server.metrics_export.clone();
+            let manager = Arc::clone(&process_manager);
+            let shutdown_rx = shutdown_rx.clone();
+            supervisor.spawn("metrics-export", "metrics export", move || {
+                let selector = export.selector.clone();
+                let selector_manager = Arc::clone(&manager);
+                metrics::run_csv_export(
+                    Arc::clone(&metrics),
+                    PathBuf::from(&export.path),
+                    Duration::from_secs(export.interval_secs),
+                    move |hostname| {
+                        selector
+                            .as_ref()
+                            .is_none_or(|s| selector_manager.backend_matches(hostname, s))
+                    },
+                    shutdown_rx.clone(),
+                )
+            });
+        }
+
+        if let Some(path) = &config.server.shutdown.state_file {
+            if config.server.shutdown.state_interval_secs > 0 {
+                let manager = Arc::clone(&process_manager);
+                let path = PathBuf::from(path);
+                let interval = Duration::from_secs(config.server.shutdown.state_interval_secs);
+                let shutdown_rx = shutdown_rx.clone();
+                supervisor.spawn("state-snapshots", "state snapshots", move || {
+                    shutdown::run_state_export(Arc::clone(&manager), path.clone(), interval, shutdown_rx.clone())
+                });
+            }
+        }
+
+        let admin_task = debug_registry.spawn("admin server", async move {
+            if let Err(e) = admin_server.serve(admin_listener).await {
+                error!(error = %e, "Admin server error");
+            }
+        });
+
+        if let Some(files) = file_discovery {
+            let manager = Arc::clone(&process_manager);
+            let consul = consul.clone();
+            let interval = Duration::from_secs(config.server.file_sd.interval_secs);
+            let shutdown_rx = shutdown_rx.clone();
+            supervisor.spawn("file-sd", "file discovery", move || {
+                Arc::clone(&files).run(Arc::clone(&manager), consul.clone(), interval, shutdown_rx.clone())
+            });
+        }
+        if let Some(kv) = kv_discovery {
+            let manager = Arc::clone(&process_manager);
+            let consul = consul.clone();
+            let interval = Duration::from_secs(config.server.kv.interval_secs);
+            let shutdown_rx = shutdown_rx.clone();
+            supervisor.spawn("kv", "KV discovery", move || {
+                Arc::clone(&kv).run(Arc::clone(&manager), consul.clone(), interval, shutdown_rx.clone())
+            });
+        }
+
+        Ok(Self {
+            process_manager,
+            listeners,
+            factory,
+            cert_resolver,
+            watching_certs,
+            debug_registry,
+            admin_addr,
+            admin_token,
+            shutdown_tx,
+            supervisor,
+            admin_task,
+            consul,
+            discovery,
+            config: current_config,
+            shutdown: config.server.shutdown.clone(),
+        })
+    }
+
+    pub fn process_manager(&self) -> &Arc<ProcessManager> {
+        &self.process_manager
+    }
+
+    pub fn debug_registry(&self) -> &Arc<DebugRegistry> {
+        &self.debug_registry
+    }
+
+    /// Local address of the HTTP listener, if enabled
+    pub fn http_addr(&self) -> Option<SocketAddr> {
+        self.listeners.http_addr()
+    }
+
+    /// Local address of the HTTPS listener, if enabled
+    pub fn https_addr(&self) -> Option<SocketAddr> {
+        self.listeners.https_addr()
+    }
+
+    pub fn admin_addr(&self) -> SocketAddr {
+        self.admin_addr
+    }
+
+    /// Token for the admin API, generated unless configured
+    pub fn admin_token(&self) -> &str {
+        &self.admin_token
+    }
+
+    /// Apply a new configuration without restarting
+    ///
+    /// Listener settings are applied first; if they fail, the current
+    /// listeners keep serving and the backends are still updated. TLS, ACME,
+    /// Consul, discovery and the admin API keep their startup settings;
+    /// discovered backends are kept.
+    pub async fn reload(&mut self, mut config: Config) -> anyhow::Result<ReloadResult> {
+        config.resolve_profiles()?;
+        config.validate()?;
+        if let Some(discovery) = &self.discovery {
+            let merged = discovery.merge(&config)?;
+            discovery.set_base(&config);
+            config = merged;
+        }
+        *self.config.write() = config.clone();
+
+        match self.apply_listener_settings(&config).await {
+            Ok(true) => info!(
+                http = ?self.listeners.http_addr(),
+                https = ?self.listeners.https_addr(),
+                "Proxy listeners replaced"
+            ),
+            Ok(false) => {}
+            Err(e) => error!(error = %e, "Failed to apply listener settings; keeping current listeners"),
+        }
+        if let Some(consul) = &self.consul {
+            consul.sync(&config.backends).await;
+        }
+        self.process_manager.apply_config(config.backends, config.defaults).await
+    }
+
+    /// Replace the proxy listeners if their settings changed
+    ///
+    /// A new default certificate is loaded before anything changes and served
+    /// once the new listeners are up. Returns whether the listeners were replaced.
+    async fn apply_listener_settings(&mut self, config: &Config) -> anyhow::Result<bool> {
+        let settings = ListenerSettings::new(&config.server);
+        let default_cert = match &settings.tls_files {
+            Some((cert, key)) if self.listeners.settings().tls_files != settings.tls_files => {
+                Some(load_certified_key(cert, key)?)
+            }
+            _ => None,
+        };
+
+        let factory = &self.factory;
+        if !self
+            .listeners
+            .replace(settings.clone(), |settings, shutdown_rx| factory.servers(settings, shutdown_rx))
+            .await?
+        {
+            return Ok(false);
+        }
+
+        if let (Some(cert), Some(resolver)) = (default_cert, &self.cert_resolver) {
+            if resolver.set_default_cert(cert) {
+                info!(cert = ?settings.tls_files.as_ref().map(|(cert, _)| cert), "Default certificate replaced");
+                if self.watching_certs {
+                    warn!("cert_watch keeps watching the certificate files configured at startup until restart");
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Run the shutdown hooks, drain open connections, then stop every backend
+    /// and wait for the servers
+    pub async fn stop(self) {
+        // Record what was running, then let the hooks take the proxy out of
+        // rotation while it still serves
+        let state_file = self.shutdown.state_file.as_ref().map(PathBuf::from);
+        if let Some(path) = &state_file {
+            shutdown::export_state(&self.process_manager, path, "shutdown").await;
+        }
+        shutdown::run_hooks(&self.shutdown.hooks, state_file.as_deref()).await;
+
+        // The proxy listeners stop accepting and drain
+        let _ = self.shutdown_tx.send(true);
+        let listeners_stopped = tokio::spawn(self.listeners.shutdown());
+
+        if let Some(consul) = &self.consul {
+            consul.deregister_all().await;
+        }
+
+        // Backends keep running until in-flight requests and streaming
+        // responses finish, which the drain timeout bounds
+        info!("Waiting for open connections to drain...");
+        let _ = tokio::time::timeout(self.factory.drain_timeout + STOP_TIMEOUT, listeners_stopped).await;
+
+        info!("Stopping all backends...");
+        self.process_manager.stop_all().await;
+
+        self.supervisor.abort("acme");
+
+        let _ = tokio::time::timeout(STOP_TIMEOUT, async {
+            let _ = self.admin_task.await;
+            // Final export covers the last partial interval
+            self.supervisor.join("metrics-export").await;
+        })
+        .await;
+    }
+}
+
+/// TLS acceptor, ACME manager and certificate resolver for the configuration
+///
+/// Priority: ACME > file-based certs > self-signed. Backends with pinned
+/// certificate files override the selection for their hostname.
+#[allow(clippy::type_complexity)]
+fn tls_setup(
+    config: &Config,
+) -> anyhow::Result<(Option<TlsAcceptor>, Option<Arc<AcmeManager>>, Option<Arc<PinnedCertResolver>>)> {
+    if config.acme_enabled() {
+        // ACME/Let's Encrypt automatic certificate provisioning
+        let acme_config = config.acme_config();
+
+        // Create cache directory if it doesn't exist
+        std::fs::create_dir_all(&acme_config.cache_dir).map_err(|e| {
+            anyhow::anyhow!("Failed to create ACME cache directory '{}': {}", acme_config.cache_dir, e)
+        })?;
+
+        info!(
+            domains = ?acme_config.domains,
+            email = ?acme_config.email,
+            cache_dir = %acme_config.cache_dir,
+            challenge_type = ?acme_config.challenge_type,
+            "ACME/Let's Encrypt certificate provisioning enabled"
+        );
+
+        if acme_config.staging {
+            warn!("ACME staging directory in use; issued certificates are not trusted by browsers");
+        }
+
+        let manager = Arc::new(AcmeManager::new(acme_config)?);
+
+        // The ACME resolver serves the issued certificate once available,
+        // plus TLS-ALPN-01 challenge certificates when that challenge is used
+        let resolver = Arc::new(PinnedCertResolver::with_fallback(manager.tls_alpn01_resolver()));
+        pin_backend_certs(config, &resolver)?;
+
+        let acceptor = tls_acceptor(config, &resolver)?;
+        Ok((Some(acceptor), Some(manager), Some(resolver)))
+    } else if config.server.tls_enabled() {
+        let (certs, key) = match (&config.server.tls_cert, &config.server.tls_key) {
+            (Some(cert_path), Some(key_path)) => {
+                let certs = load_certs(cert_path)?;
+                let key = load_key(key_path)?;
+                info!(cert = %cert_path, key = %key_path, "TLS enabled with provided certificates");
+                (certs, key)
+            }
+            _ => {
+                let (certs, key) = generate_self_signed_cert()?;
+                warn!("TLS enabled with auto-generated self-signed certificate (not for production)");
+                (certs, key)
+            }
+        };
+
+        let resolver = Arc::new(PinnedCertResolver::with_default_cert(certified_key(certs, &key)?));
+        pin_backend_certs(config, &resolver)?;
+
+        let acceptor = tls_acceptor(config, &resolver)?;
+        Ok((Some(acceptor), None, Some(resolver)))
+    } else {
+        if !config.pinned_certs().is_empty() {
+            warn!("Backends pin certificate files but TLS is not enabled; certificates are ignored");
+        }
+        Ok((None, None, None))
+    }
+}
+
+fn tls_acceptor(config: &Config, resolver: &Arc<PinnedCertResolver>) -> anyhow::Result<TlsAcceptor> {
+    let mut tls_config = rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::clone(resolver) as Arc<dyn ResolvesServerCert>);
+    configure_session_resumption(&mut tls_config, &config.server.tls_session)?;
+    if config.server.early_data.enabled {
+        early_data::configure(&mut tls_config, config.server.early_data.max_size);
+    }
+    // Clients that offer h2 get HTTP/2; without ALPN they fall back to HTTP/1.1
+    tls_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    // Validation servers offer only acme-tls/1, and rustls refuses handshakes without a common protocol
+    if config.acme_enabled() && config.server.acme.challenge_type == AcmeChallengeType::TlsAlpn01 {
+        tls_config.alpn_protocols.push(ACME_TLS_ALPN_NAME.to_vec());
+    }
+    Ok(TlsAcceptor::from(Arc::new(tls_config)))
+}
+
+/// Builds the proxy servers for a set of listener settings
+struct ProxyFactory {
+    process_manager: Arc<ProcessManager>,
+    shared_defaults: SharedDefaults,
+    pool_config: PoolConfig,
+    shared_pool: Option<Arc<ConnectionPool>>,
+    debug_registry: Arc<DebugRegistry>,
+    router: Option<Arc<dyn Router>>,
+    cold_start_throttle: Option<Arc<ColdStartThrottle>>,
+    request_metrics: Option<Arc<RequestMetrics>>,
+    trace_sampler: Option<Arc<TraceSampler>>,
+    slow_log: Option<Arc<SlowRequestLog>>,
+    cold_start_slo: Option<Arc<ColdStartSlo>>,
+    experiment_metrics: Arc<ExperimentMetrics>,
+    acme_http01_challenges: Option<Http01Challenges>,
+    tls_acceptor: Option<TlsAcceptor>,
+    tls_metrics: Option<Arc<TlsHandshakeMetrics>>,
+    early_data: EarlyDataConfig,
+    fingerprinter: Option<Arc<Fingerprinter>>,
+    path_normalization: PathNormalizationConfig,
+    drain_timeout: Duration,
+    drain_close_keep_alive: bool,
+}
+
+impl ProxyFactory {
+    fn servers(&self, settings: &ListenerSettings, shutdown_rx: watch::Receiver<bool>) -> anyhow::Result<ProxyServers> {
+        let mut servers = ProxyServers::default();
+
+        if let Some(addr) = settings.http_addr()? {
+            let mut http_proxy = self.server(addr, shutdown_rx.clone());
+
+            // Add ACME HTTP-01 challenge handler if configured
+            if let Some(challenges) = self.acme_http01_challenges.clone() {
+                http_proxy = http_proxy.with_acme_challenges(challenges);
+                info!("ACME HTTP-01 challenge handler enabled on HTTP port");
+            }
+
+            // If force_https is enabled and HTTPS is available, redirect HTTP to HTTPS
+            // Note: ACME challenges are handled before redirect
+            if settings.force_https && settings.https_port > 0 {
+                let status = hyper::StatusCode::from_u16(settings.https_redirect_status)
+                    .map_err(|e| anyhow::anyhow!("Invalid https_redirect_status: {}", e))?;
+                http_proxy = http_proxy
+                    .with_https_redirect(settings.https_port)
+                    .with_https_redirect_status(status);
+                info!(http_port = settings.http_port, https_port = settings.https_port, "HTTP to HTTPS redirect enabled");
+            }
+            servers.http = Some(http_proxy);
+        }
+
+        if let Some(addr) = settings.https_addr()? {
+            let acceptor = self
+                .tls_acceptor
+                .clone()
+                .ok_or_else(|| anyhow::anyhow!("TLS was not enabled at startup; enabling it requires a restart"))?;
+            let mut https_proxy = self.server(addr, shutdown_rx).with_tls(acceptor);
+            if let Some(metrics) = self.tls_metrics.clone() {
+                https_proxy = https_proxy.with_tls_metrics(metrics);
+            }
+            if self.early_data.enabled {
+                info!(methods = ?self.early_data.methods, "TLS early data (0-RTT) enabled");
+                https_proxy = https_proxy.with_early_data(self.early_data.clone());
+            }
+            servers.https = Some(https_proxy);
+        }
+
+        Ok(servers)
+    }
+
+    /// A proxy server with the stages shared by both listeners
+    fn server(&self, addr: SocketAddr, shutdown_rx: watch::Receiver<bool>) -> ProxyServer {
+        let pool = self.shared_pool.clone().unwrap_or_else(|| {
+            Arc::new(ConnectionPool::with_registry(self.pool_config.clone(), Arc::clone(&self.debug_registry)))
+        });
+        let mut proxy = ProxyServer::new(
+            addr,
+            Arc::clone(&self.process_manager),
+            Arc::clone(&self.shared_defaults),
+            shutdown_rx,
+        )
+        .with_pool(pool)
+        .with_debug_registry(Arc::clone(&self.debug_registry))
+        .with_drain_timeout(self.drain_timeout)
+        .with_drain_close_keep_alive(self.drain_close_keep_alive)
+        .with_path_normalization(self.path_normalization.clone());
+
+        if let Some(router) = self.router.clone() {
+            proxy = proxy.with_router(router);
+        }
+        if let Some(throttle) = self.cold_start_throttle.clone() {
+            proxy = proxy.with_cold_start_throttle(throttle);
+        }
+        if let Some(metrics) = self.request_metrics.clone() {
+            proxy = proxy.with_request_metrics(metrics);
+        }
+        if let Some(sampler) = self.trace_sampler.clone() {
+            proxy = proxy.with_trace_sampler(sampler);
+        }
+        if let Some(slow_log) = self.slow_log.clone() {
+            proxy = proxy.with_slow_log(slow_log);
+        }
+        if let Some(slo) = self.cold_start_slo.clone() {
+            proxy = proxy.with_cold_start_slo(slo);
+        }
+        // Also set on the HTTP listener, so clients cannot send their own fingerprint headers
+        if let Some(fingerprinter) = self.fingerprinter.clone() {
+            proxy = proxy.with_tls_fingerprint(fingerprinter);
+        }
+        proxy.with_experiment_metrics(Arc::clone(&self.experiment_metrics))
+    }
+}
+
+/// Load pinned per-backend certificates into the resolver
+fn pin_backend_certs(config: &Config, resolver: &PinnedCertResolver) -> anyhow::Result<()> {
+    for (hostname, cert_path, key_path) in config.pinned_certs() {
+        resolver.pin_files(hostname, cert_path, key_path)?;
+        info!(hostname, cert = %cert_path, "Serving pinned certificate");
+    }
+    Ok(())
+}
+
+fn generate_self_signed_cert() -> anyhow::Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let subject_alt_names = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+
+    let CertifiedKey { cert, key_pair } = generate_simple_self_signed(subject_alt_names)
+        .map_err(|e| anyhow::anyhow!("Failed to generate self-signed certificate: {}", e))?;
+
+    let cert_der = CertificateDer::from(cert.der().to_vec());
+    let key_der = PrivateKeyDer::try_from(key_pair.serialize_der())
+        .map_err(|e| anyhow::anyhow!("Failed to serialize private key: {}", e))?;
+
+    Ok((vec![cert_der], key_der))
+}
@@ -0,0 +1,454 @@
+//! Declarative apply: plan and apply a desired set of backends
+//!
+//! `POST /apply` on the admin API takes a file in the configuration format;
+//! its `[defaults]` and `[backends]` (with `[profiles]` resolved) replace the
+//! running ones, while `[server]` is ignored. Backends missing from the file
+//! are removed, including those created with `PUT /backends/{hostname}`.
+//!
+//! A dry run returns the plan: backends to add, update (with the settings
+//! that change once defaults are applied) and remove, and which running
+//! backends restart to pick up their changes. The plan's ETag can be sent as
+//! `If-Match` when applying, so nothing is applied if the plan changed since
+//! it was reviewed. `spawngate apply -f` does both steps.
+
+use crate::config::{redact_secrets, BackendConfig, BackendDefaults, Config};
+use crate::discovery::Discovery;
+use crate::hashicorp::ConsulRegistrar;
+use crate::process::{BackendState, Precondition, ProcessManager};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::header::{AUTHORIZATION, ETAG, IF_MATCH};
+use hyper::{Method, Request, StatusCode};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::info;
+
+/// Time allowed for a call to the admin API
+const ADMIN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A setting that differs between two definitions
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: serde_json::Value,
+    pub new: serde_json::Value,
+}
+
+/// Changes with the values of secrets redacted, as in the effective configuration
+pub(crate) fn redacted(changes: Vec<FieldChange>) -> Vec<FieldChange> {
+    changes
+        .into_iter()
+        .map(|change| {
+            let field = &change.field;
+            let mut values = serde_json::json!({ "old": { field: change.old }, "new": { field: change.new } });
+            redact_secrets(&mut values);
+            FieldChange {
+                old: values["old"][field].take(),
+                new: values["new"][field].take(),
+                field: change.field,
+            }
+        })
+        .collect()
+}
+
+/// Top-level settings that differ between two definitions, `null` standing
+/// for a missing definition or setting
+pub fn diff_fields(old: &serde_json::Value, new: &serde_json::Value) -> Vec<FieldChange> {
+    let empty = serde_json::Map::new();
+    let old = old.as_object().unwrap_or(&empty);
+    let new = new.as_object().unwrap_or(&empty);
+    let fields: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    fields
+        .into_iter()
+        .filter_map(|field| {
+            let old = old.get(field).unwrap_or(&serde_json::Value::Null);
+            let new = new.get(field).unwrap_or(&serde_json::Value::Null);
+            (old != new).then(|| FieldChange {
+                field: field.clone(),
+                old: old.clone(),
+                new: new.clone(),
+            })
+        })
+        .collect()
+}
+
+/// A backend whose settings change
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BackendUpdate {
+    pub hostname: String,
+    pub changes: Vec<FieldChange>,
+    /// The backend is running and restarts to pick up the changes
+    pub restart: bool,
+}
+
+/// Changes that turn the running backends into the desired ones
+///
+/// Values of secrets are redacted; a changed secret is still listed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Plan {
+    pub add: Vec<String>,
+    pub update: Vec<BackendUpdate>,
+    pub remove: Vec<String>,
+    /// Changes to `[defaults]`, already included in each backend's changes
+    pub defaults: Vec<FieldChange>,
+}
+
+impl Plan {
+    /// Compare backends with defaults applied, so a changed default shows
+    /// up on every backend it affects
+    pub fn new(
+        current: &HashMap<String, BackendConfig>,
+        current_defaults: &BackendDefaults,
+        desired: &HashMap<String, BackendConfig>,
+        desired_defaults: &BackendDefaults,
+        running: impl Fn(&str) -> bool,
+    ) -> Self {
+        let resolved = |backend: &BackendConfig, defaults: &BackendDefaults| {
+            let mut backend = backend.clone();
+            backend.apply_defaults(defaults);
+            serde_json::to_value(backend).unwrap_or_default()
+        };
+
+        let mut plan = Plan {
+            defaults: redacted(diff_fields(
+                &serde_json::to_value(current_defaults).unwrap_or_default(),
+                &serde_json::to_value(desired_defaults).unwrap_or_default(),
+            )),
+            ..Default::default()
+        };
+        let hostnames: BTreeSet<&String> = current.keys().chain(desired.keys()).collect();
+        for hostname in hostnames {
+            match (current.get(hostname), desired.get(hostname)) {
+                (None, Some(_)) => plan.add.push(hostname.clone()),
+                (Some(_), None) => plan.remove.push(hostname.clone()),
+                (Some(old), Some(new)) => {
+                    let changes = diff_fields(&resolved(old, current_defaults), &resolved(new, desired_defaults));
+                    if !changes.is_empty() {
+                        plan.update.push(BackendUpdate {
+                            hostname: hostname.clone(),
+                            changes: redacted(changes),
+                            restart: running(hostname),
+                        });
+                    }
+                }
+                (None, None) => {}
+            }
+        }
+        plan
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.add.is_empty() && self.update.is_empty() && self.remove.is_empty() && self.defaults.is_empty()
+    }
+
+    /// Backends restarted when the plan is applied
+    pub fn restarts(&self) -> impl Iterator<Item = &str> {
+        self.update.iter().filter(|u| u.restart).map(|u| u.hostname.as_str())
+    }
+
+    /// Strong validator of the plan, to apply only what was reviewed
+    pub fn etag(&self) -> String {
+        let json = serde_json::to_string(self).unwrap_or_default();
+        let digest = ring::digest::digest(&ring::digest::SHA256, json.as_bytes());
+        let hex: String = digest.as_ref()[..8].iter().map(|b| format!("{:02x}", b)).collect();
+        format!("\"{}\"", hex)
+    }
+}
+
+/// Lists the changes like a diff: `+` added, `~` updated, `-` removed
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let changes = |f: &mut fmt::Formatter<'_>, changes: &[FieldChange]| {
+            changes
+                .iter()
+                .try_for_each(|c| writeln!(f, "      {}: {} -> {}", c.field, c.old, c.new))
+        };
+        if !self.defaults.is_empty() {
+            writeln!(f, "  ~ [defaults]")?;
+            changes(f, &self.defaults)?;
+        }
+        for hostname in &self.add {
+            writeln!(f, "  + {}", hostname)?;
+        }
+        for update in &self.update {
+            let restart = if update.restart { " (restarts)" } else { "" };
+            writeln!(f, "  ~ {}{}", update.hostname, restart)?;
+            changes(f, &update.changes)?;
+        }
+        for hostname in &self.remove {
+            writeln!(f, "  - {}", hostname)?;
+        }
+        write!(
+            f,
+            "{} to add, {} to update, {} to remove, {} to restart",
+            self.add.len(),
+            self.update.len(),
+            self.remove.len(),
+            self.restarts().count()
+        )
+    }
+}
+
+/// Why a desired configuration was not applied
+#[derive(Debug)]
+pub enum ApplyError {
+    /// The desired configuration is invalid
+    Invalid(anyhow::Error),
+    /// The plan no longer has the expected ETag; nothing was changed
+    PlanChanged(Plan),
+}
+
+impl fmt::Display for ApplyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApplyError::Invalid(e) => write!(f, "{}", e),
+            ApplyError::PlanChanged(_) => f.write_str("plan changed since it was reviewed"),
+        }
+    }
+}
+
+/// Plans and applies desired configurations against the running proxy
+pub struct Applier {
+    process_manager: Arc<ProcessManager>,
+    /// Configuration the proxy runs with, updated on apply
+    config: Arc<RwLock<Config>>,
+    discovery: Option<Arc<Discovery>>,
+    consul: Option<Arc<ConsulRegistrar>>,
+    /// Applies one plan at a time
+    lock: tokio::sync::Mutex<()>,
+}
+
+impl Applier {
+    pub fn new(
+        process_manager: Arc<ProcessManager>,
+        config: Arc<RwLock<Config>>,
+        discovery: Option<Arc<Discovery>>,
+        consul: Option<Arc<ConsulRegistrar>>,
+    ) -> Self {
+        Self {
+            process_manager,
+            config,
+            discovery,
+            consul,
+            lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// The current configuration with the desired backends and defaults, and
+    /// the same with discovered backends added
+    fn desired(&self, desired: &Config) -> anyhow::Result<(Config, Config)> {
+        let mut base = self.config.read().clone();
+        base.profiles = desired.profiles.clone();
+        base.defaults = desired.defaults.clone();
+        base.backends = desired.backends.clone();
+        let merged = match &self.discovery {
+            Some(discovery) => discovery.merge(&base)?,
+            None => base.clone(),
+        };
+        Ok((base, merged))
+    }
+
+    fn plan_for(&self, merged: &Config) -> Plan {
+        let manager = &self.process_manager;
+        Plan::new(
+            &manager.get_configs(),
+            &manager.get_defaults(),
+            &merged.backends,
+            &merged.defaults,
+            |hostname| manager.get_state(hostname) != BackendState::Stopped,
+        )
+    }
+
+    /// What applying `desired` would change
+    pub fn plan(&self, desired: &Config) -> anyhow::Result<Plan> {
+        let (_, merged) = self.desired(desired)?;
+        Ok(self.plan_for(&merged))
+    }
+
+    /// Apply `desired`, if given only when the plan still has the ETag `expected`
+    pub async fn apply(&self, desired: &Config, expected: Option<&str>) -> Result<Plan, ApplyError> {
+        let _guard = self.lock.lock().await;
+        let (base, merged) = self.desired(desired).map_err(ApplyError::Invalid)?;
+        let plan = self.plan_for(&merged);
+        if expected.is_some_and(|etag| etag != plan.etag()) {
+            return Err(ApplyError::PlanChanged(plan));
+        }
+
+        if let Some(discovery) = &self.discovery {
+            discovery.set_base(&base);
+        }
+        if let Some(consul) = &self.consul {
+            consul.sync(&merged.backends).await;
+        }
+        let manager = &self.process_manager;
+        manager
+            .apply_config(merged.backends.clone(), merged.defaults.clone())
+            .await
+            .map_err(ApplyError::Invalid)?;
+        // Reloads keep backends created through the admin API; an apply doesn't
+        for hostname in &plan.remove {
+            let _ = manager.remove_api_backend(hostname, &Precondition::default()).await;
+        }
+        for hostname in plan.restarts() {
+            manager.restart_backend(hostname);
+        }
+        *self.config.write() = merged;
+
+        info!(
+            added = plan.add.len(),
+            updated = plan.update.len(),
+            removed = plan.remove.len(),
+            restarted = plan.restarts().count(),
+            "Applied desired configuration"
+        );
+        Ok(plan)
+    }
+}
+
+type AdminHttpClient = Client<HttpsConnector<HttpConnector>, Full<Bytes>>;
+
+/// Sends desired configurations to the admin API of a running proxy
+pub struct RemoteApply {
+    client: AdminHttpClient,
+    address: String,
+    token: String,
+}
+
+impl RemoteApply {
+    /// `address` is the admin API's base URL, `http://` or `https://`
+    pub fn new(address: &str, token: String) -> anyhow::Result<Self> {
+        let connector = HttpsConnectorBuilder::new()
+            .with_provider_and_native_roots(rustls::crypto::ring::default_provider())?
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Ok(Self {
+            client: Client::builder(TokioExecutor::new()).build(connector),
+            address: address.trim_end_matches('/').to_string(),
+            token,
+        })
+    }
+
+    /// The plan for `desired` (the file's content) and its ETag
+    pub async fn plan(&self, desired: &str) -> anyhow::Result<(Plan, String)> {
+        self.send("/apply?dry_run=true", desired, None).await
+    }
+
+    /// Apply `desired` if its plan still has the ETag `expected`
+    pub async fn apply(&self, desired: &str, expected: &str) -> anyhow::Result<Plan> {
+        Ok(self.send("/apply", desired, Some(expected)).await?.0)
+    }
+
+    async fn send(&self, path: &str, desired: &str, if_match: Option<&str>) -> anyhow::Result<(Plan, String)> {
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(format!("{}{}", self.address, path))
+            .header(AUTHORIZATION, format!("Bearer {}", self.token))
+            .header("content-type", "application/toml");
+        if let Some(etag) = if_match {
+            request = request.header(IF_MATCH, etag);
+        }
+        let request = request.body(Full::new(Bytes::from(desired.to_string())))?;
+
+        let response = tokio::time::timeout(ADMIN_TIMEOUT, self.client.request(request))
+            .await
+            .map_err(|_| anyhow::anyhow!("Request to {} timed out", self.address))??;
+        let status = response.status();
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let body = response.into_body().collect().await?.to_bytes();
+        match status {
+            StatusCode::OK => {
+                #[derive(Deserialize)]
+                struct Response {
+                    plan: Plan,
+                }
+                let response: Response = serde_json::from_slice(&body)
+                    .map_err(|e| anyhow::anyhow!("Unexpected response from {}: {}", self.address, e))?;
+                Ok((response.plan, etag))
+            }
+            StatusCode::PRECONDITION_FAILED => {
+                anyhow::bail!("The plan changed since it was shown; nothing was applied, run apply again")
+            }
+            status => anyhow::bail!("{} returned {}: {}", self.address, status, String::from_utf8_lossy(&body).trim()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan() {
+        let mut web = BackendConfig::local("./web", 3000);
+        web.idle_timeout_secs = Some(60);
+        let current = HashMap::from([
+            ("web.local".to_string(), web.clone()),
+            ("api.local".to_string(), BackendConfig::local("./api", 3001)),
+            ("old.local".to_string(), BackendConfig::local("./old", 3002)),
+        ]);
+        let defaults = BackendDefaults::default();
+
+        // Same backends: nothing to do
+        let plan = Plan::new(&current, &defaults, &current, &defaults, |_| true);
+        assert!(plan.is_empty(), "{:?}", plan);
+
+        // Setting explicitly what the default already gives changes nothing
+        let mut desired = current.clone();
+        desired.remove("old.local");
+        desired.get_mut("api.local").unwrap().request_timeout_secs = Some(defaults.request_timeout_secs);
+        desired.insert("new.local".to_string(), BackendConfig::local("./new", 3003));
+        let new_defaults = BackendDefaults {
+            idle_timeout_secs: 120,
+            ..Default::default()
+        };
+        let plan = Plan::new(&current, &defaults, &desired, &new_defaults, |hostname| hostname == "api.local");
+        assert_eq!(plan.add, vec!["new.local"]);
+        assert_eq!(plan.remove, vec!["old.local"]);
+        assert_eq!(plan.defaults.len(), 1);
+        assert_eq!(plan.defaults[0].field, "idle_timeout_secs");
+
+        // The new default idle timeout only affects api.local, which is running
+        assert_eq!(plan.update.len(), 1);
+        assert_eq!(plan.update[0].hostname, "api.local");
+        assert!(plan.update[0].restart);
+        assert_eq!(
+            plan.update[0].changes,
+            vec![FieldChange {
+                field: "idle_timeout_secs".to_string(),
+                old: 600.into(),
+                new: 120.into(),
+            }]
+        );
+        assert_eq!(plan.restarts().collect::<Vec<_>>(), vec!["api.local"]);
+
+        let text = plan.to_string();
+        assert!(text.contains("  ~ api.local (restarts)\n      idle_timeout_secs: 600 -> 120\n"), "{}", text);
+        assert!(text.ends_with("1 to add, 1 to update, 1 to remove, 1 to restart"), "{}", text);
+
+        // Changed secrets are listed without their values
+        let mut rotated = current.clone();
+        let api = rotated.get_mut("api.local").unwrap();
+        api.env.insert("DATABASE_PASSWORD".to_string(), "new".to_string());
+        let plan = Plan::new(&current, &defaults, &rotated, &defaults, |_| false);
+        assert_eq!(plan.update[0].changes[0].field, "env");
+        assert_eq!(plan.update[0].changes[0].old, serde_json::json!({}));
+        assert_eq!(plan.update[0].changes[0].new, serde_json::json!({ "DATABASE_PASSWORD": "<redacted>" }));
+
+        assert_eq!(plan.etag(), plan.clone().etag());
+        assert_ne!(plan.etag(), Plan::default().etag());
+    }
+}
@@ -0,0 +1,382 @@
+//! Expiry checks and hot reload for file-based certificates
+//!
+//! Certificates renewed by external tooling (certbot, cert-manager sidecars)
+//! are picked up by polling file modification times, so replacing the files
+//! is enough and no restart or SIGHUP is needed. A replaced pair is only
+//! swapped in once both files parse and the key matches the certificate;
+//! until then the previous certificate keeps being served.
+
+use crate::config::CertWatchConfig;
+use crate::tls::{load_certified_key, not_after, PinnedCertResolver};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+use tracing::{debug, error, info, warn};
+
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Minimum time between repeated expiry warnings for the same certificate
+const EXPIRY_WARNING_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Where a watched certificate is served
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CertTarget {
+    /// The server-wide certificate (`tls_cert`/`tls_key`)
+    Default,
+    /// A backend's pinned certificate
+    Host(String),
+}
+
+/// Validity of a watched certificate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExpiryState {
+    Valid,
+    /// Within `renew_before_days` of expiry
+    RenewalOverdue,
+    Expired,
+}
+
+/// Status of a watched certificate, as exposed through the admin API
+#[derive(Debug, Clone, Serialize)]
+pub struct CertStatus {
+    /// Hostname the certificate is pinned to, or `None` for the default certificate
+    pub hostname: Option<String>,
+    pub cert_path: String,
+    pub key_path: String,
+    /// Unix timestamp of the certificate's notAfter
+    pub not_after: Option<i64>,
+    pub days_remaining: Option<i64>,
+    pub state: Option<ExpiryState>,
+    /// Times the files were reloaded since startup
+    pub reloads: u64,
+    /// Why the current files couldn't be loaded, if they couldn't
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct WatchState {
+    modified: Option<SystemTime>,
+    not_after: Option<i64>,
+    reloads: u64,
+    last_error: Option<String>,
+    last_warning: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct WatchedCert {
+    target: CertTarget,
+    cert_path: String,
+    key_path: String,
+    state: Mutex<WatchState>,
+}
+
+/// Polls certificate files, reloading replaced ones into a resolver
+#[derive(Debug)]
+pub struct CertWatcher {
+    resolver: Arc<PinnedCertResolver>,
+    certs: Vec<WatchedCert>,
+    renew_before_days: i64,
+    interval: Duration,
+}
+
+impl CertWatcher {
+    pub fn new(resolver: Arc<PinnedCertResolver>, config: &CertWatchConfig) -> Self {
+        Self {
+            resolver,
+            certs: Vec::new(),
+            renew_before_days: config.renew_before_days as i64,
+            interval: Duration::from_secs(config.interval_secs),
+        }
+    }
+
+    /// Watch a certificate/key pair that is already being served for `target`
+    pub fn watch(&mut self, target: CertTarget, cert_path: &str, key_path: &str) {
+        let state = WatchState {
+            modified: modified(cert_path, key_path),
+            not_after: read_not_after(cert_path),
+            ..WatchState::default()
+        };
+        self.certs.push(WatchedCert {
+            target,
+            cert_path: cert_path.to_string(),
+            key_path: key_path.to_string(),
+            state: Mutex::new(state),
+        });
+    }
+
+    /// Number of watched certificates
+    pub fn len(&self) -> usize {
+        self.certs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.certs.is_empty()
+    }
+
+    /// Reload replaced files and check expiry of every watched certificate
+    pub fn check(&self) {
+        let now = unix_now();
+        for cert in &self.certs {
+            self.reload_if_changed(cert);
+
+            let mut state = cert.state.lock();
+            let Some(expiry) = state.not_after else { continue };
+            let days = (expiry - now).div_euclid(SECS_PER_DAY);
+            let expiry_state = expiry_state(expiry, now, self.renew_before_days);
+            if expiry_state == ExpiryState::Valid {
+                state.last_warning = None;
+                continue;
+            }
+            if state.last_warning.is_some_and(|at| at.elapsed() < EXPIRY_WARNING_INTERVAL) {
+                continue;
+            }
+            state.last_warning = Some(Instant::now());
+            match expiry_state {
+                ExpiryState::Expired => error!(
+                    hostname = ?target_hostname(&cert.target),
+                    cert = %cert.cert_path,
+                    "Certificate has expired and was not replaced"
+                ),
+                _ => warn!(
+                    hostname = ?target_hostname(&cert.target),
+                    cert = %cert.cert_path,
+                    days_remaining = days,
+                    "Certificate renewal overdue"
+                ),
+            }
+        }
+    }
+
+    fn reload_if_changed(&self, cert: &WatchedCert) {
+        let modified = modified(&cert.cert_path, &cert.key_path);
+        if modified.is_none() || modified == cert.state.lock().modified {
+            return;
+        }
+
+        match load_certified_key(&cert.cert_path, &cert.key_path) {
+            Ok(key) => {
+                let expiry = key.end_entity_cert().ok().and_then(not_after);
+                match &cert.target {
+                    CertTarget::Default => {
+                        self.resolver.set_default_cert(key);
+                    }
+                    CertTarget::Host(hostname) => self.resolver.pin(hostname, key),
+                }
+                let mut state = cert.state.lock();
+                state.modified = modified;
+                state.not_after = expiry;
+                state.reloads += 1;
+                state.last_error = None;
+                state.last_warning = None;
+                info!(
+                    hostname = ?target_hostname(&cert.target),
+                    cert = %cert.cert_path,
+                    "Reloaded replaced certificate"
+                );
+            }
+            Err(e) => {
+                // Leave `modified` unchanged so the next pass retries, e.g. when
+                // the certificate was replaced but the key not yet
+                let message = e.to_string();
+                let mut state = cert.state.lock();
+                if state.last_error.as_ref() != Some(&message) {
+                    warn!(cert = %cert.cert_path, error = %message, "Replaced certificate not loaded yet");
+                }
+                state.last_error = Some(message);
+            }
+        }
+    }
+
+    /// Status of every watched certificate
+    pub fn statuses(&self) -> Vec<CertStatus> {
+        let now = unix_now();
+        self.certs
+            .iter()
+            .map(|cert| {
+                let state = cert.state.lock();
+                CertStatus {
+                    hostname: target_hostname(&cert.target).map(str::to_string),
+                    cert_path: cert.cert_path.clone(),
+                    key_path: cert.key_path.clone(),
+                    not_after: state.not_after,
+                    days_remaining: state.not_after.map(|t| (t - now).div_euclid(SECS_PER_DAY)),
+                    state: state.not_after.map(|t| expiry_state(t, now, self.renew_before_days)),
+                    reloads: state.reloads,
+                    last_error: state.last_error.clone(),
+                }
+            })
+            .collect()
+    }
+
+    /// Check certificates every interval until shutdown
+    pub async fn run(self: Arc<Self>, mut shutdown_rx: watch::Receiver<bool>) {
+        info!(
+            certificates = self.certs.len(),
+            interval_secs = self.interval.as_secs(),
+            "Watching certificate files"
+        );
+        let mut ticker = tokio::time::interval(self.interval);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    let watcher = Arc::clone(&self);
+                    if let Err(e) = tokio::task::spawn_blocking(move || watcher.check()).await {
+                        error!(error = %e, "Certificate check failed");
+                    }
+                    debug!("Checked watched certificates");
+                }
+                _ = shutdown_rx.changed() => {
+                    if *shutdown_rx.borrow() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn target_hostname(target: &CertTarget) -> Option<&str> {
+    match target {
+        CertTarget::Default => None,
+        CertTarget::Host(hostname) => Some(hostname),
+    }
+}
+
+fn expiry_state(not_after: i64, now: i64, renew_before_days: i64) -> ExpiryState {
+    if not_after <= now {
+        ExpiryState::Expired
+    } else if not_after - now < renew_before_days * SECS_PER_DAY {
+        ExpiryState::RenewalOverdue
+    } else {
+        ExpiryState::Valid
+    }
+}
+
+/// Latest modification time of the pair; a change to either triggers a reload
+fn modified(cert_path: &str, key_path: &str) -> Option<SystemTime> {
+    let mtime = |path: &str| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    mtime(cert_path).max(mtime(key_path))
+}
+
+fn read_not_after(cert_path: &str) -> Option<i64> {
+    crate::tls::load_certs(cert_path).ok()?.first().and_then(not_after)
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls::certified_key;
+    use rcgen::{CertificateParams, KeyPair};
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use std::path::{Path, PathBuf};
+
+    /// Write a self-signed certificate valid for `days` and return its paths
+    fn write_cert(dir: &Path, name: &str, days: i64) -> (String, String) {
+        let mut params = CertificateParams::new(vec!["watch.local".to_string()]).unwrap();
+        params.not_after = time::OffsetDateTime::now_utc() + time::Duration::days(days);
+        let key = KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+
+        let cert_path = dir.join(format!("{}.crt", name));
+        let key_path = dir.join(format!("{}.key", name));
+        std::fs::write(&cert_path, cert.pem()).unwrap();
+        std::fs::write(&key_path, key.serialize_pem()).unwrap();
+        (cert_path.display().to_string(), key_path.display().to_string())
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("spawngate-certwatch-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn config() -> CertWatchConfig {
+        CertWatchConfig::default()
+    }
+
+    #[test]
+    fn test_expiry_state() {
+        let now = 1_000_000_000;
+        assert_eq!(expiry_state(now - 1, now, 30), ExpiryState::Expired);
+        assert_eq!(expiry_state(now + 10 * SECS_PER_DAY, now, 30), ExpiryState::RenewalOverdue);
+        assert_eq!(expiry_state(now + 60 * SECS_PER_DAY, now, 30), ExpiryState::Valid);
+    }
+
+    #[test]
+    fn test_reload_replaced_files() {
+        let dir = temp_dir("reload");
+        let (cert_path, key_path) = write_cert(&dir, "host", 10);
+        let initial = load_certified_key(&cert_path, &key_path).unwrap();
+        let resolver = Arc::new(PinnedCertResolver::with_default_cert(Arc::clone(&initial)));
+        resolver.pin("watch.local", Arc::clone(&initial));
+
+        let mut watcher = CertWatcher::new(Arc::clone(&resolver), &config());
+        watcher.watch(CertTarget::Host("watch.local".to_string()), &cert_path, &key_path);
+        let status = &watcher.statuses()[0];
+        assert_eq!(status.state, Some(ExpiryState::RenewalOverdue));
+        assert_eq!(status.reloads, 0);
+
+        // Unchanged files are not reloaded
+        watcher.check();
+        assert_eq!(watcher.statuses()[0].reloads, 0);
+
+        // A renewed pair replaces the served certificate
+        std::thread::sleep(Duration::from_millis(20));
+        let (renewed_cert, renewed_key) = write_cert(&dir, "renewed", 90);
+        std::fs::copy(&renewed_key, &key_path).unwrap();
+        std::fs::copy(&renewed_cert, &cert_path).unwrap();
+        watcher.check();
+        let status = &watcher.statuses()[0];
+        assert_eq!(status.reloads, 1);
+        assert_eq!(status.state, Some(ExpiryState::Valid));
+        assert!(status.last_error.is_none());
+        let served = resolver.lookup(Some("watch.local")).unwrap();
+        assert!(!Arc::ptr_eq(&served, &initial));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_mismatched_pair_keeps_previous_cert() {
+        let dir = temp_dir("mismatch");
+        let (cert_path, key_path) = write_cert(&dir, "default", 90);
+        let initial = load_certified_key(&cert_path, &key_path).unwrap();
+        let resolver = Arc::new(PinnedCertResolver::with_default_cert(Arc::clone(&initial)));
+
+        let mut watcher = CertWatcher::new(resolver, &config());
+        watcher.watch(CertTarget::Default, &cert_path, &key_path);
+
+        // Only the certificate has been replaced so far
+        std::thread::sleep(Duration::from_millis(20));
+        let (other_cert, _) = write_cert(&dir, "other", 90);
+        std::fs::copy(&other_cert, &cert_path).unwrap();
+        watcher.check();
+        let status = &watcher.statuses()[0];
+        assert_eq!(status.reloads, 0);
+        assert!(status.last_error.as_ref().unwrap().contains("does not match"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_not_after() {
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec!["a.local".to_string()]).unwrap();
+        let der = CertificateDer::from(cert.der().to_vec());
+        assert!(not_after(&der).unwrap() > unix_now());
+
+        let key = PrivateKeyDer::try_from(key_pair.serialize_der()).unwrap();
+        assert!(certified_key(vec![der], &key).unwrap().keys_match().is_ok());
+    }
+}
@@ -0,0 +1,238 @@
+//! cgroups v2 limits for local process backends
+//!
+//! A local backend with a `memory` or `cpus` limit runs in a cgroup of its
+//! own, `<root>/<hostname>.scope`, created before the process is
+//! spawned and removed once it has exited. The process joins the cgroup
+//! before it execs, so everything it forks is limited as well. With
+//! `memory.oom.group` set, the kernel kills the whole cgroup when the memory
+//! limit is hit, and the process manager restarts the backend.
+//!
+//! Limits are only enforced with `[defaults.cgroups]` enabled. Its `root`
+//! must be on a cgroup2 mount and writable by the proxy, e.g. a subtree
+//! delegated by systemd (`Delegate=yes`).
+
+use crate::docker::ContainerStats;
+use parking_lot::Mutex;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// CFS period for `cpu.max`, in microseconds
+const CPU_PERIOD_USEC: u64 = 100_000;
+
+/// Attempts to remove a cgroup while its killed processes exit
+const REMOVE_ATTEMPTS: u32 = 20;
+
+/// The cgroup of one running local backend
+#[derive(Debug)]
+pub struct Cgroup {
+    path: PathBuf,
+    memory_limit: Option<u64>,
+    /// Time and `usage_usec` of the last CPU sample
+    cpu_sample: Mutex<(Instant, u64)>,
+    /// `oom_kill` count when the cgroup was created; a reused one keeps its count
+    oom_kills: u64,
+}
+
+impl Cgroup {
+    /// Create the cgroup for `hostname` under `root` and apply the limits
+    ///
+    /// A cgroup left behind by a previous run is emptied and reused.
+    pub fn create(root: &Path, hostname: &str, memory_limit: Option<u64>, cpus: Option<f64>) -> anyhow::Result<Self> {
+        fs::create_dir_all(root)
+            .map_err(|e| anyhow::anyhow!("Failed to create cgroup root {}: {}", root.display(), e))?;
+        // Controllers the root doesn't offer show up as errors writing the limits below
+        for controller in ["+cpu", "+memory", "+pids"] {
+            let _ = fs::write(root.join("cgroup.subtree_control"), controller);
+        }
+
+        let path = root.join(format!("{}.scope", hostname.replace('/', "_")));
+        match fs::create_dir(&path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                let _ = fs::write(path.join("cgroup.kill"), "1");
+            }
+            Err(e) => anyhow::bail!("Failed to create cgroup {}: {}", path.display(), e),
+        }
+
+        let mut cgroup = Self {
+            path,
+            memory_limit,
+            cpu_sample: Mutex::new((Instant::now(), 0)),
+            oom_kills: 0,
+        };
+        if let Some(limit) = memory_limit {
+            cgroup.write("memory.max", &limit.to_string())?;
+            cgroup.write("memory.oom.group", "1")?;
+        }
+        if let Some(cpus) = cpus {
+            let quota = ((cpus * CPU_PERIOD_USEC as f64) as u64).max(1000);
+            cgroup.write("cpu.max", &format!("{} {}", quota, CPU_PERIOD_USEC))?;
+        }
+        *cgroup.cpu_sample.lock() = (Instant::now(), cgroup.cpu_usage_usec());
+        cgroup.oom_kills = cgroup.oom_kill_count();
+        Ok(cgroup)
+    }
+
+    fn write(&self, file: &str, value: &str) -> anyhow::Result<()> {
+        fs::write(self.path.join(file), value)
+            .map_err(|e| anyhow::anyhow!("Failed to set {} of cgroup {}: {}", file, self.path.display(), e))
+    }
+
+    fn read(&self, file: &str) -> Option<String> {
+        fs::read_to_string(self.path.join(file)).ok()
+    }
+
+    /// Directory of the cgroup
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// `cgroup.procs`, opened before spawning so the child can join with [`join`]
+    pub fn procs(&self) -> io::Result<File> {
+        OpenOptions::new().write(true).open(self.path.join("cgroup.procs"))
+    }
+
+    /// Whether the kernel killed the cgroup for exceeding its memory limit
+    pub fn oom_killed(&self) -> bool {
+        self.oom_kill_count() > self.oom_kills
+    }
+
+    fn oom_kill_count(&self) -> u64 {
+        self.read("memory.events")
+            .and_then(|events| keyed(&events, "oom_kill"))
+            .unwrap_or(0)
+    }
+
+    fn cpu_usage_usec(&self) -> u64 {
+        self.read("cpu.stat").and_then(|stat| keyed(&stat, "usage_usec")).unwrap_or(0)
+    }
+
+    /// Current usage, with CPU use since the previous call (or since the cgroup was created)
+    ///
+    /// None once the cgroup is gone.
+    pub fn stats(&self) -> Option<ContainerStats> {
+        let current: u64 = self.read("memory.current")?.trim().parse().ok()?;
+        let inactive_file = self
+            .read("memory.stat")
+            .and_then(|stat| keyed(&stat, "inactive_file"))
+            .unwrap_or(0);
+
+        let usage = self.cpu_usage_usec();
+        let now = Instant::now();
+        let cpu_percent = {
+            let mut sample = self.cpu_sample.lock();
+            let elapsed = now.duration_since(sample.0).as_micros() as f64;
+            let used = usage.saturating_sub(sample.1) as f64;
+            *sample = (now, usage);
+            if elapsed > 0.0 {
+                (used / elapsed * 10_000.0).round() / 100.0
+            } else {
+                0.0
+            }
+        };
+
+        Some(ContainerStats {
+            cpu_percent,
+            memory_bytes: current.saturating_sub(inactive_file),
+            memory_limit_bytes: self.memory_limit,
+            pids: self.read("pids.current").and_then(|pids| pids.trim().parse().ok()),
+        })
+    }
+
+    /// Kill what is left in the cgroup and remove it
+    pub async fn remove(&self) {
+        let _ = fs::write(self.path.join("cgroup.kill"), "1");
+        for _ in 1..REMOVE_ATTEMPTS {
+            if fs::remove_dir(&self.path).is_ok() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        if let Err(e) = fs::remove_dir(&self.path) {
+            warn!(cgroup = %self.path.display(), error = %e, "Failed to remove backend cgroup");
+        }
+    }
+}
+
+/// Move the calling process into the cgroup whose `cgroup.procs` is `procs`
+///
+/// Only makes a single `write` call, so it is safe to run between fork and exec.
+#[cfg(unix)]
+pub fn join(procs: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // Writing "0" moves the writing process
+    let written = unsafe { libc::write(procs.as_raw_fd(), b"0".as_ptr().cast(), 1) };
+    if written < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Value of `key` in a flat-keyed cgroup file such as `memory.events`
+fn keyed(contents: &str, key: &str) -> Option<u64> {
+    contents
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .find(|(name, _)| *name == key)
+        .and_then(|(_, value)| value.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("spawngate-cgroup-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        root
+    }
+
+    #[test]
+    fn test_keyed() {
+        let events = "low 0\nhigh 3\nmax 12\noom 1\noom_kill 1\noom_group_kill 1\n";
+        assert_eq!(keyed(events, "oom_kill"), Some(1));
+        assert_eq!(keyed(events, "max"), Some(12));
+        assert_eq!(keyed(events, "oom_kill_disable"), None);
+    }
+
+    /// A plain directory stands in for the cgroup2 mount
+    #[test]
+    fn test_cgroup_limits_and_stats() {
+        let root = test_root("limits");
+        let cgroup = Cgroup::create(&root, "app.local", Some(256 * 1024 * 1024), Some(0.5)).unwrap();
+        assert_eq!(cgroup.path(), root.join("app.local.scope"));
+        assert_eq!(fs::read_to_string(cgroup.path().join("memory.max")).unwrap(), "268435456");
+        assert_eq!(fs::read_to_string(cgroup.path().join("memory.oom.group")).unwrap(), "1");
+        assert_eq!(fs::read_to_string(cgroup.path().join("cpu.max")).unwrap(), "50000 100000");
+        assert!(cgroup.procs().is_err());
+        fs::write(cgroup.path().join("cgroup.procs"), "").unwrap();
+        assert!(cgroup.procs().is_ok());
+
+        assert!(cgroup.stats().is_none());
+        fs::write(cgroup.path().join("memory.current"), "1048576\n").unwrap();
+        fs::write(cgroup.path().join("memory.stat"), "anon 786432\ninactive_file 262144\n").unwrap();
+        fs::write(cgroup.path().join("pids.current"), "3\n").unwrap();
+        let stats = cgroup.stats().unwrap();
+        assert_eq!(stats.memory_bytes, 786432);
+        assert_eq!(stats.memory_limit_bytes, Some(268435456));
+        assert_eq!(stats.pids, Some(3));
+
+        assert!(!cgroup.oom_killed());
+        fs::write(cgroup.path().join("memory.events"), "max 4\noom 1\noom_kill 1\n").unwrap();
+        assert!(cgroup.oom_killed());
+
+        // A left-over cgroup is reused, without the kills of its previous instance
+        let reused = Cgroup::create(&root, "app.local", None, None).unwrap();
+        assert!(!reused.oom_killed());
+        fs::write(reused.path().join("memory.events"), "max 9
+oom 2
+oom_kill 2
+").unwrap();
+        assert!(reused.oom_killed());
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,506 @@
+//! Cloud VM backends
+//!
+//! A backend with `type = "cloud-vm"` runs on a cloud instance that is kept
+//! stopped while idle: the first request starts it through the provider's
+//! API, and the idle timeout stops it again, scale-to-zero for whole machines.
+//! Once the instance runs, its address is looked up (unless configured) and
+//! a relay on `127.0.0.1:{port}` forwards to it, so health polling waits for
+//! the VM to boot and routing and pooling work as for any other backend.
+//!
+//! Providers implement [`Provider`]; EC2 and Hetzner Cloud are built in.
+//! Credentials come from the environment rather than the config file.
+
+use crate::config::{BackendConfig, CloudProvider, CloudVmConfig};
+use crate::process::Spawner;
+use crate::relay::relay;
+use dashmap::DashMap;
+use futures::future::{BoxFuture, FutureExt};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::{Method, Request, StatusCode};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use parking_lot::Mutex;
+use ring::{digest, hmac};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// Time allowed for a call to a provider's API
+const API_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often a starting VM's address is looked up
+const LOCATE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Starts, stops and locates cloud VMs
+pub trait Provider: Send + Sync {
+    /// Start the VM; starting a running VM is not an error
+    fn start<'a>(&'a self, vm: &'a CloudVmConfig) -> BoxFuture<'a, anyhow::Result<()>>;
+
+    /// Stop the VM, shutting it down gracefully if the provider supports it
+    fn stop<'a>(&'a self, vm: &'a CloudVmConfig) -> BoxFuture<'a, anyhow::Result<()>>;
+
+    /// Address of the VM once it runs, `None` while it is still starting
+    fn address<'a>(&'a self, vm: &'a CloudVmConfig) -> BoxFuture<'a, anyhow::Result<Option<IpAddr>>>;
+}
+
+pub(crate) type HttpsClient = Client<HttpsConnector<HttpConnector>, Full<Bytes>>;
+
+pub(crate) fn https_client() -> anyhow::Result<HttpsClient> {
+    let connector = HttpsConnectorBuilder::new()
+        .with_provider_and_native_roots(rustls::crypto::ring::default_provider())?
+        .https_only()
+        .enable_http1()
+        .build();
+    Ok(Client::builder(TokioExecutor::new()).build(connector))
+}
+
+/// Send a request, returning the status and body
+pub(crate) async fn fetch(client: &HttpsClient, request: Request<Full<Bytes>>) -> anyhow::Result<(StatusCode, Bytes)> {
+    let host = request.uri().host().unwrap_or_default().to_string();
+    let response = tokio::time::timeout(API_TIMEOUT, client.request(request))
+        .await
+        .map_err(|_| anyhow::anyhow!("Request to {} timed out", host))??;
+    let status = response.status();
+    let body = response.into_body().collect().await?.to_bytes();
+    Ok((status, body))
+}
+
+/// Amazon EC2, called through its query API with Signature Version 4
+pub struct Ec2 {
+    client: HttpsClient,
+}
+
+pub(crate) struct AwsCredentials {
+    pub(crate) access_key_id: String,
+    pub(crate) secret_access_key: String,
+    pub(crate) session_token: Option<String>,
+}
+
+impl AwsCredentials {
+    pub(crate) fn from_env() -> anyhow::Result<Self> {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        match (var("AWS_ACCESS_KEY_ID"), var("AWS_SECRET_ACCESS_KEY")) {
+            (Some(access_key_id), Some(secret_access_key)) => Ok(Self {
+                access_key_id,
+                secret_access_key,
+                session_token: var("AWS_SESSION_TOKEN"),
+            }),
+            _ => anyhow::bail!("AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY must be set"),
+        }
+    }
+}
+
+impl Ec2 {
+    pub fn new() -> anyhow::Result<Self> {
+        Ok(Self { client: https_client()? })
+    }
+
+    /// Call an EC2 action for the VM's instance, returning the XML response
+    async fn call(&self, vm: &CloudVmConfig, action: &str) -> anyhow::Result<String> {
+        let credentials = AwsCredentials::from_env()?;
+        let region = vm.region.as_deref().unwrap_or("us-east-1");
+        let host = format!("ec2.{}.amazonaws.com", region);
+        let query = canonical_query(&[("Action", action), ("InstanceId.1", &vm.instance_id), ("Version", "2016-11-15")]);
+        let amz_date = amz_date(SystemTime::now());
+
+        let mut headers = vec![("host", host.as_str()), ("x-amz-date", amz_date.as_str())];
+        if let Some(token) = &credentials.session_token {
+            headers.push(("x-amz-security-token", token));
+        }
+        let authorization =
+            sigv4_authorization(&credentials, region, "ec2", "GET", "/", &query, &headers, b"", &amz_date);
+
+        let mut request = Request::get(format!("https://{}/?{}", host, query));
+        for (name, value) in headers.iter().filter(|(name, _)| *name != "host") {
+            request = request.header(*name, *value);
+        }
+        let request = request.header("authorization", authorization).body(Full::new(Bytes::new()))?;
+
+        let (status, body) = fetch(&self.client, request).await?;
+        let body = String::from_utf8_lossy(&body).into_owned();
+        if !status.is_success() {
+            let message = xml_value(&body, "Message").unwrap_or(body.trim());
+            anyhow::bail!("EC2 {} failed ({}): {}", action, status, message);
+        }
+        Ok(body)
+    }
+}
+
+impl Provider for Ec2 {
+    fn start<'a>(&'a self, vm: &'a CloudVmConfig) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move { self.call(vm, "StartInstances").await.map(drop) }.boxed()
+    }
+
+    fn stop<'a>(&'a self, vm: &'a CloudVmConfig) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move { self.call(vm, "StopInstances").await.map(drop) }.boxed()
+    }
+
+    fn address<'a>(&'a self, vm: &'a CloudVmConfig) -> BoxFuture<'a, anyhow::Result<Option<IpAddr>>> {
+        async move {
+            let body = self.call(vm, "DescribeInstances").await?;
+            let state = xml_value(&body, "instanceState").and_then(|state| xml_value(state, "name"));
+            if state != Some("running") {
+                return Ok(None);
+            }
+            let tag = if vm.private_ip { "privateIpAddress" } else { "ipAddress" };
+            Ok(xml_value(&body, tag).and_then(|ip| ip.parse().ok()))
+        }
+        .boxed()
+    }
+}
+
+/// Hetzner Cloud, with the API token from `HCLOUD_TOKEN`
+pub struct Hetzner {
+    client: HttpsClient,
+}
+
+impl Hetzner {
+    pub fn new() -> anyhow::Result<Self> {
+        Ok(Self { client: https_client()? })
+    }
+
+    async fn call(&self, method: Method, path: &str) -> anyhow::Result<serde_json::Value> {
+        let token = std::env::var("HCLOUD_TOKEN").map_err(|_| anyhow::anyhow!("HCLOUD_TOKEN must be set"))?;
+        let request = Request::builder()
+            .method(method)
+            .uri(format!("https://api.hetzner.cloud/v1{}", path))
+            .header("authorization", format!("Bearer {}", token))
+            .body(Full::new(Bytes::new()))?;
+
+        let (status, body) = fetch(&self.client, request).await?;
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+        if !status.is_success() {
+            let message = json["error"]["message"].as_str().unwrap_or("unknown error");
+            anyhow::bail!("Hetzner API {} failed ({}): {}", path, status, message);
+        }
+        Ok(json)
+    }
+}
+
+impl Provider for Hetzner {
+    fn start<'a>(&'a self, vm: &'a CloudVmConfig) -> BoxFuture<'a, anyhow::Result<()>> {
+        let path = format!("/servers/{}/actions/poweron", vm.instance_id);
+        async move { self.call(Method::POST, &path).await.map(drop) }.boxed()
+    }
+
+    fn stop<'a>(&'a self, vm: &'a CloudVmConfig) -> BoxFuture<'a, anyhow::Result<()>> {
+        let path = format!("/servers/{}/actions/shutdown", vm.instance_id);
+        async move { self.call(Method::POST, &path).await.map(drop) }.boxed()
+    }
+
+    fn address<'a>(&'a self, vm: &'a CloudVmConfig) -> BoxFuture<'a, anyhow::Result<Option<IpAddr>>> {
+        let path = format!("/servers/{}", vm.instance_id);
+        async move {
+            let json = self.call(Method::GET, &path).await?;
+            Ok(server_address(&json["server"], vm.private_ip))
+        }
+        .boxed()
+    }
+}
+
+/// Address of a running Hetzner server
+fn server_address(server: &serde_json::Value, private_ip: bool) -> Option<IpAddr> {
+    if server["status"] != "running" {
+        return None;
+    }
+    let ip = if private_ip {
+        &server["private_net"][0]["ip"]
+    } else {
+        &server["public_net"]["ipv4"]["ip"]
+    };
+    ip.as_str().and_then(|ip| ip.parse().ok())
+}
+
+/// A VM started for a backend
+struct RunningVm {
+    vm: CloudVmConfig,
+    provider: Arc<dyn Provider>,
+    relay: JoinHandle<()>,
+    locate: Option<JoinHandle<()>>,
+}
+
+/// Starts and stops the VMs of cloud-vm backends
+#[derive(Default)]
+pub struct CloudVmSpawner {
+    /// Providers, created when first used
+    providers: Mutex<HashMap<CloudProvider, Arc<dyn Provider>>>,
+    running: DashMap<String, RunningVm>,
+}
+
+impl CloudVmSpawner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn provider(&self, kind: CloudProvider) -> anyhow::Result<Arc<dyn Provider>> {
+        let mut providers = self.providers.lock();
+        if let Some(provider) = providers.get(&kind) {
+            return Ok(Arc::clone(provider));
+        }
+        let provider: Arc<dyn Provider> = match kind {
+            CloudProvider::Ec2 => Arc::new(Ec2::new()?),
+            CloudProvider::Hetzner => Arc::new(Hetzner::new()?),
+        };
+        providers.insert(kind, Arc::clone(&provider));
+        Ok(provider)
+    }
+
+    async fn start_vm(&self, hostname: &str, config: &BackendConfig) -> anyhow::Result<String> {
+        let vm = config
+            .cloud_vm
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("Cloud VM backend requires a 'cloud_vm' table"))?;
+        let provider = self.provider(vm.provider)?;
+        let listener = TcpListener::bind(("127.0.0.1", config.port))
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to listen on 127.0.0.1:{}: {}", config.port, e))?;
+
+        info!(hostname, provider = ?vm.provider, instance_id = %vm.instance_id, "Starting cloud VM");
+        provider.start(&vm).await?;
+
+        let port = config.port;
+        let (target_tx, target_rx) = watch::channel(vm.address.map(|ip| SocketAddr::new(ip, port)));
+        let relay = tokio::spawn(relay(listener, hostname.to_string(), target_rx));
+
+        // Until the address is known, health checks fail and the backend stays starting
+        let locate = vm.address.is_none().then(|| {
+            let (provider, vm, hostname) = (Arc::clone(&provider), vm.clone(), hostname.to_string());
+            tokio::spawn(async move {
+                loop {
+                    match provider.address(&vm).await {
+                        Ok(Some(ip)) => {
+                            info!(hostname, %ip, "Cloud VM is running");
+                            target_tx.send_replace(Some(SocketAddr::new(ip, port)));
+                            return;
+                        }
+                        Ok(None) => debug!(hostname, "Cloud VM not running yet"),
+                        Err(e) => warn!(hostname, error = %e, "Failed to look up cloud VM"),
+                    }
+                    tokio::time::sleep(LOCATE_INTERVAL).await;
+                }
+            })
+        });
+
+        let id = vm.instance_id.clone();
+        self.running.insert(id.clone(), RunningVm { vm, provider, relay, locate });
+        Ok(id)
+    }
+
+    async fn stop_vm(&self, hostname: &str, id: &str) {
+        let Some((_, running)) = self.running.remove(id) else {
+            return;
+        };
+        running.relay.abort();
+        if let Some(locate) = running.locate {
+            locate.abort();
+        }
+        match running.provider.stop(&running.vm).await {
+            Ok(()) => info!(hostname, instance_id = id, "Stopped cloud VM"),
+            Err(e) => warn!(hostname, instance_id = id, error = %e, "Failed to stop cloud VM"),
+        }
+    }
+}
+
+impl Spawner for CloudVmSpawner {
+    fn start<'a>(
+        &'a self,
+        hostname: &'a str,
+        config: &'a BackendConfig,
+        _env: &'a [(String, String)],
+    ) -> BoxFuture<'a, anyhow::Result<String>> {
+        self.start_vm(hostname, config).boxed()
+    }
+
+    fn stop<'a>(&'a self, hostname: &'a str, id: &'a str, _grace_period: Duration) -> BoxFuture<'a, ()> {
+        self.stop_vm(hostname, id).boxed()
+    }
+}
+
+/// Text content of the first `<tag>` element
+pub(crate) fn xml_value<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let open = format!("<{}>", tag);
+    let start = xml.find(&open)? + open.len();
+    let end = xml[start..].find(&format!("</{}>", tag))?;
+    Some(&xml[start..start + end])
+}
+
+/// Query string with names and values percent-encoded and sorted, as SigV4 requires
+pub(crate) fn canonical_query(params: &[(&str, &str)]) -> String {
+    let mut params: Vec<(String, String)> = params.iter().map(|(k, v)| (uri_encode(k), uri_encode(v))).collect();
+    params.sort();
+    params.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join("&")
+}
+
+pub(crate) fn uri_encode(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+            out.push(byte as char);
+        } else {
+            let _ = write!(out, "%{:02X}", byte);
+        }
+    }
+    out
+}
+
+/// `YYYYMMDDTHHMMSSZ` timestamp in UTC
+pub(crate) fn amz_date(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+    let (days, rem) = ((secs / 86_400) as i64, secs % 86_400);
+
+    // Civil date from days since the epoch (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// `Authorization` header of an AWS Signature Version 4 signed request
+///
+/// `path` must already be URI-encoded. `headers` are the signed headers with
+/// lowercase names, including `host` and `x-amz-date`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn sigv4_authorization(
+    credentials: &AwsCredentials,
+    region: &str,
+    service: &str,
+    method: &str,
+    path: &str,
+    canonical_query: &str,
+    headers: &[(&str, &str)],
+    payload: &[u8],
+    amz_date: &str,
+) -> String {
+    let mut headers = headers.to_vec();
+    headers.sort();
+    let canonical_headers: String = headers.iter().map(|(name, value)| format!("{}:{}\n", name, value.trim())).collect();
+    let signed_headers = headers.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(";");
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method,
+        path,
+        canonical_query,
+        canonical_headers,
+        signed_headers,
+        hex(digest::digest(&digest::SHA256, payload).as_ref())
+    );
+
+    let date = &amz_date[..8];
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex(digest::digest(&digest::SHA256, canonical_request.as_bytes()).as_ref())
+    );
+
+    let sign = |key: &[u8], data: &str| hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), data.as_bytes());
+    let key = sign(format!("AWS4{}", credentials.secret_access_key).as_bytes(), date);
+    let key = sign(key.as_ref(), region);
+    let key = sign(key.as_ref(), service);
+    let key = sign(key.as_ref(), "aws4_request");
+    let signature = hex(sign(key.as_ref(), &string_to_sign).as_ref());
+
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        credentials.access_key_id, scope, signed_headers, signature
+    )
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::with_capacity(bytes.len() * 2), |mut out, b| {
+        let _ = write!(out, "{:02x}", b);
+        out
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sigv4() {
+        // Example request from the AWS Signature Version 4 documentation
+        let credentials = AwsCredentials {
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            session_token: None,
+        };
+        let query = canonical_query(&[("Version", "2010-05-08"), ("Action", "ListUsers")]);
+        assert_eq!(query, "Action=ListUsers&Version=2010-05-08");
+        let headers = [
+            ("host", "iam.amazonaws.com"),
+            ("content-type", "application/x-www-form-urlencoded; charset=utf-8"),
+            ("x-amz-date", "20150830T123600Z"),
+        ];
+        let authorization = sigv4_authorization(
+            &credentials,
+            "us-east-1",
+            "iam",
+            "GET",
+            "/",
+            &query,
+            &headers,
+            b"",
+            "20150830T123600Z",
+        );
+        assert_eq!(
+            authorization,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-date, \
+             Signature=5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7"
+        );
+
+        assert_eq!(uri_encode("i-0abc/ä ~"), "i-0abc%2F%C3%A4%20~");
+        assert_eq!(amz_date(UNIX_EPOCH + Duration::from_secs(1_440_938_160)), "20150830T123600Z");
+        assert_eq!(amz_date(UNIX_EPOCH + Duration::from_secs(951_782_400)), "20000229T000000Z");
+    }
+
+    #[test]
+    fn test_instance_addresses() {
+        let xml = "<DescribeInstancesResponse><reservationSet><item><instancesSet><item>\
+                   <instanceId>i-1</instanceId><instanceState><code>16</code><name>running</name></instanceState>\
+                   <groupSet><item><groupName>web</groupName></item></groupSet>\
+                   <privateIpAddress>10.0.0.4</privateIpAddress><ipAddress>3.4.5.6</ipAddress>\
+                   </item></instancesSet></item></reservationSet></DescribeInstancesResponse>";
+        let state = xml_value(xml, "instanceState").and_then(|s| xml_value(s, "name"));
+        assert_eq!(state, Some("running"));
+        assert_eq!(xml_value(xml, "ipAddress"), Some("3.4.5.6"));
+        assert_eq!(xml_value(xml, "privateIpAddress"), Some("10.0.0.4"));
+        assert_eq!(xml_value(xml, "missing"), None);
+
+        let server = serde_json::json!({
+            "status": "running",
+            "public_net": { "ipv4": { "ip": "1.2.3.4" } },
+            "private_net": [{ "ip": "10.0.0.2" }],
+        });
+        assert_eq!(server_address(&server, false), Some("1.2.3.4".parse().unwrap()));
+        assert_eq!(server_address(&server, true), Some("10.0.0.2".parse().unwrap()));
+        let starting = serde_json::json!({ "status": "starting", "public_net": { "ipv4": { "ip": "1.2.3.4" } } });
+        assert_eq!(server_address(&starting, false), None);
+    }
+}
@@ -0,0 +1,233 @@
+//! Retry hints for requests waiting on a cold start
+//!
+//! With `spawn_wait_secs` set, a request waits at most that long for its
+//! backend to start. If the backend is still starting by then, the request is
+//! answered with a 503 telling the client when to come back, instead of being
+//! held until the startup timeout:
+//!
+//! ```text
+//! Retry-After: 3
+//! X-Queue-Position: 2
+//! X-Estimated-Ready-Ms: 2400
+//! ```
+//!
+//! The queue position counts the requests waiting for the backend that will
+//! be served first, plus one: those of a higher priority class, and those of
+//! the request's own class that arrived before it. The estimate is the median of the
+//! backend's recent spawn-to-ready times less the time the current spawn has
+//! taken; it's left out until the backend has become ready once.
+//!
+//! With `max_queue_depth` set, requests arriving while that many are waiting
+//! for the backend are answered with a 503 (`COLD_START_QUEUE_FULL`) right
+//! away, with the same `Retry-After` and `X-Estimated-Ready-Ms`, unless they
+//! can take the place of a lower-priority request (see [`crate::priority`]).
+
+use crate::config::PriorityClass;
+use crate::error::{json_error_response, ProxyErrorCode};
+use crate::proxy::ProxyResponse;
+use dashmap::DashMap;
+use hyper::header::{HeaderValue, RETRY_AFTER};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Header with the request's position among those waiting for the backend
+pub const QUEUE_POSITION_HEADER: &str = "x-queue-position";
+
+/// Header with the milliseconds until the backend is likely ready
+pub const ESTIMATED_READY_HEADER: &str = "x-estimated-ready-ms";
+
+/// Requests waiting for their backend to start, with their class, in arrival order
+#[derive(Default)]
+pub struct SpawnQueue {
+    next: AtomicU64,
+    waiting: DashMap<String, BTreeMap<u64, PriorityClass>>,
+}
+
+impl SpawnQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a request for `hostname` until the ticket is dropped
+    pub fn join(self: &Arc<Self>, hostname: &str, class: PriorityClass) -> QueueTicket {
+        let ticket = self.next.fetch_add(1, Ordering::Relaxed);
+        self.waiting.entry(hostname.to_string()).or_default().insert(ticket, class);
+        QueueTicket {
+            queue: Arc::clone(self),
+            hostname: hostname.to_string(),
+            ticket,
+            class,
+        }
+    }
+}
+
+/// A request's place in the [`SpawnQueue`]
+pub struct QueueTicket {
+    queue: Arc<SpawnQueue>,
+    hostname: String,
+    ticket: u64,
+    class: PriorityClass,
+}
+
+impl QueueTicket {
+    /// 1 for the longest-waiting request of the highest class
+    pub fn position(&self) -> usize {
+        let ahead = |(ticket, class): (&u64, &PriorityClass)| {
+            *class > self.class || *class == self.class && *ticket < self.ticket
+        };
+        self.queue
+            .waiting
+            .get(&self.hostname)
+            .map_or(0, |waiting| waiting.iter().filter(|entry| ahead(*entry)).count())
+            + 1
+    }
+}
+
+impl Drop for QueueTicket {
+    fn drop(&mut self) {
+        if let Some(mut waiting) = self.queue.waiting.get_mut(&self.hostname) {
+            waiting.remove(&self.ticket);
+        }
+        self.queue.waiting.remove_if(&self.hostname, |_, waiting| waiting.is_empty());
+    }
+}
+
+/// A request gave up waiting for a backend that is still starting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StillStarting {
+    pub position: usize,
+    pub estimated_ready: Option<Duration>,
+}
+
+impl std::fmt::Display for StillStarting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Backend still starting (queue position {})", self.position)
+    }
+}
+
+impl std::error::Error for StillStarting {}
+
+impl StillStarting {
+    /// 503 response with the retry hints
+    ///
+    /// Without an estimate, clients are asked to retry after a second.
+    pub fn response(&self) -> ProxyResponse {
+        let mut response = json_error_response(ProxyErrorCode::BackendStarting, "Backend is starting, please retry");
+        insert_retry_hints(&mut response, self.estimated_ready);
+        response
+            .headers_mut()
+            .insert(QUEUE_POSITION_HEADER, HeaderValue::from(self.position));
+        response
+    }
+}
+
+/// A request found `max_queue_depth` requests waiting for its backend already
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueFull {
+    pub depth: usize,
+    pub estimated_ready: Option<Duration>,
+}
+
+impl std::fmt::Display for QueueFull {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Cold-start queue full ({} requests waiting)", self.depth)
+    }
+}
+
+impl std::error::Error for QueueFull {}
+
+impl QueueFull {
+    /// 503 response with the retry hints
+    pub fn response(&self) -> ProxyResponse {
+        let mut response = json_error_response(
+            ProxyErrorCode::ColdStartQueueFull,
+            "Too many requests waiting for the backend to start, please retry",
+        );
+        insert_retry_hints(&mut response, self.estimated_ready);
+        response
+    }
+}
+
+/// Set `Retry-After` (a second without an estimate) and the estimate itself
+fn insert_retry_hints(response: &mut ProxyResponse, estimated_ready: Option<Duration>) {
+    let retry_after = estimated_ready.map_or(1, |ready| ready.as_millis().div_ceil(1000).max(1) as u64);
+    let headers = response.headers_mut();
+    headers.insert(RETRY_AFTER, HeaderValue::from(retry_after));
+    if let Some(ready) = estimated_ready {
+        headers.insert(ESTIMATED_READY_HEADER, HeaderValue::from(ready.as_millis() as u64));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::StatusCode;
+
+    #[test]
+    fn test_queue_positions() {
+        let queue = Arc::new(SpawnQueue::new());
+        let first = queue.join("app.local", PriorityClass::Normal);
+        let second = queue.join("app.local", PriorityClass::Normal);
+        let other = queue.join("other.local", PriorityClass::Normal);
+        assert_eq!((first.position(), second.position(), other.position()), (1, 2, 1));
+
+        drop(first);
+        assert_eq!(second.position(), 1);
+        let third = queue.join("app.local", PriorityClass::Normal);
+        assert_eq!(third.position(), 2);
+
+        // Higher classes go ahead of earlier arrivals, lower ones behind later ones
+        let high = queue.join("app.local", PriorityClass::High);
+        let low = queue.join("app.local", PriorityClass::Low);
+        let normal = queue.join("app.local", PriorityClass::Normal);
+        assert_eq!((high.position(), second.position(), normal.position(), low.position()), (1, 2, 4, 5));
+
+        drop((second, third, other, high, low, normal));
+        assert!(queue.waiting.is_empty());
+    }
+
+    #[test]
+    fn test_still_starting_response() {
+        let starting = StillStarting {
+            position: 3,
+            estimated_ready: Some(Duration::from_millis(2400)),
+        };
+        let response = starting.response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()["x-proxy-error"], "BACKEND_STARTING");
+        assert_eq!(response.headers()[RETRY_AFTER], "3");
+        assert_eq!(response.headers()[QUEUE_POSITION_HEADER], "3");
+        assert_eq!(response.headers()[ESTIMATED_READY_HEADER], "2400");
+
+        let unknown = StillStarting {
+            position: 1,
+            estimated_ready: None,
+        };
+        let response = unknown.response();
+        assert_eq!(response.headers()[RETRY_AFTER], "1");
+        assert!(response.headers().get(ESTIMATED_READY_HEADER).is_none());
+
+        // Overdue backends still get a short retry
+        let overdue = StillStarting {
+            position: 1,
+            estimated_ready: Some(Duration::ZERO),
+        };
+        assert_eq!(overdue.response().headers()[RETRY_AFTER], "1");
+    }
+
+    #[test]
+    fn test_queue_full_response() {
+        let full = QueueFull {
+            depth: 50,
+            estimated_ready: Some(Duration::from_millis(1200)),
+        };
+        let response = full.response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()["x-proxy-error"], "COLD_START_QUEUE_FULL");
+        assert_eq!(response.headers()[RETRY_AFTER], "2");
+        assert_eq!(response.headers()[ESTIMATED_READY_HEADER], "1200");
+        assert!(response.headers().get(QUEUE_POSITION_HEADER).is_none());
+    }
+}
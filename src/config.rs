@@ -1,11 +1,36 @@
-use serde::Deserialize;
-use std::collections::HashMap;
+use crate::configvars::ConfigVars;
+use crate::selector::Selector;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr};
 use std::path::Path;
 use std::time::Duration;
+use toml_edit::DocumentMut;
+use tracing::warn;
+
+fn default_config_version() -> u32 {
+    CONFIG_VERSION
+}
+
+/// Current config file schema version
+pub const CONFIG_VERSION: u32 = 1;
+
+/// Migrations between schema versions, applied in order.
+///
+/// Entry `i` upgrades a document from version `i` to version `i + 1`.
+/// Version 0 means the file predates the `version` field.
+const MIGRATIONS: &[fn(&mut DocumentMut)] = &[
+    // 0 -> 1: layout unchanged, the file only gains a `version` key
+    |_| {},
+];
 
 /// Global configuration for the proxy
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
+    /// Config file schema version (always `CONFIG_VERSION` after loading)
+    #[serde(default = "default_config_version")]
+    pub version: u32,
+
     /// Server configuration
     #[serde(default)]
     pub server: ServerConfig,
@@ -14,12 +39,28 @@ pub struct Config {
     #[serde(default)]
     pub defaults: BackendDefaults,
 
+    /// Named backend profiles, referenced via `profile = "name"`
+    #[serde(default)]
+    pub profiles: HashMap<String, BackendProfile>,
+
     /// Virtual host configurations
     #[serde(default)]
     pub backends: HashMap<String, BackendConfig>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            server: ServerConfig::default(),
+            defaults: BackendDefaults::default(),
+            profiles: HashMap::new(),
+            backends: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ServerConfig {
     /// HTTP port (default: 80, set to 0 to disable)
     #[serde(default = "default_listen_port")]
@@ -48,9 +89,29 @@ pub struct ServerConfig {
     #[serde(default = "default_pool_idle_timeout")]
     pub pool_idle_timeout_secs: u64,
 
+    /// Share one connection pool between the HTTP and HTTPS listeners, so idle
+    /// connection limits apply per backend rather than per listener (default: false)
+    #[serde(default)]
+    pub pool_shared: bool,
+
+    /// How long listeners replaced on reload, or stopped on shutdown, wait for
+    /// open client connections to finish, in seconds (default: 30)
+    #[serde(default = "default_listener_drain_timeout")]
+    pub listener_drain_timeout_secs: u64,
+
+    /// Send `Connection: close` on HTTP/1.1 responses once a listener starts
+    /// draining, so keep-alive clients reconnect elsewhere (default: true).
+    /// When false, open connections keep serving requests until the client
+    /// closes them or the drain timeout ends
+    #[serde(default = "default_drain_close_keep_alive")]
+    pub drain_close_keep_alive: bool,
+
     /// Path to PID file (optional)
     pub pid_file: Option<String>,
 
+    /// JSON file keeping env vars set via the admin API (default: kept in memory only)
+    pub config_vars_file: Option<String>,
+
     /// Enable TLS (default: false). If true without cert/key, generates self-signed.
     #[serde(default)]
     pub tls: bool,
@@ -65,13 +126,85 @@ pub struct ServerConfig {
     #[serde(default)]
     pub force_https: bool,
 
+    /// Status code of HTTPS redirects: 301, 302, 307 or 308 (default: 301)
+    #[serde(default = "default_https_redirect_status")]
+    pub https_redirect_status: u16,
+
     /// ACME/Let's Encrypt configuration
     #[serde(default)]
     pub acme: AcmeConfig,
+
+    /// Per-client limit on requests that cold-start a backend
+    #[serde(default)]
+    pub cold_start_throttle: ColdStartThrottleConfig,
+
+    /// Periodic export of aggregated request metrics
+    #[serde(default)]
+    pub metrics_export: MetricsExportConfig,
+
+    /// Expiry checks and hot reload for file-based certificates
+    #[serde(default)]
+    pub cert_watch: CertWatchConfig,
+
+    /// TLS session resumption (session cache and tickets)
+    #[serde(default)]
+    pub tls_session: TlsSessionConfig,
+
+    /// TLS 1.3 early data (0-RTT) on resumed connections
+    #[serde(default)]
+    pub early_data: EarlyDataConfig,
+
+    /// JA3/JA4 fingerprints of TLS clients and a blocklist of known abusive ones
+    #[serde(default)]
+    pub tls_fingerprint: TlsFingerprintConfig,
+
+    /// Normalization of request paths before routing
+    #[serde(default)]
+    pub path_normalization: PathNormalizationConfig,
+
+    /// Lockout of clients repeatedly failing admin API authentication
+    #[serde(default)]
+    pub admin_lockout: AdminLockoutConfig,
+
+    /// Sampling of per-request trace events
+    #[serde(default)]
+    pub trace_sampling: TraceSamplingConfig,
+
+    /// Logging and summary of requests exceeding a latency threshold
+    #[serde(default)]
+    pub slow_requests: SlowRequestConfig,
+
+    /// Objective for the latency cold starts add to requests
+    #[serde(default)]
+    pub cold_start_slo: ColdStartSloConfig,
+
+    /// Registration of the backends in the Consul catalog
+    #[serde(default)]
+    pub consul: ConsulConfig,
+
+    /// Commands run on shutdown and the state snapshot file
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+
+    /// Panic logging, crash counting and crash dumps
+    #[serde(default)]
+    pub panic: PanicConfig,
+
+    /// Backends read from a directory of discovery files
+    #[serde(default)]
+    pub file_sd: FileSdConfig,
+
+    /// Backends read from keys in Consul's KV store or etcd
+    #[serde(default)]
+    pub kv: KvConfig,
+
+    /// CPU and heap profiles from the admin API
+    #[serde(default)]
+    pub profiling: ProfilingConfig,
 }
 
 /// Challenge type for ACME domain validation
-#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
 pub enum AcmeChallengeType {
     /// HTTP-01: Serves challenge response on port 80 at /.well-known/acme-challenge/
     #[default]
@@ -82,10 +215,41 @@ pub enum AcmeChallengeType {
     #[serde(alias = "tls-alpn01", alias = "TLS-ALPN-01")]
     #[serde(rename = "tls-alpn-01")]
     TlsAlpn01,
+    /// DNS-01: Publishes a TXT record through a DNS provider's API; the only
+    /// challenge that can validate wildcard domains
+    #[serde(alias = "dns01", alias = "DNS-01")]
+    #[serde(rename = "dns-01")]
+    Dns01,
+}
+
+/// DNS provider that publishes DNS-01 challenge records
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AcmeDnsProvider {
+    /// Cloudflare, with an API token from `CLOUDFLARE_API_TOKEN`
+    Cloudflare,
+    /// Amazon Route 53, with credentials from `AWS_ACCESS_KEY_ID`,
+    /// `AWS_SECRET_ACCESS_KEY` and optionally `AWS_SESSION_TOKEN`
+    Route53,
+}
+
+/// Settings of the DNS-01 challenge
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AcmeDnsConfig {
+    pub provider: AcmeDnsProvider,
+
+    /// Zone (Cloudflare) or hosted zone (Route 53) ID; looked up from the
+    /// domain when unset
+    pub zone_id: Option<String>,
+
+    /// Time to wait after publishing a record before asking the CA to
+    /// validate it (default: 10)
+    #[serde(default = "default_acme_dns_propagation_secs")]
+    pub propagation_secs: u64,
 }
 
 /// ACME (Let's Encrypt) configuration for automatic certificate provisioning
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AcmeConfig {
     /// Enable ACME certificate provisioning
     #[serde(default)]
@@ -98,10 +262,14 @@ pub struct AcmeConfig {
     /// Contact email for Let's Encrypt notifications (required when enabled)
     pub email: Option<String>,
 
-    /// ACME directory URL (defaults to Let's Encrypt production)
-    /// Use "https://acme-staging-v02.api.letsencrypt.org/directory" for testing
+    /// ACME directory URL (defaults to Let's Encrypt production, or staging
+    /// when `staging` is set)
     pub directory_url: Option<String>,
 
+    /// Use the Let's Encrypt staging directory (untrusted certificates, relaxed rate limits)
+    #[serde(default)]
+    pub staging: bool,
+
     /// Local directory for certificate and account cache
     #[serde(default = "default_acme_cache_dir")]
     pub cache_dir: String,
@@ -109,6 +277,18 @@ pub struct AcmeConfig {
     /// Challenge type for domain validation (default: http-01)
     #[serde(default)]
     pub challenge_type: AcmeChallengeType,
+
+    /// DNS provider for the dns-01 challenge
+    pub dns: Option<AcmeDnsConfig>,
+
+    /// Check DNS and CAA records before each order (default: true)
+    #[serde(default = "default_acme_preflight")]
+    pub preflight: bool,
+
+    /// Public addresses of this server; when set, every domain must resolve
+    /// only to these
+    #[serde(default)]
+    pub expected_ips: Vec<IpAddr>,
 }
 
 impl Default for AcmeConfig {
@@ -118,964 +298,5652 @@ impl Default for AcmeConfig {
             domains: Vec::new(),
             email: None,
             directory_url: None,
+            staging: false,
             cache_dir: default_acme_cache_dir(),
             challenge_type: AcmeChallengeType::default(),
+            dns: None,
+            preflight: true,
+            expected_ips: Vec::new(),
         }
     }
 }
 
+fn default_https_redirect_status() -> u16 {
+    301
+}
+
 fn default_acme_cache_dir() -> String {
     "./acme_cache".to_string()
 }
 
-impl ServerConfig {
-    pub fn tls_enabled(&self) -> bool {
-        self.acme_enabled() || self.tls || self.tls_cert.is_some() && self.tls_key.is_some()
-    }
+fn default_acme_preflight() -> bool {
+    true
+}
 
-    pub fn has_tls_files(&self) -> bool {
-        self.tls_cert.is_some() && self.tls_key.is_some()
-    }
+fn default_acme_dns_propagation_secs() -> u64 {
+    10
+}
 
-    pub fn acme_enabled(&self) -> bool {
-        self.acme.enabled && !self.acme.domains.is_empty()
-    }
+/// Limits how often a single client IP may trigger backend spawns
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ColdStartThrottleConfig {
+    /// Enable the throttle (default: false)
+    #[serde(default)]
+    pub enabled: bool,
 
-    /// Get HTTP port (0 means disabled)
-    pub fn http_port(&self) -> u16 {
-        self.port
-    }
+    /// Spawn-triggering requests allowed per client within a window
+    #[serde(default = "default_throttle_burst")]
+    pub burst: u32,
 
-    /// Get HTTPS port (0 means disabled)
-    pub fn https_port(&self) -> u16 {
-        if !self.tls_enabled() {
-            return 0;
-        }
-        self.tls_port.unwrap_or(443)
-    }
+    /// Length of the counting window in seconds
+    #[serde(default = "default_throttle_window")]
+    pub window_secs: u64,
+
+    /// How long a client exceeding the burst is refused cold starts, in seconds
+    #[serde(default = "default_throttle_ban")]
+    pub ban_secs: u64,
 }
 
-impl Default for ServerConfig {
+impl Default for ColdStartThrottleConfig {
     fn default() -> Self {
         Self {
-            port: default_listen_port(),
-            tls_port: None,
-            bind: default_bind_address(),
-            admin_port: default_admin_port(),
-            admin_token: None,
-            pool_max_idle_per_host: default_pool_max_idle_per_host(),
-            pool_idle_timeout_secs: default_pool_idle_timeout(),
-            pid_file: None,
-            tls: false,
-            tls_cert: None,
-            tls_key: None,
-            force_https: false,
-            acme: AcmeConfig::default(),
+            enabled: false,
+            burst: default_throttle_burst(),
+            window_secs: default_throttle_window(),
+            ban_secs: default_throttle_ban(),
         }
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
-pub struct BackendDefaults {
-    /// Default idle timeout in seconds before shutting down a backend
-    #[serde(default = "default_idle_timeout")]
-    pub idle_timeout_secs: u64,
+/// Locks out client IPs that repeatedly fail admin API authentication
+///
+/// Each lockout of the same client lasts twice as long as the previous one,
+/// up to `max_lockout_secs`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AdminLockoutConfig {
+    /// Enable lockouts (default: true)
+    #[serde(default = "default_admin_lockout_enabled")]
+    pub enabled: bool,
 
-    /// Default startup timeout in seconds
-    #[serde(default = "default_startup_timeout")]
-    pub startup_timeout_secs: u64,
+    /// Failed attempts allowed per client within a window
+    #[serde(default = "default_admin_lockout_max_failures")]
+    pub max_failures: u32,
 
-    /// Default health check interval in milliseconds
-    #[serde(default = "default_health_interval")]
-    pub health_check_interval_ms: u64,
+    /// Length of the counting window in seconds
+    #[serde(default = "default_admin_lockout_window")]
+    pub window_secs: u64,
 
-    /// Default health endpoint path
-    #[serde(default = "default_health_path")]
-    pub health_path: String,
+    /// Duration of the first lockout in seconds
+    #[serde(default = "default_admin_lockout_secs")]
+    pub lockout_secs: u64,
 
-    /// Default grace period in seconds between SIGTERM and SIGKILL
-    #[serde(default = "default_shutdown_grace_period")]
-    pub shutdown_grace_period_secs: u64,
+    /// Upper bound for repeated lockouts in seconds
+    #[serde(default = "default_admin_lockout_max_secs")]
+    pub max_lockout_secs: u64,
+}
 
-    /// Default drain timeout in seconds (wait for in-flight requests before SIGTERM)
-    #[serde(default = "default_drain_timeout")]
-    pub drain_timeout_secs: u64,
+impl Default for AdminLockoutConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_admin_lockout_enabled(),
+            max_failures: default_admin_lockout_max_failures(),
+            window_secs: default_admin_lockout_window(),
+            lockout_secs: default_admin_lockout_secs(),
+            max_lockout_secs: default_admin_lockout_max_secs(),
+        }
+    }
+}
 
-    /// Default request timeout in seconds (max time to wait for backend response)
-    #[serde(default = "default_request_timeout")]
-    pub request_timeout_secs: u64,
+/// Status classes accepted as keys of trace sample rates
+pub const TRACE_STATUS_CLASSES: [&str; 5] = ["1xx", "2xx", "3xx", "4xx", "5xx"];
 
-    /// Default health check interval for ready backends in milliseconds
-    #[serde(default = "default_ready_health_interval")]
-    pub ready_health_check_interval_ms: u64,
+/// Sampling rules for per-request trace events
+///
+/// Rates are fractions between 0.0 and 1.0 keyed by status class, so e.g.
+/// all 5xx responses and 1% of 2xx responses can be traced. Backends may
+/// override individual classes with `trace_sample_rates`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TraceSamplingConfig {
+    /// Enable trace events (default: false)
+    #[serde(default)]
+    pub enabled: bool,
 
-    /// Number of consecutive health check failures before marking backend unhealthy
-    #[serde(default = "default_unhealthy_threshold")]
-    pub unhealthy_threshold: u32,
+    /// Requests carrying this header are always traced (default: X-Debug-Trace)
+    #[serde(default = "default_trace_force_header")]
+    pub force_header: String,
+
+    /// Sample rate per status class ("2xx", "5xx", ...)
+    #[serde(default)]
+    pub rates: HashMap<String, f64>,
+
+    /// Sample rate for classes without an entry in `rates` (default: 0.0)
+    #[serde(default)]
+    pub default_rate: f64,
 }
 
-impl Default for BackendDefaults {
+impl Default for TraceSamplingConfig {
     fn default() -> Self {
         Self {
-            idle_timeout_secs: default_idle_timeout(),
-            startup_timeout_secs: default_startup_timeout(),
-            health_check_interval_ms: default_health_interval(),
-            health_path: default_health_path(),
-            shutdown_grace_period_secs: default_shutdown_grace_period(),
-            drain_timeout_secs: default_drain_timeout(),
-            request_timeout_secs: default_request_timeout(),
-            ready_health_check_interval_ms: default_ready_health_interval(),
-            unhealthy_threshold: default_unhealthy_threshold(),
+            enabled: false,
+            force_header: default_trace_force_header(),
+            rates: HashMap::new(),
+            default_rate: 0.0,
         }
     }
 }
 
-
-/// Backend type: local process or Docker container
-#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
-#[serde(rename_all = "lowercase")]
-pub enum BackendType {
-    /// Local process spawned directly (default)
-    #[default]
-    Local,
-    /// Docker container managed via Docker API
-    Docker,
-}
-
-/// Image pull policy for Docker backends
-#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
-#[serde(rename_all = "lowercase")]
-pub enum PullPolicy {
-    /// Pull if image doesn't exist locally (default)
-    #[default]
-    #[serde(alias = "if-not-present")]
-    IfNotPresent,
-    /// Always pull before starting
-    Always,
-    /// Never pull, fail if image doesn't exist
-    Never,
+fn default_trace_force_header() -> String {
+    "X-Debug-Trace".to_string()
 }
 
-/// Configuration for a single backend
-///
-/// # Security Warning
+/// Logging of requests that take longer than a threshold
 ///
-/// For local backends, the `command` and `args` fields allow arbitrary command execution.
-/// For Docker backends, container images are pulled and run.
-/// Configuration files must be protected with appropriate file permissions
-/// (e.g., readable only by the service user). Malicious configuration files
-/// could execute arbitrary code with the permissions of the proxy process.
-#[derive(Debug, Deserialize, Clone)]
-pub struct BackendConfig {
-    /// Backend type: "local" (default) or "docker"
-    #[serde(default, rename = "type")]
-    pub backend_type: BackendType,
+/// Slow requests are logged with a timing breakdown and kept for
+/// `window_secs` so the admin API can summarize the slowest endpoints.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SlowRequestConfig {
+    /// Enable the slow request log (default: false)
+    #[serde(default)]
+    pub enabled: bool,
 
-    // === Local process fields ===
-    /// Command to execute to start the backend (local only)
-    ///
-    /// **Security:** This command is executed directly. Ensure config files
-    /// are protected and commands come from trusted sources only.
-    pub command: Option<String>,
+    /// Requests taking at least this long, including the response body, are slow
+    #[serde(default = "default_slow_request_threshold")]
+    pub threshold_ms: u64,
 
-    /// Arguments to pass to the command (local only)
-    #[serde(default)]
-    pub args: Vec<String>,
+    /// How long slow requests are kept for the summary in seconds
+    #[serde(default = "default_slow_request_window")]
+    pub window_secs: u64,
 
-    /// Working directory for the command (local only)
-    pub working_dir: Option<String>,
+    /// Endpoints listed per backend in the summary
+    #[serde(default = "default_slow_request_top")]
+    pub top: usize,
+}
 
-    // === Docker-specific fields ===
-    /// Docker image to run (required for Docker backends)
-    pub image: Option<String>,
+impl Default for SlowRequestConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold_ms: default_slow_request_threshold(),
+            window_secs: default_slow_request_window(),
+            top: default_slow_request_top(),
+        }
+    }
+}
 
-    /// Container name (default: spawngate-{hostname})
-    pub container_name: Option<String>,
+fn default_slow_request_threshold() -> u64 {
+    1000
+}
 
-    /// Docker host URL (default: unix:///var/run/docker.sock)
-    pub docker_host: Option<String>,
+fn default_slow_request_window() -> u64 {
+    3600
+}
 
-    /// Docker network to connect to (default: bridge)
-    pub network: Option<String>,
+fn default_slow_request_top() -> usize {
+    10
+}
 
-    /// Image pull policy: "always", "never", or "if-not-present" (default)
+/// Cold-start latency SLO, tracked per backend
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ColdStartSloConfig {
+    /// Track attainment and burn rate (default: false)
     #[serde(default)]
-    pub pull_policy: PullPolicy,
+    pub enabled: bool,
 
-    /// Memory limit (e.g., "512m", "1g")
-    pub memory: Option<String>,
+    /// Cold-start latency a request may see, in milliseconds
+    #[serde(default = "default_cold_start_slo_threshold")]
+    pub threshold_ms: u64,
 
-    /// CPU limit (e.g., "0.5", "2")
-    pub cpus: Option<String>,
+    /// Share of requests that must stay under the threshold (e.g. 0.99)
+    #[serde(default = "default_cold_start_slo_target")]
+    pub target: f64,
 
-    // === Common fields ===
-    /// Environment variables to set
-    #[serde(default)]
-    pub env: HashMap<String, String>,
+    /// Rolling window attainment and burn rate are computed over, in seconds
+    #[serde(default = "default_cold_start_slo_window")]
+    pub window_secs: u64,
 
-    /// Port the backend will listen on
-    pub port: u16,
+    /// Alert when the error budget burns this many times faster than sustainable
+    #[serde(default = "default_cold_start_slo_alert_burn_rate")]
+    pub alert_burn_rate: f64,
+}
 
-    /// Health check endpoint path (overrides default)
-    pub health_path: Option<String>,
+impl Default for ColdStartSloConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold_ms: default_cold_start_slo_threshold(),
+            target: default_cold_start_slo_target(),
+            window_secs: default_cold_start_slo_window(),
+            alert_burn_rate: default_cold_start_slo_alert_burn_rate(),
+        }
+    }
+}
 
-    /// Idle timeout in seconds (overrides default)
-    pub idle_timeout_secs: Option<u64>,
+fn default_cold_start_slo_threshold() -> u64 {
+    2000
+}
 
-    /// Startup timeout in seconds (overrides default)
-    pub startup_timeout_secs: Option<u64>,
+fn default_cold_start_slo_target() -> f64 {
+    0.99
+}
 
-    /// Health check interval in milliseconds (overrides default)
-    pub health_check_interval_ms: Option<u64>,
+fn default_cold_start_slo_window() -> u64 {
+    3600
+}
 
-    /// Grace period in seconds between SIGTERM and SIGKILL (overrides default)
-    pub shutdown_grace_period_secs: Option<u64>,
+fn default_cold_start_slo_alert_burn_rate() -> f64 {
+    10.0
+}
 
-    /// Drain timeout in seconds (wait for in-flight requests before SIGTERM, overrides default)
-    pub drain_timeout_secs: Option<u64>,
+/// Registration of the backends as services in the Consul catalog
+///
+/// Every backend is registered with the proxy's address and port, since
+/// clients reach it (and start it) through the proxy.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ConsulConfig {
+    #[serde(default)]
+    pub enabled: bool,
 
-    /// Request timeout in seconds (overrides default)
-    pub request_timeout_secs: Option<u64>,
+    /// HTTP address of the Consul agent
+    #[serde(default = "default_consul_address")]
+    pub address: String,
 
-    /// Health check interval for ready backends in milliseconds (overrides default)
-    pub ready_health_check_interval_ms: Option<u64>,
+    /// ACL token sent as `X-Consul-Token`
+    pub token: Option<String>,
 
-    /// Number of consecutive health check failures before marking backend unhealthy (overrides default)
-    pub unhealthy_threshold: Option<u32>,
+    /// Address registered for the services (default: the agent's address)
+    pub service_address: Option<String>,
+
+    /// Tags added to every service
+    #[serde(default = "default_consul_tags")]
+    pub tags: Vec<String>,
 }
 
-impl BackendConfig {
-    /// Create a new local backend config with defaults
-    pub fn local(command: &str, port: u16) -> Self {
+impl Default for ConsulConfig {
+    fn default() -> Self {
         Self {
-            backend_type: BackendType::Local,
-            command: Some(command.to_string()),
-            args: Vec::new(),
-            working_dir: None,
-            image: None,
-            container_name: None,
-            docker_host: None,
-            network: None,
-            pull_policy: PullPolicy::default(),
-            memory: None,
-            cpus: None,
-            env: HashMap::new(),
-            port,
-            health_path: None,
-            idle_timeout_secs: None,
-            startup_timeout_secs: None,
-            health_check_interval_ms: None,
-            shutdown_grace_period_secs: None,
-            drain_timeout_secs: None,
-            request_timeout_secs: None,
-            ready_health_check_interval_ms: None,
-            unhealthy_threshold: None,
+            enabled: false,
+            address: default_consul_address(),
+            token: None,
+            service_address: None,
+            tags: default_consul_tags(),
         }
     }
+}
 
-    /// Create a new Docker backend config with defaults
-    pub fn docker(image: &str, port: u16) -> Self {
+fn default_consul_address() -> String {
+    "http://127.0.0.1:8500".to_string()
+}
+
+fn default_consul_tags() -> Vec<String> {
+    vec!["spawngate".to_string()]
+}
+
+/// What happens when the proxy shuts down
+///
+/// Hooks run in order before the listeners stop accepting, e.g. to take the
+/// proxy out of an external load balancer. The state snapshot is rewritten
+/// every `state_interval_secs` too, so a crash leaves a recent one behind.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ShutdownConfig {
+    /// Commands to run on shutdown
+    #[serde(default)]
+    pub hooks: Vec<ShutdownHook>,
+
+    /// JSON file the running backends and queued requests are written to
+    pub state_file: Option<String>,
+
+    /// Seconds between snapshots while running, 0 to only write one on
+    /// shutdown (default: 30)
+    #[serde(default = "default_shutdown_state_interval")]
+    pub state_interval_secs: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
         Self {
-            backend_type: BackendType::Docker,
-            command: None,
-            args: Vec::new(),
-            working_dir: None,
-            image: Some(image.to_string()),
-            container_name: None,
-            docker_host: None,
-            network: None,
-            pull_policy: PullPolicy::default(),
-            memory: None,
-            cpus: None,
-            env: HashMap::new(),
-            port,
-            health_path: None,
-            idle_timeout_secs: None,
-            startup_timeout_secs: None,
-            health_check_interval_ms: None,
-            shutdown_grace_period_secs: None,
-            drain_timeout_secs: None,
-            request_timeout_secs: None,
-            ready_health_check_interval_ms: None,
-            unhealthy_threshold: None,
+            hooks: Vec::new(),
+            state_file: None,
+            state_interval_secs: default_shutdown_state_interval(),
         }
     }
+}
 
-    /// Set arguments for this backend config (builder pattern)
-    pub fn with_args(mut self, args: Vec<String>) -> Self {
-        self.args = args;
-        self
-    }
+/// A command run on shutdown
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ShutdownHook {
+    /// Command to execute
+    pub command: String,
 
-    /// Set environment variables (builder pattern)
-    pub fn with_env(mut self, env: HashMap<String, String>) -> Self {
-        self.env = env;
-        self
-    }
+    /// Arguments to pass to the command
+    #[serde(default)]
+    pub args: Vec<String>,
 
-    /// Set working directory (builder pattern)
-    pub fn with_working_dir(mut self, dir: &str) -> Self {
-        self.working_dir = Some(dir.to_string());
-        self
-    }
+    /// Seconds the command may run before it's killed (default: 10)
+    #[serde(default = "default_shutdown_hook_timeout")]
+    pub timeout_secs: u64,
+}
 
-    pub fn idle_timeout(&self, defaults: &BackendDefaults) -> Duration {
-        Duration::from_secs(self.idle_timeout_secs.unwrap_or(defaults.idle_timeout_secs))
-    }
+/// What happens when a thread or task panics
+///
+/// Panics are always logged with a backtrace. Read when the proxy starts.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct PanicConfig {
+    /// Abort the whole process when a task panics, instead of failing only
+    /// that task (default: false). Aborting leaves a core dump where the OS
+    /// is set up for one
+    #[serde(default)]
+    pub abort: bool,
 
-    pub fn startup_timeout(&self, defaults: &BackendDefaults) -> Duration {
-        Duration::from_secs(self.startup_timeout_secs.unwrap_or(defaults.startup_timeout_secs))
-    }
+    /// File counting the panics across restarts
+    pub crash_count_file: Option<String>,
 
-    pub fn health_check_interval(&self, defaults: &BackendDefaults) -> Duration {
-        Duration::from_millis(
-            self.health_check_interval_ms
-                .unwrap_or(defaults.health_check_interval_ms),
-        )
-    }
+    /// Directory a crash dump (message, location, thread and backtrace) is
+    /// written to for every panic
+    pub dump_dir: Option<String>,
+}
 
-    pub fn health_path<'a>(&'a self, defaults: &'a BackendDefaults) -> &'a str {
-        self.health_path
-            .as_deref()
-            .unwrap_or(&defaults.health_path)
-    }
+/// Backends defined by files that external automation writes to a directory
+///
+/// Every `*.json` or `*.toml` file in `dir` maps hostnames to backends, with
+/// the same settings as `[backends.*]`. The directory is checked every
+/// `interval_secs` and changes are applied like a reload.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FileSdConfig {
+    /// Directory to read; discovery is off unless it's set
+    pub dir: Option<String>,
+
+    /// Seconds between checks for changed files (default: 10)
+    #[serde(default = "default_file_sd_interval")]
+    pub interval_secs: u64,
+}
 
-    pub fn shutdown_grace_period(&self, defaults: &BackendDefaults) -> Duration {
-        Duration::from_secs(
-            self.shutdown_grace_period_secs
-                .unwrap_or(defaults.shutdown_grace_period_secs),
-        )
+impl Default for FileSdConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            interval_secs: default_file_sd_interval(),
+        }
     }
+}
 
-    pub fn drain_timeout(&self, defaults: &BackendDefaults) -> Duration {
-        Duration::from_secs(
-            self.drain_timeout_secs
-                .unwrap_or(defaults.drain_timeout_secs),
-        )
-    }
+fn default_file_sd_interval() -> u64 {
+    10
+}
 
-    pub fn request_timeout(&self, defaults: &BackendDefaults) -> Duration {
-        Duration::from_secs(
-            self.request_timeout_secs
-                .unwrap_or(defaults.request_timeout_secs),
-        )
-    }
+/// Backends defined by keys under a prefix of a key-value store
+///
+/// Every key under `prefix` holds the backend for the hostname that follows
+/// the prefix, as JSON with the same settings as `[backends.*]`. The keys are
+/// read every `interval_secs` and changes are applied like a reload.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KvConfig {
+    #[serde(default)]
+    pub enabled: bool,
 
-    pub fn ready_health_check_interval(&self, defaults: &BackendDefaults) -> Duration {
-        Duration::from_millis(
-            self.ready_health_check_interval_ms
-                .unwrap_or(defaults.ready_health_check_interval_ms),
-        )
-    }
+    /// Store holding the keys (default: consul)
+    #[serde(default)]
+    pub store: KvStore,
 
-    pub fn unhealthy_threshold(&self, defaults: &BackendDefaults) -> u32 {
-        self.unhealthy_threshold
-            .unwrap_or(defaults.unhealthy_threshold)
+    /// HTTP address of the Consul agent or etcd member (default: the
+    /// store's local address)
+    pub address: Option<String>,
+
+    /// Prefix of the backend keys
+    #[serde(default = "default_kv_prefix")]
+    pub prefix: String,
+
+    /// ACL token sent as `X-Consul-Token`, or etcd auth token sent as
+    /// `Authorization`
+    pub token: Option<String>,
+
+    /// Seconds between reads of the keys (default: 10)
+    #[serde(default = "default_kv_interval")]
+    pub interval_secs: u64,
+}
+
+impl KvConfig {
+    /// Configured address, or the store's default one
+    pub fn address(&self) -> &str {
+        match (&self.address, self.store) {
+            (Some(address), _) => address,
+            (None, KvStore::Consul) => "http://127.0.0.1:8500",
+            (None, KvStore::Etcd) => "http://127.0.0.1:2379",
+        }
     }
+}
 
-    /// Validate the backend configuration
-    pub fn validate(&self, hostname: &str) -> Result<(), String> {
-        match self.backend_type {
-            BackendType::Local => {
-                if self.command.is_none() {
-                    return Err(format!(
-                        "Backend '{}': local backend requires 'command' field",
-                        hostname
-                    ));
-                }
-            }
-            BackendType::Docker => {
-                if self.image.is_none() {
-                    return Err(format!(
-                        "Backend '{}': Docker backend requires 'image' field",
-                        hostname
-                    ));
-                }
-            }
+impl Default for KvConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            store: KvStore::default(),
+            address: None,
+            prefix: default_kv_prefix(),
+            token: None,
+            interval_secs: default_kv_interval(),
         }
+    }
+}
+
+/// Key-value store backends are read from
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum KvStore {
+    /// Consul's KV store, via the agent's HTTP API (default)
+    #[default]
+    Consul,
+    /// etcd, via the v3 JSON gateway
+    Etcd,
+}
+
+fn default_kv_prefix() -> String {
+    "spawngate/backends/".to_string()
+}
 
-        if self.port == 0 {
+fn default_kv_interval() -> u64 {
+    10
+}
+
+fn default_shutdown_state_interval() -> u64 {
+    30
+}
+
+fn default_shutdown_hook_timeout() -> u64 {
+    10
+}
+
+/// Check that sample rates use known status classes and lie within 0.0..=1.0
+fn validate_sample_rates(rates: &HashMap<String, f64>) -> Result<(), String> {
+    let mut classes: Vec<_> = rates.keys().collect();
+    classes.sort();
+    for class in classes {
+        if !TRACE_STATUS_CLASSES.contains(&class.as_str()) {
             return Err(format!(
-                "Backend '{}': 'port' must be greater than 0",
-                hostname
+                "unknown status class '{}' (expected one of {})",
+                class,
+                TRACE_STATUS_CLASSES.join(", ")
             ));
         }
-
-        Ok(())
+        if !(0.0..=1.0).contains(&rates[class]) {
+            return Err(format!("rate for '{}' must be between 0.0 and 1.0", class));
+        }
     }
+    Ok(())
 }
 
-// Default value functions
-fn default_listen_port() -> u16 {
-    80
+fn default_admin_lockout_enabled() -> bool {
+    true
 }
 
-fn default_bind_address() -> String {
-    "0.0.0.0".to_string()
+fn default_admin_lockout_max_failures() -> u32 {
+    5
 }
 
-fn default_admin_port() -> u16 {
-    9999
+fn default_admin_lockout_window() -> u64 {
+    300
 }
 
-fn default_pool_max_idle_per_host() -> usize {
-    10 // Keep up to 10 idle connections per backend
+fn default_admin_lockout_secs() -> u64 {
+    60
 }
 
-fn default_pool_idle_timeout() -> u64 {
-    90 // Close idle connections after 90 seconds
+fn default_admin_lockout_max_secs() -> u64 {
+    3600
 }
 
-fn default_idle_timeout() -> u64 {
-    600 // 10 minutes
-}
+/// Profiling endpoints of the admin API
+///
+/// `GET /debug/pprof/profile` samples the CPU for `?seconds=` and returns a
+/// flamegraph (`pprof` build feature). `GET /debug/pprof/heap` returns a
+/// jemalloc heap profile of the allocations made meanwhile (`jemalloc`
+/// build feature).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProfilingConfig {
+    /// Enable the profiling endpoints (default: false)
+    #[serde(default)]
+    pub enabled: bool,
 
-fn default_startup_timeout() -> u64 {
-    30 // 30 seconds
+    /// Longest profile a request may ask for in seconds (default: 60)
+    #[serde(default = "default_profiling_max_seconds")]
+    pub max_seconds: u64,
 }
 
-fn default_health_interval() -> u64 {
-    100 // 100ms
+impl Default for ProfilingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_seconds: default_profiling_max_seconds(),
+        }
+    }
 }
 
-fn default_health_path() -> String {
-    "/health".to_string()
+fn default_profiling_max_seconds() -> u64 {
+    60
 }
 
-fn default_shutdown_grace_period() -> u64 {
-    10 // 10 seconds between SIGTERM and SIGKILL
+/// Periodically append per-backend request metrics to a CSV file
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MetricsExportConfig {
+    /// Enable the exporter (default: false)
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// CSV file to append to (created with a header if missing)
+    #[serde(default = "default_metrics_export_path")]
+    pub path: String,
+
+    /// Seconds between exports; each row aggregates one interval
+    #[serde(default = "default_metrics_export_interval")]
+    pub interval_secs: u64,
+
+    /// Only export backends whose labels match this selector
+    pub selector: Option<Selector>,
 }
 
-fn default_drain_timeout() -> u64 {
-    30 // 30 seconds to wait for in-flight requests to complete
+impl Default for MetricsExportConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: default_metrics_export_path(),
+            interval_secs: default_metrics_export_interval(),
+            selector: None,
+        }
+    }
 }
 
-fn default_request_timeout() -> u64 {
-    30 // 30 seconds max for backend to respond
+/// Watches `tls_cert`/`tls_key` and per-backend certificate files
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CertWatchConfig {
+    /// Enable the watcher (default: true)
+    #[serde(default = "default_cert_watch_enabled")]
+    pub enabled: bool,
+
+    /// Seconds between checks for replaced files
+    #[serde(default = "default_cert_watch_interval")]
+    pub interval_secs: u64,
+
+    /// Warn once fewer than this many days of validity remain
+    #[serde(default = "default_cert_renew_before_days")]
+    pub renew_before_days: u64,
 }
 
-fn default_ready_health_interval() -> u64 {
-    5000 // 5 seconds between health checks for ready backends
+impl Default for CertWatchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_cert_watch_enabled(),
+            interval_secs: default_cert_watch_interval(),
+            renew_before_days: default_cert_renew_before_days(),
+        }
+    }
 }
 
-fn default_unhealthy_threshold() -> u32 {
-    3 // 3 consecutive failures before marking unhealthy
-}
+/// TLS session resumption settings
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TlsSessionConfig {
+    /// Sessions kept in the server-side resumption cache (0 disables it)
+    #[serde(default = "default_session_cache_size")]
+    pub cache_size: usize,
+
+    /// Issue stateless session tickets (default: false)
+    #[serde(default)]
+    pub tickets: bool,
+
+    /// Ticket lifetime hint in seconds; random keys are rotated at this interval
+    #[serde(default = "default_ticket_lifetime")]
+    pub ticket_lifetime_secs: u32,
+
+    /// File of ticket keys shared across restarts and cluster nodes
+    /// (created with one random key if missing)
+    pub ticket_key_file: Option<String>,
+
+    /// TLS 1.3 tickets sent after each full handshake
+    #[serde(default = "default_tickets_per_handshake")]
+    pub tickets_per_handshake: usize,
+}
+
+impl Default for TlsSessionConfig {
+    fn default() -> Self {
+        Self {
+            cache_size: default_session_cache_size(),
+            tickets: false,
+            ticket_lifetime_secs: default_ticket_lifetime(),
+            ticket_key_file: None,
+            tickets_per_handshake: default_tickets_per_handshake(),
+        }
+    }
+}
+
+/// Methods that may be processed from early data, as replaying them is harmless
+pub const EARLY_DATA_SAFE_METHODS: &[&str] = &["GET", "HEAD", "OPTIONS"];
+
+/// TLS 1.3 early data (0-RTT) settings
+///
+/// Early data can be replayed by an attacker, so only requests with one of
+/// `methods` are processed before the handshake completes; others get
+/// `425 Too Early` and are retried by the client after the handshake.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EarlyDataConfig {
+    /// Accept early data on resumed connections (default: false)
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Maximum early data accepted per connection, in bytes
+    #[serde(default = "default_early_data_max_size")]
+    pub max_size: u32,
+
+    /// Request methods processed from early data (default: GET)
+    #[serde(default = "default_early_data_methods")]
+    pub methods: Vec<String>,
+}
+
+impl Default for EarlyDataConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_size: default_early_data_max_size(),
+            methods: default_early_data_methods(),
+        }
+    }
+}
+
+impl EarlyDataConfig {
+    /// Whether a request with this method may be processed from early data
+    pub fn allows(&self, method: &str) -> bool {
+        self.methods.iter().any(|m| m.eq_ignore_ascii_case(method))
+    }
+}
+
+fn default_early_data_max_size() -> u32 {
+    16 * 1024
+}
+
+fn default_early_data_methods() -> Vec<String> {
+    vec!["GET".to_string()]
+}
+
+/// What clients whose fingerprint is on the blocklist are refused
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum FingerprintBlock {
+    /// Close the connection before the TLS handshake
+    #[default]
+    Connection,
+    /// Serve requests to running backends, but never start a stopped one
+    ColdStart,
+}
+
+/// JA3/JA4 fingerprinting of TLS client hellos
+///
+/// Fingerprints are logged with each request and can be forwarded to
+/// backends; blocklisted clients are refused connections or cold starts.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TlsFingerprintConfig {
+    /// Fingerprint TLS clients (default: false)
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Send `X-JA3-Fingerprint` and `X-JA4-Fingerprint` to backends (default: true)
+    #[serde(default = "default_fingerprint_forward_headers")]
+    pub forward_headers: bool,
+
+    /// JA3 hashes or JA4 fingerprints of clients to block
+    #[serde(default)]
+    pub blocklist: Vec<String>,
+
+    /// What blocklisted clients are refused: `connection` or `cold-start` (default: connection)
+    #[serde(default)]
+    pub block: FingerprintBlock,
+}
+
+impl Default for TlsFingerprintConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            forward_headers: default_fingerprint_forward_headers(),
+            blocklist: Vec::new(),
+            block: FingerprintBlock::default(),
+        }
+    }
+}
+
+fn default_fingerprint_forward_headers() -> bool {
+    true
+}
+
+fn default_session_cache_size() -> usize {
+    256
+}
+
+fn default_ticket_lifetime() -> u32 {
+    12 * 60 * 60
+}
+
+fn default_tickets_per_handshake() -> usize {
+    2
+}
+
+fn default_cert_watch_enabled() -> bool {
+    true
+}
+
+/// How percent-encoded separators (`%2F`, `%5C`) in request paths are handled
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EncodedSlashes {
+    /// Forward them encoded; they are not path separators (default)
+    #[default]
+    Keep,
+    /// Decode them to `/` before normalizing
+    Decode,
+    /// Reject the request with `400 INVALID_PATH`
+    Reject,
+}
+
+/// Normalizes request paths before routing so path-based rules can't be bypassed
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PathNormalizationConfig {
+    /// Normalize request paths (default: true)
+    #[serde(default = "default_path_normalization_enabled")]
+    pub enabled: bool,
+
+    /// Merge repeated slashes (default: true)
+    #[serde(default = "default_merge_slashes")]
+    pub merge_slashes: bool,
+
+    /// Handling of `%2F` and `%5C`: "keep" (default), "decode" or "reject"
+    #[serde(default)]
+    pub encoded_slashes: EncodedSlashes,
+
+    /// Reject paths that are not already normalized instead of rewriting them (default: false)
+    #[serde(default)]
+    pub strict: bool,
+}
+
+impl Default for PathNormalizationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_path_normalization_enabled(),
+            merge_slashes: default_merge_slashes(),
+            encoded_slashes: EncodedSlashes::default(),
+            strict: false,
+        }
+    }
+}
+
+fn default_path_normalization_enabled() -> bool {
+    true
+}
+
+fn default_merge_slashes() -> bool {
+    true
+}
+
+fn default_cert_watch_interval() -> u64 {
+    60
+}
+
+fn default_cert_renew_before_days() -> u64 {
+    30
+}
+
+fn default_metrics_export_path() -> String {
+    "./metrics.csv".to_string()
+}
+
+fn default_metrics_export_interval() -> u64 {
+    60
+}
+
+fn default_throttle_burst() -> u32 {
+    10
+}
+
+fn default_throttle_window() -> u64 {
+    60
+}
+
+fn default_throttle_ban() -> u64 {
+    600
+}
+
+impl ServerConfig {
+    pub fn tls_enabled(&self) -> bool {
+        self.acme_enabled() || self.tls || self.tls_cert.is_some() && self.tls_key.is_some()
+    }
+
+    pub fn has_tls_files(&self) -> bool {
+        self.tls_cert.is_some() && self.tls_key.is_some()
+    }
+
+    pub fn acme_enabled(&self) -> bool {
+        self.acme.enabled && !self.acme.domains.is_empty()
+    }
+
+    /// Get HTTP port (0 means disabled)
+    pub fn http_port(&self) -> u16 {
+        self.port
+    }
+
+    /// Get HTTPS port (0 means disabled)
+    pub fn https_port(&self) -> u16 {
+        if !self.tls_enabled() {
+            return 0;
+        }
+        self.tls_port.unwrap_or(443)
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            port: default_listen_port(),
+            tls_port: None,
+            bind: default_bind_address(),
+            admin_port: default_admin_port(),
+            admin_token: None,
+            pool_max_idle_per_host: default_pool_max_idle_per_host(),
+            pool_idle_timeout_secs: default_pool_idle_timeout(),
+            pool_shared: false,
+            listener_drain_timeout_secs: default_listener_drain_timeout(),
+            drain_close_keep_alive: default_drain_close_keep_alive(),
+            pid_file: None,
+            config_vars_file: None,
+            tls: false,
+            tls_cert: None,
+            tls_key: None,
+            force_https: false,
+            https_redirect_status: default_https_redirect_status(),
+            acme: AcmeConfig::default(),
+            cold_start_throttle: ColdStartThrottleConfig::default(),
+            metrics_export: MetricsExportConfig::default(),
+            cert_watch: CertWatchConfig::default(),
+            tls_session: TlsSessionConfig::default(),
+            early_data: EarlyDataConfig::default(),
+            tls_fingerprint: TlsFingerprintConfig::default(),
+            path_normalization: PathNormalizationConfig::default(),
+            admin_lockout: AdminLockoutConfig::default(),
+            trace_sampling: TraceSamplingConfig::default(),
+            slow_requests: SlowRequestConfig::default(),
+            cold_start_slo: ColdStartSloConfig::default(),
+            consul: ConsulConfig::default(),
+            shutdown: ShutdownConfig::default(),
+            panic: PanicConfig::default(),
+            file_sd: FileSdConfig::default(),
+            kv: KvConfig::default(),
+            profiling: ProfilingConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BackendDefaults {
+    /// Default idle timeout in seconds before shutting down a backend
+    #[serde(default = "default_idle_timeout")]
+    pub idle_timeout_secs: u64,
+
+    /// Default startup timeout in seconds
+    #[serde(default = "default_startup_timeout")]
+    pub startup_timeout_secs: u64,
+
+    /// Default longest time in seconds a request waits for a backend to start
+    /// before a 503 with retry hints (default: the startup timeout)
+    pub spawn_wait_secs: Option<u64>,
+
+    /// Default most requests waiting for a backend to start; more are
+    /// answered with a 503 right away (default: unlimited)
+    pub max_queue_depth: Option<u64>,
+
+    /// Default time in seconds after which a running backend is restarted
+    /// (default: never)
+    pub max_lifetime_secs: Option<u64>,
+
+    /// Default health check interval in milliseconds
+    #[serde(default = "default_health_interval")]
+    pub health_check_interval_ms: u64,
+
+    /// Default health endpoint path
+    #[serde(default = "default_health_path")]
+    pub health_path: String,
+
+    /// Default grace period in seconds between SIGTERM and SIGKILL
+    #[serde(default = "default_shutdown_grace_period")]
+    pub shutdown_grace_period_secs: u64,
+
+    /// Default drain timeout in seconds (wait for in-flight requests before SIGTERM)
+    #[serde(default = "default_drain_timeout")]
+    pub drain_timeout_secs: u64,
+
+    /// Default request timeout in seconds (max time to wait for backend response)
+    #[serde(default = "default_request_timeout")]
+    pub request_timeout_secs: u64,
+
+    /// Default health check interval for ready backends in milliseconds
+    #[serde(default = "default_ready_health_interval")]
+    pub ready_health_check_interval_ms: u64,
+
+    /// Number of consecutive health check failures before marking backend unhealthy
+    #[serde(default = "default_unhealthy_threshold")]
+    pub unhealthy_threshold: u32,
+
+    /// Default time zone (`TZ`), e.g. "Europe/Stockholm" (default: inherited from the proxy)
+    pub timezone: Option<String>,
+
+    /// Default locale (`LANG` and `LC_ALL`), e.g. "sv_SE.UTF-8" (default: inherited from the proxy)
+    pub locale: Option<String>,
+
+    /// Idle timeouts learned from the gaps between requests
+    #[serde(default)]
+    pub adaptive_idle: AdaptiveIdleConfig,
+
+    /// Backend sizes and prices for the savings report
+    #[serde(default)]
+    pub cost: CostModel,
+
+    /// Request inspection rules (SQL injection, XSS, header anomalies)
+    #[serde(default)]
+    pub waf: WafConfig,
+
+    /// Credentials for pulling images, by registry host (e.g. "ghcr.io",
+    /// "docker.io" for Docker Hub)
+    #[serde(default)]
+    pub registries: HashMap<String, RegistryAuth>,
+
+    /// Enforce `memory` and `cpus` of local backends with cgroups v2
+    #[serde(default)]
+    pub cgroups: CgroupConfig,
+
+    /// Range and policy for the ports of backends with `port = 0`
+    #[serde(default)]
+    pub ports: PortAllocationConfig,
+}
+
+impl Default for BackendDefaults {
+    fn default() -> Self {
+        Self {
+            idle_timeout_secs: default_idle_timeout(),
+            startup_timeout_secs: default_startup_timeout(),
+            spawn_wait_secs: None,
+            max_queue_depth: None,
+            max_lifetime_secs: None,
+            health_check_interval_ms: default_health_interval(),
+            health_path: default_health_path(),
+            shutdown_grace_period_secs: default_shutdown_grace_period(),
+            drain_timeout_secs: default_drain_timeout(),
+            request_timeout_secs: default_request_timeout(),
+            ready_health_check_interval_ms: default_ready_health_interval(),
+            unhealthy_threshold: default_unhealthy_threshold(),
+            timezone: None,
+            locale: None,
+            adaptive_idle: AdaptiveIdleConfig::default(),
+            cost: CostModel::default(),
+            waf: WafConfig::default(),
+            registries: HashMap::new(),
+            cgroups: CgroupConfig::default(),
+            ports: PortAllocationConfig::default(),
+        }
+    }
+}
+
+/// What to do with requests whose rule score reaches the threshold
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WafMode {
+    /// Log the matched rules and let the request through
+    Log,
+    /// Log the matched rules and reject the request with 403
+    #[default]
+    Block,
+}
+
+/// Scores requests against a small set of OWASP CRS-style rules
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WafConfig {
+    /// Inspect requests for all backends (default: false; backends can opt in or out)
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Anomaly score at which a request is logged or blocked (default: 5,
+    /// a single critical match)
+    #[serde(default = "default_waf_threshold")]
+    pub threshold: u32,
+
+    /// Whether requests over the threshold are only logged or blocked (default: block)
+    #[serde(default)]
+    pub mode: WafMode,
+}
+
+impl Default for WafConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold: default_waf_threshold(),
+            mode: WafMode::default(),
+        }
+    }
+}
+
+fn default_waf_threshold() -> u32 {
+    5
+}
+
+/// Weights the time backends spend stopped into compute saved
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CostModel {
+    /// vCPUs assumed for backends that don't set `cpus` (default: 1.0)
+    #[serde(default = "default_cost_cpus")]
+    pub cpus: f64,
+
+    /// Memory assumed for backends that don't set `memory` (default: "512m")
+    #[serde(default = "default_cost_memory")]
+    pub memory: String,
+
+    /// Price of one vCPU-hour, to estimate the amount saved
+    pub cpu_hour_price: Option<f64>,
+
+    /// Price of one GB-hour of memory, to estimate the amount saved
+    pub gb_hour_price: Option<f64>,
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        Self {
+            cpus: default_cost_cpus(),
+            memory: default_cost_memory(),
+            cpu_hour_price: None,
+            gb_hour_price: None,
+        }
+    }
+}
+
+fn default_cost_cpus() -> f64 {
+    1.0
+}
+
+fn default_cost_memory() -> String {
+    "512m".to_string()
+}
+
+/// Resource limits of local backends, enforced with cgroups v2 (Linux only)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CgroupConfig {
+    /// Run local backends with `memory` or `cpus` in a cgroup with those limits
+    /// (default: false, they only size the savings report)
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// cgroup2 directory the backends' cgroups are created in, writable by
+    /// the proxy (default: "/sys/fs/cgroup/spawngate")
+    #[serde(default = "default_cgroup_root")]
+    pub root: String,
+}
+
+impl Default for CgroupConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            root: default_cgroup_root(),
+        }
+    }
+}
+
+fn default_cgroup_root() -> String {
+    "/sys/fs/cgroup/spawngate".to_string()
+}
+
+/// Ports assigned to backends with `port = 0`, each time they start
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PortAllocationConfig {
+    /// First port of the range (default: 20000)
+    #[serde(default = "default_port_range_start")]
+    pub range_start: u16,
+
+    /// Last port of the range, inclusive (default: 29999)
+    #[serde(default = "default_port_range_end")]
+    pub range_end: u16,
+
+    /// Start a backend on its previous port again if that is free (default: true);
+    /// needs backends that listen with SO_REUSEADDR
+    #[serde(default = "default_port_reuse")]
+    pub reuse: bool,
+
+    /// Seconds a released port is not handed out again, to let connections
+    /// in TIME_WAIT expire (default: 60)
+    #[serde(default = "default_port_cooldown")]
+    pub cooldown_secs: u64,
+}
+
+impl Default for PortAllocationConfig {
+    fn default() -> Self {
+        Self {
+            range_start: default_port_range_start(),
+            range_end: default_port_range_end(),
+            reuse: default_port_reuse(),
+            cooldown_secs: default_port_cooldown(),
+        }
+    }
+}
+
+fn default_port_range_start() -> u16 {
+    20000
+}
+
+fn default_port_range_end() -> u16 {
+    29999
+}
+
+fn default_port_reuse() -> bool {
+    true
+}
+
+fn default_port_cooldown() -> u64 {
+    60
+}
+
+/// Tunes each backend's idle timeout to the gaps observed between its requests
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AdaptiveIdleConfig {
+    /// Learn idle timeouts for all backends (default: false; backends can opt in or out)
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Share of observed gaps the learned timeout covers (default: 0.9)
+    #[serde(default = "default_adaptive_idle_quantile")]
+    pub quantile: f64,
+
+    /// Gaps to observe before the learned timeout replaces `idle_timeout_secs`
+    #[serde(default = "default_adaptive_idle_min_samples")]
+    pub min_samples: usize,
+
+    /// Lower bound for learned timeouts in seconds
+    #[serde(default = "default_adaptive_idle_min_timeout")]
+    pub min_timeout_secs: u64,
+
+    /// Upper bound for learned timeouts in seconds
+    #[serde(default = "default_adaptive_idle_max_timeout")]
+    pub max_timeout_secs: u64,
+}
+
+impl Default for AdaptiveIdleConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            quantile: default_adaptive_idle_quantile(),
+            min_samples: default_adaptive_idle_min_samples(),
+            min_timeout_secs: default_adaptive_idle_min_timeout(),
+            max_timeout_secs: default_adaptive_idle_max_timeout(),
+        }
+    }
+}
+
+fn default_adaptive_idle_quantile() -> f64 {
+    0.9
+}
+
+fn default_adaptive_idle_min_samples() -> usize {
+    20
+}
+
+fn default_adaptive_idle_min_timeout() -> u64 {
+    60
+}
+
+fn default_adaptive_idle_max_timeout() -> u64 {
+    3600
+}
+
+
+/// Backend type: local process, Docker container, custom spawner, microVM, Nomad job, cloud VM or SSH tunnel
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendType {
+    /// Local process spawned directly (default)
+    #[default]
+    Local,
+    /// Docker container managed via Docker API
+    Docker,
+    /// Launched by a spawner registered with the process manager
+    Custom,
+    /// Firecracker microVM booted from a kernel and root filesystem
+    Firecracker,
+    /// Task group of a Nomad job, scaled from 0 to 1 on demand
+    Nomad,
+    /// Cloud instance started when traffic arrives and stopped when idle
+    #[serde(rename = "cloud-vm")]
+    CloudVm,
+    /// Service on a remote machine, reached through an SSH tunnel the proxy maintains
+    #[serde(rename = "ssh-tunnel")]
+    SshTunnel,
+}
+
+impl BackendType {
+    /// Whether backends of this type may use `port = 0`
+    ///
+    /// Processes and containers listen on the assigned `PORT`; the other types
+    /// relay or tunnel the port to the same port on another machine.
+    pub fn assigns_ports(&self) -> bool {
+        matches!(self, BackendType::Local | BackendType::Docker)
+    }
+}
+
+/// Engine running the containers of Docker backends
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContainerEngine {
+    /// The Docker API (default)
+    #[default]
+    Docker,
+    /// The `podman` CLI, e.g. for rootless Podman
+    Podman,
+}
+
+/// Credentials for pulling images from a registry
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct RegistryAuth {
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Registry bearer token, instead of a username and password
+    pub token: Option<String>,
+}
+
+impl RegistryAuth {
+    fn validate(&self) -> Result<(), String> {
+        match (&self.username, &self.password, &self.token) {
+            (Some(_), Some(_), None) | (None, None, Some(_)) => Ok(()),
+            (_, _, Some(_)) => Err("'token' can't be combined with 'username' and 'password'".to_string()),
+            _ => Err("requires 'username' and 'password', or 'token'".to_string()),
+        }
+    }
+}
+
+/// Image pull policy for Docker backends
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PullPolicy {
+    /// Pull if image doesn't exist locally (default)
+    #[default]
+    #[serde(alias = "if-not-present")]
+    IfNotPresent,
+    /// Always pull before starting
+    Always,
+    /// Never pull, fail if image doesn't exist
+    Never,
+}
+
+/// Settings of a Firecracker microVM backend
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FirecrackerConfig {
+    /// Uncompressed guest kernel (vmlinux)
+    pub kernel: String,
+
+    /// Root filesystem image, e.g. an ext4 file
+    pub rootfs: String,
+
+    /// Kernel command line; the guest's network settings and env vars are appended
+    #[serde(default = "default_firecracker_boot_args")]
+    pub boot_args: String,
+
+    /// Address of the host end of the tap device (a /30 with `guest_ip`)
+    pub host_ip: Ipv4Addr,
+
+    /// Address of the guest, where the backend listens on `port`
+    pub guest_ip: Ipv4Addr,
+
+    /// Tap device (default: "fc-" and a hash of the hostname), created if missing
+    pub tap_device: Option<String>,
+
+    /// Firecracker binary
+    #[serde(default = "default_firecracker_binary")]
+    pub binary: String,
+}
+
+fn default_firecracker_boot_args() -> String {
+    "console=ttyS0 reboot=k panic=1 pci=off".to_string()
+}
+
+fn default_firecracker_binary() -> String {
+    "firecracker".to_string()
+}
+
+/// Settings of a backend run as a Nomad job
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct NomadConfig {
+    /// HTTP address of the Nomad agent
+    #[serde(default = "default_nomad_address")]
+    pub address: String,
+
+    /// Job to scale
+    pub job: String,
+
+    /// Task group to scale (default: the job's name)
+    pub group: Option<String>,
+
+    /// Namespace of the job
+    pub namespace: Option<String>,
+
+    /// Label of the allocation's port to route to (default: its first port)
+    pub port_label: Option<String>,
+
+    /// ACL token sent as `X-Nomad-Token`
+    pub token: Option<String>,
+}
+
+fn default_nomad_address() -> String {
+    "http://127.0.0.1:4646".to_string()
+}
+
+/// Cloud provider of a cloud VM backend
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum CloudProvider {
+    /// Amazon EC2, with credentials from `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`
+    /// and optionally `AWS_SESSION_TOKEN`
+    Ec2,
+    /// Hetzner Cloud, with the API token from `HCLOUD_TOKEN`
+    Hetzner,
+}
+
+/// Settings of a backend run on a cloud VM
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CloudVmConfig {
+    pub provider: CloudProvider,
+
+    /// ID of the instance (EC2) or server (Hetzner)
+    pub instance_id: String,
+
+    /// Region of the instance (required for EC2)
+    pub region: Option<String>,
+
+    /// Reach the VM on its private address instead of its public one
+    #[serde(default)]
+    pub private_ip: bool,
+
+    /// Address of the VM, if fixed, instead of looking it up once it runs
+    pub address: Option<IpAddr>,
+}
+
+fn default_ssh_port() -> u16 {
+    22
+}
+
+fn default_remote_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn default_ssh_binary() -> String {
+    "ssh".to_string()
+}
+
+/// Settings of a backend reached through an SSH tunnel
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SshTunnelConfig {
+    /// SSH server to connect to
+    pub host: String,
+
+    /// SSH port (default: 22)
+    #[serde(default = "default_ssh_port")]
+    pub port: u16,
+
+    /// Login user (default: ssh's own default)
+    pub user: Option<String>,
+
+    /// Private key to authenticate with
+    pub identity_file: Option<String>,
+
+    /// known_hosts file to verify the server against (default: ssh's own)
+    pub known_hosts_file: Option<String>,
+
+    /// Host the service listens on, as seen from the SSH server (default: "127.0.0.1")
+    #[serde(default = "default_remote_host")]
+    pub remote_host: String,
+
+    /// Port of the service (default: the backend's port)
+    pub remote_port: Option<u16>,
+
+    /// ssh binary to run (default: "ssh")
+    #[serde(default = "default_ssh_binary")]
+    pub binary: String,
+}
+
+/// A container started and stopped together with a Docker backend's own
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SidecarConfig {
+    /// Host name the other containers of the backend reach it by
+    pub name: String,
+
+    /// Image to run
+    pub image: String,
+
+    /// Container command arguments (passed to CMD)
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    /// Environment variables to set
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+
+    /// Memory limit (e.g., "512m", "1g")
+    pub memory: Option<String>,
+
+    /// CPU limit (e.g., "0.5", "2")
+    pub cpus: Option<String>,
+
+    /// Credentials for pulling the image (default: from `[defaults.registries]`)
+    pub registry_auth: Option<RegistryAuth>,
+}
+
+/// What to do when a Docker backend's container exits without the proxy stopping it
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContainerExitPolicy {
+    /// Start a new container right away (default)
+    #[default]
+    Restart,
+    /// Mark the backend stopped; it is spawned again by the next request
+    Stop,
+}
+
+/// Extra conditions a backend must meet, besides its health check, to become ready
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ReadinessGates {
+    /// Backends that must be ready first (started on demand)
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+
+    /// File that must exist, e.g. written when migrations complete
+    pub marker_file: Option<String>,
+
+    /// Require approval via the admin API (`POST /approve/{hostname}`)
+    #[serde(default)]
+    pub manual_approval: bool,
+}
+
+impl ReadinessGates {
+    pub fn is_empty(&self) -> bool {
+        self.depends_on.is_empty() && self.marker_file.is_none() && !self.manual_approval
+    }
+}
+
+/// Faults injected into a backend's traffic while enabled via the admin API
+///
+/// Nothing is injected until `POST /faults/{hostname}` enables the faults, and
+/// they switch off by themselves after `duration_secs`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FaultConfig {
+    /// Fraction of requests answered with `error_status` instead of being forwarded
+    #[serde(default)]
+    pub error_rate: f64,
+
+    /// Status returned for injected errors (default: 500)
+    #[serde(default = "default_fault_error_status")]
+    pub error_status: u16,
+
+    /// Delay added before forwarding, in milliseconds
+    #[serde(default)]
+    pub latency_ms: u64,
+
+    /// Fraction of requests that get the added delay (default: 1.0)
+    #[serde(default = "default_fault_latency_rate")]
+    pub latency_rate: f64,
+
+    /// Fraction of requests whose client connection is dropped without a response
+    #[serde(default)]
+    pub drop_rate: f64,
+
+    /// Fraction of spawn attempts that fail
+    #[serde(default)]
+    pub spawn_failure_rate: f64,
+
+    /// How long faults stay enabled, in seconds (default: 300)
+    #[serde(default = "default_fault_duration")]
+    pub duration_secs: u64,
+}
+
+impl Default for FaultConfig {
+    fn default() -> Self {
+        Self {
+            error_rate: 0.0,
+            error_status: default_fault_error_status(),
+            latency_ms: 0,
+            latency_rate: default_fault_latency_rate(),
+            drop_rate: 0.0,
+            spawn_failure_rate: 0.0,
+            duration_secs: default_fault_duration(),
+        }
+    }
+}
+
+impl FaultConfig {
+    fn validate(&self) -> Result<(), String> {
+        for (name, rate) in [
+            ("error_rate", self.error_rate),
+            ("latency_rate", self.latency_rate),
+            ("drop_rate", self.drop_rate),
+            ("spawn_failure_rate", self.spawn_failure_rate),
+        ] {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(format!("'{}' must be between 0.0 and 1.0", name));
+            }
+        }
+        if !(400..=599).contains(&self.error_status) {
+            return Err("'error_status' must be between 400 and 599".to_string());
+        }
+        if self.duration_secs == 0 {
+            return Err("'duration_secs' must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn default_fault_error_status() -> u16 {
+    500
+}
+
+fn default_fault_latency_rate() -> f64 {
+    1.0
+}
+
+fn default_fault_duration() -> u64 {
+    300
+}
+
+/// How often a backend's traffic quota starts over
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaWindow {
+    /// Every clock hour
+    #[default]
+    Hour,
+    /// Every day at midnight UTC
+    Day,
+}
+
+impl QuotaWindow {
+    pub fn duration(self) -> Duration {
+        match self {
+            QuotaWindow::Hour => Duration::from_secs(3600),
+            QuotaWindow::Day => Duration::from_secs(86400),
+        }
+    }
+}
+
+/// What happens to a backend's traffic once its quota is used up
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaAction {
+    /// Reject requests with 429 until the window starts over
+    #[default]
+    Block,
+    /// Keep serving, with response bodies slowed to `throttle_bytes_per_sec`
+    Throttle,
+    /// Keep serving, only log that the quota was exceeded
+    Notify,
+}
+
+/// Caps on the requests and body bytes a backend serves per hour or day
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct QuotaConfig {
+    /// Window the caps apply to: `hour` or `day` (default: hour)
+    #[serde(default)]
+    pub window: QuotaWindow,
+
+    /// Requests allowed per window
+    pub max_requests: Option<u64>,
+
+    /// Request and response body bytes allowed per window
+    pub max_bytes: Option<u64>,
+
+    /// Over-quota behavior: `block`, `throttle` or `notify` (default: block)
+    #[serde(default)]
+    pub on_exceed: QuotaAction,
+
+    /// Response body rate once over quota with `on_exceed = "throttle"` (default: 65536)
+    #[serde(default = "default_quota_throttle_rate")]
+    pub throttle_bytes_per_sec: u64,
+}
+
+impl QuotaConfig {
+    fn validate(&self) -> Result<(), String> {
+        if self.max_requests.is_none() && self.max_bytes.is_none() {
+            return Err("'max_requests' or 'max_bytes' is required".to_string());
+        }
+        if self.max_requests == Some(0) || self.max_bytes == Some(0) {
+            return Err("'max_requests' and 'max_bytes' must be greater than 0".to_string());
+        }
+        if self.throttle_bytes_per_sec == 0 {
+            return Err("'throttle_bytes_per_sec' must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn default_quota_throttle_rate() -> u64 {
+    65536
+}
+
+/// Configuration for a single backend
+///
+/// # Security Warning
+///
+/// For local backends, the `command` and `args` fields allow arbitrary command execution.
+/// For Docker backends, container images are pulled and run.
+/// Configuration files must be protected with appropriate file permissions
+/// (e.g., readable only by the service user). Malicious configuration files
+/// could execute arbitrary code with the permissions of the proxy process.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BackendConfig {
+    /// Backend type: "local" (default) or "docker"
+    #[serde(default, rename = "type")]
+    pub backend_type: BackendType,
+
+    // === Local process fields ===
+    /// Command to execute to start the backend (local only)
+    ///
+    /// **Security:** This command is executed directly. Ensure config files
+    /// are protected and commands come from trusted sources only.
+    pub command: Option<String>,
+
+    /// Arguments to pass to the command (local only)
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    /// Working directory for the command (local only)
+    pub working_dir: Option<String>,
+
+    /// User to run the command as, by name or ID (local only; spawngate must run as root)
+    pub user: Option<String>,
+
+    /// Group to run the command as, by name or ID (local only; default: the user's primary group)
+    pub group: Option<String>,
+
+    // === Docker-specific fields ===
+    /// Docker image to run (required for Docker backends)
+    pub image: Option<String>,
+
+    /// Container name (default: spawngate-{hostname})
+    pub container_name: Option<String>,
+
+    /// Docker host URL (default: unix:///var/run/docker.sock); with Podman, the service passed as `--url`
+    pub docker_host: Option<String>,
+
+    /// Container engine: "docker" (default) or "podman"
+    #[serde(default)]
+    pub container_runtime: ContainerEngine,
+
+    /// Docker network to connect to (default: bridge)
+    pub network: Option<String>,
+
+    /// Image pull policy: "always", "never", or "if-not-present" (default)
+    #[serde(default)]
+    pub pull_policy: PullPolicy,
+
+    /// Credentials for pulling the image (default: from `[defaults.registries]`
+    /// for the image's registry)
+    pub registry_auth: Option<RegistryAuth>,
+
+    /// Digest the image is pinned to, e.g. "sha256:..." (also accepted as `image@sha256:...`)
+    pub image_digest: Option<String>,
+
+    /// Platform to pull and run the image for, e.g. "linux/arm64" (default: the daemon's)
+    pub platform: Option<String>,
+
+    /// Memory limit (e.g., "512m", "1g"); with `[defaults.cgroups]` enabled, local
+    /// backends are killed and restarted when they exceed it
+    pub memory: Option<String>,
+
+    /// CPU limit (e.g., "0.5", "2"), enforced as a CFS quota (for local
+    /// backends with `[defaults.cgroups]` enabled)
+    pub cpus: Option<String>,
+
+    /// Relative CPU weight while the host's CPUs are contended (Docker's default: 1024)
+    pub cpu_shares: Option<i64>,
+
+    /// Most processes and threads the container may run
+    pub pids_limit: Option<i64>,
+
+    /// What to do when the container exits on its own or is stopped out-of-band:
+    /// "restart" (default) or "stop"
+    #[serde(default)]
+    pub on_container_exit: ContainerExitPolicy,
+
+    /// Other containers started before the backend's own and stopped with it, on a private
+    /// network created for the backend; only the backend's own container is published
+    #[serde(default)]
+    pub sidecars: Vec<SidecarConfig>,
+
+    // === Custom backend fields ===
+    /// Name of the registered spawner that launches the backend (custom only)
+    pub spawner: Option<String>,
+
+    // === Firecracker fields ===
+    /// MicroVM kernel, root filesystem and network (firecracker only); sized by `cpus` and `memory`
+    pub firecracker: Option<FirecrackerConfig>,
+
+    // === Nomad fields ===
+    /// Job and task group the backend runs as (nomad only)
+    pub nomad: Option<NomadConfig>,
+
+    // === Cloud VM fields ===
+    /// Provider and instance the backend runs on (cloud-vm only)
+    pub cloud_vm: Option<CloudVmConfig>,
+
+    // === SSH tunnel fields ===
+    /// SSH server and remote service the backend is reached through (ssh-tunnel only)
+    pub ssh_tunnel: Option<SshTunnelConfig>,
+
+    // === Common fields ===
+    /// Environment variables to set
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+
+    /// Port the backend will listen on, or 0 to assign one from
+    /// `[defaults.ports]` each time it starts (passed as `PORT`; local and docker only)
+    pub port: u16,
+
+    /// Health check endpoint path (overrides default)
+    pub health_path: Option<String>,
+
+    /// Idle timeout in seconds (overrides default)
+    pub idle_timeout_secs: Option<u64>,
+
+    /// Startup timeout in seconds (overrides default)
+    pub startup_timeout_secs: Option<u64>,
+
+    /// Longest time in seconds a request waits for the backend to start
+    /// (overrides default)
+    pub spawn_wait_secs: Option<u64>,
+
+    /// Most requests waiting for the backend to start (overrides default)
+    pub max_queue_depth: Option<u64>,
+
+    /// Health check interval in milliseconds (overrides default)
+    pub health_check_interval_ms: Option<u64>,
+
+    /// Grace period in seconds between SIGTERM and SIGKILL (overrides default)
+    pub shutdown_grace_period_secs: Option<u64>,
+
+    /// Drain timeout in seconds (wait for in-flight requests before SIGTERM, overrides default)
+    pub drain_timeout_secs: Option<u64>,
+
+    /// Request timeout in seconds (overrides default)
+    pub request_timeout_secs: Option<u64>,
+
+    /// Health check interval for ready backends in milliseconds (overrides default)
+    pub ready_health_check_interval_ms: Option<u64>,
+
+    /// Number of consecutive health check failures before marking backend unhealthy (overrides default)
+    pub unhealthy_threshold: Option<u32>,
+
+    /// Time zone (`TZ`) for the backend (overrides default)
+    pub timezone: Option<String>,
+
+    /// Locale (`LANG` and `LC_ALL`) for the backend (overrides default)
+    pub locale: Option<String>,
+
+    /// Learn the idle timeout from observed traffic (overrides `adaptive_idle.enabled`)
+    pub adaptive_idle: Option<bool>,
+
+    /// Start the backend with the proxy and keep it running regardless of
+    /// traffic, skipping cold starts (default: false)
+    pub keep_warm: Option<bool>,
+
+    /// Restart the backend once it has run this many seconds (overrides default)
+    pub max_lifetime_secs: Option<u64>,
+
+    /// Profile to inherit unset settings from
+    pub profile: Option<String>,
+
+    /// Language runtime preset filling in settings left unset by the backend and its profile
+    pub runtime: Option<Runtime>,
+
+    /// Include this hostname in ACME issuance (default: true)
+    #[serde(default = "default_backend_acme")]
+    pub acme: bool,
+
+    /// Certificate file served for this hostname instead of ACME or the global certificate
+    pub tls_cert: Option<String>,
+
+    /// Private key file for `tls_cert`
+    pub tls_key: Option<String>,
+
+    /// Honor X-Accel-Redirect / X-Sendfile headers in responses from this backend
+    #[serde(default)]
+    pub internal_redirect: bool,
+
+    /// Directory that internal redirects may serve files from
+    pub internal_root: Option<String>,
+
+    /// Path prefixes this backend serves; other paths get a 404 without spawning it
+    /// (empty = allow all)
+    #[serde(default)]
+    pub allowed_paths: Vec<String>,
+
+    /// Redirect plain HTTP requests to HTTPS when `force_https` is on (default: true)
+    #[serde(default = "default_backend_https_redirect")]
+    pub https_redirect: bool,
+
+    /// Path prefixes served over plain HTTP even when `force_https` is on
+    #[serde(default)]
+    pub https_redirect_exclude: Vec<String>,
+
+    /// Trace sample rates per status class, overriding `[server.trace_sampling]`
+    #[serde(default)]
+    pub trace_sample_rates: HashMap<String, f64>,
+
+    /// Cold-start latency threshold in milliseconds, overriding `[server.cold_start_slo]`
+    pub cold_start_slo_ms: Option<u64>,
+
+    /// Cold-start SLO target, overriding `[server.cold_start_slo]`
+    pub cold_start_slo_target: Option<f64>,
+
+    /// Inspect requests with the WAF rules (overrides `waf.enabled`)
+    pub waf: Option<bool>,
+
+    /// WAF anomaly score threshold (overrides `waf.threshold`)
+    pub waf_threshold: Option<u32>,
+
+    /// Log or block requests over the WAF threshold (overrides `waf.mode`)
+    pub waf_mode: Option<WafMode>,
+
+    /// Send the request deadline as `X-Request-Deadline` and, for gRPC
+    /// requests, `grpc-timeout` (default: false)
+    #[serde(default)]
+    pub deadline_headers: bool,
+
+    /// Speak HTTP/2 with prior knowledge (h2c) to the backend, multiplexing
+    /// requests over one connection (default: false)
+    #[serde(default)]
+    pub backend_http2: bool,
+
+    /// Connect to the backend over TLS, with HTTP/2 if it selects it via ALPN
+    /// (default: false). The backend's certificate isn't verified.
+    #[serde(default)]
+    pub backend_tls: bool,
+
+    /// Find/replace pairs applied to response bodies and `Location` headers,
+    /// e.g. to fix absolute URLs the backend builds from its local address
+    #[serde(default)]
+    pub response_rewrites: Vec<ResponseRewrite>,
+
+    /// Media types whose bodies `response_rewrites` apply to
+    /// (default: text/html and application/json)
+    #[serde(default = "default_rewrite_content_types")]
+    pub rewrite_content_types: Vec<String>,
+
+    /// Backend that serves this host's traffic while it fails to start or is unhealthy
+    pub fallback: Option<String>,
+
+    /// Rules sending some of this host's requests to other backends, tried in
+    /// order; requests matching none are served by this backend
+    #[serde(default)]
+    pub routes: Vec<RouteRule>,
+
+    /// Rules putting requests into priority classes for the cold-start queue,
+    /// first match wins; requests matching none are `normal`
+    #[serde(default)]
+    pub priority_rules: Vec<PriorityRule>,
+
+    /// A/B experiment splitting requests no route matches between variants
+    pub experiment: Option<ExperimentConfig>,
+
+    /// URL clients use to reach this backend, injected as PUBLIC_URL
+    /// (default: derived from the hostname and the proxy's listener)
+    pub public_url: Option<String>,
+
+    /// Secret the proxy signs its health probes with, also injected as
+    /// SPAWNGATE_SIGNING_SECRET (see [`crate::signing`])
+    pub signing_secret: Option<String>,
+
+    /// Conditions checked together with the health probe before marking ready
+    #[serde(default)]
+    pub readiness: ReadinessGates,
+
+    /// Arbitrary labels for selecting groups of backends (e.g. `env = "prod"`)
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+
+    /// Backends this one's image or command may be promoted to
+    #[serde(default)]
+    pub promote_to: Vec<String>,
+
+    /// Faults that can be injected for resilience testing
+    pub faults: Option<FaultConfig>,
+
+    /// Caps on the traffic served per hour or day
+    pub quota: Option<QuotaConfig>,
+}
+
+/// Shared settings for groups of similar backends
+///
+/// Any field a backend leaves unset is taken from its profile; the global
+/// `[defaults]` still apply to anything neither of them sets.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct BackendProfile {
+    /// Memory limit (e.g., "512m", "1g")
+    pub memory: Option<String>,
+
+    /// CPU limit (e.g., "0.5", "2")
+    pub cpus: Option<String>,
+
+    /// Environment variables (backend `env` entries take precedence)
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+
+    /// Health check endpoint path
+    pub health_path: Option<String>,
+
+    /// Idle timeout in seconds
+    pub idle_timeout_secs: Option<u64>,
+
+    /// Startup timeout in seconds
+    pub startup_timeout_secs: Option<u64>,
+
+    /// Longest time in seconds a request waits for the backend to start
+    pub spawn_wait_secs: Option<u64>,
+
+    /// Most requests waiting for the backend to start
+    pub max_queue_depth: Option<u64>,
+
+    /// Health check interval in milliseconds
+    pub health_check_interval_ms: Option<u64>,
+
+    /// Grace period in seconds between SIGTERM and SIGKILL
+    pub shutdown_grace_period_secs: Option<u64>,
+
+    /// Drain timeout in seconds
+    pub drain_timeout_secs: Option<u64>,
+
+    /// Request timeout in seconds
+    pub request_timeout_secs: Option<u64>,
+
+    /// Health check interval for ready backends in milliseconds
+    pub ready_health_check_interval_ms: Option<u64>,
+
+    /// Consecutive health check failures before marking backend unhealthy
+    pub unhealthy_threshold: Option<u32>,
+
+    /// Time zone (`TZ`)
+    pub timezone: Option<String>,
+
+    /// Locale (`LANG` and `LC_ALL`)
+    pub locale: Option<String>,
+
+    /// Learn the idle timeout from observed traffic
+    pub adaptive_idle: Option<bool>,
+
+    /// Keep the backend running regardless of traffic
+    pub keep_warm: Option<bool>,
+
+    /// Seconds after which a running backend is restarted
+    pub max_lifetime_secs: Option<u64>,
+}
+
+/// Built-in presets for common application stacks
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Runtime {
+    /// `npm start`, with the port passed as `PORT`
+    Node,
+    /// gunicorn serving `app:app`
+    Python,
+    /// `bin/rails server` with the Rails 7.1 `/up` health check
+    Rails,
+}
+
+impl Runtime {
+    /// Command and arguments starting the app; `{port}` is replaced by the backend port
+    fn command(self) -> (&'static str, &'static [&'static str]) {
+        match self {
+            Runtime::Node => ("npm", &["start"]),
+            Runtime::Python => ("python", &["-m", "gunicorn", "--bind", "127.0.0.1:{port}", "app:app"]),
+            Runtime::Rails => ("bin/rails", &["server", "--binding", "127.0.0.1", "--port", "{port}"]),
+        }
+    }
+
+    /// Settings the preset provides, applied like a profile
+    fn profile(self) -> BackendProfile {
+        let (health_path, startup_timeout_secs, env): (_, _, &[(&str, &str)]) = match self {
+            Runtime::Node => ("/health", 30, &[("NODE_ENV", "production")]),
+            Runtime::Python => ("/health", 30, &[("PYTHONUNBUFFERED", "1")]),
+            Runtime::Rails => ("/up", 60, &[("RAILS_ENV", "production"), ("RAILS_LOG_TO_STDOUT", "1")]),
+        };
+        BackendProfile {
+            health_path: Some(health_path.to_string()),
+            startup_timeout_secs: Some(startup_timeout_secs),
+            env: env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            ..Default::default()
+        }
+    }
+}
+
+impl BackendConfig {
+    /// Strong validator of the definition, as served in `ETag` headers
+    pub fn etag(&self) -> String {
+        let definition = serde_json::to_value(self).unwrap_or_default();
+        let digest = ring::digest::digest(&ring::digest::SHA256, definition.to_string().as_bytes());
+        let hex: String = digest.as_ref()[..8].iter().map(|b| format!("{:02x}", b)).collect();
+        format!("\"{}\"", hex)
+    }
+
+    /// Create a new local backend config with defaults
+    pub fn local(command: &str, port: u16) -> Self {
+        Self {
+            backend_type: BackendType::Local,
+            command: Some(command.to_string()),
+            args: Vec::new(),
+            working_dir: None,
+            user: None,
+            group: None,
+            image: None,
+            container_name: None,
+            docker_host: None,
+            container_runtime: ContainerEngine::default(),
+            network: None,
+            pull_policy: PullPolicy::default(),
+            registry_auth: None,
+            image_digest: None,
+            platform: None,
+            memory: None,
+            cpus: None,
+            cpu_shares: None,
+            pids_limit: None,
+            on_container_exit: ContainerExitPolicy::default(),
+            sidecars: Vec::new(),
+            spawner: None,
+            firecracker: None,
+            nomad: None,
+            cloud_vm: None,
+            ssh_tunnel: None,
+            env: HashMap::new(),
+            port,
+            health_path: None,
+            idle_timeout_secs: None,
+            startup_timeout_secs: None,
+            spawn_wait_secs: None,
+            max_queue_depth: None,
+            health_check_interval_ms: None,
+            shutdown_grace_period_secs: None,
+            drain_timeout_secs: None,
+            request_timeout_secs: None,
+            ready_health_check_interval_ms: None,
+            unhealthy_threshold: None,
+            timezone: None,
+            locale: None,
+            adaptive_idle: None,
+            keep_warm: None,
+            max_lifetime_secs: None,
+            profile: None,
+            runtime: None,
+            acme: true,
+            tls_cert: None,
+            tls_key: None,
+            internal_redirect: false,
+            internal_root: None,
+            allowed_paths: Vec::new(),
+            https_redirect: true,
+            https_redirect_exclude: Vec::new(),
+            trace_sample_rates: HashMap::new(),
+            cold_start_slo_ms: None,
+            cold_start_slo_target: None,
+            waf: None,
+            waf_threshold: None,
+            waf_mode: None,
+            deadline_headers: false,
+            backend_http2: false,
+            backend_tls: false,
+            response_rewrites: Vec::new(),
+            rewrite_content_types: default_rewrite_content_types(),
+            fallback: None,
+            routes: Vec::new(),
+            priority_rules: Vec::new(),
+            experiment: None,
+            public_url: None,
+            signing_secret: None,
+            readiness: ReadinessGates::default(),
+            labels: HashMap::new(),
+            promote_to: Vec::new(),
+            faults: None,
+            quota: None,
+        }
+    }
+
+    /// Create a new Docker backend config with defaults
+    pub fn docker(image: &str, port: u16) -> Self {
+        Self {
+            backend_type: BackendType::Docker,
+            command: None,
+            args: Vec::new(),
+            working_dir: None,
+            user: None,
+            group: None,
+            image: Some(image.to_string()),
+            container_name: None,
+            docker_host: None,
+            container_runtime: ContainerEngine::default(),
+            network: None,
+            pull_policy: PullPolicy::default(),
+            registry_auth: None,
+            image_digest: None,
+            platform: None,
+            memory: None,
+            cpus: None,
+            cpu_shares: None,
+            pids_limit: None,
+            on_container_exit: ContainerExitPolicy::default(),
+            sidecars: Vec::new(),
+            spawner: None,
+            firecracker: None,
+            nomad: None,
+            cloud_vm: None,
+            ssh_tunnel: None,
+            env: HashMap::new(),
+            port,
+            health_path: None,
+            idle_timeout_secs: None,
+            startup_timeout_secs: None,
+            spawn_wait_secs: None,
+            max_queue_depth: None,
+            health_check_interval_ms: None,
+            shutdown_grace_period_secs: None,
+            drain_timeout_secs: None,
+            request_timeout_secs: None,
+            ready_health_check_interval_ms: None,
+            unhealthy_threshold: None,
+            timezone: None,
+            locale: None,
+            adaptive_idle: None,
+            keep_warm: None,
+            max_lifetime_secs: None,
+            profile: None,
+            runtime: None,
+            acme: true,
+            tls_cert: None,
+            tls_key: None,
+            internal_redirect: false,
+            internal_root: None,
+            allowed_paths: Vec::new(),
+            https_redirect: true,
+            https_redirect_exclude: Vec::new(),
+            trace_sample_rates: HashMap::new(),
+            cold_start_slo_ms: None,
+            cold_start_slo_target: None,
+            waf: None,
+            waf_threshold: None,
+            waf_mode: None,
+            deadline_headers: false,
+            backend_http2: false,
+            backend_tls: false,
+            response_rewrites: Vec::new(),
+            rewrite_content_types: default_rewrite_content_types(),
+            fallback: None,
+            routes: Vec::new(),
+            priority_rules: Vec::new(),
+            experiment: None,
+            public_url: None,
+            signing_secret: None,
+            readiness: ReadinessGates::default(),
+            labels: HashMap::new(),
+            promote_to: Vec::new(),
+            faults: None,
+            quota: None,
+        }
+    }
+
+    /// Create a new config for a backend launched by a registered spawner
+    pub fn custom(spawner: &str, port: u16) -> Self {
+        Self {
+            backend_type: BackendType::Custom,
+            command: None,
+            spawner: Some(spawner.to_string()),
+            ..Self::local("", port)
+        }
+    }
+
+    /// Set arguments for this backend config (builder pattern)
+    pub fn with_args(mut self, args: Vec<String>) -> Self {
+        self.args = args;
+        self
+    }
+
+    /// Set environment variables (builder pattern)
+    pub fn with_env(mut self, env: HashMap<String, String>) -> Self {
+        self.env = env;
+        self
+    }
+
+    /// Set working directory (builder pattern)
+    pub fn with_working_dir(mut self, dir: &str) -> Self {
+        self.working_dir = Some(dir.to_string());
+        self
+    }
+
+    /// Whether this backend serves its own certificate files
+    pub fn has_pinned_cert(&self) -> bool {
+        self.tls_cert.is_some() && self.tls_key.is_some()
+    }
+
+    /// Whether this hostname must be left out of ACME issuance
+    pub fn acme_excluded(&self) -> bool {
+        !self.acme || self.has_pinned_cert()
+    }
+
+    /// Whether `path` falls under one of `allowed_paths`
+    ///
+    /// Prefixes match on segment boundaries: `/api` allows `/api` and
+    /// `/api/users` but not `/apiary`.
+    pub fn allows_path(&self, path: &str) -> bool {
+        if self.allowed_paths.is_empty() {
+            return true;
+        }
+        self.allowed_paths.iter().any(|prefix| path_has_prefix(path, prefix))
+    }
+
+    /// Whether a plain HTTP request for `path` is redirected when `force_https` is on
+    pub fn redirects_to_https(&self, path: &str) -> bool {
+        self.https_redirect && !self.https_redirect_exclude.iter().any(|prefix| path_has_prefix(path, prefix))
+    }
+
+    /// Fill in settings this backend leaves unset from a profile
+    pub fn apply_profile(&mut self, profile: &BackendProfile) {
+        fn inherit<T: Clone>(field: &mut Option<T>, value: &Option<T>) {
+            if field.is_none() {
+                *field = value.clone();
+            }
+        }
+
+        inherit(&mut self.memory, &profile.memory);
+        inherit(&mut self.cpus, &profile.cpus);
+        inherit(&mut self.health_path, &profile.health_path);
+        inherit(&mut self.idle_timeout_secs, &profile.idle_timeout_secs);
+        inherit(&mut self.startup_timeout_secs, &profile.startup_timeout_secs);
+        inherit(&mut self.spawn_wait_secs, &profile.spawn_wait_secs);
+        inherit(&mut self.max_queue_depth, &profile.max_queue_depth);
+        inherit(&mut self.health_check_interval_ms, &profile.health_check_interval_ms);
+        inherit(&mut self.shutdown_grace_period_secs, &profile.shutdown_grace_period_secs);
+        inherit(&mut self.drain_timeout_secs, &profile.drain_timeout_secs);
+        inherit(&mut self.request_timeout_secs, &profile.request_timeout_secs);
+        inherit(&mut self.ready_health_check_interval_ms, &profile.ready_health_check_interval_ms);
+        inherit(&mut self.unhealthy_threshold, &profile.unhealthy_threshold);
+        inherit(&mut self.timezone, &profile.timezone);
+        inherit(&mut self.locale, &profile.locale);
+        inherit(&mut self.adaptive_idle, &profile.adaptive_idle);
+        inherit(&mut self.keep_warm, &profile.keep_warm);
+        inherit(&mut self.max_lifetime_secs, &profile.max_lifetime_secs);
+
+        for (key, value) in &profile.env {
+            self.env.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+    }
+
+    /// Fill in settings still unset from the runtime preset
+    ///
+    /// Local backends without a `command` get the preset's command, and its
+    /// arguments unless `args` is set.
+    pub fn apply_runtime(&mut self) {
+        let Some(runtime) = self.runtime else {
+            return;
+        };
+        if self.backend_type == BackendType::Local && self.command.is_none() {
+            let (command, args) = runtime.command();
+            self.command = Some(command.to_string());
+            if self.args.is_empty() {
+                let port = self.port.to_string();
+                self.args = args.iter().map(|arg| arg.replace("{port}", &port)).collect();
+            }
+        }
+        self.apply_profile(&runtime.profile());
+    }
+
+    /// Fill in settings still unset from `[defaults]`, as the proxy applies them
+    pub fn apply_defaults(&mut self, defaults: &BackendDefaults) {
+        self.idle_timeout_secs.get_or_insert(defaults.idle_timeout_secs);
+        self.startup_timeout_secs.get_or_insert(defaults.startup_timeout_secs);
+        self.spawn_wait_secs = self.spawn_wait_secs.or(defaults.spawn_wait_secs);
+        self.max_queue_depth = self.max_queue_depth.or(defaults.max_queue_depth);
+        self.health_check_interval_ms.get_or_insert(defaults.health_check_interval_ms);
+        self.health_path.get_or_insert_with(|| defaults.health_path.clone());
+        self.shutdown_grace_period_secs.get_or_insert(defaults.shutdown_grace_period_secs);
+        self.drain_timeout_secs.get_or_insert(defaults.drain_timeout_secs);
+        self.request_timeout_secs.get_or_insert(defaults.request_timeout_secs);
+        self.ready_health_check_interval_ms.get_or_insert(defaults.ready_health_check_interval_ms);
+        self.unhealthy_threshold.get_or_insert(defaults.unhealthy_threshold);
+        self.timezone = self.timezone.take().or_else(|| defaults.timezone.clone());
+        self.locale = self.locale.take().or_else(|| defaults.locale.clone());
+        self.adaptive_idle.get_or_insert(defaults.adaptive_idle.enabled);
+        self.keep_warm.get_or_insert(false);
+        self.max_lifetime_secs = self.max_lifetime_secs.or(defaults.max_lifetime_secs);
+        self.waf.get_or_insert(defaults.waf.enabled);
+        self.waf_threshold.get_or_insert(defaults.waf.threshold);
+        self.waf_mode.get_or_insert(defaults.waf.mode);
+        self.resolve_registry_auth(defaults);
+    }
+
+    /// Fill in unset registry credentials of the image and sidecar images
+    /// from `[defaults.registries]`
+    pub fn resolve_registry_auth(&mut self, defaults: &BackendDefaults) {
+        let lookup = |image: &str| defaults.registries.get(crate::docker::image_registry(image)).cloned();
+        if self.registry_auth.is_none() {
+            self.registry_auth = self.image.as_deref().and_then(lookup);
+        }
+        for sidecar in &mut self.sidecars {
+            if sidecar.registry_auth.is_none() {
+                sidecar.registry_auth = lookup(&sidecar.image);
+            }
+        }
+    }
+
+    /// `TZ`, `LANG` and `LC_ALL` for the configured time zone and locale
+    ///
+    /// Empty when neither is configured, so the backend inherits the proxy's.
+    pub fn locale_env(&self, defaults: &BackendDefaults) -> Vec<(String, String)> {
+        let mut env = Vec::new();
+        if let Some(timezone) = self.timezone.as_ref().or(defaults.timezone.as_ref()) {
+            env.push(("TZ".to_string(), timezone.clone()));
+        }
+        if let Some(locale) = self.locale.as_ref().or(defaults.locale.as_ref()) {
+            env.push(("LANG".to_string(), locale.clone()));
+            env.push(("LC_ALL".to_string(), locale.clone()));
+        }
+        env
+    }
+
+    pub fn idle_timeout(&self, defaults: &BackendDefaults) -> Duration {
+        Duration::from_secs(self.idle_timeout_secs.unwrap_or(defaults.idle_timeout_secs))
+    }
+
+    /// Whether the idle timeout is learned from traffic
+    pub fn adaptive_idle(&self, defaults: &BackendDefaults) -> bool {
+        self.adaptive_idle.unwrap_or(defaults.adaptive_idle.enabled)
+    }
+
+    /// How long the backend runs before it is restarted, if limited
+    pub fn max_lifetime(&self, defaults: &BackendDefaults) -> Option<Duration> {
+        self.max_lifetime_secs.or(defaults.max_lifetime_secs).map(Duration::from_secs)
+    }
+
+    /// Whether the backend runs regardless of traffic, exempt from idle shutdown
+    pub fn keep_warm(&self) -> bool {
+        self.keep_warm.unwrap_or(false)
+    }
+
+    /// WAF threshold and mode, or `None` if requests aren't inspected
+    pub fn waf(&self, defaults: &BackendDefaults) -> Option<(u32, WafMode)> {
+        self.waf.unwrap_or(defaults.waf.enabled).then(|| {
+            (
+                self.waf_threshold.unwrap_or(defaults.waf.threshold),
+                self.waf_mode.unwrap_or(defaults.waf.mode),
+            )
+        })
+    }
+
+    pub fn startup_timeout(&self, defaults: &BackendDefaults) -> Duration {
+        Duration::from_secs(self.startup_timeout_secs.unwrap_or(defaults.startup_timeout_secs))
+    }
+
+    /// Longest a request waits for the backend to start, if limited
+    pub fn spawn_wait(&self, defaults: &BackendDefaults) -> Option<Duration> {
+        self.spawn_wait_secs.or(defaults.spawn_wait_secs).map(Duration::from_secs)
+    }
+
+    /// Most requests that may wait for the backend to start, if limited
+    pub fn max_queue_depth(&self, defaults: &BackendDefaults) -> Option<usize> {
+        self.max_queue_depth.or(defaults.max_queue_depth).map(|depth| depth as usize)
+    }
+
+    pub fn health_check_interval(&self, defaults: &BackendDefaults) -> Duration {
+        Duration::from_millis(
+            self.health_check_interval_ms
+                .unwrap_or(defaults.health_check_interval_ms),
+        )
+    }
+
+    pub fn health_path<'a>(&'a self, defaults: &'a BackendDefaults) -> &'a str {
+        self.health_path
+            .as_deref()
+            .unwrap_or(&defaults.health_path)
+    }
+
+    pub fn shutdown_grace_period(&self, defaults: &BackendDefaults) -> Duration {
+        Duration::from_secs(
+            self.shutdown_grace_period_secs
+                .unwrap_or(defaults.shutdown_grace_period_secs),
+        )
+    }
+
+    pub fn drain_timeout(&self, defaults: &BackendDefaults) -> Duration {
+        Duration::from_secs(
+            self.drain_timeout_secs
+                .unwrap_or(defaults.drain_timeout_secs),
+        )
+    }
+
+    pub fn request_timeout(&self, defaults: &BackendDefaults) -> Duration {
+        Duration::from_secs(
+            self.request_timeout_secs
+                .unwrap_or(defaults.request_timeout_secs),
+        )
+    }
+
+    pub fn ready_health_check_interval(&self, defaults: &BackendDefaults) -> Duration {
+        Duration::from_millis(
+            self.ready_health_check_interval_ms
+                .unwrap_or(defaults.ready_health_check_interval_ms),
+        )
+    }
+
+    pub fn unhealthy_threshold(&self, defaults: &BackendDefaults) -> u32 {
+        self.unhealthy_threshold
+            .unwrap_or(defaults.unhealthy_threshold)
+    }
+
+    fn validate_sidecars(&self, hostname: &str) -> Result<(), String> {
+        if self.sidecars.is_empty() {
+            return Ok(());
+        }
+        if self.network.is_some() {
+            return Err(format!(
+                "Backend '{}': 'network' can't be combined with sidecars, which get a network of their own",
+                hostname
+            ));
+        }
+        if self.container_runtime != ContainerEngine::Docker {
+            return Err(format!("Backend '{}': sidecars are only supported with the docker runtime", hostname));
+        }
+        let mut names = HashSet::new();
+        for sidecar in &self.sidecars {
+            let valid_name = !sidecar.name.is_empty()
+                && !sidecar.name.starts_with('-')
+                && sidecar.name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-');
+            if !valid_name {
+                return Err(format!(
+                    "Backend '{}': invalid sidecar name '{}' (expected lowercase letters, digits and '-')",
+                    hostname, sidecar.name
+                ));
+            }
+            if !names.insert(sidecar.name.as_str()) {
+                return Err(format!("Backend '{}': duplicate sidecar '{}'", hostname, sidecar.name));
+            }
+            if sidecar.image.is_empty() {
+                return Err(format!("Backend '{}': sidecar '{}' requires 'image'", hostname, sidecar.name));
+            }
+            if let Some(Err(e)) = sidecar.memory.as_deref().map(crate::docker::parse_memory_limit) {
+                return Err(format!("Backend '{}': sidecar '{}': {}", hostname, sidecar.name, e));
+            }
+            if sidecar.cpus.as_deref().is_some_and(|cpus| cpus.parse::<f64>().is_err()) {
+                return Err(format!("Backend '{}': sidecar '{}': invalid CPU limit", hostname, sidecar.name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate the backend configuration
+    pub fn validate(&self, hostname: &str) -> Result<(), String> {
+        if self.backend_type != BackendType::Local && (self.user.is_some() || self.group.is_some()) {
+            return Err(format!("Backend '{}': 'user' and 'group' only apply to local backends", hostname));
+        }
+        if self.max_lifetime_secs == Some(0) {
+            return Err(format!("Backend '{}': 'max_lifetime_secs' must be greater than 0", hostname));
+        }
+        if self.port == 0 && !self.backend_type.assigns_ports() {
+            return Err(format!(
+                "Backend '{}': 'port' must be greater than 0 (only local and docker backends get assigned ports)",
+                hostname
+            ));
+        }
+        match self.backend_type {
+            BackendType::Local => {
+                if self.command.is_none() {
+                    return Err(format!(
+                        "Backend '{}': local backend requires 'command' field",
+                        hostname
+                    ));
+                }
+                if let Some(Err(e)) = self.memory.as_deref().map(crate::docker::parse_memory_limit) {
+                    return Err(format!("Backend '{}': {}", hostname, e));
+                }
+                if self.cpus.as_deref().is_some_and(|cpus| !cpus.parse::<f64>().is_ok_and(|cpus| cpus > 0.0)) {
+                    return Err(format!("Backend '{}': invalid CPU limit", hostname));
+                }
+                if self.user.as_deref().is_some_and(str::is_empty) || self.group.as_deref().is_some_and(str::is_empty) {
+                    return Err(format!("Backend '{}': 'user' and 'group' must not be empty", hostname));
+                }
+            }
+            BackendType::Docker => {
+                let Some(ref image) = self.image else {
+                    return Err(format!(
+                        "Backend '{}': Docker backend requires 'image' field",
+                        hostname
+                    ));
+                };
+                if let Err(e) = crate::docker::pinned_image(image, self.image_digest.as_deref()) {
+                    return Err(format!("Backend '{}': {}", hostname, e));
+                }
+                if let Some(Err(e)) = self.platform.as_deref().map(crate::docker::Platform::parse) {
+                    return Err(format!("Backend '{}': {}", hostname, e));
+                }
+                if self.cpu_shares.is_some_and(|shares| shares < 2) {
+                    return Err(format!("Backend '{}': 'cpu_shares' must be at least 2", hostname));
+                }
+                if self.pids_limit.is_some_and(|limit| limit <= 0) {
+                    return Err(format!("Backend '{}': 'pids_limit' must be greater than 0", hostname));
+                }
+                self.validate_sidecars(hostname)?;
+                let auths = std::iter::once((&self.registry_auth, self.image.as_deref().unwrap_or_default()))
+                    .chain(self.sidecars.iter().map(|s| (&s.registry_auth, s.image.as_str())));
+                for (auth, image) in auths {
+                    let Some(auth) = auth else { continue };
+                    if let Err(e) = auth.validate() {
+                        return Err(format!("Backend '{}': registry_auth for '{}' {}", hostname, image, e));
+                    }
+                    if auth.token.is_some() && self.container_runtime == ContainerEngine::Podman {
+                        return Err(format!(
+                            "Backend '{}': registry_auth 'token' is not supported with the podman runtime",
+                            hostname
+                        ));
+                    }
+                }
+            }
+            BackendType::Custom => {
+                if self.spawner.is_none() {
+                    return Err(format!(
+                        "Backend '{}': custom backend requires 'spawner' field",
+                        hostname
+                    ));
+                }
+            }
+            BackendType::Firecracker => {
+                let Some(ref vm) = self.firecracker else {
+                    return Err(format!(
+                        "Backend '{}': firecracker backend requires a 'firecracker' table",
+                        hostname
+                    ));
+                };
+                if vm.host_ip == vm.guest_ip {
+                    return Err(format!(
+                        "Backend '{}': firecracker 'host_ip' and 'guest_ip' must differ",
+                        hostname
+                    ));
+                }
+                if vm.tap_device.as_ref().is_some_and(|tap| tap.is_empty() || tap.len() > 15) {
+                    return Err(format!(
+                        "Backend '{}': firecracker 'tap_device' must be 1 to 15 characters",
+                        hostname
+                    ));
+                }
+            }
+            BackendType::Nomad => {
+                let Some(ref nomad) = self.nomad else {
+                    return Err(format!("Backend '{}': nomad backend requires a 'nomad' table", hostname));
+                };
+                if nomad.job.is_empty() {
+                    return Err(format!("Backend '{}': nomad 'job' must not be empty", hostname));
+                }
+                if !nomad.address.starts_with("http://") {
+                    return Err(format!(
+                        "Backend '{}': nomad 'address' must be an http:// URL, got '{}'",
+                        hostname, nomad.address
+                    ));
+                }
+            }
+            BackendType::CloudVm => {
+                let Some(ref vm) = self.cloud_vm else {
+                    return Err(format!("Backend '{}': cloud-vm backend requires a 'cloud_vm' table", hostname));
+                };
+                if vm.instance_id.is_empty() {
+                    return Err(format!("Backend '{}': cloud_vm 'instance_id' must not be empty", hostname));
+                }
+                if vm.provider == CloudProvider::Ec2 && vm.region.is_none() {
+                    return Err(format!("Backend '{}': cloud_vm 'region' is required for ec2", hostname));
+                }
+            }
+            BackendType::SshTunnel => {
+                let Some(ref tunnel) = self.ssh_tunnel else {
+                    return Err(format!("Backend '{}': ssh-tunnel backend requires an 'ssh_tunnel' table", hostname));
+                };
+                if tunnel.host.is_empty() || tunnel.host.starts_with('-') {
+                    return Err(format!("Backend '{}': ssh_tunnel 'host' must be a host name", hostname));
+                }
+                if tunnel.port == 0 || tunnel.remote_port == Some(0) {
+                    return Err(format!("Backend '{}': ssh_tunnel ports must be greater than 0", hostname));
+                }
+            }
+        }
+
+        if self.routes.iter().any(RouteRule::is_empty) {
+            return Err(format!("Backend '{}': routes entries need at least one condition", hostname));
+        }
+        for route in &self.routes {
+            match &route.path_prefix {
+                Some(prefix) if !prefix.starts_with('/') => {
+                    return Err(format!(
+                        "Backend '{}': route path_prefix '{}' must start with '/'",
+                        hostname, prefix
+                    ));
+                }
+                None if route.strip_prefix => {
+                    return Err(format!("Backend '{}': route strip_prefix needs a path_prefix", hostname));
+                }
+                _ => {}
+            }
+            let invalid_header = |name: &&String| hyper::header::HeaderName::try_from(name.as_str()).is_err();
+            if let Some(name) = route.headers.keys().find(invalid_header) {
+                return Err(format!("Backend '{}': route header '{}' is not a valid header name", hostname, name));
+            }
+            let invalid_method = |method: &&String| {
+                hyper::Method::from_bytes(method.as_bytes()).is_err() || method.to_ascii_uppercase() != **method
+            };
+            if let Some(method) = route.methods.iter().find(invalid_method) {
+                return Err(format!("Backend '{}': route method '{}' must be an uppercase HTTP method", hostname, method));
+            }
+            // HEAD must describe the response GET would get
+            if route.methods.iter().any(|m| m == "GET") != route.methods.iter().any(|m| m == "HEAD") {
+                return Err(format!("Backend '{}': route methods must list GET and HEAD together", hostname));
+            }
+            // Skipping a rule that takes writes would send them elsewhere, not the reads
+            if route.read_your_writes_secs > 0 && !route.is_read_only() {
+                return Err(format!(
+                    "Backend '{}': route read_your_writes_secs needs methods limited to GET, HEAD, OPTIONS and TRACE",
+                    hostname
+                ));
+            }
+            // Reads skipping the rule are served here, so writes must be too
+            if route.read_your_writes_secs > 0 {
+                let writes = |rule: &&RouteRule| rule.methods.iter().any(|m| !is_safe_method(m));
+                if let Some(writes) = self.routes.iter().find(writes) {
+                    return Err(format!(
+                        "Backend '{}': route read_your_writes_secs needs writes served by '{}', not routed to '{}'",
+                        hostname, hostname, writes.backend
+                    ));
+                }
+            }
+        }
+
+        for rule in &self.priority_rules {
+            if rule.path_prefix.is_none() && rule.headers.is_empty() && rule.methods.is_empty() {
+                return Err(format!("Backend '{}': priority_rules entries need at least one condition", hostname));
+            }
+            if let Some(prefix) = rule.path_prefix.as_ref().filter(|prefix| !prefix.starts_with('/')) {
+                return Err(format!(
+                    "Backend '{}': priority rule path_prefix '{}' must start with '/'",
+                    hostname, prefix
+                ));
+            }
+            let invalid_header = |name: &&String| hyper::header::HeaderName::try_from(name.as_str()).is_err();
+            if let Some(name) = rule.headers.keys().find(invalid_header) {
+                return Err(format!(
+                    "Backend '{}': priority rule header '{}' is not a valid header name",
+                    hostname, name
+                ));
+            }
+            let invalid_method = |method: &&String| {
+                hyper::Method::from_bytes(method.as_bytes()).is_err() || method.to_ascii_uppercase() != **method
+            };
+            if let Some(method) = rule.methods.iter().find(invalid_method) {
+                return Err(format!(
+                    "Backend '{}': priority rule method '{}' must be an uppercase HTTP method",
+                    hostname, method
+                ));
+            }
+        }
+
+        if let Some(experiment) = &self.experiment {
+            if !is_cookie_token(&experiment.name) {
+                return Err(format!(
+                    "Backend '{}': experiment name '{}' may only contain letters, digits, '-', '_' and '.'",
+                    hostname, experiment.name
+                ));
+            }
+            if experiment.variants.len() < 2 {
+                return Err(format!("Backend '{}': experiment needs at least two variants", hostname));
+            }
+            if let Some(variant) = experiment.variants.iter().find(|v| !is_cookie_token(&v.name)) {
+                return Err(format!(
+                    "Backend '{}': experiment variant name '{}' may only contain letters, digits, '-', '_' and '.'",
+                    hostname, variant.name
+                ));
+            }
+            let mut names = HashSet::new();
+            if let Some(variant) = experiment.variants.iter().find(|v| !names.insert(v.name.as_str())) {
+                return Err(format!("Backend '{}': duplicate experiment variant '{}'", hostname, variant.name));
+            }
+            if experiment.variants.iter().all(|v| v.weight == 0) {
+                return Err(format!("Backend '{}': experiment variants need a non-zero weight", hostname));
+            }
+            if experiment.key_cookie.as_ref().is_some_and(|cookie| cookie.is_empty()) {
+                return Err(format!("Backend '{}': experiment key_cookie must not be empty", hostname));
+            }
+        }
+
+        if self.signing_secret.as_ref().is_some_and(|secret| secret.is_empty()) {
+            return Err(format!("Backend '{}': 'signing_secret' must not be empty", hostname));
+        }
+
+        if self.backend_http2 && self.backend_tls {
+            return Err(format!(
+                "Backend '{}': 'backend_http2' is for plaintext backends, TLS backends negotiate HTTP/2 via ALPN",
+                hostname
+            ));
+        }
+
+        if self.response_rewrites.iter().any(|rewrite| rewrite.find.is_empty()) {
+            return Err(format!(
+                "Backend '{}': response_rewrites entries need a non-empty 'find'",
+                hostname
+            ));
+        }
+
+        if let Some(prefix) = self.allowed_paths.iter().find(|p| !p.starts_with('/')) {
+            return Err(format!(
+                "Backend '{}': allowed_paths entry '{}' must start with '/'",
+                hostname, prefix
+            ));
+        }
+
+        if let Some(prefix) = self.https_redirect_exclude.iter().find(|p| !p.starts_with('/')) {
+            return Err(format!(
+                "Backend '{}': https_redirect_exclude entry '{}' must start with '/'",
+                hostname, prefix
+            ));
+        }
+
+        if let Err(e) = validate_sample_rates(&self.trace_sample_rates) {
+            return Err(format!("Backend '{}': trace_sample_rates: {}", hostname, e));
+        }
+
+        if self.cold_start_slo_target.is_some_and(|t| !(t > 0.0 && t < 1.0)) {
+            return Err(format!(
+                "Backend '{}': 'cold_start_slo_target' must be between 0.0 and 1.0 (exclusive)",
+                hostname
+            ));
+        }
+
+        if self.waf_threshold == Some(0) {
+            return Err(format!(
+                "Backend '{}': 'waf_threshold' must be greater than 0",
+                hostname
+            ));
+        }
+
+        if self.tls_cert.is_some() != self.tls_key.is_some() {
+            return Err(format!(
+                "Backend '{}': 'tls_cert' and 'tls_key' must be set together",
+                hostname
+            ));
+        }
+
+        if let Some(Err(e)) = self.faults.as_ref().map(FaultConfig::validate) {
+            return Err(format!("Backend '{}': faults: {}", hostname, e));
+        }
+
+        if let Some(Err(e)) = self.quota.as_ref().map(QuotaConfig::validate) {
+            return Err(format!("Backend '{}': quota: {}", hostname, e));
+        }
+
+        Ok(())
+    }
+}
+
+// Default value functions
+fn default_backend_acme() -> bool {
+    true
+}
+
+fn default_backend_https_redirect() -> bool {
+    true
+}
+
+fn default_rewrite_content_types() -> Vec<String> {
+    vec!["text/html".to_string(), "application/json".to_string()]
+}
+
+/// A find/replace pair for `response_rewrites`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ResponseRewrite {
+    /// Text to find, e.g. "http://localhost:3000"
+    pub find: String,
+
+    /// Replacement, e.g. "https://app.example.com"
+    pub replace: String,
+}
+
+/// Rule routing matching requests for a hostname to another backend
+///
+/// Every condition set must match. Media types may end in `/*` to match a
+/// whole type, and `application/grpc` also matches `application/grpc+proto`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct RouteRule {
+    /// Backend serving matching requests
+    pub backend: String,
+
+    /// Path prefix the request must be under, on a segment boundary
+    /// (`/v2` matches `/v2` and `/v2/users` but not `/v2beta`)
+    pub path_prefix: Option<String>,
+
+    /// Remove `path_prefix` from the path before forwarding (default: false)
+    #[serde(default)]
+    pub strip_prefix: bool,
+
+    /// Rules with a higher priority are tried first; equal ones in order (default: 0)
+    #[serde(default)]
+    pub priority: i32,
+
+    /// Media types the request's `Content-Type` must be one of
+    #[serde(default)]
+    pub content_type: Vec<String>,
+
+    /// Media types the request's `Accept` header must name explicitly
+    /// (`*/*` in the header doesn't match)
+    #[serde(default)]
+    pub accept: Vec<String>,
+
+    /// Headers the request must have, by name, with the given values
+    /// (`"*"`: any value)
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+
+    /// Cookies the request must have, by name, with the given values
+    /// (`"*"`: any value)
+    #[serde(default)]
+    pub cookies: HashMap<String, String>,
+
+    /// Methods the request must use, e.g. `["GET", "HEAD"]` to send reads to a replica
+    #[serde(default)]
+    pub methods: Vec<String>,
+
+    /// Skip this rule for this many seconds after the client sends a write
+    /// to the hostname, so it reads its own writes from the backend that took
+    /// them; only for rules matching safe methods (default: 0, off)
+    #[serde(default)]
+    pub read_your_writes_secs: u64,
+}
+
+impl RouteRule {
+    /// Whether the rule has no conditions, and so would match everything
+    pub fn is_empty(&self) -> bool {
+        self.path_prefix.is_none()
+            && self.content_type.is_empty()
+            && self.accept.is_empty()
+            && self.headers.is_empty()
+            && self.cookies.is_empty()
+            && self.methods.is_empty()
+    }
+
+    /// Whether the rule only matches safe methods, which don't change state
+    pub fn is_read_only(&self) -> bool {
+        !self.methods.is_empty() && self.methods.iter().all(|method| is_safe_method(method))
+    }
+
+    /// Whether a request for `path` satisfies `path_prefix`
+    pub fn matches_path(&self, path: &str) -> bool {
+        self.path_prefix.as_ref().is_none_or(|prefix| path_has_prefix(path, prefix))
+    }
+}
+
+/// Priority class of a request waiting for its backend to start
+///
+/// Ordered from lowest to highest.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum PriorityClass {
+    /// Background work, shed first when the queue is full
+    Low,
+    #[default]
+    Normal,
+    /// Takes the place of lower-class requests when the queue is full
+    High,
+}
+
+impl PriorityClass {
+    pub const ALL: [PriorityClass; 3] = [PriorityClass::High, PriorityClass::Normal, PriorityClass::Low];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PriorityClass::Low => "low",
+            PriorityClass::Normal => "normal",
+            PriorityClass::High => "high",
+        }
+    }
+}
+
+/// Rule putting matching requests into a priority class
+///
+/// Every condition set must match, as for [`RouteRule`].
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct PriorityRule {
+    pub class: PriorityClass,
+
+    /// Path prefix the request must be under, on a segment boundary
+    pub path_prefix: Option<String>,
+
+    /// Headers the request must have, by name, with the given values
+    /// (`"*"`: any value), e.g. an API key
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+
+    /// Methods the request must use
+    #[serde(default)]
+    pub methods: Vec<String>,
+}
+
+impl PriorityRule {
+    /// Whether a request for `path` satisfies `path_prefix`
+    pub fn matches_path(&self, path: &str) -> bool {
+        self.path_prefix.as_ref().is_none_or(|prefix| path_has_prefix(path, prefix))
+    }
+}
+
+/// A/B experiment of a hostname
+///
+/// Clients are assigned a variant by hashing `key_cookie` (or their IP
+/// address) and keep it through an assignment cookie, so changing weights
+/// only moves clients that weren't assigned yet.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ExperimentConfig {
+    /// Name, used in the assignment cookie and metrics, e.g. "checkout-redesign"
+    pub name: String,
+
+    /// Cookie identifying the client, e.g. a session cookie (default: the client's IP address)
+    pub key_cookie: Option<String>,
+
+    /// How long clients keep their variant (default: 30 days)
+    #[serde(default = "default_experiment_cookie_max_age")]
+    pub cookie_max_age_secs: u64,
+
+    /// Variants clients are split between, in proportion to their weights
+    pub variants: Vec<ExperimentVariant>,
+}
+
+/// One arm of an experiment
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ExperimentVariant {
+    /// Name, e.g. "control"
+    pub name: String,
+
+    /// Backend serving the variant (default: the hostname's own backend)
+    pub backend: Option<String>,
+
+    /// Share of clients, relative to the other variants (default: 1)
+    #[serde(default = "default_experiment_weight")]
+    pub weight: u32,
+}
+
+fn default_experiment_cookie_max_age() -> u64 {
+    30 * 24 * 60 * 60
+}
+
+fn default_experiment_weight() -> u32 {
+    1
+}
+
+/// Whether `name` can be used in a cookie name or value without quoting
+fn is_cookie_token(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+/// Whether requests with `method` are safe (RFC 9110): GET, HEAD, OPTIONS and TRACE
+pub fn is_safe_method(method: &str) -> bool {
+    matches!(method, "GET" | "HEAD" | "OPTIONS" | "TRACE")
+}
+
+/// Whether `path` starts with `prefix` on a segment boundary
+///
+/// `/api` matches `/api` and `/api/users` but not `/apiary`.
+fn path_has_prefix(path: &str, prefix: &str) -> bool {
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || prefix.ends_with('/') || rest.starts_with('/'))
+}
+
+fn default_listen_port() -> u16 {
+    80
+}
+
+fn default_bind_address() -> String {
+    "0.0.0.0".to_string()
+}
+
+fn default_admin_port() -> u16 {
+    9999
+}
+
+fn default_listener_drain_timeout() -> u64 {
+    30
+}
+
+fn default_drain_close_keep_alive() -> bool {
+    true
+}
+
+fn default_pool_max_idle_per_host() -> usize {
+    10 // Keep up to 10 idle connections per backend
+}
+
+fn default_pool_idle_timeout() -> u64 {
+    90 // Close idle connections after 90 seconds
+}
+
+fn default_idle_timeout() -> u64 {
+    600 // 10 minutes
+}
+
+fn default_startup_timeout() -> u64 {
+    30 // 30 seconds
+}
+
+fn default_health_interval() -> u64 {
+    100 // 100ms
+}
+
+fn default_health_path() -> String {
+    "/health".to_string()
+}
+
+fn default_shutdown_grace_period() -> u64 {
+    10 // 10 seconds between SIGTERM and SIGKILL
+}
+
+fn default_drain_timeout() -> u64 {
+    30 // 30 seconds to wait for in-flight requests to complete
+}
+
+fn default_request_timeout() -> u64 {
+    30 // 30 seconds max for backend to respond
+}
+
+fn default_ready_health_interval() -> u64 {
+    5000 // 5 seconds between health checks for ready backends
+}
+
+fn default_unhealthy_threshold() -> u32 {
+    3 // 3 consecutive failures before marking unhealthy
+}
+
+
+/// Value shown in place of secrets in the effective configuration
+pub const REDACTED: &str = "<redacted>";
+
+/// Whether a setting or environment variable called `name` holds a secret
+fn is_secret(name: &str) -> bool {
+    let lower = name.to_ascii_lowercase();
+    ["token", "secret", "password", "passwd", "credential", "api_key", "apikey", "private_key"]
+        .iter()
+        .any(|word| lower.contains(word))
+        || name == "KEY"
+        || name.ends_with("_KEY")
+}
+
+/// Replace the values of settings and variables named like secrets
+///
+/// Only values are replaced, so a backend whose hostname looks like a secret
+/// is still listed in full.
+pub(crate) fn redact_secrets(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (name, value) in map.iter_mut() {
+                match value {
+                    serde_json::Value::Null | serde_json::Value::Object(_) => redact_secrets(value),
+                    _ if is_secret(name) => *value = serde_json::Value::String(REDACTED.to_string()),
+                    _ => redact_secrets(value),
+                }
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(redact_secrets),
+        _ => {}
+    }
+}
+
+/// Put back the values of secrets that `value` still shows as [`REDACTED`]
+///
+/// Lets a definition read with its secrets redacted be changed and sent back
+/// without resending the secrets.
+pub(crate) fn restore_secrets(value: &mut serde_json::Value, current: &serde_json::Value) {
+    if let (serde_json::Value::Object(map), serde_json::Value::Object(current)) = (value, current) {
+        for (name, value) in map.iter_mut() {
+            let Some(current) = current.get(name) else { continue };
+            if value.as_str() == Some(REDACTED) && is_secret(name) {
+                *value = current.clone();
+            } else {
+                restore_secrets(value, current);
+            }
+        }
+    }
+}
+
+impl Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        Self::parse(&content)
+    }
+
+    /// Parse and validate configuration from TOML, migrating older layouts
+    pub fn parse(content: &str) -> anyhow::Result<Self> {
+        let mut doc: DocumentMut = content.parse()?;
+        let from_version = migrate_document(&mut doc)?;
+        if from_version < CONFIG_VERSION {
+            warn!(
+                from_version,
+                to_version = CONFIG_VERSION,
+                "Configuration uses an older layout; run `spawngate migrate-config` to update the file"
+            );
+        }
+
+        let mut config: Config = toml::from_str(&doc.to_string())?;
+        config.resolve_profiles()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// ACME settings with hosts that opt out or pin their own certificate removed
+    pub fn acme_config(&self) -> AcmeConfig {
+        let mut acme = self.server.acme.clone();
+        acme.domains.retain(|domain| {
+            self.backends
+                .get(domain)
+                .is_none_or(|backend| !backend.acme_excluded())
+        });
+        acme
+    }
+
+    /// Whether ACME is enabled and at least one domain remains after exclusions
+    pub fn acme_enabled(&self) -> bool {
+        self.server.acme.enabled && !self.acme_config().domains.is_empty()
+    }
+
+    /// Backends that pin their own certificate files, as (hostname, cert, key)
+    pub fn pinned_certs(&self) -> Vec<(&str, &str, &str)> {
+        self.backends
+            .iter()
+            .filter_map(|(hostname, backend)| {
+                Some((
+                    hostname.as_str(),
+                    backend.tls_cert.as_deref()?,
+                    backend.tls_key.as_deref()?,
+                ))
+            })
+            .collect()
+    }
+
+    /// The configuration as the proxy applies it, with secrets redacted
+    ///
+    /// Each backend's unset settings are filled in from `[defaults]` and
+    /// `[server.cold_start_slo]`, and its config vars are layered over its
+    /// `env`. Settings and variables named like secrets (tokens, passwords,
+    /// `*_KEY` variables...) read [`REDACTED`].
+    pub fn effective(&self, vars: &ConfigVars) -> serde_json::Value {
+        let mut config = self.clone();
+        let slo = &config.server.cold_start_slo;
+        for (hostname, backend) in &mut config.backends {
+            backend.apply_defaults(&config.defaults);
+            backend.cold_start_slo_ms.get_or_insert(slo.threshold_ms);
+            backend.cold_start_slo_target.get_or_insert(slo.target);
+            backend.env.extend(vars.get(hostname));
+        }
+        let mut value = serde_json::to_value(&config).unwrap_or_default();
+        redact_secrets(&mut value);
+        value
+    }
+
+    /// Apply each backend's profile, then its runtime preset, to its unset settings
+    pub fn resolve_profiles(&mut self) -> anyhow::Result<()> {
+        let mut errors = Vec::new();
+
+        for (hostname, backend) in &mut self.backends {
+            if let Some(name) = backend.profile.as_deref() {
+                match self.profiles.get(name) {
+                    Some(profile) => backend.apply_profile(profile),
+                    None => errors.push(format!("Backend '{}': unknown profile '{}'", hostname, name)),
+                }
+            }
+            backend.apply_runtime();
+        }
+
+        if !errors.is_empty() {
+            anyhow::bail!("Configuration errors:\n  - {}", errors.join("\n  - "));
+        }
+
+        Ok(())
+    }
+
+    /// Validate all configuration
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut errors = Vec::new();
+
+        let throttle = &self.server.cold_start_throttle;
+        if throttle.enabled && (throttle.burst == 0 || throttle.window_secs == 0) {
+            errors.push("cold_start_throttle: 'burst' and 'window_secs' must be greater than 0".to_string());
+        }
+
+        if ![301, 302, 307, 308].contains(&self.server.https_redirect_status) {
+            errors.push(format!(
+                "https_redirect_status must be 301, 302, 307 or 308, got {}",
+                self.server.https_redirect_status
+            ));
+        }
+
+        if self.server.metrics_export.enabled && self.server.metrics_export.interval_secs == 0 {
+            errors.push("metrics_export: 'interval_secs' must be greater than 0".to_string());
+        }
+
+        if self.server.file_sd.dir.is_some() && self.server.file_sd.interval_secs == 0 {
+            errors.push("file_sd: 'interval_secs' must be greater than 0".to_string());
+        }
+
+        let kv = &self.server.kv;
+        if kv.enabled {
+            if !kv.address().starts_with("http://") {
+                errors.push(format!("kv: 'address' must be an http:// URL, got '{}'", kv.address()));
+            }
+            if kv.interval_secs == 0 {
+                errors.push("kv: 'interval_secs' must be greater than 0".to_string());
+            }
+        }
+
+        for hook in &self.server.shutdown.hooks {
+            if hook.command.is_empty() {
+                errors.push("shutdown: hook 'command' must not be empty".to_string());
+            }
+            if hook.timeout_secs == 0 {
+                errors.push(format!("shutdown: 'timeout_secs' of hook '{}' must be greater than 0", hook.command));
+            }
+        }
+
+        let lockout = &self.server.admin_lockout;
+        if lockout.enabled {
+            if lockout.max_failures == 0 || lockout.window_secs == 0 || lockout.lockout_secs == 0 {
+                errors.push(
+                    "admin_lockout: 'max_failures', 'window_secs' and 'lockout_secs' must be greater than 0"
+                        .to_string(),
+                );
+            }
+            if lockout.max_lockout_secs < lockout.lockout_secs {
+                errors.push("admin_lockout: 'max_lockout_secs' must be at least 'lockout_secs'".to_string());
+            }
+        }
+
+        let adaptive = &self.defaults.adaptive_idle;
+        if !(adaptive.quantile > 0.0 && adaptive.quantile <= 1.0) {
+            errors.push("adaptive_idle: 'quantile' must be greater than 0.0 and at most 1.0".to_string());
+        }
+        if adaptive.min_samples == 0 {
+            errors.push("adaptive_idle: 'min_samples' must be greater than 0".to_string());
+        }
+        if adaptive.max_timeout_secs < adaptive.min_timeout_secs {
+            errors.push("adaptive_idle: 'max_timeout_secs' must be at least 'min_timeout_secs'".to_string());
+        }
+
+        if self.defaults.waf.threshold == 0 {
+            errors.push("waf: 'threshold' must be greater than 0".to_string());
+        }
+
+        let cost = &self.defaults.cost;
+        if cost.cpus <= 0.0 {
+            errors.push("cost: 'cpus' must be greater than 0".to_string());
+        }
+        if crate::docker::parse_memory_limit(&cost.memory).is_err() {
+            errors.push(format!("cost: invalid 'memory' '{}' (e.g. \"512m\" or \"1g\")", cost.memory));
+        }
+        if [cost.cpu_hour_price, cost.gb_hour_price].iter().flatten().any(|price| *price < 0.0) {
+            errors.push("cost: prices must not be negative".to_string());
+        }
+
+        let sampling = &self.server.trace_sampling;
+        if let Err(e) = validate_sample_rates(&sampling.rates) {
+            errors.push(format!("trace_sampling: rates: {}", e));
+        }
+        if !(0.0..=1.0).contains(&sampling.default_rate) {
+            errors.push("trace_sampling: 'default_rate' must be between 0.0 and 1.0".to_string());
+        }
+        if hyper::header::HeaderName::from_bytes(sampling.force_header.as_bytes()).is_err() {
+            errors.push(format!(
+                "trace_sampling: 'force_header' is not a valid header name: '{}'",
+                sampling.force_header
+            ));
+        }
+
+        let slow = &self.server.slow_requests;
+        if slow.enabled && (slow.threshold_ms == 0 || slow.window_secs == 0 || slow.top == 0) {
+            errors.push(
+                "slow_requests: 'threshold_ms', 'window_secs' and 'top' must be greater than 0".to_string(),
+            );
+        }
+
+        if self.server.profiling.enabled && self.server.profiling.max_seconds == 0 {
+            errors.push("profiling: 'max_seconds' must be greater than 0".to_string());
+        }
+
+        let consul = &self.server.consul;
+        if consul.enabled && !consul.address.starts_with("http://") {
+            errors.push(format!("consul: 'address' must be an http:// URL, got '{}'", consul.address));
+        }
+
+        let slo = &self.server.cold_start_slo;
+        if slo.enabled {
+            if !(slo.target > 0.0 && slo.target < 1.0) {
+                errors.push("cold_start_slo: 'target' must be between 0.0 and 1.0 (exclusive)".to_string());
+            }
+            if slo.window_secs == 0 {
+                errors.push("cold_start_slo: 'window_secs' must be greater than 0".to_string());
+            }
+            if slo.alert_burn_rate <= 0.0 {
+                errors.push("cold_start_slo: 'alert_burn_rate' must be greater than 0".to_string());
+            }
+        }
+
+        let acme = &self.server.acme;
+        if acme.challenge_type == AcmeChallengeType::Dns01 && acme.dns.is_none() {
+            errors.push("acme: 'challenge_type = \"dns-01\"' requires a [server.acme.dns] table".to_string());
+        }
+        if acme.dns.is_some() && acme.challenge_type != AcmeChallengeType::Dns01 {
+            errors.push("acme: [server.acme.dns] requires 'challenge_type = \"dns-01\"'".to_string());
+        }
+        if acme.challenge_type != AcmeChallengeType::Dns01 {
+            for domain in acme.domains.iter().filter(|domain| domain.starts_with("*.")) {
+                errors.push(format!("acme: wildcard domain '{}' requires 'challenge_type = \"dns-01\"'", domain));
+            }
+        }
+
+        if self.server.cert_watch.enabled && self.server.cert_watch.interval_secs == 0 {
+            errors.push("cert_watch: 'interval_secs' must be greater than 0".to_string());
+        }
+
+        let session = &self.server.tls_session;
+        if session.tickets && session.ticket_lifetime_secs == 0 {
+            errors.push("tls_session: 'ticket_lifetime_secs' must be greater than 0".to_string());
+        }
+        if session.ticket_key_file.is_some() && !session.tickets {
+            errors.push("tls_session: 'ticket_key_file' requires 'tickets = true'".to_string());
+        }
+
+        let early_data = &self.server.early_data;
+        if early_data.enabled {
+            // Early data is only accepted on resumptions from the server-side cache, whose
+            // entries are single-use; stateless tickets could be replayed
+            if session.tickets || session.cache_size == 0 {
+                errors.push(
+                    "early_data: requires the tls_session cache ('cache_size' > 0) and 'tickets = false'"
+                        .to_string(),
+                );
+            }
+            if early_data.max_size == 0 {
+                errors.push("early_data: 'max_size' must be greater than 0".to_string());
+            }
+            for method in &early_data.methods {
+                if !EARLY_DATA_SAFE_METHODS.iter().any(|m| m.eq_ignore_ascii_case(method)) {
+                    errors.push(format!(
+                        "early_data: method '{}' is not safe to replay (allowed: {})",
+                        method,
+                        EARLY_DATA_SAFE_METHODS.join(", ")
+                    ));
+                }
+            }
+        }
+
+        let fingerprint = &self.server.tls_fingerprint;
+        if !fingerprint.blocklist.is_empty() && !fingerprint.enabled {
+            errors.push("tls_fingerprint: 'blocklist' requires 'enabled = true'".to_string());
+        }
+        for entry in &fingerprint.blocklist {
+            if !crate::fingerprint::is_fingerprint(entry) {
+                errors.push(format!("tls_fingerprint: '{}' is neither a JA3 hash nor a JA4 fingerprint", entry));
+            }
+        }
+
+        for (registry, auth) in &self.defaults.registries {
+            if let Err(e) = auth.validate() {
+                errors.push(format!("defaults: registries.\"{}\" {}", registry, e));
+            }
+        }
+
+        if self.defaults.max_lifetime_secs == Some(0) {
+            errors.push("defaults: 'max_lifetime_secs' must be greater than 0".to_string());
+        }
+
+        let ports = &self.defaults.ports;
+        if ports.range_start == 0 || ports.range_start > ports.range_end {
+            errors.push(format!(
+                "defaults: ports range {}-{} is invalid (expected 0 < range_start <= range_end)",
+                ports.range_start, ports.range_end
+            ));
+        }
+
+        for (hostname, backend) in &self.backends {
+            if let Err(e) = backend.validate(hostname) {
+                errors.push(e);
+            }
+
+            for target in &backend.promote_to {
+                match self.backends.get(target) {
+                    None => errors.push(format!("Backend '{}': unknown promotion target '{}'", hostname, target)),
+                    Some(t) if t.backend_type != backend.backend_type => errors.push(format!(
+                        "Backend '{}': promotion target '{}' has a different backend type",
+                        hostname, target
+                    )),
+                    Some(_) => {}
+                }
+            }
+
+            for dependency in &backend.readiness.depends_on {
+                if !self.backends.contains_key(dependency) {
+                    errors.push(format!("Backend '{}': unknown dependency '{}'", hostname, dependency));
+                }
+            }
+
+            for route in &backend.routes {
+                if route.backend == *hostname {
+                    errors.push(format!("Backend '{}': route cannot refer to itself", hostname));
+                } else if !self.backends.contains_key(&route.backend) {
+                    errors.push(format!("Backend '{}': unknown route backend '{}'", hostname, route.backend));
+                }
+            }
+
+            let variants = backend.experiment.iter().flat_map(|experiment| &experiment.variants);
+            for variant in variants.filter_map(|v| v.backend.as_ref()) {
+                if variant != hostname && !self.backends.contains_key(variant) {
+                    errors.push(format!("Backend '{}': unknown experiment backend '{}'", hostname, variant));
+                }
+            }
+
+            match &backend.fallback {
+                Some(fallback) if fallback == hostname => {
+                    errors.push(format!("Backend '{}': 'fallback' cannot refer to itself", hostname));
+                }
+                Some(fallback) if !self.backends.contains_key(fallback) => {
+                    errors.push(format!("Backend '{}': unknown fallback backend '{}'", hostname, fallback));
+                }
+                _ => {}
+            }
+        }
+
+        // Metrics are kept by experiment name
+        let mut experiments: HashMap<&str, &str> = HashMap::new();
+        for (hostname, backend) in &self.backends {
+            if let Some(experiment) = &backend.experiment {
+                if let Some(other) = experiments.insert(&experiment.name, hostname) {
+                    errors.push(format!(
+                        "Backend '{}': experiment name '{}' is also used by '{}'",
+                        hostname, experiment.name, other
+                    ));
+                }
+            }
+        }
+
+        if let Some(hostname) = self.dependency_cycle() {
+            errors.push(format!("Backend '{}': readiness depends_on forms a cycle", hostname));
+        }
+
+        if !errors.is_empty() {
+            anyhow::bail!("Configuration errors:\n  - {}", errors.join("\n  - "));
+        }
+
+        Ok(())
+    }
+
+    /// Find a backend whose `depends_on` chain leads back to itself
+    fn dependency_cycle(&self) -> Option<&str> {
+        fn visits<'a>(config: &'a Config, target: &str, current: &'a str, seen: &mut Vec<&'a str>) -> bool {
+            let Some(backend) = config.backends.get(current) else {
+                return false;
+            };
+            backend.readiness.depends_on.iter().any(|dep| {
+                if dep == target {
+                    return true;
+                }
+                if seen.contains(&dep.as_str()) {
+                    return false;
+                }
+                seen.push(dep);
+                visits(config, target, dep, seen)
+            })
+        }
+
+        let mut hostnames: Vec<&str> = self.backends.keys().map(String::as_str).collect();
+        hostnames.sort_unstable();
+        hostnames
+            .into_iter()
+            .find(|hostname| visits(self, hostname, hostname, &mut Vec::new()))
+    }
+}
+
+/// Read the schema version of a config document (0 if absent)
+fn document_version(doc: &DocumentMut) -> anyhow::Result<u32> {
+    match doc.get("version") {
+        None => Ok(0),
+        Some(item) => item
+            .as_integer()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| anyhow::anyhow!("'version' must be a non-negative integer")),
+    }
+}
+
+/// Upgrade a config document in place to `CONFIG_VERSION`.
+///
+/// Comments and formatting are preserved. Returns the version the document
+/// had before migration.
+pub fn migrate_document(doc: &mut DocumentMut) -> anyhow::Result<u32> {
+    let from_version = document_version(doc)?;
+    if from_version > CONFIG_VERSION {
+        anyhow::bail!(
+            "Config version {} is newer than this build supports ({}); upgrade spawngate",
+            from_version,
+            CONFIG_VERSION
+        );
+    }
+
+    for migration in &MIGRATIONS[from_version as usize..] {
+        migration(doc);
+    }
+    doc.insert("version", toml_edit::value(i64::from(CONFIG_VERSION)));
+
+    Ok(from_version)
+}
+
+/// Rewrite a config file on disk to the current schema version.
+///
+/// The original file is kept next to it with a `.bak` suffix. Returns the
+/// previous version, or `None` if the file was already current.
+pub fn migrate_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Option<u32>> {
+    let path = path.as_ref();
+    let content = std::fs::read_to_string(path)?;
+    let mut doc: DocumentMut = content.parse()?;
+
+    let from_version = migrate_document(&mut doc)?;
+    if from_version == CONFIG_VERSION {
+        return Ok(None);
+    }
+
+    // Make sure the migrated file still loads before touching the original
+    let migrated = doc.to_string();
+    Config::parse(&migrated)?;
+
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bak");
+    std::fs::copy(path, &backup)?;
+    std::fs::write(path, migrated)?;
+
+    Ok(Some(from_version))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let toml = r#"
+[server]
+port = 8080
+bind = "127.0.0.1"
+admin_port = 9000
+
+[defaults]
+idle_timeout_secs = 300
+startup_timeout_secs = 60
+health_check_interval_ms = 200
+health_path = "/healthz"
+
+[backends."example.com"]
+command = "node"
+args = ["server.js"]
+port = 3000
+working_dir = "/app"
+
+[backends."api.example.com"]
+command = "python"
+args = ["-m", "http.server", "8000"]
+port = 8000
+idle_timeout_secs = 120
+"#;
+
+        let config: Config = toml::from_str(toml).unwrap();
+        assert_eq!(config.server.port, 8080);
+        assert_eq!(config.defaults.idle_timeout_secs, 300);
+        assert_eq!(config.backends.len(), 2);
+        assert!(config.backends.contains_key("example.com"));
+    }
+
+    #[test]
+    fn test_default_server_config() {
+        let config = ServerConfig::default();
+        assert_eq!(config.port, 80);
+        assert_eq!(config.bind, "0.0.0.0");
+        assert_eq!(config.admin_port, 9999);
+        assert_eq!(config.pool_max_idle_per_host, 10);
+        assert_eq!(config.pool_idle_timeout_secs, 90);
+        assert_eq!(config.listener_drain_timeout_secs, 30);
+        assert!(config.drain_close_keep_alive);
+    }
+
+    #[test]
+    fn test_default_backend_defaults() {
+        let defaults = BackendDefaults::default();
+        assert_eq!(defaults.idle_timeout_secs, 600);
+        assert_eq!(defaults.startup_timeout_secs, 30);
+        assert_eq!(defaults.health_check_interval_ms, 100);
+        assert_eq!(defaults.health_path, "/health");
+        assert_eq!(defaults.shutdown_grace_period_secs, 10);
+        assert_eq!(defaults.drain_timeout_secs, 30);
+        assert_eq!(defaults.request_timeout_secs, 30);
+        assert_eq!(defaults.ready_health_check_interval_ms, 5000);
+        assert_eq!(defaults.unhealthy_threshold, 3);
+    }
+
+    #[test]
+    fn test_backend_config_uses_defaults() {
+        let defaults = BackendDefaults::default();
+        let backend: BackendConfig = toml::from_str(r#"
+command = "node"
+port = 3000
+"#)
+        .unwrap();
+
+        assert_eq!(backend.idle_timeout(&defaults), Duration::from_secs(600));
+        assert_eq!(backend.startup_timeout(&defaults), Duration::from_secs(30));
+        assert_eq!(
+            backend.health_check_interval(&defaults),
+            Duration::from_millis(100)
+        );
+        assert_eq!(backend.health_path(&defaults), "/health");
+        assert_eq!(
+            backend.shutdown_grace_period(&defaults),
+            Duration::from_secs(10)
+        );
+        assert_eq!(backend.drain_timeout(&defaults), Duration::from_secs(30));
+        assert_eq!(backend.request_timeout(&defaults), Duration::from_secs(30));
+        assert_eq!(
+            backend.ready_health_check_interval(&defaults),
+            Duration::from_millis(5000)
+        );
+        assert_eq!(backend.unhealthy_threshold(&defaults), 3);
+    }
+
+    #[test]
+    fn test_backend_config_overrides_defaults() {
+        let defaults = BackendDefaults::default();
+        let backend: BackendConfig = toml::from_str(r#"
+command = "node"
+port = 3000
+idle_timeout_secs = 120
+startup_timeout_secs = 60
+health_check_interval_ms = 500
+health_path = "/ready"
+shutdown_grace_period_secs = 5
+drain_timeout_secs = 15
+request_timeout_secs = 10
+ready_health_check_interval_ms = 10000
+unhealthy_threshold = 5
+"#)
+        .unwrap();
+
+        assert_eq!(backend.idle_timeout(&defaults), Duration::from_secs(120));
+        assert_eq!(backend.startup_timeout(&defaults), Duration::from_secs(60));
+        assert_eq!(
+            backend.health_check_interval(&defaults),
+            Duration::from_millis(500)
+        );
+        assert_eq!(backend.health_path(&defaults), "/ready");
+        assert_eq!(
+            backend.shutdown_grace_period(&defaults),
+            Duration::from_secs(5)
+        );
+        assert_eq!(backend.drain_timeout(&defaults), Duration::from_secs(15));
+        assert_eq!(backend.request_timeout(&defaults), Duration::from_secs(10));
+        assert_eq!(
+            backend.ready_health_check_interval(&defaults),
+            Duration::from_millis(10000)
+        );
+        assert_eq!(backend.unhealthy_threshold(&defaults), 5);
+    }
+
+    #[test]
+    fn test_minimal_config() {
+        let toml = r#"
+[backends."example.com"]
+command = "node"
+port = 3000
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+
+        // Should use defaults for server
+        assert_eq!(config.server.port, 80);
+        assert_eq!(config.server.bind, "0.0.0.0");
+
+        // Should use defaults for backend defaults
+        assert_eq!(config.defaults.idle_timeout_secs, 600);
+
+        // Backend should be present
+        assert!(config.backends.contains_key("example.com"));
+        let backend = config.backends.get("example.com").unwrap();
+        assert_eq!(backend.command, Some("node".to_string()));
+        assert_eq!(backend.port, 3000);
+    }
+
+    #[test]
+    fn test_backend_with_env_vars() {
+        let toml = r#"
+command = "node"
+port = 3000
+
+[env]
+NODE_ENV = "production"
+DEBUG = "true"
+"#;
+        let backend: BackendConfig = toml::from_str(toml).unwrap();
+
+        assert_eq!(backend.env.len(), 2);
+        assert_eq!(backend.env.get("NODE_ENV"), Some(&"production".to_string()));
+        assert_eq!(backend.env.get("DEBUG"), Some(&"true".to_string()));
+    }
+
+    #[test]
+    fn test_backend_with_args() {
+        let toml = r#"
+command = "python"
+args = ["-m", "http.server", "8000"]
+port = 8000
+"#;
+        let backend: BackendConfig = toml::from_str(toml).unwrap();
+
+        assert_eq!(backend.args, vec!["-m", "http.server", "8000"]);
+    }
+
+    #[test]
+    fn test_empty_config() {
+        let toml = "";
+        let config: Config = toml::from_str(toml).unwrap();
+
+        // Should use all defaults
+        assert_eq!(config.server.port, 80);
+        assert_eq!(config.defaults.idle_timeout_secs, 600);
+        assert!(config.backends.is_empty());
+    }
+
+    #[test]
+    fn test_acme_config_defaults() {
+        let toml = "";
+        let config: Config = toml::from_str(toml).unwrap();
+
+        // ACME should be disabled by default
+        assert!(!config.server.acme.enabled);
+        assert!(config.server.acme.domains.is_empty());
+        assert!(config.server.acme.email.is_none());
+        assert!(config.server.acme.directory_url.is_none());
+        assert!(!config.server.acme.staging);
+        assert!(config.server.acme.preflight);
+        assert!(config.server.acme.expected_ips.is_empty());
+        assert_eq!(config.server.acme.cache_dir, "./acme_cache");
+        assert!(!config.server.acme_enabled());
+    }
+
+    #[test]
+    fn test_acme_config_enabled() {
+        let toml = r#"
+[server.acme]
+enabled = true
+domains = ["example.com", "api.example.com"]
+email = "admin@example.com"
+cache_dir = "/var/lib/acme"
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+
+        assert!(config.server.acme.enabled);
+        assert_eq!(config.server.acme.domains, vec!["example.com", "api.example.com"]);
+        assert_eq!(config.server.acme.email, Some("admin@example.com".to_string()));
+        assert_eq!(config.server.acme.cache_dir, "/var/lib/acme");
+        assert!(config.server.acme_enabled());
+    }
+
+    #[test]
+    fn test_acme_config_with_staging() {
+        let toml = r#"
+[server.acme]
+enabled = true
+domains = ["test.example.com"]
+email = "test@example.com"
+directory_url = "https://acme-staging-v02.api.letsencrypt.org/directory"
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+
+        assert!(config.server.acme.enabled);
+        assert_eq!(
+            config.server.acme.directory_url,
+            Some("https://acme-staging-v02.api.letsencrypt.org/directory".to_string())
+        );
+    }
+
+    #[test]
+    fn test_acme_preflight_settings() {
+        let toml = r#"
+[server.acme]
+enabled = true
+domains = ["example.com"]
+preflight = false
+expected_ips = ["203.0.113.10", "2001:db8::10"]
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+
+        assert!(!config.server.acme.preflight);
+        assert_eq!(
+            config.server.acme.expected_ips,
+            vec!["203.0.113.10".parse::<IpAddr>().unwrap(), "2001:db8::10".parse().unwrap()]
+        );
+        assert!(toml::from_str::<Config>("[server.acme]\nexpected_ips = [\"nope\"]").is_err());
+    }
+
+    #[test]
+    fn test_acme_enabled_requires_domains() {
+        let toml = r#"
+[server.acme]
+enabled = true
+email = "admin@example.com"
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+
+        // ACME enabled but no domains - acme_enabled() should return false
+        assert!(config.server.acme.enabled);
+        assert!(config.server.acme.domains.is_empty());
+        assert!(!config.server.acme_enabled());
+    }
+
+    #[test]
+    fn test_tls_enabled_includes_acme() {
+        // Without ACME
+        let toml = "";
+        let config: Config = toml::from_str(toml).unwrap();
+        assert!(!config.server.tls_enabled());
+
+        // With ACME
+        let toml = r#"
+[server.acme]
+enabled = true
+domains = ["example.com"]
+email = "admin@example.com"
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        assert!(config.server.tls_enabled());
+        assert!(config.server.acme_enabled());
+    }
+
+    #[test]
+    fn test_acme_challenge_type_defaults_to_http01() {
+        let toml = r#"
+[server.acme]
+enabled = true
+domains = ["example.com"]
+email = "admin@example.com"
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        assert_eq!(config.server.acme.challenge_type, AcmeChallengeType::Http01);
+    }
+
+    #[test]
+    fn test_acme_challenge_type_tls_alpn01() {
+        let toml = r#"
+[server.acme]
+enabled = true
+domains = ["example.com"]
+email = "admin@example.com"
+challenge_type = "tls-alpn-01"
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        assert_eq!(config.server.acme.challenge_type, AcmeChallengeType::TlsAlpn01);
+    }
+
+    #[test]
+    fn test_acme_challenge_type_http01_explicit() {
+        let toml = r#"
+[server.acme]
+enabled = true
+domains = ["example.com"]
+email = "admin@example.com"
+challenge_type = "http-01"
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        assert_eq!(config.server.acme.challenge_type, AcmeChallengeType::Http01);
+    }
+
+    #[test]
+    fn test_acme_dns01_config() {
+        let toml = r#"
+[server.acme]
+enabled = true
+domains = ["*.apps.example.com", "apps.example.com"]
+email = "admin@example.com"
+challenge_type = "dns-01"
+
+[server.acme.dns]
+provider = "route53"
+zone_id = "Z0123456789"
+"#;
+        let config = Config::parse(toml).unwrap();
+        assert_eq!(config.server.acme.challenge_type, AcmeChallengeType::Dns01);
+        let dns = config.server.acme.dns.as_ref().unwrap();
+        assert_eq!(dns.provider, AcmeDnsProvider::Route53);
+        assert_eq!(dns.zone_id.as_deref(), Some("Z0123456789"));
+        assert_eq!(dns.propagation_secs, 10);
+        // Wildcards are kept even though no backend is named after them
+        assert_eq!(config.acme_config().domains, vec!["*.apps.example.com", "apps.example.com"]);
+
+        let err = Config::parse(&toml.replace("challenge_type = \"dns-01\"", "")).unwrap_err().to_string();
+        assert!(err.contains("wildcard domain '*.apps.example.com' requires"), "{}", err);
+        assert!(err.contains("[server.acme.dns] requires"), "{}", err);
+
+        let without_provider = toml.split("[server.acme.dns]").next().unwrap();
+        let err = Config::parse(without_provider).unwrap_err().to_string();
+        assert!(err.contains("requires a [server.acme.dns] table"), "{}", err);
+    }
+
+    #[test]
+    fn test_docker_backend_config() {
+        let toml = r#"
+[backends."app.example.com"]
+type = "docker"
+image = "myapp:latest"
+port = 3000
+container_name = "myapp-container"
+network = "host"
+pull_policy = "always"
+memory = "512m"
+cpus = "1.0"
+cpu_shares = 512
+pids_limit = 100
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        let backend = config.backends.get("app.example.com").unwrap();
+
+        assert_eq!(backend.backend_type, BackendType::Docker);
+        assert_eq!(backend.image, Some("myapp:latest".to_string()));
+        assert_eq!(backend.port, 3000);
+        assert_eq!(backend.container_name, Some("myapp-container".to_string()));
+        assert_eq!(backend.network, Some("host".to_string()));
+        assert_eq!(backend.pull_policy, PullPolicy::Always);
+        assert_eq!(backend.memory, Some("512m".to_string()));
+        assert_eq!(backend.cpus, Some("1.0".to_string()));
+        assert_eq!(backend.cpu_shares, Some(512));
+        assert_eq!(backend.pids_limit, Some(100));
+        assert!(backend.command.is_none());
+        assert!(config.validate().is_ok());
+
+        let mut backend = backend.clone();
+        backend.cpu_shares = Some(1);
+        assert!(backend.validate("app.example.com").unwrap_err().contains("'cpu_shares'"));
+        backend.cpu_shares = None;
+        backend.pids_limit = Some(0);
+        assert!(backend.validate("app.example.com").unwrap_err().contains("'pids_limit'"));
+    }
+
+    #[test]
+    fn test_local_backend_resource_limits() {
+        let toml = r#"
+[defaults.cgroups]
+enabled = true
+root = "/sys/fs/cgroup/system.slice/spawngate.service/backends"
+
+[backends."app.local"]
+command = "node"
+port = 3000
+memory = "256m"
+cpus = "0.5"
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        assert!(config.defaults.cgroups.enabled);
+        assert_eq!(config.defaults.cgroups.root, "/sys/fs/cgroup/system.slice/spawngate.service/backends");
+        assert!(config.validate().is_ok());
+        let defaults = BackendDefaults::default();
+        assert!(!defaults.cgroups.enabled);
+        assert_eq!(defaults.cgroups.root, "/sys/fs/cgroup/spawngate");
+
+        let mut backend = config.backends["app.local"].clone();
+        backend.memory = Some("lots".to_string());
+        assert!(backend.validate("app.local").unwrap_err().contains("Invalid memory limit"));
+        backend.memory = None;
+        backend.cpus = Some("0".to_string());
+        assert!(backend.validate("app.local").unwrap_err().contains("invalid CPU limit"));
+    }
+
+    #[test]
+    fn test_local_backend_user_and_group() {
+        let toml = r#"
+[backends."app.local"]
+command = "node"
+port = 3000
+user = "www-data"
+group = "www-data"
+
+[backends."api.local"]
+type = "docker"
+image = "api:latest"
+port = 8080
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        let backend = config.backends["app.local"].clone();
+        assert_eq!(backend.user.as_deref(), Some("www-data"));
+        assert_eq!(backend.group.as_deref(), Some("www-data"));
+        assert!(backend.validate("app.local").is_ok());
+        assert!(config.backends["api.local"].user.is_none());
+
+        let mut empty = backend.clone();
+        empty.group = Some(String::new());
+        assert!(empty.validate("app.local").unwrap_err().contains("must not be empty"));
+
+        let mut docker = config.backends["api.local"].clone();
+        docker.user = Some("1000".to_string());
+        assert!(docker.validate("api.local").unwrap_err().contains("only apply to local backends"));
+    }
+
+    #[test]
+    fn test_local_backend_is_default() {
+        let toml = r#"
+[backends."app.local"]
+command = "node"
+args = ["server.js"]
+port = 3000
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        let backend = config.backends.get("app.local").unwrap();
+
+        assert_eq!(backend.backend_type, BackendType::Local);
+        assert_eq!(backend.command, Some("node".to_string()));
+        assert!(backend.image.is_none());
+    }
+
+    #[test]
+    fn test_docker_image_pinning_config() {
+        let digest = format!("sha256:{}", "0f".repeat(32));
+        let config = Config::parse(&format!(
+            r#"
+[backends."app.local"]
+type = "docker"
+image = "myapp:1.2"
+image_digest = "{}"
+platform = "linux/arm64"
+port = 3000
+"#,
+            digest
+        ))
+        .unwrap();
+        let backend = &config.backends["app.local"];
+        assert_eq!(backend.image_digest.as_deref(), Some(digest.as_str()));
+        assert_eq!(backend.platform.as_deref(), Some("linux/arm64"));
+
+        let mut backend = BackendConfig::docker("myapp:1.2", 3000);
+        backend.image_digest = Some("sha256:abc".to_string());
+        assert!(backend.validate("app.local").is_err());
+
+        let mut backend = BackendConfig::docker(&format!("myapp@{}", digest), 3000);
+        assert!(backend.validate("app.local").is_ok());
+        backend.platform = Some("arm64".to_string());
+        assert!(backend.validate("app.local").is_err());
+    }
+
+    #[test]
+    fn test_pull_policy_if_not_present() {
+        let toml = r#"
+[backends."app.example.com"]
+type = "docker"
+image = "myapp:latest"
+port = 3000
+pull_policy = "if-not-present"
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        let backend = config.backends.get("app.example.com").unwrap();
+        assert_eq!(backend.pull_policy, PullPolicy::IfNotPresent);
+    }
+
+    #[test]
+    fn test_pull_policy_never() {
+        let toml = r#"
+[backends."app.example.com"]
+type = "docker"
+image = "myapp:latest"
+port = 3000
+pull_policy = "never"
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        let backend = config.backends.get("app.example.com").unwrap();
+        assert_eq!(backend.pull_policy, PullPolicy::Never);
+    }
+
+    #[test]
+    fn test_container_runtime() {
+        let toml = r#"
+[backends."app.example.com"]
+type = "docker"
+image = "myapp:latest"
+port = 3000
+container_runtime = "podman"
+
+[backends."api.example.com"]
+type = "docker"
+image = "api:latest"
+port = 3001
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        assert_eq!(config.backends["app.example.com"].container_runtime, ContainerEngine::Podman);
+        assert_eq!(config.backends["api.example.com"].container_runtime, ContainerEngine::Docker);
+    }
+
+    #[test]
+    fn test_sidecars() {
+        let toml = r#"
+[backends."app.example.com"]
+type = "docker"
+image = "myapp:latest"
+port = 3000
+
+[[backends."app.example.com".sidecars]]
+name = "redis"
+image = "redis:7"
+memory = "128m"
+
+[[backends."app.example.com".sidecars]]
+name = "metrics-agent"
+image = "agent:1"
+args = ["--listen", ":9100"]
+env = { MODE = "push" }
+"#;
+        let config = Config::parse(toml).unwrap();
+        let backend = &config.backends["app.example.com"];
+        assert_eq!(backend.sidecars.len(), 2);
+        assert_eq!(backend.sidecars[0].name, "redis");
+        assert_eq!(backend.sidecars[1].args, vec!["--listen", ":9100"]);
+        assert_eq!(backend.sidecars[1].env["MODE"], "push");
+
+        let invalid = |change: fn(&mut BackendConfig)| {
+            let mut backend = backend.clone();
+            change(&mut backend);
+            backend.validate("app.example.com").unwrap_err()
+        };
+        assert!(invalid(|b| b.sidecars[1].name = "redis".to_string()).contains("duplicate sidecar 'redis'"));
+        assert!(invalid(|b| b.sidecars[0].name = "Redis_1".to_string()).contains("invalid sidecar name"));
+        assert!(invalid(|b| b.sidecars[0].memory = Some("lots".to_string())).contains("sidecar 'redis'"));
+        assert!(invalid(|b| b.network = Some("bridge".to_string())).contains("can't be combined with sidecars"));
+        assert!(invalid(|b| b.container_runtime = ContainerEngine::Podman).contains("docker runtime"));
+    }
+
+    #[test]
+    fn test_effective_config() {
+        let toml = r#"
+[server]
+admin_token = "hunter2"
+
+[server.cold_start_slo]
+threshold_ms = 1500
+
+[defaults]
+idle_timeout_secs = 120
+health_path = "/healthz"
+
+[backends."secret.example.com"]
+command = "./app"
+port = 3000
+idle_timeout_secs = 60
+env = { STRIPE_API_KEY = "sk_live", LOG_LEVEL = "info", PASSWORD_FILE = "/run/pw" }
+"#;
+        let config = Config::parse(toml).unwrap();
+        let vars = ConfigVars::new();
+        vars.update("secret.example.com", HashMap::from([("LOG_LEVEL".to_string(), Some("debug".to_string()))]))
+            .unwrap();
+        let effective = config.effective(&vars);
+
+        let backend = &effective["backends"]["secret.example.com"];
+        assert_eq!(backend["idle_timeout_secs"], 60);
+        assert_eq!(backend["health_path"], "/healthz");
+        assert_eq!(backend["startup_timeout_secs"], 30);
+        assert_eq!(backend["cold_start_slo_ms"], 1500);
+        assert_eq!(backend["env"]["LOG_LEVEL"], "debug");
+        assert_eq!(backend["env"]["STRIPE_API_KEY"], REDACTED);
+        assert_eq!(backend["env"]["PASSWORD_FILE"], REDACTED);
+        assert_eq!(backend["tls_key"], serde_json::Value::Null);
+        assert_eq!(effective["server"]["admin_token"], REDACTED);
+        assert_eq!(effective["defaults"]["idle_timeout_secs"], 120);
+    }
+
+    #[test]
+    fn test_registry_auth() {
+        let toml = r#"
+[defaults.registries."ghcr.io"]
+username = "deploy"
+password = "ghp_secret"
+
+[backends."app.example.com"]
+type = "docker"
+image = "ghcr.io/team/app:1"
+port = 3000
+
+[[backends."app.example.com".sidecars]]
+name = "cache"
+image = "redis:7"
+
+[backends."private.example.com"]
+type = "docker"
+image = "registry.local:5000/private:1"
+port = 3001
+registry_auth = { token = "abc" }
+"#;
+        let config = Config::parse(toml).unwrap();
+        let mut app = config.backends["app.example.com"].clone();
+        assert_eq!(app.registry_auth, None);
+        app.resolve_registry_auth(&config.defaults);
+        assert_eq!(app.registry_auth.as_ref().unwrap().username.as_deref(), Some("deploy"));
+        assert_eq!(app.sidecars[0].registry_auth, None);
+
+        let mut private = config.backends["private.example.com"].clone();
+        private.resolve_registry_auth(&config.defaults);
+        assert_eq!(private.registry_auth.as_ref().unwrap().token.as_deref(), Some("abc"));
+
+        let invalid = |change: fn(&mut BackendConfig)| {
+            let mut backend = config.backends["private.example.com"].clone();
+            change(&mut backend);
+            backend.validate("private.example.com").unwrap_err()
+        };
+        assert!(invalid(|b| b.registry_auth.as_mut().unwrap().username = Some("me".to_string()))
+            .contains("can't be combined"));
+        assert!(invalid(|b| b.registry_auth = Some(RegistryAuth::default())).contains("requires 'username'"));
+        assert!(invalid(|b| b.container_runtime = ContainerEngine::Podman).contains("podman runtime"));
+
+        let err = Config::parse(&toml.replace("password = \"ghp_secret\"", "")).unwrap_err();
+        assert!(err.to_string().contains("registries.\"ghcr.io\""), "{}", err);
+    }
+
+    #[test]
+    fn test_on_container_exit() {
+        let toml = r#"
+[backends."app.example.com"]
+type = "docker"
+image = "myapp:latest"
+port = 3000
+on_container_exit = "stop"
+
+[backends."api.example.com"]
+type = "docker"
+image = "api:latest"
+port = 3001
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        assert_eq!(config.backends["app.example.com"].on_container_exit, ContainerExitPolicy::Stop);
+        assert_eq!(config.backends["api.example.com"].on_container_exit, ContainerExitPolicy::Restart);
+    }
+
+    #[test]
+    fn test_firecracker_config() {
+        let toml = r#"
+[backends."vm.example.com"]
+type = "firecracker"
+port = 8080
+
+[backends."vm.example.com".firecracker]
+kernel = "/vm/vmlinux"
+rootfs = "/vm/app.ext4"
+host_ip = "172.16.0.1"
+guest_ip = "172.16.0.2"
+"#;
+        let config = Config::parse(toml).unwrap();
+        let backend = &config.backends["vm.example.com"];
+        assert_eq!(backend.backend_type, BackendType::Firecracker);
+        let vm = backend.firecracker.as_ref().unwrap();
+        assert_eq!(vm.guest_ip, Ipv4Addr::new(172, 16, 0, 2));
+        assert_eq!(vm.binary, "firecracker");
+        assert!(vm.boot_args.starts_with("console=ttyS0"));
+
+        assert!(Config::parse(&toml.replace("172.16.0.2", "172.16.0.1")).is_err());
+        let missing = toml.split("\n[backends.\"vm.example.com\".firecracker]").next().unwrap();
+        assert!(Config::parse(missing).is_err());
+    }
+
+    #[test]
+    fn test_cloud_vm_config() {
+        let toml = r#"
+[backends."build.example.com"]
+type = "cloud-vm"
+port = 8080
+
+[backends."build.example.com".cloud_vm]
+provider = "ec2"
+instance_id = "i-0123456789abcdef0"
+region = "eu-west-1"
+"#;
+        let config = Config::parse(toml).unwrap();
+        let backend = &config.backends["build.example.com"];
+        assert_eq!(backend.backend_type, BackendType::CloudVm);
+        let vm = backend.cloud_vm.as_ref().unwrap();
+        assert_eq!(vm.provider, CloudProvider::Ec2);
+        assert_eq!(vm.region.as_deref(), Some("eu-west-1"));
+        assert!(!vm.private_ip);
+        assert!(vm.address.is_none());
+
+        assert!(Config::parse(&toml.replace("region = \"eu-west-1\"\n", "")).is_err());
+        let hetzner = toml.replace("\"ec2\"", "\"hetzner\"").replace("region = \"eu-west-1\"\n", "");
+        assert!(Config::parse(&hetzner).is_ok());
+        let missing = toml.split("\n[backends.\"build.example.com\".cloud_vm]").next().unwrap();
+        assert!(Config::parse(missing).is_err());
+    }
+
+    #[test]
+    fn test_ssh_tunnel_config() {
+        let toml = r#"
+[backends."legacy.example.com"]
+type = "ssh-tunnel"
+port = 9000
+
+[backends."legacy.example.com".ssh_tunnel]
+host = "10.1.2.3"
+user = "deploy"
+identity_file = "/etc/spawngate/id_ed25519"
+remote_port = 8080
+"#;
+        let config = Config::parse(toml).unwrap();
+        let backend = &config.backends["legacy.example.com"];
+        assert_eq!(backend.backend_type, BackendType::SshTunnel);
+        let tunnel = backend.ssh_tunnel.as_ref().unwrap();
+        assert_eq!(tunnel.port, 22);
+        assert_eq!(tunnel.remote_host, "127.0.0.1");
+        assert_eq!(tunnel.remote_port, Some(8080));
+        assert_eq!(tunnel.binary, "ssh");
+
+        assert!(Config::parse(&toml.replace("\"10.1.2.3\"", "\"-oProxyCommand=x\"")).is_err());
+        let missing = toml.split("\n[backends.\"legacy.example.com\".ssh_tunnel]").next().unwrap();
+        assert!(Config::parse(missing).is_err());
+    }
+
+    #[test]
+    fn test_backend_config_helpers() {
+        let local = BackendConfig::local("node", 3000);
+        assert_eq!(local.backend_type, BackendType::Local);
+        assert_eq!(local.command, Some("node".to_string()));
+        assert_eq!(local.port, 3000);
+
+        let docker = BackendConfig::docker("myapp:latest", 8080);
+        assert_eq!(docker.backend_type, BackendType::Docker);
+        assert_eq!(docker.image, Some("myapp:latest".to_string()));
+        assert_eq!(docker.port, 8080);
+        assert!(docker.command.is_none());
+    }
+
+    #[test]
+    fn test_mixed_backend_types() {
+        let toml = r#"
+[backends."local.app"]
+command = "node"
+port = 3000
+
+[backends."docker.app"]
+type = "docker"
+image = "nginx:latest"
+port = 8080
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+
+        let local = config.backends.get("local.app").unwrap();
+        assert_eq!(local.backend_type, BackendType::Local);
+
+        let docker = config.backends.get("docker.app").unwrap();
+        assert_eq!(docker.backend_type, BackendType::Docker);
+    }
+
+    #[test]
+    fn test_validate_docker_requires_image() {
+        let toml = r#"
+[backends."app.example.com"]
+type = "docker"
+port = 3000
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        let result = config.validate();
+        assert!(result.is_err());
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("Docker backend requires 'image' field"));
+        assert!(err.contains("app.example.com"));
+    }
+
+    #[test]
+    fn test_validate_custom_requires_spawner() {
+        let toml = r#"
+[backends."vm.app"]
+type = "custom"
+port = 3000
+"#;
+        let mut config: Config = toml::from_str(toml).unwrap();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("custom backend requires 'spawner' field"));
+
+        config.backends.get_mut("vm.app").unwrap().spawner = Some("firecracker".to_string());
+        assert!(config.validate().is_ok());
+        assert_eq!(config.backends["vm.app"].backend_type, BackendType::Custom);
+    }
+
+    #[test]
+    fn test_validate_local_requires_command() {
+        let toml = r#"
+[backends."app.local"]
+port = 3000
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        let result = config.validate();
+        assert!(result.is_err());
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("local backend requires 'command' field"));
+        assert!(err.contains("app.local"));
+    }
+
+    #[test]
+    fn test_port_allocation_config() {
+        let toml = r#"
+[backends."app.example.com"]
+command = "node"
+port = 0
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        assert!(config.validate().is_ok());
+        let ports = &config.defaults.ports;
+        assert_eq!((ports.range_start, ports.range_end), (20000, 29999));
+        assert!(ports.reuse);
+        assert_eq!(ports.cooldown_secs, 60);
+
+        assert!(BackendConfig::docker("nginx:latest", 0).validate("docker.app").is_ok());
+        for backend_type in [
+            BackendType::Custom,
+            BackendType::Firecracker,
+            BackendType::Nomad,
+            BackendType::CloudVm,
+            BackendType::SshTunnel,
+        ] {
+            let mut backend = BackendConfig::local("node", 0);
+            backend.backend_type = backend_type.clone();
+            let err = backend.validate("app.example.com").unwrap_err();
+            assert!(err.contains("'port' must be greater than 0"), "{:?}: {}", backend_type, err);
+        }
+
+        let err = Config::parse("[defaults.ports]\nrange_start = 30000\nrange_end = 29999").unwrap_err();
+        assert!(err.to_string().contains("ports range 30000-29999 is invalid"));
+        let err = Config::parse("[defaults.ports]\nrange_start = 0").unwrap_err();
+        assert!(err.to_string().contains("ports range 0-29999 is invalid"));
+    }
+
+    #[test]
+    fn test_validate_multiple_errors() {
+        let toml = r#"
+[backends."local.app"]
+port = 3000
+
+[backends."docker.app"]
+type = "docker"
+port = 8080
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        let result = config.validate();
+        assert!(result.is_err());
+        let err = result.unwrap_err().to_string();
+        // Should report both errors
+        assert!(err.contains("local backend requires 'command' field"));
+        assert!(err.contains("Docker backend requires 'image' field"));
+    }
+
+    #[test]
+    fn test_validate_valid_configs() {
+        let local = BackendConfig::local("node", 3000);
+        assert!(local.validate("test.local").is_ok());
+
+        let docker = BackendConfig::docker("nginx:latest", 8080);
+        assert!(docker.validate("test.docker").is_ok());
+    }
+
+    #[test]
+    fn test_unversioned_config_is_migrated() {
+        let toml = r#"
+[backends."app.local"]
+command = "node"
+port = 3000
+"#;
+        let config = Config::parse(toml).unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert!(config.backends.contains_key("app.local"));
+    }
+
+    #[test]
+    fn test_future_config_version_rejected() {
+        let toml = format!("version = {}\n", CONFIG_VERSION + 1);
+        let err = Config::parse(&toml).unwrap_err();
+        assert!(err.to_string().contains("newer than this build supports"));
+    }
+
+    #[test]
+    fn test_invalid_config_version_rejected() {
+        let err = Config::parse("version = \"one\"\n").unwrap_err();
+        assert!(err.to_string().contains("'version' must be a non-negative integer"));
+    }
+
+    #[test]
+    fn test_migrate_document_preserves_comments() {
+        let mut doc: DocumentMut = "# Main config\n[server]\nport = 8080 # http\n"
+            .parse()
+            .unwrap();
+        assert_eq!(migrate_document(&mut doc).unwrap(), 0);
+
+        let migrated = doc.to_string();
+        assert!(migrated.contains("# Main config"));
+        assert!(migrated.contains("port = 8080 # http"));
+        assert!(migrated.contains(&format!("version = {}", CONFIG_VERSION)));
+
+        // Already current: no-op
+        assert_eq!(migrate_document(&mut doc).unwrap(), CONFIG_VERSION);
+    }
+
+    #[test]
+    fn test_migrate_file() {
+        let dir = std::env::temp_dir().join(format!("spawngate-migrate-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        std::fs::write(&path, "[server]\nport = 8080\n").unwrap();
+
+        assert_eq!(migrate_file(&path).unwrap(), Some(0));
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.contains(&format!("version = {}", CONFIG_VERSION)));
+        assert!(dir.join("config.toml.bak").exists());
+
+        assert_eq!(migrate_file(&path).unwrap(), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_backend_profiles() {
+        let toml = r#"
+[profiles.nodejs-small]
+memory = "256m"
+idle_timeout_secs = 120
+health_path = "/healthz"
+keep_warm = true
+
+[profiles.nodejs-small.env]
+NODE_ENV = "production"
+LOG_LEVEL = "info"
+
+[backends."a.local"]
+command = "node"
+port = 3000
+profile = "nodejs-small"
+
+[backends."b.local"]
+command = "node"
+port = 3001
+profile = "nodejs-small"
+idle_timeout_secs = 30
+keep_warm = false
+
+[backends."b.local".env]
+LOG_LEVEL = "debug"
+
+[backends."c.local"]
+command = "node"
+port = 3002
+"#;
+        let config = Config::parse(toml).unwrap();
+
+        let a = &config.backends["a.local"];
+        assert_eq!(a.memory, Some("256m".to_string()));
+        assert_eq!(a.idle_timeout_secs, Some(120));
+        assert_eq!(a.health_path, Some("/healthz".to_string()));
+        assert_eq!(a.env.get("NODE_ENV"), Some(&"production".to_string()));
+        assert!(a.keep_warm());
+
+        // Backend settings win over the profile
+        let b = &config.backends["b.local"];
+        assert_eq!(b.idle_timeout_secs, Some(30));
+        assert_eq!(b.env.get("LOG_LEVEL"), Some(&"debug".to_string()));
+        assert_eq!(b.env.get("NODE_ENV"), Some(&"production".to_string()));
+        assert!(!b.keep_warm());
+
+        let c = &config.backends["c.local"];
+        assert_eq!(c.memory, None);
+        assert_eq!(c.idle_timeout_secs, None);
+        assert!(!c.keep_warm());
+    }
+
+    #[test]
+    fn test_max_lifetime_config() {
+        let toml = r#"
+[defaults]
+max_lifetime_secs = 86400
+
+[profiles.leaky]
+max_lifetime_secs = 3600
+
+[backends."a.local"]
+command = "node"
+port = 3000
+profile = "leaky"
+
+[backends."b.local"]
+command = "node"
+port = 3001
+max_lifetime_secs = 600
+
+[backends."c.local"]
+command = "node"
+port = 3002
+"#;
+        let config = Config::parse(toml).unwrap();
+        let defaults = &config.defaults;
+        assert_eq!(config.backends["a.local"].max_lifetime(defaults), Some(Duration::from_secs(3600)));
+        assert_eq!(config.backends["b.local"].max_lifetime(defaults), Some(Duration::from_secs(600)));
+        assert_eq!(config.backends["c.local"].max_lifetime(defaults), Some(Duration::from_secs(86400)));
+        assert_eq!(config.backends["c.local"].max_lifetime(&BackendDefaults::default()), None);
+
+        let mut backend = config.backends["b.local"].clone();
+        backend.max_lifetime_secs = Some(0);
+        assert!(backend.validate("b.local").unwrap_err().contains("'max_lifetime_secs' must be greater than 0"));
+        let err = Config::parse("[defaults]\nmax_lifetime_secs = 0\n").unwrap_err();
+        assert!(err.to_string().contains("defaults: 'max_lifetime_secs'"), "{}", err);
+    }
+
+    #[test]
+    fn test_unknown_profile_rejected() {
+        let toml = r#"
+[backends."a.local"]
+command = "node"
+port = 3000
+profile = "missing"
+"#;
+        let err = Config::parse(toml).unwrap_err();
+        assert!(err.to_string().contains("Backend 'a.local': unknown profile 'missing'"));
+    }
+
+    #[test]
+    fn test_runtime_presets() {
+        let toml = r#"
+[profiles.slow]
+startup_timeout_secs = 120
+
+[backends."api.local"]
+runtime = "python"
+port = 8000
+
+[backends."web.local"]
+runtime = "rails"
+command = "bundle"
+args = ["exec", "puma"]
+port = 3000
+profile = "slow"
 
-impl Config {
-    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
-        let content = std::fs::read_to_string(path)?;
-        let config: Config = toml::from_str(&content)?;
-        config.validate()?;
-        Ok(config)
+[backends."web.local".env]
+RAILS_ENV = "staging"
+
+[backends."shop.local"]
+type = "docker"
+image = "shop:latest"
+runtime = "node"
+port = 3001
+"#;
+        let config = Config::parse(toml).unwrap();
+
+        let api = &config.backends["api.local"];
+        assert_eq!(api.command, Some("python".to_string()));
+        assert_eq!(api.args, ["-m", "gunicorn", "--bind", "127.0.0.1:8000", "app:app"]);
+        assert_eq!(api.health_path, Some("/health".to_string()));
+        assert_eq!(api.env.get("PYTHONUNBUFFERED"), Some(&"1".to_string()));
+
+        // The backend and its profile win over the preset
+        let web = &config.backends["web.local"];
+        assert_eq!(web.command, Some("bundle".to_string()));
+        assert_eq!(web.args, ["exec", "puma"]);
+        assert_eq!(web.startup_timeout_secs, Some(120));
+        assert_eq!(web.health_path, Some("/up".to_string()));
+        assert_eq!(web.env.get("RAILS_ENV"), Some(&"staging".to_string()));
+        assert_eq!(web.env.get("RAILS_LOG_TO_STDOUT"), Some(&"1".to_string()));
+
+        // Docker backends keep their image's command
+        let shop = &config.backends["shop.local"];
+        assert_eq!(shop.command, None);
+        assert_eq!(shop.env.get("NODE_ENV"), Some(&"production".to_string()));
+
+        let err = Config::parse("[backends.\"a.local\"]\nruntime = \"cobol\"\nport = 1").unwrap_err();
+        assert!(err.to_string().contains("unknown variant"), "{}", err);
     }
 
-    /// Validate all configuration
-    pub fn validate(&self) -> anyhow::Result<()> {
-        let mut errors = Vec::new();
+    #[test]
+    fn test_locale_env() {
+        let toml = r#"
+[defaults]
+timezone = "UTC"
+locale = "en_US.UTF-8"
 
-        for (hostname, backend) in &self.backends {
-            if let Err(e) = backend.validate(hostname) {
-                errors.push(e);
-            }
-        }
+[backends."plain.local"]
+command = "app"
+port = 3000
 
-        if !errors.is_empty() {
-            anyhow::bail!("Configuration errors:\n  - {}", errors.join("\n  - "));
-        }
+[backends."se.local"]
+command = "app"
+port = 3001
+timezone = "Europe/Stockholm"
+locale = "sv_SE.UTF-8"
+"#;
+        let config = Config::parse(toml).unwrap();
+        let env = |host: &str| config.backends[host].locale_env(&config.defaults);
 
-        Ok(())
-    }
-}
+        assert_eq!(
+            env("plain.local"),
+            [
+                ("TZ".to_string(), "UTC".to_string()),
+                ("LANG".to_string(), "en_US.UTF-8".to_string()),
+                ("LC_ALL".to_string(), "en_US.UTF-8".to_string()),
+            ]
+        );
+        assert_eq!(env("se.local")[0].1, "Europe/Stockholm");
+        assert_eq!(env("se.local")[2].1, "sv_SE.UTF-8");
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        // Nothing configured: the backend inherits the proxy's environment
+        assert!(BackendConfig::local("app", 3000)
+            .locale_env(&BackendDefaults::default())
+            .is_empty());
+    }
 
     #[test]
-    fn test_parse_config() {
+    fn test_adaptive_idle_config() {
         let toml = r#"
-[server]
-port = 8080
-bind = "127.0.0.1"
-admin_port = 9000
-
-[defaults]
-idle_timeout_secs = 300
-startup_timeout_secs = 60
-health_check_interval_ms = 200
-health_path = "/healthz"
+[defaults.adaptive_idle]
+enabled = true
+quantile = 0.75
 
-[backends."example.com"]
-command = "node"
-args = ["server.js"]
+[backends."app.local"]
+command = "app"
 port = 3000
-working_dir = "/app"
 
-[backends."api.example.com"]
-command = "python"
-args = ["-m", "http.server", "8000"]
-port = 8000
-idle_timeout_secs = 120
+[backends."cron.local"]
+command = "cron"
+port = 3001
+adaptive_idle = false
 "#;
+        let config = Config::parse(toml).unwrap();
+        assert_eq!(config.defaults.adaptive_idle.quantile, 0.75);
+        assert_eq!(config.defaults.adaptive_idle.min_samples, 20);
+        assert!(config.backends["app.local"].adaptive_idle(&config.defaults));
+        assert!(!config.backends["cron.local"].adaptive_idle(&config.defaults));
+
+        let err = Config::parse("[defaults.adaptive_idle]\nquantile = 0.0\nmin_timeout_secs = 600\nmax_timeout_secs = 60")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("'quantile'"), "{}", err);
+        assert!(err.contains("'max_timeout_secs'"), "{}", err);
+    }
 
-        let config: Config = toml::from_str(toml).unwrap();
-        assert_eq!(config.server.port, 8080);
-        assert_eq!(config.defaults.idle_timeout_secs, 300);
-        assert_eq!(config.backends.len(), 2);
-        assert!(config.backends.contains_key("example.com"));
+    #[test]
+    fn test_cold_start_slo_config() {
+        let slo = Config::parse("").unwrap().server.cold_start_slo;
+        assert!(!slo.enabled);
+        assert_eq!((slo.threshold_ms, slo.target), (2000, 0.99));
+
+        let toml = r#"
+[server.cold_start_slo]
+enabled = true
+target = 0.995
+
+[backends."api.local"]
+command = "api"
+port = 3000
+cold_start_slo_ms = 500
+"#;
+        let config = Config::parse(toml).unwrap();
+        assert_eq!(config.server.cold_start_slo.target, 0.995);
+        assert_eq!(config.backends["api.local"].cold_start_slo_ms, Some(500));
+
+        let err = Config::parse("[server.cold_start_slo]\nenabled = true\ntarget = 1.0").unwrap_err();
+        assert!(err.to_string().contains("'target'"), "{}", err);
+        let err = Config::parse("[backends.\"a.local\"]\ncommand = \"a\"\nport = 1\ncold_start_slo_target = 0.0")
+            .unwrap_err();
+        assert!(err.to_string().contains("cold_start_slo_target"), "{}", err);
     }
 
     #[test]
-    fn test_default_server_config() {
-        let config = ServerConfig::default();
-        assert_eq!(config.port, 80);
-        assert_eq!(config.bind, "0.0.0.0");
-        assert_eq!(config.admin_port, 9999);
-        assert_eq!(config.pool_max_idle_per_host, 10);
-        assert_eq!(config.pool_idle_timeout_secs, 90);
+    fn test_cost_model_config() {
+        let config = Config::parse("[defaults.cost]\nmemory = \"1g\"\ngb_hour_price = 0.01").unwrap();
+        assert_eq!(config.defaults.cost.cpus, 1.0);
+        assert_eq!(config.defaults.cost.memory, "1g");
+        assert_eq!(config.defaults.cost.gb_hour_price, Some(0.01));
+
+        let err = Config::parse("[defaults.cost]\ncpus = 0.0\nmemory = \"lots\"").unwrap_err().to_string();
+        assert!(err.contains("'cpus'"), "{}", err);
+        assert!(err.contains("'memory'"), "{}", err);
     }
 
     #[test]
-    fn test_default_backend_defaults() {
-        let defaults = BackendDefaults::default();
-        assert_eq!(defaults.idle_timeout_secs, 600);
-        assert_eq!(defaults.startup_timeout_secs, 30);
-        assert_eq!(defaults.health_check_interval_ms, 100);
-        assert_eq!(defaults.health_path, "/health");
-        assert_eq!(defaults.shutdown_grace_period_secs, 10);
-        assert_eq!(defaults.drain_timeout_secs, 30);
-        assert_eq!(defaults.request_timeout_secs, 30);
-        assert_eq!(defaults.ready_health_check_interval_ms, 5000);
-        assert_eq!(defaults.unhealthy_threshold, 3);
+    fn test_path_normalization_config() {
+        let normalization = Config::parse("").unwrap().server.path_normalization;
+        assert!(normalization.enabled && normalization.merge_slashes && !normalization.strict);
+        assert_eq!(normalization.encoded_slashes, EncodedSlashes::Keep);
+
+        let config = Config::parse("[server.path_normalization]\nencoded_slashes = \"reject\"\nstrict = true").unwrap();
+        assert_eq!(config.server.path_normalization.encoded_slashes, EncodedSlashes::Reject);
+        assert!(config.server.path_normalization.strict);
+        assert!(Config::parse("[server.path_normalization]\nencoded_slashes = \"drop\"").is_err());
     }
 
     #[test]
-    fn test_backend_config_uses_defaults() {
-        let defaults = BackendDefaults::default();
-        let backend: BackendConfig = toml::from_str(r#"
+    fn test_waf_config() {
+        let config = Config::parse(
+            r#"
+[defaults.waf]
+enabled = true
+mode = "log"
+
+[backends."app.local"]
 command = "node"
 port = 3000
-"#)
+
+[backends."admin.local"]
+command = "node"
+port = 3001
+waf_threshold = 10
+waf_mode = "block"
+
+[backends."static.local"]
+command = "node"
+port = 3002
+waf = false
+"#,
+        )
         .unwrap();
 
-        assert_eq!(backend.idle_timeout(&defaults), Duration::from_secs(600));
-        assert_eq!(backend.startup_timeout(&defaults), Duration::from_secs(30));
-        assert_eq!(
-            backend.health_check_interval(&defaults),
-            Duration::from_millis(100)
-        );
-        assert_eq!(backend.health_path(&defaults), "/health");
-        assert_eq!(
-            backend.shutdown_grace_period(&defaults),
-            Duration::from_secs(10)
-        );
-        assert_eq!(backend.drain_timeout(&defaults), Duration::from_secs(30));
-        assert_eq!(backend.request_timeout(&defaults), Duration::from_secs(30));
-        assert_eq!(
-            backend.ready_health_check_interval(&defaults),
-            Duration::from_millis(5000)
-        );
-        assert_eq!(backend.unhealthy_threshold(&defaults), 3);
+        let waf = |hostname: &str| config.backends[hostname].waf(&config.defaults);
+        assert_eq!(waf("app.local"), Some((5, WafMode::Log)));
+        assert_eq!(waf("admin.local"), Some((10, WafMode::Block)));
+        assert_eq!(waf("static.local"), None);
+        assert_eq!(BackendConfig::local("node", 3000).waf(&BackendDefaults::default()), None);
+
+        assert!(Config::parse("[defaults.waf]\nthreshold = 0").is_err());
     }
 
     #[test]
-    fn test_backend_config_overrides_defaults() {
-        let defaults = BackendDefaults::default();
-        let backend: BackendConfig = toml::from_str(r#"
+    fn test_response_rewrites_config() {
+        let config = Config::parse(
+            r#"
+[backends."app.local"]
 command = "node"
 port = 3000
-idle_timeout_secs = 120
-startup_timeout_secs = 60
-health_check_interval_ms = 500
-health_path = "/ready"
-shutdown_grace_period_secs = 5
-drain_timeout_secs = 15
-request_timeout_secs = 10
-ready_health_check_interval_ms = 10000
-unhealthy_threshold = 5
-"#)
-        .unwrap();
+rewrite_content_types = ["text/html", "text/css"]
 
-        assert_eq!(backend.idle_timeout(&defaults), Duration::from_secs(120));
-        assert_eq!(backend.startup_timeout(&defaults), Duration::from_secs(60));
-        assert_eq!(
-            backend.health_check_interval(&defaults),
-            Duration::from_millis(500)
-        );
-        assert_eq!(backend.health_path(&defaults), "/ready");
-        assert_eq!(
-            backend.shutdown_grace_period(&defaults),
-            Duration::from_secs(5)
-        );
-        assert_eq!(backend.drain_timeout(&defaults), Duration::from_secs(15));
-        assert_eq!(backend.request_timeout(&defaults), Duration::from_secs(10));
+[[backends."app.local".response_rewrites]]
+find = "http://localhost:3000"
+replace = "https://app.local"
+"#,
+        )
+        .unwrap();
+        let backend = &config.backends["app.local"];
+        assert_eq!(backend.response_rewrites[0].replace, "https://app.local");
+        assert_eq!(backend.rewrite_content_types, ["text/html", "text/css"]);
         assert_eq!(
-            backend.ready_health_check_interval(&defaults),
-            Duration::from_millis(10000)
+            BackendConfig::local("node", 3000).rewrite_content_types,
+            ["text/html", "application/json"]
         );
-        assert_eq!(backend.unhealthy_threshold(&defaults), 5);
+
+        let mut backend = BackendConfig::local("node", 3000);
+        backend.response_rewrites.push(ResponseRewrite {
+            find: String::new(),
+            replace: "x".to_string(),
+        });
+        assert!(backend.validate("app.local").is_err());
     }
 
     #[test]
-    fn test_minimal_config() {
+    fn test_acme_exclusions() {
         let toml = r#"
-[backends."example.com"]
+[server.acme]
+enabled = true
+domains = ["app.example.com", "cdn.example.com", "static.example.com"]
+
+[backends."app.example.com"]
 command = "node"
 port = 3000
-"#;
-        let config: Config = toml::from_str(toml).unwrap();
 
-        // Should use defaults for server
-        assert_eq!(config.server.port, 80);
-        assert_eq!(config.server.bind, "0.0.0.0");
+[backends."cdn.example.com"]
+command = "node"
+port = 3001
+acme = false
 
-        // Should use defaults for backend defaults
-        assert_eq!(config.defaults.idle_timeout_secs, 600);
+[backends."static.example.com"]
+command = "node"
+port = 3002
+tls_cert = "/etc/certs/static.pem"
+tls_key = "/etc/certs/static.key"
+"#;
+        let config = Config::parse(toml).unwrap();
 
-        // Backend should be present
-        assert!(config.backends.contains_key("example.com"));
-        let backend = config.backends.get("example.com").unwrap();
-        assert_eq!(backend.command, Some("node".to_string()));
-        assert_eq!(backend.port, 3000);
+        assert!(config.acme_enabled());
+        assert_eq!(config.acme_config().domains, vec!["app.example.com"]);
+        assert_eq!(
+            config.pinned_certs(),
+            vec![("static.example.com", "/etc/certs/static.pem", "/etc/certs/static.key")]
+        );
     }
 
     #[test]
-    fn test_backend_with_env_vars() {
+    fn test_acme_disabled_when_all_domains_excluded() {
         let toml = r#"
-command = "node"
-port = 3000
+[server.acme]
+enabled = true
+domains = ["cdn.example.com"]
 
-[env]
-NODE_ENV = "production"
-DEBUG = "true"
+[backends."cdn.example.com"]
+command = "node"
+port = 3001
+acme = false
 "#;
-        let backend: BackendConfig = toml::from_str(toml).unwrap();
-
-        assert_eq!(backend.env.len(), 2);
-        assert_eq!(backend.env.get("NODE_ENV"), Some(&"production".to_string()));
-        assert_eq!(backend.env.get("DEBUG"), Some(&"true".to_string()));
+        let config = Config::parse(toml).unwrap();
+        assert!(config.server.acme_enabled());
+        assert!(!config.acme_enabled());
     }
 
     #[test]
-    fn test_backend_with_args() {
+    fn test_pinned_cert_requires_key() {
         let toml = r#"
-command = "python"
-args = ["-m", "http.server", "8000"]
-port = 8000
+[backends."a.local"]
+command = "node"
+port = 3000
+tls_cert = "/etc/certs/a.pem"
 "#;
-        let backend: BackendConfig = toml::from_str(toml).unwrap();
+        let err = Config::parse(toml).unwrap_err();
+        assert!(err.to_string().contains("'tls_cert' and 'tls_key' must be set together"));
+    }
 
-        assert_eq!(backend.args, vec!["-m", "http.server", "8000"]);
+    #[test]
+    fn test_allowed_paths() {
+        let mut backend = BackendConfig::local("node", 3000);
+        assert!(backend.allows_path("/wp-login.php"));
+
+        backend.allowed_paths = vec!["/api".to_string(), "/static/".to_string(), "/".to_string()];
+        assert!(backend.allows_path("/"));
+
+        backend.allowed_paths = vec!["/api".to_string(), "/static/".to_string()];
+        assert!(backend.allows_path("/api"));
+        assert!(backend.allows_path("/api/users"));
+        assert!(backend.allows_path("/static/app.js"));
+        assert!(!backend.allows_path("/apiary"));
+        assert!(!backend.allows_path("/static"));
+        assert!(!backend.allows_path("/wp-login.php"));
+        assert!(!backend.allows_path("/"));
     }
 
     #[test]
-    fn test_empty_config() {
-        let toml = "";
-        let config: Config = toml::from_str(toml).unwrap();
+    fn test_https_redirect_settings() {
+        let config = Config::parse(
+            r#"
+[server]
+force_https = true
+https_redirect_status = 308
 
-        // Should use all defaults
-        assert_eq!(config.server.port, 80);
-        assert_eq!(config.defaults.idle_timeout_secs, 600);
-        assert!(config.backends.is_empty());
+[backends."app.local"]
+command = "app"
+port = 3000
+https_redirect_exclude = ["/.well-known/", "/internal"]
+
+[backends."plain.local"]
+command = "plain"
+port = 3001
+https_redirect = false
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.server.https_redirect_status, 308);
+
+        let app = &config.backends["app.local"];
+        assert!(app.redirects_to_https("/"));
+        assert!(app.redirects_to_https("/internals"));
+        assert!(!app.redirects_to_https("/internal/status"));
+        assert!(!app.redirects_to_https("/.well-known/security.txt"));
+        assert!(!config.backends["plain.local"].redirects_to_https("/"));
+
+        assert_eq!(Config::parse("").unwrap().server.https_redirect_status, 301);
+        let err = Config::parse("[server]\nhttps_redirect_status = 303").unwrap_err();
+        assert!(err.to_string().contains("https_redirect_status"));
+
+        let mut backend = BackendConfig::local("app", 3000);
+        backend.https_redirect_exclude = vec!["health".to_string()];
+        assert!(backend.validate("app.local").unwrap_err().contains("https_redirect_exclude"));
     }
 
     #[test]
-    fn test_acme_config_defaults() {
-        let toml = "";
-        let config: Config = toml::from_str(toml).unwrap();
-
-        // ACME should be disabled by default
-        assert!(!config.server.acme.enabled);
-        assert!(config.server.acme.domains.is_empty());
-        assert!(config.server.acme.email.is_none());
-        assert!(config.server.acme.directory_url.is_none());
-        assert_eq!(config.server.acme.cache_dir, "./acme_cache");
-        assert!(!config.server.acme_enabled());
+    fn test_allowed_paths_must_be_absolute() {
+        let mut backend = BackendConfig::local("node", 3000);
+        backend.allowed_paths = vec!["api".to_string()];
+        let err = backend.validate("a.local").unwrap_err();
+        assert!(err.contains("allowed_paths entry 'api' must start with '/'"));
     }
 
     #[test]
-    fn test_acme_config_enabled() {
-        let toml = r#"
-[server.acme]
+    fn test_cold_start_throttle_config() {
+        let config = Config::parse(
+            r#"
+[server.cold_start_throttle]
 enabled = true
-domains = ["example.com", "api.example.com"]
-email = "admin@example.com"
-cache_dir = "/var/lib/acme"
-"#;
-        let config: Config = toml::from_str(toml).unwrap();
-
-        assert!(config.server.acme.enabled);
-        assert_eq!(config.server.acme.domains, vec!["example.com", "api.example.com"]);
-        assert_eq!(config.server.acme.email, Some("admin@example.com".to_string()));
-        assert_eq!(config.server.acme.cache_dir, "/var/lib/acme");
-        assert!(config.server.acme_enabled());
+burst = 3
+"#,
+        )
+        .unwrap();
+        let throttle = &config.server.cold_start_throttle;
+        assert!(throttle.enabled);
+        assert_eq!(throttle.burst, 3);
+        assert_eq!(throttle.window_secs, 60);
+        assert_eq!(throttle.ban_secs, 600);
+
+        let err = Config::parse(
+            r#"
+[server.cold_start_throttle]
+enabled = true
+burst = 0
+"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("cold_start_throttle"));
     }
 
     #[test]
-    fn test_acme_config_with_staging() {
-        let toml = r#"
-[server.acme]
-enabled = true
-domains = ["test.example.com"]
-email = "test@example.com"
-directory_url = "https://acme-staging-v02.api.letsencrypt.org/directory"
-"#;
-        let config: Config = toml::from_str(toml).unwrap();
+    fn test_tls_session_config() {
+        let config = Config::parse("").unwrap();
+        let session = &config.server.tls_session;
+        assert_eq!(session.cache_size, 256);
+        assert!(!session.tickets);
+        assert_eq!(session.ticket_lifetime_secs, 43200);
+        assert_eq!(session.tickets_per_handshake, 2);
+
+        let config = Config::parse(
+            r#"
+[server.tls_session]
+tickets = true
+ticket_lifetime_secs = 3600
+ticket_key_file = "/etc/spawngate/tickets.keys"
+"#,
+        )
+        .unwrap();
+        assert!(config.server.tls_session.tickets);
+        assert_eq!(config.server.tls_session.ticket_key_file.as_deref(), Some("/etc/spawngate/tickets.keys"));
 
-        assert!(config.server.acme.enabled);
-        assert_eq!(
-            config.server.acme.directory_url,
-            Some("https://acme-staging-v02.api.letsencrypt.org/directory".to_string())
-        );
+        let err = Config::parse("[server.tls_session]\nticket_key_file = \"/tmp/keys\"").unwrap_err();
+        assert!(err.to_string().contains("requires 'tickets = true'"));
+        let err = Config::parse("[server.tls_session]\ntickets = true\nticket_lifetime_secs = 0").unwrap_err();
+        assert!(err.to_string().contains("ticket_lifetime_secs"));
     }
 
     #[test]
-    fn test_acme_enabled_requires_domains() {
-        let toml = r#"
-[server.acme]
+    fn test_early_data_config() {
+        let config = Config::parse("").unwrap();
+        let early_data = &config.server.early_data;
+        assert!(!early_data.enabled);
+        assert_eq!(early_data.max_size, 16384);
+        assert!(early_data.allows("GET"));
+        assert!(!early_data.allows("HEAD"));
+
+        let config = Config::parse("[server.early_data]
 enabled = true
-email = "admin@example.com"
-"#;
-        let config: Config = toml::from_str(toml).unwrap();
+methods = [\"get\", \"HEAD\"]").unwrap();
+        assert!(config.server.early_data.allows("GET"));
+        assert!(config.server.early_data.allows("HEAD"));
+        assert!(!config.server.early_data.allows("POST"));
 
-        // ACME enabled but no domains - acme_enabled() should return false
-        assert!(config.server.acme.enabled);
-        assert!(config.server.acme.domains.is_empty());
-        assert!(!config.server.acme_enabled());
+        let err = Config::parse("[server.early_data]
+enabled = true
+methods = [\"GET\", \"POST\"]").unwrap_err();
+        assert!(err.to_string().contains("'POST' is not safe to replay"));
+
+        let err = Config::parse("[server.tls_session]
+tickets = true
+[server.early_data]
+enabled = true").unwrap_err();
+        assert!(err.to_string().contains("tickets = false"));
     }
 
     #[test]
-    fn test_tls_enabled_includes_acme() {
-        // Without ACME
-        let toml = "";
-        let config: Config = toml::from_str(toml).unwrap();
-        assert!(!config.server.tls_enabled());
+    fn test_tls_fingerprint_config() {
+        let config = Config::parse("").unwrap();
+        let fingerprint = &config.server.tls_fingerprint;
+        assert!(!fingerprint.enabled);
+        assert!(fingerprint.forward_headers);
+        assert_eq!(fingerprint.block, FingerprintBlock::Connection);
+
+        let config = Config::parse("[server.tls_fingerprint]
+enabled = true
+block = \"cold-start\"
+blocklist = [\"11138d9933242c3a03b6aad35a296476\", \"t13d0306h2_5559582ccdc4_0d385148b956\"]").unwrap();
+        assert_eq!(config.server.tls_fingerprint.block, FingerprintBlock::ColdStart);
+        assert_eq!(config.server.tls_fingerprint.blocklist.len(), 2);
 
-        // With ACME
-        let toml = r#"
-[server.acme]
+        let err = Config::parse("[server.tls_fingerprint]
+blocklist = [\"11138d9933242c3a03b6aad35a296476\"]").unwrap_err();
+        assert!(err.to_string().contains("requires 'enabled = true'"));
+
+        let err = Config::parse("[server.tls_fingerprint]
 enabled = true
-domains = ["example.com"]
-email = "admin@example.com"
-"#;
-        let config: Config = toml::from_str(toml).unwrap();
-        assert!(config.server.tls_enabled());
-        assert!(config.server.acme_enabled());
+blocklist = [\"curl\"]").unwrap_err();
+        assert!(err.to_string().contains("neither a JA3 hash nor a JA4 fingerprint"));
     }
 
     #[test]
-    fn test_acme_challenge_type_defaults_to_http01() {
-        let toml = r#"
-[server.acme]
-enabled = true
-domains = ["example.com"]
-email = "admin@example.com"
-"#;
-        let config: Config = toml::from_str(toml).unwrap();
-        assert_eq!(config.server.acme.challenge_type, AcmeChallengeType::Http01);
+    fn test_admin_lockout_config() {
+        let config = Config::parse("").unwrap();
+        let lockout = &config.server.admin_lockout;
+        assert!(lockout.enabled);
+        assert_eq!(lockout.max_failures, 5);
+        assert_eq!(lockout.window_secs, 300);
+        assert_eq!(lockout.lockout_secs, 60);
+        assert_eq!(lockout.max_lockout_secs, 3600);
+
+        let err = Config::parse("[server.admin_lockout]\nmax_failures = 0").unwrap_err();
+        assert!(err.to_string().contains("admin_lockout"));
+        let err = Config::parse("[server.admin_lockout]\nlockout_secs = 600\nmax_lockout_secs = 300").unwrap_err();
+        assert!(err.to_string().contains("max_lockout_secs"));
+        assert!(Config::parse("[server.admin_lockout]\nenabled = false\nmax_failures = 0").is_ok());
     }
 
     #[test]
-    fn test_acme_challenge_type_tls_alpn01() {
-        let toml = r#"
-[server.acme]
+    fn test_trace_sampling_config() {
+        let config = Config::parse(
+            r#"
+[server.trace_sampling]
 enabled = true
-domains = ["example.com"]
-email = "admin@example.com"
-challenge_type = "tls-alpn-01"
-"#;
-        let config: Config = toml::from_str(toml).unwrap();
-        assert_eq!(config.server.acme.challenge_type, AcmeChallengeType::TlsAlpn01);
+default_rate = 0.001
+rates = { "5xx" = 1.0, "2xx" = 0.01 }
+
+[backends."api.local"]
+command = "node"
+port = 3000
+trace_sample_rates = { "4xx" = 0.5 }
+"#,
+        )
+        .unwrap();
+        let sampling = &config.server.trace_sampling;
+        assert!(sampling.enabled);
+        assert_eq!(sampling.force_header, "X-Debug-Trace");
+        assert_eq!(sampling.rates["5xx"], 1.0);
+        assert_eq!(sampling.default_rate, 0.001);
+        assert_eq!(config.backends["api.local"].trace_sample_rates["4xx"], 0.5);
+
+        let err = Config::parse("[server.trace_sampling]\nrates = { \"6xx\" = 1.0 }").unwrap_err();
+        assert!(err.to_string().contains("unknown status class '6xx'"));
+        let err = Config::parse("[server.trace_sampling]\ndefault_rate = 1.5").unwrap_err();
+        assert!(err.to_string().contains("default_rate"));
+        let err = Config::parse("[server.trace_sampling]\nforce_header = \"X Trace\"").unwrap_err();
+        assert!(err.to_string().contains("force_header"));
+
+        let mut backend = BackendConfig::local("app", 3000);
+        backend.trace_sample_rates.insert("5xx".to_string(), -0.1);
+        assert!(backend.validate("app.local").unwrap_err().contains("trace_sample_rates"));
     }
 
     #[test]
-    fn test_acme_challenge_type_http01_explicit() {
-        let toml = r#"
-[server.acme]
-enabled = true
-domains = ["example.com"]
-email = "admin@example.com"
-challenge_type = "http-01"
-"#;
-        let config: Config = toml::from_str(toml).unwrap();
-        assert_eq!(config.server.acme.challenge_type, AcmeChallengeType::Http01);
+    fn test_slow_request_config() {
+        let slow = Config::parse("").unwrap().server.slow_requests;
+        assert!(!slow.enabled);
+        assert_eq!(slow.threshold_ms, 1000);
+        assert_eq!(slow.window_secs, 3600);
+        assert_eq!(slow.top, 10);
+
+        let config = Config::parse("[server.slow_requests]\nenabled = true\nthreshold_ms = 250").unwrap();
+        assert_eq!(config.server.slow_requests.threshold_ms, 250);
+        let err = Config::parse("[server.slow_requests]\nenabled = true\nthreshold_ms = 0").unwrap_err();
+        assert!(err.to_string().contains("slow_requests"));
     }
 
     #[test]
-    fn test_docker_backend_config() {
-        let toml = r#"
-[backends."app.example.com"]
-type = "docker"
-image = "myapp:latest"
-port = 3000
-container_name = "myapp-container"
-network = "host"
-pull_policy = "always"
-memory = "512m"
-cpus = "1.0"
-"#;
-        let config: Config = toml::from_str(toml).unwrap();
-        let backend = config.backends.get("app.example.com").unwrap();
+    fn test_profiling_config() {
+        let profiling = Config::parse("").unwrap().server.profiling;
+        assert!(!profiling.enabled);
+        assert_eq!(profiling.max_seconds, 60);
+
+        let config = Config::parse("[server.profiling]\nenabled = true\nmax_seconds = 300").unwrap();
+        assert_eq!(config.server.profiling.max_seconds, 300);
+        let err = Config::parse("[server.profiling]\nenabled = true\nmax_seconds = 0").unwrap_err();
+        assert!(err.to_string().contains("profiling"));
+    }
 
-        assert_eq!(backend.backend_type, BackendType::Docker);
-        assert_eq!(backend.image, Some("myapp:latest".to_string()));
-        assert_eq!(backend.port, 3000);
-        assert_eq!(backend.container_name, Some("myapp-container".to_string()));
-        assert_eq!(backend.network, Some("host".to_string()));
-        assert_eq!(backend.pull_policy, PullPolicy::Always);
-        assert_eq!(backend.memory, Some("512m".to_string()));
-        assert_eq!(backend.cpus, Some("1.0".to_string()));
-        assert!(backend.command.is_none());
+    #[test]
+    fn test_cert_watch_config() {
+        let config = Config::parse("").unwrap();
+        let watch = &config.server.cert_watch;
+        assert!(watch.enabled);
+        assert_eq!(watch.interval_secs, 60);
+        assert_eq!(watch.renew_before_days, 30);
+
+        let err = Config::parse("[server.cert_watch]\ninterval_secs = 0").unwrap_err();
+        assert!(err.to_string().contains("cert_watch"));
     }
 
     #[test]
-    fn test_local_backend_is_default() {
+    fn test_routes_validation() {
         let toml = r#"
 [backends."app.local"]
-command = "node"
-args = ["server.js"]
+command = "./api"
 port = 3000
-"#;
-        let config: Config = toml::from_str(toml).unwrap();
-        let backend = config.backends.get("app.local").unwrap();
 
-        assert_eq!(backend.backend_type, BackendType::Local);
-        assert_eq!(backend.command, Some("node".to_string()));
-        assert!(backend.image.is_none());
+[[backends."app.local".routes]]
+backend = "grpc.local"
+content_type = ["application/grpc"]
+
+[[backends."app.local".routes]]
+backend = "grpc.local"
+headers = { "X-Tenant" = "acme" }
+cookies = { beta = "1" }
+
+[[backends."app.local".routes]]
+backend = "ssr.local"
+path_prefix = "/app"
+strip_prefix = true
+priority = 10
+
+[[backends."app.local".routes]]
+backend = "ssr.local"
+accept = ["text/html"]
+
+[backends."grpc.local"]
+command = "./grpc"
+port = 3001
+
+[backends."ssr.local"]
+command = "./ssr"
+port = 3002
+"#;
+        let config = Config::parse(toml).unwrap();
+        let routes = &config.backends["app.local"].routes;
+        assert_eq!(routes.len(), 4);
+        assert_eq!(routes[0].backend, "grpc.local");
+        assert_eq!(routes[0].priority, 0);
+        assert_eq!(routes[1].headers["X-Tenant"], "acme");
+        assert_eq!(routes[1].cookies["beta"], "1");
+        assert!(routes[2].strip_prefix);
+        assert!(routes[2].matches_path("/app/home"));
+        assert!(!routes[2].matches_path("/apple"));
+        assert_eq!(routes[3].accept, ["text/html"]);
+
+        let err = Config::parse(&toml.replace("backend = \"ssr.local\"", "backend = \"missing.local\"")).unwrap_err();
+        assert!(err.to_string().contains("unknown route backend 'missing.local'"));
+        let err = Config::parse(&toml.replace("accept = [\"text/html\"]", "")).unwrap_err();
+        assert!(err.to_string().contains("at least one condition"));
+        let err = Config::parse(&toml.replace("path_prefix = \"/app\"", "path_prefix = \"app\"")).unwrap_err();
+        assert!(err.to_string().contains("must start with '/'"));
+        let err = Config::parse(&toml.replace("path_prefix = \"/app\"", "accept = [\"text/plain\"]")).unwrap_err();
+        assert!(err.to_string().contains("strip_prefix needs a path_prefix"));
+        let err = Config::parse(&toml.replace("\"X-Tenant\"", "\"X Tenant\"")).unwrap_err();
+        assert!(err.to_string().contains("not a valid header name"));
     }
 
     #[test]
-    fn test_pull_policy_if_not_present() {
+    fn test_method_routes_validation() {
         let toml = r#"
-[backends."app.example.com"]
-type = "docker"
-image = "myapp:latest"
+[backends."app.local"]
+command = "./app"
 port = 3000
-pull_policy = "if-not-present"
+
+[[backends."app.local".routes]]
+backend = "replica.local"
+methods = ["GET", "HEAD"]
+read_your_writes_secs = 5
+
+[backends."replica.local"]
+command = "./app --read-only"
+port = 3001
 "#;
-        let config: Config = toml::from_str(toml).unwrap();
-        let backend = config.backends.get("app.example.com").unwrap();
-        assert_eq!(backend.pull_policy, PullPolicy::IfNotPresent);
+        let config = Config::parse(toml).unwrap();
+        let route = &config.backends["app.local"].routes[0];
+        assert_eq!(route.methods, ["GET", "HEAD"]);
+        assert_eq!(route.read_your_writes_secs, 5);
+        assert!(route.is_read_only());
+
+        let err = Config::parse(&toml.replace("\"HEAD\"", "\"head\"")).unwrap_err();
+        assert!(err.to_string().contains("must be an uppercase HTTP method"));
+        let err = Config::parse(&toml.replace("\"GET\", \"HEAD\"", "\"GET\"")).unwrap_err();
+        assert!(err.to_string().contains("GET and HEAD together"));
+        let err = Config::parse(&toml.replace("\"HEAD\"]", "\"HEAD\", \"POST\"]")).unwrap_err();
+        assert!(err.to_string().contains("methods limited to GET, HEAD, OPTIONS and TRACE"));
+
+        // Writes routed away from the backend reads fall back to
+        let writes = format!(
+            "{}\n[[backends.\"app.local\".routes]]\nbackend = \"replica.local\"\nmethods = [\"POST\"]\n",
+            toml
+        );
+        let err = Config::parse(&writes).unwrap_err();
+        assert!(err.to_string().contains("needs writes served by 'app.local'"), "{}", err);
     }
 
     #[test]
-    fn test_pull_policy_never() {
+    fn test_priority_rules() {
         let toml = r#"
-[backends."app.example.com"]
-type = "docker"
-image = "myapp:latest"
+[backends."api.local"]
+command = "./api"
 port = 3000
-pull_policy = "never"
+max_queue_depth = 10
+
+[[backends."api.local".priority_rules]]
+class = "high"
+headers = { "x-api-key" = "premium" }
+
+[[backends."api.local".priority_rules]]
+class = "low"
+path_prefix = "/jobs"
+methods = ["POST"]
 "#;
-        let config: Config = toml::from_str(toml).unwrap();
-        let backend = config.backends.get("app.example.com").unwrap();
-        assert_eq!(backend.pull_policy, PullPolicy::Never);
+        let config = Config::parse(toml).unwrap();
+        let rules = &config.backends["api.local"].priority_rules;
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].class, PriorityClass::High);
+        assert_eq!(rules[0].headers["x-api-key"], "premium");
+        assert_eq!(rules[1].class, PriorityClass::Low);
+        assert_eq!(rules[1].methods, ["POST"]);
+        assert!(rules[1].matches_path("/jobs/rebuild") && !rules[1].matches_path("/jobsite"));
+
+        let err = Config::parse(&toml.replace("path_prefix = \"/jobs\"\nmethods = [\"POST\"]\n", "")).unwrap_err();
+        assert!(err.to_string().contains("need at least one condition"), "{}", err);
+        let err = Config::parse(&toml.replace("\"/jobs\"", "\"jobs\"")).unwrap_err();
+        assert!(err.to_string().contains("must start with '/'"));
+        let err = Config::parse(&toml.replace("\"x-api-key\"", "\"x api key\"")).unwrap_err();
+        assert!(err.to_string().contains("is not a valid header name"));
+        let err = Config::parse(&toml.replace("\"POST\"", "\"post\"")).unwrap_err();
+        assert!(err.to_string().contains("must be an uppercase HTTP method"));
+        assert!(Config::parse(&toml.replace("\"high\"", "\"urgent\"")).is_err());
     }
 
     #[test]
-    fn test_backend_config_helpers() {
-        let local = BackendConfig::local("node", 3000);
-        assert_eq!(local.backend_type, BackendType::Local);
-        assert_eq!(local.command, Some("node".to_string()));
-        assert_eq!(local.port, 3000);
+    fn test_experiment_validation() {
+        let toml = r#"
+[backends."shop.local"]
+command = "./shop"
+port = 3000
 
-        let docker = BackendConfig::docker("myapp:latest", 8080);
-        assert_eq!(docker.backend_type, BackendType::Docker);
-        assert_eq!(docker.image, Some("myapp:latest".to_string()));
-        assert_eq!(docker.port, 8080);
-        assert!(docker.command.is_none());
+[backends."shop.local".experiment]
+name = "checkout-redesign"
+key_cookie = "session_id"
+variants = [
+    { name = "control", weight = 90 },
+    { name = "redesign", backend = "shop-b.local", weight = 10 },
+]
+
+[backends."shop-b.local"]
+command = "./shop --redesign"
+port = 3001
+"#;
+        let config = Config::parse(toml).unwrap();
+        let experiment = config.backends["shop.local"].experiment.as_ref().unwrap();
+        assert_eq!(experiment.key_cookie.as_deref(), Some("session_id"));
+        assert_eq!(experiment.cookie_max_age_secs, 30 * 24 * 60 * 60);
+        assert_eq!(experiment.variants[0].backend, None);
+        assert_eq!(experiment.variants[1].weight, 10);
+
+        let err = Config::parse(&toml.replace("backend = \"shop-b.local\"", "backend = \"missing.local\"")).unwrap_err();
+        assert!(err.to_string().contains("unknown experiment backend 'missing.local'"));
+        let err = Config::parse(&toml.replace("name = \"checkout-redesign\"", "name = \"checkout redesign\"")).unwrap_err();
+        assert!(err.to_string().contains("may only contain"));
+        let err = Config::parse(&toml.replace("\"redesign\"", "\"control\"")).unwrap_err();
+        assert!(err.to_string().contains("duplicate experiment variant 'control'"));
+        let err = Config::parse(&toml.replace("weight = 90", "weight = 0").replace("weight = 10", "weight = 0")).unwrap_err();
+        assert!(err.to_string().contains("non-zero weight"));
+        let err = Config::parse(&toml.replace("    { name = \"control\", weight = 90 },\n", "")).unwrap_err();
+        assert!(err.to_string().contains("at least two variants"));
+
+        let twice = format!(
+            "{}\n[backends.\"shop-b.local\".experiment]\nname = \"checkout-redesign\"\nvariants = [{{ name = \"a\" }}, {{ name = \"b\" }}]\n",
+            toml
+        );
+        let err = Config::parse(&twice).unwrap_err();
+        assert!(err.to_string().contains("experiment name 'checkout-redesign' is also used by"), "{}", err);
     }
 
     #[test]
-    fn test_mixed_backend_types() {
-        let toml = r#"
-[backends."local.app"]
-command = "node"
+    fn test_fallback_validation() {
+        let config = Config::parse(
+            r#"
+[backends."app.local"]
+command = "./app"
 port = 3000
+fallback = "maintenance.local"
 
-[backends."docker.app"]
-type = "docker"
-image = "nginx:latest"
-port = 8080
-"#;
-        let config: Config = toml::from_str(toml).unwrap();
+[backends."maintenance.local"]
+command = "./maintenance"
+port = 3001
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.backends["app.local"].fallback.as_deref(), Some("maintenance.local"));
 
-        let local = config.backends.get("local.app").unwrap();
-        assert_eq!(local.backend_type, BackendType::Local);
+        let err = Config::parse(
+            r#"
+[backends."app.local"]
+command = "./app"
+port = 3000
+fallback = "missing.local"
+"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("unknown fallback backend 'missing.local'"));
 
-        let docker = config.backends.get("docker.app").unwrap();
-        assert_eq!(docker.backend_type, BackendType::Docker);
+        let err = Config::parse(
+            r#"
+[backends."app.local"]
+command = "./app"
+port = 3000
+fallback = "app.local"
+"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("cannot refer to itself"));
     }
 
     #[test]
-    fn test_validate_docker_requires_image() {
-        let toml = r#"
-[backends."app.example.com"]
-type = "docker"
+    fn test_backend_protocol_validation() {
+        let config = Config::parse(
+            r#"
+[backends."grpc.local"]
+command = "./grpc"
 port = 3000
-"#;
-        let config: Config = toml::from_str(toml).unwrap();
-        let result = config.validate();
-        assert!(result.is_err());
-        let err = result.unwrap_err().to_string();
-        assert!(err.contains("Docker backend requires 'image' field"));
-        assert!(err.contains("app.example.com"));
+backend_http2 = true
+"#,
+        )
+        .unwrap();
+        assert!(config.backends["grpc.local"].backend_http2);
+        assert!(!config.backends["grpc.local"].backend_tls);
+
+        let err = Config::parse(
+            r#"
+[backends."grpc.local"]
+command = "./grpc"
+port = 3000
+backend_http2 = true
+backend_tls = true
+"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("TLS backends negotiate HTTP/2 via ALPN"), "{}", err);
     }
 
     #[test]
-    fn test_validate_local_requires_command() {
-        let toml = r#"
+    fn test_readiness_gates() {
+        let config = Config::parse(
+            r#"
 [backends."app.local"]
+command = "./app"
 port = 3000
-"#;
-        let config: Config = toml::from_str(toml).unwrap();
-        let result = config.validate();
-        assert!(result.is_err());
-        let err = result.unwrap_err().to_string();
-        assert!(err.contains("local backend requires 'command' field"));
-        assert!(err.contains("app.local"));
+
+[backends."app.local".readiness]
+depends_on = ["db.local"]
+marker_file = "/srv/app/.migrated"
+manual_approval = true
+
+[backends."db.local"]
+command = "./db"
+port = 5432
+"#,
+        )
+        .unwrap();
+        let gates = &config.backends["app.local"].readiness;
+        assert_eq!(gates.depends_on, vec!["db.local".to_string()]);
+        assert_eq!(gates.marker_file.as_deref(), Some("/srv/app/.migrated"));
+        assert!(gates.manual_approval);
+        assert!(config.backends["db.local"].readiness.is_empty());
+
+        let err = Config::parse(
+            r#"
+[backends."a.local"]
+command = "./a"
+port = 3000
+readiness = { depends_on = ["b.local"] }
+
+[backends."b.local"]
+command = "./b"
+port = 3001
+readiness = { depends_on = ["a.local", "c.local"] }
+"#,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("unknown dependency 'c.local'"));
+        assert!(err.contains("Backend 'a.local': readiness depends_on forms a cycle"));
     }
 
     #[test]
-    fn test_validate_port_nonzero() {
-        let toml = r#"
-[backends."app.example.com"]
-command = "node"
-port = 0
-"#;
-        let config: Config = toml::from_str(toml).unwrap();
-        let result = config.validate();
-        assert!(result.is_err());
-        let err = result.unwrap_err().to_string();
-        assert!(err.contains("'port' must be greater than 0"));
+    fn test_fault_config() {
+        let config = Config::parse(
+            r#"
+[backends."app.local"]
+command = "./app"
+port = 3000
+faults = { error_rate = 0.1, latency_ms = 200, drop_rate = 0.05 }
+"#,
+        )
+        .unwrap();
+        let faults = config.backends["app.local"].faults.as_ref().unwrap();
+        assert_eq!(faults.error_rate, 0.1);
+        assert_eq!(faults.error_status, 500);
+        assert_eq!(faults.latency_ms, 200);
+        assert_eq!(faults.latency_rate, 1.0);
+        assert_eq!(faults.drop_rate, 0.05);
+        assert_eq!(faults.spawn_failure_rate, 0.0);
+        assert_eq!(faults.duration_secs, 300);
+
+        let err = Config::parse(
+            r#"
+[backends."app.local"]
+command = "./app"
+port = 3000
+faults = { error_rate = 1.5 }
+"#,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("Backend 'app.local': faults: 'error_rate' must be between 0.0 and 1.0"));
+
+        let err = Config::parse(
+            r#"
+[backends."app.local"]
+command = "./app"
+port = 3000
+faults = { error_status = 200 }
+"#,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("'error_status' must be between 400 and 599"));
     }
 
     #[test]
-    fn test_validate_multiple_errors() {
-        let toml = r#"
-[backends."local.app"]
+    fn test_quota_config() {
+        let config = Config::parse(
+            r#"
+[backends."app.local"]
+command = "./app"
 port = 3000
-
-[backends."docker.app"]
-type = "docker"
-port = 8080
-"#;
-        let config: Config = toml::from_str(toml).unwrap();
-        let result = config.validate();
-        assert!(result.is_err());
-        let err = result.unwrap_err().to_string();
-        // Should report both errors
-        assert!(err.contains("local backend requires 'command' field"));
-        assert!(err.contains("Docker backend requires 'image' field"));
+quota = { window = "day", max_bytes = 1073741824, on_exceed = "throttle" }
+"#,
+        )
+        .unwrap();
+        let quota = config.backends["app.local"].quota.as_ref().unwrap();
+        assert_eq!(quota.window, QuotaWindow::Day);
+        assert_eq!(quota.window.duration(), Duration::from_secs(86400));
+        assert_eq!(quota.max_requests, None);
+        assert_eq!(quota.max_bytes, Some(1073741824));
+        assert_eq!(quota.on_exceed, QuotaAction::Throttle);
+        assert_eq!(quota.throttle_bytes_per_sec, 65536);
+
+        for (quota, message) in [
+            ("{ window = \"hour\" }", "'max_requests' or 'max_bytes' is required"),
+            ("{ max_requests = 0 }", "'max_requests' and 'max_bytes' must be greater than 0"),
+            ("{ max_requests = 10, throttle_bytes_per_sec = 0 }", "'throttle_bytes_per_sec' must be greater than 0"),
+        ] {
+            let toml = format!("[backends.\"app.local\"]\ncommand = \"./app\"\nport = 3000\nquota = {}\n", quota);
+            let err = Config::parse(&toml).unwrap_err().to_string();
+            assert!(err.contains(&format!("Backend 'app.local': quota: {}", message)), "{}", err);
+        }
     }
 
     #[test]
-    fn test_validate_valid_configs() {
-        let local = BackendConfig::local("node", 3000);
-        assert!(local.validate("test.local").is_ok());
+    fn test_labels_and_export_selector() {
+        let config = Config::parse(
+            r#"
+[server.metrics_export]
+enabled = true
+selector = "env=prod"
 
-        let docker = BackendConfig::docker("nginx:latest", 8080);
-        assert!(docker.validate("test.docker").is_ok());
+[backends."app.local"]
+command = "./app"
+port = 3000
+labels = { env = "prod", team = "core" }
+"#,
+        )
+        .unwrap();
+        let backend = &config.backends["app.local"];
+        assert_eq!(backend.labels["env"], "prod");
+        assert!(config.server.metrics_export.selector.as_ref().unwrap().matches(&backend.labels));
+
+        let err = Config::parse(
+            r#"
+[server.metrics_export]
+selector = "env in prod"
+"#,
+        )
+        .unwrap_err();
+        assert!(format!("{:#}", err).contains("invalid selector"));
+    }
+    #[test]
+    fn test_shutdown_config() {
+        let config = Config::parse(
+            r#"
+[server.shutdown]
+state_file = "/var/lib/spawngate/state.json"
+
+[[server.shutdown.hooks]]
+command = "/usr/local/bin/lb-deregister"
+args = ["--pool", "web"]
+
+[[server.shutdown.hooks]]
+command = "notify"
+timeout_secs = 3
+"#,
+        )
+        .unwrap();
+        let shutdown = &config.server.shutdown;
+        assert_eq!(shutdown.state_file.as_deref(), Some("/var/lib/spawngate/state.json"));
+        assert_eq!(shutdown.state_interval_secs, 30);
+        assert_eq!(shutdown.hooks.len(), 2);
+        assert_eq!(shutdown.hooks[0].args, vec!["--pool", "web"]);
+        assert_eq!(shutdown.hooks[0].timeout_secs, 10);
+        assert_eq!(shutdown.hooks[1].timeout_secs, 3);
+
+        let err = Config::parse(
+            r#"
+[[server.shutdown.hooks]]
+command = "notify"
+timeout_secs = 0
+"#,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("'timeout_secs' of hook 'notify' must be greater than 0"));
+    }
+
+    #[test]
+    fn test_panic_config() {
+        let config = Config::parse(
+            r#"
+[server.panic]
+abort = true
+dump_dir = "/var/lib/spawngate/crash-dumps"
+"#,
+        )
+        .unwrap();
+        assert!(config.server.panic.abort);
+        assert_eq!(config.server.panic.dump_dir.as_deref(), Some("/var/lib/spawngate/crash-dumps"));
+        assert!(config.server.panic.crash_count_file.is_none());
+        assert!(!ServerConfig::default().panic.abort);
     }
 }
@@ -0,0 +1,187 @@
+//! Config vars: per-backend environment variables managed through the admin API
+//!
+//! Vars are layered over a backend's `env` table the next time it spawns
+//! (the variables the proxy injects still win). With `config_vars_file` set
+//! they are saved to that JSON file, so they survive restarts and reloads.
+
+use parking_lot::{Mutex, RwLock};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Vars of one backend, sorted by name
+pub type Vars = BTreeMap<String, String>;
+
+/// Why a change to config vars was not applied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigVarsError {
+    /// A name or value can't be used in an environment
+    Invalid(String),
+    /// The vars file could not be written; nothing was changed
+    Save(String),
+}
+
+impl fmt::Display for ConfigVarsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigVarsError::Invalid(e) | ConfigVarsError::Save(e) => f.write_str(e),
+        }
+    }
+}
+
+/// Config vars for every backend
+#[derive(Debug, Default)]
+pub struct ConfigVars {
+    /// File the vars are saved to, if any
+    path: Mutex<Option<PathBuf>>,
+    vars: RwLock<HashMap<String, Vars>>,
+}
+
+impl ConfigVars {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Save vars to `path` from now on, loading the vars already saved there
+    pub fn persist_to(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let saved: HashMap<String, Vars> = match std::fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| anyhow::anyhow!("Invalid config vars file '{}': {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => anyhow::bail!("Failed to read config vars file '{}': {}", path.display(), e),
+        };
+        *self.vars.write() = saved;
+        *self.path.lock() = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    /// Vars set for a backend
+    pub fn get(&self, hostname: &str) -> Vars {
+        self.vars.read().get(hostname).cloned().unwrap_or_default()
+    }
+
+    /// Set vars, removing those whose value is `None`
+    ///
+    /// Returns the backend's vars afterwards and whether anything changed.
+    pub fn update(
+        &self,
+        hostname: &str,
+        changes: HashMap<String, Option<String>>,
+    ) -> Result<(Vars, bool), ConfigVarsError> {
+        for (name, value) in &changes {
+            validate(name, value.as_deref()).map_err(ConfigVarsError::Invalid)?;
+        }
+
+        let mut all = self.vars.write();
+        let mut vars = all.get(hostname).cloned().unwrap_or_default();
+        let before = vars.clone();
+        for (name, value) in changes {
+            match value {
+                Some(value) => vars.insert(name, value),
+                None => vars.remove(&name),
+            };
+        }
+
+        if vars == before {
+            return Ok((vars, false));
+        }
+        let mut updated = all.clone();
+        if vars.is_empty() {
+            updated.remove(hostname);
+        } else {
+            updated.insert(hostname.to_string(), vars.clone());
+        }
+        self.save(&updated)?;
+        *all = updated;
+        Ok((vars, true))
+    }
+
+    /// Remove all of a backend's vars, returns whether it had any
+    pub fn clear(&self, hostname: &str) -> Result<bool, ConfigVarsError> {
+        let mut all = self.vars.write();
+        if !all.contains_key(hostname) {
+            return Ok(false);
+        }
+        let mut updated = all.clone();
+        updated.remove(hostname);
+        self.save(&updated)?;
+        *all = updated;
+        Ok(true)
+    }
+
+    /// Write the vars through a temporary file so a crash can't truncate them
+    fn save(&self, vars: &HashMap<String, Vars>) -> Result<(), ConfigVarsError> {
+        let Some(path) = self.path.lock().clone() else {
+            return Ok(());
+        };
+        let tmp = path.with_extension("tmp");
+        serde_json::to_string_pretty(vars)
+            .map_err(std::io::Error::other)
+            .and_then(|json| std::fs::write(&tmp, json))
+            .and_then(|_| std::fs::rename(&tmp, &path))
+            .map_err(|e| ConfigVarsError::Save(format!("Failed to save config vars to '{}': {}", path.display(), e)))
+    }
+}
+
+/// Names must be usable as environment variable names
+fn validate(name: &str, value: Option<&str>) -> Result<(), String> {
+    if name.is_empty() || name.contains(['=', '\0']) {
+        return Err(format!("Invalid config var name '{}'", name.escape_debug()));
+    }
+    if value.is_some_and(|v| v.contains('\0')) {
+        return Err(format!("Config var '{}' contains a NUL byte", name));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn changes(pairs: &[(&str, Option<&str>)]) -> HashMap<String, Option<String>> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.map(str::to_string)))
+            .collect()
+    }
+
+    #[test]
+    fn test_update_and_clear() {
+        let vars = ConfigVars::new();
+        let (set, changed) = vars
+            .update("app.local", changes(&[("A", Some("1")), ("B", Some("2"))]))
+            .unwrap();
+        assert!(changed);
+        assert_eq!(set.len(), 2);
+
+        let (set, changed) = vars
+            .update("app.local", changes(&[("A", Some("1")), ("B", None)]))
+            .unwrap();
+        assert!(changed);
+        assert_eq!(set, Vars::from([("A".to_string(), "1".to_string())]));
+
+        let (_, changed) = vars.update("app.local", changes(&[("A", Some("1"))])).unwrap();
+        assert!(!changed);
+
+        assert!(vars.update("app.local", changes(&[("A=B", Some("1"))])).is_err());
+        assert!(vars.clear("app.local").unwrap());
+        assert!(!vars.clear("app.local").unwrap());
+        assert!(vars.get("app.local").is_empty());
+    }
+
+    #[test]
+    fn test_persisted_vars_survive_reload() {
+        let path = std::env::temp_dir().join(format!("spawngate-vars-{}.json", uuid::Uuid::new_v4()));
+
+        let vars = ConfigVars::new();
+        vars.persist_to(&path).unwrap();
+        vars.update("app.local", changes(&[("SECRET", Some("s3cret"))])).unwrap();
+
+        let reloaded = ConfigVars::new();
+        reloaded.persist_to(&path).unwrap();
+        assert_eq!(reloaded.get("app.local").get("SECRET").map(String::as_str), Some("s3cret"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,180 @@
+//! Panic handling
+//!
+//! [`install`] replaces the default panic hook with one that logs the panic
+//! as a structured event with its backtrace, then, if configured:
+//!
+//! - increments the crash counter in `crash_count_file`, so crashes are
+//!   counted across restarts;
+//! - writes a crash dump to `dump_dir` (`crash-<unix time>-<pid>.json`) with
+//!   the message, location, thread, version and backtrace;
+//! - aborts the process. Without `abort`, a panicking task fails on its own
+//!   and the proxy keeps serving; a panic on the main thread still exits.
+//!
+//! The hook is global to the process, so only the binary installs it;
+//! applications embedding the proxy keep their own.
+
+use crate::admin::VERSION;
+use crate::config::PanicConfig;
+use serde::Serialize;
+use std::backtrace::Backtrace;
+use std::panic::PanicHookInfo;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{error, info, warn};
+
+/// What is known about a panic
+#[derive(Debug, Clone, Serialize)]
+pub struct CrashReport {
+    /// Unix time in seconds
+    pub timestamp: u64,
+    pub pid: u32,
+    pub version: &'static str,
+    pub thread: String,
+    pub message: String,
+    /// `file:line:column` of the panic
+    pub location: Option<String>,
+    pub backtrace: String,
+}
+
+impl CrashReport {
+    pub fn new(message: String, location: Option<String>, backtrace: String) -> Self {
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            pid: std::process::id(),
+            version: VERSION,
+            thread: std::thread::current().name().unwrap_or("<unnamed>").to_string(),
+            message,
+            location,
+            backtrace,
+        }
+    }
+
+    /// Report for the panic being handled, with a backtrace of this thread
+    pub fn capture(info: &PanicHookInfo<'_>) -> Self {
+        let payload = info.payload();
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "Box<dyn Any>".to_string());
+        let location = info
+            .location()
+            .map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column()));
+        Self::new(message, location, Backtrace::force_capture().to_string())
+    }
+
+    /// Write the report to a new file in `dir`, returning its path
+    pub fn write_dump(&self, dir: &Path) -> std::io::Result<PathBuf> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!("crash-{}-{}.json", self.timestamp, self.pid));
+        let json = serde_json::to_vec_pretty(self).map_err(std::io::Error::other)?;
+        std::fs::write(&path, json)?;
+        Ok(path)
+    }
+}
+
+/// Crashes counted in `path`, 0 if it doesn't exist yet
+pub fn crash_count(path: &Path) -> u64 {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|count| count.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Add a crash to the count in `path`, returning the new count
+pub fn record_crash(path: &Path) -> std::io::Result<u64> {
+    let count = crash_count(path) + 1;
+    std::fs::write(path, format!("{}\n", count))?;
+    Ok(count)
+}
+
+/// Replace the panic hook with one applying `config`
+pub fn install(config: &PanicConfig) {
+    let config = config.clone();
+    if let Some(path) = &config.crash_count_file {
+        let count = crash_count(Path::new(path));
+        if count > 0 {
+            warn!(crashes = count, path, "Previous runs panicked");
+        }
+    }
+    info!(abort = config.abort, "Panic handler installed");
+
+    std::panic::set_hook(Box::new(move |info| {
+        let report = CrashReport::capture(info);
+        error!(
+            thread = %report.thread,
+            location = report.location.as_deref().unwrap_or("unknown"),
+            message = %report.message,
+            backtrace = %report.backtrace,
+            "Panic"
+        );
+
+        if let Some(path) = &config.crash_count_file {
+            match record_crash(Path::new(path)) {
+                Ok(count) => error!(crashes = count, "Crash counted"),
+                Err(e) => error!(path, error = %e, "Failed to update crash count"),
+            }
+        }
+        if let Some(dir) = &config.dump_dir {
+            match report.write_dump(Path::new(dir)) {
+                Ok(path) => error!(path = %path.display(), "Crash dump written"),
+                Err(e) => error!(dir, error = %e, "Failed to write crash dump"),
+            }
+        }
+        if config.abort {
+            error!("Aborting after panic");
+            std::process::abort();
+        }
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("spawngate-crash-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_crash_count_persists() {
+        let dir = temp_dir("count");
+        let path = dir.join("crashes");
+        assert_eq!(crash_count(&path), 0);
+        assert_eq!(record_crash(&path).unwrap(), 1);
+        assert_eq!(record_crash(&path).unwrap(), 2);
+        assert_eq!(crash_count(&path), 2);
+
+        std::fs::write(&path, "garbage").unwrap();
+        assert_eq!(record_crash(&path).unwrap(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_crash_dump() {
+        let dir = temp_dir("dump");
+        let report = CrashReport::new(
+            "index out of bounds".to_string(),
+            Some("src/proxy.rs:10:5".to_string()),
+            "0: spawngate::proxy::forward".to_string(),
+        );
+        let path = report.write_dump(&dir.join("dumps")).unwrap();
+        assert_eq!(
+            path.file_name().unwrap().to_str().unwrap(),
+            format!("crash-{}-{}.json", report.timestamp, report.pid)
+        );
+
+        let dump: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(dump["message"], "index out of bounds");
+        assert_eq!(dump["location"], "src/proxy.rs:10:5");
+        assert_eq!(dump["version"], VERSION);
+        assert_eq!(dump["backtrace"], "0: spawngate::proxy::forward");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,221 @@
+//! User and group of local process backends
+//!
+//! Spawngate often runs as root to bind ports 80 and 443. Local backends
+//! with `user` or `group` drop those privileges before they exec: the names
+//! are looked up before spawning, and the child only makes the `setgroups`,
+//! `setgid` and `setuid` calls, in that order, so it can't regain root.
+
+use std::ffi::{CStr, CString};
+use std::io;
+
+/// Identity a backend process runs as
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+    /// Supplementary groups: the user's groups from the group database, or
+    /// only `gid` when the group is set explicitly
+    pub groups: Vec<libc::gid_t>,
+    /// User name and home directory, if the user has a passwd entry
+    pub name: Option<String>,
+    pub home: Option<String>,
+}
+
+/// A passwd entry
+struct Passwd {
+    name: String,
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+    home: String,
+}
+
+impl Credentials {
+    /// Look up `user` and `group`, each a name or numeric ID
+    ///
+    /// Without a user, the proxy's own user is kept and only the group changes.
+    /// A numeric user without a passwd entry needs an explicit group.
+    pub fn resolve(user: Option<&str>, group: Option<&str>) -> anyhow::Result<Self> {
+        // Err holds a uid without a passwd entry
+        let passwd = match user.map(|user| (user, user.parse::<libc::uid_t>())) {
+            Some((user, Err(_))) => {
+                Ok(lookup_user(user)?.ok_or_else(|| anyhow::anyhow!("Unknown user '{}'", user))?)
+            }
+            Some((_, Ok(uid))) => lookup_uid(uid)?.ok_or(uid),
+            None => {
+                let uid = unsafe { libc::getuid() };
+                lookup_uid(uid)?.ok_or(uid)
+            }
+        };
+
+        let gid = match group {
+            Some(group) => Some(match group.parse::<libc::gid_t>() {
+                Ok(gid) => gid,
+                Err(_) => lookup_group(group)?.ok_or_else(|| anyhow::anyhow!("Unknown group '{}'", group))?,
+            }),
+            None => None,
+        };
+
+        match passwd {
+            Ok(passwd) => {
+                let gid = gid.unwrap_or(passwd.gid);
+                let groups = match (group, user) {
+                    (None, Some(_)) => group_list(&passwd.name, gid)?,
+                    _ => vec![gid],
+                };
+                Ok(Self {
+                    uid: passwd.uid,
+                    gid,
+                    groups,
+                    name: Some(passwd.name),
+                    home: Some(passwd.home),
+                })
+            }
+            Err(uid) => {
+                let gid = gid.ok_or_else(|| anyhow::anyhow!("User {} has no passwd entry; set 'group' as well", uid))?;
+                Ok(Self {
+                    uid,
+                    gid,
+                    groups: vec![gid],
+                    name: None,
+                    home: None,
+                })
+            }
+        }
+    }
+
+    /// Switch the calling process to these credentials
+    ///
+    /// Only makes system calls, so it is safe to run between fork and exec.
+    pub fn apply(&self) -> io::Result<()> {
+        unsafe {
+            if libc::setgroups(self.groups.len() as _, self.groups.as_ptr()) != 0
+                || libc::setgid(self.gid) != 0
+                || libc::setuid(self.uid) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Call a reentrant `get*_r` lookup, growing the buffer until the entry fits
+///
+/// `call` returns the error number and the entry, which it must copy out of
+/// the buffer.
+fn lookup<T>(mut call: impl FnMut(&mut [libc::c_char]) -> (libc::c_int, Option<T>)) -> io::Result<Option<T>> {
+    let mut buf = vec![0 as libc::c_char; 1024];
+    loop {
+        match call(&mut buf) {
+            (0, entry) => return Ok(entry),
+            (libc::ERANGE, _) if buf.len() < 1 << 20 => buf.resize(buf.len() * 2, 0),
+            (errno, _) => return Err(io::Error::from_raw_os_error(errno)),
+        }
+    }
+}
+
+fn lookup_user(name: &str) -> io::Result<Option<Passwd>> {
+    let name = CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    lookup_passwd(|pwd, buf, result| unsafe {
+        libc::getpwnam_r(name.as_ptr(), pwd, buf.as_mut_ptr(), buf.len(), result)
+    })
+}
+
+fn lookup_uid(uid: libc::uid_t) -> io::Result<Option<Passwd>> {
+    lookup_passwd(|pwd, buf, result| unsafe { libc::getpwuid_r(uid, pwd, buf.as_mut_ptr(), buf.len(), result) })
+}
+
+fn lookup_passwd(
+    get: impl Fn(*mut libc::passwd, &mut [libc::c_char], *mut *mut libc::passwd) -> libc::c_int,
+) -> io::Result<Option<Passwd>> {
+    lookup(|buf| {
+        let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let errno = get(&mut pwd, buf, &mut result);
+        let entry = (!result.is_null()).then(|| unsafe {
+            Passwd {
+                name: CStr::from_ptr(pwd.pw_name).to_string_lossy().into_owned(),
+                uid: pwd.pw_uid,
+                gid: pwd.pw_gid,
+                home: CStr::from_ptr(pwd.pw_dir).to_string_lossy().into_owned(),
+            }
+        });
+        (errno, entry)
+    })
+}
+
+fn lookup_group(name: &str) -> io::Result<Option<libc::gid_t>> {
+    let name = CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    lookup(|buf| {
+        let mut group: libc::group = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let errno = unsafe { libc::getgrnam_r(name.as_ptr(), &mut group, buf.as_mut_ptr(), buf.len(), &mut result) };
+        (errno, (!result.is_null()).then_some(group.gr_gid))
+    })
+}
+
+/// Groups `user` is a member of, starting with `gid`
+#[cfg(target_os = "linux")]
+fn group_list(user: &str, gid: libc::gid_t) -> io::Result<Vec<libc::gid_t>> {
+    let name = CString::new(user).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut groups: Vec<libc::gid_t> = vec![0; 64];
+    loop {
+        let mut count = groups.len() as libc::c_int;
+        if unsafe { libc::getgrouplist(name.as_ptr(), gid, groups.as_mut_ptr(), &mut count) } >= 0 {
+            groups.truncate(count as usize);
+            return Ok(groups);
+        }
+        // `count` now holds the number of groups
+        groups.resize((count as usize).max(groups.len() * 2), 0);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn group_list(_user: &str, gid: libc::gid_t) -> io::Result<Vec<libc::gid_t>> {
+    Ok(vec![gid])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_credentials() {
+        let root = Credentials::resolve(Some("root"), None).unwrap();
+        assert_eq!((root.uid, root.gid), (0, 0));
+        assert_eq!(root.name.as_deref(), Some("root"));
+        assert!(root.groups.contains(&0));
+        assert_eq!(Credentials::resolve(Some("0"), None).unwrap().uid, 0);
+
+        let explicit = Credentials::resolve(Some("root"), Some("12345")).unwrap();
+        assert_eq!((explicit.gid, explicit.groups), (12345, vec![12345]));
+
+        // IDs without database entries
+        let numeric = Credentials::resolve(Some("54321"), Some("54321")).unwrap();
+        assert_eq!((numeric.uid, numeric.gid, numeric.home), (54321, 54321, None));
+        let err = Credentials::resolve(Some("54321"), None).unwrap_err();
+        assert!(err.to_string().contains("set 'group' as well"));
+
+        let err = Credentials::resolve(Some("no-such-user-spawngate"), None).unwrap_err();
+        assert!(err.to_string().contains("Unknown user"));
+        let err = Credentials::resolve(None, Some("no-such-group-spawngate")).unwrap_err();
+        assert!(err.to_string().contains("Unknown group"));
+    }
+
+    /// Runs `id` with dropped privileges, when the tests run as root
+    #[tokio::test]
+    async fn test_apply_credentials() {
+        if unsafe { libc::geteuid() } != 0 {
+            eprintln!("Skipping test: not running as root");
+            return;
+        }
+        let credentials = Credentials::resolve(Some("12345"), Some("23456")).unwrap();
+        let mut cmd = tokio::process::Command::new("id");
+        unsafe {
+            cmd.pre_exec(move || credentials.apply());
+        }
+        let output = cmd.output().await.unwrap();
+        let id = String::from_utf8_lossy(&output.stdout);
+        assert!(id.starts_with("uid=12345 gid=23456 groups=23456"), "{}", id);
+    }
+}
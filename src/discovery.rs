@@ -0,0 +1,506 @@
+//! Backends from discovery files and key-value stores
+//!
+//! Config-management runs and other automation can define backends without
+//! editing the main config, in either of two sources.
+//!
+//! **Files** in `[server.file_sd] dir`: every `*.json`, `*.toml`, `*.yaml` or
+//! `*.yml` file maps hostnames to backends, with the same settings as `[backends.*]`:
+//!
+//! ```json
+//! {"api.example.com": {"command": "./api", "port": 3000, "profile": "web"}}
+//! ```
+//!
+//! Other files, e.g. the temporary files of an atomic write, are ignored.
+//!
+//! **Keys** under `[server.kv] prefix` in Consul's KV store or etcd: the key
+//! `spawngate/backends/api.example.com` holds the backend for
+//! `api.example.com` as JSON. Keys below a further `/` are ignored.
+//!
+//! Both may use the profiles of the main config. They are read every
+//! `interval_secs`, and a change is applied like a reload: new backends are
+//! added, those whose file or key is gone are stopped.
+//!
+//! A file or key that doesn't parse or validate is logged and its last good
+//! backends stay. A hostname of the main config can't be taken over, and of
+//! two sources defining a hostname, files come before keys and the first by
+//! name wins.
+
+use crate::config::{BackendConfig, Config, KvConfig, KvStore};
+use crate::hashicorp::{ApiClient, ConsulRegistrar};
+use crate::process::ProcessManager;
+use base64::Engine;
+use hyper::{Method, StatusCode};
+use parking_lot::{Mutex, RwLock};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{debug, error, info, warn};
+
+/// Backends read from one file or key
+struct Source {
+    /// Content the backends were last read from, valid or not
+    content: String,
+    backends: HashMap<String, BackendConfig>,
+}
+
+/// Merges the discovered backends into the configuration
+pub struct Discovery {
+    /// Main configuration, with profiles resolved
+    base: RwLock<Config>,
+    /// Sources by name, `file:<path>` or `kv:<key>`
+    sources: Mutex<BTreeMap<String, Source>>,
+}
+
+impl Discovery {
+    pub fn new(base: &Config) -> Self {
+        Self {
+            base: RwLock::new(base.clone()),
+            sources: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Replace the main configuration, e.g. after a reload
+    pub fn set_base(&self, base: &Config) {
+        *self.base.write() = base.clone();
+    }
+
+    /// Replace the sources named `kind:*` with `contents`, by name, reading
+    /// the ones whose content changed with `parse`
+    ///
+    /// Returns whether any backends were added, changed or removed.
+    fn update<F>(&self, kind: &str, contents: BTreeMap<String, String>, parse: F) -> bool
+    where
+        F: Fn(&str, &str) -> anyhow::Result<HashMap<String, BackendConfig>>,
+    {
+        let base = self.base.read().clone();
+        let mut sources = self.sources.lock();
+        let before = sources.len();
+        sources.retain(|name, _| !name.starts_with(kind) || contents.contains_key(name));
+        let mut changed = sources.len() != before;
+
+        for (name, content) in contents {
+            if sources.get(&name).is_some_and(|source| source.content == content) {
+                continue;
+            }
+
+            let backends = parse(&name, &content).and_then(|backends| {
+                let mut config = base.clone();
+                config.backends = backends;
+                config.resolve_profiles()?;
+                config.validate()?;
+                Ok(config.backends)
+            });
+            match backends {
+                Ok(backends) => {
+                    debug!(source = %name, backends = backends.len(), "Read discovered backends");
+                    sources.insert(name, Source { content, backends });
+                    changed = true;
+                }
+                Err(e) => {
+                    error!(source = %name, error = %e, "Invalid discovered backends; keeping the last good ones");
+                    // Remember the content so the error is logged once per change
+                    sources
+                        .entry(name)
+                        .and_modify(|source| source.content = content.clone())
+                        .or_insert(Source {
+                            content,
+                            backends: HashMap::new(),
+                        });
+                }
+            }
+        }
+        changed
+    }
+
+    /// The main configuration with the discovered backends added
+    pub fn merge(&self, base: &Config) -> anyhow::Result<Config> {
+        let mut config = base.clone();
+        for (name, source) in self.sources.lock().iter() {
+            for (hostname, backend) in &source.backends {
+                if config.backends.contains_key(hostname) {
+                    warn!(hostname, source = %name, "Backend is already defined; ignoring discovered one");
+                    continue;
+                }
+                config.backends.insert(hostname.clone(), backend.clone());
+            }
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Call `poll` every `interval` and apply the backends whenever it
+    /// reports a change, until shutdown
+    pub async fn watch<F, Fut>(
+        &self,
+        manager: &ProcessManager,
+        consul: Option<&ConsulRegistrar>,
+        interval: Duration,
+        mut shutdown_rx: watch::Receiver<bool>,
+        poll: F,
+    ) where
+        F: Fn() -> Fut,
+        Fut: Future<Output = bool>,
+    {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    if poll().await {
+                        self.apply(manager, consul).await;
+                    }
+                }
+                _ = shutdown_rx.changed() => {
+                    if *shutdown_rx.borrow() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn apply(&self, manager: &ProcessManager, consul: Option<&ConsulRegistrar>) {
+        let base = self.base.read().clone();
+        let config = match self.merge(&base) {
+            Ok(config) => config,
+            Err(e) => {
+                error!(error = %e, "Discovered backends are invalid; keeping current backends");
+                return;
+            }
+        };
+        if let Some(consul) = consul {
+            consul.sync(&config.backends).await;
+        }
+        match manager.apply_config(config.backends, config.defaults).await {
+            Ok(result) => info!(added = ?result.added, removed = ?result.removed, "Applied discovered backends"),
+            Err(e) => error!(error = %e, "Failed to apply discovered backends"),
+        }
+    }
+}
+
+/// Reads backends from a directory of discovery files
+pub struct FileDiscovery {
+    discovery: Arc<Discovery>,
+    dir: PathBuf,
+}
+
+impl FileDiscovery {
+    pub fn new(discovery: Arc<Discovery>, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            discovery,
+            dir: dir.into(),
+        }
+    }
+
+    /// Read the files that changed since the last scan
+    ///
+    /// Returns whether any backends were added, changed or removed.
+    pub fn scan(&self) -> bool {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!(dir = %self.dir.display(), error = %e, "Failed to read discovery directory");
+                return false;
+            }
+        };
+
+        let mut contents = BTreeMap::new();
+        for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+            if !is_fragment(&path) {
+                continue;
+            }
+            match std::fs::read_to_string(&path) {
+                Ok(content) => {
+                    contents.insert(format!("file:{}", path.display()), content);
+                }
+                Err(e) => warn!(path = %path.display(), error = %e, "Failed to read discovery file"),
+            }
+        }
+        self.discovery.update("file:", contents, |name, content| {
+            if name.ends_with(".json") {
+                Ok(serde_json::from_str(content)?)
+            } else if name.ends_with(".yaml") || name.ends_with(".yml") {
+                Ok(serde_yaml::from_str(content)?)
+            } else {
+                Ok(toml::from_str(content)?)
+            }
+        })
+    }
+
+    /// Check the directory every `interval` and apply changes until shutdown
+    pub async fn run(
+        self: Arc<Self>,
+        manager: Arc<ProcessManager>,
+        consul: Option<Arc<ConsulRegistrar>>,
+        interval: Duration,
+        shutdown_rx: watch::Receiver<bool>,
+    ) {
+        info!(dir = %self.dir.display(), interval_secs = interval.as_secs(), "File discovery enabled");
+        let poll = || {
+            let files = Arc::clone(&self);
+            async move { tokio::task::spawn_blocking(move || files.scan()).await.unwrap_or(false) }
+        };
+        self.discovery
+            .watch(&manager, consul.as_deref(), interval, shutdown_rx, poll)
+            .await;
+    }
+}
+
+fn is_fragment(path: &Path) -> bool {
+    path.is_file() && matches!(path.extension().and_then(|e| e.to_str()), Some("json" | "toml" | "yaml" | "yml"))
+}
+
+/// Reads backends from keys in Consul's KV store or etcd
+pub struct KvDiscovery {
+    discovery: Arc<Discovery>,
+    api: ApiClient,
+    store: KvStore,
+    prefix: String,
+}
+
+/// Entry of Consul's `GET /v1/kv/{prefix}?recurse` response
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ConsulEntry {
+    key: String,
+    /// Base64, `null` for folders
+    value: Option<String>,
+}
+
+/// etcd's `POST /v3/kv/range` response
+#[derive(Deserialize)]
+struct EtcdRange {
+    #[serde(default)]
+    kvs: Vec<EtcdEntry>,
+}
+
+/// Key and value, both base64
+#[derive(Deserialize)]
+struct EtcdEntry {
+    key: String,
+    #[serde(default)]
+    value: String,
+}
+
+impl KvDiscovery {
+    pub fn new(discovery: Arc<Discovery>, config: &KvConfig) -> Self {
+        let header = match config.store {
+            KvStore::Consul => "x-consul-token",
+            KvStore::Etcd => "authorization",
+        };
+        Self {
+            discovery,
+            api: ApiClient::new(config.address(), config.token.clone().map(|t| (header, t))),
+            store: config.store,
+            prefix: config.prefix.clone(),
+        }
+    }
+
+    /// Read the keys, applying those that changed since the last poll
+    ///
+    /// Returns whether any backends were added, changed or removed. If the
+    /// store can't be reached, the discovered backends stay as they are.
+    pub async fn poll(&self) -> bool {
+        let entries = match self.store {
+            KvStore::Consul => self.consul_entries().await,
+            KvStore::Etcd => self.etcd_entries().await,
+        };
+        let entries = match entries {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!(store = ?self.store, prefix = %self.prefix, error = %e, "Failed to read backend keys");
+                return false;
+            }
+        };
+
+        let contents = entries
+            .into_iter()
+            .filter(|(key, _)| key.strip_prefix(&self.prefix).is_some_and(is_hostname_key))
+            .map(|(key, value)| (format!("kv:{}", key), value))
+            .collect();
+        let skip = "kv:".len() + self.prefix.len();
+        self.discovery.update("kv:", contents, |name, content| {
+            let backend: BackendConfig = serde_json::from_str(content)?;
+            Ok(HashMap::from([(name[skip..].to_string(), backend)]))
+        })
+    }
+
+    async fn consul_entries(&self) -> anyhow::Result<Vec<(String, String)>> {
+        let path = format!("/v1/kv/{}?recurse=true", self.prefix);
+        let (status, body) = self.api.request(Method::GET, &path, None).await?;
+        if status == StatusCode::NOT_FOUND {
+            return Ok(Vec::new());
+        }
+        if !status.is_success() {
+            anyhow::bail!("Consul returned {}: {}", status, String::from_utf8_lossy(&body).trim());
+        }
+
+        let entries: Vec<ConsulEntry> = serde_json::from_slice(&body)?;
+        entries
+            .into_iter()
+            .filter_map(|entry| Some((entry.key, entry.value?)))
+            .map(|(key, value)| Ok((key, decode(&value)?)))
+            .collect()
+    }
+
+    async fn etcd_entries(&self) -> anyhow::Result<Vec<(String, String)>> {
+        let engine = base64::engine::general_purpose::STANDARD;
+        let body = serde_json::json!({
+            "key": engine.encode(&self.prefix),
+            "range_end": engine.encode(prefix_end(self.prefix.as_bytes())),
+        });
+        let (status, body) = self.api.request(Method::POST, "/v3/kv/range", Some(body)).await?;
+        if !status.is_success() {
+            anyhow::bail!("etcd returned {}: {}", status, String::from_utf8_lossy(&body).trim());
+        }
+
+        let range: EtcdRange = serde_json::from_slice(&body)?;
+        range
+            .kvs
+            .into_iter()
+            .map(|entry| Ok((decode(&entry.key)?, decode(&entry.value)?)))
+            .collect()
+    }
+
+    /// Read the keys every `interval` and apply changes until shutdown
+    pub async fn run(
+        self: Arc<Self>,
+        manager: Arc<ProcessManager>,
+        consul: Option<Arc<ConsulRegistrar>>,
+        interval: Duration,
+        shutdown_rx: watch::Receiver<bool>,
+    ) {
+        info!(store = ?self.store, prefix = %self.prefix, interval_secs = interval.as_secs(), "KV discovery enabled");
+        self.discovery
+            .watch(&manager, consul.as_deref(), interval, shutdown_rx, || self.poll())
+            .await;
+    }
+}
+
+/// A key right under the prefix, naming a hostname
+fn is_hostname_key(rest: &str) -> bool {
+    !rest.is_empty() && !rest.contains('/')
+}
+
+fn decode(value: &str) -> anyhow::Result<String> {
+    let bytes = base64::engine::general_purpose::STANDARD.decode(value)?;
+    Ok(String::from_utf8(bytes)?)
+}
+
+/// End of the etcd key range holding every key starting with `prefix`
+fn prefix_end(prefix: &[u8]) -> Vec<u8> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return end;
+        }
+    }
+    // Every key
+    vec![0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("spawngate-discovery-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn base() -> Config {
+        Config::parse(
+            r#"
+[profiles.web]
+idle_timeout_secs = 42
+
+[backends."static.local"]
+command = "./static"
+port = 3000
+"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_scan_merges_fragments() {
+        let dir = temp_dir("merge");
+        std::fs::write(
+            dir.join("10-api.json"),
+            r#"{"api.local": {"command": "./api", "port": 3001, "profile": "web"},
+                "static.local": {"command": "./other", "port": 3002}}"#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("20-jobs.toml"), "[\"jobs.local\"]\ncommand = \"./jobs\"\nport = 3003\n").unwrap();
+        std::fs::write(dir.join("30-api.json"), r#"{"api.local": {"command": "./shadow", "port": 3004}}"#).unwrap();
+        let yaml = "web.local:\n  command: ./web\n  port: 3005\n  profile: web\n";
+        std::fs::write(dir.join("40-web.yaml"), yaml).unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a fragment").unwrap();
+
+        let base = base();
+        let discovery = Arc::new(Discovery::new(&base));
+        let files = FileDiscovery::new(Arc::clone(&discovery), &dir);
+        assert!(files.scan());
+        assert!(!files.scan());
+
+        let config = discovery.merge(&base).unwrap();
+        let mut hostnames: Vec<_> = config.backends.keys().cloned().collect();
+        hostnames.sort();
+        assert_eq!(hostnames, vec!["api.local", "jobs.local", "static.local", "web.local"]);
+        // The main config and the first file by name win
+        assert_eq!(config.backends["static.local"].command.as_deref(), Some("./static"));
+        assert_eq!(config.backends["api.local"].command.as_deref(), Some("./api"));
+        assert_eq!(config.backends["api.local"].idle_timeout_secs, Some(42));
+        assert_eq!(config.backends["web.local"].port, 3005);
+        assert_eq!(config.backends["web.local"].idle_timeout_secs, Some(42));
+
+        std::fs::remove_file(dir.join("20-jobs.toml")).unwrap();
+        assert!(files.scan());
+        assert!(!discovery.merge(&base).unwrap().backends.contains_key("jobs.local"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_fragment_keeps_last_good_backends() {
+        let dir = temp_dir("invalid");
+        let path = dir.join("api.json");
+        std::fs::write(&path, r#"{"api.local": {"command": "./api", "port": 3001}}"#).unwrap();
+
+        let base = base();
+        let discovery = Arc::new(Discovery::new(&base));
+        let files = FileDiscovery::new(Arc::clone(&discovery), &dir);
+        assert!(files.scan());
+
+        for invalid in [
+            r#"{"api.local": {"command": "./api""#,
+            r#"{"api.local": {"command": "./api", "port": 3001, "profile": "missing"}}"#,
+        ] {
+            std::fs::write(&path, invalid).unwrap();
+            assert!(!files.scan());
+            let config = discovery.merge(&base).unwrap();
+            assert_eq!(config.backends["api.local"].port, 3001);
+        }
+
+        std::fs::write(&path, r#"{"api.local": {"command": "./api", "port": 3005}}"#).unwrap();
+        assert!(files.scan());
+        assert_eq!(discovery.merge(&base).unwrap().backends["api.local"].port, 3005);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_kv_keys() {
+        assert!(is_hostname_key("api.local"));
+        assert!(!is_hostname_key(""));
+        assert!(!is_hostname_key("old/api.local"));
+
+        assert_eq!(prefix_end(b"spawngate/backends/"), b"spawngate/backends0");
+        assert_eq!(prefix_end(&[b'a', 0xff]), b"b");
+        assert_eq!(prefix_end(&[]), vec![0]);
+    }
+}
@@ -0,0 +1,321 @@
+//! DNS providers for the ACME DNS-01 challenge
+//!
+//! DNS-01 proves control of a domain with a TXT record at
+//! `_acme-challenge.{domain}`, so it is the only challenge that can validate
+//! wildcard domains, and the CA never needs to reach this server. Records are
+//! published through the provider's API.
+//!
+//! Providers implement [`DnsProvider`]; Cloudflare and Route 53 are built in.
+//! As for cloud VMs, credentials come from the environment rather than the
+//! config file.
+
+use crate::cloud::{
+    amz_date, canonical_query, fetch, https_client, sigv4_authorization, uri_encode, xml_value, AwsCredentials,
+    HttpsClient,
+};
+use crate::config::{AcmeDnsConfig, AcmeDnsProvider};
+use futures::future::{BoxFuture, FutureExt};
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::{Method, Request};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::debug;
+
+/// TTL of challenge records, short so a retried order isn't served a stale value
+const RECORD_TTL: u64 = 60;
+
+/// How often a pending Route 53 change is checked
+const CHANGE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Time allowed for a Route 53 change to reach all of its name servers
+const CHANGE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Publishes and removes DNS-01 challenge records
+pub trait DnsProvider: Send + Sync {
+    /// Publish `value` as the TXT record at `name`, returning once the
+    /// provider has accepted it
+    fn add_txt<'a>(&'a self, name: &'a str, value: &'a str) -> BoxFuture<'a, anyhow::Result<()>>;
+
+    /// Remove a record published by `add_txt`
+    fn remove_txt<'a>(&'a self, name: &'a str, value: &'a str) -> BoxFuture<'a, anyhow::Result<()>>;
+}
+
+/// Built-in provider for a config
+pub fn provider(config: &AcmeDnsConfig) -> anyhow::Result<Arc<dyn DnsProvider>> {
+    Ok(match config.provider {
+        AcmeDnsProvider::Cloudflare => Arc::new(Cloudflare::new(config.zone_id.clone())?),
+        AcmeDnsProvider::Route53 => Arc::new(Route53::new(config.zone_id.clone())?),
+    })
+}
+
+/// Name of the TXT record that validates `domain`; a wildcard is validated
+/// at its base domain
+pub fn challenge_record_name(domain: &str) -> String {
+    format!("_acme-challenge.{}", domain.trim_start_matches("*."))
+}
+
+/// Domains that may be the zone of a record, closest first, down to (but
+/// not including) the TLD
+fn zone_candidates(name: &str) -> impl Iterator<Item = &str> {
+    std::iter::successors(Some(name), |name| name.split_once('.').map(|(_, parent)| parent))
+        .skip(1)
+        .filter(|name| name.contains('.'))
+}
+
+/// Cloudflare, with an API token from `CLOUDFLARE_API_TOKEN` that has the
+/// Zone:DNS:Edit permission
+pub struct Cloudflare {
+    client: HttpsClient,
+    zone_id: Option<String>,
+    /// Zone and record ID of each published record, by name and value
+    records: Mutex<HashMap<(String, String), (String, String)>>,
+}
+
+impl Cloudflare {
+    pub fn new(zone_id: Option<String>) -> anyhow::Result<Self> {
+        Ok(Self {
+            client: https_client()?,
+            zone_id,
+            records: Mutex::new(HashMap::new()),
+        })
+    }
+
+    async fn call(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> anyhow::Result<serde_json::Value> {
+        let token = std::env::var("CLOUDFLARE_API_TOKEN")
+            .map_err(|_| anyhow::anyhow!("CLOUDFLARE_API_TOKEN must be set"))?;
+        let request = Request::builder()
+            .method(method)
+            .uri(format!("https://api.cloudflare.com/client/v4{}", path))
+            .header("authorization", format!("Bearer {}", token))
+            .header("content-type", "application/json");
+        let body = body.map(|body| body.to_string()).unwrap_or_default();
+        let request = request.body(Full::new(Bytes::from(body)))?;
+
+        let (status, body) = fetch(&self.client, request).await?;
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+        if !status.is_success() || json["success"] == false {
+            let message = json["errors"][0]["message"].as_str().unwrap_or("unknown error");
+            anyhow::bail!("Cloudflare API {} failed ({}): {}", path, status, message);
+        }
+        Ok(json)
+    }
+
+    /// ID of the zone that `name` belongs to
+    async fn zone(&self, name: &str) -> anyhow::Result<String> {
+        if let Some(zone_id) = &self.zone_id {
+            return Ok(zone_id.clone());
+        }
+        for candidate in zone_candidates(name) {
+            let json = self.call(Method::GET, &format!("/zones?name={}", uri_encode(candidate)), None).await?;
+            if let Some(id) = json["result"][0]["id"].as_str() {
+                debug!(zone = candidate, id, "Found Cloudflare zone");
+                return Ok(id.to_string());
+            }
+        }
+        anyhow::bail!("No Cloudflare zone found for {}; set 'zone_id'", name)
+    }
+}
+
+impl DnsProvider for Cloudflare {
+    fn add_txt<'a>(&'a self, name: &'a str, value: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let zone = self.zone(name).await?;
+            let record = serde_json::json!({ "type": "TXT", "name": name, "content": value, "ttl": RECORD_TTL });
+            let json = self.call(Method::POST, &format!("/zones/{}/dns_records", zone), Some(record)).await?;
+            let id = json["result"]["id"]
+                .as_str()
+                .ok_or_else(|| anyhow::anyhow!("Cloudflare returned no ID for the record at {}", name))?;
+            self.records.lock().insert((name.to_string(), value.to_string()), (zone, id.to_string()));
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn remove_txt<'a>(&'a self, name: &'a str, value: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let (zone, id) = self
+                .records
+                .lock()
+                .remove(&(name.to_string(), value.to_string()))
+                .ok_or_else(|| anyhow::anyhow!("No record was published at {}", name))?;
+            self.call(Method::DELETE, &format!("/zones/{}/dns_records/{}", zone, id), None).await.map(drop)
+        }
+        .boxed()
+    }
+}
+
+/// Amazon Route 53, called through its REST API with Signature Version 4
+///
+/// The credentials need `route53:ChangeResourceRecordSets` and
+/// `route53:GetChange`, plus `route53:ListHostedZonesByName` without a
+/// configured zone.
+pub struct Route53 {
+    client: HttpsClient,
+    zone_id: Option<String>,
+}
+
+impl Route53 {
+    pub fn new(zone_id: Option<String>) -> anyhow::Result<Self> {
+        Ok(Self {
+            client: https_client()?,
+            zone_id,
+        })
+    }
+
+    /// Call the API, returning the XML response
+    async fn call(&self, method: Method, path: &str, params: &[(&str, &str)], body: String) -> anyhow::Result<String> {
+        let credentials = AwsCredentials::from_env()?;
+        let host = "route53.amazonaws.com";
+        let query = canonical_query(params);
+        let amz_date = amz_date(SystemTime::now());
+
+        let mut headers = vec![("host", host), ("x-amz-date", amz_date.as_str())];
+        if let Some(token) = &credentials.session_token {
+            headers.push(("x-amz-security-token", token));
+        }
+        // Route 53 is a global service, signed for us-east-1
+        let authorization = sigv4_authorization(
+            &credentials,
+            "us-east-1",
+            "route53",
+            method.as_str(),
+            path,
+            &query,
+            &headers,
+            body.as_bytes(),
+            &amz_date,
+        );
+
+        let uri = if query.is_empty() {
+            format!("https://{}{}", host, path)
+        } else {
+            format!("https://{}{}?{}", host, path, query)
+        };
+        let mut request = Request::builder().method(method).uri(uri);
+        for (name, value) in headers.iter().filter(|(name, _)| *name != "host") {
+            request = request.header(*name, *value);
+        }
+        let request = request.header("authorization", authorization).body(Full::new(Bytes::from(body)))?;
+
+        let (status, body) = fetch(&self.client, request).await?;
+        let body = String::from_utf8_lossy(&body).into_owned();
+        if !status.is_success() {
+            let message = xml_value(&body, "Message").unwrap_or(body.trim());
+            anyhow::bail!("Route 53 {} failed ({}): {}", path, status, message);
+        }
+        Ok(body)
+    }
+
+    /// ID of the hosted zone that `name` belongs to
+    async fn zone(&self, name: &str) -> anyhow::Result<String> {
+        if let Some(zone_id) = &self.zone_id {
+            return Ok(zone_id.trim_start_matches("/hostedzone/").to_string());
+        }
+        for candidate in zone_candidates(name) {
+            let params = [("dnsname", candidate), ("maxitems", "1")];
+            let body = self.call(Method::GET, "/2013-04-01/hostedzonesbyname", &params, String::new()).await?;
+            // Zones are listed from the given name onwards, so the first may be another one
+            let zone = xml_value(&body, "HostedZone").unwrap_or_default();
+            if xml_value(zone, "Name").is_some_and(|zone_name| zone_name.trim_end_matches('.') == candidate) {
+                if let Some(id) = xml_value(zone, "Id") {
+                    debug!(zone = candidate, id, "Found Route 53 hosted zone");
+                    return Ok(id.trim_start_matches("/hostedzone/").to_string());
+                }
+            }
+        }
+        anyhow::bail!("No Route 53 hosted zone found for {}; set 'zone_id'", name)
+    }
+
+    /// Apply a change to the TXT record at `name`, returning the change ID
+    async fn change(&self, action: &str, name: &str, value: &str) -> anyhow::Result<String> {
+        let zone = self.zone(name).await?;
+        let path = format!("/2013-04-01/hostedzone/{}/rrset/", zone);
+        let body = self.call(Method::POST, &path, &[], change_batch(action, name, value)).await?;
+        xml_value(&body, "Id")
+            .map(|id| id.trim_start_matches("/change/").to_string())
+            .ok_or_else(|| anyhow::anyhow!("Route 53 returned no change ID for {}", name))
+    }
+
+    /// Wait until a change has reached all of the zone's name servers
+    async fn wait_for_change(&self, id: &str) -> anyhow::Result<()> {
+        let path = format!("/2013-04-01/change/{}", id);
+        let wait = async {
+            loop {
+                let body = self.call(Method::GET, &path, &[], String::new()).await?;
+                if xml_value(&body, "Status") == Some("INSYNC") {
+                    return anyhow::Ok(());
+                }
+                debug!(change = id, "Route 53 change pending");
+                tokio::time::sleep(CHANGE_POLL_INTERVAL).await;
+            }
+        };
+        tokio::time::timeout(CHANGE_TIMEOUT, wait)
+            .await
+            .map_err(|_| anyhow::anyhow!("Route 53 change {} did not complete in time", id))?
+    }
+}
+
+impl DnsProvider for Route53 {
+    fn add_txt<'a>(&'a self, name: &'a str, value: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let id = self.change("UPSERT", name, value).await?;
+            self.wait_for_change(&id).await
+        }
+        .boxed()
+    }
+
+    fn remove_txt<'a>(&'a self, name: &'a str, value: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move { self.change("DELETE", name, value).await.map(drop) }.boxed()
+    }
+}
+
+/// `ChangeResourceRecordSets` request body for a single-value TXT record
+fn change_batch(action: &str, name: &str, value: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+         <ChangeResourceRecordSetsRequest xmlns=\"https://route53.amazonaws.com/doc/2013-04-01/\">\
+         <ChangeBatch><Changes><Change><Action>{}</Action><ResourceRecordSet>\
+         <Name>{}</Name><Type>TXT</Type><TTL>{}</TTL>\
+         <ResourceRecords><ResourceRecord><Value>\"{}\"</Value></ResourceRecord></ResourceRecords>\
+         </ResourceRecordSet></Change></Changes></ChangeBatch></ChangeResourceRecordSetsRequest>",
+        action, name, RECORD_TTL, value
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_names() {
+        assert_eq!(challenge_record_name("example.com"), "_acme-challenge.example.com");
+        assert_eq!(challenge_record_name("*.apps.example.com"), "_acme-challenge.apps.example.com");
+
+        let candidates: Vec<&str> = zone_candidates("_acme-challenge.apps.example.com").collect();
+        assert_eq!(candidates, vec!["apps.example.com", "example.com"]);
+        assert_eq!(zone_candidates("_acme-challenge.com").count(), 0);
+    }
+
+    #[test]
+    fn test_route53_change_batch() {
+        let body = change_batch("UPSERT", "_acme-challenge.example.com", "abc-123_x");
+        assert!(body.contains("<Action>UPSERT</Action>"));
+        assert!(body.contains("<Name>_acme-challenge.example.com</Name><Type>TXT</Type><TTL>60</TTL>"));
+        assert!(body.contains("<Value>\"abc-123_x\"</Value>"));
+
+        let response = "<ListHostedZonesByNameResponse><HostedZones><HostedZone><Id>/hostedzone/Z1</Id>\
+                        <Name>example.com.</Name><Config><PrivateZone>false</PrivateZone></Config>\
+                        </HostedZone></HostedZones><DNSName>example.com</DNSName></ListHostedZonesByNameResponse>";
+        let zone = xml_value(response, "HostedZone").unwrap();
+        assert_eq!(xml_value(zone, "Name"), Some("example.com."));
+        assert_eq!(xml_value(zone, "Id"), Some("/hostedzone/Z1"));
+    }
+}
@@ -1,23 +1,130 @@
 //! Docker container management for Docker-based backends
 
-use crate::config::{BackendConfig, PullPolicy};
+use crate::config::{BackendConfig, PullPolicy, RegistryAuth, SidecarConfig};
+use crate::logs::{BackendLogs, LogStream};
+use bollard::auth::DockerCredentials;
 use bollard::container::{
-    Config, CreateContainerOptions, LogOutput, LogsOptions, RemoveContainerOptions,
-    StartContainerOptions, StopContainerOptions,
+    Config, CreateContainerOptions, LogOutput, LogsOptions, MemoryStatsStats, NetworkingConfig,
+    RemoveContainerOptions, StartContainerOptions, Stats, StatsOptions, StopContainerOptions,
 };
 use bollard::image::CreateImageOptions;
-use bollard::models::{HostConfig, PortBinding};
+use bollard::models::{EndpointSettings, EventMessage, EventMessageTypeEnum, HostConfig, ImageInspect, PortBinding};
+use bollard::network::CreateNetworkOptions;
+use bollard::system::EventsOptions;
 use bollard::Docker;
-use futures::StreamExt;
+use dashmap::DashMap;
+use futures::future::BoxFuture;
+use futures::{FutureExt, StreamExt};
+use serde::Serialize;
 use std::collections::HashMap;
+use std::fmt;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::watch;
+use tokio::sync::{mpsc, watch};
 use tracing::{debug, info, warn};
 
+/// Engine running the containers of Docker backends
+///
+/// A backend's `container_runtime` picks one: [`DockerManager`] talks to the
+/// Docker API, [`PodmanRuntime`](crate::podman::PodmanRuntime) drives the
+/// `podman` CLI.
+pub trait ContainerRuntime: Send + Sync {
+    /// Name of the engine, for logs
+    fn name(&self) -> &'static str;
+
+    /// Pull the image as needed and start a container for a backend, returning its ID
+    ///
+    /// `injected_env` holds the proxy-provided variables (PORT, callback URL,
+    /// instance metadata); they take precedence over the backend's `env` table.
+    fn start_container<'a>(
+        &'a self,
+        config: &'a BackendConfig,
+        hostname: &'a str,
+        injected_env: &'a [(String, String)],
+    ) -> BoxFuture<'a, anyhow::Result<String>>;
+
+    /// Stop a container gracefully, waiting up to `timeout`
+    fn stop_container<'a>(&'a self, container_id: &'a str, timeout: Duration) -> BoxFuture<'a, anyhow::Result<()>>;
+
+    /// Force kill a container
+    fn kill_container<'a>(&'a self, container_id: &'a str) -> BoxFuture<'a, anyhow::Result<()>>;
+
+    /// Remove a container, succeeding if it doesn't exist
+    fn remove_container<'a>(&'a self, container_id: &'a str) -> BoxFuture<'a, anyhow::Result<()>>;
+
+    /// Check if a container is running
+    fn is_running<'a>(&'a self, container_id: &'a str) -> BoxFuture<'a, bool>;
+
+    /// Current resource usage of a running container
+    fn stats<'a>(&'a self, container_id: &'a str) -> BoxFuture<'a, anyhow::Result<ContainerStats>>;
+
+    /// Follow container logs, returning a sender that stops it
+    fn stream_logs(&self, container_id: String, hostname: String, logs: Arc<BackendLogs>) -> watch::Sender<bool>;
+
+    /// Report every container that exits, until the receiver is dropped
+    fn watch_exits(&self) -> mpsc::UnboundedReceiver<ContainerExit>;
+}
+
+/// Resource usage of a container, as `docker stats` shows it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ContainerStats {
+    /// CPU use in percent of one CPU, so 200 is two CPUs fully used
+    pub cpu_percent: f64,
+    /// Memory in use, without the page cache that can be reclaimed
+    pub memory_bytes: u64,
+    /// Memory limit, the host's memory if the container has none
+    pub memory_limit_bytes: Option<u64>,
+    /// Processes and threads running in the container
+    pub pids: Option<u64>,
+}
+
+impl ContainerStats {
+    /// Usage from a Docker stats sample, CPU use since the previous one
+    pub(crate) fn from_docker(stats: &Stats) -> Self {
+        let (cpu, previous) = (&stats.cpu_stats, &stats.precpu_stats);
+        let cpu_delta = cpu.cpu_usage.total_usage.saturating_sub(previous.cpu_usage.total_usage) as f64;
+        let system_delta = cpu
+            .system_cpu_usage
+            .unwrap_or(0)
+            .saturating_sub(previous.system_cpu_usage.unwrap_or(0)) as f64;
+        let online_cpus = cpu
+            .online_cpus
+            .or_else(|| cpu.cpu_usage.percpu_usage.as_ref().map(|usage| usage.len() as u64))
+            .unwrap_or(1) as f64;
+        let cpu_percent = if system_delta > 0.0 {
+            (cpu_delta / system_delta * online_cpus * 10_000.0).round() / 100.0
+        } else {
+            0.0
+        };
+
+        let memory = &stats.memory_stats;
+        let inactive_file = match memory.stats {
+            Some(MemoryStatsStats::V1(v1)) => v1.total_inactive_file,
+            Some(MemoryStatsStats::V2(v2)) => v2.inactive_file,
+            None => 0,
+        };
+        Self {
+            cpu_percent,
+            memory_bytes: memory.usage.unwrap_or(0).saturating_sub(inactive_file),
+            memory_limit_bytes: memory.limit,
+            pids: stats.pids_stats.current,
+        }
+    }
+}
+
 /// Manages Docker containers for backends
 pub struct DockerManager {
     client: Docker,
+    /// Sidecars and network of backends that have them, by main container ID
+    groups: Arc<DashMap<String, ContainerGroup>>,
+}
+
+/// The private network of a backend with sidecars, and the sidecars on it
+#[derive(Debug, Clone)]
+struct ContainerGroup {
+    network: String,
+    /// Sidecar container IDs, in start order
+    sidecars: Vec<String>,
 }
 
 impl DockerManager {
@@ -58,7 +165,10 @@ impl DockerManager {
         })?;
 
         debug!("Connected to Docker daemon");
-        Ok(Self { client })
+        Ok(Self {
+            client,
+            groups: Arc::new(DashMap::new()),
+        })
     }
 
     fn connect_to_host(host: &str) -> anyhow::Result<Docker> {
@@ -138,44 +248,39 @@ impl DockerManager {
     }
 
     /// Pull a Docker image if needed based on pull policy
+    ///
+    /// With a `platform`, a local image built for another platform counts as
+    /// missing for `if-not-present`. `auth` is sent to the image's registry.
     pub async fn pull_image_if_needed(
         &self,
         image: &str,
+        platform: Option<&Platform>,
         policy: &PullPolicy,
+        auth: Option<&RegistryAuth>,
     ) -> anyhow::Result<()> {
-        let should_pull = match policy {
-            PullPolicy::Always => true,
-            PullPolicy::Never => {
-                // Check if image exists, fail if not
-                if self.client.inspect_image(image).await.is_err() {
-                    anyhow::bail!(
-                        "Image '{}' not found locally and pull_policy is 'never'. \
-                         Pull the image manually with 'docker pull {}' or change pull_policy.",
-                        image, image
-                    );
-                }
-                false
-            }
-            PullPolicy::IfNotPresent => {
-                // Check if image exists locally
-                match self.client.inspect_image(image).await {
-                    Ok(_) => {
-                        debug!(image, "Image exists locally, skipping pull");
-                        false
-                    }
-                    Err(_) => true,
-                }
-            }
+        let local = match policy {
+            PullPolicy::Always => None,
+            _ => self.client.inspect_image(image).await.ok(),
         };
+        let should_pull = should_pull(image, local.as_ref(), platform, policy, "docker")?;
 
         if should_pull {
-            info!(image, "Pulling Docker image");
+            info!(image, platform = platform.map(tracing::field::display), "Pulling Docker image");
+            let platform_str = platform.map(Platform::to_string).unwrap_or_default();
             let options = CreateImageOptions {
                 from_image: image,
+                platform: platform_str.as_str(),
                 ..Default::default()
             };
 
-            let mut stream = self.client.create_image(Some(options), None, None);
+            let credentials = auth.map(|auth| DockerCredentials {
+                username: auth.username.clone(),
+                password: auth.password.clone(),
+                registrytoken: auth.token.clone(),
+                serveraddress: Some(image_registry(image).to_string()),
+                ..Default::default()
+            });
+            let mut stream = self.client.create_image(Some(options), None, credentials);
             let mut last_error = None;
 
             while let Some(result) = stream.next().await {
@@ -200,8 +305,10 @@ impl DockerManager {
                         } else if err_str.contains("unauthorized") || err_str.contains("authentication") {
                             anyhow::bail!(
                                 "Authentication required to pull '{}'. \
-                                 Run 'docker login' first or check your credentials.",
-                                image
+                                 Set registry_auth for the backend or [defaults.registries.\"{}\"], \
+                                 or check the credentials.",
+                                image,
+                                image_registry(image)
                             );
                         } else if err_str.contains("timeout") || err_str.contains("connection") {
                             anyhow::bail!(
@@ -226,19 +333,45 @@ impl DockerManager {
         Ok(())
     }
 
+    /// Check that the local image has the pinned digest and platform
+    pub async fn verify_image(
+        &self,
+        image: &str,
+        digest: Option<&str>,
+        platform: Option<&Platform>,
+    ) -> anyhow::Result<()> {
+        if digest.is_none() && platform.is_none() {
+            return Ok(());
+        }
+        let inspect = self
+            .client
+            .inspect_image(image)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to inspect image '{}': {}", image, e))?;
+
+        check_image(image, &inspect, digest, platform)
+    }
+
     /// Start a container for a backend
+    ///
+    /// `injected_env` holds the proxy-provided variables (PORT, callback URL,
+    /// instance metadata); they take precedence over the backend's `env` table.
     pub async fn start_container(
         &self,
         config: &BackendConfig,
         hostname: &str,
-        admin_url: &str,
+        injected_env: &[(String, String)],
     ) -> anyhow::Result<String> {
         let image = config.image.as_ref().ok_or_else(|| {
             anyhow::anyhow!("Docker backend requires 'image' field")
         })?;
+        let image = pinned_image(image, config.image_digest.as_deref())?;
+        let platform = config.platform.as_deref().map(Platform::parse).transpose()?;
 
-        // Pull image if needed
-        self.pull_image_if_needed(image, &config.pull_policy).await?;
+        // Pull image if needed, then check it is the one pinned
+        self.pull_image_if_needed(&image, platform.as_ref(), &config.pull_policy, config.registry_auth.as_ref())
+            .await?;
+        self.verify_image(&image, image_digest(&image), platform.as_ref()).await?;
 
         // Generate container name
         let container_name = config
@@ -249,17 +382,53 @@ impl DockerManager {
         // Remove existing container with same name if it exists
         let _ = self.remove_container(&container_name).await;
 
+        // Sidecars go first, on a network of the backend's own
+        let group = if config.sidecars.is_empty() {
+            None
+        } else {
+            Some(self.start_sidecars(config, &container_name, platform.as_ref()).await?)
+        };
+        let network = group.as_ref().map(|group| group.network.clone()).or_else(|| config.network.clone());
+
+        match self
+            .run_container(config, &image, platform.as_ref(), &container_name, network, injected_env)
+            .await
+        {
+            Ok(container_id) => {
+                info!(hostname, container_id, "Started Docker container");
+                if let Some(group) = group {
+                    self.groups.insert(container_id.clone(), group);
+                }
+                Ok(container_id)
+            }
+            Err(e) => {
+                if let Some(group) = group {
+                    self.remove_group(&group).await;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Create and start a backend's main container
+    async fn run_container(
+        &self,
+        config: &BackendConfig,
+        image: &str,
+        platform: Option<&Platform>,
+        container_name: &str,
+        network: Option<String>,
+        injected_env: &[(String, String)],
+    ) -> anyhow::Result<String> {
+        let container_name = container_name.to_string();
+
         // Build environment variables
         let mut env: Vec<String> = config
             .env
             .iter()
             .map(|(k, v)| format!("{}={}", k, v))
             .collect();
-        env.push(format!("PORT={}", config.port));
-        env.push(format!(
-            "SERVERLESS_PROXY_READY_URL={}/ready/{}",
-            admin_url, hostname
-        ));
+        env.extend(injected_env.iter().map(|(k, v)| format!("{}={}", k, v)));
 
         // Build port bindings
         let port_key = format!("{}/tcp", config.port);
@@ -279,21 +448,14 @@ impl DockerManager {
         // Build host config
         let mut host_config = HostConfig {
             port_bindings: Some(port_bindings),
-            network_mode: config.network.clone(),
+            network_mode: network,
             ..Default::default()
         };
 
         // Apply resource limits
-        if let Some(ref memory) = config.memory {
-            host_config.memory = Some(parse_memory_limit(memory)?);
-        }
-        if let Some(ref cpus) = config.cpus {
-            let cpu_count: f64 = cpus.parse().map_err(|_| {
-                anyhow::anyhow!("Invalid CPU limit: {}", cpus)
-            })?;
-            // NanoCPUs is CPUs * 1e9
-            host_config.nano_cpus = Some((cpu_count * 1_000_000_000.0) as i64);
-        }
+        apply_limits(&mut host_config, config.memory.as_deref(), config.cpus.as_deref())?;
+        host_config.cpu_shares = config.cpu_shares;
+        host_config.pids_limit = config.pids_limit;
 
         // Build command arguments if provided
         let cmd = if config.args.is_empty() {
@@ -315,7 +477,7 @@ impl DockerManager {
         // Create container
         let create_options = CreateContainerOptions {
             name: container_name.clone(),
-            platform: None,
+            platform: platform.map(Platform::to_string),
         };
 
         let response = self
@@ -346,13 +508,7 @@ impl DockerManager {
             })?;
 
         let container_id = response.id;
-        info!(
-            hostname,
-            container_id,
-            container_name,
-            image,
-            "Created Docker container"
-        );
+        info!(container_id, container_name, image, "Created Docker container");
 
         // Start container
         self.client
@@ -386,17 +542,145 @@ impl DockerManager {
                 }
             })?;
 
-        info!(hostname, container_id, "Started Docker container");
+        Ok(container_id)
+    }
+
+    /// Create a backend's network and start its sidecars on it
+    ///
+    /// Containers and a network left behind by an earlier run are removed
+    /// first. Sidecars are named `{container_name}-{name}` and reachable
+    /// from the other containers as `name`.
+    async fn start_sidecars(
+        &self,
+        config: &BackendConfig,
+        container_name: &str,
+        platform: Option<&Platform>,
+    ) -> anyhow::Result<ContainerGroup> {
+        let network = format!("{}-net", container_name);
+        for sidecar in &config.sidecars {
+            let _ = self.remove_container(&format!("{}-{}", container_name, sidecar.name)).await;
+        }
+        let _ = self.client.remove_network(&network).await;
+
+        let options = CreateNetworkOptions {
+            name: network.clone(),
+            check_duplicate: true,
+            driver: "bridge".to_string(),
+            ..Default::default()
+        };
+        self.client
+            .create_network(options)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to create network '{}': {}", network, e))?;
+        debug!(network, "Created backend network");
+
+        let mut group = ContainerGroup {
+            network,
+            sidecars: Vec::new(),
+        };
+        for sidecar in &config.sidecars {
+            let name = format!("{}-{}", container_name, sidecar.name);
+            match self
+                .start_sidecar(sidecar, &name, &group.network, platform, &config.pull_policy)
+                .await
+            {
+                Ok(container_id) => group.sidecars.push(container_id),
+                Err(e) => {
+                    self.remove_group(&group).await;
+                    anyhow::bail!("Failed to start sidecar '{}': {}", sidecar.name, e);
+                }
+            }
+        }
+        Ok(group)
+    }
+
+    async fn start_sidecar(
+        &self,
+        sidecar: &SidecarConfig,
+        container_name: &str,
+        network: &str,
+        platform: Option<&Platform>,
+        policy: &PullPolicy,
+    ) -> anyhow::Result<String> {
+        self.pull_image_if_needed(&sidecar.image, platform, policy, sidecar.registry_auth.as_ref()).await?;
 
+        let mut host_config = HostConfig {
+            network_mode: Some(network.to_string()),
+            ..Default::default()
+        };
+        apply_limits(&mut host_config, sidecar.memory.as_deref(), sidecar.cpus.as_deref())?;
+        let endpoint = EndpointSettings {
+            aliases: Some(vec![sidecar.name.clone()]),
+            ..Default::default()
+        };
+        let container_config = Config {
+            image: Some(sidecar.image.clone()),
+            cmd: (!sidecar.args.is_empty()).then(|| sidecar.args.clone()),
+            env: Some(sidecar.env.iter().map(|(k, v)| format!("{}={}", k, v)).collect()),
+            host_config: Some(host_config),
+            networking_config: Some(NetworkingConfig {
+                endpoints_config: HashMap::from([(network.to_string(), endpoint)]),
+            }),
+            ..Default::default()
+        };
+        let options = CreateContainerOptions {
+            name: container_name.to_string(),
+            platform: platform.map(Platform::to_string),
+        };
+
+        let container_id = self
+            .client
+            .create_container(Some(options), container_config)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to create container from image '{}': {}", sidecar.image, e))?
+            .id;
+        if let Err(e) = self
+            .client
+            .start_container(&container_id, None::<StartContainerOptions<String>>)
+            .await
+        {
+            let _ = self.remove_container(&container_id).await;
+            anyhow::bail!("Failed to start container '{}': {}", container_name, e);
+        }
+        info!(container_id, container_name, image = %sidecar.image, "Started sidecar container");
         Ok(container_id)
     }
 
-    /// Stop a container gracefully
+    /// Remove a backend's sidecars, last started first, then its network
+    async fn remove_group(&self, group: &ContainerGroup) {
+        for container_id in group.sidecars.iter().rev() {
+            let _ = self.remove_container(container_id).await;
+        }
+        match self.client.remove_network(&group.network).await {
+            Ok(()) => debug!(network = %group.network, "Removed backend network"),
+            Err(e) => warn!(network = %group.network, error = %e, "Failed to remove backend network"),
+        }
+    }
+
+    /// Sidecars started with a main container, last started first
+    fn sidecars_of(&self, container_id: &str) -> Vec<String> {
+        self.groups
+            .get(container_id)
+            .map(|group| group.sidecars.iter().rev().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Stop a container gracefully, then its sidecars
     pub async fn stop_container(
         &self,
         container_id: &str,
         timeout: Duration,
     ) -> anyhow::Result<()> {
+        let result = self.stop_one(container_id, timeout).await;
+        for sidecar in self.sidecars_of(container_id) {
+            if let Err(e) = self.stop_one(&sidecar, timeout).await {
+                warn!(container_id = %sidecar, error = %e, "Error stopping sidecar");
+            }
+        }
+        result
+    }
+
+    async fn stop_one(&self, container_id: &str, timeout: Duration) -> anyhow::Result<()> {
         let options = StopContainerOptions {
             t: timeout.as_secs() as i64,
         };
@@ -424,8 +708,16 @@ impl DockerManager {
         }
     }
 
-    /// Force kill a container
+    /// Force kill a container and its sidecars
     pub async fn kill_container(&self, container_id: &str) -> anyhow::Result<()> {
+        let result = self.kill_one(container_id).await;
+        for sidecar in self.sidecars_of(container_id) {
+            let _ = self.kill_one(&sidecar).await;
+        }
+        result
+    }
+
+    async fn kill_one(&self, container_id: &str) -> anyhow::Result<()> {
         match self.client.kill_container::<String>(container_id, None).await {
             Ok(_) => {
                 info!(container_id, "Killed Docker container");
@@ -448,7 +740,7 @@ impl DockerManager {
         }
     }
 
-    /// Remove a container
+    /// Remove a container, and its sidecars and network if it has them
     pub async fn remove_container(&self, container_id: &str) -> anyhow::Result<()> {
         let options = RemoveContainerOptions {
             force: true,
@@ -458,19 +750,21 @@ impl DockerManager {
         match self.client.remove_container(container_id, Some(options)).await {
             Ok(_) => {
                 debug!(container_id, "Removed Docker container");
-                Ok(())
             }
             Err(bollard::errors::Error::DockerResponseServerError {
                 status_code: 404, ..
             }) => {
                 debug!(container_id, "Container not found");
-                Ok(())
             }
             Err(e) => {
                 warn!(container_id, error = %e, "Failed to remove container");
-                Ok(()) // Don't fail on removal errors
+                // Don't fail on removal errors
             }
         }
+        if let Some((_, group)) = self.groups.remove(container_id) {
+            Box::pin(self.remove_group(&group)).await;
+        }
+        Ok(())
     }
 
     /// Check if a container is running
@@ -484,7 +778,24 @@ impl DockerManager {
         }
     }
 
-    /// Stream container logs and forward them to tracing
+    /// Current resource usage of a container
+    ///
+    /// Takes about a second, as Docker waits for a second sample to measure CPU use.
+    pub async fn stats(&self, container_id: &str) -> anyhow::Result<ContainerStats> {
+        let options = StatsOptions {
+            stream: false,
+            one_shot: false,
+        };
+        let stats = self
+            .client
+            .stats(container_id, Some(options))
+            .next()
+            .await
+            .ok_or_else(|| anyhow::anyhow!("No stats for container {}", container_id))??;
+        Ok(ContainerStats::from_docker(&stats))
+    }
+
+    /// Follow container logs and forward them to tracing and the backend's log buffer
     ///
     /// Returns a shutdown sender that can be used to stop log streaming.
     /// The spawned task will exit when the sender is dropped or when
@@ -493,6 +804,7 @@ impl DockerManager {
         &self,
         container_id: String,
         hostname: String,
+        logs: Arc<BackendLogs>,
     ) -> watch::Sender<bool> {
         let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
         let client = self.client.clone();
@@ -517,50 +829,26 @@ impl DockerManager {
                     log_result = log_stream.next() => {
                         match log_result {
                             Some(Ok(output)) => {
-                                match output {
-                                    LogOutput::StdOut { message } => {
-                                        if let Ok(line) = String::from_utf8(message.to_vec()) {
-                                            let line = line.trim_end();
-                                            if !line.is_empty() {
-                                                info!(
-                                                    target: "container",
-                                                    hostname,
-                                                    stream = "stdout",
-                                                    "{}",
-                                                    line
-                                                );
-                                            }
+                                let (stream, message) = match output {
+                                    LogOutput::StdOut { message } => (LogStream::Stdout, message),
+                                    LogOutput::StdErr { message } => (LogStream::Stderr, message),
+                                    LogOutput::Console { message } => (LogStream::Console, message),
+                                    LogOutput::StdIn { .. } => continue,
+                                };
+                                let text = String::from_utf8_lossy(&message);
+                                for line in text.lines().map(str::trim_end).filter(|line| !line.is_empty()) {
+                                    match stream {
+                                        LogStream::Stdout => {
+                                            info!(target: "container", hostname, stream = "stdout", "{}", line)
                                         }
-                                    }
-                                    LogOutput::StdErr { message } => {
-                                        if let Ok(line) = String::from_utf8(message.to_vec()) {
-                                            let line = line.trim_end();
-                                            if !line.is_empty() {
-                                                warn!(
-                                                    target: "container",
-                                                    hostname,
-                                                    stream = "stderr",
-                                                    "{}",
-                                                    line
-                                                );
-                                            }
+                                        LogStream::Stderr => {
+                                            warn!(target: "container", hostname, stream = "stderr", "{}", line)
                                         }
-                                    }
-                                    LogOutput::Console { message } => {
-                                        if let Ok(line) = String::from_utf8(message.to_vec()) {
-                                            let line = line.trim_end();
-                                            if !line.is_empty() {
-                                                info!(
-                                                    target: "container",
-                                                    hostname,
-                                                    stream = "console",
-                                                    "{}",
-                                                    line
-                                                );
-                                            }
+                                        LogStream::Console => {
+                                            info!(target: "container", hostname, stream = "console", "{}", line)
                                         }
                                     }
-                                    LogOutput::StdIn { .. } => {}
+                                    logs.record(&hostname, stream, line);
                                 }
                             }
                             Some(Err(e)) => {
@@ -584,10 +872,214 @@ impl DockerManager {
 
         shutdown_tx
     }
+
+    /// Follow the daemon's event stream and report every container that exits
+    ///
+    /// Covers containers stopped, killed or removed out-of-band as well as
+    /// ones that crash. The stream is reopened (after a short delay) when the
+    /// daemon drops it; the task ends when the receiver is dropped.
+    pub fn watch_exits(&self) -> mpsc::UnboundedReceiver<ContainerExit> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let client = self.client.clone();
+        let groups = Arc::clone(&self.groups);
+
+        tokio::spawn(async move {
+            loop {
+                let options = EventsOptions::<String> {
+                    filters: HashMap::from([
+                        ("type".to_string(), vec!["container".to_string()]),
+                        ("event".to_string(), vec!["die".to_string()]),
+                    ]),
+                    ..Default::default()
+                };
+                let mut events = client.events(Some(options));
+                loop {
+                    tokio::select! {
+                        _ = tx.closed() => return,
+                        event = events.next() => match event {
+                            Some(Ok(event)) => {
+                                if let Some(mut exit) = container_exit(&event) {
+                                    // A sidecar exiting takes its backend down with it
+                                    let main = groups
+                                        .iter()
+                                        .find(|group| group.sidecars.contains(&exit.container_id))
+                                        .map(|group| group.key().clone());
+                                    if let Some(main) = main {
+                                        info!(sidecar = %exit.container_id, container_id = %main, "Sidecar exited");
+                                        exit.container_id = main;
+                                    }
+                                    debug!(container_id = %exit.container_id, exit_code = ?exit.exit_code, "Container exited");
+                                    if tx.send(exit).is_err() {
+                                        return;
+                                    }
+                                }
+                            }
+                            Some(Err(e)) => {
+                                warn!(error = %e, "Error reading Docker events");
+                                break;
+                            }
+                            None => break,
+                        }
+                    }
+                }
+                tokio::time::sleep(EVENTS_RECONNECT_DELAY).await;
+            }
+        });
+
+        rx
+    }
+}
+
+impl ContainerRuntime for DockerManager {
+    fn name(&self) -> &'static str {
+        "docker"
+    }
+
+    fn start_container<'a>(
+        &'a self,
+        config: &'a BackendConfig,
+        hostname: &'a str,
+        injected_env: &'a [(String, String)],
+    ) -> BoxFuture<'a, anyhow::Result<String>> {
+        DockerManager::start_container(self, config, hostname, injected_env).boxed()
+    }
+
+    fn stop_container<'a>(&'a self, container_id: &'a str, timeout: Duration) -> BoxFuture<'a, anyhow::Result<()>> {
+        DockerManager::stop_container(self, container_id, timeout).boxed()
+    }
+
+    fn kill_container<'a>(&'a self, container_id: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        DockerManager::kill_container(self, container_id).boxed()
+    }
+
+    fn remove_container<'a>(&'a self, container_id: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        DockerManager::remove_container(self, container_id).boxed()
+    }
+
+    fn is_running<'a>(&'a self, container_id: &'a str) -> BoxFuture<'a, bool> {
+        DockerManager::is_running(self, container_id).boxed()
+    }
+
+    fn stats<'a>(&'a self, container_id: &'a str) -> BoxFuture<'a, anyhow::Result<ContainerStats>> {
+        DockerManager::stats(self, container_id).boxed()
+    }
+
+    fn stream_logs(&self, container_id: String, hostname: String, logs: Arc<BackendLogs>) -> watch::Sender<bool> {
+        DockerManager::stream_logs(self, container_id, hostname, logs)
+    }
+
+    fn watch_exits(&self) -> mpsc::UnboundedReceiver<ContainerExit> {
+        DockerManager::watch_exits(self)
+    }
+}
+
+/// Set the memory and CPU limits of a container
+fn apply_limits(host_config: &mut HostConfig, memory: Option<&str>, cpus: Option<&str>) -> anyhow::Result<()> {
+    if let Some(memory) = memory {
+        host_config.memory = Some(parse_memory_limit(memory)?);
+    }
+    if let Some(cpus) = cpus {
+        let cpu_count: f64 = cpus.parse().map_err(|_| {
+            anyhow::anyhow!("Invalid CPU limit: {}", cpus)
+        })?;
+        // NanoCPUs is CPUs * 1e9
+        host_config.nano_cpus = Some((cpu_count * 1_000_000_000.0) as i64);
+    }
+    Ok(())
+}
+
+/// Whether `image` has to be pulled under `policy`, given the local image if any
+///
+/// With a `platform`, a local image built for another platform counts as
+/// missing for `if-not-present`. `cli` is the command suggested for pulling
+/// by hand when the policy is `never`.
+pub(crate) fn should_pull(
+    image: &str,
+    local: Option<&ImageInspect>,
+    platform: Option<&Platform>,
+    policy: &PullPolicy,
+    cli: &str,
+) -> anyhow::Result<bool> {
+    match (policy, local) {
+        (PullPolicy::Always, _) => Ok(true),
+        (PullPolicy::Never, None) => anyhow::bail!(
+            "Image '{}' not found locally and pull_policy is 'never'. \
+             Pull the image manually with '{} pull {}' or change pull_policy.",
+            image, cli, image
+        ),
+        (PullPolicy::Never, Some(_)) => Ok(false),
+        (PullPolicy::IfNotPresent, Some(inspect)) if platform.is_none_or(|p| p.matches(inspect)) => {
+            debug!(image, "Image exists locally, skipping pull");
+            Ok(false)
+        }
+        (PullPolicy::IfNotPresent, Some(_)) => {
+            debug!(image, "Image exists locally for another platform, pulling");
+            Ok(true)
+        }
+        (PullPolicy::IfNotPresent, None) => Ok(true),
+    }
+}
+
+/// Check that a local image has the pinned digest and platform
+pub(crate) fn check_image(
+    image: &str,
+    inspect: &ImageInspect,
+    digest: Option<&str>,
+    platform: Option<&Platform>,
+) -> anyhow::Result<()> {
+    if let Some(digest) = digest {
+        if !has_digest(inspect, digest) {
+            anyhow::bail!(
+                "Image '{}' does not match the pinned digest {} (repo digests: {})",
+                image,
+                digest,
+                inspect.repo_digests.clone().unwrap_or_default().join(", ")
+            );
+        }
+    }
+    if let Some(platform) = platform {
+        if !platform.matches(inspect) {
+            anyhow::bail!(
+                "Image '{}' is built for {}/{}, not {}",
+                image,
+                inspect.os.as_deref().unwrap_or("unknown"),
+                inspect.architecture.as_deref().unwrap_or("unknown"),
+                platform
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Delay before reopening the Docker event stream
+const EVENTS_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// A container that exited
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContainerExit {
+    pub container_id: String,
+    /// Exit code reported by the daemon (137 for SIGKILL, 143 for SIGTERM)
+    pub exit_code: Option<i64>,
+}
+
+/// The exit reported by a container `die` event
+fn container_exit(event: &EventMessage) -> Option<ContainerExit> {
+    if event.typ != Some(EventMessageTypeEnum::CONTAINER) || event.action.as_deref() != Some("die") {
+        return None;
+    }
+    let actor = event.actor.as_ref()?;
+    Some(ContainerExit {
+        container_id: actor.id.clone()?,
+        exit_code: actor
+            .attributes
+            .as_ref()
+            .and_then(|attributes| attributes.get("exitCode"))
+            .and_then(|code| code.parse().ok()),
+    })
 }
 
 /// Parse memory limit string (e.g., "512m", "1g") to bytes
-fn parse_memory_limit(limit: &str) -> anyhow::Result<i64> {
+pub(crate) fn parse_memory_limit(limit: &str) -> anyhow::Result<i64> {
     let limit = limit.trim().to_lowercase();
     let (num_str, multiplier) = if limit.ends_with("g") || limit.ends_with("gb") {
         let num = limit.trim_end_matches("gb").trim_end_matches("g");
@@ -609,9 +1101,116 @@ fn parse_memory_limit(limit: &str) -> anyhow::Result<i64> {
     Ok((num * multiplier as f64) as i64)
 }
 
+/// Image reference pinned to `digest`, e.g. `myapp@sha256:...`
+///
+/// A tag is dropped, since the digest identifies the image. An image that
+/// already includes a digest is returned as is, unless it conflicts with
+/// `digest`.
+pub(crate) fn pinned_image(image: &str, digest: Option<&str>) -> anyhow::Result<String> {
+    if let Some(existing) = image_digest(image) {
+        validate_digest(existing)?;
+        if let Some(digest) = digest.filter(|digest| *digest != existing) {
+            anyhow::bail!("image '{}' conflicts with image_digest '{}'", image, digest);
+        }
+        return Ok(image.to_string());
+    }
+    let Some(digest) = digest else {
+        return Ok(image.to_string());
+    };
+    validate_digest(digest)?;
+
+    // A colon after the last slash starts the tag (before it, a registry port)
+    let name_start = image.rfind('/').map_or(0, |i| i + 1);
+    let repository = match image[name_start..].find(':') {
+        Some(tag) => &image[..name_start + tag],
+        None => image,
+    };
+    Ok(format!("{}@{}", repository, digest))
+}
+
+/// Registry host of an image reference, "docker.io" for Docker Hub images
+///
+/// Like Docker, the first path component is a registry if it has a `.` or
+/// `:` or is `localhost`.
+pub fn image_registry(image: &str) -> &str {
+    match image.split_once('/') {
+        Some((host, _)) if host.contains(['.', ':']) || host == "localhost" => host,
+        _ => "docker.io",
+    }
+}
+
+/// Digest part of an image reference (`name@digest`)
+pub(crate) fn image_digest(image: &str) -> Option<&str> {
+    image.split_once('@').map(|(_, digest)| digest)
+}
+
+fn validate_digest(digest: &str) -> anyhow::Result<()> {
+    let valid = digest
+        .strip_prefix("sha256:")
+        .is_some_and(|hex| hex.len() == 64 && hex.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')));
+    if !valid {
+        anyhow::bail!("invalid image digest '{}' (expected sha256:<64 hex digits>)", digest);
+    }
+    Ok(())
+}
+
+fn has_digest(inspect: &ImageInspect, digest: &str) -> bool {
+    inspect
+        .repo_digests
+        .iter()
+        .flatten()
+        .any(|repo_digest| image_digest(repo_digest) == Some(digest))
+}
+
+/// Image platform, `os/architecture[/variant]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Platform {
+    pub os: String,
+    pub architecture: String,
+    pub variant: Option<String>,
+}
+
+impl Platform {
+    /// Parse e.g. "linux/amd64" or "linux/arm/v7"
+    pub fn parse(platform: &str) -> anyhow::Result<Self> {
+        let parts: Vec<&str> = platform.split('/').collect();
+        if !(2..=3).contains(&parts.len()) || parts.iter().any(|part| part.is_empty()) {
+            anyhow::bail!("invalid platform '{}' (expected os/arch[/variant], e.g. linux/arm64)", platform);
+        }
+        Ok(Self {
+            os: parts[0].to_string(),
+            architecture: parts[1].to_string(),
+            variant: parts.get(2).map(|variant| variant.to_string()),
+        })
+    }
+
+    /// Whether an image is built for this platform (the variant only counts if set)
+    fn matches(&self, inspect: &ImageInspect) -> bool {
+        inspect.os.as_deref() == Some(self.os.as_str())
+            && inspect.architecture.as_deref() == Some(self.architecture.as_str())
+            && self
+                .variant
+                .as_ref()
+                .is_none_or(|variant| inspect.variant.as_ref() == Some(variant))
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.os, self.architecture)?;
+        if let Some(ref variant) = self.variant {
+            write!(f, "/{}", variant)?;
+        }
+        Ok(())
+    }
+}
+
 /// Wrapper to share DockerManager across tasks
 pub type SharedDockerManager = Arc<DockerManager>;
 
+/// Container runtime shared across tasks
+pub type SharedContainerRuntime = Arc<dyn ContainerRuntime>;
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -626,4 +1225,122 @@ mod tests {
         assert_eq!(parse_memory_limit("1048576").unwrap(), 1048576);
         assert!(parse_memory_limit("invalid").is_err());
     }
+
+    #[test]
+    fn test_container_stats() {
+        let cpu = |total: u64, system: u64| {
+            serde_json::json!({
+                "cpu_usage": { "total_usage": total, "usage_in_usermode": 0, "usage_in_kernelmode": 0 },
+                "system_cpu_usage": system,
+                "online_cpus": 4,
+                "throttling_data": { "periods": 0, "throttled_periods": 0, "throttled_time": 0 }
+            })
+        };
+        let mut memory: serde_json::Map<String, serde_json::Value> = [
+            "anon", "file", "kernel_stack", "slab", "sock", "shmem", "file_mapped", "file_dirty", "file_writeback",
+            "anon_thp", "inactive_anon", "active_anon", "inactive_file", "active_file", "unevictable",
+            "slab_reclaimable", "slab_unreclaimable", "pgfault", "pgmajfault", "workingset_refault",
+            "workingset_activate", "workingset_nodereclaim", "pgrefill", "pgscan", "pgsteal", "pgactivate",
+            "pgdeactivate", "pglazyfree", "pglazyfreed", "thp_fault_alloc", "thp_collapse_alloc",
+        ]
+        .into_iter()
+        .map(|field| (field.to_string(), serde_json::json!(0)))
+        .collect();
+        memory.insert("inactive_file".to_string(), serde_json::json!(1_000_000));
+        let stats: Stats = serde_json::from_value(serde_json::json!({
+            "read": "", "preread": "", "num_procs": 0,
+            "pids_stats": { "current": 3 },
+            "memory_stats": { "usage": 11_000_000, "limit": 536_870_912, "stats": memory },
+            "blkio_stats": {},
+            "cpu_stats": cpu(3_000_000, 20_000_000),
+            "precpu_stats": cpu(1_000_000, 10_000_000),
+            "storage_stats": {}
+        }))
+        .unwrap();
+
+        let usage = ContainerStats::from_docker(&stats);
+        assert_eq!(usage.cpu_percent, 80.0);
+        assert_eq!(usage.memory_bytes, 10_000_000);
+        assert_eq!(usage.memory_limit_bytes, Some(536_870_912));
+        assert_eq!(usage.pids, Some(3));
+    }
+
+    #[test]
+    fn test_image_registry() {
+        assert_eq!(image_registry("nginx"), "docker.io");
+        assert_eq!(image_registry("nginx:1.27"), "docker.io");
+        assert_eq!(image_registry("team/app:1"), "docker.io");
+        assert_eq!(image_registry("ghcr.io/team/app:1"), "ghcr.io");
+        assert_eq!(image_registry("registry.local:5000/app"), "registry.local:5000");
+        assert_eq!(image_registry("localhost/app"), "localhost");
+    }
+
+    #[test]
+    fn test_pinned_image() {
+        let digest = format!("sha256:{}", "ab".repeat(32));
+        let pinned = |image: &str| pinned_image(image, Some(&digest)).unwrap();
+
+        assert_eq!(pinned("myapp"), format!("myapp@{}", digest));
+        assert_eq!(pinned("myapp:1.2"), format!("myapp@{}", digest));
+        assert_eq!(
+            pinned("registry.local:5000/team/myapp:latest"),
+            format!("registry.local:5000/team/myapp@{}", digest)
+        );
+        assert_eq!(pinned(&format!("myapp@{}", digest)), format!("myapp@{}", digest));
+        assert_eq!(pinned_image("myapp:1.2", None).unwrap(), "myapp:1.2");
+        assert_eq!(image_digest(&pinned("myapp")), Some(digest.as_str()));
+
+        assert!(pinned_image("myapp", Some("sha256:1234")).is_err());
+        assert!(pinned_image("myapp", Some(&digest.to_uppercase())).is_err());
+        let other = format!("sha256:{}", "cd".repeat(32));
+        assert!(pinned_image(&format!("myapp@{}", other), Some(&digest)).is_err());
+    }
+
+    #[test]
+    fn test_platform() {
+        let arm = Platform::parse("linux/arm/v7").unwrap();
+        assert_eq!(arm.variant.as_deref(), Some("v7"));
+        assert_eq!(arm.to_string(), "linux/arm/v7");
+        assert!(Platform::parse("linux").is_err());
+        assert!(Platform::parse("linux//v7").is_err());
+        assert!(Platform::parse("linux/arm64/v8/x").is_err());
+
+        let inspect = |architecture: &str, variant: Option<&str>| ImageInspect {
+            os: Some("linux".to_string()),
+            architecture: Some(architecture.to_string()),
+            variant: variant.map(str::to_string),
+            ..Default::default()
+        };
+        let arm64 = Platform::parse("linux/arm64").unwrap();
+        assert!(arm64.matches(&inspect("arm64", Some("v8"))));
+        assert!(!arm64.matches(&inspect("amd64", None)));
+        assert!(!arm.matches(&inspect("arm", Some("v6"))));
+
+        let digest = format!("sha256:{}", "ab".repeat(32));
+        let image = ImageInspect {
+            repo_digests: Some(vec![format!("docker.io/library/myapp@{}", digest)]),
+            ..Default::default()
+        };
+        assert!(has_digest(&image, &digest));
+        assert!(!has_digest(&ImageInspect::default(), &digest));
+    }
+
+    #[test]
+    fn test_container_exit() {
+        let event = |typ, action: &str| EventMessage {
+            typ: Some(typ),
+            action: Some(action.to_string()),
+            actor: Some(bollard::models::EventActor {
+                id: Some("abc123".to_string()),
+                attributes: Some(HashMap::from([("exitCode".to_string(), "137".to_string())])),
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            container_exit(&event(EventMessageTypeEnum::CONTAINER, "die")),
+            Some(ContainerExit { container_id: "abc123".to_string(), exit_code: Some(137) })
+        );
+        assert_eq!(container_exit(&event(EventMessageTypeEnum::CONTAINER, "start")), None);
+        assert_eq!(container_exit(&event(EventMessageTypeEnum::IMAGE, "die")), None);
+    }
 }
@@ -0,0 +1,352 @@
+//! TLS server streams that hand TLS 1.3 early data (0-RTT) to the application
+//!
+//! tokio-rustls only completes an accept once the client's Finished message
+//! arrives, which throws away the round trip 0-RTT is meant to save. The
+//! stream here returns from [`accept`] as soon as the server's handshake
+//! flight is sent and early data is available, serving the early data first
+//! and everything after the handshake from the regular TLS reader.
+//!
+//! Early data can be replayed, so [`EarlyDataStream::handshake_state`] lets
+//! request handlers tell whether the client has completed the handshake yet.
+
+use rustls::{ServerConfig, ServerConnection};
+use std::future::poll_fn;
+use std::io::{self, Read, Write};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Tracks whether a connection's handshake has completed
+#[derive(Debug, Clone, Default)]
+pub struct HandshakeState(Arc<AtomicBool>);
+
+impl HandshakeState {
+    /// Whether the client has completed the handshake, proving the
+    /// connection is not a replay
+    pub fn is_complete(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+
+    fn set_complete(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+/// Enable early data on a server config
+///
+/// Also lets the server send its response before the client's Finished
+/// message arrives, without which early data would save nothing.
+pub fn configure(config: &mut ServerConfig, max_size: u32) {
+    config.max_early_data_size = max_size;
+    config.send_half_rtt_data = true;
+}
+
+/// Accept a TLS connection, returning early once early data has arrived
+pub async fn accept<IO>(config: Arc<ServerConfig>, io: IO) -> io::Result<EarlyDataStream<IO>>
+where
+    IO: AsyncRead + AsyncWrite + Unpin,
+{
+    let conn = ServerConnection::new(config).map_err(io::Error::other)?;
+    let mut stream = EarlyDataStream {
+        io,
+        conn,
+        early: Vec::new(),
+        early_pos: 0,
+        outgoing: Vec::new(),
+        eof: false,
+        closing: false,
+        state: HandshakeState::default(),
+    };
+    poll_fn(|cx| stream.poll_handshake(cx)).await?;
+    Ok(stream)
+}
+
+/// A server-side TLS stream that may start with early data
+pub struct EarlyDataStream<IO> {
+    io: IO,
+    conn: ServerConnection,
+    /// Early data received but not yet read
+    early: Vec<u8>,
+    early_pos: usize,
+    /// Encrypted bytes waiting to be written to `io`
+    outgoing: Vec<u8>,
+    eof: bool,
+    closing: bool,
+    state: HandshakeState,
+}
+
+impl<IO> EarlyDataStream<IO> {
+    pub fn get_ref(&self) -> (&IO, &ServerConnection) {
+        (&self.io, &self.conn)
+    }
+
+    /// Handshake state shared with request handlers
+    pub fn handshake_state(&self) -> HandshakeState {
+        self.state.clone()
+    }
+
+    /// Move newly decrypted early data into the read buffer
+    fn take_early_data(&mut self) -> io::Result<()> {
+        if let Some(mut early) = self.conn.early_data() {
+            if self.early_pos == self.early.len() {
+                self.early.clear();
+                self.early_pos = 0;
+            }
+            early.read_to_end(&mut self.early)?;
+        }
+        Ok(())
+    }
+}
+
+impl<IO> EarlyDataStream<IO>
+where
+    IO: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Write pending TLS records to `io`
+    fn poll_write_tls(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            if self.outgoing.is_empty() {
+                if !self.conn.wants_write() {
+                    return Poll::Ready(Ok(()));
+                }
+                self.conn.write_tls(&mut self.outgoing)?;
+            }
+            let n = ready!(Pin::new(&mut self.io).poll_write(cx, &self.outgoing))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.outgoing.drain(..n);
+        }
+    }
+
+    /// Read and process one batch of TLS records
+    fn poll_read_tls(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut buf = [0u8; 16 * 1024];
+        let mut read_buf = ReadBuf::new(&mut buf);
+        ready!(Pin::new(&mut self.io).poll_read(cx, &mut read_buf))?;
+
+        let mut data = read_buf.filled();
+        if data.is_empty() {
+            self.eof = true;
+        }
+        loop {
+            // Reading an empty slice tells rustls the peer closed the connection
+            self.conn.read_tls(&mut data)?;
+            if let Err(e) = self.conn.process_new_packets() {
+                // Best effort to send the alert before failing
+                let _ = self.poll_write_tls(cx);
+                return Poll::Ready(Err(io::Error::new(io::ErrorKind::InvalidData, e)));
+            }
+            if data.is_empty() {
+                break;
+            }
+        }
+
+        self.take_early_data()?;
+        if !self.conn.is_handshaking() {
+            self.state.set_complete();
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_handshake(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            ready!(self.poll_write_tls(cx))?;
+            if !self.conn.is_handshaking() || self.early_pos < self.early.len() {
+                return Poll::Ready(Ok(()));
+            }
+            if self.eof {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed during TLS handshake",
+                )));
+            }
+            ready!(self.poll_read_tls(cx))?;
+        }
+    }
+}
+
+impl<IO> AsyncRead for EarlyDataStream<IO>
+where
+    IO: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.early_pos < this.early.len() {
+                let n = buf.remaining().min(this.early.len() - this.early_pos);
+                buf.put_slice(&this.early[this.early_pos..this.early_pos + n]);
+                this.early_pos += n;
+                return Poll::Ready(Ok(()));
+            }
+
+            match this.conn.reader().read(buf.initialize_unfilled()) {
+                Ok(n) => {
+                    buf.advance(n);
+                    return Poll::Ready(Ok(()));
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+
+            // Handshake messages and alerts produced by earlier reads
+            if let Poll::Ready(Err(e)) = this.poll_write_tls(cx) {
+                return Poll::Ready(Err(e));
+            }
+            if this.eof {
+                return Poll::Ready(Ok(()));
+            }
+            ready!(this.poll_read_tls(cx))?;
+        }
+    }
+}
+
+impl<IO> AsyncWrite for EarlyDataStream<IO>
+where
+    IO: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            let n = this.conn.writer().write(buf)?;
+            if n > 0 || buf.is_empty() {
+                if let Poll::Ready(Err(e)) = this.poll_write_tls(cx) {
+                    return Poll::Ready(Err(e));
+                }
+                return Poll::Ready(Ok(n));
+            }
+            // Plaintext buffer is full: flush before accepting more
+            ready!(this.poll_write_tls(cx))?;
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_tls(cx))?;
+        Pin::new(&mut this.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.closing {
+            this.conn.send_close_notify();
+            this.closing = true;
+        }
+        ready!(this.poll_write_tls(cx))?;
+        Pin::new(&mut this.io).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+    use rustls::ClientConnection;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+    const REQUEST: &[u8] = b"GET / HTTP/1.1\r\nhost: early.local\r\n\r\n";
+
+    fn configs() -> (Arc<ServerConfig>, Arc<rustls::ClientConfig>) {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec!["early.local".to_string()]).unwrap();
+        let cert_der = CertificateDer::from(cert.der().to_vec());
+        let key = PrivateKeyDer::try_from(key_pair.serialize_der()).unwrap();
+
+        let mut server = ServerConfig::builder_with_provider(Arc::clone(&provider))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert_der.clone()], key)
+            .unwrap();
+        configure(&mut server, 16 * 1024);
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(cert_der).unwrap();
+        let mut client = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        client.enable_early_data = true;
+        (Arc::new(server), Arc::new(client))
+    }
+
+    /// Serve one request, returning whether the handshake was complete when it was read
+    async fn serve_one(config: Arc<ServerConfig>, io: DuplexStream) -> bool {
+        let mut stream = accept(config, io).await.unwrap();
+        let state = stream.handshake_state();
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !request.ends_with(b"\r\n\r\n") {
+            let n = stream.read(&mut buf).await.unwrap();
+            assert!(n > 0);
+            request.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(request, REQUEST);
+        let complete = state.is_complete();
+        stream.write_all(b"ok").await.unwrap();
+        stream.flush().await.unwrap();
+        // Keep the connection open until the client hangs up
+        let _ = stream.read(&mut buf).await;
+        complete
+    }
+
+    /// Drive a client connection until `expected` plaintext has been received
+    async fn client_exchange(client: &mut ClientConnection, io: &mut DuplexStream, expected: &[u8]) {
+        let mut received = Vec::new();
+        let mut buf = [0u8; 16 * 1024];
+        while !received.ends_with(expected) {
+            while client.wants_write() {
+                let mut out = Vec::new();
+                client.write_tls(&mut out).unwrap();
+                io.write_all(&out).await.unwrap();
+            }
+            let n = io.read(&mut buf).await.unwrap();
+            assert!(n > 0, "server closed the connection");
+            client.read_tls(&mut &buf[..n]).unwrap();
+            client.process_new_packets().unwrap();
+            let _ = client.reader().read_to_end(&mut received);
+        }
+        // Send the client's Finished if it is still pending
+        while client.wants_write() {
+            let mut out = Vec::new();
+            client.write_tls(&mut out).unwrap();
+            io.write_all(&out).await.unwrap();
+        }
+    }
+
+    async fn connect(server: Arc<ServerConfig>, client: Arc<rustls::ClientConfig>, early: bool) -> (bool, bool) {
+        let (mut client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let served = tokio::spawn(serve_one(server, server_io));
+
+        let name = ServerName::try_from("early.local").unwrap();
+        let mut conn = ClientConnection::new(client, name).unwrap();
+        if early {
+            conn.early_data().expect("early data offered").write_all(REQUEST).unwrap();
+        } else {
+            conn.writer().write_all(REQUEST).unwrap();
+        }
+        client_exchange(&mut conn, &mut client_io, b"ok").await;
+        let accepted = conn.is_early_data_accepted();
+        drop(client_io);
+        (served.await.unwrap(), accepted)
+    }
+
+    #[tokio::test]
+    async fn test_early_data_is_served_before_handshake_completes() {
+        let (server, client) = configs();
+
+        // The first connection is a full handshake and leaves a resumable session
+        let (complete, accepted) = connect(Arc::clone(&server), Arc::clone(&client), false).await;
+        assert!(complete);
+        assert!(!accepted);
+
+        // The resumed connection's request is read and answered from early data
+        let (complete, accepted) = connect(server, client, true).await;
+        assert!(accepted);
+        assert!(!complete);
+    }
+}
@@ -2,6 +2,7 @@
 
 use http_body_util::{combinators::BoxBody, BodyExt, Full};
 use hyper::body::Bytes;
+use hyper::header::RETRY_AFTER;
 use hyper::{Response, StatusCode};
 use serde::Serialize;
 
@@ -19,12 +20,34 @@ pub enum ProxyErrorCode {
     BackendUnhealthy,
     /// Backend failed to start
     BackendStartFailed,
+    /// Backend is still starting after the request's spawn wait
+    BackendStarting,
+    /// Too many requests waiting for the backend to start
+    ColdStartQueueFull,
     /// Backend configuration error
     BackendConfigError,
     /// Request timed out waiting for backend
     RequestTimeout,
     /// Failed to connect to backend
     ConnectionFailed,
+    /// Request path is outside the backend's allowed paths
+    PathNotAllowed,
+    /// Request path is malformed or rejected by path normalization
+    InvalidPath,
+    /// Request blocked by the WAF rules
+    RequestBlocked,
+    /// Client's TLS fingerprint is on the blocklist, so it may not start backends
+    FingerprintBlocked,
+    /// Client triggered too many backend cold starts
+    ColdStartThrottled,
+    /// Backend used up its traffic quota for the current window
+    QuotaExceeded,
+    /// Request received in TLS early data with a method that may not be replayed
+    TooEarly,
+    /// File requested via internal redirect does not exist or is outside the allowed root
+    FileNotFound,
+    /// Error injected by an enabled fault (status set by the fault config)
+    FaultInjected,
     /// Internal proxy error
     InternalError,
 }
@@ -38,9 +61,20 @@ impl ProxyErrorCode {
             ProxyErrorCode::BackendShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
             ProxyErrorCode::BackendUnhealthy => StatusCode::SERVICE_UNAVAILABLE,
             ProxyErrorCode::BackendStartFailed => StatusCode::SERVICE_UNAVAILABLE,
+            ProxyErrorCode::BackendStarting => StatusCode::SERVICE_UNAVAILABLE,
+            ProxyErrorCode::ColdStartQueueFull => StatusCode::SERVICE_UNAVAILABLE,
             ProxyErrorCode::BackendConfigError => StatusCode::INTERNAL_SERVER_ERROR,
             ProxyErrorCode::RequestTimeout => StatusCode::GATEWAY_TIMEOUT,
             ProxyErrorCode::ConnectionFailed => StatusCode::BAD_GATEWAY,
+            ProxyErrorCode::PathNotAllowed => StatusCode::NOT_FOUND,
+            ProxyErrorCode::InvalidPath => StatusCode::BAD_REQUEST,
+            ProxyErrorCode::RequestBlocked => StatusCode::FORBIDDEN,
+            ProxyErrorCode::FingerprintBlocked => StatusCode::FORBIDDEN,
+            ProxyErrorCode::ColdStartThrottled => StatusCode::TOO_MANY_REQUESTS,
+            ProxyErrorCode::QuotaExceeded => StatusCode::TOO_MANY_REQUESTS,
+            ProxyErrorCode::TooEarly => StatusCode::TOO_EARLY,
+            ProxyErrorCode::FileNotFound => StatusCode::NOT_FOUND,
+            ProxyErrorCode::FaultInjected => StatusCode::INTERNAL_SERVER_ERROR,
             ProxyErrorCode::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -53,16 +87,27 @@ impl ProxyErrorCode {
             ProxyErrorCode::BackendShuttingDown => "BACKEND_SHUTTING_DOWN",
             ProxyErrorCode::BackendUnhealthy => "BACKEND_UNHEALTHY",
             ProxyErrorCode::BackendStartFailed => "BACKEND_START_FAILED",
+            ProxyErrorCode::BackendStarting => "BACKEND_STARTING",
+            ProxyErrorCode::ColdStartQueueFull => "COLD_START_QUEUE_FULL",
             ProxyErrorCode::BackendConfigError => "BACKEND_CONFIG_ERROR",
             ProxyErrorCode::RequestTimeout => "REQUEST_TIMEOUT",
             ProxyErrorCode::ConnectionFailed => "CONNECTION_FAILED",
+            ProxyErrorCode::PathNotAllowed => "PATH_NOT_ALLOWED",
+            ProxyErrorCode::InvalidPath => "INVALID_PATH",
+            ProxyErrorCode::RequestBlocked => "REQUEST_BLOCKED",
+            ProxyErrorCode::FingerprintBlocked => "FINGERPRINT_BLOCKED",
+            ProxyErrorCode::ColdStartThrottled => "COLD_START_THROTTLED",
+            ProxyErrorCode::QuotaExceeded => "QUOTA_EXCEEDED",
+            ProxyErrorCode::TooEarly => "TOO_EARLY",
+            ProxyErrorCode::FileNotFound => "FILE_NOT_FOUND",
+            ProxyErrorCode::FaultInjected => "FAULT_INJECTED",
             ProxyErrorCode::InternalError => "INTERNAL_ERROR",
         }
     }
 }
 
 /// JSON error response body
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ErrorResponse {
     /// The error code
     pub code: ProxyErrorCode,
@@ -70,6 +115,10 @@ pub struct ErrorResponse {
     pub message: String,
     /// HTTP status code (for reference)
     pub status: u16,
+    /// ID of the failed request, as sent to backends in `X-Request-ID`
+    pub request_id: Option<String>,
+    /// Seconds to wait before retrying, if the response has `Retry-After`
+    pub retry_after: Option<u64>,
 }
 
 impl ErrorResponse {
@@ -79,6 +128,8 @@ impl ErrorResponse {
             status: code.status_code().as_u16(),
             code,
             message: message.into(),
+            request_id: None,
+            retry_after: None,
         }
     }
 
@@ -100,16 +151,47 @@ pub fn json_error_response(
     code: ProxyErrorCode,
     message: impl Into<String>,
 ) -> Response<BoxBody<Bytes, hyper::Error>> {
-    let error = ErrorResponse::new(code, message);
-    let status = code.status_code();
+    json_error_response_with_status(code, code.status_code(), message)
+}
+
+/// Create a JSON error response with a status other than the code's default
+pub fn json_error_response_with_status(
+    code: ProxyErrorCode,
+    status: StatusCode,
+    message: impl Into<String>,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let mut error = ErrorResponse::new(code, message);
+    error.status = status.as_u16();
     let body = error.to_json();
 
-    Response::builder()
+    let mut response = Response::builder()
         .status(status)
         .header("Content-Type", "application/json")
         .header("X-Proxy-Error", code.as_header_value())
         .body(Full::new(Bytes::from(body)).map_err(|e| match e {}).boxed())
-        .expect("valid response with StatusCode enum and static headers")
+        .expect("valid response with StatusCode enum and static headers");
+    // Kept until the request ID is known, see `with_request_details`
+    response.extensions_mut().insert(error);
+    response
+}
+
+/// Add the request ID and any `Retry-After` delay to a JSON error response's body
+///
+/// Other responses are returned unchanged.
+pub fn with_request_details(
+    mut response: Response<BoxBody<Bytes, hyper::Error>>,
+    request_id: &str,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let Some(mut error) = response.extensions_mut().remove::<ErrorResponse>() else {
+        return response;
+    };
+    error.request_id = Some(request_id.to_string());
+    error.retry_after = response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok()?.parse().ok());
+    *response.body_mut() = Full::new(Bytes::from(error.to_json())).map_err(|e| match e {}).boxed();
+    response
 }
 
 #[cfg(test)]
@@ -165,6 +247,47 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_json_error_response_with_status() {
+        let response = json_error_response_with_status(
+            ProxyErrorCode::FaultInjected,
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Injected fault",
+        );
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            response.headers().get("X-Proxy-Error").unwrap(),
+            "FAULT_INJECTED"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_with_request_details() {
+        let mut response = json_error_response(ProxyErrorCode::ColdStartThrottled, "Too many requests");
+        response.headers_mut().insert(RETRY_AFTER, 30.into());
+
+        let response = with_request_details(response, "req-123");
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["code"], "COLD_START_THROTTLED");
+        assert_eq!(json["message"], "Too many requests");
+        assert_eq!(json["request_id"], "req-123");
+        assert_eq!(json["retry_after"], 30);
+
+        let response = with_request_details(json_error_response(ProxyErrorCode::RequestTimeout, "Timed out"), "req-456");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["request_id"], "req-456");
+        assert!(json["retry_after"].is_null());
+
+        // Responses from backends are left alone
+        let response = Response::new(Full::new(Bytes::from("ok")).map_err(|e| match e {}).boxed());
+        let body = with_request_details(response, "req-789").into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "ok");
+    }
+
     #[test]
     fn test_error_code_header_values() {
         assert_eq!(
@@ -0,0 +1,272 @@
+//! A/B experiments
+//!
+//! A backend's `experiment` splits the requests for its hostname that no
+//! route matches between variants served by different backends. Clients are
+//! bucketed deterministically: a hash of the experiment name and the client's
+//! key (the `key_cookie` value, or its IP address without one) picks a
+//! variant in proportion to the weights, so the same client lands in the
+//! same variant on every proxy instance.
+//!
+//! The chosen variant is stored in an assignment cookie (`spawngate_exp_` and
+//! the experiment name), which takes precedence over the hash: clients keep
+//! their variant when weights change or their IP address does.
+//!
+//! [`ExperimentMetrics`] counts requests, server errors and latency per
+//! variant for comparing them.
+
+use crate::config::{ExperimentConfig, ExperimentVariant};
+use crate::routing;
+use dashmap::DashMap;
+use hyper::header::{HeaderMap, HeaderValue};
+use hyper::StatusCode;
+use parking_lot::Mutex;
+use ring::digest;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Prefix of assignment cookie names, followed by the experiment name
+pub const COOKIE_PREFIX: &str = "spawngate_exp_";
+
+/// Latencies kept per variant for percentiles
+const LATENCY_SAMPLES: usize = 1000;
+
+/// Variant chosen for a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assignment {
+    pub experiment: String,
+    pub variant: String,
+    /// Backend serving the variant, `None` for the hostname's own backend
+    pub backend: Option<String>,
+    /// `Set-Cookie` value to send when the client wasn't assigned before
+    pub cookie: Option<HeaderValue>,
+}
+
+/// Assign a request to a variant of `experiment`
+pub fn assign(experiment: &ExperimentConfig, headers: &HeaderMap, client_ip: IpAddr) -> Assignment {
+    let cookie_name = format!("{}{}", COOKIE_PREFIX, experiment.name);
+    let assigned = cookie(headers, &cookie_name)
+        .and_then(|name| experiment.variants.iter().find(|v| v.name == name && v.weight > 0));
+    let (variant, cookie) = match assigned {
+        Some(variant) => (variant, None),
+        None => {
+            let key = experiment
+                .key_cookie
+                .as_deref()
+                .and_then(|name| cookie(headers, name))
+                .map_or_else(|| client_ip.to_string(), str::to_string);
+            let variant = bucket(&experiment.name, &key, &experiment.variants);
+            let cookie = format!(
+                "{}={}; Max-Age={}; Path=/; HttpOnly; SameSite=Lax",
+                cookie_name, variant.name, experiment.cookie_max_age_secs
+            );
+            (variant, HeaderValue::from_str(&cookie).ok())
+        }
+    };
+    Assignment {
+        experiment: experiment.name.clone(),
+        variant: variant.name.clone(),
+        backend: variant.backend.clone(),
+        cookie,
+    }
+}
+
+/// Variant for a client key, in proportion to the variants' weights
+fn bucket<'a>(experiment: &str, key: &str, variants: &'a [ExperimentVariant]) -> &'a ExperimentVariant {
+    let total: u64 = variants.iter().map(|v| u64::from(v.weight)).sum();
+    // A stable hash, unlike std's hashers, so every instance and release agrees
+    let hash = digest::digest(&digest::SHA256, format!("{}\0{}", experiment, key).as_bytes());
+    let mut point = u64::from_be_bytes(hash.as_ref()[..8].try_into().expect("8 bytes")) % total.max(1);
+    for variant in variants {
+        if point < u64::from(variant.weight) {
+            return variant;
+        }
+        point -= u64::from(variant.weight);
+    }
+    &variants[0]
+}
+
+/// Value of the first cookie called `name`
+fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    routing::cookies(headers).find(|(cookie, _)| *cookie == name).map(|(_, value)| value)
+}
+
+#[derive(Debug, Default)]
+struct VariantStats {
+    requests: u64,
+    errors: u64,
+    total_latency: Duration,
+    /// Most recent latencies, for percentiles
+    latencies: VecDeque<Duration>,
+}
+
+/// Requests, errors and latency of one variant, as returned by the admin API
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct VariantReport {
+    pub experiment: String,
+    pub variant: String,
+    pub requests: u64,
+    /// Responses with a 5xx status
+    pub errors: u64,
+    pub error_rate: f64,
+    pub mean_ms: f64,
+    /// Percentiles over the last 1000 requests
+    pub p50_ms: u64,
+    pub p95_ms: u64,
+}
+
+/// Per-variant request metrics of every experiment
+#[derive(Default)]
+pub struct ExperimentMetrics {
+    variants: DashMap<(String, String), Mutex<VariantStats>>,
+}
+
+impl ExperimentMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a request served for `assignment`
+    pub fn record(&self, assignment: &Assignment, status: StatusCode, latency: Duration) {
+        let key = (assignment.experiment.clone(), assignment.variant.clone());
+        let entry = self.variants.entry(key).or_default();
+        let mut stats = entry.lock();
+        stats.requests += 1;
+        if status.is_server_error() {
+            stats.errors += 1;
+        }
+        stats.total_latency += latency;
+        if stats.latencies.len() == LATENCY_SAMPLES {
+            stats.latencies.pop_front();
+        }
+        stats.latencies.push_back(latency);
+    }
+
+    /// Every variant that served requests, by experiment and variant name
+    pub fn report(&self) -> Vec<VariantReport> {
+        let mut report: Vec<VariantReport> = self
+            .variants
+            .iter()
+            .map(|entry| {
+                let (experiment, variant) = entry.key().clone();
+                let stats = entry.value().lock();
+                let mut latencies: Vec<Duration> = stats.latencies.iter().copied().collect();
+                latencies.sort();
+                let requests = stats.requests.max(1) as f64;
+                VariantReport {
+                    experiment,
+                    variant,
+                    requests: stats.requests,
+                    errors: stats.errors,
+                    error_rate: stats.errors as f64 / requests,
+                    mean_ms: stats.total_latency.as_secs_f64() * 1000.0 / requests,
+                    p50_ms: percentile(&latencies, 0.50),
+                    p95_ms: percentile(&latencies, 0.95),
+                }
+            })
+            .collect();
+        report.sort_by(|a, b| (&a.experiment, &a.variant).cmp(&(&b.experiment, &b.variant)));
+        report
+    }
+}
+
+/// Nearest-rank percentile of sorted latencies, in milliseconds
+fn percentile(sorted: &[Duration], p: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = ((p * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len());
+    sorted[rank - 1].as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::COOKIE;
+
+    fn experiment() -> ExperimentConfig {
+        ExperimentConfig {
+            name: "checkout".to_string(),
+            key_cookie: Some("session".to_string()),
+            cookie_max_age_secs: 3600,
+            variants: vec![
+                ExperimentVariant {
+                    name: "control".to_string(),
+                    backend: None,
+                    weight: 3,
+                },
+                ExperimentVariant {
+                    name: "redesign".to_string(),
+                    backend: Some("checkout-b.internal".to_string()),
+                    weight: 1,
+                },
+            ],
+        }
+    }
+
+    fn headers(cookie: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, HeaderValue::from_str(cookie).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_assignment_is_sticky() {
+        let experiment = experiment();
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+
+        let first = assign(&experiment, &headers("session=abc"), ip);
+        let cookie = first.cookie.clone().unwrap();
+        assert!(cookie.to_str().unwrap().starts_with(&format!("spawngate_exp_checkout={};", first.variant)));
+        assert!(cookie.to_str().unwrap().contains("Max-Age=3600"));
+        // Same key, same variant, from any address
+        let again = assign(&experiment, &headers("session=abc"), "198.51.100.1".parse().unwrap());
+        assert_eq!(again.variant, first.variant);
+
+        // The assignment cookie wins and isn't set again
+        let assigned = assign(&experiment, &headers("session=abc; spawngate_exp_checkout=redesign"), ip);
+        assert_eq!(assigned.variant, "redesign");
+        assert_eq!(assigned.backend.as_deref(), Some("checkout-b.internal"));
+        assert!(assigned.cookie.is_none());
+
+        // Unknown variants are reassigned
+        let stale = assign(&experiment, &headers("spawngate_exp_checkout=old"), ip);
+        assert!(stale.cookie.is_some());
+    }
+
+    #[test]
+    fn test_bucketing_follows_weights() {
+        let experiment = experiment();
+        let redesign = (0..4000)
+            .map(|i| bucket(&experiment.name, &format!("client-{}", i), &experiment.variants))
+            .filter(|v| v.name == "redesign")
+            .count();
+        // A quarter of the clients, give or take
+        assert!((800..1200).contains(&redesign), "{} clients in redesign", redesign);
+
+        let mut off = experiment.variants.clone();
+        off[1].weight = 0;
+        assert!((0..100).all(|i| bucket("checkout", &i.to_string(), &off).name == "control"));
+    }
+
+    #[test]
+    fn test_metrics_report() {
+        let metrics = ExperimentMetrics::new();
+        let assignment = assign(&experiment(), &headers("spawngate_exp_checkout=control"), "::1".parse().unwrap());
+        for ms in 1..=100 {
+            metrics.record(&assignment, StatusCode::OK, Duration::from_millis(ms));
+        }
+        metrics.record(&assignment, StatusCode::BAD_GATEWAY, Duration::from_millis(101));
+
+        let report = metrics.report();
+        assert_eq!(report.len(), 1);
+        let control = &report[0];
+        assert_eq!((control.experiment.as_str(), control.variant.as_str()), ("checkout", "control"));
+        assert_eq!(control.requests, 101);
+        assert_eq!(control.errors, 1);
+        assert_eq!(control.p50_ms, 51);
+        assert_eq!(control.p95_ms, 96);
+        assert!((control.mean_ms - 51.0).abs() < 0.01);
+    }
+}
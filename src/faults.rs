@@ -0,0 +1,229 @@
+//! Fault injection for resilience testing
+//!
+//! A backend's `faults` config describes what to inject; nothing happens
+//! until the faults are enabled through the admin API. Enabled faults expire
+//! on their own after the configured duration, so a forgotten test cannot
+//! keep breaking a backend.
+
+use crate::config::FaultConfig;
+use dashmap::DashMap;
+use hyper::StatusCode;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// What happens to a request instead of being forwarded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultAction {
+    /// Answer with this status
+    Error(StatusCode),
+    /// Close the client connection without a response
+    Drop,
+}
+
+/// Faults drawn for one request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RequestFaults {
+    /// Delay before the request is forwarded or the action taken
+    pub delay: Option<Duration>,
+    pub action: Option<FaultAction>,
+}
+
+/// Response extension asking the server to drop the client connection
+#[derive(Debug, Clone, Copy)]
+pub struct DropConnection;
+
+#[derive(Debug)]
+struct ActiveFaults {
+    config: FaultConfig,
+    until: Instant,
+}
+
+/// Faults enabled for a backend, as returned by `GET /faults`
+#[derive(Debug, Clone, Serialize)]
+pub struct FaultStatus {
+    pub hostname: String,
+    pub remaining_secs: u64,
+    pub error_rate: f64,
+    pub error_status: u16,
+    pub latency_ms: u64,
+    pub latency_rate: f64,
+    pub drop_rate: f64,
+    pub spawn_failure_rate: f64,
+}
+
+/// Faults currently enabled, per backend
+#[derive(Debug, Default)]
+pub struct FaultInjector {
+    active: DashMap<String, ActiveFaults>,
+}
+
+impl FaultInjector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inject `config`'s faults into a backend's traffic for `duration`
+    ///
+    /// Replaces faults already enabled for the backend.
+    pub fn enable(&self, hostname: &str, config: FaultConfig, duration: Duration) {
+        info!(hostname, duration_secs = duration.as_secs(), "Fault injection enabled");
+        self.active.insert(
+            hostname.to_string(),
+            ActiveFaults {
+                config,
+                until: Instant::now() + duration,
+            },
+        );
+    }
+
+    /// Stop injecting faults, returns true if any were enabled
+    pub fn disable(&self, hostname: &str) -> bool {
+        let removed = self.active.remove(hostname).is_some_and(|(_, f)| f.until > Instant::now());
+        if removed {
+            info!(hostname, "Fault injection disabled");
+        }
+        removed
+    }
+
+    /// Backends with faults enabled, sorted by hostname
+    pub fn active(&self) -> Vec<FaultStatus> {
+        let now = Instant::now();
+        self.active.retain(|hostname, faults| Self::unexpired(hostname, faults, now));
+
+        let mut statuses: Vec<FaultStatus> = self
+            .active
+            .iter()
+            .map(|entry| {
+                let config = &entry.config;
+                FaultStatus {
+                    hostname: entry.key().clone(),
+                    remaining_secs: entry.until.saturating_duration_since(now).as_secs(),
+                    error_rate: config.error_rate,
+                    error_status: config.error_status,
+                    latency_ms: config.latency_ms,
+                    latency_rate: config.latency_rate,
+                    drop_rate: config.drop_rate,
+                    spawn_failure_rate: config.spawn_failure_rate,
+                }
+            })
+            .collect();
+        statuses.sort_by(|a, b| a.hostname.cmp(&b.hostname));
+        statuses
+    }
+
+    /// Draw the faults for a request to `hostname`
+    pub fn request_faults(&self, hostname: &str) -> RequestFaults {
+        let Some(config) = self.config(hostname) else {
+            return RequestFaults::default();
+        };
+
+        let delay = (config.latency_ms > 0 && chance(config.latency_rate))
+            .then(|| Duration::from_millis(config.latency_ms));
+        let action = if chance(config.drop_rate) {
+            Some(FaultAction::Drop)
+        } else if chance(config.error_rate) {
+            let status = StatusCode::from_u16(config.error_status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+            Some(FaultAction::Error(status))
+        } else {
+            None
+        };
+        RequestFaults { delay, action }
+    }
+
+    /// Whether a spawn of `hostname` should fail
+    pub fn spawn_fails(&self, hostname: &str) -> bool {
+        self.config(hostname).is_some_and(|config| chance(config.spawn_failure_rate))
+    }
+
+    /// The backend's enabled faults, removing them once expired
+    fn config(&self, hostname: &str) -> Option<FaultConfig> {
+        // Cheap check first: most requests go to backends without faults
+        if self.active.is_empty() {
+            return None;
+        }
+        let now = Instant::now();
+        self.active
+            .remove_if(hostname, |hostname, faults| !Self::unexpired(hostname, faults, now));
+        self.active.get(hostname).map(|faults| faults.config.clone())
+    }
+
+    fn unexpired(hostname: &str, faults: &ActiveFaults, now: Instant) -> bool {
+        let unexpired = faults.until > now;
+        if !unexpired {
+            info!(hostname, "Fault injection expired");
+        }
+        unexpired
+    }
+}
+
+/// True with probability `rate`
+fn chance(rate: f64) -> bool {
+    if rate <= 0.0 {
+        return false;
+    }
+    // The low 62 bits of a v4 UUID are random
+    let bits = uuid::Uuid::new_v4().as_u128() as u64 & ((1 << 53) - 1);
+    let point = bits as f64 / (1u64 << 53) as f64;
+    point < rate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_faults_only_while_enabled() {
+        let injector = FaultInjector::new();
+        let config = FaultConfig {
+            error_rate: 1.0,
+            error_status: 503,
+            latency_ms: 250,
+            spawn_failure_rate: 1.0,
+            ..Default::default()
+        };
+        assert_eq!(injector.request_faults("api.local"), RequestFaults::default());
+        assert!(!injector.spawn_fails("api.local"));
+
+        injector.enable("api.local", config, Duration::from_secs(60));
+        assert_eq!(
+            injector.request_faults("api.local"),
+            RequestFaults {
+                delay: Some(Duration::from_millis(250)),
+                action: Some(FaultAction::Error(StatusCode::SERVICE_UNAVAILABLE)),
+            }
+        );
+        assert!(injector.spawn_fails("api.local"));
+        assert_eq!(injector.request_faults("other.local"), RequestFaults::default());
+
+        let active = injector.active();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].hostname, "api.local");
+        assert!(active[0].remaining_secs > 50);
+
+        assert!(injector.disable("api.local"));
+        assert!(!injector.disable("api.local"));
+        assert_eq!(injector.request_faults("api.local"), RequestFaults::default());
+    }
+
+    #[test]
+    fn test_faults_expire() {
+        let injector = FaultInjector::new();
+        let config = FaultConfig {
+            drop_rate: 1.0,
+            ..Default::default()
+        };
+        injector.enable("api.local", config, Duration::ZERO);
+        assert_eq!(injector.request_faults("api.local"), RequestFaults::default());
+        assert!(injector.active().is_empty());
+        assert!(!injector.disable("api.local"));
+    }
+
+    #[test]
+    fn test_chance_follows_rate() {
+        assert!(!(0..1000).any(|_| chance(0.0)));
+        assert!((0..1000).all(|_| chance(1.0)));
+        let hits = (0..4000).filter(|_| chance(0.25)).count();
+        assert!((800..1200).contains(&hits), "hit {} of 4000", hits);
+    }
+}
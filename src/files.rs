@@ -0,0 +1,459 @@
+//! Serving files from disk
+//!
+//! Used for X-Accel-Redirect / X-Sendfile responses, where a backend hands
+//! the actual byte transfer back to the proxy.
+
+use crate::error::{json_error_response, ProxyErrorCode};
+use crate::proxy::ProxyResponse;
+use futures::StreamExt;
+use http_body_util::{BodyExt, Empty, StreamBody};
+use hyper::body::{Bytes, Frame};
+use hyper::header::{
+    HeaderMap, HeaderName, HeaderValue, ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE,
+    ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED, RANGE,
+};
+use hyper::{Response, StatusCode};
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use tracing::warn;
+
+/// Headers from the backend response that must not be copied onto the file response
+const DROPPED_HEADERS: &[&str] = &[
+    "content-length",
+    "transfer-encoding",
+    "content-encoding",
+    "content-range",
+    "x-accel-redirect",
+    "x-sendfile",
+];
+
+/// Canonicalize `path` and make sure it lies inside `root` (after following symlinks)
+pub async fn resolve_within(root: &Path, path: &Path) -> Option<PathBuf> {
+    let root = tokio::fs::canonicalize(root).await.ok()?;
+    let path = tokio::fs::canonicalize(path).await.ok()?;
+    path.starts_with(&root).then_some(path)
+}
+
+/// Guess a Content-Type from the file extension
+pub fn content_type_for(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+
+    match ext.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+        Some("gz") => "application/gzip",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        Some("mp3") => "audio/mpeg",
+        Some("wasm") => "application/wasm",
+        Some("woff2") => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+/// A satisfiable byte range (inclusive end)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+/// Result of evaluating a Range header against a file size
+#[derive(Debug, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// No usable Range header: serve the whole file
+    Full,
+    /// Serve this single range with 206
+    Partial(ByteRange),
+    /// Range cannot be satisfied: respond 416
+    Unsatisfiable,
+}
+
+/// Parse a `Range` header for a file of `size` bytes
+///
+/// Only single `bytes=` ranges are honored; multi-range and malformed
+/// headers fall back to a full response, as RFC 9110 allows.
+pub fn parse_range(value: &str, size: u64) -> RangeRequest {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+    if spec.contains(',') {
+        return RangeRequest::Full;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return RangeRequest::Full;
+    };
+
+    let range = match (start.trim(), end.trim()) {
+        // Suffix range: last N bytes
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => return RangeRequest::Unsatisfiable,
+            Ok(n) if size > 0 => ByteRange {
+                start: size.saturating_sub(n),
+                end: size - 1,
+            },
+            Ok(_) => return RangeRequest::Unsatisfiable,
+            Err(_) => return RangeRequest::Full,
+        },
+        (start, end) => {
+            let Ok(start) = start.parse::<u64>() else {
+                return RangeRequest::Full;
+            };
+            let end = match end {
+                "" => size.saturating_sub(1),
+                end => match end.parse::<u64>() {
+                    Ok(end) if end >= start => end.min(size.saturating_sub(1)),
+                    _ => return RangeRequest::Full,
+                },
+            };
+            if start >= size {
+                return RangeRequest::Unsatisfiable;
+            }
+            ByteRange { start, end }
+        }
+    };
+
+    RangeRequest::Partial(range)
+}
+
+/// Request headers relevant to file responses
+const FILE_REQUEST_HEADERS: &[HeaderName] = &[RANGE, IF_RANGE, IF_NONE_MATCH, IF_MODIFIED_SINCE];
+
+/// Strong validator derived from file size and modification time
+fn file_etag(metadata: &std::fs::Metadata) -> String {
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    format!("\"{:x}-{:x}\"", metadata.len(), mtime)
+}
+
+/// Whether an If-None-Match header matches `etag` (weak comparison)
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+/// Whether the client's cached copy is still current (RFC 9110 section 13.2.2)
+fn not_modified(request_headers: &HeaderMap, etag: &str, modified: Option<SystemTime>) -> bool {
+    // If-None-Match takes precedence over If-Modified-Since
+    if let Some(value) = request_headers.get(IF_NONE_MATCH) {
+        return value.to_str().is_ok_and(|v| etag_matches(v, etag));
+    }
+
+    let since = request_headers
+        .get(IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| httpdate::parse_http_date(v).ok());
+    match (since, modified) {
+        // HTTP dates have one-second resolution
+        (Some(since), Some(modified)) => httpdate::HttpDate::from(modified) <= httpdate::HttpDate::from(since),
+        _ => false,
+    }
+}
+
+/// Copy the request headers that `serve_file` looks at
+pub fn file_request_headers(headers: &HeaderMap) -> HeaderMap {
+    let mut selected = HeaderMap::new();
+    for name in FILE_REQUEST_HEADERS {
+        if let Some(value) = headers.get(name) {
+            selected.insert(name.clone(), value.clone());
+        }
+    }
+    selected
+}
+
+/// Serve a file that must live under `root`
+///
+/// `headers` are carried over from the backend response (e.g. Content-Type,
+/// Content-Disposition, Cache-Control); length and encoding headers are
+/// replaced with the file's own. `request_headers` supplies Range/If-Range.
+pub async fn serve_file(
+    root: &Path,
+    path: &Path,
+    mut headers: HeaderMap,
+    request_headers: &HeaderMap,
+) -> ProxyResponse {
+    let Some(path) = resolve_within(root, path).await else {
+        warn!(root = %root.display(), path = %path.display(), "Internal redirect target missing or outside root");
+        return json_error_response(ProxyErrorCode::FileNotFound, "File not found");
+    };
+
+    let mut file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(e) => {
+            warn!(path = %path.display(), error = %e, "Failed to open file");
+            return json_error_response(ProxyErrorCode::FileNotFound, "File not found");
+        }
+    };
+
+    let metadata = match file.metadata().await {
+        Ok(metadata) if metadata.is_file() => metadata,
+        _ => return json_error_response(ProxyErrorCode::FileNotFound, "File not found"),
+    };
+    let size = metadata.len();
+    let modified = metadata.modified().ok();
+    let last_modified = modified.map(httpdate::fmt_http_date);
+    let etag = file_etag(&metadata);
+
+    for name in DROPPED_HEADERS {
+        headers.remove(*name);
+    }
+    if !headers.contains_key(CONTENT_TYPE) {
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type_for(&path)));
+    }
+    headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    headers.insert(ETAG, HeaderValue::from_str(&etag).expect("valid header"));
+    if let Some(value) = last_modified.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+        headers.insert(LAST_MODIFIED, value);
+    }
+
+    if not_modified(request_headers, &etag, modified) {
+        headers.remove(CONTENT_TYPE);
+        let mut response = Response::new(Empty::<Bytes>::new().map_err(|never| match never {}).boxed());
+        *response.status_mut() = StatusCode::NOT_MODIFIED;
+        *response.headers_mut() = headers;
+        return response;
+    }
+
+    // If-Range: only honor Range when the validator (ETag or date) still matches
+    let range_allowed = match request_headers.get(IF_RANGE) {
+        None => true,
+        Some(value) => {
+            value.as_bytes() == etag.as_bytes()
+                || last_modified.as_deref().is_some_and(|lm| value.as_bytes() == lm.as_bytes())
+        }
+    };
+    let range = match request_headers.get(RANGE).and_then(|v| v.to_str().ok()) {
+        Some(value) if range_allowed => parse_range(value, size),
+        _ => RangeRequest::Full,
+    };
+
+    let (status, range) = match range {
+        RangeRequest::Full => (StatusCode::OK, ByteRange { start: 0, end: size.saturating_sub(1) }),
+        RangeRequest::Partial(range) => {
+            let content_range = format!("bytes {}-{}/{}", range.start, range.end, size);
+            headers.insert(CONTENT_RANGE, HeaderValue::from_str(&content_range).expect("valid header"));
+            (StatusCode::PARTIAL_CONTENT, range)
+        }
+        RangeRequest::Unsatisfiable => {
+            headers.insert(CONTENT_RANGE, HeaderValue::from_str(&format!("bytes */{}", size)).expect("valid header"));
+            headers.insert(CONTENT_LENGTH, HeaderValue::from(0u64));
+            let mut response = Response::new(Empty::<Bytes>::new().map_err(|never| match never {}).boxed());
+            *response.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+            *response.headers_mut() = headers;
+            return response;
+        }
+    };
+
+    let length = if size == 0 { 0 } else { range.end - range.start + 1 };
+    headers.insert(CONTENT_LENGTH, HeaderValue::from(length));
+
+    if range.start > 0 {
+        if let Err(e) = file.seek(SeekFrom::Start(range.start)).await {
+            warn!(path = %path.display(), error = %e, "Failed to seek file");
+            return json_error_response(ProxyErrorCode::InternalError, "Failed to read file");
+        }
+    }
+
+    // ReaderStream ends after yielding an error; the truncated body makes
+    // hyper abort the connection since Content-Length is not met
+    let stream = ReaderStream::new(file.take(length)).filter_map(|chunk| {
+        futures::future::ready(match chunk {
+            Ok(bytes) => Some(Ok(Frame::data(bytes))),
+            Err(e) => {
+                warn!(error = %e, "File read failed mid-stream");
+                None
+            }
+        })
+    });
+
+    let mut response = Response::new(BodyExt::boxed(StreamBody::new(stream)));
+    *response.status_mut() = status;
+    *response.headers_mut() = headers;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_root(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("spawngate-files-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_content_type_for() {
+        assert_eq!(content_type_for(Path::new("a/b.PNG")), "image/png");
+        assert_eq!(content_type_for(Path::new("report.pdf")), "application/pdf");
+        assert_eq!(content_type_for(Path::new("noext")), "application/octet-stream");
+    }
+
+    #[tokio::test]
+    async fn test_resolve_within_rejects_traversal() {
+        let root = temp_root("traversal");
+        std::fs::write(root.join("inside.txt"), "ok").unwrap();
+
+        assert!(resolve_within(&root, &root.join("inside.txt")).await.is_some());
+        assert!(resolve_within(&root, &root.join("../")).await.is_none());
+        assert!(resolve_within(&root, &root.join("missing.txt")).await.is_none());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_serve_file() {
+        let root = temp_root("serve");
+        std::fs::write(root.join("report.txt"), "hello file").unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("content-disposition", HeaderValue::from_static("attachment"));
+        headers.insert("content-length", HeaderValue::from_static("0"));
+
+        let response = serve_file(&root, &root.join("report.txt"), headers, &HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-length"], "10");
+        assert_eq!(response.headers()["content-type"], "text/plain; charset=utf-8");
+        assert_eq!(response.headers()["content-disposition"], "attachment");
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"hello file");
+
+        let response = serve_file(&root, &root.join("missing.txt"), HeaderMap::new(), &HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_parse_range() {
+        let partial = |start, end| RangeRequest::Partial(ByteRange { start, end });
+
+        assert_eq!(parse_range("bytes=0-9", 100), partial(0, 9));
+        assert_eq!(parse_range("bytes=90-", 100), partial(90, 99));
+        assert_eq!(parse_range("bytes=-10", 100), partial(90, 99));
+        assert_eq!(parse_range("bytes=-500", 100), partial(0, 99));
+        assert_eq!(parse_range("bytes=50-500", 100), partial(50, 99));
+
+        assert_eq!(parse_range("bytes=100-", 100), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 100), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-5", 0), RangeRequest::Unsatisfiable);
+
+        assert_eq!(parse_range("bytes=0-1,5-6", 100), RangeRequest::Full);
+        assert_eq!(parse_range("items=0-1", 100), RangeRequest::Full);
+        assert_eq!(parse_range("bytes=9-1", 100), RangeRequest::Full);
+        assert_eq!(parse_range("bytes=abc", 100), RangeRequest::Full);
+    }
+
+    #[tokio::test]
+    async fn test_serve_file_range() {
+        let root = temp_root("range");
+        let path = root.join("data.bin");
+        std::fs::write(&path, "0123456789").unwrap();
+
+        let mut request = HeaderMap::new();
+        request.insert(RANGE, HeaderValue::from_static("bytes=2-5"));
+        let response = serve_file(&root, &path, HeaderMap::new(), &request).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[CONTENT_RANGE], "bytes 2-5/10");
+        assert_eq!(response.headers()[CONTENT_LENGTH], "4");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"2345");
+
+        // Stale If-Range validator: full response
+        request.insert(IF_RANGE, HeaderValue::from_static("Thu, 01 Jan 1970 00:00:00 GMT"));
+        let response = serve_file(&root, &path, HeaderMap::new(), &request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[ACCEPT_RANGES], "bytes");
+
+        // Matching If-Range validator: partial response
+        let last_modified = response.headers()[LAST_MODIFIED].clone();
+        request.insert(IF_RANGE, last_modified);
+        let response = serve_file(&root, &path, HeaderMap::new(), &request).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+
+        let mut request = HeaderMap::new();
+        request.insert(RANGE, HeaderValue::from_static("bytes=20-"));
+        let response = serve_file(&root, &path, HeaderMap::new(), &request).await;
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()[CONTENT_RANGE], "bytes */10");
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_etag_matches() {
+        assert!(etag_matches("\"abc\"", "\"abc\""));
+        assert!(etag_matches("\"x\", W/\"abc\"", "\"abc\""));
+        assert!(etag_matches("*", "\"abc\""));
+        assert!(!etag_matches("\"abd\"", "\"abc\""));
+    }
+
+    #[tokio::test]
+    async fn test_serve_file_conditional() {
+        let root = temp_root("conditional");
+        let path = root.join("page.html");
+        std::fs::write(&path, "<p>hi</p>").unwrap();
+
+        let response = serve_file(&root, &path, HeaderMap::new(), &HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()[ETAG].clone();
+        let last_modified = response.headers()[LAST_MODIFIED].clone();
+
+        let mut request = HeaderMap::new();
+        request.insert(IF_NONE_MATCH, etag.clone());
+        let response = serve_file(&root, &path, HeaderMap::new(), &request).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[ETAG], etag);
+
+        // Mismatched If-None-Match wins over a matching If-Modified-Since
+        request.insert(IF_NONE_MATCH, HeaderValue::from_static("\"stale\""));
+        request.insert(IF_MODIFIED_SINCE, last_modified.clone());
+        let response = serve_file(&root, &path, HeaderMap::new(), &request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let mut request = HeaderMap::new();
+        request.insert(IF_MODIFIED_SINCE, last_modified);
+        let response = serve_file(&root, &path, HeaderMap::new(), &request).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let mut request = HeaderMap::new();
+        request.insert(IF_MODIFIED_SINCE, HeaderValue::from_static("Thu, 01 Jan 1970 00:00:00 GMT"));
+        let response = serve_file(&root, &path, HeaderMap::new(), &request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // If-Range accepts the ETag too
+        let mut request = HeaderMap::new();
+        request.insert(RANGE, HeaderValue::from_static("bytes=0-2"));
+        request.insert(IF_RANGE, etag);
+        let response = serve_file(&root, &path, HeaderMap::new(), &request).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
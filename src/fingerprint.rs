@@ -0,0 +1,490 @@
+//! JA3 and JA4 fingerprints of TLS clients
+//!
+//! The ClientHello is read off the connection before rustls sees it, then
+//! replayed to the TLS acceptor. The fields fingerprints are built from (the
+//! order of extensions, elliptic curves and point formats, GREASE values) are
+//! not exposed by rustls, so the ClientHello is parsed here.
+//!
+//! - JA3: MD5 of `version,ciphers,extensions,curves,point formats`, in the order sent
+//! - JA4: `t13d1516h2_<ciphers>_<extensions>` with sorted, truncated SHA-256 hashes,
+//!   so it doesn't change when a client shuffles its extensions
+//!
+//! GREASE values (RFC 8701) are left out of both.
+
+use crate::config::{FingerprintBlock, TlsFingerprintConfig};
+use md5::{Digest, Md5};
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Largest ClientHello read before giving up on fingerprinting the connection
+const MAX_CLIENT_HELLO: usize = 64 * 1024;
+
+const TLS_HANDSHAKE: u8 = 0x16;
+const CLIENT_HELLO: u8 = 0x01;
+
+const EXT_SERVER_NAME: u16 = 0x0000;
+const EXT_SUPPORTED_GROUPS: u16 = 0x000a;
+const EXT_EC_POINT_FORMATS: u16 = 0x000b;
+const EXT_SIGNATURE_ALGORITHMS: u16 = 0x000d;
+const EXT_ALPN: u16 = 0x0010;
+const EXT_SUPPORTED_VERSIONS: u16 = 0x002b;
+
+/// Fingerprints of a TLS client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsFingerprint {
+    /// MD5 of the JA3 string, as lowercase hex
+    pub ja3: String,
+    pub ja4: String,
+    /// Either fingerprint is on the blocklist
+    pub blocked: bool,
+}
+
+/// The ClientHello fields fingerprints are built from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientHello {
+    pub legacy_version: u16,
+    pub ciphers: Vec<u16>,
+    /// Extension types, in the order sent
+    pub extensions: Vec<u16>,
+    pub groups: Vec<u16>,
+    pub point_formats: Vec<u8>,
+    pub signature_algorithms: Vec<u16>,
+    pub supported_versions: Vec<u16>,
+    /// First protocol offered via ALPN
+    pub alpn: Option<Vec<u8>>,
+}
+
+impl ClientHello {
+    /// Parse a ClientHello handshake message, including its 4-byte header
+    pub fn parse(message: &[u8]) -> Option<Self> {
+        let mut reader = Reader(message);
+        if reader.u8()? != CLIENT_HELLO {
+            return None;
+        }
+        let len = reader.u24()?;
+        let mut body = Reader(reader.bytes(len)?);
+
+        let mut hello = ClientHello {
+            legacy_version: body.u16()?,
+            ..Default::default()
+        };
+        body.bytes(32)?; // random
+        let session_id = body.u8()? as usize;
+        body.bytes(session_id)?;
+        let mut ciphers = Reader(body.vec16()?);
+        while !ciphers.is_empty() {
+            hello.ciphers.push(ciphers.u16()?);
+        }
+        let compression = body.u8()? as usize;
+        body.bytes(compression)?;
+        if body.is_empty() {
+            return Some(hello);
+        }
+
+        let mut extensions = Reader(body.vec16()?);
+        while !extensions.is_empty() {
+            let typ = extensions.u16()?;
+            let mut data = Reader(extensions.vec16()?);
+            hello.extensions.push(typ);
+            match typ {
+                EXT_SUPPORTED_GROUPS => hello.groups = data.list16()?,
+                EXT_SIGNATURE_ALGORITHMS => hello.signature_algorithms = data.list16()?,
+                EXT_EC_POINT_FORMATS => {
+                    let len = data.u8()? as usize;
+                    hello.point_formats = data.bytes(len)?.to_vec();
+                }
+                EXT_SUPPORTED_VERSIONS => {
+                    let len = data.u8()? as usize;
+                    let mut versions = Reader(data.bytes(len)?);
+                    while !versions.is_empty() {
+                        hello.supported_versions.push(versions.u16()?);
+                    }
+                }
+                EXT_ALPN => {
+                    let mut protocols = Reader(data.vec16()?);
+                    let len = protocols.u8()? as usize;
+                    hello.alpn = Some(protocols.bytes(len)?.to_vec());
+                }
+                _ => {}
+            }
+        }
+        Some(hello)
+    }
+
+    /// The JA3 string, before hashing
+    pub fn ja3_string(&self) -> String {
+        fn join<T: ToString>(values: impl Iterator<Item = T>) -> String {
+            values.map(|v| v.to_string()).collect::<Vec<_>>().join("-")
+        }
+        format!(
+            "{},{},{},{},{}",
+            self.legacy_version,
+            join(self.ciphers.iter().filter(|c| !is_grease(**c))),
+            join(self.extensions.iter().filter(|e| !is_grease(**e))),
+            join(self.groups.iter().filter(|g| !is_grease(**g))),
+            join(self.point_formats.iter()),
+        )
+    }
+
+    pub fn ja3(&self) -> String {
+        hex(&Md5::digest(self.ja3_string().as_bytes()))
+    }
+
+    pub fn ja4(&self) -> String {
+        let version = self
+            .supported_versions
+            .iter()
+            .copied()
+            .filter(|v| !is_grease(*v))
+            .max()
+            .unwrap_or(self.legacy_version);
+        let version = match version {
+            0x0304 => "13",
+            0x0303 => "12",
+            0x0302 => "11",
+            0x0301 => "10",
+            0x0300 => "s3",
+            0x0002 => "s2",
+            _ => "00",
+        };
+        let sni = if self.extensions.contains(&EXT_SERVER_NAME) { 'd' } else { 'i' };
+
+        let mut ciphers: Vec<u16> = self.ciphers.iter().copied().filter(|c| !is_grease(*c)).collect();
+        let extensions: Vec<u16> = self.extensions.iter().copied().filter(|e| !is_grease(*e)).collect();
+        let mut hashed_extensions: Vec<u16> = extensions
+            .iter()
+            .copied()
+            .filter(|e| *e != EXT_SERVER_NAME && *e != EXT_ALPN)
+            .collect();
+        ciphers.sort_unstable();
+        hashed_extensions.sort_unstable();
+        let signature_algorithms: Vec<u16> =
+            self.signature_algorithms.iter().copied().filter(|s| !is_grease(*s)).collect();
+
+        let mut extension_string = hex_list(&hashed_extensions);
+        if !signature_algorithms.is_empty() {
+            extension_string.push('_');
+            extension_string.push_str(&hex_list(&signature_algorithms));
+        }
+
+        format!(
+            "t{}{}{:02}{:02}{}_{}_{}",
+            version,
+            sni,
+            ciphers.len().min(99),
+            extensions.len().min(99),
+            ja4_alpn(self.alpn.as_deref()),
+            truncated_hash(&ciphers, &hex_list(&ciphers)),
+            truncated_hash(&hashed_extensions, &extension_string),
+        )
+    }
+}
+
+/// Computes fingerprints and matches them against the blocklist
+#[derive(Debug)]
+pub struct Fingerprinter {
+    blocklist: HashSet<String>,
+    block: FingerprintBlock,
+    forward_headers: bool,
+}
+
+impl Fingerprinter {
+    pub fn new(config: &TlsFingerprintConfig) -> Self {
+        Self {
+            blocklist: config.blocklist.iter().map(|entry| entry.to_ascii_lowercase()).collect(),
+            block: config.block,
+            forward_headers: config.forward_headers,
+        }
+    }
+
+    /// Fingerprint a client, marking it blocked if either fingerprint is on the blocklist
+    pub fn fingerprint(&self, hello: &ClientHello) -> TlsFingerprint {
+        let ja3 = hello.ja3();
+        let ja4 = hello.ja4();
+        let blocked = self.blocklist.contains(&ja3) || self.blocklist.contains(&ja4);
+        TlsFingerprint { ja3, ja4, blocked }
+    }
+
+    /// What blocked clients are refused
+    pub fn block(&self) -> FingerprintBlock {
+        self.block
+    }
+
+    /// Whether fingerprints are sent to backends in request headers
+    pub fn forward_headers(&self) -> bool {
+        self.forward_headers
+    }
+}
+
+/// Whether `value` looks like a JA3 hash or a JA4 fingerprint
+pub fn is_fingerprint(value: &str) -> bool {
+    let is_hex = |s: &str| s.chars().all(|c| c.is_ascii_hexdigit());
+    if value.len() == 32 && is_hex(value) {
+        return true;
+    }
+    let parts: Vec<&str> = value.split('_').collect();
+    matches!(
+        parts.as_slice(),
+        [prefix, ciphers, extensions]
+            if prefix.len() == 10 && prefix.is_ascii()
+                && ciphers.len() == 12 && is_hex(ciphers)
+                && extensions.len() == 12 && is_hex(extensions)
+    )
+}
+
+/// Read a connection's ClientHello, returning the bytes read and the parsed hello
+///
+/// The bytes must be replayed to the TLS acceptor. The hello is `None` if the
+/// client sent something else, or a ClientHello too large or malformed.
+pub async fn read_client_hello<IO>(io: &mut IO) -> io::Result<(Vec<u8>, Option<ClientHello>)>
+where
+    IO: AsyncRead + Unpin,
+{
+    let mut buf = Vec::with_capacity(2048);
+    loop {
+        match handshake_message(&buf) {
+            Ok(Some(message)) => {
+                let hello = ClientHello::parse(&message);
+                return Ok((buf, hello));
+            }
+            Ok(None) if buf.len() < MAX_CLIENT_HELLO => {}
+            _ => return Ok((buf, None)),
+        }
+        let mut chunk = [0u8; 4096];
+        let n = io.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok((buf, None));
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+/// The first handshake message in `records`, once all its records have arrived
+///
+/// Errors if `records` doesn't start with handshake records.
+fn handshake_message(mut records: &[u8]) -> Result<Option<Vec<u8>>, ()> {
+    let mut message = Vec::new();
+    while records.len() >= 5 {
+        if records[0] != TLS_HANDSHAKE {
+            return Err(());
+        }
+        let len = u16::from_be_bytes([records[3], records[4]]) as usize;
+        let Some(fragment) = records.get(5..5 + len) else {
+            break;
+        };
+        message.extend_from_slice(fragment);
+        records = &records[5 + len..];
+        if message.len() >= 4 {
+            let total = 4 + u32::from_be_bytes([0, message[1], message[2], message[3]]) as usize;
+            if message.len() >= total {
+                message.truncate(total);
+                return Ok(Some(message));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// GREASE values are `0x?a?a` with both bytes equal
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        let _ = write!(out, "{:02x}", b);
+    }
+    out
+}
+
+fn hex_list(values: &[u16]) -> String {
+    values.iter().map(|v| format!("{:04x}", v)).collect::<Vec<_>>().join(",")
+}
+
+/// First 12 hex characters of the SHA-256 of `string`, zeros if `values` is empty
+fn truncated_hash(values: &[u16], string: &str) -> String {
+    if values.is_empty() {
+        return "0".repeat(12);
+    }
+    let digest = ring::digest::digest(&ring::digest::SHA256, string.as_bytes());
+    hex(&digest.as_ref()[..6])
+}
+
+/// First and last character of the first ALPN protocol, hex digits if not alphanumeric
+fn ja4_alpn(alpn: Option<&[u8]>) -> String {
+    match alpn {
+        Some([first, .., last]) | Some([first @ last]) => {
+            if first.is_ascii_alphanumeric() && last.is_ascii_alphanumeric() {
+                format!("{}{}", *first as char, *last as char)
+            } else {
+                let first = hex(&[*first]);
+                let last = hex(&[*last]);
+                format!("{}{}", &first[..1], &last[1..])
+            }
+        }
+        _ => "00".to_string(),
+    }
+}
+
+/// Big-endian reader over a byte slice
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let b = self.bytes(2)?;
+        Some(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u24(&mut self) -> Option<usize> {
+        let b = self.bytes(3)?;
+        Some(u32::from_be_bytes([0, b[0], b[1], b[2]]) as usize)
+    }
+
+    /// Bytes with a 16-bit length prefix
+    fn vec16(&mut self) -> Option<&'a [u8]> {
+        let len = self.u16()? as usize;
+        self.bytes(len)
+    }
+
+    /// 16-bit values with a 16-bit length prefix
+    fn list16(&mut self) -> Option<Vec<u16>> {
+        let mut list = Reader(self.vec16()?);
+        let mut values = Vec::new();
+        while !list.is_empty() {
+            values.push(list.u16()?);
+        }
+        Some(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extension(typ: u16, data: &[u8]) -> Vec<u8> {
+        let mut out = typ.to_be_bytes().to_vec();
+        out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        out.extend_from_slice(data);
+        out
+    }
+
+    /// A ClientHello with GREASE values, as TLS records split after `split` bytes
+    fn client_hello_records(split: usize) -> Vec<u8> {
+        let mut extensions = Vec::new();
+        extensions.extend(extension(0x1a1a, &[]));
+        extensions.extend(extension(EXT_SERVER_NAME, b"\x00\x0b\x00\x00\x08app.test"));
+        extensions.extend(extension(EXT_SUPPORTED_GROUPS, &[0x00, 0x06, 0x2a, 0x2a, 0x00, 0x1d, 0x00, 0x17]));
+        extensions.extend(extension(EXT_EC_POINT_FORMATS, &[0x01, 0x00]));
+        extensions.extend(extension(EXT_SIGNATURE_ALGORITHMS, &[0x00, 0x06, 0x04, 0x03, 0x08, 0x04, 0x04, 0x01]));
+        extensions.extend(extension(EXT_ALPN, b"\x00\x0c\x02h2\x08http/1.1"));
+        extensions.extend(extension(EXT_SUPPORTED_VERSIONS, &[0x06, 0x3a, 0x3a, 0x03, 0x04, 0x03, 0x03]));
+
+        let mut body = vec![0x03, 0x03];
+        body.extend_from_slice(&[7u8; 32]);
+        body.push(0);
+        body.extend_from_slice(&[0x00, 0x08, 0x0a, 0x0a, 0x13, 0x01, 0x13, 0x02, 0xc0, 0x2b]);
+        body.extend_from_slice(&[0x01, 0x00]);
+        body.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        body.extend(extensions);
+
+        let mut message = vec![CLIENT_HELLO];
+        message.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        message.extend(body);
+
+        let mut records = Vec::new();
+        for fragment in [&message[..split], &message[split..]] {
+            records.extend_from_slice(&[TLS_HANDSHAKE, 0x03, 0x01]);
+            records.extend_from_slice(&(fragment.len() as u16).to_be_bytes());
+            records.extend_from_slice(fragment);
+        }
+        records
+    }
+
+    #[test]
+    fn test_fingerprints() {
+        let records = client_hello_records(40);
+        let message = handshake_message(&records).unwrap().unwrap();
+        let hello = ClientHello::parse(&message).unwrap();
+        assert_eq!(hello.alpn.as_deref(), Some(&b"h2"[..]));
+
+        assert_eq!(hello.ja3_string(), "771,4865-4866-49195,0-10-11-13-16-43,29-23,0");
+        assert_eq!(hello.ja3(), "11138d9933242c3a03b6aad35a296476");
+        assert_eq!(hello.ja4(), "t13d0306h2_5559582ccdc4_0d385148b956");
+
+        // Shuffled extensions change JA3 but not JA4
+        let mut shuffled = hello.clone();
+        shuffled.extensions.reverse();
+        assert_ne!(shuffled.ja3(), hello.ja3());
+        assert_eq!(shuffled.ja4(), hello.ja4());
+
+        let bare = ClientHello {
+            legacy_version: 0x0303,
+            ..Default::default()
+        };
+        assert_eq!(bare.ja3_string(), "771,,,,");
+        assert_eq!(bare.ja4(), "t12i000000_000000000000_000000000000");
+        assert_eq!(ja4_alpn(Some(b"\xab\xcd")), "ad");
+        assert_eq!(ja4_alpn(Some(b"h")), "hh");
+    }
+
+    #[test]
+    fn test_handshake_message() {
+        let records = client_hello_records(40);
+        assert_eq!(handshake_message(&records[..30]), Ok(None));
+        assert_eq!(handshake_message(&records[..records.len() - 1]), Ok(None));
+        assert!(handshake_message(b"GET / HTTP/1.1\r\n").is_err());
+        assert!(ClientHello::parse(&[CLIENT_HELLO, 0, 0, 10, 3]).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_read_client_hello() {
+        let records = client_hello_records(100);
+        let mut input = &records[..];
+        let (read, hello) = read_client_hello(&mut input).await.unwrap();
+        assert_eq!(read, records);
+        assert!(hello.is_some());
+
+        let mut input = &b"GET / HTTP/1.1\r\nHost: app.test\r\n\r\n"[..];
+        let (read, hello) = read_client_hello(&mut input).await.unwrap();
+        assert!(read.starts_with(b"GET /"));
+        assert!(hello.is_none());
+    }
+
+    #[test]
+    fn test_fingerprinter() {
+        let records = client_hello_records(40);
+        let hello = ClientHello::parse(&handshake_message(&records).unwrap().unwrap()).unwrap();
+        let config = TlsFingerprintConfig {
+            enabled: true,
+            blocklist: vec!["T13D0306H2_5559582CCDC4_0D385148B956".to_string()],
+            ..Default::default()
+        };
+        assert!(Fingerprinter::new(&config).fingerprint(&hello).blocked);
+        assert!(!Fingerprinter::new(&TlsFingerprintConfig::default()).fingerprint(&hello).blocked);
+
+        assert!(is_fingerprint("11138d9933242c3a03b6aad35a296476"));
+        assert!(is_fingerprint("t13d0306h2_5559582ccdc4_0d385148b956"));
+        assert!(!is_fingerprint("t13d0306h2"));
+        assert!(!is_fingerprint("not a fingerprint"));
+    }
+}
@@ -0,0 +1,352 @@
+//! Firecracker microVM backends
+//!
+//! A backend with `type = "firecracker"` is booted as a microVM from a guest
+//! kernel and root filesystem, for stronger isolation than a process or
+//! container. Each VM gets a tap device (created if missing) whose host end
+//! has `host_ip`, and the guest kernel configures `guest_ip` on its `eth0`.
+//! Like Docker publishing a container's port, the proxy relays
+//! `127.0.0.1:{port}` to `guest_ip:{port}`, so routing, health checks, pooling
+//! and idle shutdown work as for any other backend.
+//!
+//! The proxy's env vars (`PORT`, instance metadata, ...) and the backend's
+//! `env` are appended to the kernel command line, where the kernel passes them
+//! on to the guest's init as environment variables. The guest can't reach the
+//! admin API on the host's loopback, so use health polling for readiness.
+//!
+//! Creating tap devices needs `CAP_NET_ADMIN` (or run as root), and
+//! Firecracker needs access to `/dev/kvm`.
+
+use crate::config::{BackendConfig, FirecrackerConfig};
+use crate::docker::parse_memory_limit;
+use crate::logs::{self, BackendLogs, LogStream};
+use crate::process::Spawner;
+use crate::relay::relay;
+use dashmap::DashMap;
+use futures::future::{BoxFuture, FutureExt};
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::process::{Child, Command};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// Memory of a VM whose backend doesn't set `memory`
+const DEFAULT_MEMORY_MIB: i64 = 128;
+
+/// A running microVM
+struct MicroVm {
+    child: Child,
+    relay: JoinHandle<()>,
+    /// Tap device to delete on stop, if the proxy created it
+    created_tap: Option<String>,
+    config_dir: PathBuf,
+}
+
+/// Boots and stops the microVMs of firecracker backends
+pub struct FirecrackerSpawner {
+    logs: Arc<BackendLogs>,
+    vms: DashMap<String, MicroVm>,
+}
+
+impl FirecrackerSpawner {
+    pub fn new(logs: Arc<BackendLogs>) -> Self {
+        Self {
+            logs,
+            vms: DashMap::new(),
+        }
+    }
+
+    async fn boot(&self, hostname: &str, config: &BackendConfig, env: &[(String, String)]) -> anyhow::Result<String> {
+        let vm = config
+            .firecracker
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Firecracker backend requires a 'firecracker' table"))?;
+        let tap = vm.tap_device.clone().unwrap_or_else(|| tap_name(hostname));
+        info!(hostname, kernel = %vm.kernel, rootfs = %vm.rootfs, tap = %tap, "Starting Firecracker microVM");
+
+        let created_tap = ensure_tap(&tap, vm.host_ip).await?.then(|| tap.clone());
+        let config_dir = std::env::temp_dir().join(format!("spawngate-{}", tap));
+        let started = self.launch(hostname, config, env, vm, &tap, &config_dir).await;
+        let (child, relay) = match started {
+            Ok(started) => started,
+            Err(e) => {
+                cleanup(created_tap.as_deref(), &config_dir).await;
+                return Err(e);
+            }
+        };
+
+        let id = child.id().map_or_else(|| tap.clone(), |pid| pid.to_string());
+        info!(hostname, id = %id, guest_ip = %vm.guest_ip, "Firecracker microVM started");
+        self.vms.insert(id.clone(), MicroVm { child, relay, created_tap, config_dir });
+        Ok(id)
+    }
+
+    async fn launch(
+        &self,
+        hostname: &str,
+        config: &BackendConfig,
+        env: &[(String, String)],
+        vm: &FirecrackerConfig,
+        tap: &str,
+        config_dir: &Path,
+    ) -> anyhow::Result<(Child, JoinHandle<()>)> {
+        // Bind first: a port in use fails the start before a VM is booted
+        let listener = TcpListener::bind(("127.0.0.1", config.port))
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to listen on 127.0.0.1:{}: {}", config.port, e))?;
+
+        tokio::fs::create_dir_all(config_dir).await?;
+        let config_file = config_dir.join("vm.json");
+        let vm_config = vm_config(config, env, vm, tap)?;
+        tokio::fs::write(&config_file, serde_json::to_vec_pretty(&vm_config)?).await?;
+
+        let mut child = Command::new(&vm.binary)
+            .arg("--no-api")
+            .arg("--config-file")
+            .arg(&config_file)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| anyhow::anyhow!("Failed to run '{}': {}", vm.binary, e))?;
+
+        // The guest's serial console is Firecracker's stdout
+        if let Some(stdout) = child.stdout.take() {
+            let (hostname, logs) = (hostname.to_string(), Arc::clone(&self.logs));
+            tokio::spawn(async move { logs::capture(stdout, hostname, LogStream::Console, &logs).await });
+        }
+        if let Some(stderr) = child.stderr.take() {
+            let (hostname, logs) = (hostname.to_string(), Arc::clone(&self.logs));
+            tokio::spawn(async move { logs::capture(stderr, hostname, LogStream::Stderr, &logs).await });
+        }
+
+        let (_, target) = watch::channel(Some((vm.guest_ip, config.port).into()));
+        let relay = tokio::spawn(relay(listener, hostname.to_string(), target));
+        Ok((child, relay))
+    }
+
+    async fn shutdown(&self, hostname: &str, id: &str, grace_period: Duration) {
+        let Some((_, mut vm)) = self.vms.remove(id) else {
+            debug!(hostname, id, "MicroVM not running");
+            return;
+        };
+        vm.relay.abort();
+
+        if let Some(pid) = vm.child.id() {
+            info!(hostname, pid, "Sending SIGTERM to Firecracker");
+            #[cfg(unix)]
+            unsafe {
+                libc::kill(pid as i32, libc::SIGTERM);
+            }
+        }
+        if tokio::time::timeout(grace_period, vm.child.wait()).await.is_err() {
+            warn!(hostname, grace_period_secs = grace_period.as_secs(), "Grace period exceeded, killing Firecracker");
+            let _ = vm.child.kill().await;
+        }
+        cleanup(vm.created_tap.as_deref(), &vm.config_dir).await;
+    }
+}
+
+impl Spawner for FirecrackerSpawner {
+    fn start<'a>(
+        &'a self,
+        hostname: &'a str,
+        config: &'a BackendConfig,
+        env: &'a [(String, String)],
+    ) -> BoxFuture<'a, anyhow::Result<String>> {
+        self.boot(hostname, config, env).boxed()
+    }
+
+    fn stop<'a>(&'a self, hostname: &'a str, id: &'a str, grace_period: Duration) -> BoxFuture<'a, ()> {
+        self.shutdown(hostname, id, grace_period).boxed()
+    }
+}
+
+/// Firecracker's `--config-file` contents for a backend
+fn vm_config(
+    config: &BackendConfig,
+    env: &[(String, String)],
+    vm: &FirecrackerConfig,
+    tap: &str,
+) -> anyhow::Result<serde_json::Value> {
+    let vcpus = match config.cpus.as_deref() {
+        Some(cpus) => cpus
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| anyhow::anyhow!("Invalid cpus: {}", cpus))?
+            .ceil()
+            .max(1.0) as u32,
+        None => 1,
+    };
+    let memory_mib = match config.memory.as_deref() {
+        Some(memory) => (parse_memory_limit(memory)? / (1024 * 1024)).max(1),
+        None => DEFAULT_MEMORY_MIB,
+    };
+    let [a, b, c, d] = vm.guest_ip.octets();
+
+    Ok(serde_json::json!({
+        "boot-source": {
+            "kernel_image_path": vm.kernel,
+            "boot_args": boot_args(config, env, vm),
+        },
+        "drives": [{
+            "drive_id": "rootfs",
+            "path_on_host": vm.rootfs,
+            "is_root_device": true,
+            "is_read_only": false,
+        }],
+        "machine-config": {
+            "vcpu_count": vcpus,
+            "mem_size_mib": memory_mib,
+        },
+        "network-interfaces": [{
+            "iface_id": "eth0",
+            "guest_mac": format!("06:00:{:02x}:{:02x}:{:02x}:{:02x}", a, b, c, d),
+            "host_dev_name": tap,
+        }],
+    }))
+}
+
+/// Kernel command line: the configured arguments, the guest's static network
+/// settings and env vars for init (the proxy's taking precedence)
+fn boot_args(config: &BackendConfig, env: &[(String, String)], vm: &FirecrackerConfig) -> String {
+    let mut args = format!(
+        "{} ip={}::{}:255.255.255.252::eth0:off",
+        vm.boot_args.trim(),
+        vm.guest_ip,
+        vm.host_ip
+    );
+    let mut vars: Vec<(&String, &String)> = config
+        .env
+        .iter()
+        .filter(|(key, _)| env.iter().all(|(injected, _)| injected != *key))
+        .collect();
+    vars.sort();
+    vars.extend(env.iter().map(|(key, value)| (key, value)));
+    for (key, value) in vars {
+        if value.contains(['"', '\n']) {
+            warn!(key = %key, "Env var can't be passed on the kernel command line, skipping");
+            continue;
+        }
+        if value.is_empty() || value.contains(char::is_whitespace) {
+            args.push_str(&format!(" {}=\"{}\"", key, value));
+        } else {
+            args.push_str(&format!(" {}={}", key, value));
+        }
+    }
+    args
+}
+
+/// Default tap device of a backend: "fc-" and a hash of its hostname, within
+/// the 15 characters Linux allows for interface names
+fn tap_name(hostname: &str) -> String {
+    // FNV-1a, so the name is stable across restarts and releases
+    let hash = hostname
+        .bytes()
+        .fold(0x811c_9dc5u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193));
+    format!("fc-{:08x}", hash)
+}
+
+/// Create the tap device with `host_ip` unless it exists; returns whether it was created
+async fn ensure_tap(tap: &str, host_ip: Ipv4Addr) -> anyhow::Result<bool> {
+    if Path::new("/sys/class/net").join(tap).exists() {
+        debug!(tap, "Using existing tap device");
+        return Ok(false);
+    }
+    let address = format!("{}/30", host_ip);
+    ip(&["tuntap", "add", "dev", tap, "mode", "tap"]).await?;
+    let configured = async {
+        ip(&["addr", "add", &address, "dev", tap]).await?;
+        ip(&["link", "set", tap, "up"]).await
+    };
+    if let Err(e) = configured.await {
+        let _ = ip(&["link", "del", tap]).await;
+        return Err(e);
+    }
+    info!(tap, address = %address, "Created tap device");
+    Ok(true)
+}
+
+/// Remove what a microVM left behind
+async fn cleanup(created_tap: Option<&str>, config_dir: &Path) {
+    if let Some(tap) = created_tap {
+        if let Err(e) = ip(&["link", "del", tap]).await {
+            warn!(tap, error = %e, "Failed to delete tap device");
+        }
+    }
+    let _ = tokio::fs::remove_dir_all(config_dir).await;
+}
+
+async fn ip(args: &[&str]) -> anyhow::Result<()> {
+    let output = Command::new("ip")
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to run 'ip': {}", e))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "'ip {}' failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backend() -> BackendConfig {
+        let mut config = BackendConfig::local("", 8080);
+        config.backend_type = crate::config::BackendType::Firecracker;
+        config.memory = Some("256m".to_string());
+        config.cpus = Some("1.5".to_string());
+        config.env.insert("GREETING".to_string(), "hello world".to_string());
+        config.env.insert("PORT".to_string(), "1".to_string());
+        config.firecracker = Some(FirecrackerConfig {
+            kernel: "/vm/vmlinux".to_string(),
+            rootfs: "/vm/rootfs.ext4".to_string(),
+            boot_args: "console=ttyS0".to_string(),
+            host_ip: Ipv4Addr::new(172, 16, 0, 1),
+            guest_ip: Ipv4Addr::new(172, 16, 0, 2),
+            tap_device: None,
+            binary: "firecracker".to_string(),
+        });
+        config
+    }
+
+    #[test]
+    fn test_vm_config() {
+        let config = backend();
+        let env = [("PORT".to_string(), "8080".to_string())];
+        let vm = config.firecracker.as_ref().unwrap();
+
+        assert_eq!(
+            boot_args(&config, &env, vm),
+            "console=ttyS0 ip=172.16.0.2::172.16.0.1:255.255.255.252::eth0:off GREETING=\"hello world\" PORT=8080"
+        );
+
+        let json = vm_config(&config, &env, vm, "fc-test").unwrap();
+        assert_eq!(json["machine-config"]["vcpu_count"], 2);
+        assert_eq!(json["machine-config"]["mem_size_mib"], 256);
+        assert_eq!(json["drives"][0]["path_on_host"], "/vm/rootfs.ext4");
+        assert_eq!(json["network-interfaces"][0]["guest_mac"], "06:00:ac:10:00:02");
+        assert_eq!(json["network-interfaces"][0]["host_dev_name"], "fc-test");
+    }
+
+    #[test]
+    fn test_tap_name() {
+        let tap = tap_name("app.example.com");
+        assert_eq!(tap.len(), 11);
+        assert!(tap.starts_with("fc-"));
+        assert_eq!(tap, tap_name("app.example.com"));
+        assert_ne!(tap, tap_name("api.example.com"));
+    }
+}
@@ -0,0 +1,366 @@
+//! HTTP/2 cleartext upgrades (RFC 7540 section 3.2)
+//!
+//! Plaintext listeners accept HTTP/2 with prior knowledge directly. Clients
+//! that don't know the server speaks HTTP/2 start with an HTTP/1.1 request
+//! carrying `Upgrade: h2c` and `HTTP2-Settings`; the proxy answers
+//! `101 Switching Protocols` and continues the connection as HTTP/2, with
+//! the upgrade request served as stream 1.
+//!
+//! hyper's HTTP/2 server only accepts connections from their start, so the
+//! upgrade request is handed to it as the HEADERS frame the client would
+//! have sent, placed after the client's connection preface. Requests with a
+//! body, or with headers too large for a single frame, are served as plain
+//! HTTP/1.1 instead, which clients must accept.
+
+use crate::proxy::ProxyResponse;
+use http_body_util::{BodyExt, Empty};
+use hyper::body::Incoming;
+use hyper::header::{HeaderValue, CONNECTION, CONTENT_LENGTH, HOST, TRANSFER_ENCODING, UPGRADE};
+use hyper::server::conn::http2;
+use hyper::service::Service;
+use hyper::{Method, Request, Response, StatusCode, Version};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+/// The client connection preface every HTTP/2 connection starts with
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Largest frame payload a peer must accept before settings say otherwise
+const MAX_FRAME_SIZE: usize = 16_384;
+
+/// How long the client gets to send its preface after the 101 response
+const PREFACE_TIMEOUT: Duration = Duration::from_secs(10);
+
+const FRAME_HEADERS: u8 = 0x1;
+const FRAME_SETTINGS: u8 = 0x4;
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+
+/// Headers that only apply to the HTTP/1.1 connection
+const CONNECTION_HEADERS: &[&str] = &[
+    "connection",
+    "upgrade",
+    "http2-settings",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "host",
+];
+
+/// Whether a request asks to continue the connection as HTTP/2
+pub fn is_upgrade<B>(req: &Request<B>) -> bool {
+    let headers = req.headers();
+    req.version() == Version::HTTP_11
+        && req.method() != Method::CONNECT
+        && has_token(headers.get_all(UPGRADE), "h2c")
+        && has_token(headers.get_all(CONNECTION), "upgrade")
+        && headers.get_all("http2-settings").iter().count() == 1
+        && !headers.contains_key(TRANSFER_ENCODING)
+        && headers
+            .get(CONTENT_LENGTH)
+            .is_none_or(|v| v.to_str().is_ok_and(|v| v.trim() == "0"))
+}
+
+fn has_token<'a>(values: impl IntoIterator<Item = &'a HeaderValue>, token: &str) -> bool {
+    values
+        .into_iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|t| t.trim().eq_ignore_ascii_case(token))
+}
+
+/// Switch an upgrade request's connection to HTTP/2 served by `service`
+///
+/// Returns the `101 Switching Protocols` response and the task serving the
+/// connection afterwards, or `None` when the request should be served as is.
+pub fn upgrade<S>(
+    req: &mut Request<Incoming>,
+    service: S,
+    mut shutdown: watch::Receiver<bool>,
+) -> Option<(ProxyResponse, JoinHandle<()>)>
+where
+    S: Service<Request<Incoming>, Response = ProxyResponse> + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    if !is_upgrade(req) {
+        return None;
+    }
+    let Some(headers) = headers_frame(req) else {
+        debug!(uri = %req.uri(), "Request headers too large for an h2c upgrade");
+        return None;
+    };
+
+    let on_upgrade = hyper::upgrade::on(req);
+    let task = tokio::spawn(async move {
+        let upgraded = match on_upgrade.await {
+            Ok(upgraded) => upgraded,
+            Err(e) => {
+                debug!(error = %e, "h2c upgrade failed");
+                return;
+            }
+        };
+        let mut io = TokioIo::new(upgraded);
+        let mut prefix = match tokio::time::timeout(PREFACE_TIMEOUT, read_preface(&mut io)).await {
+            Ok(Ok(prefix)) => prefix,
+            Ok(Err(e)) => {
+                debug!(error = %e, "Invalid HTTP/2 preface after h2c upgrade");
+                return;
+            }
+            Err(_) => {
+                debug!("No HTTP/2 preface after h2c upgrade");
+                return;
+            }
+        };
+        prefix.extend_from_slice(&headers);
+
+        let mut builder = http2::Builder::new(TokioExecutor::new());
+        builder.max_concurrent_streams(250);
+        let conn = builder.serve_connection(TokioIo::new(Rewind::new(prefix, io)), service);
+        tokio::pin!(conn);
+
+        let result = tokio::select! {
+            result = conn.as_mut() => result,
+            _ = async {
+                if shutdown.wait_for(|stop| *stop).await.is_err() {
+                    std::future::pending::<()>().await;
+                }
+            } => {
+                conn.as_mut().graceful_shutdown();
+                conn.await
+            }
+        };
+        if let Err(e) = result {
+            debug!(error = %e, "h2c connection error");
+        }
+    });
+
+    let response = Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(CONNECTION, "Upgrade")
+        .header(UPGRADE, "h2c")
+        .body(Empty::new().map_err(|never| match never {}).boxed())
+        .expect("valid response builder");
+    Some((response, task))
+}
+
+/// Read the client's preface and initial SETTINGS frame
+async fn read_preface<R: AsyncRead + Unpin>(io: &mut R) -> io::Result<Vec<u8>> {
+    let mut prefix = vec![0; PREFACE.len() + 9];
+    io.read_exact(&mut prefix).await?;
+    if &prefix[..PREFACE.len()] != PREFACE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not an HTTP/2 connection preface"));
+    }
+    let header = &prefix[PREFACE.len()..];
+    let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+    if header[3] != FRAME_SETTINGS || length > MAX_FRAME_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "expected a SETTINGS frame"));
+    }
+    let start = prefix.len();
+    prefix.resize(start + length, 0);
+    io.read_exact(&mut prefix[start..]).await?;
+    Ok(prefix)
+}
+
+/// HEADERS frame of stream 1 carrying the upgrade request
+///
+/// Fields are sent as HPACK literals without indexing, so the frame doesn't
+/// touch either side's dynamic table. `None` when it exceeds the default
+/// maximum frame size.
+fn headers_frame<B>(req: &Request<B>) -> Option<Vec<u8>> {
+    let mut block = Vec::new();
+    let path = req.uri().path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+    encode_field(&mut block, b":method", req.method().as_str().as_bytes());
+    encode_field(&mut block, b":scheme", b"http");
+    encode_field(&mut block, b":path", path.as_bytes());
+    if let Some(host) = req.headers().get(HOST) {
+        encode_field(&mut block, b":authority", host.as_bytes());
+    }
+    for (name, value) in req.headers() {
+        if CONNECTION_HEADERS.contains(&name.as_str()) || (name == "te" && value != "trailers") {
+            continue;
+        }
+        encode_field(&mut block, name.as_str().as_bytes(), value.as_bytes());
+    }
+    if block.len() > MAX_FRAME_SIZE {
+        return None;
+    }
+
+    let mut frame = Vec::with_capacity(9 + block.len());
+    frame.extend_from_slice(&(block.len() as u32).to_be_bytes()[1..]);
+    frame.push(FRAME_HEADERS);
+    frame.push(FLAG_END_STREAM | FLAG_END_HEADERS);
+    frame.extend_from_slice(&1u32.to_be_bytes());
+    frame.extend_from_slice(&block);
+    Some(frame)
+}
+
+/// Literal header field without indexing, with a new name
+fn encode_field(out: &mut Vec<u8>, name: &[u8], value: &[u8]) {
+    out.push(0);
+    encode_string(out, name);
+    encode_string(out, value);
+}
+
+fn encode_string(out: &mut Vec<u8>, s: &[u8]) {
+    // Not Huffman encoded: 7-bit length prefix with the H bit clear
+    encode_integer(out, s.len(), 7);
+    out.extend_from_slice(s);
+}
+
+fn encode_integer(out: &mut Vec<u8>, mut value: usize, prefix_bits: u32) {
+    let max = (1usize << prefix_bits) - 1;
+    if value < max {
+        out.push(value as u8);
+        return;
+    }
+    out.push(max as u8);
+    value -= max;
+    while value >= 128 {
+        out.push((value % 128) as u8 | 0x80);
+        value /= 128;
+    }
+    out.push(value as u8);
+}
+
+/// IO that yields `prefix` before reading from the inner stream
+pub(crate) struct Rewind<T> {
+    prefix: Vec<u8>,
+    offset: usize,
+    inner: T,
+}
+
+impl<T> Rewind<T> {
+    pub(crate) fn new(prefix: Vec<u8>, inner: T) -> Self {
+        Self { prefix, offset: 0, inner }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Rewind<T> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        if self.offset < self.prefix.len() {
+            let n = (self.prefix.len() - self.offset).min(buf.remaining());
+            let start = self.offset;
+            buf.put_slice(&self.prefix[start..start + n]);
+            self.offset += n;
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Rewind<T> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upgrade_request() -> hyper::http::request::Builder {
+        Request::builder()
+            .uri("/search?q=1")
+            .header("host", "app.local")
+            .header("connection", "Upgrade, HTTP2-Settings")
+            .header("upgrade", "h2c")
+            .header("http2-settings", "AAMAAABkAAQAoAAAAAIAAAAA")
+    }
+
+    #[test]
+    fn test_is_upgrade() {
+        assert!(is_upgrade(&upgrade_request().body(()).unwrap()));
+        assert!(is_upgrade(&upgrade_request().header("content-length", "0").body(()).unwrap()));
+
+        assert!(!is_upgrade(&Request::builder().uri("/").body(()).unwrap()));
+        assert!(!is_upgrade(&upgrade_request().header("content-length", "5").body(()).unwrap()));
+        assert!(!is_upgrade(&upgrade_request().header("transfer-encoding", "chunked").body(()).unwrap()));
+        assert!(!is_upgrade(&upgrade_request().version(Version::HTTP_10).body(()).unwrap()));
+        assert!(!is_upgrade(&upgrade_request().header("http2-settings", "").body(()).unwrap()));
+        let websocket = Request::builder()
+            .header("connection", "upgrade")
+            .header("upgrade", "websocket")
+            .header("http2-settings", "")
+            .body(())
+            .unwrap();
+        assert!(!is_upgrade(&websocket));
+    }
+
+    #[test]
+    fn test_headers_frame() {
+        let req = upgrade_request().header("x-trace", "abc").header("te", "gzip").body(()).unwrap();
+        let frame = headers_frame(&req).unwrap();
+        let length = u32::from_be_bytes([0, frame[0], frame[1], frame[2]]) as usize;
+        assert_eq!(length, frame.len() - 9);
+        assert_eq!(frame[3], FRAME_HEADERS);
+        assert_eq!(frame[4], FLAG_END_STREAM | FLAG_END_HEADERS);
+        assert_eq!(&frame[5..9], &[0, 0, 0, 1]);
+
+        let mut expected = Vec::new();
+        encode_field(&mut expected, b":method", b"GET");
+        encode_field(&mut expected, b":scheme", b"http");
+        encode_field(&mut expected, b":path", b"/search?q=1");
+        encode_field(&mut expected, b":authority", b"app.local");
+        encode_field(&mut expected, b"x-trace", b"abc");
+        assert_eq!(&frame[9..], &expected[..]);
+
+        let large = upgrade_request().header("cookie", "a".repeat(MAX_FRAME_SIZE)).body(()).unwrap();
+        assert!(headers_frame(&large).is_none());
+    }
+
+    #[test]
+    fn test_encode_integer() {
+        // RFC 7541 appendix C.1
+        let mut out = Vec::new();
+        encode_integer(&mut out, 10, 5);
+        assert_eq!(out, [10]);
+        out.clear();
+        encode_integer(&mut out, 1337, 5);
+        assert_eq!(out, [31, 154, 10]);
+        out.clear();
+        encode_integer(&mut out, 127, 7);
+        assert_eq!(out, [127, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_read_preface() {
+        let mut input = PREFACE.to_vec();
+        input.extend_from_slice(&[0, 0, 6, FRAME_SETTINGS, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 100]);
+        input.extend_from_slice(b"next");
+        let mut io = &input[..];
+        let prefix = read_preface(&mut io).await.unwrap();
+        assert_eq!(prefix, &input[..input.len() - 4]);
+        assert_eq!(io, b"next");
+
+        let mut http1 = &b"GET / HTTP/1.1\r\nHost: app.local\r\n\r\n"[..];
+        assert!(read_preface(&mut http1).await.is_err());
+    }
+}
@@ -0,0 +1,438 @@
+//! Consul and Nomad integration
+//!
+//! With `[server.consul]` enabled, every backend is registered in the Consul
+//! catalog as a service reached through the proxy, so other services discover
+//! it (and start it on demand) the same way as anything else in the cluster.
+//! Registrations follow config reloads and are removed on shutdown.
+//!
+//! A backend with `type = "nomad"` delegates spawning to Nomad: its job's task
+//! group is scaled from 0 to 1 when traffic arrives and back to 0 when the
+//! backend is stopped, while Nomad decides placement and runs the tasks. Once
+//! the allocation runs, its address is read from the Nomad API and a relay on
+//! `127.0.0.1:{port}` forwards to it.
+
+use crate::config::{BackendConfig, ConsulConfig, NomadConfig};
+use crate::cloud::uri_encode;
+use crate::process::Spawner;
+use crate::relay::relay;
+use dashmap::{DashMap, DashSet};
+use futures::future::{BoxFuture, FutureExt};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// Time allowed for a call to the Consul or Nomad API
+const API_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often a scaled-up Nomad task group is checked for a running allocation
+const LOCATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Client for the HTTP API of a Consul or Nomad agent, or an etcd member
+pub(crate) struct ApiClient {
+    client: Client<HttpConnector, Full<Bytes>>,
+    address: String,
+    /// Token header and value
+    token: Option<(&'static str, String)>,
+}
+
+impl ApiClient {
+    pub(crate) fn new(address: &str, token: Option<(&'static str, String)>) -> Self {
+        Self {
+            client: Client::builder(TokioExecutor::new()).build_http(),
+            address: address.trim_end_matches('/').to_string(),
+            token,
+        }
+    }
+
+    async fn send(&self, method: Method, path: &str, body: Option<serde_json::Value>) -> anyhow::Result<()> {
+        let (status, body) = self.request(method, path, body).await?;
+        if !status.is_success() {
+            anyhow::bail!("{} returned {}: {}", self.address, status, String::from_utf8_lossy(&body).trim());
+        }
+        Ok(())
+    }
+
+    /// Send a GET request, returning the JSON response
+    async fn get(&self, path: &str) -> anyhow::Result<serde_json::Value> {
+        let (status, body) = self.request(Method::GET, path, None).await?;
+        if !status.is_success() {
+            anyhow::bail!("{} returned {}: {}", self.address, status, String::from_utf8_lossy(&body).trim());
+        }
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Send a request, returning the response status and body
+    pub(crate) async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> anyhow::Result<(StatusCode, Bytes)> {
+        let mut request = Request::builder().method(method).uri(format!("{}{}", self.address, path));
+        if let Some((header, token)) = &self.token {
+            request = request.header(*header, token);
+        }
+        let body = body.map_or_else(Bytes::new, |body| Bytes::from(body.to_string()));
+        let request = request.header("content-type", "application/json").body(Full::new(body))?;
+
+        tokio::time::timeout(API_TIMEOUT, async {
+            let response = self.client.request(request).await?;
+            let status = response.status();
+            let body = response.into_body().collect().await?.to_bytes();
+            Ok((status, body))
+        })
+        .await
+        .map_err(|_| anyhow::anyhow!("Request to {} timed out", self.address))?
+    }
+}
+
+/// Keeps the backends registered as services in the Consul catalog
+pub struct ConsulRegistrar {
+    api: ApiClient,
+    service_address: Option<String>,
+    tags: Vec<String>,
+    /// Proxy port registered for every service
+    port: u16,
+    registered: DashSet<String>,
+}
+
+impl ConsulRegistrar {
+    pub fn new(config: &ConsulConfig, port: u16) -> Self {
+        Self {
+            api: ApiClient::new(&config.address, config.token.clone().map(|t| ("x-consul-token", t))),
+            service_address: config.service_address.clone(),
+            tags: config.tags.clone(),
+            port,
+            registered: DashSet::new(),
+        }
+    }
+
+    /// Register every backend and deregister the ones no longer configured
+    ///
+    /// Failures are logged; the proxy keeps serving without the registration.
+    pub async fn sync(&self, backends: &HashMap<String, BackendConfig>) {
+        let removed: Vec<String> = self
+            .registered
+            .iter()
+            .filter(|hostname| !backends.contains_key(hostname.as_str()))
+            .map(|hostname| hostname.clone())
+            .collect();
+        for hostname in removed {
+            self.deregister(&hostname).await;
+        }
+        for (hostname, config) in backends {
+            let path = "/v1/agent/service/register";
+            match self.api.send(Method::PUT, path, Some(self.service(hostname, config))).await {
+                Ok(()) => {
+                    debug!(hostname, "Registered backend in Consul");
+                    self.registered.insert(hostname.clone());
+                }
+                Err(e) => warn!(hostname, error = %e, "Failed to register backend in Consul"),
+            }
+        }
+    }
+
+    /// Deregister every backend registered by this proxy
+    pub async fn deregister_all(&self) {
+        let hostnames: Vec<String> = self.registered.iter().map(|hostname| hostname.clone()).collect();
+        for hostname in hostnames {
+            self.deregister(&hostname).await;
+        }
+    }
+
+    async fn deregister(&self, hostname: &str) {
+        let path = format!("/v1/agent/service/deregister/{}", service_id(hostname));
+        match self.api.send(Method::PUT, &path, None).await {
+            Ok(()) => debug!(hostname, "Deregistered backend from Consul"),
+            Err(e) => warn!(hostname, error = %e, "Failed to deregister backend from Consul"),
+        }
+        self.registered.remove(hostname);
+    }
+
+    /// Service definition of a backend for the agent's register endpoint
+    fn service(&self, hostname: &str, config: &BackendConfig) -> serde_json::Value {
+        let mut meta: HashMap<&str, &str> = config
+            .labels
+            .iter()
+            .filter(|(key, _)| valid_meta_key(key))
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        meta.insert("hostname", hostname);
+
+        let mut service = serde_json::json!({
+            "ID": service_id(hostname),
+            "Name": hostname.replace('.', "-"),
+            "Tags": self.tags,
+            "Port": self.port,
+            "Meta": meta,
+        });
+        if let Some(address) = &self.service_address {
+            service["Address"] = address.clone().into();
+        }
+        service
+    }
+}
+
+fn service_id(hostname: &str) -> String {
+    format!("spawngate-{}", hostname)
+}
+
+/// Consul only accepts meta keys of letters, digits, `-` and `_`, up to 128 characters
+fn valid_meta_key(key: &str) -> bool {
+    !key.is_empty()
+        && key.len() <= 128
+        && key.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        && !key.starts_with("consul-")
+}
+
+/// A scaled-up Nomad task group and the relay to its allocation
+struct RunningJob {
+    nomad: NomadConfig,
+    relay: JoinHandle<()>,
+    locate: JoinHandle<()>,
+}
+
+/// Starts and stops nomad backends by scaling their task group
+#[derive(Default)]
+pub struct NomadSpawner {
+    /// Running instances, by ID
+    running: DashMap<String, RunningJob>,
+}
+
+impl NomadSpawner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    async fn scale(&self, hostname: &str, nomad: &NomadConfig, count: u32) -> anyhow::Result<()> {
+        let body = serde_json::json!({
+            "Count": count,
+            "Target": { "Group": group_name(nomad) },
+            "Message": format!("spawngate: scaled to {} for {}", count, hostname),
+        });
+        nomad_api(nomad).send(Method::POST, &job_path(nomad, "/scale"), Some(body)).await
+    }
+
+    async fn scale_up(&self, hostname: &str, config: &BackendConfig) -> anyhow::Result<String> {
+        let nomad = config
+            .nomad
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Nomad backend requires a 'nomad' table"))?;
+        let id = format!("{}/{}", nomad.job, group_name(nomad));
+        let listener = TcpListener::bind(("127.0.0.1", config.port))
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to listen on 127.0.0.1:{}: {}", config.port, e))?;
+        self.scale(hostname, nomad, 1)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to scale up Nomad job '{}': {}", id, e))?;
+        info!(hostname, id = %id, "Scaled Nomad task group to 1");
+
+        // Until the allocation runs, health checks fail and the backend stays starting
+        let (target_tx, target_rx) = watch::channel(None);
+        let relay = tokio::spawn(relay(listener, hostname.to_string(), target_rx));
+        let locate = {
+            let (nomad, hostname) = (nomad.clone(), hostname.to_string());
+            tokio::spawn(async move {
+                loop {
+                    match locate_allocation(&nomad).await {
+                        Ok(Some(addr)) => {
+                            info!(hostname, %addr, "Nomad allocation is running");
+                            target_tx.send_replace(Some(addr));
+                            return;
+                        }
+                        Ok(None) => debug!(hostname, "Nomad allocation not running yet"),
+                        Err(e) => warn!(hostname, error = %e, "Failed to look up Nomad allocation"),
+                    }
+                    tokio::time::sleep(LOCATE_INTERVAL).await;
+                }
+            })
+        };
+        let running = RunningJob { nomad: nomad.clone(), relay, locate };
+        self.running.insert(id.clone(), running);
+        Ok(id)
+    }
+
+    async fn scale_down(&self, hostname: &str, id: &str) {
+        let Some((_, running)) = self.running.remove(id) else {
+            return;
+        };
+        running.relay.abort();
+        running.locate.abort();
+        // Nomad stops the tasks, honoring their kill_timeout
+        match self.scale(hostname, &running.nomad, 0).await {
+            Ok(()) => info!(hostname, id, "Scaled Nomad task group to 0"),
+            Err(e) => warn!(hostname, id, error = %e, "Failed to scale down Nomad job"),
+        }
+    }
+}
+
+fn nomad_api(nomad: &NomadConfig) -> ApiClient {
+    ApiClient::new(&nomad.address, nomad.token.clone().map(|t| ("x-nomad-token", t)))
+}
+
+fn group_name(nomad: &NomadConfig) -> &str {
+    nomad.group.as_deref().unwrap_or(&nomad.job)
+}
+
+/// Path of the job's `suffix` endpoint, in the job's namespace
+fn job_path(nomad: &NomadConfig, suffix: &str) -> String {
+    namespaced(nomad, format!("/v1/job/{}{}", uri_encode(&nomad.job), suffix))
+}
+
+fn namespaced(nomad: &NomadConfig, path: String) -> String {
+    match &nomad.namespace {
+        Some(namespace) => {
+            let query = form_urlencoded::Serializer::new(String::new()).append_pair("namespace", namespace).finish();
+            format!("{}?{}", path, query)
+        }
+        None => path,
+    }
+}
+
+/// Address of a running allocation of the job's task group, `None` while there is none
+async fn locate_allocation(nomad: &NomadConfig) -> anyhow::Result<Option<SocketAddr>> {
+    let api = nomad_api(nomad);
+    let allocations = api.get(&job_path(nomad, "/allocations")).await?;
+    let running = allocations.as_array().into_iter().flatten().find(|allocation| {
+        allocation["TaskGroup"] == group_name(nomad) && allocation["ClientStatus"] == "running"
+    });
+    let Some(id) = running.and_then(|allocation| allocation["ID"].as_str()) else {
+        return Ok(None);
+    };
+    let allocation = api.get(&namespaced(nomad, format!("/v1/allocation/{}", uri_encode(id)))).await?;
+    allocation_address(&allocation, nomad.port_label.as_deref())
+        .map(Some)
+        .ok_or_else(|| anyhow::anyhow!("Nomad allocation '{}' has no matching port", id))
+}
+
+/// Host address of the allocation's port labelled `label`, or of its first port
+fn allocation_address(allocation: &serde_json::Value, label: Option<&str>) -> Option<SocketAddr> {
+    let resources = &allocation["AllocatedResources"];
+    // Group networks list their ports with the host IP, task networks (older jobs) per network
+    let mapped = resources["Shared"]["Ports"].as_array().into_iter().flatten().map(|port| (&port["HostIP"], port));
+    let task_networks = resources["Tasks"].as_object().into_iter().flat_map(|tasks| tasks.values());
+    let networks = std::iter::once(&resources["Shared"]["Networks"])
+        .chain(task_networks.map(|task| &task["Networks"]))
+        .filter_map(|networks| networks.as_array())
+        .flatten();
+    let listed = networks.flat_map(|network| {
+        ["ReservedPorts", "DynamicPorts"]
+            .into_iter()
+            .filter_map(move |kind| network[kind].as_array())
+            .flatten()
+            .map(move |port| (&network["IP"], port))
+    });
+    mapped
+        .chain(listed)
+        .filter(|(_, port)| label.is_none_or(|label| port["Label"] == label))
+        .find_map(|(ip, port)| {
+            let ip = ip.as_str()?.parse().ok()?;
+            Some(SocketAddr::new(ip, port["Value"].as_u64()?.try_into().ok()?))
+        })
+}
+
+impl Spawner for NomadSpawner {
+    fn start<'a>(
+        &'a self,
+        hostname: &'a str,
+        config: &'a BackendConfig,
+        _env: &'a [(String, String)],
+    ) -> BoxFuture<'a, anyhow::Result<String>> {
+        self.scale_up(hostname, config).boxed()
+    }
+
+    fn stop<'a>(&'a self, hostname: &'a str, id: &'a str, _grace_period: Duration) -> BoxFuture<'a, ()> {
+        self.scale_down(hostname, id).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_consul_service() {
+        let config = ConsulConfig {
+            enabled: true,
+            service_address: Some("10.0.0.5".to_string()),
+            ..Default::default()
+        };
+        let registrar = ConsulRegistrar::new(&config, 8080);
+        let mut backend = BackendConfig::local("node", 3000);
+        backend.labels.insert("team".to_string(), "payments".to_string());
+        backend.labels.insert("tier.level".to_string(), "1".to_string());
+
+        let service = registrar.service("api.example.com", &backend);
+        assert_eq!(service["ID"], "spawngate-api.example.com");
+        assert_eq!(service["Name"], "api-example-com");
+        assert_eq!(service["Port"], 8080);
+        assert_eq!(service["Address"], "10.0.0.5");
+        assert_eq!(service["Tags"], serde_json::json!(["spawngate"]));
+        assert_eq!(
+            service["Meta"],
+            serde_json::json!({ "hostname": "api.example.com", "team": "payments" })
+        );
+    }
+
+    #[test]
+    fn test_nomad_job_path() {
+        let mut nomad = NomadConfig {
+            address: "http://127.0.0.1:4646".to_string(),
+            job: "api/v2".to_string(),
+            group: None,
+            namespace: None,
+            port_label: None,
+            token: None,
+        };
+        assert_eq!(job_path(&nomad, "/scale"), "/v1/job/api%2Fv2/scale");
+        nomad.namespace = Some("team a&b".to_string());
+        assert_eq!(job_path(&nomad, "/allocations"), "/v1/job/api%2Fv2/allocations?namespace=team+a%26b");
+    }
+
+    #[test]
+    fn test_nomad_allocation_address() {
+        let group_network = serde_json::json!({
+            "AllocatedResources": {
+                "Shared": {
+                    "Ports": [
+                        { "Label": "metrics", "Value": 29001, "To": 9090, "HostIP": "10.0.0.7" },
+                        { "Label": "http", "Value": 29000, "To": 8080, "HostIP": "10.0.0.7" }
+                    ],
+                    "Networks": null
+                },
+                "Tasks": {}
+            }
+        });
+        assert_eq!(allocation_address(&group_network, None), Some("10.0.0.7:29001".parse().unwrap()));
+        assert_eq!(allocation_address(&group_network, Some("http")), Some("10.0.0.7:29000".parse().unwrap()));
+        assert_eq!(allocation_address(&group_network, Some("grpc")), None);
+
+        // Task networks of older jobs
+        let task_network = serde_json::json!({
+            "AllocatedResources": {
+                "Shared": { "Ports": null, "Networks": null },
+                "Tasks": {
+                    "web": {
+                        "Networks": [{
+                            "IP": "10.0.0.8",
+                            "ReservedPorts": null,
+                            "DynamicPorts": [{ "Label": "http", "Value": 25123 }]
+                        }]
+                    }
+                }
+            }
+        });
+        assert_eq!(allocation_address(&task_network, Some("http")), Some("10.0.0.8:25123".parse().unwrap()));
+        assert_eq!(allocation_address(&serde_json::json!({}), None), None);
+    }
+}
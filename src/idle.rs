@@ -0,0 +1,172 @@
+//! Adaptive idle timeouts learned from request inter-arrival times
+//!
+//! For backends with adaptive idle enabled, the gaps between consecutive
+//! requests are recorded and the idle timeout becomes the gap that
+//! `quantile` of them are shorter than: if 90% of requests arrive within
+//! eight minutes of the previous one, the backend is kept for eight minutes
+//! instead of being stopped just before the next request would have reused
+//! it. A backend that is rarely revisited learns a short timeout and is
+//! stopped sooner. Until `min_samples` gaps were seen the configured
+//! `idle_timeout_secs` applies.
+
+use crate::config::AdaptiveIdleConfig;
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Gaps kept per backend; older ones are forgotten so the timeout follows
+/// changing traffic patterns
+const GAP_HISTORY: usize = 200;
+
+/// Requests closer together than this belong to the same burst (e.g. one
+/// page load) and say nothing about when the next visit comes
+const MIN_GAP: Duration = Duration::from_secs(1);
+
+/// Idle timeout of one backend, as reported by the admin API
+#[derive(Debug, Clone, Serialize)]
+pub struct IdleTimeoutStatus {
+    pub hostname: String,
+    /// Whether the timeout is learned from traffic
+    pub adaptive: bool,
+    /// `idle_timeout_secs` from the config
+    pub configured_secs: u64,
+    /// Timeout learned from the observed gaps, once there are enough
+    pub learned_secs: Option<u64>,
+    /// Timeout currently applied
+    pub effective_secs: u64,
+    /// Gaps observed so far
+    pub samples: usize,
+    /// Kept running regardless of traffic, so the timeout doesn't apply
+    pub keep_warm: bool,
+}
+
+#[derive(Debug)]
+struct Arrivals {
+    last: Instant,
+    gaps: VecDeque<Duration>,
+}
+
+/// Inter-arrival times of every backend's requests
+#[derive(Debug, Default)]
+pub struct IdlePredictor {
+    arrivals: DashMap<String, Mutex<Arrivals>>,
+}
+
+impl IdlePredictor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a request to the backend
+    pub fn record(&self, hostname: &str) {
+        self.record_at(hostname, Instant::now());
+    }
+
+    fn record_at(&self, hostname: &str, now: Instant) {
+        if let Some(arrivals) = self.arrivals.get(hostname) {
+            let mut arrivals = arrivals.lock();
+            let gap = now.saturating_duration_since(arrivals.last);
+            arrivals.last = now;
+            if gap >= MIN_GAP {
+                if arrivals.gaps.len() == GAP_HISTORY {
+                    arrivals.gaps.pop_front();
+                }
+                arrivals.gaps.push_back(gap);
+            }
+            return;
+        }
+        self.arrivals.entry(hostname.to_string()).or_insert_with(|| {
+            Mutex::new(Arrivals {
+                last: now,
+                gaps: VecDeque::new(),
+            })
+        });
+    }
+
+    /// Number of gaps observed for the backend
+    pub fn samples(&self, hostname: &str) -> usize {
+        self.arrivals.get(hostname).map_or(0, |a| a.lock().gaps.len())
+    }
+
+    /// Timeout learned for the backend, or `None` until `min_samples` gaps were seen
+    pub fn learned(&self, hostname: &str, config: &AdaptiveIdleConfig) -> Option<Duration> {
+        let mut gaps: Vec<Duration> = self.arrivals.get(hostname)?.lock().gaps.iter().copied().collect();
+        if gaps.is_empty() || gaps.len() < config.min_samples {
+            return None;
+        }
+        gaps.sort_unstable();
+        let rank = (config.quantile * gaps.len() as f64).ceil() as usize;
+        let gap = gaps[rank.clamp(1, gaps.len()) - 1];
+        // Round up so the gap itself is still covered
+        let secs = gap.as_secs() + u64::from(gap.subsec_nanos() > 0);
+        Some(Duration::from_secs(
+            secs.clamp(config.min_timeout_secs, config.max_timeout_secs),
+        ))
+    }
+
+    /// Drop the history of a removed backend
+    pub fn forget(&self, hostname: &str) {
+        self.arrivals.remove(hostname);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(min_samples: usize) -> AdaptiveIdleConfig {
+        AdaptiveIdleConfig {
+            enabled: true,
+            min_samples,
+            min_timeout_secs: 10,
+            max_timeout_secs: 1000,
+            ..Default::default()
+        }
+    }
+
+    fn record_gaps(predictor: &IdlePredictor, gaps: &[u64]) -> Instant {
+        let mut now = Instant::now();
+        predictor.record_at("app.local", now);
+        for &gap in gaps {
+            now += Duration::from_secs(gap);
+            predictor.record_at("app.local", now);
+        }
+        now
+    }
+
+    #[test]
+    fn test_learns_quantile_of_gaps() {
+        let predictor = IdlePredictor::new();
+        let gaps: Vec<u64> = (1..=10).map(|i| i * 30).collect();
+        record_gaps(&predictor, &gaps);
+
+        assert_eq!(predictor.samples("app.local"), 10);
+        assert_eq!(predictor.learned("app.local", &config(20)), None);
+        // 90% of the gaps are at most 270s
+        assert_eq!(predictor.learned("app.local", &config(10)), Some(Duration::from_secs(270)));
+
+        let all = AdaptiveIdleConfig {
+            quantile: 1.0,
+            ..config(10)
+        };
+        assert_eq!(predictor.learned("app.local", &all), Some(Duration::from_secs(300)));
+    }
+
+    #[test]
+    fn test_bursts_and_bounds() {
+        let predictor = IdlePredictor::new();
+        let start = record_gaps(&predictor, &[2, 2, 2]);
+        // Requests in the same burst are not gaps
+        predictor.record_at("app.local", start + Duration::from_millis(100));
+        assert_eq!(predictor.samples("app.local"), 3);
+
+        // Short gaps are raised to the lower bound
+        assert_eq!(predictor.learned("app.local", &config(3)), Some(Duration::from_secs(10)));
+
+        predictor.forget("app.local");
+        assert_eq!(predictor.samples("app.local"), 0);
+        assert_eq!(predictor.learned("missing.local", &config(1)), None);
+    }
+}
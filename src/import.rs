@@ -0,0 +1,851 @@
+//! Importing nginx and Caddy configurations
+//!
+//! `spawngate import --from <file>` translates the common parts of an nginx
+//! config or a Caddyfile into spawngate backends, as a starting point for a
+//! migration:
+//!
+//! - every server block or site proxying `/` becomes a backend for its first
+//!   host name, on the port of the upstream;
+//! - certificate and key paths become `tls_cert` and `tls_key`;
+//! - redirects of plain HTTP to HTTPS turn on `force_https`.
+//!
+//! Anything else, e.g. other locations, other redirects or static files, is
+//! listed as a comment in the output for review. Spawngate starts backends
+//! itself, so every backend gets a placeholder `command` to fill in.
+
+use std::fmt::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Format of the configuration being imported
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ImportFormat {
+    #[default]
+    Nginx,
+    Caddy,
+}
+
+impl ImportFormat {
+    /// Caddy for `Caddyfile*` and `*.caddy` files, nginx otherwise
+    pub fn detect(path: &Path) -> Self {
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        if name.starts_with("Caddyfile") || name.ends_with(".caddy") {
+            ImportFormat::Caddy
+        } else {
+            ImportFormat::Nginx
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ImportFormat::Nginx => "nginx",
+            ImportFormat::Caddy => "Caddy",
+        }
+    }
+}
+
+impl FromStr for ImportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nginx" => Ok(ImportFormat::Nginx),
+            "caddy" => Ok(ImportFormat::Caddy),
+            _ => anyhow::bail!("unknown format '{}' (expected nginx or caddy)", s),
+        }
+    }
+}
+
+/// A backend translated from server blocks or sites for one host name
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImportedBackend {
+    pub hostname: String,
+    /// Port of the upstream `/` was proxied to
+    pub port: Option<u16>,
+    /// The upstream was reached over HTTPS
+    pub backend_tls: bool,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    /// Plain HTTP requests were redirected to HTTPS
+    pub https_redirect: bool,
+    /// Plain HTTP requests were proxied without a redirect
+    pub serves_http: bool,
+    /// What wasn't translated, for review
+    pub notes: Vec<String>,
+}
+
+/// Result of translating a configuration
+#[derive(Debug, Default)]
+pub struct Import {
+    pub format: ImportFormat,
+    /// Backends in the order their hosts first appear
+    pub backends: Vec<ImportedBackend>,
+    /// What wasn't translated and belongs to no backend
+    pub notes: Vec<String>,
+}
+
+/// What one server block or site does
+#[derive(Debug, Default)]
+struct Site {
+    hostnames: Vec<String>,
+    /// Listens for plain HTTP
+    plain: bool,
+    upstream: Option<Upstream>,
+    redirects_to_https: bool,
+    tls_cert: Option<String>,
+    tls_key: Option<String>,
+    notes: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Upstream {
+    host: String,
+    port: u16,
+    tls: bool,
+}
+
+/// A directive with its arguments and block, if it has one
+#[derive(Debug, Clone, PartialEq)]
+struct Directive {
+    name: String,
+    args: Vec<String>,
+    block: Vec<Directive>,
+}
+
+impl Directive {
+    fn arg(&self, index: usize) -> &str {
+        self.args.get(index).map_or("", String::as_str)
+    }
+
+    fn line(&self) -> String {
+        std::iter::once(self.name.as_str())
+            .chain(self.args.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl Import {
+    /// Translate a configuration in `format`
+    pub fn parse(format: ImportFormat, content: &str) -> anyhow::Result<Self> {
+        let mut import = Import {
+            format,
+            ..Default::default()
+        };
+        match format {
+            ImportFormat::Nginx => import.add_nginx(&parse_nginx(content)?),
+            ImportFormat::Caddy => import.add_caddy(&parse_caddy(content)?),
+        }
+        Ok(import)
+    }
+
+    /// The backends as a config file, with the notes as comments
+    pub fn to_toml(&self) -> String {
+        let force_https = self.backends.iter().any(|b| b.https_redirect);
+        let mut out = format!(
+            "# Imported from {} by `spawngate import`; review before use\n",
+            self.format.name()
+        );
+        if force_https {
+            out.push_str("\n[server]\nforce_https = true\n");
+        }
+        let skipped: Vec<&ImportedBackend> = self.backends.iter().filter(|b| b.port.is_none()).collect();
+        if !self.notes.is_empty() || !skipped.is_empty() {
+            out.push_str("\n# Not imported:\n");
+            for note in &self.notes {
+                let _ = writeln!(out, "# - {}", note);
+            }
+            for backend in skipped {
+                let _ = writeln!(out, "# - {}: no proxy to an upstream", backend.hostname);
+            }
+        }
+
+        for backend in &self.backends {
+            let Some(port) = backend.port else {
+                continue;
+            };
+            let _ = writeln!(out, "\n[backends.{}]", quote(&backend.hostname));
+            let _ = writeln!(
+                out,
+                "command = \"/path/to/app\"  # TODO: command starting the service on port {}",
+                port
+            );
+            let _ = writeln!(out, "port = {}", port);
+            if backend.backend_tls {
+                out.push_str("backend_tls = true\n");
+            }
+            if let Some(cert) = &backend.tls_cert {
+                let _ = writeln!(out, "tls_cert = {}", quote(cert));
+            }
+            if let Some(key) = &backend.tls_key {
+                let _ = writeln!(out, "tls_key = {}", quote(key));
+            }
+            if force_https && backend.serves_http && !backend.https_redirect {
+                out.push_str("https_redirect = false\n");
+            }
+            if !backend.notes.is_empty() {
+                out.push_str("# Not imported:\n");
+                for note in &backend.notes {
+                    let _ = writeln!(out, "# - {}", note);
+                }
+            }
+        }
+        out
+    }
+
+    fn note(&mut self, note: String) {
+        if !self.notes.contains(&note) {
+            self.notes.push(note);
+        }
+    }
+
+    /// Merge a site into the backend for its first host name
+    fn add_site(&mut self, site: Site) {
+        let Some(hostname) = site.hostnames.first() else {
+            if site.upstream.is_some() {
+                self.note("a server block without a host name proxies to an upstream".to_string());
+            }
+            return;
+        };
+        let index = match self.backends.iter().position(|b| &b.hostname == hostname) {
+            Some(index) => index,
+            None => {
+                self.backends.push(ImportedBackend {
+                    hostname: hostname.clone(),
+                    ..Default::default()
+                });
+                self.backends.len() - 1
+            }
+        };
+        let backend = &mut self.backends[index];
+
+        let mut notes = site.notes;
+        for alias in &site.hostnames[1..] {
+            notes.push(format!("also served as {}; spawngate routes one host name per backend", alias));
+        }
+        if let Some(upstream) = site.upstream {
+            if !matches!(upstream.host.as_str(), "127.0.0.1" | "localhost" | "::1" | "[::1]") {
+                notes.push(format!(
+                    "upstream host {} is not local; spawngate forwards to 127.0.0.1",
+                    upstream.host
+                ));
+            }
+            backend.port = Some(upstream.port);
+            backend.backend_tls = upstream.tls;
+            backend.serves_http |= site.plain;
+        }
+        backend.https_redirect |= site.redirects_to_https;
+        backend.tls_cert = site.tls_cert.or(backend.tls_cert.take());
+        backend.tls_key = site.tls_key.or(backend.tls_key.take());
+        for note in notes {
+            if !backend.notes.contains(&note) {
+                backend.notes.push(note);
+            }
+        }
+    }
+
+    fn add_nginx(&mut self, directives: &[Directive]) {
+        let mut upstreams = Vec::new();
+        collect_nginx_upstreams(directives, &mut upstreams);
+        self.add_nginx_block(directives, &upstreams);
+    }
+
+    fn add_nginx_block(&mut self, directives: &[Directive], upstreams: &[(String, String)]) {
+        for directive in directives {
+            match directive.name.as_str() {
+                "http" => self.add_nginx_block(&directive.block, upstreams),
+                "server" => {
+                    let site = nginx_site(directive, upstreams);
+                    self.add_site(site);
+                }
+                "include" => self.note(format!("include {} was not followed", directive.arg(0))),
+                _ => {}
+            }
+        }
+    }
+
+    fn add_caddy(&mut self, directives: &[Directive]) {
+        for site in directives {
+            match site.name.as_str() {
+                // Global options
+                "" => {}
+                name if name.starts_with('(') => self.note(format!("snippet {} was not imported", name)),
+                "import" => self.note(format!("import {} was not followed", site.arg(0))),
+                _ => {
+                    let site = caddy_site(site);
+                    if site.upstream.is_some() && site.tls_cert.is_none() && !site.plain {
+                        self.note(
+                            "Caddy obtained certificates automatically; enable [server.acme] for the same".to_string(),
+                        );
+                    }
+                    self.add_site(site);
+                }
+            }
+        }
+    }
+}
+
+/// `upstream` blocks by name, with their first server
+fn collect_nginx_upstreams(directives: &[Directive], upstreams: &mut Vec<(String, String)>) {
+    for directive in directives {
+        match directive.name.as_str() {
+            "http" => collect_nginx_upstreams(&directive.block, upstreams),
+            "upstream" => {
+                if let Some(server) = directive.block.iter().find(|d| d.name == "server") {
+                    upstreams.push((directive.arg(0).to_string(), server.arg(0).to_string()));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn nginx_site(server: &Directive, upstreams: &[(String, String)]) -> Site {
+    let mut site = Site::default();
+    let mut listens = false;
+
+    for directive in &server.block {
+        match directive.name.as_str() {
+            "server_name" => {
+                for name in &directive.args {
+                    if name.starts_with('~') || name.contains('*') {
+                        site.notes.push(format!("server_name {} is a pattern", name));
+                    } else if name != "_" && !name.is_empty() {
+                        site.hostnames.push(name.clone());
+                    }
+                }
+            }
+            "listen" => {
+                listens = true;
+                if !directive.args.iter().any(|a| a == "ssl" || a.ends_with("443")) {
+                    site.plain = true;
+                }
+            }
+            "ssl_certificate" => site.tls_cert = Some(directive.arg(0).to_string()),
+            "ssl_certificate_key" => site.tls_key = Some(directive.arg(0).to_string()),
+            "return" => {
+                if is_https_redirect(directive.arg(0), directive.arg(1)) {
+                    site.redirects_to_https = true;
+                } else {
+                    site.notes.push(directive.line());
+                }
+            }
+            "location" => {
+                let path = directive.args.last().map_or("", String::as_str);
+                let modifier = if directive.args.len() > 1 { directive.arg(0) } else { "" };
+                let proxy = directive.block.iter().find(|d| d.name == "proxy_pass");
+                match proxy {
+                    Some(proxy) if path == "/" && modifier.is_empty() => {
+                        match nginx_upstream(proxy.arg(0), upstreams) {
+                            Some(upstream) => site.upstream = Some(upstream),
+                            None => site.notes.push(format!("location / proxies to {}", proxy.arg(0))),
+                        }
+                    }
+                    Some(proxy) => site.notes.push(format!(
+                        "location {} proxies to {}; add a backend and a route with path_prefix",
+                        directive.args.join(" "),
+                        proxy.arg(0)
+                    )),
+                    None => {
+                        if let Some(ret) = directive.block.iter().find(|d| d.name == "return") {
+                            if path == "/" && is_https_redirect(ret.arg(0), ret.arg(1)) {
+                                site.redirects_to_https = true;
+                                continue;
+                            }
+                        }
+                        site.notes.push(format!("location {} does not proxy", directive.args.join(" ")));
+                    }
+                }
+            }
+            "root" => site.notes.push(format!("static files from {}", directive.arg(0))),
+            "include" => site.notes.push(format!("include {} was not followed", directive.arg(0))),
+            _ => {}
+        }
+    }
+
+    // nginx listens on port 80 when a server has no listen directive
+    if !listens {
+        site.plain = true;
+    }
+    site
+}
+
+/// `return 301 https://$host$request_uri` and similar
+fn is_https_redirect(code: &str, target: &str) -> bool {
+    matches!(code, "301" | "302" | "307" | "308")
+        && target.starts_with("https://")
+        && (target.ends_with("$request_uri") || target.ends_with("{uri}"))
+}
+
+/// Upstream of a `proxy_pass` URL, resolving `upstream` block names
+fn nginx_upstream(url: &str, upstreams: &[(String, String)]) -> Option<Upstream> {
+    let (tls, rest) = if let Some(rest) = url.strip_prefix("https://") {
+        (true, rest)
+    } else {
+        (false, url.strip_prefix("http://")?)
+    };
+    let authority = rest.split('/').next().unwrap_or("");
+    if authority.contains('$') {
+        return None;
+    }
+    let authority = upstreams
+        .iter()
+        .find(|(name, _)| name == authority)
+        .map_or(authority, |(_, server)| server.as_str());
+    let (host, port) = split_host_port(authority, if tls { 443 } else { 80 })?;
+    Some(Upstream { host, port, tls })
+}
+
+fn caddy_site(site: &Directive) -> Site {
+    let mut result = Site::default();
+    for address in std::iter::once(&site.name).chain(&site.args) {
+        let address = address.trim_end_matches(',');
+        let (plain, rest) = match address.strip_prefix("http://") {
+            Some(rest) => (true, rest),
+            None => (false, address.strip_prefix("https://").unwrap_or(address)),
+        };
+        result.plain |= plain;
+        let host = match rest.rsplit_once(':') {
+            Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => {
+                if port == "80" {
+                    result.plain = true;
+                }
+                host
+            }
+            _ => rest,
+        };
+        if host.contains('*') {
+            result.notes.push(format!("site address {} is a pattern", address));
+        } else if !host.is_empty() {
+            result.hostnames.push(host.to_string());
+        }
+    }
+    // Caddy redirects plain HTTP to HTTPS for every site served over HTTPS
+    if !result.plain {
+        result.redirects_to_https = true;
+    }
+
+    add_caddy_directives(&mut result, &site.block);
+    result
+}
+
+fn add_caddy_directives(site: &mut Site, directives: &[Directive]) {
+    for directive in directives {
+        let matcher = directive
+            .args
+            .first()
+            .filter(|a| a.starts_with('/') || a.starts_with('@') || a.as_str() == "*");
+        match directive.name.as_str() {
+            "reverse_proxy" => {
+                let root = matcher.is_none_or(|m| m == "*" || m == "/*");
+                let upstream = directive.args.iter().skip(matcher.is_some() as usize).find_map(|a| caddy_upstream(a));
+                match upstream {
+                    Some(upstream) if root => site.upstream = Some(upstream),
+                    _ => site.notes.push(directive.line()),
+                }
+            }
+            "handle" if directive.args.is_empty() => add_caddy_directives(site, &directive.block),
+            "redir" => {
+                let (target, code) = if matcher.is_some() {
+                    (directive.arg(1), directive.arg(2))
+                } else {
+                    (directive.arg(0), directive.arg(1))
+                };
+                let code = if code.is_empty() || code == "permanent" { "301" } else { code };
+                if matcher.is_none() && is_https_redirect(code, target) {
+                    site.redirects_to_https = true;
+                } else {
+                    site.notes.push(directive.line());
+                }
+            }
+            "tls" if directive.args.len() == 2 => {
+                site.tls_cert = Some(directive.arg(0).to_string());
+                site.tls_key = Some(directive.arg(1).to_string());
+            }
+            "tls" => site.notes.push(directive.line()),
+            "encode" | "log" => {}
+            _ => site.notes.push(directive.line()),
+        }
+    }
+}
+
+/// Upstream of a `reverse_proxy` address: `localhost:3000`, `:3000`,
+/// `http://127.0.0.1:3000` or `https://...`
+fn caddy_upstream(address: &str) -> Option<Upstream> {
+    let (tls, rest) = if let Some(rest) = address.strip_prefix("https://") {
+        (true, rest)
+    } else {
+        (false, address.strip_prefix("http://").unwrap_or(address))
+    };
+    if rest.contains("://") || rest.contains('{') {
+        return None;
+    }
+    let (host, port) = split_host_port(rest, if tls { 443 } else { 80 })?;
+    let host = if host.is_empty() { "localhost".to_string() } else { host };
+    Some(Upstream { host, port, tls })
+}
+
+/// Host and port of `host[:port]` or `[v6][:port]`
+fn split_host_port(authority: &str, default_port: u16) -> Option<(String, u16)> {
+    if let Some(rest) = authority.strip_prefix('[') {
+        let (host, port) = rest.split_once(']')?;
+        let port = match port.strip_prefix(':') {
+            Some(port) => port.parse().ok()?,
+            None => default_port,
+        };
+        return Some((format!("[{}]", host), port));
+    }
+    match authority.rsplit_once(':') {
+        Some((host, port)) => Some((host.to_string(), port.parse().ok()?)),
+        None => Some((authority.to_string(), default_port)),
+    }
+}
+
+/// Quote a string as a TOML basic string
+fn quote(s: &str) -> String {
+    toml::Value::String(s.to_string()).to_string()
+}
+
+/// Token of an nginx config
+#[derive(Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Semicolon,
+    Open,
+    Close,
+}
+
+fn tokenize_nginx(content: &str) -> anyhow::Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '#' => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            ';' => tokens.push(Token::Semicolon),
+            '{' => tokens.push(Token::Open),
+            '}' => tokens.push(Token::Close),
+            '"' | '\'' => {
+                let mut word = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => word.extend(chars.next()),
+                        Some(q) if q == c => break,
+                        Some(other) => word.push(other),
+                        None => anyhow::bail!("unterminated quoted string"),
+                    }
+                }
+                tokens.push(Token::Word(word));
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || matches!(next, ';' | '{' | '}') {
+                        break;
+                    }
+                    word.push(next);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_nginx(content: &str) -> anyhow::Result<Vec<Directive>> {
+    let tokens = tokenize_nginx(content)?;
+    let mut tokens = tokens.into_iter();
+    let directives = parse_nginx_block(&mut tokens, false)?;
+    Ok(directives)
+}
+
+fn parse_nginx_block(tokens: &mut std::vec::IntoIter<Token>, nested: bool) -> anyhow::Result<Vec<Directive>> {
+    let mut directives = Vec::new();
+    let mut words = Vec::new();
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Word(word) => words.push(word),
+            Token::Semicolon | Token::Open => {
+                if words.is_empty() {
+                    anyhow::bail!("unexpected '{}'", if token == Token::Open { "{" } else { ";" });
+                }
+                let block = if token == Token::Open {
+                    parse_nginx_block(tokens, true)?
+                } else {
+                    Vec::new()
+                };
+                let mut words = std::mem::take(&mut words).into_iter();
+                directives.push(Directive {
+                    name: words.next().unwrap_or_default(),
+                    args: words.collect(),
+                    block,
+                });
+            }
+            Token::Close if nested && words.is_empty() => return Ok(directives),
+            Token::Close => anyhow::bail!("unexpected '}}'"),
+        }
+    }
+    if nested {
+        anyhow::bail!("missing '}}'");
+    }
+    if !words.is_empty() {
+        anyhow::bail!("missing ';' after '{}'", words.join(" "));
+    }
+    Ok(directives)
+}
+
+/// Words of a Caddyfile line, without its comment
+fn caddy_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '#' => break,
+            '"' => {
+                let mut word = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => word.extend(chars.next()),
+                        '"' => break,
+                        c => word.push(c),
+                    }
+                }
+                words.push(word);
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() {
+                        break;
+                    }
+                    word.push(next);
+                    chars.next();
+                }
+                words.push(word);
+            }
+        }
+    }
+    words
+}
+
+/// Parse a Caddyfile into sites, named by their first address with the
+/// others as arguments; the global options block has an empty name
+fn parse_caddy(content: &str) -> anyhow::Result<Vec<Directive>> {
+    let lines: Vec<Vec<String>> = content
+        .lines()
+        .map(caddy_words)
+        .filter(|words| !words.is_empty())
+        .collect();
+    let mut lines = lines.into_iter().peekable();
+
+    // A single site may leave out the braces
+    if lines.peek().is_some_and(|first| first.last().is_some_and(|w| w != "{")) {
+        let mut addresses = lines.next().unwrap_or_default().into_iter();
+        let block = parse_caddy_block(&mut lines, false)?;
+        return Ok(vec![Directive {
+            name: addresses.next().unwrap_or_default(),
+            args: addresses.collect(),
+            block,
+        }]);
+    }
+    parse_caddy_block(&mut lines, false)
+}
+
+fn parse_caddy_block(
+    lines: &mut std::iter::Peekable<std::vec::IntoIter<Vec<String>>>,
+    nested: bool,
+) -> anyhow::Result<Vec<Directive>> {
+    let mut directives = Vec::new();
+    while let Some(mut words) = lines.next() {
+        if words == ["}"] {
+            if nested {
+                return Ok(directives);
+            }
+            anyhow::bail!("unexpected '}}'");
+        }
+        let block = if words.last().is_some_and(|w| w == "{") {
+            words.pop();
+            parse_caddy_block(lines, true)?
+        } else {
+            Vec::new()
+        };
+        let mut words = words.into_iter();
+        directives.push(Directive {
+            name: words.next().unwrap_or_default(),
+            args: words.collect(),
+            block,
+        });
+    }
+    if nested {
+        anyhow::bail!("missing '}}'");
+    }
+    Ok(directives)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_import_nginx() {
+        let import = Import::parse(
+            ImportFormat::Nginx,
+            r#"
+http {
+    include /etc/nginx/mime.types;
+    upstream app_servers {
+        server 127.0.0.1:3000;
+    }
+
+    # Redirect everything to HTTPS
+    server {
+        listen 80;
+        server_name example.com www.example.com;
+        return 301 https://$host$request_uri;
+    }
+
+    server {
+        listen 443 ssl http2;
+        server_name example.com www.example.com;
+        ssl_certificate "/etc/ssl/example.com.pem";
+        ssl_certificate_key /etc/ssl/example.com.key;
+
+        location / {
+            proxy_pass http://app_servers;
+            proxy_set_header Host $host;
+        }
+        location /api/ {
+            proxy_pass http://127.0.0.1:4000;
+        }
+    }
+
+    server {
+        listen 80;
+        server_name internal.local;
+        location / {
+            proxy_pass https://10.0.0.5:8443/;
+        }
+    }
+
+    server {
+        listen 80;
+        server_name static.local;
+        root /var/www;
+    }
+}
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(import.notes, vec!["include /etc/nginx/mime.types was not followed"]);
+        let example = &import.backends[0];
+        assert_eq!(example.hostname, "example.com");
+        assert_eq!(example.port, Some(3000));
+        assert_eq!(example.tls_cert.as_deref(), Some("/etc/ssl/example.com.pem"));
+        assert_eq!(example.tls_key.as_deref(), Some("/etc/ssl/example.com.key"));
+        assert!(example.https_redirect);
+        assert!(!example.serves_http);
+        assert_eq!(
+            example.notes,
+            vec![
+                "also served as www.example.com; spawngate routes one host name per backend",
+                "location /api/ proxies to http://127.0.0.1:4000; add a backend and a route with path_prefix",
+            ]
+        );
+
+        let internal = &import.backends[1];
+        assert_eq!((internal.port, internal.backend_tls, internal.serves_http), (Some(8443), true, true));
+        assert_eq!(internal.notes, vec!["upstream host 10.0.0.5 is not local; spawngate forwards to 127.0.0.1"]);
+        assert_eq!(import.backends[2].port, None);
+
+        let toml = import.to_toml();
+        assert!(toml.contains("force_https = true"), "{}", toml);
+        assert!(toml.contains("# - static.local: no proxy to an upstream"), "{}", toml);
+        let config = Config::parse(&toml).unwrap();
+        assert!(config.server.force_https);
+        assert_eq!(config.backends.len(), 2);
+        assert_eq!(config.backends["example.com"].port, 3000);
+        assert!(config.backends["example.com"].https_redirect);
+        assert!(!config.backends["internal.local"].https_redirect);
+        assert!(config.backends["internal.local"].backend_tls);
+    }
+
+    #[test]
+    fn test_import_caddy() {
+        let import = Import::parse(
+            ImportFormat::Caddy,
+            r#"
+{
+    email admin@example.com
+}
+
+example.com, www.example.com {
+    encode gzip
+    reverse_proxy localhost:3000
+    reverse_proxy /api/* localhost:4000
+}
+
+secure.example.com {
+    tls /etc/ssl/secure.pem /etc/ssl/secure.key
+    handle {
+        reverse_proxy 127.0.0.1:5000
+    }
+}
+
+http://plain.local {
+    reverse_proxy :6000
+}
+"#,
+        )
+        .unwrap();
+
+        let ports: Vec<_> = import.backends.iter().map(|b| (b.hostname.as_str(), b.port)).collect();
+        assert_eq!(
+            ports,
+            vec![
+                ("example.com", Some(3000)),
+                ("secure.example.com", Some(5000)),
+                ("plain.local", Some(6000))
+            ]
+        );
+        assert!(import.backends[0].https_redirect);
+        assert!(import.backends[0].notes.contains(&"reverse_proxy /api/* localhost:4000".to_string()));
+        assert_eq!(import.backends[1].tls_cert.as_deref(), Some("/etc/ssl/secure.pem"));
+        assert!(import.backends[2].serves_http && !import.backends[2].https_redirect);
+        assert_eq!(
+            import.notes,
+            vec!["Caddy obtained certificates automatically; enable [server.acme] for the same"]
+        );
+
+        let config = Config::parse(&import.to_toml()).unwrap();
+        assert!(config.server.force_https);
+        assert!(!config.backends["plain.local"].https_redirect);
+
+        // A single site may leave out the braces
+        let import = Import::parse(ImportFormat::Caddy, "app.local\nreverse_proxy localhost:3000\n").unwrap();
+        assert_eq!(import.backends[0].hostname, "app.local");
+        assert_eq!(import.backends[0].port, Some(3000));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Import::parse(ImportFormat::Nginx, "server { listen 80;").is_err());
+        assert!(Import::parse(ImportFormat::Nginx, "server_name a.local").is_err());
+        assert!(Import::parse(ImportFormat::Nginx, "}").is_err());
+        assert!(Import::parse(ImportFormat::Caddy, "a.local {\nreverse_proxy :3000\n").is_err());
+
+        assert_eq!(ImportFormat::detect(Path::new("/etc/caddy/Caddyfile")), ImportFormat::Caddy);
+        assert_eq!(ImportFormat::detect(Path::new("sites/app.caddy")), ImportFormat::Caddy);
+        assert_eq!(ImportFormat::detect(Path::new("/etc/nginx/nginx.conf")), ImportFormat::Nginx);
+        assert!("apache".parse::<ImportFormat>().is_err());
+    }
+}
@@ -3,17 +3,74 @@
 //! This library provides a serverless-style reverse proxy that:
 //! - Routes HTTP traffic based on Host header to configured backends
 //! - Spawns backend processes on-demand when traffic arrives
-//! - Supports both local processes and Docker containers as backends
+//! - Supports local processes, Docker containers and Firecracker microVMs as backends
 //! - Monitors backend health via polling and callback mechanisms
 //! - Automatically shuts down idle backends after a configurable timeout
 //! - Uses connection pooling for efficient backend communication
 //! - Supports automatic TLS via ACME/Let's Encrypt
+//!
+//! [`Spawngate::builder`] runs the whole proxy inside another binary.
 
 pub mod acme;
 pub mod admin;
+pub mod app;
+pub mod apply;
+pub mod certwatch;
+pub mod cgroup;
+pub mod cloud;
+pub mod coldstart;
 pub mod config;
+pub mod configvars;
+pub mod crash;
+#[cfg(unix)]
+pub mod credentials;
+pub mod discovery;
+pub mod dns;
 pub mod docker;
+pub mod early_data;
 pub mod error;
+pub mod experiments;
+pub mod faults;
+pub mod files;
+pub mod fingerprint;
+pub mod h2c;
+pub mod firecracker;
+pub mod hashicorp;
+pub mod idle;
+pub mod import;
+pub mod listeners;
+pub mod lockout;
+pub mod logs;
+pub mod metrics;
+pub mod normalize;
+pub mod podman;
 pub mod pool;
+pub mod ports;
+pub mod preflight;
+pub mod priority;
 pub mod process;
+pub mod profiling;
+pub mod promotion;
 pub mod proxy;
+pub mod quota;
+pub mod registry;
+pub mod relay;
+pub mod rewrite;
+pub mod routing;
+pub mod sampling;
+pub mod savings;
+pub mod selector;
+pub mod shutdown;
+pub mod signing;
+pub mod slo;
+pub mod slowlog;
+pub mod ssh;
+pub mod supervisor;
+#[cfg(any(test, feature = "testkit"))]
+pub mod testkit;
+pub mod throttle;
+pub mod tickets;
+pub mod tls;
+pub mod waf;
+
+pub use app::{Spawngate, SpawngateBuilder};
@@ -0,0 +1,228 @@
+//! Proxy listeners that can be replaced without a restart
+//!
+//! Reloading `[server]` binds listeners for new addresses before the running
+//! servers stop accepting, so a failed bind leaves everything as it was.
+//! Listeners whose address did not change are handed over to the new servers
+//! rather than closed and bound again. The replaced servers then drain their
+//! open connections in the background.
+
+use crate::config::ServerConfig;
+use crate::proxy::ProxyServer;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::error;
+
+/// `[server]` settings that take effect by replacing the proxy listeners
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListenerSettings {
+    pub bind: String,
+    /// HTTP port (0 when disabled)
+    pub http_port: u16,
+    /// HTTPS port (0 when disabled)
+    pub https_port: u16,
+    pub force_https: bool,
+    pub https_redirect_status: u16,
+    /// Default certificate and key files, unless ACME or a self-signed
+    /// certificate is used
+    pub tls_files: Option<(String, String)>,
+}
+
+impl ListenerSettings {
+    pub fn new(server: &ServerConfig) -> Self {
+        let tls_files = match (&server.tls_cert, &server.tls_key) {
+            (Some(cert), Some(key)) if !server.acme_enabled() => Some((cert.clone(), key.clone())),
+            _ => None,
+        };
+        Self {
+            bind: server.bind.clone(),
+            http_port: server.http_port(),
+            https_port: server.https_port(),
+            force_https: server.force_https,
+            https_redirect_status: server.https_redirect_status,
+            tls_files,
+        }
+    }
+
+    /// Address of the HTTP listener, if enabled
+    pub fn http_addr(&self) -> anyhow::Result<Option<SocketAddr>> {
+        self.addr(self.http_port)
+    }
+
+    /// Address of the HTTPS listener, if enabled
+    pub fn https_addr(&self) -> anyhow::Result<Option<SocketAddr>> {
+        self.addr(self.https_port)
+    }
+
+    fn addr(&self, port: u16) -> anyhow::Result<Option<SocketAddr>> {
+        if port == 0 {
+            return Ok(None);
+        }
+        let addr = format!("{}:{}", self.bind, port)
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid bind address '{}': {}", self.bind, e))?;
+        Ok(Some(addr))
+    }
+}
+
+/// Proxy servers built for a set of listener settings
+#[derive(Default)]
+pub struct ProxyServers {
+    pub http: Option<ProxyServer>,
+    pub https: Option<ProxyServer>,
+}
+
+/// The proxy listeners currently serving, with their servers
+pub struct Listeners {
+    settings: ListenerSettings,
+    http: Option<(SocketAddr, Arc<TcpListener>)>,
+    https: Option<(SocketAddr, Arc<TcpListener>)>,
+    shutdown_tx: watch::Sender<bool>,
+    servers: Vec<JoinHandle<()>>,
+}
+
+impl Listeners {
+    /// Bind the listeners and start the servers returned by `build`
+    ///
+    /// `build` gets the shutdown receiver the servers must use. A server is
+    /// only started if its listener is enabled in `settings`.
+    pub async fn start<F>(settings: ListenerSettings, build: F) -> anyhow::Result<Self>
+    where
+        F: FnOnce(&ListenerSettings, watch::Receiver<bool>) -> anyhow::Result<ProxyServers>,
+    {
+        Self::launch(settings, None, build).await
+    }
+
+    /// Move to new listener settings; returns false if nothing changed
+    ///
+    /// New listeners are bound and serving before the current servers stop
+    /// accepting. On error the current listeners keep serving.
+    pub async fn replace<F>(&mut self, settings: ListenerSettings, build: F) -> anyhow::Result<bool>
+    where
+        F: FnOnce(&ListenerSettings, watch::Receiver<bool>) -> anyhow::Result<ProxyServers>,
+    {
+        if settings == self.settings {
+            return Ok(false);
+        }
+
+        let next = Self::launch(settings, Some(self), build).await?;
+        // The replaced servers drain their connections in their own tasks
+        let previous = std::mem::replace(self, next);
+        let _ = previous.shutdown_tx.send(true);
+        Ok(true)
+    }
+
+    async fn launch<F>(settings: ListenerSettings, current: Option<&Self>, build: F) -> anyhow::Result<Self>
+    where
+        F: FnOnce(&ListenerSettings, watch::Receiver<bool>) -> anyhow::Result<ProxyServers>,
+    {
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let servers = build(&settings, shutdown_rx)?;
+
+        // Bind everything before starting anything, so a failure changes nothing
+        let http = match &servers.http {
+            Some(_) => Some(Self::listener(settings.http_addr()?, current).await?),
+            None => None,
+        };
+        let https = match &servers.https {
+            Some(_) => Some(Self::listener(settings.https_addr()?, current).await?),
+            None => None,
+        };
+
+        let mut tasks = Vec::new();
+        for (server, listener, protocol) in [(servers.http, &http, "HTTP"), (servers.https, &https, "HTTPS")] {
+            let (Some(server), Some((addr, listener))) = (server, listener) else {
+                continue;
+            };
+            let listener = Arc::clone(listener);
+            let name = format!("{} proxy server {}", protocol, addr);
+            let registry = Arc::clone(server.debug_registry());
+            tasks.push(registry.spawn(name, async move {
+                if let Err(e) = server.serve_shared(listener).await {
+                    error!(protocol, error = %e, "Proxy server error");
+                }
+            }));
+        }
+
+        Ok(Self {
+            settings,
+            http,
+            https,
+            shutdown_tx,
+            servers: tasks,
+        })
+    }
+
+    /// Reuse the current listener for `addr` or bind a new one
+    async fn listener(
+        addr: Option<SocketAddr>,
+        current: Option<&Self>,
+    ) -> anyhow::Result<(SocketAddr, Arc<TcpListener>)> {
+        let addr = addr.ok_or_else(|| anyhow::anyhow!("Proxy server built for a disabled listener"))?;
+        let existing = current
+            .into_iter()
+            .flat_map(|c| [&c.http, &c.https])
+            .flatten()
+            .find(|(bound, _)| *bound == addr);
+        if let Some((_, listener)) = existing {
+            return Ok((addr, Arc::clone(listener)));
+        }
+
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to bind {}: {}", addr, e))?;
+        Ok((addr, Arc::new(listener)))
+    }
+
+    pub fn settings(&self) -> &ListenerSettings {
+        &self.settings
+    }
+
+    /// Local address of the HTTP listener
+    pub fn http_addr(&self) -> Option<SocketAddr> {
+        self.http.as_ref().and_then(|(_, l)| l.local_addr().ok())
+    }
+
+    /// Local address of the HTTPS listener
+    pub fn https_addr(&self) -> Option<SocketAddr> {
+        self.https.as_ref().and_then(|(_, l)| l.local_addr().ok())
+    }
+
+    /// Stop accepting and wait for the servers to drain their connections
+    pub async fn shutdown(self) {
+        let _ = self.shutdown_tx.send(true);
+        // Listeners handed over to other servers stay open
+        drop(self.http);
+        drop(self.https);
+        for server in self.servers {
+            let _ = server.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listener_settings() {
+        let mut server = ServerConfig {
+            port: 8080,
+            tls_cert: Some("cert.pem".to_string()),
+            tls_key: Some("key.pem".to_string()),
+            ..Default::default()
+        };
+        let settings = ListenerSettings::new(&server);
+        assert_eq!(settings.http_addr().unwrap(), Some("0.0.0.0:8080".parse().unwrap()));
+        assert_eq!(settings.https_addr().unwrap(), Some("0.0.0.0:443".parse().unwrap()));
+        assert_eq!(settings.tls_files, Some(("cert.pem".to_string(), "key.pem".to_string())));
+
+        server.port = 0;
+        server.bind = "not an address".to_string();
+        let settings = ListenerSettings::new(&server);
+        assert_eq!(settings.http_addr().unwrap(), None);
+        assert!(settings.https_addr().is_err());
+    }
+}
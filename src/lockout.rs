@@ -0,0 +1,301 @@
+//! Lockout of clients that repeatedly fail admin API authentication
+//!
+//! Failed attempts are counted per client IP in a fixed window. A client
+//! exceeding the limit is refused for a lockout period that doubles with
+//! each repeat offence, so guessing the admin token is impractical even for
+//! a patient attacker. Failures and lockouts are kept in a bounded audit log.
+
+use crate::config::AdminLockoutConfig;
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Number of audit entries kept
+const AUDIT_LIMIT: usize = 200;
+
+/// Tracked clients above which idle entries are pruned on insert
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// What happened in an audit entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthEvent {
+    /// A request failed authentication
+    Failure,
+    /// The client was locked out
+    Lockout,
+}
+
+/// An authentication failure or lockout
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEntry {
+    /// Unix timestamp of the event
+    pub at: u64,
+    pub client: IpAddr,
+    pub event: AuthEvent,
+    pub method: String,
+    pub path: String,
+    /// Lockout duration, for lockout events
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lockout_secs: Option<u64>,
+}
+
+/// Counters exposed through the admin API
+#[derive(Debug, Clone, Serialize)]
+pub struct LockoutStats {
+    /// Failed authentication attempts since startup
+    pub failures: u64,
+    /// Lockouts issued since startup
+    pub lockouts: u64,
+    /// Clients currently locked out
+    pub locked_clients: Vec<LockedClient>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LockedClient {
+    pub client: IpAddr,
+    pub remaining_secs: u64,
+}
+
+#[derive(Debug)]
+struct ClientState {
+    window_started: Instant,
+    failures: u32,
+    /// Lockouts so far; each doubles the next one
+    strikes: u32,
+    locked_until: Option<Instant>,
+    last_failure: Instant,
+}
+
+/// Per-client failure counter with exponential lockout
+#[derive(Debug)]
+pub struct AuthLockout {
+    max_failures: u32,
+    window: Duration,
+    lockout: Duration,
+    max_lockout: Duration,
+    clients: DashMap<IpAddr, ClientState>,
+    audit: Mutex<VecDeque<AuditEntry>>,
+    failures: AtomicU64,
+    lockouts: AtomicU64,
+}
+
+impl AuthLockout {
+    pub fn new(config: &AdminLockoutConfig) -> Self {
+        Self {
+            max_failures: config.max_failures,
+            window: Duration::from_secs(config.window_secs),
+            lockout: Duration::from_secs(config.lockout_secs),
+            max_lockout: Duration::from_secs(config.max_lockout_secs),
+            clients: DashMap::new(),
+            audit: Mutex::new(VecDeque::new()),
+            failures: AtomicU64::new(0),
+            lockouts: AtomicU64::new(0),
+        }
+    }
+
+    /// Remaining lockout for `ip`, if it is locked out
+    pub fn locked_out(&self, ip: IpAddr) -> Option<Duration> {
+        self.locked_out_at(ip, Instant::now())
+    }
+
+    fn locked_out_at(&self, ip: IpAddr, now: Instant) -> Option<Duration> {
+        let client = self.clients.get(&ip)?;
+        client.locked_until.filter(|until| now < *until).map(|until| until - now)
+    }
+
+    /// Record a failed authentication, returning the lockout it triggered
+    pub fn record_failure(&self, ip: IpAddr, method: &str, path: &str) -> Option<Duration> {
+        self.record_failure_at(ip, method, path, Instant::now())
+    }
+
+    fn record_failure_at(&self, ip: IpAddr, method: &str, path: &str, now: Instant) -> Option<Duration> {
+        if self.clients.len() > PRUNE_THRESHOLD {
+            self.prune(now);
+        }
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        self.audit(ip, AuthEvent::Failure, method, path, None);
+
+        let mut client = self.clients.entry(ip).or_insert_with(|| ClientState {
+            window_started: now,
+            failures: 0,
+            strikes: 0,
+            locked_until: None,
+            last_failure: now,
+        });
+
+        // Strikes are forgiven after a full maximum lockout without failures
+        if now.duration_since(client.last_failure) >= self.max_lockout + self.window {
+            client.strikes = 0;
+        }
+        client.last_failure = now;
+
+        if now.duration_since(client.window_started) >= self.window {
+            client.window_started = now;
+            client.failures = 0;
+        }
+        client.failures += 1;
+        if client.failures <= self.max_failures {
+            return None;
+        }
+
+        let lockout = self
+            .lockout
+            .saturating_mul(2u32.saturating_pow(client.strikes))
+            .min(self.max_lockout);
+        client.strikes += 1;
+        client.failures = 0;
+        client.window_started = now;
+        client.locked_until = Some(now + lockout);
+        drop(client);
+
+        self.lockouts.fetch_add(1, Ordering::Relaxed);
+        self.audit(ip, AuthEvent::Lockout, method, path, Some(lockout.as_secs()));
+        warn!(client = %ip, lockout_secs = lockout.as_secs(), "Admin API client locked out after failed authentication");
+        Some(lockout)
+    }
+
+    /// Record a successful authentication, clearing the client's failures
+    pub fn record_success(&self, ip: IpAddr) {
+        self.clients.remove(&ip);
+    }
+
+    fn audit(&self, client: IpAddr, event: AuthEvent, method: &str, path: &str, lockout_secs: Option<u64>) {
+        let entry = AuditEntry {
+            at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            client,
+            event,
+            method: method.to_string(),
+            path: path.to_string(),
+            lockout_secs,
+        };
+        let mut audit = self.audit.lock();
+        if audit.len() == AUDIT_LIMIT {
+            audit.pop_front();
+        }
+        audit.push_back(entry);
+    }
+
+    /// Recent failures and lockouts, oldest first
+    pub fn audit_log(&self) -> Vec<AuditEntry> {
+        self.audit.lock().iter().cloned().collect()
+    }
+
+    /// Drop clients that are neither locked out nor have recent failures
+    fn prune(&self, now: Instant) {
+        self.clients.retain(|_, client| {
+            client.locked_until.is_some_and(|until| now < until)
+                || now.duration_since(client.last_failure) < self.max_lockout + self.window
+        });
+    }
+
+    pub fn stats(&self) -> LockoutStats {
+        let now = Instant::now();
+        let mut locked_clients: Vec<LockedClient> = self
+            .clients
+            .iter()
+            .filter_map(|c| {
+                let until = c.locked_until.filter(|until| now < *until)?;
+                Some(LockedClient {
+                    client: *c.key(),
+                    remaining_secs: (until - now).as_millis().div_ceil(1000) as u64,
+                })
+            })
+            .collect();
+        locked_clients.sort_by_key(|c| c.client);
+        LockoutStats {
+            failures: self.failures.load(Ordering::Relaxed),
+            lockouts: self.lockouts.load(Ordering::Relaxed),
+            locked_clients,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lockout(max_failures: u32) -> AuthLockout {
+        AuthLockout::new(&AdminLockoutConfig {
+            enabled: true,
+            max_failures,
+            window_secs: 60,
+            lockout_secs: 10,
+            max_lockout_secs: 35,
+        })
+    }
+
+    #[test]
+    fn test_lockout_doubles_up_to_max() {
+        let lockout = lockout(2);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+        let mut now = Instant::now();
+
+        let fail_until_locked = |now: Instant| {
+            assert_eq!(lockout.record_failure_at(ip, "GET", "/backends", now), None);
+            assert_eq!(lockout.record_failure_at(ip, "GET", "/backends", now), None);
+            lockout.record_failure_at(ip, "GET", "/backends", now)
+        };
+        assert_eq!(fail_until_locked(now), Some(Duration::from_secs(10)));
+        now += Duration::from_secs(11);
+        assert_eq!(fail_until_locked(now), Some(Duration::from_secs(20)));
+        now += Duration::from_secs(21);
+        assert_eq!(fail_until_locked(now), Some(Duration::from_secs(35)));
+
+        assert_eq!(lockout.locked_out_at(ip, now + Duration::from_secs(5)), Some(Duration::from_secs(30)));
+        assert_eq!(lockout.locked_out_at(ip, now + Duration::from_secs(35)), None);
+        assert_eq!(lockout.locked_out_at(other, now), None);
+
+        let stats = lockout.stats();
+        assert_eq!(stats.failures, 9);
+        assert_eq!(stats.lockouts, 3);
+
+        let audit = lockout.audit_log();
+        assert_eq!(audit.len(), 12);
+        assert_eq!(audit[3].event, AuthEvent::Lockout);
+        assert_eq!(audit[3].lockout_secs, Some(10));
+    }
+
+    #[test]
+    fn test_success_and_window_reset_failures() {
+        let lockout = lockout(1);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let now = Instant::now();
+
+        assert_eq!(lockout.record_failure_at(ip, "GET", "/", now), None);
+        assert_eq!(lockout.record_failure_at(ip, "GET", "/", now + Duration::from_secs(60)), None);
+        lockout.record_success(ip);
+        assert_eq!(lockout.record_failure_at(ip, "GET", "/", now + Duration::from_secs(61)), None);
+        assert!(lockout.record_failure_at(ip, "GET", "/", now + Duration::from_secs(62)).is_some());
+    }
+
+    #[test]
+    fn test_strikes_are_forgiven() {
+        let lockout = lockout(0);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let now = Instant::now();
+
+        assert_eq!(lockout.record_failure_at(ip, "GET", "/", now), Some(Duration::from_secs(10)));
+        let later = now + Duration::from_secs(95);
+        assert_eq!(lockout.record_failure_at(ip, "GET", "/", later), Some(Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn test_audit_log_is_bounded() {
+        let lockout = lockout(u32::MAX);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        for _ in 0..AUDIT_LIMIT + 5 {
+            lockout.record_failure(ip, "GET", "/");
+        }
+        assert_eq!(lockout.audit_log().len(), AUDIT_LIMIT);
+    }
+}
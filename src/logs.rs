@@ -0,0 +1,145 @@
+//! Recent output of backends
+//!
+//! The stdout and stderr of local processes and the logs of Docker containers
+//! (followed through the Docker API) are both forwarded to tracing and kept
+//! in a per-backend buffer of recent lines, so the admin API can show why a
+//! backend failed to start or crashed without access to the host.
+
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tracing::{info, warn};
+
+/// Lines kept per backend
+const MAX_LINES: usize = 1000;
+
+/// Longer lines are truncated in the buffer
+const MAX_LINE_LEN: usize = 8 * 1024;
+
+/// Output stream a line was written to
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+    /// Container TTY output, where stdout and stderr are combined
+    Console,
+}
+
+/// A line of backend output
+#[derive(Debug, Clone, Serialize)]
+pub struct LogLine {
+    /// Milliseconds since the Unix epoch when the proxy received the line
+    pub timestamp: u64,
+    pub stream: LogStream,
+    pub line: String,
+}
+
+/// Recent output lines of every backend
+#[derive(Debug, Default)]
+pub struct BackendLogs {
+    backends: DashMap<String, Mutex<VecDeque<LogLine>>>,
+}
+
+impl BackendLogs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep a line of output
+    pub fn record(&self, hostname: &str, stream: LogStream, line: &str) {
+        let mut line = line.to_string();
+        if line.len() > MAX_LINE_LEN {
+            let end = (0..=MAX_LINE_LEN).rev().find(|&i| line.is_char_boundary(i)).unwrap_or(0);
+            line.truncate(end);
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+
+        let entry = self.backends.entry(hostname.to_string()).or_default();
+        let mut lines = entry.lock();
+        if lines.len() == MAX_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(LogLine { timestamp, stream, line });
+    }
+
+    /// The last `count` lines of a backend, oldest first
+    pub fn tail(&self, hostname: &str, count: usize) -> Vec<LogLine> {
+        self.backends.get(hostname).map_or_else(Vec::new, |lines| {
+            let lines = lines.lock();
+            lines.iter().skip(lines.len().saturating_sub(count)).cloned().collect()
+        })
+    }
+
+    /// Drop the lines of a removed backend
+    pub fn forget(&self, hostname: &str) {
+        self.backends.remove(hostname);
+    }
+}
+
+/// Forward the output of a local process until it closes the stream
+///
+/// Lines are logged with `target: "process"`, stdout at INFO and stderr at WARN.
+pub async fn capture<R: AsyncRead + Unpin>(reader: R, hostname: String, stream: LogStream, logs: &BackendLogs) {
+    let mut reader = BufReader::new(reader);
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {
+                let line = String::from_utf8_lossy(&buf);
+                let line = line.trim_end();
+                if line.is_empty() {
+                    continue;
+                }
+                match stream {
+                    LogStream::Stderr => warn!(target: "process", hostname, stream = "stderr", "{}", line),
+                    _ => info!(target: "process", hostname, stream = "stdout", "{}", line),
+                }
+                logs.record(&hostname, stream, line);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keeps_recent_lines() {
+        let logs = BackendLogs::new();
+        for i in 0..MAX_LINES + 5 {
+            logs.record("app.local", LogStream::Stdout, &format!("line {}", i));
+        }
+        logs.record("app.local", LogStream::Stderr, &"x".repeat(MAX_LINE_LEN + 10));
+
+        let tail = logs.tail("app.local", 3);
+        assert_eq!(tail.len(), 3);
+        assert_eq!(tail[0].line, format!("line {}", MAX_LINES + 3));
+        assert_eq!(tail[2].stream, LogStream::Stderr);
+        assert_eq!(tail[2].line.len(), MAX_LINE_LEN);
+        assert_eq!(logs.tail("app.local", usize::MAX).len(), MAX_LINES);
+        assert_eq!(logs.tail("app.local", usize::MAX)[0].line, "line 6");
+
+        logs.forget("app.local");
+        assert!(logs.tail("app.local", 10).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_capture_splits_lines() {
+        let logs = BackendLogs::new();
+        let output: &[u8] = b"starting\n\ninvalid \xff utf-8\r\nno newline";
+        capture(output, "app.local".to_string(), LogStream::Stderr, &logs).await;
+
+        let lines: Vec<String> = logs.tail("app.local", 10).into_iter().map(|l| l.line).collect();
+        assert_eq!(lines, ["starting", "invalid \u{fffd} utf-8", "no newline"]);
+    }
+}
@@ -1,34 +1,139 @@
-use rcgen::{CertifiedKey, generate_simple_self_signed};
-use rustls::pki_types::{CertificateDer, PrivateKeyDer};
-use spawngate::acme::AcmeManager;
-use spawngate::admin::{AdminServer, PKG_NAME, VERSION};
-use spawngate::config::{AcmeChallengeType, Config};
-use spawngate::pool::PoolConfig;
+use spawngate::acme::{self, AcmeManager, ACME_TLS_ALPN_NAME};
+use spawngate::admin::{PKG_NAME, VERSION};
+use spawngate::apply::RemoteApply;
+use spawngate::config::{self, AcmeChallengeType, AcmeConfig, Config, CONFIG_VERSION};
+use spawngate::configvars::ConfigVars;
+use spawngate::crash;
+use spawngate::discovery::{Discovery, FileDiscovery};
+use spawngate::import::{Import, ImportFormat};
+use spawngate::preflight::{self, LETS_ENCRYPT_CAA_IDENTITY};
 use spawngate::process::ProcessManager;
 use spawngate::proxy::ProxyServer;
-use std::fs::File;
-use std::io::BufReader;
+use spawngate::Spawngate;
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
 use tokio::sync::watch;
 use tokio_rustls::TlsAcceptor;
 use tracing::{error, info, warn};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+
+/// jemalloc with heap profiling compiled in, for `GET /debug/pprof/heap`
+#[cfg(all(unix, feature = "jemalloc"))]
+#[global_allocator]
+static ALLOCATOR: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+/// Heap profiling starts inactive; the admin API turns it on while it profiles
+#[cfg(all(unix, feature = "jemalloc"))]
+#[allow(non_upper_case_globals)]
+#[export_name = "_rjem_malloc_conf"]
+pub static malloc_conf: &[u8] = b"prof:true,prof_active:false,lg_prof_sample:19\0";
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    // Initialize logging
+    // Initialize logging, on stderr when stdout is the output of `config dump`
+    let writer = if std::env::args().nth(1).as_deref() == Some("config") {
+        BoxMakeWriter::new(std::io::stderr)
+    } else {
+        BoxMakeWriter::new(std::io::stdout)
+    };
     tracing_subscriber::fmt()
         .with_env_filter(
             tracing_subscriber::EnvFilter::from_default_env()
                 .add_directive("spawngate=debug".parse().expect("valid log directive")),
         )
+        .with_writer(writer)
         .init();
 
+    // Both ring and aws-lc-rs end up enabled through dependencies, so pick one explicitly
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let mut args = std::env::args().skip(1).peekable();
+
+    // `spawngate migrate-config [path]` rewrites the config file and exits
+    if args.peek().map(String::as_str) == Some("migrate-config") {
+        args.next();
+        let path = args
+            .next()
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("config.toml"));
+        return migrate_config(&path);
+    }
+
+    // `spawngate import --from <file> [--format nginx|caddy]` prints backends translated from another proxy
+    if args.peek().map(String::as_str) == Some("import") {
+        args.next();
+        let (mut from, mut format) = (None, None);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--from" => from = args.next().map(PathBuf::from),
+                "--format" => format = Some(args.next().unwrap_or_default().parse()?),
+                _ => anyhow::bail!("unexpected argument '{}'", arg),
+            }
+        }
+        let Some(from) = from else {
+            anyhow::bail!("usage: spawngate import --from <nginx.conf|Caddyfile> [--format nginx|caddy]");
+        };
+        return import_config(&from, format);
+    }
+
+    // `spawngate config dump [config] [--json]` prints the configuration with defaults resolved
+    if args.peek().map(String::as_str) == Some("config") {
+        args.next();
+        if args.next().as_deref() != Some("dump") {
+            anyhow::bail!("usage: spawngate config dump [config] [--json]");
+        }
+        let (mut path, mut json) = (None, false);
+        for arg in args {
+            match arg.as_str() {
+                "--json" => json = true,
+                _ if path.is_none() => path = Some(PathBuf::from(arg)),
+                _ => anyhow::bail!("unexpected argument '{}'", arg),
+            }
+        }
+        return dump_config(&path.unwrap_or_else(|| PathBuf::from("config.toml")), json);
+    }
+
+    // `spawngate apply -f <file> [--admin <url>] [--token <token>] [--yes]` applies backends to a running proxy
+    if args.peek().map(String::as_str) == Some("apply") {
+        args.next();
+        let (mut file, mut admin, mut token, mut yes) = (None, None, None, false);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-f" | "--file" => file = args.next().map(PathBuf::from),
+                "--admin" => admin = args.next(),
+                "--token" => token = args.next(),
+                "-y" | "--yes" => yes = true,
+                _ => anyhow::bail!("unexpected argument '{}'", arg),
+            }
+        }
+        let Some(file) = file else {
+            anyhow::bail!("usage: spawngate apply -f <file> [--admin <url>] [--token <token>] [--yes]");
+        };
+        let Some(token) = token.or_else(|| std::env::var(ADMIN_TOKEN_ENV).ok()) else {
+            anyhow::bail!("an admin token is required: pass --token or set {}", ADMIN_TOKEN_ENV);
+        };
+        let admin = admin.unwrap_or_else(|| "http://127.0.0.1:9999".to_string());
+        return apply(&file, &admin, token, yes).await;
+    }
+
+    // `spawngate acme test <domain> [config]` runs a dry run against Let's Encrypt staging
+    if args.peek().map(String::as_str) == Some("acme") {
+        args.next();
+        let (Some("test"), Some(domain)) = (args.next().as_deref(), args.next()) else {
+            anyhow::bail!("usage: spawngate acme test <domain> [config]");
+        };
+        let path = args
+            .next()
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("config.toml"));
+        return acme_test(&domain, &path).await;
+    }
+
     // Load configuration
-    let config_path = std::env::args()
-        .nth(1)
+    let config_path = args
+        .next()
         .map(PathBuf::from)
         .unwrap_or_else(|| PathBuf::from("config.toml"));
 
@@ -39,6 +144,8 @@ async fn main() -> anyhow::Result<()> {
 
     info!(path = %config_path.display(), "Configuration loaded");
 
+    crash::install(&config.server.panic);
+
     // Print startup banner
     print_startup_banner(&config);
 
@@ -52,212 +159,10 @@ async fn main() -> anyhow::Result<()> {
         None
     };
 
-    // Create shutdown channel
-    let (shutdown_tx, shutdown_rx) = watch::channel(false);
-
-    // Build admin API URL
-    let admin_url = format!("http://127.0.0.1:{}", config.server.admin_port);
-
-    // Create process manager
-    let process_manager = ProcessManager::new(
-        config.backends.clone(),
-        config.defaults.clone(),
-        admin_url,
-    );
-
-    let pool_config = PoolConfig {
-        max_idle_per_host: config.server.pool_max_idle_per_host,
-        idle_timeout: Duration::from_secs(config.server.pool_idle_timeout_secs),
-    };
-
-    info!(
-        max_idle = pool_config.max_idle_per_host,
-        idle_timeout_secs = pool_config.idle_timeout.as_secs(),
-        "Connection pool configured"
-    );
-
-    // Get shared defaults reference for ProxyServer instances
-    let shared_defaults = process_manager.shared_defaults();
-
-    // Load TLS configuration if enabled
-    // Priority: ACME > file-based certs > self-signed
-    let (tls_acceptor, acme_manager) = if config.server.acme_enabled() {
-        // ACME/Let's Encrypt automatic certificate provisioning
-        let acme_config = config.server.acme.clone();
-
-        // Create cache directory if it doesn't exist
-        std::fs::create_dir_all(&acme_config.cache_dir).map_err(|e| {
-            anyhow::anyhow!("Failed to create ACME cache directory '{}': {}", acme_config.cache_dir, e)
-        })?;
-
-        info!(
-            domains = ?acme_config.domains,
-            email = ?acme_config.email,
-            cache_dir = %acme_config.cache_dir,
-            challenge_type = ?acme_config.challenge_type,
-            "ACME/Let's Encrypt certificate provisioning enabled"
-        );
-
-        let manager = Arc::new(AcmeManager::new(acme_config.clone())?);
-
-        // For TLS-ALPN-01, we use the resolver directly
-        // For HTTP-01, we need to wait for initial certificate
-        let tls_acceptor = if acme_config.challenge_type == AcmeChallengeType::TlsAlpn01 {
-            let resolver = manager.tls_alpn01_resolver();
-            let rustls_config = rustls::ServerConfig::builder()
-                .with_no_client_auth()
-                .with_cert_resolver(resolver);
-            Some(TlsAcceptor::from(Arc::new(rustls_config)))
-        } else {
-            // For HTTP-01, we'll set up TLS after getting the certificate
-            None
-        };
-
-        (tls_acceptor, Some(manager))
-    } else if config.server.tls_enabled() {
-        let (certs, key) = if config.server.has_tls_files() {
-            let cert_path = config.server.tls_cert.as_ref().unwrap();
-            let key_path = config.server.tls_key.as_ref().unwrap();
-            let certs = load_certs(cert_path)?;
-            let key = load_key(key_path)?;
-            info!(cert = %cert_path, key = %key_path, "TLS enabled with provided certificates");
-            (certs, key)
-        } else {
-            let (certs, key) = generate_self_signed_cert()?;
-            warn!("TLS enabled with auto-generated self-signed certificate (not for production)");
-            (certs, key)
-        };
-
-        let tls_config = rustls::ServerConfig::builder()
-            .with_no_client_auth()
-            .with_single_cert(certs, key)
-            .map_err(|e| anyhow::anyhow!("TLS configuration error: {}", e))?;
-
-        (Some(TlsAcceptor::from(Arc::new(tls_config))), None::<Arc<AcmeManager>>)
-    } else {
-        (None, None::<Arc<AcmeManager>>)
-    };
-
-    // Get ACME HTTP-01 challenges if using HTTP-01 challenge type
-    let acme_http01_challenges = acme_manager.as_ref().and_then(|m| {
-        if config.server.acme.challenge_type == AcmeChallengeType::Http01 {
-            Some(m.http01_challenges())
-        } else {
-            None
-        }
-    });
-
-    // Create HTTP proxy server (if port > 0)
-    let http_port = config.server.http_port();
-    let https_port = config.server.https_port();
-    let http_proxy_handle = if http_port > 0 {
-        let http_addr: SocketAddr = format!("{}:{}", config.server.bind, http_port)
-            .parse()
-            .map_err(|e| {
-                error!(bind = %config.server.bind, port = http_port, error = %e, "Invalid HTTP bind address");
-                anyhow::anyhow!("Invalid HTTP bind address: {}", e)
-            })?;
-
-        let mut http_proxy = ProxyServer::with_pool_config(
-            http_addr,
-            Arc::clone(&process_manager),
-            Arc::clone(&shared_defaults),
-            shutdown_rx.clone(),
-            pool_config.clone(),
-        );
-
-        // Add ACME HTTP-01 challenge handler if configured
-        if let Some(challenges) = acme_http01_challenges.clone() {
-            http_proxy = http_proxy.with_acme_challenges(challenges);
-            info!("ACME HTTP-01 challenge handler enabled on HTTP port");
-        }
-
-        // If force_https is enabled and HTTPS is available, redirect HTTP to HTTPS
-        // Note: ACME challenges are handled before redirect
-        if config.server.force_https && https_port > 0 {
-            http_proxy = http_proxy.with_https_redirect(https_port);
-            info!(http_port, https_port, "HTTP to HTTPS redirect enabled");
-        }
-
-        Some(tokio::spawn(async move {
-            if let Err(e) = http_proxy.run().await {
-                error!(error = %e, "HTTP proxy server error");
-            }
-        }))
-    } else {
-        None
-    };
-
-    // Create HTTPS proxy server (if TLS enabled and port > 0)
-    let https_proxy_handle = if https_port > 0 && tls_acceptor.is_some() {
-        let https_addr: SocketAddr = format!("{}:{}", config.server.bind, https_port)
-            .parse()
-            .map_err(|e| {
-                error!(bind = %config.server.bind, port = https_port, error = %e, "Invalid HTTPS bind address");
-                anyhow::anyhow!("Invalid HTTPS bind address: {}", e)
-            })?;
-
-        let https_proxy = ProxyServer::with_pool_config(
-            https_addr,
-            Arc::clone(&process_manager),
-            Arc::clone(&shared_defaults),
-            shutdown_rx.clone(),
-            pool_config,
-        )
-        .with_tls(tls_acceptor.clone().expect("TLS acceptor required for HTTPS"));
-
-        Some(tokio::spawn(async move {
-            if let Err(e) = https_proxy.run().await {
-                error!(error = %e, "HTTPS proxy server error");
-            }
-        }))
-    } else {
-        None
-    };
-
-    // Spawn ACME manager task if configured
-    let acme_task = if let Some(ref manager) = acme_manager {
-        let mgr = Arc::clone(manager);
-        let shutdown = shutdown_rx.clone();
-        Some(tokio::spawn(async move {
-            if let Err(e) = mgr.run(shutdown).await {
-                error!(error = %e, "ACME manager error");
-            }
-        }))
-    } else {
-        None
-    };
-
-    // Create admin server (always HTTP for internal use)
-    let admin_addr: SocketAddr = format!("127.0.0.1:{}", config.server.admin_port)
-        .parse()
-        .map_err(|e| {
-            error!(admin_port = config.server.admin_port, error = %e, "Invalid admin bind address");
-            anyhow::anyhow!("Invalid admin bind address: {}", e)
-        })?;
-
-    // Generate or use configured admin token
-    let admin_token = config.server.admin_token.clone().unwrap_or_else(|| {
-        let token = uuid::Uuid::new_v4().to_string();
-        info!(token = %token, "Generated admin API token (configure admin_token to set a fixed value)");
-        token
-    });
-
-    let admin_server = AdminServer::new(admin_addr, Arc::clone(&process_manager), shutdown_rx.clone(), admin_token);
-
-    // Spawn idle cleanup task
-    let cleanup_manager = Arc::clone(&process_manager);
-    let cleanup_shutdown_rx = shutdown_rx.clone();
-    tokio::spawn(async move {
-        idle_cleanup_loop(cleanup_manager, cleanup_shutdown_rx).await;
-    });
-
-    // Spawn admin server
-    let admin_handle = tokio::spawn(async move {
-        if let Err(e) = admin_server.run().await {
-            error!(error = %e, "Admin server error");
-        }
-    });
+    let mut gate = Spawngate::builder().config(config).start().await.map_err(|e| {
+        error!(error = %e, "Failed to start");
+        e
+    })?;
 
     // Wait for shutdown signal (Ctrl+C or SIGTERM) or config reload (SIGHUP)
     #[cfg(unix)]
@@ -280,7 +185,14 @@ async fn main() -> anyhow::Result<()> {
                 }
                 _ = sighup.recv() => {
                     info!(path = %config_path.display(), "Received SIGHUP, reloading configuration...");
-                    match process_manager.reload_config(&config_path).await {
+                    let new_config = match Config::load(&config_path) {
+                        Ok(new_config) => new_config,
+                        Err(e) => {
+                            error!(error = %e, "Failed to reload configuration");
+                            continue;
+                        }
+                    };
+                    match gate.reload(new_config).await {
                         Ok(result) => {
                             info!(
                                 added = result.added.len(),
@@ -310,29 +222,7 @@ async fn main() -> anyhow::Result<()> {
         info!("Received Ctrl+C, shutting down...");
     }
 
-    // Signal shutdown
-    let _ = shutdown_tx.send(true);
-
-    // Stop all backends
-    info!("Stopping all backends...");
-    process_manager.stop_all().await;
-
-    // Stop ACME task if running
-    if let Some(handle) = acme_task {
-        handle.abort();
-    }
-
-    // Wait for servers to stop (with timeout)
-    let _ = tokio::time::timeout(Duration::from_secs(5), async {
-        if let Some(handle) = http_proxy_handle {
-            let _ = handle.await;
-        }
-        if let Some(handle) = https_proxy_handle {
-            let _ = handle.await;
-        }
-        let _ = admin_handle.await;
-    })
-    .await;
+    gate.stop().await;
 
     // Clean up PID file
     if let Some(ref path) = pid_file_path {
@@ -345,23 +235,6 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
-async fn idle_cleanup_loop(process_manager: Arc<ProcessManager>, mut shutdown_rx: watch::Receiver<bool>) {
-    let interval = Duration::from_secs(10); // Check every 10 seconds
-
-    loop {
-        tokio::select! {
-            _ = tokio::time::sleep(interval) => {
-                process_manager.cleanup_idle_backends().await;
-            }
-            _ = shutdown_rx.changed() => {
-                if *shutdown_rx.borrow() {
-                    break;
-                }
-            }
-        }
-    }
-}
-
 /// PID file handle that maintains an exclusive lock
 #[cfg(unix)]
 struct PidFile {
@@ -419,6 +292,265 @@ fn write_pid_file(path: &Path) -> anyhow::Result<PidFile> {
     PidFile::create(path)
 }
 
+/// Rewrite a config file to the current schema version
+fn migrate_config(path: &Path) -> anyhow::Result<()> {
+    match config::migrate_file(path)? {
+        Some(from) => println!(
+            "Migrated {} from version {} to {} (original saved as {}.bak)",
+            path.display(),
+            from,
+            CONFIG_VERSION,
+            path.display()
+        ),
+        None => println!(
+            "{} is already at version {}",
+            path.display(),
+            CONFIG_VERSION
+        ),
+    }
+    Ok(())
+}
+
+/// Print the backends translated from an nginx config or Caddyfile
+fn import_config(path: &Path, format: Option<ImportFormat>) -> anyhow::Result<()> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?;
+    let format = format.unwrap_or_else(|| ImportFormat::detect(path));
+    let import = Import::parse(format, &content).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+    print!("{}", import.to_toml());
+    Ok(())
+}
+
+/// Print the configuration the proxy would run with, secrets redacted
+///
+/// Backends from `file_sd` discovery and saved config vars are included;
+/// backends from KV stores or the admin API only show up in
+/// `GET /config/effective` of a running proxy.
+fn dump_config(path: &Path, json: bool) -> anyhow::Result<()> {
+    let mut config = Config::load(path)?;
+    if let Some(dir) = &config.server.file_sd.dir {
+        let discovery = Arc::new(Discovery::new(&config));
+        FileDiscovery::new(Arc::clone(&discovery), dir).scan();
+        config = discovery.merge(&config)?;
+    }
+    let vars = ConfigVars::new();
+    if let Some(file) = &config.server.config_vars_file {
+        vars.persist_to(file)?;
+    }
+    let mut effective = config.effective(&vars);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&effective)?);
+    } else {
+        // TOML has no null, unset settings are left out instead
+        strip_nulls(&mut effective);
+        print!("{}", toml::to_string(&effective)?);
+    }
+    Ok(())
+}
+
+fn strip_nulls(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            map.retain(|_, value| !value.is_null());
+            map.values_mut().for_each(strip_nulls);
+        }
+        serde_json::Value::Array(items) => {
+            items.retain(|value| !value.is_null());
+            items.iter_mut().for_each(strip_nulls);
+        }
+        _ => {}
+    }
+}
+
+/// Environment variable with the admin token for `spawngate apply`
+const ADMIN_TOKEN_ENV: &str = "SPAWNGATE_ADMIN_TOKEN";
+
+/// Show the plan for a desired configuration, then apply it once confirmed
+async fn apply(file: &Path, admin: &str, token: String, yes: bool) -> anyhow::Result<()> {
+    let desired = std::fs::read_to_string(file)
+        .map_err(|e| anyhow::anyhow!("failed to read {}: {}", file.display(), e))?;
+    let remote = RemoteApply::new(admin, token)?;
+    let (plan, etag) = remote.plan(&desired).await?;
+    if plan.is_empty() {
+        println!("No changes; the running backends match {}", file.display());
+        return Ok(());
+    }
+    println!("Plan for {}:\n{}", admin, plan);
+
+    if !yes {
+        print!("Apply these changes? [y/N] ");
+        std::io::Write::flush(&mut std::io::stdout())?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            println!("Nothing applied");
+            return Ok(());
+        }
+    }
+    let plan = remote.apply(&desired, &etag).await?;
+    println!(
+        "Applied: {} added, {} updated, {} removed, {} restarting",
+        plan.add.len(),
+        plan.update.len(),
+        plan.remove.len(),
+        plan.restarts().count()
+    );
+    Ok(())
+}
+
+/// Print the outcome of one dry-run step, failing the run on error
+fn report_step<T>(step: &str, result: Result<(T, String), String>) -> anyhow::Result<T> {
+    match result {
+        Ok((value, detail)) => {
+            println!("  [ok]   {}: {}", step, detail);
+            Ok(value)
+        }
+        Err(e) => {
+            println!("  [FAIL] {}: {}", step, e);
+            anyhow::bail!("ACME dry run failed at the {} step", step)
+        }
+    }
+}
+
+/// Dry-run step a failed staging order points at, based on the ACME problem type
+fn challenge_failure_step(error: &str) -> &'static str {
+    if error.contains("urn:ietf:params:acme:error:caa") {
+        "CAA"
+    } else if error.contains("urn:ietf:params:acme:error:dns") {
+        "DNS"
+    } else if error.contains("urn:ietf:params:acme:error:connection") {
+        "port reachability"
+    } else {
+        "challenge"
+    }
+}
+
+/// Check each prerequisite for issuing a certificate for `domain`, then run
+/// a full order against Let's Encrypt staging
+async fn acme_test(domain: &str, config_path: &Path) -> anyhow::Result<()> {
+    // Listener and challenge settings come from the config file when present
+    let config = if config_path.exists() {
+        Config::load(config_path)?
+    } else {
+        Config::parse(&format!("version = {}", CONFIG_VERSION))?
+    };
+    let acme_config = AcmeConfig {
+        domains: vec![domain.to_string()],
+        directory_url: None,
+        staging: true,
+        ..config.server.acme.clone()
+    };
+    let challenge_type = acme_config.challenge_type.clone();
+    println!("ACME dry run for {} against {}", domain, acme::directory_url(&acme_config));
+
+    let expected_ips = config.server.acme.expected_ips.clone();
+    // DNS-01 validation doesn't connect to the domain, which may be a wildcard without addresses
+    let dns = match challenge_type {
+        AcmeChallengeType::Dns01 => Ok((Vec::new(), "not needed for DNS-01".to_string())),
+        _ => preflight::resolve(domain).await.and_then(|addrs| {
+            preflight::check_addresses(domain, &addrs, &expected_ips)?;
+            let list: Vec<String> = addrs.iter().map(ToString::to_string).collect();
+            let detail = format!("resolves to {}", list.join(", "));
+            Ok((addrs, detail))
+        }),
+    };
+    let addrs = report_step("DNS", dns)?;
+
+    let caa = match preflight::lookup_caa(domain).await {
+        Ok((at, records)) => preflight::caa_verdict(domain, &at, &records, LETS_ENCRYPT_CAA_IDENTITY).map(|d| ((), d)),
+        Err(e) => Err(format!("lookup failed: {}", e)),
+    };
+    report_step("CAA", caa)?;
+
+    // Serve challenges on the port spawngate would use, then reach it through the domain.
+    // The CA always connects on 80 (HTTP-01) or 443 (TLS-ALPN-01).
+    let manager = Arc::new(AcmeManager::new(acme_config)?);
+    let (listen_port, public_port) = match challenge_type {
+        AcmeChallengeType::Http01 => (config.server.http_port(), 80),
+        AcmeChallengeType::TlsAlpn01 => (config.server.tls_port.unwrap_or(443), 443),
+        // The CA only queries DNS, so nothing needs to be reachable
+        AcmeChallengeType::Dns01 => (0, 0),
+    };
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let reachability = async {
+        if challenge_type == AcmeChallengeType::Dns01 {
+            return Ok(((), "not needed for DNS-01".to_string()));
+        }
+        if listen_port == 0 {
+            return Err("the HTTP listener is disabled (port = 0) but HTTP-01 needs it".to_string());
+        }
+        let bind_addr: SocketAddr = format!("{}:{}", config.server.bind, listen_port)
+            .parse()
+            .map_err(|e| format!("invalid bind address: {}", e))?;
+        let listener = tokio::net::TcpListener::bind(bind_addr)
+            .await
+            .map_err(|e| format!("cannot listen on {}: {} (is spawngate already running?)", bind_addr, e))?;
+
+        let process_manager = ProcessManager::new(
+            HashMap::new(),
+            config.defaults.clone(),
+            format!("http://127.0.0.1:{}", config.server.admin_port),
+        );
+        let mut proxy = ProxyServer::new(
+            bind_addr,
+            Arc::clone(&process_manager),
+            process_manager.shared_defaults(),
+            shutdown_rx,
+        );
+        let probe_token = format!("spawngate-dry-run-{}", uuid::Uuid::new_v4().simple());
+        match challenge_type {
+            AcmeChallengeType::Http01 => {
+                manager.http01_challenges().set(probe_token.clone(), probe_token.clone()).await;
+                proxy = proxy.with_acme_challenges(manager.http01_challenges());
+            }
+            AcmeChallengeType::TlsAlpn01 => {
+                let mut tls_config = rustls::ServerConfig::builder()
+                    .with_no_client_auth()
+                    .with_cert_resolver(manager.tls_alpn01_resolver());
+                tls_config.alpn_protocols = vec![ACME_TLS_ALPN_NAME.to_vec()];
+                proxy = proxy.with_tls(TlsAcceptor::from(Arc::new(tls_config)));
+            }
+            AcmeChallengeType::Dns01 => {}
+        }
+        tokio::spawn(proxy.serve(listener));
+
+        let probed = async {
+            for ip in &addrs {
+                let addr = SocketAddr::new(*ip, public_port);
+                match challenge_type {
+                    AcmeChallengeType::Http01 => {
+                        preflight::probe_http01(addr, domain, &probe_token, &probe_token).await?
+                    }
+                    AcmeChallengeType::TlsAlpn01 => preflight::check_port(addr).await?,
+                    AcmeChallengeType::Dns01 => {}
+                }
+            }
+            Ok::<_, String>(())
+        }
+        .await;
+        // Removed whether or not the probes passed, so the token isn't left being served
+        manager.http01_challenges().remove(&probe_token).await;
+        probed?;
+        Ok(((), format!("port {} reaches this server on every address", public_port)))
+    };
+    let result = async {
+        report_step("port reachability", reachability.await)?;
+        match manager.test_issuance().await {
+            Ok(()) => report_step("challenge", Ok(((), "staging certificate issued".to_string()))),
+            Err(e) => {
+                let e = e.to_string();
+                report_step(challenge_failure_step(&e), Err(e))
+            }
+        }
+    }
+    .await;
+    let _ = shutdown_tx.send(true);
+    result?;
+
+    println!("All checks passed; {} is ready for a production certificate", domain);
+    Ok(())
+}
+
 fn print_startup_banner(config: &Config) {
     info!(
         name = PKG_NAME,
@@ -433,7 +565,7 @@ fn print_startup_banner(config: &Config) {
         https_port = if https_port > 0 { Some(https_port) } else { None },
         admin_port = config.server.admin_port,
         tls = config.server.tls_enabled(),
-        acme = config.server.acme_enabled(),
+        acme = config.acme_enabled(),
         "Server configuration"
     );
     info!(
@@ -465,51 +597,3 @@ fn print_startup_banner(config: &Config) {
         "Configured backends"
     );
 }
-
-fn load_certs(path: &str) -> anyhow::Result<Vec<CertificateDer<'static>>> {
-    let file = File::open(path)
-        .map_err(|e| anyhow::anyhow!("Failed to open certificate file {}: {}", path, e))?;
-    let mut reader = BufReader::new(file);
-    let certs = rustls_pemfile::certs(&mut reader)
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|e| anyhow::anyhow!("Failed to parse certificates from {}: {}", path, e))?;
-
-    if certs.is_empty() {
-        anyhow::bail!("No certificates found in {}", path);
-    }
-
-    Ok(certs)
-}
-
-fn load_key(path: &str) -> anyhow::Result<PrivateKeyDer<'static>> {
-    let file = File::open(path)
-        .map_err(|e| anyhow::anyhow!("Failed to open key file {}: {}", path, e))?;
-    let mut reader = BufReader::new(file);
-
-    loop {
-        match rustls_pemfile::read_one(&mut reader)
-            .map_err(|e| anyhow::anyhow!("Failed to parse key from {}: {}", path, e))?
-        {
-            Some(rustls_pemfile::Item::Pkcs1Key(key)) => return Ok(key.into()),
-            Some(rustls_pemfile::Item::Pkcs8Key(key)) => return Ok(key.into()),
-            Some(rustls_pemfile::Item::Sec1Key(key)) => return Ok(key.into()),
-            None => break,
-            _ => continue,
-        }
-    }
-
-    anyhow::bail!("No private key found in {}", path)
-}
-
-fn generate_self_signed_cert() -> anyhow::Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
-    let subject_alt_names = vec!["localhost".to_string(), "127.0.0.1".to_string()];
-
-    let CertifiedKey { cert, key_pair } = generate_simple_self_signed(subject_alt_names)
-        .map_err(|e| anyhow::anyhow!("Failed to generate self-signed certificate: {}", e))?;
-
-    let cert_der = CertificateDer::from(cert.der().to_vec());
-    let key_der = PrivateKeyDer::try_from(key_pair.serialize_der())
-        .map_err(|e| anyhow::anyhow!("Failed to serialize private key: {}", e))?;
-
-    Ok((vec![cert_der], key_der))
-}
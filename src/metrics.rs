@@ -0,0 +1,418 @@
+//! Aggregated per-backend request metrics and periodic CSV export
+//!
+//! Counters accumulate between exports; each export appends one row per
+//! backend that saw traffic and resets the counters, so every row covers
+//! exactly one interval.
+//!
+//! TLS handshake counters are cumulative and exposed through the admin API.
+
+use dashmap::DashMap;
+use hyper::StatusCode;
+use rustls::ServerConnection;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+use tracing::{debug, error, info};
+
+/// CSV header written to new export files
+pub const CSV_HEADER: &str =
+    "timestamp,backend,requests,status_2xx,status_3xx,status_4xx,status_5xx,avg_latency_ms,max_latency_ms";
+
+/// Request counters for one backend over one interval
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BackendMetrics {
+    pub requests: u64,
+    pub status_2xx: u64,
+    pub status_3xx: u64,
+    pub status_4xx: u64,
+    pub status_5xx: u64,
+    pub total_latency: Duration,
+    pub max_latency: Duration,
+}
+
+impl BackendMetrics {
+    fn record(&mut self, status: StatusCode, latency: Duration) {
+        self.requests += 1;
+        match status.as_u16() {
+            200..=299 => self.status_2xx += 1,
+            300..=399 => self.status_3xx += 1,
+            400..=499 => self.status_4xx += 1,
+            500..=599 => self.status_5xx += 1,
+            _ => {}
+        }
+        self.total_latency += latency;
+        self.max_latency = self.max_latency.max(latency);
+    }
+
+    fn avg_latency_ms(&self) -> f64 {
+        if self.requests == 0 {
+            return 0.0;
+        }
+        self.total_latency.as_secs_f64() * 1000.0 / self.requests as f64
+    }
+
+    /// Format as a CSV row (without trailing newline)
+    pub fn csv_row(&self, timestamp: u64, backend: &str) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{:.3},{:.3}",
+            timestamp,
+            csv_field(backend),
+            self.requests,
+            self.status_2xx,
+            self.status_3xx,
+            self.status_4xx,
+            self.status_5xx,
+            self.avg_latency_ms(),
+            self.max_latency.as_secs_f64() * 1000.0,
+        )
+    }
+}
+
+/// Quote a CSV field if it contains a separator, quote or newline
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Shared per-backend request counters
+#[derive(Debug, Default)]
+pub struct RequestMetrics {
+    backends: DashMap<String, BackendMetrics>,
+}
+
+impl RequestMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a completed request for a backend
+    pub fn record(&self, hostname: &str, status: StatusCode, latency: Duration) {
+        if let Some(mut metrics) = self.backends.get_mut(hostname) {
+            metrics.record(status, latency);
+            return;
+        }
+        self.backends
+            .entry(hostname.to_string())
+            .or_default()
+            .record(status, latency);
+    }
+
+    /// Take the counters accumulated since the last call, sorted by backend
+    pub fn take(&self) -> Vec<(String, BackendMetrics)> {
+        let hostnames: Vec<String> = self.backends.iter().map(|e| e.key().clone()).collect();
+        let mut taken: Vec<(String, BackendMetrics)> = hostnames
+            .into_iter()
+            .filter_map(|hostname| self.backends.remove(&hostname))
+            .collect();
+        taken.sort_by(|a, b| a.0.cmp(&b.0));
+        taken
+    }
+}
+
+/// Append rows to a CSV file, writing the header if the file is new or empty
+pub fn append_csv(path: &Path, timestamp: u64, rows: &[(String, BackendMetrics)]) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut out = String::new();
+    if file.metadata()?.len() == 0 {
+        out.push_str(CSV_HEADER);
+        out.push('\n');
+    }
+    for (backend, metrics) in rows {
+        out.push_str(&metrics.csv_row(timestamp, backend));
+        out.push('\n');
+    }
+    file.write_all(out.as_bytes())
+}
+
+/// Periodically append aggregated metrics to a CSV file until shutdown
+///
+/// Only backends for which `include` returns true are written. A final
+/// export runs on shutdown so the last partial interval isn't lost.
+pub async fn run_csv_export(
+    metrics: Arc<RequestMetrics>,
+    path: PathBuf,
+    interval: Duration,
+    include: impl Fn(&str) -> bool + Send + 'static,
+    mut shutdown_rx: watch::Receiver<bool>,
+) {
+    info!(path = %path.display(), interval_secs = interval.as_secs(), "Metrics CSV export enabled");
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await; // First tick completes immediately
+
+    loop {
+        let shutting_down = tokio::select! {
+            _ = ticker.tick() => false,
+            _ = shutdown_rx.changed() => *shutdown_rx.borrow(),
+        };
+
+        let mut rows = metrics.take();
+        rows.retain(|(backend, _)| include(backend));
+        if !rows.is_empty() {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            let path = path.clone();
+            let count = rows.len();
+            match tokio::task::spawn_blocking(move || append_csv(&path, timestamp, &rows)).await {
+                Ok(Ok(())) => debug!(backends = count, "Exported request metrics"),
+                Ok(Err(e)) => error!(error = %e, "Failed to write metrics export"),
+                Err(e) => error!(error = %e, "Metrics export task failed"),
+            }
+        }
+
+        if shutting_down {
+            break;
+        }
+    }
+}
+
+/// Distinct handshake profiles tracked before new SNI values are folded together
+const MAX_HANDSHAKE_PROFILES: usize = 1024;
+
+/// SNI (or JA4 fingerprint) reported once [`MAX_HANDSHAKE_PROFILES`] is reached
+pub const OTHER_SNI: &str = "(other)";
+
+/// What was negotiated in a completed TLS handshake
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub struct HandshakeProfile {
+    /// Protocol version, e.g. `TLSv1.3`
+    pub version: String,
+    /// Cipher suite, e.g. `TLS13_AES_128_GCM_SHA256`
+    pub cipher: String,
+    pub sni: Option<String>,
+    pub alpn: Option<String>,
+    pub resumed: bool,
+}
+
+impl HandshakeProfile {
+    pub fn of(conn: &ServerConnection) -> Self {
+        Self {
+            version: conn
+                .protocol_version()
+                .map(|v| v.as_str().map(|s| s.replace('_', ".")).unwrap_or_else(|| format!("{:?}", v)))
+                .unwrap_or_else(|| "unknown".to_string()),
+            cipher: conn
+                .negotiated_cipher_suite()
+                .map(|s| s.suite().as_str().map(str::to_string).unwrap_or_else(|| format!("{:?}", s.suite())))
+                .unwrap_or_else(|| "unknown".to_string()),
+            sni: conn.server_name().map(str::to_string),
+            alpn: conn.alpn_protocol().map(|p| String::from_utf8_lossy(p).into_owned()),
+            resumed: conn.handshake_kind() == Some(rustls::HandshakeKind::Resumed),
+        }
+    }
+}
+
+/// A handshake profile and how often it was seen
+#[derive(Debug, Clone, Serialize)]
+pub struct HandshakeCount {
+    #[serde(flatten)]
+    pub profile: HandshakeProfile,
+    pub count: u64,
+}
+
+/// Snapshot of TLS handshake counters
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TlsHandshakeStats {
+    pub total: u64,
+    pub resumed: u64,
+    /// Handshakes that failed before completing
+    pub failed: u64,
+    pub versions: BTreeMap<String, u64>,
+    pub ciphers: BTreeMap<String, u64>,
+    /// Counts per distinct handshake profile, most frequent first
+    pub handshakes: Vec<HandshakeCount>,
+    /// Connections per client JA4 fingerprint, when fingerprinting is enabled
+    pub fingerprints: BTreeMap<String, u64>,
+    /// Connections closed because their fingerprint is on the blocklist
+    pub blocked: u64,
+}
+
+/// Cumulative counters of completed and failed TLS handshakes
+#[derive(Debug, Default)]
+pub struct TlsHandshakeMetrics {
+    profiles: DashMap<HandshakeProfile, u64>,
+    failed: AtomicU64,
+    fingerprints: DashMap<String, u64>,
+    blocked: AtomicU64,
+}
+
+impl TlsHandshakeMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a completed handshake
+    pub fn record_connection(&self, conn: &ServerConnection) {
+        self.record(HandshakeProfile::of(conn));
+    }
+
+    /// Record a completed handshake with the given profile
+    ///
+    /// Once the number of distinct profiles reaches a limit, new SNI values are
+    /// counted as [`OTHER_SNI`] so clients can't grow the table without bound.
+    pub fn record(&self, mut profile: HandshakeProfile) {
+        if let Some(mut count) = self.profiles.get_mut(&profile) {
+            *count += 1;
+            return;
+        }
+        if self.profiles.len() >= MAX_HANDSHAKE_PROFILES && profile.sni.is_some() {
+            profile.sni = Some(OTHER_SNI.to_string());
+        }
+        *self.profiles.entry(profile).or_default() += 1;
+    }
+
+    /// Record a handshake that failed
+    pub fn record_failure(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a client's JA4 fingerprint, and whether its connection was closed for it
+    ///
+    /// Past [`MAX_HANDSHAKE_PROFILES`] distinct fingerprints, new ones are counted as [`OTHER_SNI`].
+    pub fn record_fingerprint(&self, ja4: &str, blocked: bool) {
+        if blocked {
+            self.blocked.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(mut count) = self.fingerprints.get_mut(ja4) {
+            *count += 1;
+            return;
+        }
+        let key = if self.fingerprints.len() >= MAX_HANDSHAKE_PROFILES { OTHER_SNI } else { ja4 };
+        *self.fingerprints.entry(key.to_string()).or_default() += 1;
+    }
+
+    pub fn stats(&self) -> TlsHandshakeStats {
+        let mut stats = TlsHandshakeStats {
+            failed: self.failed.load(Ordering::Relaxed),
+            blocked: self.blocked.load(Ordering::Relaxed),
+            fingerprints: self.fingerprints.iter().map(|e| (e.key().clone(), *e.value())).collect(),
+            ..Default::default()
+        };
+        for entry in self.profiles.iter() {
+            let (profile, count) = (entry.key(), *entry.value());
+            stats.total += count;
+            if profile.resumed {
+                stats.resumed += count;
+            }
+            *stats.versions.entry(profile.version.clone()).or_default() += count;
+            *stats.ciphers.entry(profile.cipher.clone()).or_default() += count;
+            stats.handshakes.push(HandshakeCount { profile: profile.clone(), count });
+        }
+        stats
+            .handshakes
+            .sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.profile.cmp(&b.profile)));
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_take() {
+        let metrics = RequestMetrics::new();
+        metrics.record("b.local", StatusCode::OK, Duration::from_millis(10));
+        metrics.record("b.local", StatusCode::SERVICE_UNAVAILABLE, Duration::from_millis(30));
+        metrics.record("a.local", StatusCode::NOT_FOUND, Duration::from_millis(5));
+
+        let taken = metrics.take();
+        assert_eq!(taken.len(), 2);
+        assert_eq!(taken[0].0, "a.local");
+        assert_eq!(taken[0].1.status_4xx, 1);
+
+        let b = &taken[1].1;
+        assert_eq!(b.requests, 2);
+        assert_eq!(b.status_2xx, 1);
+        assert_eq!(b.status_5xx, 1);
+        assert_eq!(b.max_latency, Duration::from_millis(30));
+        assert_eq!(b.csv_row(100, "b.local"), "100,b.local,2,1,0,0,1,20.000,30.000");
+
+        // Counters reset after each take
+        assert!(metrics.take().is_empty());
+    }
+
+    #[test]
+    fn test_append_csv_writes_header_once() {
+        let path = std::env::temp_dir().join(format!("spawngate-metrics-{}.csv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let rows = vec![("a.local".to_string(), BackendMetrics::default())];
+        append_csv(&path, 1, &rows).unwrap();
+        append_csv(&path, 2, &rows).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines, vec![CSV_HEADER, "1,a.local,0,0,0,0,0,0.000,0.000", "2,a.local,0,0,0,0,0,0.000,0.000"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_csv_field_quoting() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    fn profile(version: &str, sni: &str, resumed: bool) -> HandshakeProfile {
+        HandshakeProfile {
+            version: version.to_string(),
+            cipher: "TLS13_AES_128_GCM_SHA256".to_string(),
+            sni: Some(sni.to_string()),
+            alpn: Some("h2".to_string()),
+            resumed,
+        }
+    }
+
+    #[test]
+    fn test_tls_handshake_stats() {
+        let metrics = TlsHandshakeMetrics::new();
+        metrics.record(profile("TLSv1.3", "a.local", false));
+        metrics.record(profile("TLSv1.3", "a.local", true));
+        metrics.record(profile("TLSv1.3", "a.local", true));
+        metrics.record(profile("TLSv1.2", "b.local", false));
+        metrics.record_failure();
+        metrics.record_fingerprint("t13d1516h2_8daaf6152771_02713d6af862", false);
+        metrics.record_fingerprint("t13d1516h2_8daaf6152771_02713d6af862", true);
+
+        let stats = metrics.stats();
+        assert_eq!(stats.fingerprints["t13d1516h2_8daaf6152771_02713d6af862"], 2);
+        assert_eq!(stats.blocked, 1);
+        assert_eq!(stats.total, 4);
+        assert_eq!(stats.resumed, 2);
+        assert_eq!(stats.failed, 1);
+        assert_eq!(stats.versions["TLSv1.3"], 3);
+        assert_eq!(stats.versions["TLSv1.2"], 1);
+        assert_eq!(stats.ciphers["TLS13_AES_128_GCM_SHA256"], 4);
+        assert_eq!(stats.handshakes.len(), 3);
+        assert_eq!(stats.handshakes[0].count, 2);
+        assert!(stats.handshakes[0].profile.resumed);
+
+        let json = serde_json::to_value(&stats.handshakes[0]).unwrap();
+        assert_eq!(json["sni"], "a.local");
+        assert_eq!(json["count"], 2);
+    }
+
+    #[test]
+    fn test_tls_handshake_profiles_are_bounded() {
+        let metrics = TlsHandshakeMetrics::new();
+        for i in 0..MAX_HANDSHAKE_PROFILES + 10 {
+            metrics.record(profile("TLSv1.3", &format!("host-{}.local", i), false));
+        }
+        let stats = metrics.stats();
+        assert_eq!(stats.total, (MAX_HANDSHAKE_PROFILES + 10) as u64);
+        assert_eq!(stats.handshakes.len(), MAX_HANDSHAKE_PROFILES + 1);
+        assert_eq!(stats.handshakes[0].profile.sni.as_deref(), Some(OTHER_SNI));
+        assert_eq!(stats.handshakes[0].count, 10);
+    }
+}
@@ -0,0 +1,220 @@
+//! Request path normalization
+//!
+//! Paths are normalized once, before routing, so that path-based rules
+//! (`allowed_paths`, `https_redirect_exclude`, ACME challenges) and the
+//! backend all see the same path, and encodings like `/static/%2e%2e/admin`
+//! or `/static//../admin` can't slip past a prefix check:
+//!
+//! 1. percent-encoded unreserved characters are decoded (`%41` → `A`,
+//!    `%2e` → `.`); other escapes are kept, in upper case
+//! 2. encoded separators (`%2F`, `%5C`) are kept, decoded or rejected, as
+//!    set by `encoded_slashes`
+//! 3. repeated slashes are merged (`merge_slashes`)
+//! 4. dot segments are removed (RFC 3986, section 5.2.4), never climbing
+//!    above the root
+//!
+//! Paths with malformed escapes or an encoded NUL byte are always rejected.
+//! With `strict`, a path that normalization would change is rejected too.
+//! The query string is left untouched.
+
+use crate::config::{EncodedSlashes, PathNormalizationConfig};
+use hyper::http::uri::PathAndQuery;
+use hyper::Uri;
+use std::borrow::Cow;
+use std::fmt;
+
+/// Why a request path was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathError {
+    /// Malformed percent-encoding or an encoded NUL byte
+    Malformed,
+    /// `%2F` or `%5C` with `encoded_slashes = "reject"`
+    EncodedSlash,
+    /// Not in normal form with `strict` enabled
+    NotNormalized,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PathError::Malformed => "Malformed request path",
+            PathError::EncodedSlash => "Encoded slashes are not allowed in the request path",
+            PathError::NotNormalized => "Request path is not normalized",
+        })
+    }
+}
+
+/// Normalize a request path (without query)
+pub fn normalize_path<'a>(path: &'a str, config: &PathNormalizationConfig) -> Result<Cow<'a, str>, PathError> {
+    // Asterisk-form (`OPTIONS *`) and other non-origin paths are left alone
+    if !path.starts_with('/') {
+        return Ok(Cow::Borrowed(path));
+    }
+
+    let decoded = decode_unreserved(path, config.encoded_slashes)?;
+    let merged = if config.merge_slashes {
+        merge_slashes(&decoded)
+    } else {
+        decoded
+    };
+    let normalized = remove_dot_segments(&merged);
+
+    if normalized == path {
+        return Ok(Cow::Borrowed(path));
+    }
+    if config.strict {
+        return Err(PathError::NotNormalized);
+    }
+    Ok(Cow::Owned(normalized))
+}
+
+/// Normalize the path of a request URI, returning the new URI if it changed
+pub fn normalize_uri(uri: &Uri, config: &PathNormalizationConfig) -> Result<Option<Uri>, PathError> {
+    let Cow::Owned(path) = normalize_path(uri.path(), config)? else {
+        return Ok(None);
+    };
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path,
+    };
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(PathAndQuery::try_from(path_and_query).map_err(|_| PathError::Malformed)?);
+    Uri::from_parts(parts).map(Some).map_err(|_| PathError::Malformed)
+}
+
+fn decode_unreserved(path: &str, encoded_slashes: EncodedSlashes) -> Result<String, PathError> {
+    let bytes = path.as_bytes();
+    let mut out = String::with_capacity(path.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'%' {
+            let len = path[i..].chars().next().map_or(1, char::len_utf8);
+            out.push_str(&path[i..i + len]);
+            i += len;
+            continue;
+        }
+
+        let byte = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            .ok_or(PathError::Malformed)?;
+        match byte {
+            0 => return Err(PathError::Malformed),
+            b'/' | b'\\' => match encoded_slashes {
+                EncodedSlashes::Keep => out.push_str(&format!("%{:02X}", byte)),
+                EncodedSlashes::Decode => out.push('/'),
+                EncodedSlashes::Reject => return Err(PathError::EncodedSlash),
+            },
+            b if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') => out.push(b as char),
+            b => out.push_str(&format!("%{:02X}", b)),
+        }
+        i += 3;
+    }
+    Ok(out)
+}
+
+fn merge_slashes(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for c in path.chars() {
+        if c != '/' || !out.ends_with('/') {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Remove `.` and `..` segments from an absolute path
+fn remove_dot_segments(path: &str) -> String {
+    let segments: Vec<&str> = path[1..].split('/').collect();
+    let last = segments.len() - 1;
+    let mut out: Vec<&str> = Vec::with_capacity(segments.len());
+    for (i, segment) in segments.into_iter().enumerate() {
+        match segment {
+            "." => {}
+            ".." => {
+                out.pop();
+            }
+            segment => {
+                out.push(segment);
+                continue;
+            }
+        }
+        // A trailing dot segment leaves a directory path
+        if i == last {
+            out.push("");
+        }
+    }
+    format!("/{}", out.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(path: &str) -> Result<String, PathError> {
+        normalize_path(path, &PathNormalizationConfig::default()).map(Cow::into_owned)
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        let cases = [
+            ("/", "/"),
+            ("/api/users", "/api/users"),
+            ("/a/b/../c", "/a/c"),
+            ("/a/./b/.", "/a/b/"),
+            ("/a/..", "/"),
+            ("/../../etc/passwd", "/etc/passwd"),
+            ("/static//../admin", "/admin"),
+            ("//api///users/", "/api/users/"),
+            ("/static/%2e%2e/admin", "/admin"),
+            ("/static/%2E%2e%2fadmin", "/static/..%2Fadmin"),
+            ("/%41pi/%7euser", "/Api/~user"),
+            ("/search/a%20b%3f", "/search/a%20b%3F"),
+            ("*", "*"),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(normalize(path).unwrap(), expected, "{}", path);
+        }
+
+        assert_eq!(normalize("/a%zz"), Err(PathError::Malformed));
+        assert_eq!(normalize("/a%2"), Err(PathError::Malformed));
+        assert_eq!(normalize("/a%00b"), Err(PathError::Malformed));
+        assert!(matches!(normalize_path("/api", &PathNormalizationConfig::default()), Ok(Cow::Borrowed(_))));
+    }
+
+    #[test]
+    fn test_normalization_options() {
+        let decode = PathNormalizationConfig {
+            encoded_slashes: EncodedSlashes::Decode,
+            merge_slashes: false,
+            ..Default::default()
+        };
+        assert_eq!(normalize_path("/static/..%2f..%5Cadmin", &decode).unwrap(), "/admin");
+        assert_eq!(normalize_path("/a//b", &decode).unwrap(), "/a//b");
+
+        let reject = PathNormalizationConfig {
+            encoded_slashes: EncodedSlashes::Reject,
+            ..Default::default()
+        };
+        assert_eq!(normalize_path("/a%2Fb", &reject), Err(PathError::EncodedSlash));
+
+        let strict = PathNormalizationConfig {
+            strict: true,
+            ..Default::default()
+        };
+        assert_eq!(normalize_path("/a/b", &strict).unwrap(), "/a/b");
+        assert_eq!(normalize_path("/a/../b", &strict), Err(PathError::NotNormalized));
+    }
+
+    #[test]
+    fn test_normalize_uri_keeps_query() {
+        let config = PathNormalizationConfig::default();
+        let uri: Uri = "/a/../b?next=/x/../y".parse().unwrap();
+        assert_eq!(normalize_uri(&uri, &config).unwrap().unwrap(), "/b?next=/x/../y");
+
+        let uri: Uri = "http://app.local/a/./b".parse().unwrap();
+        assert_eq!(normalize_uri(&uri, &config).unwrap().unwrap(), "http://app.local/a/b");
+        assert_eq!(normalize_uri(&"/ok".parse().unwrap(), &config).unwrap(), None);
+    }
+}
@@ -0,0 +1,728 @@
+//! Podman containers for Docker backends
+//!
+//! A Docker backend with `container_runtime = "podman"` runs its container
+//! through the `podman` CLI instead of the Docker API, so rootless Podman
+//! works without enabling its API socket. The container is set up as with
+//! Docker: the image is pulled according to `pull_policy` (logging in with
+//! `registry_auth`) and checked against `image_digest` and `platform`, the
+//! port is published on 127.0.0.1, and `network` and the resource limits
+//! are applied. `docker_host`, if set, is passed as `--url` to reach a Podman
+//! service instead, e.g. `unix:///run/user/1000/podman/podman.sock`.
+//!
+//! Exits are followed through `podman events`, so `on_container_exit` works
+//! the same way.
+
+use crate::config::{BackendConfig, PullPolicy, RegistryAuth};
+use crate::docker::{
+    check_image, image_digest, image_registry, parse_memory_limit, pinned_image, should_pull, ContainerExit,
+    ContainerRuntime, ContainerStats, Platform,
+};
+use crate::logs::{BackendLogs, LogStream};
+use bollard::models::ImageInspect;
+use futures::future::{BoxFuture, FutureExt};
+use base64::Engine;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Command;
+use tokio::sync::{mpsc, watch};
+use tracing::{debug, info, warn};
+
+/// Delay before running `podman events` again after it ends
+const EVENTS_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Podman auth file with the credentials for one registry, removed when dropped
+struct AuthFile {
+    path: PathBuf,
+}
+
+impl AuthFile {
+    fn create(registry: &str, auth: &RegistryAuth) -> anyhow::Result<Self> {
+        let (Some(username), Some(password)) = (&auth.username, &auth.password) else {
+            anyhow::bail!("registry_auth 'token' is not supported with the podman runtime");
+        };
+        let encoded = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", username, password));
+        let content = serde_json::json!({ "auths": { registry: { "auth": encoded } } });
+
+        let path = std::env::temp_dir().join(format!("spawngate-auth-{}.json", uuid::Uuid::new_v4()));
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options
+            .open(&path)
+            .map_err(|e| anyhow::anyhow!("Failed to write auth file '{}': {}", path.display(), e))?;
+        // Removed again if writing fails
+        let auth_file = Self { path };
+        file.write_all(content.to_string().as_bytes())?;
+        Ok(auth_file)
+    }
+}
+
+impl Drop for AuthFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Runs the containers of backends with `container_runtime = "podman"`
+#[derive(Debug, Clone)]
+pub struct PodmanRuntime {
+    binary: String,
+    /// Podman service to connect to, passed as `--url`
+    url: Option<String>,
+}
+
+impl PodmanRuntime {
+    /// Check that `podman` can be run, connecting to the service at `url` if set
+    pub async fn new(url: Option<&str>) -> anyhow::Result<Self> {
+        let runtime = Self {
+            binary: "podman".to_string(),
+            url: url.map(str::to_string),
+        };
+        let version = runtime
+            .run(&["version", "--format", "{{.Client.Version}}"])
+            .await
+            .map_err(|e| anyhow::anyhow!("Podman is not available: {}. Ensure podman is installed and on PATH.", e))?;
+        debug!(version = version.trim(), "Using Podman");
+        Ok(runtime)
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut cmd = Command::new(&self.binary);
+        if let Some(url) = &self.url {
+            cmd.arg("--url").arg(url);
+        }
+        cmd.args(args).stdin(Stdio::null()).kill_on_drop(true);
+        cmd
+    }
+
+    /// Run podman to completion, returning its output
+    async fn run(&self, args: &[&str]) -> anyhow::Result<String> {
+        self.run_with_env(args, &[]).await
+    }
+
+    /// Run podman with extra environment variables, which `--env NAME`
+    /// arguments pass on without putting their values on the command line
+    async fn run_with_env(&self, args: &[&str], env: &[(String, String)]) -> anyhow::Result<String> {
+        let output = self
+            .command(args)
+            .envs(env.iter().map(|(key, value)| (key, value)))
+            .output()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to run '{}': {}", self.binary, e))?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "'podman {}' failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    async fn inspect_image(&self, image: &str) -> Option<ImageInspect> {
+        let json = self.run(&["image", "inspect", image]).await.ok()?;
+        let images: Vec<PodmanImage> = serde_json::from_str(&json).ok()?;
+        images.into_iter().next().map(PodmanImage::into_inspect)
+    }
+
+    /// Pull an image if needed based on pull policy, logging in with `auth`
+    pub async fn pull_image_if_needed(
+        &self,
+        image: &str,
+        platform: Option<&Platform>,
+        policy: &PullPolicy,
+        auth: Option<&RegistryAuth>,
+    ) -> anyhow::Result<()> {
+        let local = match policy {
+            PullPolicy::Always => None,
+            _ => self.inspect_image(image).await,
+        };
+        if !should_pull(image, local.as_ref(), platform, policy, "podman")? {
+            return Ok(());
+        }
+
+        info!(image, platform = platform.map(tracing::field::display), "Pulling image with Podman");
+        let platform = platform.map(Platform::to_string);
+        let mut args = vec!["pull", "--quiet"];
+        if let Some(platform) = &platform {
+            args.extend(["--platform", platform]);
+        }
+        // Credentials go in an auth file, so they don't show up in the process list
+        let auth_file = auth.map(|auth| AuthFile::create(image_registry(image), auth)).transpose()?;
+        let auth_path = auth_file.as_ref().map(|file| file.path.to_string_lossy().into_owned());
+        if let Some(path) = &auth_path {
+            args.extend(["--authfile", path]);
+        }
+        args.push(image);
+        self.run(&args)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to pull image '{}': {}", image, e))?;
+        info!(image, "Image pulled successfully");
+        Ok(())
+    }
+
+    /// Start a container for a backend, returning its ID
+    pub async fn start_container(
+        &self,
+        config: &BackendConfig,
+        hostname: &str,
+        injected_env: &[(String, String)],
+    ) -> anyhow::Result<String> {
+        let image = config
+            .image
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Docker backend requires 'image' field"))?;
+        if !config.sidecars.is_empty() {
+            anyhow::bail!("sidecars are only supported with the docker runtime");
+        }
+        let image = pinned_image(image, config.image_digest.as_deref())?;
+        let platform = config.platform.as_deref().map(Platform::parse).transpose()?;
+
+        // Pull image if needed, then check it is the one pinned
+        self.pull_image_if_needed(&image, platform.as_ref(), &config.pull_policy, config.registry_auth.as_ref())
+            .await?;
+        let digest = image_digest(&image);
+        if digest.is_some() || platform.is_some() {
+            let inspect = self
+                .inspect_image(&image)
+                .await
+                .ok_or_else(|| anyhow::anyhow!("Failed to inspect image '{}'", image))?;
+            check_image(&image, &inspect, digest, platform.as_ref())?;
+        }
+
+        let container_name = config
+            .container_name
+            .clone()
+            .unwrap_or_else(|| format!("spawngate-{}", hostname.replace('.', "-")));
+
+        // Remove existing container with same name if it exists
+        let _ = self.remove_container(&container_name).await;
+
+        let command = run_args(config, &image, &container_name, platform.as_ref(), injected_env)?;
+        let args: Vec<&str> = command.args.iter().map(String::as_str).collect();
+        let output = self.run_with_env(&args, &command.env).await.map_err(|e| {
+            let err_str = e.to_string();
+            if err_str.contains("address already in use") {
+                anyhow::anyhow!(
+                    "Port {} is already in use. Another container or process is using this port. \
+                     Stop the conflicting service or use a different port.",
+                    config.port
+                )
+            } else {
+                anyhow::anyhow!(
+                    "Failed to start container '{}' from image '{}': {}",
+                    container_name, image, e
+                )
+            }
+        })?;
+
+        let container_id = output.trim().to_string();
+        info!(hostname, container_id, container_name, image, "Started Podman container");
+        Ok(container_id)
+    }
+
+    /// Stop a container gracefully
+    pub async fn stop_container(&self, container_id: &str, timeout: Duration) -> anyhow::Result<()> {
+        let timeout = timeout.as_secs().to_string();
+        match self.run(&["stop", "--time", &timeout, container_id]).await {
+            Ok(_) => {
+                info!(container_id, "Stopped Podman container");
+                Ok(())
+            }
+            Err(e) if is_missing(&e) => {
+                debug!(container_id, "Container not found");
+                Ok(())
+            }
+            Err(e) => Err(anyhow::anyhow!("Failed to stop container: {}", e)),
+        }
+    }
+
+    /// Force kill a container
+    pub async fn kill_container(&self, container_id: &str) -> anyhow::Result<()> {
+        match self.run(&["kill", container_id]).await {
+            Ok(_) => {
+                info!(container_id, "Killed Podman container");
+                Ok(())
+            }
+            Err(e) if is_missing(&e) || e.to_string().contains("not running") => {
+                debug!(container_id, "Container not running");
+                Ok(())
+            }
+            Err(e) => Err(anyhow::anyhow!("Failed to kill container: {}", e)),
+        }
+    }
+
+    /// Remove a container
+    pub async fn remove_container(&self, container_id: &str) -> anyhow::Result<()> {
+        match self.run(&["rm", "--force", container_id]).await {
+            Ok(_) => debug!(container_id, "Removed Podman container"),
+            Err(e) if is_missing(&e) => debug!(container_id, "Container not found"),
+            // Don't fail on removal errors
+            Err(e) => warn!(container_id, error = %e, "Failed to remove container"),
+        }
+        Ok(())
+    }
+
+    /// Check if a container is running
+    pub async fn is_running(&self, container_id: &str) -> bool {
+        self.run(&["container", "inspect", "--format", "{{.State.Running}}", container_id])
+            .await
+            .is_ok_and(|running| running.trim() == "true")
+    }
+
+    /// A one-off sample of a container's resource usage
+    pub async fn stats(&self, container_id: &str) -> anyhow::Result<ContainerStats> {
+        let json = self
+            .run(&["stats", "--no-stream", "--no-reset", "--format", "json", container_id])
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to get container stats: {}", e))?;
+        container_stats(&json).ok_or_else(|| anyhow::anyhow!("Unexpected podman stats output: {}", json.trim()))
+    }
+
+    /// Follow container logs and forward them to tracing and the backend's log buffer
+    ///
+    /// Returns a shutdown sender that stops `podman logs`.
+    pub fn stream_logs(&self, container_id: String, hostname: String, logs: Arc<BackendLogs>) -> watch::Sender<bool> {
+        let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
+        let mut cmd = self.command(&["logs", "--follow", &container_id]);
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+
+        tokio::spawn(async move {
+            let mut child = match cmd.spawn() {
+                Ok(child) => child,
+                Err(e) => {
+                    warn!(hostname, container_id, error = %e, "Error reading container logs");
+                    return;
+                }
+            };
+            let stdout = forward_logs(child.stdout.take(), &hostname, LogStream::Stdout, &logs);
+            let stderr = forward_logs(child.stderr.take(), &hostname, LogStream::Stderr, &logs);
+            tokio::select! {
+                _ = shutdown_rx.changed() => debug!(hostname, container_id, "Log streaming stopped"),
+                _ = futures::future::join(stdout, stderr) => {
+                    debug!(hostname, container_id, "Container log stream ended")
+                }
+            }
+        });
+
+        shutdown_tx
+    }
+
+    /// Follow `podman events` and report every container that exits
+    ///
+    /// `podman events` is run again (after a short delay) when it ends; the
+    /// task ends when the receiver is dropped.
+    pub fn watch_exits(&self) -> mpsc::UnboundedReceiver<ContainerExit> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let runtime = self.clone();
+
+        tokio::spawn(async move {
+            while !tx.is_closed() {
+                let mut cmd = runtime.command(&[
+                    "events",
+                    "--format",
+                    "json",
+                    "--filter",
+                    "type=container",
+                    "--filter",
+                    "event=died",
+                ]);
+                cmd.stdout(Stdio::piped());
+                let mut child = match cmd.spawn() {
+                    Ok(child) => child,
+                    Err(e) => {
+                        warn!(error = %e, "Failed to run podman events");
+                        tokio::time::sleep(EVENTS_RECONNECT_DELAY).await;
+                        continue;
+                    }
+                };
+                let Some(stdout) = child.stdout.take() else {
+                    break;
+                };
+                let mut lines = BufReader::new(stdout).lines();
+                loop {
+                    tokio::select! {
+                        _ = tx.closed() => return,
+                        line = lines.next_line() => match line {
+                            Ok(Some(line)) => {
+                                if let Some(exit) = container_exit(&line) {
+                                    debug!(container_id = %exit.container_id, exit_code = ?exit.exit_code, "Container exited");
+                                    if tx.send(exit).is_err() {
+                                        return;
+                                    }
+                                }
+                            }
+                            Ok(None) => break,
+                            Err(e) => {
+                                warn!(error = %e, "Error reading Podman events");
+                                break;
+                            }
+                        }
+                    }
+                }
+                tokio::time::sleep(EVENTS_RECONNECT_DELAY).await;
+            }
+        });
+
+        rx
+    }
+}
+
+impl ContainerRuntime for PodmanRuntime {
+    fn name(&self) -> &'static str {
+        "podman"
+    }
+
+    fn start_container<'a>(
+        &'a self,
+        config: &'a BackendConfig,
+        hostname: &'a str,
+        injected_env: &'a [(String, String)],
+    ) -> BoxFuture<'a, anyhow::Result<String>> {
+        PodmanRuntime::start_container(self, config, hostname, injected_env).boxed()
+    }
+
+    fn stop_container<'a>(&'a self, container_id: &'a str, timeout: Duration) -> BoxFuture<'a, anyhow::Result<()>> {
+        PodmanRuntime::stop_container(self, container_id, timeout).boxed()
+    }
+
+    fn kill_container<'a>(&'a self, container_id: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        PodmanRuntime::kill_container(self, container_id).boxed()
+    }
+
+    fn remove_container<'a>(&'a self, container_id: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        PodmanRuntime::remove_container(self, container_id).boxed()
+    }
+
+    fn is_running<'a>(&'a self, container_id: &'a str) -> BoxFuture<'a, bool> {
+        PodmanRuntime::is_running(self, container_id).boxed()
+    }
+
+    fn stats<'a>(&'a self, container_id: &'a str) -> BoxFuture<'a, anyhow::Result<ContainerStats>> {
+        PodmanRuntime::stats(self, container_id).boxed()
+    }
+
+    fn stream_logs(&self, container_id: String, hostname: String, logs: Arc<BackendLogs>) -> watch::Sender<bool> {
+        PodmanRuntime::stream_logs(self, container_id, hostname, logs)
+    }
+
+    fn watch_exits(&self) -> mpsc::UnboundedReceiver<ContainerExit> {
+        PodmanRuntime::watch_exits(self)
+    }
+}
+
+/// The `podman run` starting a backend's container
+///
+/// The container's variables are only named on the command line, which any
+/// local user can read; podman takes their values from its own environment.
+struct RunCommand {
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+}
+
+/// Command starting a backend's container
+///
+/// The image is pulled beforehand, so `podman run` never pulls.
+fn run_args(
+    config: &BackendConfig,
+    image: &str,
+    container_name: &str,
+    platform: Option<&Platform>,
+    injected_env: &[(String, String)],
+) -> anyhow::Result<RunCommand> {
+    let mut args: Vec<String> = vec![
+        "run".into(),
+        "--detach".into(),
+        "--name".into(),
+        container_name.into(),
+        "--pull=never".into(),
+        "--publish".into(),
+        format!("127.0.0.1:{}:{}", config.port, config.port),
+    ];
+    if let Some(network) = &config.network {
+        args.extend(["--network".into(), network.clone()]);
+    }
+    if let Some(memory) = &config.memory {
+        args.extend(["--memory".into(), parse_memory_limit(memory)?.to_string()]);
+    }
+    if let Some(cpus) = &config.cpus {
+        cpus.parse::<f64>()
+            .map_err(|_| anyhow::anyhow!("Invalid CPU limit: {}", cpus))?;
+        args.extend(["--cpus".into(), cpus.clone()]);
+    }
+    if let Some(shares) = config.cpu_shares {
+        args.extend(["--cpu-shares".into(), shares.to_string()]);
+    }
+    if let Some(limit) = config.pids_limit {
+        args.extend(["--pids-limit".into(), limit.to_string()]);
+    }
+    if let Some(platform) = platform {
+        args.extend(["--platform".into(), platform.to_string()]);
+    }
+
+    // Injected variables take precedence
+    let mut env: BTreeMap<&String, &String> = config.env.iter().collect();
+    env.extend(injected_env.iter().map(|(key, value)| (key, value)));
+    for key in env.keys() {
+        args.extend(["--env".into(), key.to_string()]);
+    }
+
+    args.push(image.to_string());
+    args.extend(config.args.iter().cloned());
+    let env = env.into_iter().map(|(key, value)| (key.clone(), value.clone())).collect();
+    Ok(RunCommand { args, env })
+}
+
+fn is_missing(error: &anyhow::Error) -> bool {
+    error.to_string().contains("no such container")
+}
+
+/// Forward one output stream of `podman logs` until it closes
+async fn forward_logs<R: AsyncRead + Unpin>(reader: Option<R>, hostname: &str, stream: LogStream, logs: &BackendLogs) {
+    let Some(reader) = reader else {
+        return;
+    };
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let line = line.trim_end();
+        if line.is_empty() {
+            continue;
+        }
+        match stream {
+            LogStream::Stderr => warn!(target: "container", hostname, stream = "stderr", "{}", line),
+            _ => info!(target: "container", hostname, stream = "stdout", "{}", line),
+        }
+        logs.record(hostname, stream, line);
+    }
+}
+
+/// Fields of `podman image inspect` checked for digest pinning and platforms
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PodmanImage {
+    os: Option<String>,
+    architecture: Option<String>,
+    variant: Option<String>,
+    repo_digests: Option<Vec<String>>,
+}
+
+impl PodmanImage {
+    fn into_inspect(self) -> ImageInspect {
+        ImageInspect {
+            os: self.os,
+            architecture: self.architecture,
+            variant: self.variant,
+            repo_digests: self.repo_digests,
+            ..Default::default()
+        }
+    }
+}
+
+/// One line of `podman events --format json`
+#[derive(Deserialize)]
+struct PodmanEvent {
+    #[serde(rename = "ID")]
+    id: String,
+    #[serde(rename = "Type")]
+    typ: String,
+    #[serde(rename = "Status")]
+    status: String,
+    #[serde(rename = "ContainerExitCode")]
+    exit_code: Option<i64>,
+}
+
+/// The exit reported by a container `died` event
+fn container_exit(line: &str) -> Option<ContainerExit> {
+    let event: PodmanEvent = serde_json::from_str(line).ok()?;
+    if event.typ != "container" || !matches!(event.status.as_str(), "died" | "die") {
+        return None;
+    }
+    Some(ContainerExit {
+        container_id: event.id,
+        exit_code: event.exit_code,
+    })
+}
+
+/// One entry of `podman stats --format json`, whose values are formatted for display
+#[derive(Deserialize)]
+struct PodmanStats {
+    cpu_percent: String,
+    mem_usage: String,
+    pids: String,
+}
+
+/// Parse `podman stats --format json` output into the usage of its first container
+fn container_stats(json: &str) -> Option<ContainerStats> {
+    let stats: Vec<PodmanStats> = serde_json::from_str(json).ok()?;
+    let stats = stats.into_iter().next()?;
+    let cpu_percent = stats.cpu_percent.trim().trim_end_matches('%').parse().ok()?;
+    let (usage, limit) = stats.mem_usage.split_once('/')?;
+    Some(ContainerStats {
+        cpu_percent,
+        memory_bytes: parse_size(usage)?,
+        memory_limit_bytes: parse_size(limit),
+        pids: stats.pids.trim().parse().ok(),
+    })
+}
+
+/// Parse a human-readable size such as `10.5MB` or `1.2GiB` into bytes
+fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let split = size.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let number: f64 = number.trim().parse().ok()?;
+    let multiplier: u64 = match unit.to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1000,
+        "mb" => 1000u64.pow(2),
+        "gb" => 1000u64.pow(3),
+        "tb" => 1000u64.pow(4),
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => return None,
+    };
+    Some((number * multiplier as f64).round() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{BackendType, ContainerEngine};
+
+    #[test]
+    fn test_run_args() {
+        let mut config = BackendConfig::local("unused", 8080);
+        config.backend_type = BackendType::Docker;
+        config.container_runtime = ContainerEngine::Podman;
+        config.network = Some("backends".to_string());
+        config.memory = Some("512m".to_string());
+        config.cpus = Some("0.5".to_string());
+        config.cpu_shares = Some(512);
+        config.pids_limit = Some(100);
+        config.args = vec!["serve".to_string(), "--verbose".to_string()];
+        config.env.insert("PORT".to_string(), "1".to_string());
+        config.env.insert("APP_MODE".to_string(), "web".to_string());
+        let platform = Platform::parse("linux/arm64").unwrap();
+
+        let RunCommand { args, env } = run_args(
+            &config,
+            "myapp:1",
+            "spawngate-app-local",
+            Some(&platform),
+            &[("PORT".to_string(), "8080".to_string())],
+        )
+        .unwrap();
+        assert_eq!(
+            args.join(" "),
+            "run --detach --name spawngate-app-local --pull=never --publish 127.0.0.1:8080:8080 \
+             --network backends --memory 536870912 --cpus 0.5 --cpu-shares 512 --pids-limit 100 \
+             --platform linux/arm64 \
+             --env APP_MODE --env PORT myapp:1 serve --verbose"
+        );
+        assert_eq!(
+            env,
+            [("APP_MODE".to_string(), "web".to_string()), ("PORT".to_string(), "8080".to_string())]
+        );
+
+        config.cpus = Some("lots".to_string());
+        assert!(run_args(&config, "myapp:1", "app", None, &[]).is_err());
+    }
+
+    #[test]
+    fn test_run_args_keep_values_off_command_line() {
+        let mut config = BackendConfig::local("unused", 8080);
+        config.env.insert("DATABASE_PASSWORD".to_string(), "hunter2".to_string());
+        let injected = [
+            ("SPAWNGATE_SIGNING_SECRET".to_string(), "s3cr3t-signing-key".to_string()),
+            ("SPAWNGATE_READY_URL".to_string(), "http://127.0.0.1:9999/ready/app?token=t0ken".to_string()),
+        ];
+
+        let RunCommand { args, env } = run_args(&config, "myapp:1", "app", None, &injected).unwrap();
+        for secret in ["hunter2", "s3cr3t-signing-key", "t0ken"] {
+            assert!(args.iter().all(|arg| !arg.contains(secret)), "{} in {:?}", secret, args);
+            assert!(env.iter().any(|(_, value)| value.contains(secret)));
+        }
+        assert!(args.windows(2).any(|pair| pair == ["--env", "SPAWNGATE_SIGNING_SECRET"]));
+    }
+
+    #[test]
+    fn test_auth_file() {
+        let auth = RegistryAuth {
+            username: Some("deploy".to_string()),
+            password: Some("s3cret".to_string()),
+            token: None,
+        };
+        let file = AuthFile::create("ghcr.io", &auth).unwrap();
+        let path = file.path.clone();
+        let content: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(content["auths"]["ghcr.io"]["auth"], "ZGVwbG95OnMzY3JldA==");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        drop(file);
+        assert!(!path.exists());
+
+        let token = RegistryAuth {
+            token: Some("abc".to_string()),
+            ..Default::default()
+        };
+        assert!(AuthFile::create("ghcr.io", &token).is_err());
+    }
+
+    #[test]
+    fn test_container_exit() {
+        let died = r#"{"ID":"3f2a","Image":"myapp:1","Name":"spawngate-app","Status":"died","Time":"2026-10-16T10:00:00Z","Type":"container","ContainerExitCode":137}"#;
+        assert_eq!(
+            container_exit(died),
+            Some(ContainerExit {
+                container_id: "3f2a".to_string(),
+                exit_code: Some(137)
+            })
+        );
+        assert_eq!(container_exit(&died.replace("\"died\"", "\"start\"")), None);
+        assert_eq!(container_exit(&died.replace("\"container\"", "\"image\"")), None);
+        assert_eq!(container_exit("not json"), None);
+    }
+
+    #[test]
+    fn test_container_stats() {
+        let json = r#"[{"id":"abc","name":"app","cpu_percent":"12.50%","mem_usage":"10.5MB / 2GiB","pids":"7"}]"#;
+        let stats = container_stats(json).unwrap();
+        assert_eq!(stats.cpu_percent, 12.5);
+        assert_eq!(stats.memory_bytes, 10_500_000);
+        assert_eq!(stats.memory_limit_bytes, Some(2 << 30));
+        assert_eq!(stats.pids, Some(7));
+
+        assert!(container_stats("[]").is_none());
+        assert!(container_stats("not json").is_none());
+        assert_eq!(parse_size("512B"), Some(512));
+        assert_eq!(parse_size("1.5kB"), Some(1500));
+        assert_eq!(parse_size("lots"), None);
+    }
+
+    #[test]
+    fn test_image_checks() {
+        let json = r#"[{"Id":"abc","Os":"linux","Architecture":"arm64","RepoDigests":["docker.io/library/myapp@sha256:0000000000000000000000000000000000000000000000000000000000000000"]}]"#;
+        let images: Vec<PodmanImage> = serde_json::from_str(json).unwrap();
+        let inspect = images.into_iter().next().unwrap().into_inspect();
+        let digest = format!("sha256:{}", "0".repeat(64));
+        let arm64 = Platform::parse("linux/arm64").unwrap();
+        assert!(check_image("myapp", &inspect, Some(&digest), Some(&arm64)).is_ok());
+        assert!(check_image("myapp", &inspect, None, Some(&Platform::parse("linux/amd64").unwrap())).is_err());
+        assert!(!should_pull("myapp", Some(&inspect), Some(&arm64), &PullPolicy::IfNotPresent, "podman").unwrap());
+
+        let err = should_pull("myapp", None, None, &PullPolicy::Never, "podman").unwrap_err();
+        assert!(err.to_string().contains("'podman pull myapp'"));
+    }
+}
@@ -2,18 +2,43 @@
 //!
 //! This module provides connection pooling for efficient reuse of HTTP connections
 //! to backend servers, reducing latency and resource usage.
-
+//!
+//! Backends speak HTTP/1.1 by default. Backends with `backend_http2` get
+//! HTTP/2 with prior knowledge (h2c), and backends with `backend_tls` get TLS
+//! with HTTP/2 when they select it via ALPN. HTTP/2 backends are sent every
+//! request over a single multiplexed connection, reopened when it closes.
+
+use crate::registry::{DebugRegistry, UpstreamConnection};
+use dashmap::{DashMap, DashSet};
+use futures::future::BoxFuture;
 use http_body_util::{combinators::BoxBody, BodyExt, Empty};
-use hyper::body::{Bytes, Incoming};
-use hyper::{Request, Response};
-use hyper_util::client::legacy::connect::HttpConnector;
+use hyper::body::Bytes;
+use hyper::client::conn::http2;
+use hyper::rt::{Read, ReadBufCursor, Write};
+use hyper::http::uri::{PathAndQuery, Scheme};
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::{Request, Response, Uri, Version};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::client::legacy::connect::{Connected, Connection, HttpConnector};
 use hyper_util::client::legacy::Client;
-use hyper_util::rt::TokioExecutor;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, SignatureScheme};
+use std::io;
+use std::net::Ipv4Addr;
+use std::pin::Pin;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio_rustls::TlsConnector;
+use tower_service::Service;
 use tracing::debug;
 
+type PoolBody = BoxBody<Bytes, hyper::Error>;
+
 /// Error type for connection pool operations
 #[derive(Debug)]
 pub enum PoolError {
@@ -21,6 +46,10 @@ pub enum PoolError {
     Client(hyper_util::client::legacy::Error),
     /// Error building a request
     RequestBuild(String),
+    /// Error opening a connection to the backend
+    Connect(String),
+    /// Error on an HTTP/2 backend connection
+    Http2(hyper::Error),
 }
 
 impl std::fmt::Display for PoolError {
@@ -28,6 +57,8 @@ impl std::fmt::Display for PoolError {
         match self {
             PoolError::Client(e) => write!(f, "Client error: {}", e),
             PoolError::RequestBuild(s) => write!(f, "Request build error: {}", s),
+            PoolError::Connect(s) => write!(f, "Connect error: {}", s),
+            PoolError::Http2(e) => write!(f, "HTTP/2 error: {}", e),
         }
     }
 }
@@ -87,10 +118,365 @@ impl Default for PoolConfig {
     }
 }
 
+/// Protocol the pool speaks to a backend
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BackendProtocol {
+    /// HTTP/1.1 over pooled connections
+    #[default]
+    Http1,
+    /// HTTP/2 with prior knowledge (h2c) over one multiplexed connection
+    Http2,
+    /// TLS, with HTTP/2 if the backend selects it via ALPN and HTTP/1.1 otherwise
+    Tls,
+}
+
+/// When and how quickly a pooled connection was established
+///
+/// Set in the extensions of every response sent over the connection. A
+/// connection established after a request was sent was opened for it.
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectTiming {
+    pub established: Instant,
+    pub duration: Duration,
+}
+
+/// HTTP connector that records [`ConnectTiming`] for new connections and
+/// registers them while open
+#[derive(Clone)]
+struct TimedConnector {
+    inner: HttpConnector,
+    registry: Arc<DebugRegistry>,
+}
+
+impl Service<Uri> for TimedConnector {
+    type Response = TimedConnection<<HttpConnector as Service<Uri>>::Response>;
+    type Error = <HttpConnector as Service<Uri>>::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let started = Instant::now();
+        let backend = uri.authority().map(|a| a.to_string()).unwrap_or_default();
+        let registry = Arc::clone(&self.registry);
+        let connecting = self.inner.call(uri);
+        Box::pin(async move {
+            let io = connecting.await?;
+            let established = Instant::now();
+            Ok(TimedConnection {
+                inner: io,
+                timing: ConnectTiming {
+                    established,
+                    duration: established - started,
+                },
+                registration: registry.upstream(backend),
+            })
+        })
+    }
+}
+
+/// Connection that reports its [`ConnectTiming`] to the client
+struct TimedConnection<T> {
+    inner: T,
+    timing: ConnectTiming,
+    registration: UpstreamConnection,
+}
+
+impl<T: Connection> Connection for TimedConnection<T> {
+    fn connected(&self) -> Connected {
+        self.inner.connected().extra(self.timing)
+    }
+}
+
+impl<T: Read + Unpin> Read for TimedConnection<T> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: ReadBufCursor<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            this.registration.record_io();
+        }
+        poll
+    }
+}
+
+impl<T: Write + Unpin> Write for TimedConnection<T> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(_)) = poll {
+            this.registration.record_io();
+        }
+        poll
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_write_vectored(cx, bufs);
+        if let Poll::Ready(Ok(_)) = poll {
+            this.registration.record_io();
+        }
+        poll
+    }
+}
+
+/// Point a request at a local backend port
+///
+/// The method, headers and body are reused as they are; the request is sent
+/// as HTTP/1.1 without the incoming request's extensions. HTTP/2 requests
+/// carry their host in the `:authority` pseudo-header, which becomes the
+/// backend's `Host` header.
+pub fn backend_request<B>(req: Request<B>, port: u16) -> Result<Request<B>, PoolError> {
+    let (mut parts, body) = req.into_parts();
+    if !parts.headers.contains_key(hyper::header::HOST) {
+        if let Some(value) = parts.uri.authority().and_then(|a| HeaderValue::from_str(a.as_str()).ok()) {
+            parts.headers.insert(hyper::header::HOST, value);
+        }
+    }
+    let path_and_query = parts
+        .uri
+        .path_and_query()
+        .cloned()
+        .unwrap_or_else(|| PathAndQuery::from_static("/"));
+    parts.uri = Uri::builder()
+        .scheme(Scheme::HTTP)
+        .authority(format!("127.0.0.1:{}", port))
+        .path_and_query(path_and_query)
+        .build()
+        .map_err(|e| PoolError::RequestBuild(e.to_string()))?;
+    parts.version = Version::HTTP_11;
+    parts.extensions.clear();
+    Ok(Request::from_parts(parts, body))
+}
+
+/// Point a request at a local HTTP/2 backend port
+///
+/// Like [`backend_request`], but the host moves from the `Host` header to the
+/// `:authority` pseudo-header, and the connection-specific headers HTTP/2
+/// forbids are dropped.
+pub fn http2_request<B>(req: Request<B>, port: u16, tls: bool) -> Result<Request<B>, PoolError> {
+    let (mut parts, body) = backend_request(req, port)?.into_parts();
+    let authority = parts
+        .headers
+        .remove(hyper::header::HOST)
+        .and_then(|host| host.to_str().ok().map(str::to_string))
+        .unwrap_or_else(|| format!("127.0.0.1:{}", port));
+    strip_connection_headers(&mut parts.headers);
+    let path_and_query = parts
+        .uri
+        .path_and_query()
+        .cloned()
+        .unwrap_or_else(|| PathAndQuery::from_static("/"));
+    parts.uri = Uri::builder()
+        .scheme(if tls { Scheme::HTTPS } else { Scheme::HTTP })
+        .authority(authority)
+        .path_and_query(path_and_query)
+        .build()
+        .map_err(|e| PoolError::RequestBuild(e.to_string()))?;
+    parts.version = Version::HTTP_2;
+    Ok(Request::from_parts(parts, body))
+}
+
+/// Remove headers that only apply to an HTTP/1.1 connection
+fn strip_connection_headers(headers: &mut HeaderMap) {
+    let listed: Vec<HeaderName> = headers
+        .get_all(hyper::header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect();
+    for name in listed {
+        headers.remove(name);
+    }
+    for name in [
+        hyper::header::CONNECTION,
+        hyper::header::TRANSFER_ENCODING,
+        hyper::header::UPGRADE,
+        HeaderName::from_static("keep-alive"),
+        HeaderName::from_static("proxy-connection"),
+    ] {
+        headers.remove(name);
+    }
+    if headers.get(hyper::header::TE).is_some_and(|te| te != "trailers") {
+        headers.remove(hyper::header::TE);
+    }
+}
+
+/// Certificate verifier for TLS backends
+///
+/// Backends listen on the loopback interface, so their certificates are
+/// usually self-signed and the connection never leaves the machine. Any
+/// certificate is accepted, but handshake signatures are still checked.
+#[derive(Debug)]
+struct LoopbackVerifier(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for LoopbackVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// TLS client configuration for backends, without ALPN protocols
+fn backend_tls_config() -> ClientConfig {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    ClientConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()
+        .expect("ring supports the default protocol versions")
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(LoopbackVerifier(provider)))
+        .with_no_client_auth()
+}
+
+/// Open TLS to a local backend over `stream`, offering HTTP/1.1
+pub async fn backend_tls<S>(stream: S) -> io::Result<tokio_rustls::client::TlsStream<S>>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let mut config = backend_tls_config();
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    TlsConnector::from(Arc::new(config))
+        .connect(ServerName::from(Ipv4Addr::LOCALHOST), stream)
+        .await
+}
+
+/// HTTP/2 connection shared by the requests to a backend
+#[derive(Clone)]
+struct Http2Connection {
+    sender: http2::SendRequest<PoolBody>,
+    timing: ConnectTiming,
+}
+
+/// One multiplexed HTTP/2 connection per backend port
+struct Http2Connections {
+    connector: TimedConnector,
+    tls: TlsConnector,
+    connections: DashMap<(u16, bool), Arc<tokio::sync::Mutex<Option<Http2Connection>>>>,
+}
+
+impl Http2Connections {
+    /// The backend's connection, opened unless one is still open
+    ///
+    /// `None` if a TLS backend selected HTTP/1.1 instead.
+    async fn get(&self, port: u16, tls: bool) -> Result<Option<Http2Connection>, PoolError> {
+        let slot = Arc::clone(&self.connections.entry((port, tls)).or_default());
+        // Held while connecting, so concurrent requests share the new connection
+        let mut slot = slot.lock().await;
+        if let Some(connection) = slot.as_ref().filter(|c| !c.sender.is_closed()) {
+            return Ok(Some(connection.clone()));
+        }
+        *slot = self.connect(port, tls).await?;
+        Ok(slot.clone())
+    }
+
+    async fn connect(&self, port: u16, tls: bool) -> Result<Option<Http2Connection>, PoolError> {
+        let started = Instant::now();
+        let uri = Uri::builder()
+            .scheme(Scheme::HTTP)
+            .authority(format!("127.0.0.1:{}", port))
+            .path_and_query("/")
+            .build()
+            .map_err(|e| PoolError::RequestBuild(e.to_string()))?;
+        let io = self
+            .connector
+            .clone()
+            .call(uri)
+            .await
+            .map_err(|e| PoolError::Connect(e.to_string()))?;
+        if !tls {
+            return handshake(io, started).await.map(Some);
+        }
+        let stream = self
+            .tls
+            .connect(ServerName::from(Ipv4Addr::LOCALHOST), TokioIo::new(io))
+            .await
+            .map_err(|e| PoolError::Connect(e.to_string()))?;
+        if stream.get_ref().1.alpn_protocol() != Some(b"h2") {
+            return Ok(None);
+        }
+        handshake(TokioIo::new(stream), started).await.map(Some)
+    }
+}
+
+/// Start HTTP/2 on a connection opened at `started`
+async fn handshake<T>(io: T, started: Instant) -> Result<Http2Connection, PoolError>
+where
+    T: Read + Write + Send + Unpin + 'static,
+{
+    let (sender, connection) = http2::handshake(TokioExecutor::new(), io)
+        .await
+        .map_err(PoolError::Http2)?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            debug!(error = %e, "Backend HTTP/2 connection failed");
+        }
+    });
+    let established = Instant::now();
+    Ok(Http2Connection {
+        sender,
+        timing: ConnectTiming {
+            established,
+            duration: established - started,
+        },
+    })
+}
+
 /// A connection pool for HTTP connections to backend servers
 pub struct ConnectionPool {
     /// Main client for proxying requests
-    client: Client<HttpConnector, Incoming>,
+    client: Client<TimedConnector, PoolBody>,
+    /// Client for TLS backends that selected HTTP/1.1
+    tls_client: Client<HttpsConnector<TimedConnector>, PoolBody>,
+    /// Connections to HTTP/2 backends
+    http2: Http2Connections,
+    /// TLS backend ports known to select HTTP/1.1
+    tls_http1: DashSet<u16>,
     /// Dedicated client for health checks (uses Empty body type)
     health_client: Client<HttpConnector, Empty<Bytes>>,
     stats: Arc<PoolStats>,
@@ -100,6 +486,11 @@ pub struct ConnectionPool {
 impl ConnectionPool {
     /// Create a new connection pool with the given configuration
     pub fn new(config: PoolConfig) -> Self {
+        Self::with_registry(config, Arc::new(DebugRegistry::new()))
+    }
+
+    /// Create a connection pool whose backend connections are listed in `registry`
+    pub fn with_registry(config: PoolConfig, registry: Arc<DebugRegistry>) -> Self {
         let mut connector = HttpConnector::new();
         connector.set_nodelay(true);
         connector.enforce_http(true);
@@ -108,7 +499,37 @@ impl ConnectionPool {
         let client = Client::builder(TokioExecutor::new())
             .pool_max_idle_per_host(config.max_idle_per_host)
             .pool_idle_timeout(config.idle_timeout)
-            .build(connector.clone());
+            .build(TimedConnector {
+                inner: connector.clone(),
+                registry: Arc::clone(&registry),
+            });
+
+        let mut tls_connector = connector.clone();
+        tls_connector.enforce_http(false);
+        let tls_client = Client::builder(TokioExecutor::new())
+            .pool_max_idle_per_host(config.max_idle_per_host)
+            .pool_idle_timeout(config.idle_timeout)
+            .build(
+                HttpsConnectorBuilder::new()
+                    .with_tls_config(backend_tls_config())
+                    .https_only()
+                    .enable_http1()
+                    .wrap_connector(TimedConnector {
+                        inner: tls_connector,
+                        registry: Arc::clone(&registry),
+                    }),
+            );
+
+        let mut http2_tls_config = backend_tls_config();
+        http2_tls_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        let http2 = Http2Connections {
+            connector: TimedConnector {
+                inner: connector.clone(),
+                registry,
+            },
+            tls: TlsConnector::from(Arc::new(http2_tls_config)),
+            connections: DashMap::new(),
+        };
 
         // Build a dedicated health check client (reused across health checks)
         let health_client = Client::builder(TokioExecutor::new())
@@ -124,6 +545,9 @@ impl ConnectionPool {
 
         Self {
             client,
+            tls_client,
+            http2,
+            tls_http1: DashSet::new(),
             health_client,
             stats: Arc::new(PoolStats::default()),
             config,
@@ -143,38 +567,44 @@ impl ConnectionPool {
     /// Send a request through the connection pool
     pub async fn send_request(
         &self,
-        req: Request<Incoming>,
+        req: Request<PoolBody>,
         port: u16,
-    ) -> Result<Response<BoxBody<Bytes, hyper::Error>>, PoolError> {
-        // Build the URI for the backend
-        let uri = format!("http://127.0.0.1:{}{}", port, req.uri().path_and_query().map(|pq| pq.as_str()).unwrap_or("/"));
-
-        // Create a new request with the backend URI
-        let (parts, body) = req.into_parts();
-        let mut builder = Request::builder()
-            .method(parts.method)
-            .uri(&uri);
-
-        // Copy headers
-        for (key, value) in parts.headers.iter() {
-            builder = builder.header(key, value);
-        }
-
-        let backend_req = builder
-            .body(body)
-            .map_err(|e| PoolError::RequestBuild(e.to_string()))?;
-
+        protocol: BackendProtocol,
+    ) -> Result<Response<PoolBody>, PoolError> {
         // Record statistics
         self.stats.record_request();
 
+        let tls = protocol == BackendProtocol::Tls;
+        if protocol != BackendProtocol::Http1 && !self.tls_http1.contains(&port) {
+            match self.http2.get(port, tls).await? {
+                Some(connection) => {
+                    let backend_req = http2_request(req, port, tls)?;
+                    let mut sender = connection.sender;
+                    sender.ready().await.map_err(PoolError::Http2)?;
+                    let mut response = sender.send_request(backend_req).await.map_err(PoolError::Http2)?;
+                    response.extensions_mut().insert(connection.timing);
+                    return Ok(response.map(BodyExt::boxed));
+                }
+                None => {
+                    debug!(port, "TLS backend selected HTTP/1.1");
+                    self.tls_http1.insert(port);
+                }
+            }
+        }
+
+        let mut backend_req = backend_request(req, port)?;
         // Send the request through the pooled client
-        let response = self.client.request(backend_req).await?;
+        let response = if tls {
+            let mut uri = backend_req.uri().clone().into_parts();
+            uri.scheme = Some(Scheme::HTTPS);
+            *backend_req.uri_mut() = Uri::from_parts(uri).map_err(|e| PoolError::RequestBuild(e.to_string()))?;
+            self.tls_client.request(backend_req).await?
+        } else {
+            self.client.request(backend_req).await?
+        };
 
         // Convert the response body to BoxBody
-        let (parts, body) = response.into_parts();
-        let boxed_body = body.boxed();
-
-        Ok(Response::from_parts(parts, boxed_body))
+        Ok(response.map(BodyExt::boxed))
     }
 
     /// Check if a backend is reachable (useful for health checks)
@@ -206,6 +636,82 @@ impl ConnectionPool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use http_body_util::Full;
+    use hyper::service::service_fn;
+    use hyper_util::server::conn::auto::Builder as AutoBuilder;
+    use rustls::pki_types::PrivateKeyDer;
+    use rustls::ServerConfig;
+    use std::convert::Infallible;
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    /// Serve HTTP/1.1 and HTTP/2 on a local port, answering with the request's
+    /// version and host, and counting connections
+    async fn backend(tls: Option<ServerConfig>) -> (u16, Arc<AtomicU64>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let connections = Arc::new(AtomicU64::new(0));
+        let accepted = Arc::clone(&connections);
+        let acceptor = tls.map(|config| TlsAcceptor::from(Arc::new(config)));
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                accepted.fetch_add(1, Ordering::SeqCst);
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(|req: Request<hyper::body::Incoming>| async move {
+                        let host = req
+                            .uri()
+                            .authority()
+                            .map(|a| a.to_string())
+                            .or_else(|| req.headers().get("host").map(|h| h.to_str().unwrap().to_string()))
+                            .unwrap_or_default();
+                        let body = format!("{:?} {}", req.version(), host);
+                        Ok::<_, Infallible>(Response::new(Full::new(Bytes::from(body))))
+                    });
+                    let builder = AutoBuilder::new(TokioExecutor::new());
+                    let _ = match acceptor {
+                        Some(acceptor) => {
+                            let stream = acceptor.accept(stream).await.unwrap();
+                            builder.serve_connection(TokioIo::new(stream), service).await
+                        }
+                        None => builder.serve_connection(TokioIo::new(stream), service).await,
+                    };
+                });
+            }
+        });
+        (port, connections)
+    }
+
+    fn tls_config(alpn: &[&[u8]]) -> ServerConfig {
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let key = PrivateKeyDer::try_from(key_pair.serialize_der()).unwrap();
+        let mut config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![CertificateDer::from(cert.der().to_vec())], key)
+            .unwrap();
+        config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+        config
+    }
+
+    /// Send `count` concurrent requests for app.local, returning the bodies
+    async fn send(pool: &ConnectionPool, port: u16, protocol: BackendProtocol, count: usize) -> Vec<String> {
+        let requests = (0..count).map(|_| async {
+            let req = Request::builder()
+                .uri("/")
+                .header("host", "app.local")
+                .header("connection", "keep-alive")
+                .body(Empty::<Bytes>::new().map_err(|never| match never {}).boxed())
+                .unwrap();
+            let response = pool.send_request(req, port, protocol).await.unwrap();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            String::from_utf8(body.to_vec()).unwrap()
+        });
+        futures::future::join_all(requests).await
+    }
 
     #[test]
     fn test_pool_config_default() {
@@ -243,4 +749,88 @@ mod tests {
         assert_eq!(pool.config().idle_timeout, Duration::from_secs(30));
         assert_eq!(pool.stats().get_total_requests(), 0);
     }
+
+    #[test]
+    fn test_backend_request() {
+        let req = Request::builder()
+            .method("POST")
+            .uri("https://app.example.com/api/items?page=2")
+            .version(Version::HTTP_2)
+            .header("host", "app.example.com")
+            .header("x-request-id", "abc")
+            .body(())
+            .unwrap();
+
+        let req = backend_request(req, 3000).unwrap();
+        assert_eq!(req.method(), "POST");
+        assert_eq!(req.uri(), "http://127.0.0.1:3000/api/items?page=2");
+        assert_eq!(req.version(), Version::HTTP_11);
+        assert_eq!(req.headers()["host"], "app.example.com");
+        assert_eq!(req.headers()["x-request-id"], "abc");
+
+        let req = backend_request(Request::new(()), 3000).unwrap();
+        assert_eq!(req.uri(), "http://127.0.0.1:3000/");
+        assert!(req.headers().get("host").is_none());
+
+        // HTTP/2 requests without a Host header
+        let req = Request::builder()
+            .uri("https://app.example.com:8443/")
+            .version(Version::HTTP_2)
+            .body(())
+            .unwrap();
+        let req = backend_request(req, 3000).unwrap();
+        assert_eq!(req.headers()["host"], "app.example.com:8443");
+    }
+
+    #[test]
+    fn test_http2_request() {
+        let req = Request::builder()
+            .uri("/api?page=2")
+            .header("host", "app.example.com")
+            .header("connection", "keep-alive, x-hop")
+            .header("keep-alive", "timeout=5")
+            .header("x-hop", "1")
+            .header("te", "gzip")
+            .header("x-request-id", "abc")
+            .body(())
+            .unwrap();
+
+        let req = http2_request(req, 3000, false).unwrap();
+        assert_eq!(req.uri(), "http://app.example.com/api?page=2");
+        assert_eq!(req.version(), Version::HTTP_2);
+        let names: Vec<_> = req.headers().keys().map(|name| name.as_str()).collect();
+        assert_eq!(names, ["x-request-id"]);
+
+        let req = http2_request(Request::new(()), 3000, true).unwrap();
+        assert_eq!(req.uri(), "https://127.0.0.1:3000/");
+    }
+
+    #[tokio::test]
+    async fn test_http2_backend_multiplexes() {
+        let pool = ConnectionPool::new(PoolConfig::default());
+        let (port, connections) = backend(None).await;
+
+        let bodies = send(&pool, port, BackendProtocol::Http2, 10).await;
+        assert!(bodies.iter().all(|body| body == "HTTP/2.0 app.local"), "{:?}", bodies);
+        assert_eq!(send(&pool, port, BackendProtocol::Http2, 1).await, ["HTTP/2.0 app.local"]);
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+
+        assert_eq!(send(&pool, port, BackendProtocol::Http1, 1).await, ["HTTP/1.1 app.local"]);
+    }
+
+    #[tokio::test]
+    async fn test_tls_backend_alpn() {
+        let pool = ConnectionPool::new(PoolConfig::default());
+
+        let (port, connections) = backend(Some(tls_config(&[b"h2", b"http/1.1"]))).await;
+        let bodies = send(&pool, port, BackendProtocol::Tls, 10).await;
+        assert!(bodies.iter().all(|body| body == "HTTP/2.0 app.local"), "{:?}", bodies);
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+
+        // Backends without HTTP/2 get HTTP/1.1 over TLS
+        let (port, _) = backend(Some(tls_config(&[b"http/1.1"]))).await;
+        assert_eq!(send(&pool, port, BackendProtocol::Tls, 1).await, ["HTTP/1.1 app.local"]);
+        assert_eq!(send(&pool, port, BackendProtocol::Tls, 1).await, ["HTTP/1.1 app.local"]);
+        assert!(pool.tls_http1.contains(&port));
+    }
 }
@@ -0,0 +1,207 @@
+//! Ports for backends started with `port = 0`
+//!
+//! A backend cycled quickly on a fixed port can fail to start while
+//! connections to its previous instance are still in TIME_WAIT. Backends
+//! with `port = 0` get a port from `[defaults.ports]` each time they start
+//! instead: with `reuse`, their previous one again if it can be bound, as
+//! servers listening with SO_REUSEADDR can despite TIME_WAIT; otherwise the
+//! next free port of the range. The search rotates through the whole range
+//! and skips ports released less than `cooldown_secs` ago, so a port is only
+//! handed out again once its old connections have expired.
+
+use crate::config::PortAllocationConfig;
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::net::{Ipv4Addr, TcpListener};
+use std::time::{Duration, Instant};
+
+/// Assigns ports to backends and tracks when they were released
+#[derive(Debug, Default)]
+pub struct PortAllocator {
+    ports: Mutex<Ports>,
+}
+
+#[derive(Debug, Default)]
+struct Ports {
+    /// Port of each running backend
+    assigned: HashMap<String, u16>,
+    /// Port each stopped backend had last
+    previous: HashMap<String, u16>,
+    /// When ports still cooling down were released
+    released: HashMap<u16, Instant>,
+    /// Where the next search through the range starts
+    cursor: u16,
+}
+
+impl Ports {
+    fn assign(&mut self, hostname: &str, port: u16) -> u16 {
+        self.assigned.insert(hostname.to_string(), port);
+        port
+    }
+}
+
+impl PortAllocator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Assign a port to `hostname`, which keeps it until [`release`](Self::release)
+    ///
+    /// `reserved` are ports configured for other backends, which are never assigned.
+    pub fn allocate(
+        &self,
+        hostname: &str,
+        config: &PortAllocationConfig,
+        reserved: &HashSet<u16>,
+    ) -> anyhow::Result<u16> {
+        self.allocate_with(hostname, config, reserved, Instant::now(), can_bind)
+    }
+
+    fn allocate_with(
+        &self,
+        hostname: &str,
+        config: &PortAllocationConfig,
+        reserved: &HashSet<u16>,
+        now: Instant,
+        free: impl Fn(u16) -> bool,
+    ) -> anyhow::Result<u16> {
+        let mut ports = self.ports.lock();
+        if let Some(&port) = ports.assigned.get(hostname) {
+            return Ok(port);
+        }
+        let cooldown = Duration::from_secs(config.cooldown_secs);
+        ports.released.retain(|_, released| now.duration_since(*released) < cooldown);
+
+        let range = config.range_start..=config.range_end;
+        let taken: HashSet<u16> = ports.assigned.values().copied().collect();
+        let usable = |port: u16| !taken.contains(&port) && !reserved.contains(&port) && free(port);
+
+        if config.reuse {
+            if let Some(&port) = ports.previous.get(hostname) {
+                if range.contains(&port) && usable(port) {
+                    return Ok(ports.assign(hostname, port));
+                }
+            }
+        }
+
+        let len = u32::from(config.range_end - config.range_start) + 1;
+        let offset = if range.contains(&ports.cursor) { ports.cursor - config.range_start } else { 0 };
+        for i in 0..len {
+            let port = config.range_start + ((u32::from(offset) + i) % len) as u16;
+            if ports.released.contains_key(&port) || !usable(port) {
+                continue;
+            }
+            ports.cursor = port.wrapping_add(1);
+            return Ok(ports.assign(hostname, port));
+        }
+        anyhow::bail!(
+            "No free port in {}-{} for backend '{}'",
+            config.range_start,
+            config.range_end,
+            hostname
+        )
+    }
+
+    /// Port assigned to a running backend
+    pub fn assigned(&self, hostname: &str) -> Option<u16> {
+        self.ports.lock().assigned.get(hostname).copied()
+    }
+
+    /// Return a backend's port once it has stopped
+    pub fn release(&self, hostname: &str) {
+        self.release_at(hostname, Instant::now());
+    }
+
+    fn release_at(&self, hostname: &str, now: Instant) {
+        let mut ports = self.ports.lock();
+        if let Some(port) = ports.assigned.remove(hostname) {
+            ports.previous.insert(hostname.to_string(), port);
+            ports.released.insert(port, now);
+        }
+    }
+}
+
+/// Whether a backend could listen on `port`
+///
+/// std's listener sets SO_REUSEADDR on Unix, so ports with connections in
+/// TIME_WAIT count as free, as they are for backends that set it too.
+fn can_bind(port: u16) -> bool {
+    TcpListener::bind((Ipv4Addr::LOCALHOST, port)).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(reuse: bool) -> PortAllocationConfig {
+        PortAllocationConfig {
+            range_start: 20000,
+            range_end: 20009,
+            reuse,
+            cooldown_secs: 60,
+        }
+    }
+
+    #[test]
+    fn test_reuse_previous_port() {
+        let ports = PortAllocator::new();
+        let config = config(true);
+        let now = Instant::now();
+        let reserved = HashSet::new();
+
+        let first = ports.allocate_with("app", &config, &reserved, now, |_| true).unwrap();
+        assert_eq!(first, 20000);
+        // Allocating again while running keeps the port
+        assert_eq!(ports.allocate_with("app", &config, &reserved, now, |_| true).unwrap(), first);
+        assert_eq!(ports.assigned("app"), Some(first));
+
+        for _ in 0..500 {
+            ports.release_at("app", now);
+            assert_eq!(ports.allocate_with("app", &config, &reserved, now, |_| true).unwrap(), first);
+        }
+        // Another backend doesn't get the port while it cools down
+        ports.release_at("app", now);
+        assert_eq!(ports.allocate_with("other", &config, &reserved, now, |_| true).unwrap(), 20001);
+        // Once it can't be bound, the backend moves on
+        let port = ports.allocate_with("app", &config, &reserved, now, |port| port != first).unwrap();
+        assert_eq!(port, 20002);
+    }
+
+    #[test]
+    fn test_rotate_through_range() {
+        let ports = PortAllocator::new();
+        let config = config(false);
+        let start = Instant::now();
+        let reserved = HashSet::from([20003]);
+
+        // Without reuse, ports rotate and no port is handed out during its cooldown
+        let mut last_used: HashMap<u16, Instant> = HashMap::new();
+        for cycle in 0..300u64 {
+            let now = start + Duration::from_secs(cycle * 10);
+            let port = ports.allocate_with("app", &config, &reserved, now, |_| true).unwrap();
+            assert_ne!(port, 20003);
+            if let Some(&released) = last_used.get(&port) {
+                assert!(now.duration_since(released) >= Duration::from_secs(60), "port {} reused early", port);
+            }
+            ports.release_at("app", now);
+            last_used.insert(port, now);
+        }
+
+        // A range in cooldown is exhausted
+        let now = start + Duration::from_secs(100_000);
+        for hostname in ["a", "b", "c", "d", "e", "f", "g", "h", "i"] {
+            ports.allocate_with(hostname, &config, &reserved, now, |_| true).unwrap();
+        }
+        let err = ports.allocate_with("j", &config, &reserved, now, |_| true).unwrap_err();
+        assert!(err.to_string().contains("No free port in 20000-20009"));
+    }
+
+    #[test]
+    fn test_can_bind() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(!can_bind(port));
+        drop(listener);
+        assert!(can_bind(port));
+    }
+}
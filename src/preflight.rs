@@ -0,0 +1,472 @@
+//! Pre-flight checks for ACME domain validation
+//!
+//! When a challenge fails, the CA's error is often just "connection refused"
+//! or "unauthorized". These checks test each prerequisite separately (the
+//! domain resolves, CAA records permit the CA, the challenge port reaches us)
+//! so a failure can be pinned to one step.
+//!
+//! CAA records aren't exposed by the system resolver, so they are queried
+//! with a minimal DNS client against the first nameserver in
+//! `/etc/resolv.conf`.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tracing::warn;
+
+/// CAA issuer domain used by Let's Encrypt
+pub const LETS_ENCRYPT_CAA_IDENTITY: &str = "letsencrypt.org";
+
+/// Timeout for each DNS query and connection attempt
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+const TYPE_CAA: u16 = 257;
+const CLASS_IN: u16 = 1;
+
+/// CAA tags defined by RFC 8659 and its extensions
+const KNOWN_CAA_TAGS: &[&str] = &["issue", "issuewild", "iodef", "issuemail", "contactemail", "contactphone"];
+
+/// A CAA resource record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaaRecord {
+    pub critical: bool,
+    pub tag: String,
+    pub value: String,
+}
+
+/// CAA issuer identity for a directory URL, if the CA is known
+pub fn caa_identity(directory_url: &str) -> Option<&'static str> {
+    directory_url
+        .contains("letsencrypt.org")
+        .then_some(LETS_ENCRYPT_CAA_IDENTITY)
+}
+
+/// Resolve a domain to its addresses
+pub async fn resolve(domain: &str) -> Result<Vec<IpAddr>, String> {
+    let lookup = tokio::time::timeout(TIMEOUT, tokio::net::lookup_host((domain, 0)))
+        .await
+        .map_err(|_| format!("DNS lookup for {} timed out", domain))?
+        .map_err(|e| format!("{} does not resolve: {}", domain, e))?;
+
+    let mut addrs: Vec<IpAddr> = lookup.map(|addr| addr.ip()).collect();
+    addrs.sort();
+    addrs.dedup();
+    if addrs.is_empty() {
+        return Err(format!("{} has no A or AAAA records", domain));
+    }
+    Ok(addrs)
+}
+
+/// Check that every address a domain resolves to is one of `expected`
+///
+/// An unexpected address means the CA may validate against another server.
+/// An empty `expected` list skips the check.
+pub fn check_addresses(domain: &str, addrs: &[IpAddr], expected: &[IpAddr]) -> Result<(), String> {
+    let unexpected: Vec<String> = addrs
+        .iter()
+        .filter(|ip| !expected.is_empty() && !expected.contains(ip))
+        .map(ToString::to_string)
+        .collect();
+    if unexpected.is_empty() {
+        return Ok(());
+    }
+    let expected: Vec<String> = expected.iter().map(ToString::to_string).collect();
+    Err(format!(
+        "{} resolves to {}, which is not this server (expected {}); update its A/AAAA records",
+        domain,
+        unexpected.join(", "),
+        expected.join(", ")
+    ))
+}
+
+/// Judge a CAA record set returned by `lookup_caa`, describing the outcome
+pub fn caa_verdict(domain: &str, at: &str, records: &[CaaRecord], identity: &str) -> Result<String, String> {
+    if records.is_empty() {
+        Ok("no CAA records, any CA may issue".to_string())
+    } else if caa_permits(records, domain, identity) {
+        Ok(format!("records at {} permit {}", at, identity))
+    } else {
+        Err(format!(
+            "CAA records at {} do not permit {}; add `{} CAA 0 issue \"{}\"`",
+            at, identity, at, identity
+        ))
+    }
+}
+
+/// Check a domain's DNS and CAA records before ordering a certificate
+///
+/// CAA is only checked when the CA's identity is known. A failed CAA lookup
+/// is logged rather than returned, since the CA's own resolver may succeed.
+pub async fn check_domain(domain: &str, expected_ips: &[IpAddr], caa_identity: Option<&str>) -> Result<(), String> {
+    // Wildcards have no addresses of their own
+    if !domain.starts_with("*.") {
+        let addrs = resolve(domain).await?;
+        check_addresses(domain, &addrs, expected_ips)?;
+    }
+
+    if let Some(identity) = caa_identity {
+        check_caa(domain, identity).await?;
+    }
+    Ok(())
+}
+
+/// Check that CAA records permit `identity` to issue for a domain
+///
+/// A failed lookup is logged rather than returned, as for `check_domain`.
+pub async fn check_caa(domain: &str, identity: &str) -> Result<(), String> {
+    match lookup_caa(domain).await {
+        Ok((at, records)) => {
+            caa_verdict(domain, &at, &records, identity)?;
+        }
+        Err(e) => warn!(domain, error = %e, "CAA lookup failed, skipping CAA check"),
+    }
+    Ok(())
+}
+
+/// Find the CAA record set that applies to a domain
+///
+/// Per RFC 8659 the closest ancestor with CAA records is authoritative, so
+/// the lookup climbs from the domain towards (but not including) the TLD.
+/// Returns the domain the records were found at, or an empty set if none apply.
+pub async fn lookup_caa(domain: &str) -> io::Result<(String, Vec<CaaRecord>)> {
+    let nameserver = system_nameserver();
+    let mut name = domain.trim_start_matches("*.").trim_end_matches('.');
+    loop {
+        let records = query_caa(nameserver, name).await?;
+        if !records.is_empty() {
+            return Ok((name.to_string(), records));
+        }
+        match name.split_once('.') {
+            Some((_, parent)) if parent.contains('.') => name = parent,
+            _ => return Ok((domain.to_string(), Vec::new())),
+        }
+    }
+}
+
+/// Whether a CAA record set allows `identity` to issue for `domain`
+pub fn caa_permits(records: &[CaaRecord], domain: &str, identity: &str) -> bool {
+    // An unknown critical property means the CA must not issue
+    if records
+        .iter()
+        .any(|r| r.critical && !KNOWN_CAA_TAGS.contains(&r.tag.to_ascii_lowercase().as_str()))
+    {
+        return false;
+    }
+
+    let has_tag = |tag: &str| records.iter().any(|r| r.tag.eq_ignore_ascii_case(tag));
+    let tag = if domain.starts_with("*.") && has_tag("issuewild") {
+        "issuewild"
+    } else {
+        "issue"
+    };
+    if !has_tag(tag) {
+        return true;
+    }
+
+    records
+        .iter()
+        .filter(|r| r.tag.eq_ignore_ascii_case(tag))
+        .any(|r| {
+            let issuer = r.value.split(';').next().unwrap_or("").trim();
+            issuer.eq_ignore_ascii_case(identity)
+        })
+}
+
+/// Check that a TCP connection to `addr` succeeds
+pub async fn check_port(addr: SocketAddr) -> Result<(), String> {
+    match tokio::time::timeout(TIMEOUT, TcpStream::connect(addr)).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => Err(format!("cannot connect to {}: {}", addr, e)),
+        Err(_) => Err(format!("connection to {} timed out", addr)),
+    }
+}
+
+/// Fetch an HTTP-01 challenge path from `addr` and check the response body
+///
+/// Verifies that requests for `domain` on `addr` actually reach this process,
+/// not just some server listening on the port.
+pub async fn probe_http01(addr: SocketAddr, domain: &str, token: &str, expected: &str) -> Result<(), String> {
+    let fetch = async {
+        let mut stream = TcpStream::connect(addr).await?;
+        let request = format!(
+            "GET /.well-known/acme-challenge/{} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            token, domain
+        );
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        io::Result::Ok(String::from_utf8_lossy(&response).into_owned())
+    };
+
+    let response = match tokio::time::timeout(TIMEOUT, fetch).await {
+        Ok(Ok(response)) => response,
+        Ok(Err(e)) => return Err(format!("cannot fetch challenge from {}: {}", addr, e)),
+        Err(_) => return Err(format!("fetching challenge from {} timed out", addr)),
+    };
+
+    let status = response.lines().next().unwrap_or("");
+    let body = response.split_once("\r\n\r\n").map(|(_, b)| b).unwrap_or("");
+    if body.trim() == expected {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} answered '{}' but not with this server's challenge response; is the port forwarded to spawngate?",
+            addr, status
+        ))
+    }
+}
+
+/// First nameserver from /etc/resolv.conf, defaulting to localhost like libc
+fn system_nameserver() -> SocketAddr {
+    let ip = std::fs::read_to_string("/etc/resolv.conf")
+        .ok()
+        .and_then(|conf| parse_nameserver(&conf))
+        .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    SocketAddr::new(ip, 53)
+}
+
+fn parse_nameserver(conf: &str) -> Option<IpAddr> {
+    conf.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("nameserver") => fields.next()?.parse().ok(),
+            _ => None,
+        }
+    })
+}
+
+/// Query CAA records for exactly `name`, retrying over TCP if truncated
+async fn query_caa(nameserver: SocketAddr, name: &str) -> io::Result<Vec<CaaRecord>> {
+    let random = uuid::Uuid::new_v4();
+    let id = u16::from_be_bytes([random.as_bytes()[0], random.as_bytes()[1]]);
+    let query = build_query(id, name, TYPE_CAA)?;
+
+    let bind: SocketAddr = if nameserver.is_ipv4() {
+        "0.0.0.0:0".parse().expect("valid address")
+    } else {
+        "[::]:0".parse().expect("valid address")
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(nameserver).await?;
+    socket.send(&query).await?;
+
+    let mut buf = vec![0u8; 4096];
+    let len = tokio::time::timeout(TIMEOUT, socket.recv(&mut buf))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, format!("DNS query to {} timed out", nameserver)))??;
+
+    match parse_caa_response(id, &buf[..len]) {
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => query_caa_tcp(nameserver, id, &query).await,
+        result => result,
+    }
+}
+
+async fn query_caa_tcp(nameserver: SocketAddr, id: u16, query: &[u8]) -> io::Result<Vec<CaaRecord>> {
+    let exchange = async {
+        let mut stream = TcpStream::connect(nameserver).await?;
+        stream.write_all(&(query.len() as u16).to_be_bytes()).await?;
+        stream.write_all(query).await?;
+        let len = stream.read_u16().await? as usize;
+        let mut buf = vec![0u8; len];
+        stream.read_exact(&mut buf).await?;
+        io::Result::Ok(buf)
+    };
+    let response = tokio::time::timeout(TIMEOUT, exchange)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, format!("DNS query to {} timed out", nameserver)))??;
+    parse_caa_response(id, &response)
+}
+
+fn build_query(id: u16, name: &str, qtype: u16) -> io::Result<Vec<u8>> {
+    let mut query = Vec::with_capacity(32 + name.len());
+    query.extend_from_slice(&id.to_be_bytes());
+    query.extend_from_slice(&[0x01, 0x00]); // Recursion desired
+    query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]); // One question
+    for label in name.split('.').filter(|l| !l.is_empty()) {
+        if label.len() > 63 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("DNS label too long in '{}'", name)));
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&qtype.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+fn malformed() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "malformed DNS response")
+}
+
+/// Parse CAA answers from a DNS response
+///
+/// NXDOMAIN yields an empty set. A truncated response is reported as
+/// `ErrorKind::Interrupted` so the caller can retry over TCP.
+fn parse_caa_response(id: u16, msg: &[u8]) -> io::Result<Vec<CaaRecord>> {
+    if msg.len() < 12 || u16::from_be_bytes([msg[0], msg[1]]) != id {
+        return Err(malformed());
+    }
+    if msg[2] & 0x02 != 0 {
+        return Err(io::Error::new(io::ErrorKind::Interrupted, "DNS response truncated"));
+    }
+    match msg[3] & 0x0f {
+        0 => {}
+        3 => return Ok(Vec::new()),
+        rcode => {
+            return Err(io::Error::other(format!("DNS server returned error code {}", rcode)));
+        }
+    }
+
+    let questions = u16::from_be_bytes([msg[4], msg[5]]);
+    let answers = u16::from_be_bytes([msg[6], msg[7]]);
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = skip_name(msg, pos)? + 4;
+    }
+
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        pos = skip_name(msg, pos)?;
+        let header = msg.get(pos..pos + 10).ok_or_else(malformed)?;
+        let rtype = u16::from_be_bytes([header[0], header[1]]);
+        let rdlength = u16::from_be_bytes([header[8], header[9]]) as usize;
+        pos += 10;
+        let rdata = msg.get(pos..pos + rdlength).ok_or_else(malformed)?;
+        pos += rdlength;
+
+        if rtype != TYPE_CAA {
+            continue;
+        }
+        let (&flags, rest) = rdata.split_first().ok_or_else(malformed)?;
+        let (&tag_len, rest) = rest.split_first().ok_or_else(malformed)?;
+        if rest.len() < tag_len as usize {
+            return Err(malformed());
+        }
+        let (tag, value) = rest.split_at(tag_len as usize);
+        records.push(CaaRecord {
+            critical: flags & 0x80 != 0,
+            tag: String::from_utf8_lossy(tag).into_owned(),
+            value: String::from_utf8_lossy(value).into_owned(),
+        });
+    }
+    Ok(records)
+}
+
+/// Return the offset just past a (possibly compressed) name
+fn skip_name(msg: &[u8], mut pos: usize) -> io::Result<usize> {
+    loop {
+        let len = *msg.get(pos).ok_or_else(malformed)?;
+        match len {
+            0 => return Ok(pos + 1),
+            l if l & 0xc0 == 0xc0 => return Ok(pos + 2),
+            l => pos += 1 + l as usize,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caa(tag: &str, value: &str) -> CaaRecord {
+        CaaRecord {
+            critical: false,
+            tag: tag.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_caa_permits() {
+        let le = LETS_ENCRYPT_CAA_IDENTITY;
+        assert!(caa_permits(&[], "example.com", le));
+        assert!(caa_permits(&[caa("iodef", "mailto:ops@example.com")], "example.com", le));
+        assert!(caa_permits(&[caa("issue", "letsencrypt.org")], "example.com", le));
+        assert!(caa_permits(&[caa("issue", "LetsEncrypt.org; validationmethods=http-01")], "example.com", le));
+        assert!(!caa_permits(&[caa("issue", "sectigo.com")], "example.com", le));
+        assert!(!caa_permits(&[caa("issue", ";")], "example.com", le));
+
+        // issuewild governs wildcards only when present
+        let records = [caa("issue", "letsencrypt.org"), caa("issuewild", "sectigo.com")];
+        assert!(caa_permits(&records, "example.com", le));
+        assert!(!caa_permits(&records, "*.example.com", le));
+        assert!(caa_permits(&records[..1], "*.example.com", le));
+
+        // Unknown critical tags forbid issuance
+        let mut critical = caa("future", "x");
+        critical.critical = true;
+        assert!(!caa_permits(&[caa("issue", "letsencrypt.org"), critical], "example.com", le));
+    }
+
+    #[test]
+    fn test_check_addresses() {
+        let ours: IpAddr = "203.0.113.10".parse().unwrap();
+        let other: IpAddr = "198.51.100.7".parse().unwrap();
+
+        assert!(check_addresses("example.com", &[ours, other], &[]).is_ok());
+        assert!(check_addresses("example.com", &[ours], &[ours, other]).is_ok());
+        let err = check_addresses("example.com", &[ours, other], &[ours]).unwrap_err();
+        assert!(err.contains("198.51.100.7"), "{}", err);
+        assert!(!err.contains("203.0.113.10,"), "{}", err);
+    }
+
+    #[test]
+    fn test_caa_verdict() {
+        let le = LETS_ENCRYPT_CAA_IDENTITY;
+        assert!(caa_verdict("example.com", "example.com", &[], le).is_ok());
+        let err = caa_verdict("www.example.com", "example.com", &[caa("issue", "sectigo.com")], le).unwrap_err();
+        assert!(err.contains("example.com CAA 0 issue \"letsencrypt.org\""), "{}", err);
+    }
+
+    #[test]
+    fn test_caa_identity() {
+        assert_eq!(
+            caa_identity("https://acme-staging-v02.api.letsencrypt.org/directory"),
+            Some(LETS_ENCRYPT_CAA_IDENTITY)
+        );
+        assert_eq!(caa_identity("https://acme.example.com/directory"), None);
+    }
+
+    #[test]
+    fn test_parse_nameserver() {
+        let conf = "# comment\nsearch example.com\nnameserver 10.0.0.53\nnameserver 1.1.1.1\n";
+        assert_eq!(parse_nameserver(conf), Some("10.0.0.53".parse().unwrap()));
+        assert_eq!(parse_nameserver("search example.com\n"), None);
+    }
+
+    #[test]
+    fn test_parse_caa_response() {
+        let mut msg = build_query(0x1234, "example.com", TYPE_CAA).unwrap();
+        msg[2] = 0x81; // Response, recursion desired
+        msg[3] = 0x80; // Recursion available, NOERROR
+        msg[7] = 1; // One answer
+
+        // Answer: pointer to the question name, CAA IN, TTL, rdata
+        let rdata = [&[0u8, 5][..], b"issue", b"letsencrypt.org"].concat();
+        msg.extend_from_slice(&[0xc0, 0x0c]);
+        msg.extend_from_slice(&TYPE_CAA.to_be_bytes());
+        msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+        msg.extend_from_slice(&300u32.to_be_bytes());
+        msg.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        msg.extend_from_slice(&rdata);
+
+        let records = parse_caa_response(0x1234, &msg).unwrap();
+        assert_eq!(records, vec![caa("issue", "letsencrypt.org")]);
+
+        // Mismatched id, truncation and NXDOMAIN
+        assert!(parse_caa_response(0x4321, &msg).is_err());
+        let mut truncated = msg.clone();
+        truncated[2] |= 0x02;
+        assert_eq!(
+            parse_caa_response(0x1234, &truncated).unwrap_err().kind(),
+            io::ErrorKind::Interrupted
+        );
+        let mut nxdomain = msg[..msg.len() - rdata.len() - 12].to_vec();
+        nxdomain[3] = 0x83;
+        nxdomain[7] = 0;
+        assert!(parse_caa_response(0x1234, &nxdomain).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,212 @@
+//! Priority classes for requests waiting on a cold start
+//!
+//! A backend's `priority_rules` put each request into a class, `high`,
+//! `normal` (the default) or `low`, e.g. by path or by API key header. When
+//! the backend's cold-start queue is full (`max_queue_depth`), a request
+//! takes the place of the most recent waiting request of the lowest class
+//! below its own, which is answered with a 503 (`COLD_START_QUEUE_FULL`)
+//! right away. Low-priority background requests are thus shed first, and
+//! high-priority ones are only turned away when the queue is full of them.
+//!
+//! Queued, admitted, shed and preempted requests are counted per class and
+//! reported with the backend's status.
+
+use crate::config::{PriorityClass, PriorityRule};
+use crate::routing::headers_match;
+use hyper::header::HeaderMap;
+use hyper::Method;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use tokio::sync::oneshot;
+
+/// Class of the first rule a request matches, `normal` if none
+pub fn classify(rules: &[PriorityRule], method: &Method, path: &str, headers: &HeaderMap) -> PriorityClass {
+    rules
+        .iter()
+        .find(|rule| {
+            rule.matches_path(path)
+                && headers_match(&rule.headers, headers)
+                && (rule.methods.is_empty() || rule.methods.iter().any(|m| m == method.as_str()))
+        })
+        .map_or(PriorityClass::Normal, |rule| rule.class)
+}
+
+/// Requests of one class in a backend's queue
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ClassStats {
+    /// Waiting now
+    pub queued: usize,
+    /// Let into the queue since startup
+    pub admitted: u64,
+    /// Turned away because the queue was full of requests of the same or a higher class
+    pub shed: u64,
+    /// Dropped from the queue for a higher-class request
+    pub preempted: u64,
+}
+
+/// Requests waiting for one backend to become ready
+#[derive(Default)]
+pub struct WaitQueue {
+    state: Mutex<QueueState>,
+}
+
+#[derive(Default)]
+struct QueueState {
+    next: u64,
+    /// Class of each waiting request and the signal that it was preempted, by arrival
+    waiting: BTreeMap<u64, (PriorityClass, oneshot::Sender<()>)>,
+    stats: HashMap<PriorityClass, ClassStats>,
+}
+
+impl WaitQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a request until the returned guard is dropped
+    ///
+    /// With `limit` requests waiting already, the newest one of the lowest
+    /// class below `class` is preempted to make room. Fails with the current
+    /// depth if there is none.
+    pub fn join(self: &Arc<Self>, class: PriorityClass, limit: Option<usize>) -> Result<QueuedRequest, usize> {
+        let mut state = self.state.lock();
+        if limit.is_some_and(|limit| state.waiting.len() >= limit) {
+            let victim = state
+                .waiting
+                .iter()
+                .filter(|(_, (waiting, _))| *waiting < class)
+                .min_by_key(|(ticket, (waiting, _))| (*waiting, std::cmp::Reverse(**ticket)))
+                .map(|(ticket, _)| *ticket);
+            let Some((victim, preempt)) = victim.and_then(|ticket| state.waiting.remove(&ticket)) else {
+                state.stats.entry(class).or_default().shed += 1;
+                return Err(state.waiting.len());
+            };
+            let _ = preempt.send(());
+            state.stats.entry(victim).or_default().preempted += 1;
+        }
+
+        let ticket = state.next;
+        state.next += 1;
+        let (preempt, preempted) = oneshot::channel();
+        state.waiting.insert(ticket, (class, preempt));
+        state.stats.entry(class).or_default().admitted += 1;
+        Ok(QueuedRequest {
+            queue: Arc::clone(self),
+            ticket,
+            preempted,
+        })
+    }
+
+    /// Requests waiting now
+    pub fn len(&self) -> usize {
+        self.state.lock().waiting.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Counts of every class
+    pub fn stats(&self) -> BTreeMap<PriorityClass, ClassStats> {
+        let state = self.state.lock();
+        let mut stats: BTreeMap<PriorityClass, ClassStats> = PriorityClass::ALL
+            .iter()
+            .map(|class| (*class, state.stats.get(class).copied().unwrap_or_default()))
+            .collect();
+        for (class, _) in state.waiting.values() {
+            stats.entry(*class).or_default().queued += 1;
+        }
+        stats
+    }
+}
+
+/// A request's place in a [`WaitQueue`]
+pub struct QueuedRequest {
+    queue: Arc<WaitQueue>,
+    ticket: u64,
+    preempted: oneshot::Receiver<()>,
+}
+
+impl QueuedRequest {
+    /// Resolves once a higher-class request has taken this one's place
+    pub async fn preempted(&mut self) {
+        if (&mut self.preempted).await.is_err() {
+            // Only preemption drops the sender, and it sends first
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+impl Drop for QueuedRequest {
+    fn drop(&mut self) {
+        self.queue.state.lock().waiting.remove(&self.ticket);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+
+    fn rule(class: PriorityClass, path_prefix: Option<&str>, headers: &[(&str, &str)]) -> PriorityRule {
+        PriorityRule {
+            class,
+            path_prefix: path_prefix.map(String::from),
+            headers: headers.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            methods: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_classify() {
+        let rules = vec![
+            rule(PriorityClass::High, None, &[("x-api-key", "premium")]),
+            rule(PriorityClass::Low, Some("/jobs"), &[]),
+        ];
+        let mut headers = HeaderMap::new();
+        assert_eq!(classify(&rules, &Method::GET, "/", &headers), PriorityClass::Normal);
+        assert_eq!(classify(&rules, &Method::POST, "/jobs/rebuild", &headers), PriorityClass::Low);
+        assert_eq!(classify(&rules, &Method::GET, "/jobsite", &headers), PriorityClass::Normal);
+
+        // First match wins
+        headers.insert("x-api-key", "premium".parse().unwrap());
+        assert_eq!(classify(&rules, &Method::POST, "/jobs/rebuild", &headers), PriorityClass::High);
+        assert_eq!(classify(&[], &Method::GET, "/", &headers), PriorityClass::Normal);
+    }
+
+    #[test]
+    fn test_higher_classes_preempt_lower_ones() {
+        let queue = Arc::new(WaitQueue::new());
+        let mut low = queue.join(PriorityClass::Low, Some(3)).unwrap();
+        let mut newer_low = queue.join(PriorityClass::Low, Some(3)).unwrap();
+        let mut normal = queue.join(PriorityClass::Normal, Some(3)).unwrap();
+
+        // Full: low requests are shed, higher ones take the newest low request's place
+        assert_eq!(queue.join(PriorityClass::Low, Some(3)).err(), Some(3));
+        let high = queue.join(PriorityClass::High, Some(3)).unwrap();
+        assert!(newer_low.preempted().now_or_never().is_some());
+        assert!(low.preempted().now_or_never().is_none());
+        let mut newer_normal = queue.join(PriorityClass::Normal, Some(3)).unwrap();
+        assert!(low.preempted().now_or_never().is_some());
+        drop((low, newer_low));
+
+        // Only lower classes are preempted
+        assert_eq!(queue.join(PriorityClass::Normal, Some(3)).err(), Some(3));
+        let _high2 = queue.join(PriorityClass::High, Some(3)).unwrap();
+        assert!(newer_normal.preempted().now_or_never().is_some());
+        assert!(normal.preempted().now_or_never().is_none());
+        drop(newer_normal);
+        assert_eq!(queue.len(), 3);
+
+        let stats = queue.stats();
+        assert_eq!(stats[&PriorityClass::High], ClassStats { queued: 2, admitted: 2, shed: 0, preempted: 0 });
+        assert_eq!(stats[&PriorityClass::Normal], ClassStats { queued: 1, admitted: 2, shed: 1, preempted: 1 });
+        assert_eq!(stats[&PriorityClass::Low], ClassStats { queued: 0, admitted: 2, shed: 1, preempted: 2 });
+
+        drop((high, normal));
+        assert_eq!(queue.len(), 1);
+        assert!(queue.join(PriorityClass::Low, None).is_ok());
+    }
+}
@@ -1,21 +1,63 @@
-use crate::config::{BackendConfig, BackendDefaults, BackendType, Config};
-use crate::docker::{DockerManager, SharedDockerManager};
-use dashmap::DashMap;
+use crate::cgroup::{self, Cgroup};
+use crate::cloud::CloudVmSpawner;
+use crate::config::{
+    BackendConfig, BackendDefaults, BackendType, Config, ContainerEngine, ContainerExitPolicy, PriorityClass,
+    QuotaConfig, WafMode,
+};
+use crate::configvars::ConfigVars;
+#[cfg(unix)]
+use crate::credentials::Credentials;
+use crate::docker::{ContainerExit, ContainerRuntime, ContainerStats, DockerManager, SharedContainerRuntime};
+use crate::experiments;
+use crate::faults::FaultInjector;
+use crate::firecracker::FirecrackerSpawner;
+use crate::hashicorp::NomadSpawner;
+use crate::idle::{IdlePredictor, IdleTimeoutStatus};
+use crate::logs::{self, BackendLogs, LogLine, LogStream};
+use crate::podman::PodmanRuntime;
+use crate::pool::{self, BackendProtocol};
+use crate::ports::PortAllocator;
+use crate::priority::{self, ClassStats, QueuedRequest, WaitQueue};
+use crate::promotion::{self, PromotionHistory, PromotionRecord, PromotionReview};
+use crate::quota::{QuotaStatus, QuotaTracker};
+use crate::rewrite::Rewriter;
+use crate::routing::{self, Route};
+use crate::savings::{BackendSavings, SavingsReport, UptimeLedger};
+use crate::selector::Selector;
+use crate::signing;
+use crate::ssh::SshTunnelSpawner;
+use dashmap::{DashMap, DashSet};
+use futures::future::{join_all, BoxFuture, FutureExt, Shared};
+use hyper::header::HeaderMap;
+use hyper::http::request::Parts;
+use hyper::Method;
 use parking_lot::{Mutex, RwLock};
-use std::collections::HashMap;
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::process::{Child, Command};
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, watch};
+use tokio::time::Instant;
 use tracing::{debug, error, info, warn};
 
 /// Interval for polling drain status during shutdown (in milliseconds)
 const DRAIN_POLL_INTERVAL_MS: u64 = 50;
 
+/// Spawn-to-ready times kept per backend for estimating cold starts
+const SPAWN_TIME_SAMPLES: usize = 20;
+
+/// How long sampling a container's resource usage may take
+const STATS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Outcome of a spawn, shared by every caller waiting on it
+type SharedSpawn = Shared<BoxFuture<'static, Result<(), Arc<anyhow::Error>>>>;
+
 /// State of a backend process
 #[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
 #[serde(rename_all = "lowercase")]
@@ -32,17 +74,102 @@ pub enum BackendState {
     Stopping,
 }
 
-/// Handle to a running backend (local process or Docker container)
+/// Readiness gate a starting backend is still waiting on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PendingGate {
+    /// A backend listed in `depends_on` is not ready
+    Dependency(String),
+    /// The marker file does not exist yet
+    MarkerFile(String),
+    /// The backend has not been approved via the admin API
+    ManualApproval,
+}
+
+impl fmt::Display for PendingGate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PendingGate::Dependency(hostname) => write!(f, "dependency {}", hostname),
+            PendingGate::MarkerFile(path) => write!(f, "marker file {}", path),
+            PendingGate::ManualApproval => write!(f, "manual approval"),
+        }
+    }
+}
+
+/// Launches backends with `type = "custom"` in their own way (microVMs,
+/// cloud instances, ...)
+///
+/// Register spawners with [`ProcessManager::register_spawner`]; a backend's
+/// `spawner` field names the one that launches it. Everything else stays with
+/// the proxy: routing, health checks, pooling, idle shutdown and restarts. The
+/// instance must be reachable on `127.0.0.1:{port}` like any other backend.
+pub trait Spawner: Send + Sync {
+    /// Start an instance of the backend, returning an ID that identifies it to `stop`
+    ///
+    /// `env` holds the variables the proxy injects (`PORT`, the ready
+    /// callback URL, instance metadata); they take precedence over `config.env`.
+    fn start<'a>(
+        &'a self,
+        hostname: &'a str,
+        config: &'a BackendConfig,
+        env: &'a [(String, String)],
+    ) -> BoxFuture<'a, anyhow::Result<String>>;
+
+    /// Stop an instance, forcing it down once `grace_period` has passed
+    fn stop<'a>(&'a self, hostname: &'a str, id: &'a str, grace_period: Duration) -> BoxFuture<'a, ()>;
+}
+
+/// Answers health checks in place of requests to the backends' health paths
+///
+/// Set with [`ProcessManager::set_health_probe`], e.g. to run the process
+/// manager against simulated backends on a paused tokio clock.
+pub trait HealthProbe: Send + Sync {
+    /// Whether the backend is healthy; `url` is its health endpoint
+    fn check<'a>(&'a self, hostname: &'a str, url: &'a str) -> BoxFuture<'a, anyhow::Result<bool>>;
+}
+
+/// Handle to a running backend (local process, Docker container or custom spawn)
+///
+/// Firecracker microVMs, Nomad jobs, cloud VMs and SSH tunnels are started by built-in
+/// spawners and use `Custom`.
 pub enum ProcessHandle {
-    /// Local process spawned directly
-    Local(Child),
-    /// Docker container
+    /// Local process spawned directly, in a cgroup of its own if it has resource limits
+    Local {
+        child: Child,
+        cgroup: Option<Arc<Cgroup>>,
+    },
+    /// Container of a Docker backend, run by Docker or Podman
     Docker {
         container_id: String,
-        docker: SharedDockerManager,
+        runtime: SharedContainerRuntime,
         /// Sender to stop log streaming when container is stopped
         log_shutdown: Option<tokio::sync::watch::Sender<bool>>,
     },
+    /// Instance started by a registered spawner
+    Custom {
+        id: String,
+        spawner: Arc<dyn Spawner>,
+    },
+}
+
+/// Identity of one spawned instance of a backend
+#[derive(Debug, Clone)]
+pub struct InstanceMetadata {
+    /// Unique ID for this instance
+    pub id: String,
+    /// How many times this backend was spawned before this instance (starts at 0)
+    pub index: u64,
+    /// Token that authorizes this instance's ready callback only
+    pub token: String,
+}
+
+impl InstanceMetadata {
+    fn new(index: u64) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            index,
+            token: uuid::Uuid::new_v4().simple().to_string(),
+        }
+    }
 }
 
 /// Information about a running backend
@@ -51,6 +178,8 @@ pub struct BackendProcess {
     handle: ProcessHandle,
     /// Current state of the backend
     state: BackendState,
+    /// When the instance was spawned
+    started: Instant,
     /// Last time traffic was received
     last_activity: Instant,
     /// Channel to notify when state changes to Ready
@@ -59,11 +188,44 @@ pub struct BackendProcess {
     in_flight: Arc<AtomicUsize>,
     /// Consecutive health check failures
     consecutive_failures: u32,
+    /// Metadata injected into this instance's environment
+    instance: InstanceMetadata,
 }
 
 /// Shared reference to backend defaults (for hot reload support)
 pub type SharedDefaults = Arc<RwLock<BackendDefaults>>;
 
+/// A change in a backend's lifecycle, delivered to hooks registered with
+/// [`ProcessManager::on_event`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackendEvent {
+    /// The process or container was started and is waiting to become ready
+    Started { hostname: String },
+    /// The backend passed its health check and readiness gates, or recovered
+    Ready { hostname: String },
+    /// The backend failed its health checks
+    Unhealthy { hostname: String },
+    /// The backend was stopped
+    Stopped { hostname: String },
+    /// Starting the backend failed
+    StartFailed { hostname: String, error: String },
+}
+
+impl BackendEvent {
+    pub fn hostname(&self) -> &str {
+        match self {
+            BackendEvent::Started { hostname }
+            | BackendEvent::Ready { hostname }
+            | BackendEvent::Unhealthy { hostname }
+            | BackendEvent::Stopped { hostname }
+            | BackendEvent::StartFailed { hostname, .. } => hostname,
+        }
+    }
+}
+
+/// Callback for backend lifecycle events
+pub type EventHook = Arc<dyn Fn(&BackendEvent) + Send + Sync>;
+
 /// Manages all backend processes.
 ///
 /// # Usage
@@ -94,8 +256,64 @@ pub struct ProcessManager {
     defaults: SharedDefaults,
     /// Admin API URL for callback notifications
     admin_url: String,
+    /// Scheme and port clients use to reach the proxy (for PUBLIC_URL)
+    public_endpoint: RwLock<(String, u16)>,
+    /// Number of instances spawned so far per backend
+    spawn_counts: DashMap<String, u64>,
+    /// Spawns in progress; concurrent starts of a backend join the same spawn
+    spawns: DashMap<String, SharedSpawn>,
+    /// Backends whose stopped instance is still shutting down
+    shutting_down: DashSet<String>,
+    /// Backends being restarted, whose requests wait for the new instance
+    restarting: DashSet<String>,
+    /// When each backend's latest spawn began
+    spawn_started: DashMap<String, Instant>,
+    /// Recent times from spawn to ready per backend, oldest first
+    spawn_times: DashMap<String, VecDeque<Duration>>,
+    /// Requests cancelled because the client disconnected, per backend
+    cancelled: DashMap<String, u64>,
+    /// Requests waiting for each backend to become ready
+    queued: DashMap<String, Arc<WaitQueue>>,
+    /// Backends approved for their manual readiness gate
+    approvals: DashSet<String>,
+    /// Backends defined via the admin API rather than the configuration
+    api_backends: DashSet<String>,
+    /// Recent artifact promotions
+    promotions: PromotionHistory,
+    /// Faults enabled via the admin API
+    faults: FaultInjector,
+    /// Traffic counted against backend quotas
+    quotas: Arc<QuotaTracker>,
+    /// Ports of running backends with `port = 0`
+    ports: PortAllocator,
+    /// Env vars set via the admin API
+    config_vars: ConfigVars,
+    /// Request inter-arrival times for adaptive idle timeouts
+    idle_predictor: IdlePredictor,
+    /// Running time of each backend for the savings report
+    uptime: UptimeLedger,
+    /// Recent stdout/stderr and container output of each backend
+    logs: Arc<BackendLogs>,
+    /// Callbacks for backend lifecycle events
+    event_hooks: RwLock<Vec<EventHook>>,
+    /// Launchers for custom backends, by name
+    spawners: RwLock<HashMap<String, Arc<dyn Spawner>>>,
+    /// Replaces HTTP health checks when set
+    health_probe: RwLock<Option<Arc<dyn HealthProbe>>>,
+    /// Serializes reloads, so configs and defaults always come from the same one
+    reload_lock: tokio::sync::Mutex<()>,
     /// Docker manager (lazily initialized when needed)
-    docker: tokio::sync::OnceCell<SharedDockerManager>,
+    docker: tokio::sync::OnceCell<SharedContainerRuntime>,
+    /// Podman runtime (lazily initialized when needed)
+    podman: tokio::sync::OnceCell<SharedContainerRuntime>,
+    /// Launcher for firecracker backends
+    firecracker: Arc<FirecrackerSpawner>,
+    /// Launcher for nomad backends
+    nomad: Arc<NomadSpawner>,
+    /// Launcher for cloud-vm backends
+    cloud_vms: Arc<CloudVmSpawner>,
+    /// Launcher for ssh-tunnel backends
+    ssh_tunnels: Arc<SshTunnelSpawner>,
 }
 
 impl ProcessManager {
@@ -108,29 +326,251 @@ impl ProcessManager {
         defaults: BackendDefaults,
         admin_url: String,
     ) -> Arc<Self> {
+        let uptime = UptimeLedger::new();
+        for hostname in configs.keys() {
+            uptime.track(hostname);
+        }
+        let logs = Arc::new(BackendLogs::new());
         Arc::new(Self {
             processes: DashMap::new(),
             configs: Arc::new(RwLock::new(configs)),
             defaults: Arc::new(RwLock::new(defaults)),
             admin_url,
+            public_endpoint: RwLock::new(("http".to_string(), 80)),
+            spawn_counts: DashMap::new(),
+            spawns: DashMap::new(),
+            shutting_down: DashSet::new(),
+            restarting: DashSet::new(),
+            spawn_started: DashMap::new(),
+            spawn_times: DashMap::new(),
+            cancelled: DashMap::new(),
+            queued: DashMap::new(),
+            approvals: DashSet::new(),
+            api_backends: DashSet::new(),
+            promotions: PromotionHistory::new(),
+            faults: FaultInjector::new(),
+            quotas: Arc::new(QuotaTracker::new()),
+            ports: PortAllocator::new(),
+            config_vars: ConfigVars::new(),
+            idle_predictor: IdlePredictor::new(),
+            uptime,
+            logs: Arc::clone(&logs),
+            event_hooks: RwLock::new(Vec::new()),
+            spawners: RwLock::new(HashMap::new()),
+            health_probe: RwLock::new(None),
+            reload_lock: tokio::sync::Mutex::new(()),
             docker: tokio::sync::OnceCell::new(),
+            podman: tokio::sync::OnceCell::new(),
+            firecracker: Arc::new(FirecrackerSpawner::new(Arc::clone(&logs))),
+            nomad: Arc::new(NomadSpawner::new()),
+            cloud_vms: Arc::new(CloudVmSpawner::new()),
+            ssh_tunnels: Arc::new(SshTunnelSpawner::new(Arc::clone(&logs))),
         })
     }
 
+    /// Call `hook` for every backend lifecycle event
+    ///
+    /// Hooks run on the task that caused the event and must not block.
+    pub fn on_event(&self, hook: impl Fn(&BackendEvent) + Send + Sync + 'static) {
+        self.event_hooks.write().push(Arc::new(hook));
+    }
+
+    /// Launch backends whose `spawner` is `name` with `spawner`
+    ///
+    /// Replaces a spawner already registered under the name; running
+    /// instances are still stopped by the spawner that started them.
+    pub fn register_spawner(&self, name: impl Into<String>, spawner: Arc<dyn Spawner>) {
+        self.spawners.write().insert(name.into(), spawner);
+    }
+
+    /// Check health with `probe` instead of HTTP requests to the backends
+    pub fn set_health_probe(&self, probe: Arc<dyn HealthProbe>) {
+        *self.health_probe.write() = Some(probe);
+    }
+
+    fn emit(&self, event: BackendEvent) {
+        let hooks = self.event_hooks.read().clone();
+        for hook in hooks {
+            hook(&event);
+        }
+    }
+
     /// Get a shared reference to the defaults (for ProxyServer)
     pub fn shared_defaults(&self) -> SharedDefaults {
         Arc::clone(&self.defaults)
     }
 
-    /// Get or initialize the Docker manager
-    async fn get_docker(&self, docker_host: Option<&str>) -> anyhow::Result<SharedDockerManager> {
-        self.docker
-            .get_or_try_init(|| async {
-                let manager = DockerManager::new(docker_host).await?;
-                Ok(Arc::new(manager))
-            })
-            .await
-            .cloned()
+    /// Set the scheme and port clients use to reach the proxy
+    ///
+    /// Used to build `PUBLIC_URL` for backends that don't set `public_url`.
+    pub fn set_public_endpoint(&self, scheme: &str, port: u16) {
+        *self.public_endpoint.write() = (scheme.to_string(), port);
+    }
+
+    /// Public URL of a backend as seen by clients
+    pub fn public_url(&self, hostname: &str, config: &BackendConfig) -> String {
+        if let Some(ref url) = config.public_url {
+            return url.clone();
+        }
+        let (scheme, port) = &*self.public_endpoint.read();
+        match (scheme.as_str(), port) {
+            ("http", 80) | ("https", 443) => format!("{}://{}", scheme, hostname),
+            _ => format!("{}://{}:{}", scheme, hostname, port),
+        }
+    }
+
+    /// Get the metadata of the currently running instance of a backend
+    pub fn instance(&self, hostname: &str) -> Option<InstanceMetadata> {
+        self.processes
+            .get(hostname)
+            .map(|p| p.lock().instance.clone())
+    }
+
+    /// Check a ready-callback token against the backend's current instance
+    pub fn verify_instance_token(&self, hostname: &str, token: &str) -> bool {
+        self.processes
+            .get(hostname)
+            .is_some_and(|p| p.lock().instance.token == token)
+    }
+
+    /// Environment variables the proxy injects into every spawned instance
+    fn instance_env(
+        &self,
+        hostname: &str,
+        config: &BackendConfig,
+        instance: &InstanceMetadata,
+    ) -> Vec<(String, String)> {
+        let callback_url = format!(
+            "{}/ready/{}?token={}",
+            self.admin_url, hostname, instance.token
+        );
+        let mut env = vec![
+            ("PORT".to_string(), config.port.to_string()),
+            ("SERVERLESS_PROXY_READY_URL".to_string(), callback_url.clone()),
+            ("ADMIN_CALLBACK_URL".to_string(), callback_url),
+            ("INSTANCE_ID".to_string(), instance.id.clone()),
+            ("INSTANCE_INDEX".to_string(), instance.index.to_string()),
+            ("SPAWNGATE_BACKEND".to_string(), hostname.to_string()),
+            ("PUBLIC_URL".to_string(), self.public_url(hostname, config)),
+        ];
+        if let Some(secret) = &config.signing_secret {
+            env.push(("SPAWNGATE_SIGNING_SECRET".to_string(), secret.clone()));
+        }
+        env
+    }
+
+    /// Get or initialize the container runtime of a Docker backend
+    ///
+    /// Initializing it also starts watching for containers that exit.
+    async fn get_container_runtime(self: &Arc<Self>, config: &BackendConfig) -> anyhow::Result<SharedContainerRuntime> {
+        let docker_host = config.docker_host.as_deref();
+        let runtime = match config.container_runtime {
+            ContainerEngine::Docker => {
+                self.docker
+                    .get_or_try_init(|| async {
+                        let manager: SharedContainerRuntime = Arc::new(DockerManager::new(docker_host).await?);
+                        self.watch_container_exits(manager.as_ref());
+                        anyhow::Ok(manager)
+                    })
+                    .await?
+            }
+            ContainerEngine::Podman => {
+                self.podman
+                    .get_or_try_init(|| async {
+                        let runtime: SharedContainerRuntime = Arc::new(PodmanRuntime::new(docker_host).await?);
+                        self.watch_container_exits(runtime.as_ref());
+                        anyhow::Ok(runtime)
+                    })
+                    .await?
+            }
+        };
+        Ok(Arc::clone(runtime))
+    }
+
+    fn watch_container_exits(self: &Arc<Self>, runtime: &dyn ContainerRuntime) {
+        let mut exits = runtime.watch_exits();
+        let process_manager = Arc::downgrade(self);
+        tokio::spawn(async move {
+            while let Some(exit) = exits.recv().await {
+                let Some(process_manager) = process_manager.upgrade() else {
+                    break;
+                };
+                process_manager.handle_container_exit(exit).await;
+            }
+        });
+    }
+
+    /// Update a backend whose container exited without the proxy stopping it
+    ///
+    /// A container that exits while starting fails the spawn. One that exits
+    /// after becoming ready is restarted, or left stopped if the backend's
+    /// `on_container_exit` is "stop". Exits of containers the proxy is
+    /// stopping itself, or doesn't manage, are ignored.
+    ///
+    /// Boxed because restarting spawns the backend, which may initialize the
+    /// Docker manager that calls this.
+    fn handle_container_exit(self: &Arc<Self>, exit: ContainerExit) -> BoxFuture<'static, ()> {
+        let manager = Arc::clone(self);
+        async move {
+            let is_exited = |process: &Mutex<BackendProcess>| {
+                let guard = process.lock();
+                guard.state != BackendState::Stopping
+                    && matches!(&guard.handle, ProcessHandle::Docker { container_id, .. } if *container_id == exit.container_id)
+            };
+            let Some(hostname) = manager
+                .processes
+                .iter()
+                .find(|entry| is_exited(entry.value()))
+                .map(|entry| entry.key().clone())
+            else {
+                return;
+            };
+            let Some((_, process)) = manager.processes.remove_if(&hostname, |_, process| is_exited(process)) else {
+                return;
+            };
+            let backend = process.into_inner();
+            let hostname = hostname.as_str();
+            warn!(
+                hostname,
+                container_id = %exit.container_id,
+                exit_code = ?exit.exit_code,
+                state = ?backend.state,
+                "Container exited unexpectedly"
+            );
+
+            if let ProcessHandle::Docker { container_id, runtime, log_shutdown } = backend.handle {
+                if let Some(shutdown) = log_shutdown {
+                    let _ = shutdown.send(true);
+                }
+                if let Err(e) = runtime.remove_container(&container_id).await {
+                    warn!(hostname, container_id, error = %e, "Error removing container");
+                }
+            }
+            manager.uptime.stopped(hostname);
+            manager.emit(BackendEvent::Stopped { hostname: hostname.to_string() });
+
+            let exit_code = exit.exit_code.map_or_else(|| "unknown".to_string(), |code| code.to_string());
+            if backend.state == BackendState::Starting {
+                manager.emit(BackendEvent::StartFailed {
+                    hostname: hostname.to_string(),
+                    error: format!("Container exited with code {} before becoming ready", exit_code),
+                });
+                return;
+            }
+            let policy = manager.get_config(hostname).map(|c| c.on_container_exit);
+            if policy == Some(ContainerExitPolicy::Restart) {
+                info!(hostname, "Restarting backend after its container exited");
+                let manager = Arc::clone(&manager);
+                let hostname_owned = hostname.to_string();
+                tokio::spawn(async move {
+                    tokio::time::sleep(Duration::from_millis(500)).await;
+                    if let Err(e) = manager.start_backend(&hostname_owned).await {
+                        error!(hostname = %hostname_owned, error = %e, "Failed to restart backend");
+                    }
+                });
+            }
+        }
+        .boxed()
     }
 
     /// Get the configuration for a hostname (cloned for thread safety)
@@ -138,11 +578,109 @@ impl ProcessManager {
         self.configs.read().get(hostname).cloned()
     }
 
+    /// Configs of all backends, including those created through the admin API
+    pub fn get_configs(&self) -> HashMap<String, BackendConfig> {
+        self.configs.read().clone()
+    }
+
+    /// Settings the upstream stage needs to forward a request, without
+    /// cloning the whole backend config
+    pub fn upstream_target(&self, hostname: &str, defaults: &BackendDefaults) -> Option<UpstreamTarget> {
+        let configs = self.configs.read();
+        let config = configs.get(hostname)?;
+        Some(UpstreamTarget {
+            port: self.backend_port(hostname, config),
+            request_timeout: config.request_timeout(defaults),
+            redirect_root: config
+                .internal_redirect
+                .then(|| config.internal_root.as_ref().map(PathBuf::from)),
+            deadline_headers: config.deadline_headers,
+            protocol: if config.backend_tls {
+                BackendProtocol::Tls
+            } else if config.backend_http2 {
+                BackendProtocol::Http2
+            } else {
+                BackendProtocol::Http1
+            },
+            rewriter: Rewriter::new(&config.response_rewrites, &config.rewrite_content_types),
+        })
+    }
+
     /// Check if a backend exists in configuration
     pub fn has_backend(&self, hostname: &str) -> bool {
         self.configs.read().contains_key(hostname)
     }
 
+    /// Check whether a backend serves the given request path (see `allowed_paths`)
+    pub fn is_path_allowed(&self, hostname: &str, path: &str) -> bool {
+        self.configs
+            .read()
+            .get(hostname)
+            .is_none_or(|config| config.allows_path(path))
+    }
+
+    /// Check whether a plain HTTP request is redirected to HTTPS when `force_https` is on
+    ///
+    /// Unknown hosts are redirected.
+    pub fn redirects_to_https(&self, hostname: &str, path: &str) -> bool {
+        self.configs
+            .read()
+            .get(hostname)
+            .is_none_or(|config| config.redirects_to_https(path))
+    }
+
+    /// Trace sample rate a backend sets for a status class, if any
+    pub fn trace_sample_rate(&self, hostname: &str, class: &str) -> Option<f64> {
+        self.configs
+            .read()
+            .get(hostname)
+            .and_then(|config| config.trace_sample_rates.get(class).copied())
+    }
+
+    /// Cold-start SLO threshold and target a backend overrides, if any
+    pub fn cold_start_slo(&self, hostname: &str) -> (Option<Duration>, Option<f64>) {
+        self.configs.read().get(hostname).map_or((None, None), |config| {
+            (
+                config.cold_start_slo_ms.map(Duration::from_millis),
+                config.cold_start_slo_target,
+            )
+        })
+    }
+
+    /// WAF threshold and mode for a hostname, or `None` if its requests aren't inspected
+    pub fn waf(&self, hostname: &str) -> Option<(u32, WafMode)> {
+        let defaults = self.defaults.read();
+        self.configs.read().get(hostname)?.waf(&defaults)
+    }
+
+    /// Backend serving a request for a hostname: the target of the matching
+    /// rule among its `routes`, otherwise the variant of its experiment the
+    /// client is assigned to, otherwise the hostname's own backend
+    pub fn route(&self, hostname: &str, parts: &Parts, client_ip: IpAddr) -> Option<Route> {
+        let configs = self.configs.read();
+        let config = configs.get(hostname)?;
+        let mut route = match (routing::select(&config.routes, parts), &config.experiment) {
+            (Some(rule), _) => Route::from(rule),
+            (None, Some(experiment)) => {
+                let assignment = experiments::assign(experiment, &parts.headers, client_ip);
+                let mut route = Route::to(assignment.backend.as_deref().unwrap_or(hostname));
+                route.experiment = Some(assignment);
+                route
+            }
+            (None, None) => Route::to(hostname),
+        };
+        route.read_your_writes = routing::read_your_writes(&config.routes, &parts.method);
+        Some(route)
+    }
+
+    /// Get the fallback backend configured for a hostname
+    pub fn fallback_for(&self, hostname: &str) -> Option<String> {
+        self.configs
+            .read()
+            .get(hostname)
+            .and_then(|config| config.fallback.clone())
+    }
+
     /// Get the current defaults (cloned for thread safety)
     pub fn get_defaults(&self) -> BackendDefaults {
         self.defaults.read().clone()
@@ -156,16 +694,173 @@ impl ProcessManager {
             .unwrap_or(BackendState::Stopped)
     }
 
+    /// Whether an instance of the backend is draining or shutting down
+    fn is_stopping(&self, hostname: &str) -> bool {
+        self.get_state(hostname) == BackendState::Stopping || self.shutting_down.contains(hostname)
+    }
+
+    /// Whether a backend is being restarted
+    ///
+    /// Requests arriving while its old instance stops wait for the new one
+    /// rather than being rejected.
+    pub fn is_restarting(&self, hostname: &str) -> bool {
+        self.restarting.contains(hostname)
+    }
+
     /// Check if a backend is ready to accept traffic
     pub fn is_ready(&self, hostname: &str) -> bool {
         self.get_state(hostname) == BackendState::Ready
     }
 
+    /// Resource usage of a Docker backend's container, or of a local backend's cgroup
+    ///
+    /// None for other backend types, local backends without limits, stopped
+    /// backends, or when the runtime doesn't answer in time.
+    pub async fn resource_stats(&self, hostname: &str) -> Option<ContainerStats> {
+        let (container_id, runtime) = {
+            let process = self.processes.get(hostname)?;
+            let guard = process.lock();
+            match &guard.handle {
+                ProcessHandle::Docker { container_id, runtime, .. } => (container_id.clone(), Arc::clone(runtime)),
+                ProcessHandle::Local { cgroup: Some(cgroup), .. } => return cgroup.stats(),
+                _ => return None,
+            }
+        };
+        match tokio::time::timeout(STATS_TIMEOUT, runtime.stats(&container_id)).await {
+            Ok(Ok(stats)) => Some(stats),
+            Ok(Err(e)) => {
+                debug!(hostname, container_id, error = %e, "Failed to get container stats");
+                None
+            }
+            Err(_) => {
+                debug!(hostname, container_id, "Timed out getting container stats");
+                None
+            }
+        }
+    }
+
+    /// Whether the kernel killed a local backend for exceeding its memory limit
+    fn memory_limit_exceeded(&self, hostname: &str) -> bool {
+        self.processes.get(hostname).is_some_and(|process| match &process.lock().handle {
+            ProcessHandle::Local { cgroup: Some(cgroup), .. } => cgroup.oom_killed(),
+            _ => false,
+        })
+    }
+
+    /// Whether a backend has run for `max_lifetime` and can be recycled
+    ///
+    /// Waits for a moment without requests in flight, for up to `drain_timeout`.
+    fn lifetime_exceeded(&self, hostname: &str, max_lifetime: Duration, drain_timeout: Duration) -> bool {
+        self.processes.get(hostname).is_some_and(|process| {
+            let guard = process.lock();
+            let age = guard.started.elapsed();
+            age >= max_lifetime && (guard.in_flight.load(Ordering::SeqCst) == 0 || age >= max_lifetime + drain_timeout)
+        })
+    }
+
     /// Update the last activity timestamp for a backend
+    ///
+    /// Backends listed in its `depends_on` are kept alive along with it.
     pub fn touch(&self, hostname: &str) {
+        self.idle_predictor.record(hostname);
         if let Some(process) = self.processes.get(hostname) {
             process.lock().last_activity = Instant::now();
         }
+
+        let dependencies = self
+            .configs
+            .read()
+            .get(hostname)
+            .map(|config| config.readiness.depends_on.clone())
+            .unwrap_or_default();
+        for dependency in dependencies {
+            self.touch(&dependency);
+        }
+    }
+
+    /// Approve a backend's manual readiness gate
+    ///
+    /// Approval lasts until revoked or the configuration is reloaded.
+    pub fn approve(&self, hostname: &str) -> bool {
+        if !self.has_backend(hostname) {
+            return false;
+        }
+        self.approvals.insert(hostname.to_string());
+        true
+    }
+
+    /// Revoke a backend's manual approval, returns true if it was approved
+    pub fn revoke_approval(&self, hostname: &str) -> bool {
+        self.approvals.remove(hostname).is_some()
+    }
+
+    /// Faults enabled for backends
+    pub fn faults(&self) -> &FaultInjector {
+        &self.faults
+    }
+
+    /// Traffic counted against backend quotas
+    pub fn quotas(&self) -> &Arc<QuotaTracker> {
+        &self.quotas
+    }
+
+    /// The quota of a backend, if it has one
+    pub fn quota(&self, hostname: &str) -> Option<QuotaConfig> {
+        self.configs.read().get(hostname)?.quota.clone()
+    }
+
+    /// Usage of every backend with a quota, sorted by hostname
+    pub fn quota_status(&self) -> Vec<QuotaStatus> {
+        let configs = self.configs.read();
+        let mut statuses: Vec<QuotaStatus> = configs
+            .iter()
+            .filter_map(|(hostname, config)| Some(self.quotas.status(hostname, config.quota.as_ref()?)))
+            .collect();
+        statuses.sort_by(|a, b| a.hostname.cmp(&b.hostname));
+        statuses
+    }
+
+    /// Env vars set via the admin API, applied on the next spawn
+    pub fn config_vars(&self) -> &ConfigVars {
+        &self.config_vars
+    }
+
+    /// Enable a backend's configured faults, returns how long they stay enabled
+    ///
+    /// `duration` can shorten the configured `duration_secs` but not extend it.
+    pub fn enable_faults(&self, hostname: &str, duration: Option<Duration>) -> Result<Duration, String> {
+        let config = self
+            .configs
+            .read()
+            .get(hostname)
+            .ok_or_else(|| format!("Unknown backend: {}", hostname))?
+            .faults
+            .clone()
+            .ok_or_else(|| format!("Backend '{}' has no faults configured", hostname))?;
+
+        let limit = Duration::from_secs(config.duration_secs);
+        let duration = duration.map_or(limit, |d| d.min(limit));
+        if duration.is_zero() {
+            return Err("Duration must be greater than 0".to_string());
+        }
+        self.faults.enable(hostname, config, duration);
+        Ok(duration)
+    }
+
+    /// First readiness gate the backend does not meet yet, if any
+    pub fn pending_gate(&self, hostname: &str) -> Option<PendingGate> {
+        let gates = self.configs.read().get(hostname)?.readiness.clone();
+
+        if let Some(dependency) = gates.depends_on.iter().find(|d| !self.is_ready(d)) {
+            return Some(PendingGate::Dependency(dependency.clone()));
+        }
+        if let Some(marker) = gates.marker_file.filter(|m| !Path::new(m).exists()) {
+            return Some(PendingGate::MarkerFile(marker));
+        }
+        if gates.manual_approval && !self.approvals.contains(hostname) {
+            return Some(PendingGate::ManualApproval);
+        }
+        None
     }
 
     /// Get a receiver that will be notified when the backend becomes ready
@@ -196,6 +891,52 @@ impl ProcessManager {
         }
     }
 
+    /// Count a request abandoned because its client disconnected
+    pub fn record_cancelled(&self, hostname: &str) {
+        *self.cancelled.entry(hostname.to_string()).or_insert(0) += 1;
+    }
+
+    /// Requests to a backend cancelled by client disconnects since startup
+    pub fn get_cancelled(&self, hostname: &str) -> u64 {
+        self.cancelled.get(hostname).map(|c| *c).unwrap_or(0)
+    }
+
+    /// Count a request as waiting for a backend to become ready until the
+    /// returned guard is dropped
+    ///
+    /// With `limit` requests waiting already, a lower-class request is
+    /// preempted to make room; fails with the current depth if there is none.
+    pub fn queue_request(
+        &self,
+        hostname: &str,
+        class: PriorityClass,
+        limit: Option<usize>,
+    ) -> Result<QueuedRequest, usize> {
+        let queue = Arc::clone(&self.queued.entry(hostname.to_string()).or_default());
+        queue.join(class, limit)
+    }
+
+    /// Requests waiting for a backend to become ready
+    pub fn get_queued(&self, hostname: &str) -> usize {
+        self.queued.get(hostname).map_or(0, |queue| queue.len())
+    }
+
+    /// Queue counts of a backend per priority class
+    pub fn queue_stats(&self, hostname: &str) -> BTreeMap<PriorityClass, ClassStats> {
+        self.queued
+            .get(hostname)
+            .map(|queue| queue.stats())
+            .unwrap_or_else(|| WaitQueue::new().stats())
+    }
+
+    /// Priority class of a request to a backend, from its `priority_rules`
+    pub fn priority_class(&self, hostname: &str, method: &Method, path: &str, headers: &HeaderMap) -> PriorityClass {
+        self.configs
+            .read()
+            .get(hostname)
+            .map_or(PriorityClass::Normal, |config| priority::classify(&config.priority_rules, method, path, headers))
+    }
+
     /// Get the in-flight request count for a backend
     pub fn get_in_flight(&self, hostname: &str) -> usize {
         self.processes
@@ -206,66 +947,118 @@ impl ProcessManager {
 
     /// Mark a backend as ready (called from health check or callback)
     pub fn mark_ready(&self, hostname: &str) -> bool {
-        if let Some(process) = self.processes.get(hostname) {
+        let marked = self.processes.get(hostname).is_some_and(|process| {
             let mut guard = process.lock();
-            if guard.state == BackendState::Starting || guard.state == BackendState::Unhealthy {
-                let was_unhealthy = guard.state == BackendState::Unhealthy;
-                guard.state = BackendState::Ready;
-                guard.last_activity = Instant::now();
-                guard.consecutive_failures = 0;
-                // Notify all waiting requests
-                let _ = guard.ready_tx.send(());
-                if was_unhealthy {
-                    info!(hostname, "Backend recovered and is now ready");
-                } else {
-                    info!(hostname, "Backend is now ready");
-                }
-                return true;
+            if guard.state != BackendState::Starting && guard.state != BackendState::Unhealthy {
+                return false;
+            }
+            let was_unhealthy = guard.state == BackendState::Unhealthy;
+            if !was_unhealthy {
+                self.record_spawn_time(hostname);
+            }
+            guard.state = BackendState::Ready;
+            guard.last_activity = Instant::now();
+            guard.consecutive_failures = 0;
+            // Notify all waiting requests
+            let _ = guard.ready_tx.send(());
+            if was_unhealthy {
+                info!(hostname, "Backend recovered and is now ready");
+            } else {
+                info!(hostname, "Backend is now ready");
             }
+            true
+        });
+        if marked {
+            self.emit(BackendEvent::Ready { hostname: hostname.to_string() });
         }
-        false
+        marked
+    }
+
+    fn record_spawn_time(&self, hostname: &str) {
+        let Some((_, started)) = self.spawn_started.remove(hostname) else {
+            return;
+        };
+        let mut times = self.spawn_times.entry(hostname.to_string()).or_default();
+        if times.len() == SPAWN_TIME_SAMPLES {
+            times.pop_front();
+        }
+        times.push_back(started.elapsed());
+    }
+
+    /// How long until a starting backend is likely ready
+    ///
+    /// The median of its recent spawn-to-ready times, less the time the current
+    /// spawn has taken so far. `None` until the backend has become ready once.
+    pub fn estimated_ready(&self, hostname: &str) -> Option<Duration> {
+        let typical = {
+            let times = self.spawn_times.get(hostname)?;
+            let mut sorted: Vec<Duration> = times.iter().copied().collect();
+            sorted.sort();
+            *sorted.get(sorted.len() / 2)?
+        };
+        let elapsed = self
+            .spawn_started
+            .get(hostname)
+            .map(|started| started.elapsed())
+            .unwrap_or_default();
+        Some(typical.saturating_sub(elapsed))
     }
 
     /// Mark a backend as unhealthy
     pub fn mark_unhealthy(&self, hostname: &str) {
-        if let Some(process) = self.processes.get(hostname) {
+        let marked = self.processes.get(hostname).is_some_and(|process| {
             let mut guard = process.lock();
-            if guard.state == BackendState::Ready {
-                guard.state = BackendState::Unhealthy;
-                warn!(hostname, "Backend marked as unhealthy");
+            if guard.state != BackendState::Ready {
+                return false;
             }
+            guard.state = BackendState::Unhealthy;
+            warn!(hostname, "Backend marked as unhealthy");
+            true
+        });
+        if marked {
+            self.emit(BackendEvent::Unhealthy { hostname: hostname.to_string() });
         }
     }
 
     /// Record a health check failure, returns true if backend should be marked unhealthy
     pub fn record_health_failure(&self, hostname: &str, threshold: u32) -> bool {
-        if let Some(process) = self.processes.get(hostname) {
+        let marked = self.processes.get(hostname).is_some_and(|process| {
             let mut guard = process.lock();
             guard.consecutive_failures += 1;
-            if guard.consecutive_failures >= threshold && guard.state == BackendState::Ready {
-                guard.state = BackendState::Unhealthy;
-                warn!(
-                    hostname,
-                    failures = guard.consecutive_failures,
-                    "Backend marked as unhealthy after consecutive failures"
-                );
-                return true;
+            if guard.consecutive_failures < threshold || guard.state != BackendState::Ready {
+                return false;
             }
+            guard.state = BackendState::Unhealthy;
+            warn!(
+                hostname,
+                failures = guard.consecutive_failures,
+                "Backend marked as unhealthy after consecutive failures"
+            );
+            true
+        });
+        if marked {
+            self.emit(BackendEvent::Unhealthy { hostname: hostname.to_string() });
         }
-        false
+        marked
     }
 
     /// Reset health check failure count (on successful health check)
     pub fn reset_health_failures(&self, hostname: &str) {
-        if let Some(process) = self.processes.get(hostname) {
+        let recovered = self.processes.get(hostname).is_some_and(|process| {
             let mut guard = process.lock();
-            if guard.consecutive_failures > 0 {
-                guard.consecutive_failures = 0;
-                if guard.state == BackendState::Unhealthy {
-                    guard.state = BackendState::Ready;
-                    info!(hostname, "Backend recovered and is now healthy");
-                }
+            if guard.consecutive_failures == 0 {
+                return false;
+            }
+            guard.consecutive_failures = 0;
+            if guard.state != BackendState::Unhealthy {
+                return false;
             }
+            guard.state = BackendState::Ready;
+            info!(hostname, "Backend recovered and is now healthy");
+            true
+        });
+        if recovered {
+            self.emit(BackendEvent::Ready { hostname: hostname.to_string() });
         }
     }
 
@@ -275,8 +1068,58 @@ impl ProcessManager {
     }
 
     /// Start a backend process or container
+    ///
+    /// Concurrent calls for the same backend, e.g. from requests arriving on
+    /// the HTTP and HTTPS listeners at once, share a single spawn and its
+    /// result. The spawn runs to completion even if every caller goes away.
     pub async fn start_backend(self: &Arc<Self>, hostname: &str) -> anyhow::Result<()> {
-        let config = self
+        let spawn = match self.spawns.entry(hostname.to_string()) {
+            dashmap::Entry::Occupied(entry) => {
+                debug!(hostname, "Joining spawn in progress");
+                entry.get().clone()
+            }
+            dashmap::Entry::Vacant(entry) => {
+                self.spawn_started.insert(hostname.to_string(), Instant::now());
+                let manager = Arc::clone(self);
+                let hostname = hostname.to_string();
+                let task = tokio::spawn(async move {
+                    let result = manager.spawn_backend(&hostname).await.map_err(Arc::new);
+                    manager.spawns.remove(&hostname);
+                    if let Err(e) = &result {
+                        manager.emit(BackendEvent::StartFailed {
+                            hostname: hostname.clone(),
+                            error: format!("{:#}", e),
+                        });
+                    }
+                    result
+                });
+                let spawn = async move {
+                    task.await
+                        .unwrap_or_else(|e| Err(Arc::new(anyhow::anyhow!("Spawn task failed: {}", e))))
+                }
+                .boxed()
+                .shared();
+                entry.insert(spawn.clone());
+                spawn
+            }
+        };
+        spawn.await.map_err(|e| anyhow::anyhow!("{:#}", e))
+    }
+
+    /// Backends with a spawn in progress, sorted
+    pub fn spawning(&self) -> Vec<String> {
+        let mut hostnames: Vec<String> = self.spawns.iter().map(|entry| entry.key().clone()).collect();
+        hostnames.sort();
+        hostnames
+    }
+
+    /// Number of instances of a backend spawned since startup
+    pub fn spawn_count(&self, hostname: &str) -> u64 {
+        self.spawn_counts.get(hostname).map(|c| *c).unwrap_or(0)
+    }
+
+    async fn spawn_backend(self: &Arc<Self>, hostname: &str) -> anyhow::Result<()> {
+        let mut config = self
             .get_config(hostname)
             .ok_or_else(|| anyhow::anyhow!("Unknown backend: {}", hostname))?;
 
@@ -289,10 +1132,45 @@ impl ProcessManager {
             }
         }
 
-        let handle = match config.backend_type {
-            BackendType::Local => self.start_local_backend(hostname, &config).await?,
-            BackendType::Docker => self.start_docker_backend(hostname, &config).await?,
+        // Starting while the previous instance drains would replace its entry and
+        // leak it, and while it shuts down it may still hold the port
+        if self.is_stopping(hostname) {
+            debug!(hostname, "Waiting for the previous instance to stop");
+            while self.is_stopping(hostname) {
+                tokio::time::sleep(Duration::from_millis(DRAIN_POLL_INTERVAL_MS)).await;
+            }
+        }
+
+        if self.faults.spawn_fails(hostname) {
+            anyhow::bail!("Spawn failed by fault injection");
+        }
+
+        let instance = {
+            let mut count = self.spawn_counts.entry(hostname.to_string()).or_insert(0);
+            let instance = InstanceMetadata::new(*count);
+            *count += 1;
+            instance
+        };
+        config.env.extend(self.config_vars.get(hostname));
+        for (key, value) in config.locale_env(&self.get_defaults()) {
+            config.env.entry(key).or_insert(value);
+        }
+        if config.port == 0 && config.backend_type.assigns_ports() {
+            config.port = self.ports.allocate(hostname, &self.get_defaults().ports, &self.configured_ports())?;
+            debug!(hostname, port = config.port, "Assigned backend port");
+        }
+        let env = self.instance_env(hostname, &config, &instance);
+
+        let started = match config.backend_type {
+            BackendType::Local => self.start_local_backend(hostname, &config, &env).await,
+            BackendType::Docker => self.start_docker_backend(hostname, &config, &env).await,
+            BackendType::Custom => self.start_custom_backend(hostname, &config, &env).await,
+            BackendType::Firecracker => self.start_with(self.firecracker.clone(), hostname, &config, &env).await,
+            BackendType::Nomad => self.start_with(self.nomad.clone(), hostname, &config, &env).await,
+            BackendType::CloudVm => self.start_with(self.cloud_vms.clone(), hostname, &config, &env).await,
+            BackendType::SshTunnel => self.start_with(self.ssh_tunnels.clone(), hostname, &config, &env).await,
         };
+        let handle = started.inspect_err(|_| self.ports.release(hostname))?;
 
         let (ready_tx, _) = broadcast::channel(16);
         let now = Instant::now();
@@ -300,13 +1178,17 @@ impl ProcessManager {
         let process = BackendProcess {
             handle,
             state: BackendState::Starting,
+            started: now,
             last_activity: now,
             ready_tx,
             in_flight: Arc::new(AtomicUsize::new(0)),
             consecutive_failures: 0,
+            instance,
         };
 
         self.processes.insert(hostname.to_string(), Mutex::new(process));
+        self.uptime.started(hostname);
+        self.emit(BackendEvent::Started { hostname: hostname.to_string() });
 
         // Start health check polling
         let manager = Arc::clone(self);
@@ -328,6 +1210,7 @@ impl ProcessManager {
         &self,
         hostname: &str,
         config: &BackendConfig,
+        injected_env: &[(String, String)],
     ) -> anyhow::Result<ProcessHandle> {
         let command = config.command.as_ref().ok_or_else(|| {
             anyhow::anyhow!("Local backend requires 'command' field")
@@ -346,39 +1229,128 @@ impl ProcessManager {
             cmd.current_dir(working_dir);
         }
 
+        // Look up the user and group to run as
+        #[cfg(unix)]
+        let credentials = match (&config.user, &config.group) {
+            (None, None) => None,
+            (user, group) => Some(Credentials::resolve(user.as_deref(), group.as_deref())?),
+        };
+        #[cfg(not(unix))]
+        if config.user.is_some() || config.group.is_some() {
+            anyhow::bail!("'user' and 'group' of local backends require Unix");
+        }
+
+        // The user's HOME, USER and LOGNAME, which the env table can override
+        #[cfg(unix)]
+        if let Some(credentials) = credentials.as_ref().filter(|_| config.user.is_some()) {
+            if let Some(name) = &credentials.name {
+                cmd.env("USER", name).env("LOGNAME", name);
+            }
+            if let Some(home) = &credentials.home {
+                cmd.env("HOME", home);
+            }
+        }
+
         // Set environment variables
         for (key, value) in &config.env {
             cmd.env(key, value);
         }
 
-        // Set the PORT environment variable
-        cmd.env("PORT", config.port.to_string());
+        // PORT, ready callback and instance metadata (override the env table)
+        cmd.envs(injected_env.iter().map(|(k, v)| (k, v)));
 
-        // Set the callback URL for ready notification
-        let callback_url = format!("{}/ready/{}", self.admin_url, hostname);
-        cmd.env("SERVERLESS_PROXY_READY_URL", &callback_url);
+        // The child joins the backend's cgroup before exec
+        let cgroup = self.local_cgroup(hostname, config)?;
+        if let Some(cgroup) = &cgroup {
+            #[cfg(unix)]
+            {
+                let procs = cgroup.procs()?;
+                // SAFETY: `cgroup::join` only makes a single write call
+                unsafe {
+                    cmd.pre_exec(move || cgroup::join(&procs));
+                }
+            }
+            #[cfg(not(unix))]
+            anyhow::bail!(
+                "'memory' and 'cpus' limits of local backends require Linux (cgroup {})",
+                cgroup.path().display()
+            );
+        }
+
+        // ...then drops privileges, which joining the cgroup may still need
+        #[cfg(unix)]
+        if let Some(credentials) = &credentials {
+            let credentials = credentials.clone();
+            // SAFETY: `Credentials::apply` only makes system calls
+            unsafe {
+                cmd.pre_exec(move || credentials.apply());
+            }
+        }
 
         // Spawn the process
-        let child = cmd.spawn()?;
+        let mut child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                if let Some(cgroup) = &cgroup {
+                    cgroup.remove().await;
+                }
+                return Err(e.into());
+            }
+        };
         let pid = child.id().unwrap_or(0);
-        info!(hostname, pid, "Backend process spawned");
+        info!(
+            hostname,
+            pid,
+            user = ?config.user,
+            group = ?config.group,
+            cgroup = ?cgroup.as_ref().map(|c| c.path()),
+            "Backend process spawned"
+        );
+
+        // Forward its output until the process exits
+        if let Some(stdout) = child.stdout.take() {
+            let (hostname, logs) = (hostname.to_string(), Arc::clone(&self.logs));
+            tokio::spawn(async move { logs::capture(stdout, hostname, LogStream::Stdout, &logs).await });
+        }
+        if let Some(stderr) = child.stderr.take() {
+            let (hostname, logs) = (hostname.to_string(), Arc::clone(&self.logs));
+            tokio::spawn(async move { logs::capture(stderr, hostname, LogStream::Stderr, &logs).await });
+        }
 
-        Ok(ProcessHandle::Local(child))
+        Ok(ProcessHandle::Local { child, cgroup })
+    }
+
+    /// Create the cgroup for a local backend with `memory` or `cpus` limits, if cgroups are enabled
+    fn local_cgroup(&self, hostname: &str, config: &BackendConfig) -> anyhow::Result<Option<Arc<Cgroup>>> {
+        let cgroups = self.get_defaults().cgroups;
+        if !cgroups.enabled || (config.memory.is_none() && config.cpus.is_none()) {
+            return Ok(None);
+        }
+        // Validated with the config
+        let memory = config
+            .memory
+            .as_deref()
+            .map(crate::docker::parse_memory_limit)
+            .transpose()?
+            .map(|bytes| bytes as u64);
+        let cpus = config.cpus.as_deref().and_then(|cpus| cpus.parse().ok());
+        Ok(Some(Arc::new(Cgroup::create(Path::new(&cgroups.root), hostname, memory, cpus)?)))
     }
 
     /// Start a Docker container backend
     async fn start_docker_backend(
-        &self,
+        self: &Arc<Self>,
         hostname: &str,
         config: &BackendConfig,
+        injected_env: &[(String, String)],
     ) -> anyhow::Result<ProcessHandle> {
         let image = config.image.as_ref().ok_or_else(|| {
             anyhow::anyhow!("Docker backend requires 'image' field")
         })?;
 
-        info!(hostname, image = %image, "Starting Docker backend");
+        info!(hostname, image = %image, runtime = ?config.container_runtime, "Starting Docker backend");
 
-        let docker = self.get_docker(config.docker_host.as_deref()).await
+        let runtime = self.get_container_runtime(config).await
             .map_err(|e| {
                 anyhow::anyhow!(
                     "Cannot start Docker backend '{}': {}",
@@ -386,8 +1358,10 @@ impl ProcessManager {
                 )
             })?;
 
-        let container_id = docker
-            .start_container(config, hostname, &self.admin_url)
+        let mut config = config.clone();
+        config.resolve_registry_auth(&self.get_defaults());
+        let container_id = runtime
+            .start_container(&config, hostname, injected_env)
             .await
             .map_err(|e| {
                 anyhow::anyhow!(
@@ -396,14 +1370,84 @@ impl ProcessManager {
                 )
             })?;
 
-        // Start streaming container logs
-        let log_shutdown = docker.stream_logs(container_id.clone(), hostname.to_string());
+        // Start streaming container logs
+        let log_shutdown = runtime.stream_logs(container_id.clone(), hostname.to_string(), Arc::clone(&self.logs));
+
+        Ok(ProcessHandle::Docker {
+            container_id,
+            runtime,
+            log_shutdown: Some(log_shutdown),
+        })
+    }
+
+    /// Start a backend with its registered spawner
+    async fn start_custom_backend(
+        &self,
+        hostname: &str,
+        config: &BackendConfig,
+        injected_env: &[(String, String)],
+    ) -> anyhow::Result<ProcessHandle> {
+        let name = config.spawner.as_ref().ok_or_else(|| {
+            anyhow::anyhow!("Custom backend requires 'spawner' field")
+        })?;
+        let spawner = self
+            .spawners
+            .read()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("No spawner registered as '{}'", name))?;
+
+        info!(hostname, spawner = %name, "Starting custom backend");
+        self.start_with(spawner, hostname, config, injected_env).await
+    }
+
+    /// Start a backend with `spawner`, which is also used to stop it
+    async fn start_with(
+        &self,
+        spawner: Arc<dyn Spawner>,
+        hostname: &str,
+        config: &BackendConfig,
+        injected_env: &[(String, String)],
+    ) -> anyhow::Result<ProcessHandle> {
+        let id = spawner.start(hostname, config, injected_env).await?;
+        info!(hostname, id = %id, "Custom backend spawned");
+
+        Ok(ProcessHandle::Custom { id, spawner })
+    }
+
+    /// Start a backend in the background
+    fn spawn_start(self: &Arc<Self>, hostname: &str) {
+        let manager = Arc::clone(self);
+        let hostname_owned = hostname.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = manager.start_backend(&hostname_owned).await {
+                error!(hostname = %hostname_owned, error = %e, "Failed to start backend");
+            }
+        });
+    }
 
-        Ok(ProcessHandle::Docker {
-            container_id,
-            docker,
-            log_shutdown: Some(log_shutdown),
-        })
+    /// Restart a running backend in the background so it picks up config changes
+    ///
+    /// In-flight requests drain first, and new ones wait for the new instance;
+    /// stopped backends are left alone and pick up the changes on their next
+    /// spawn. Returns whether a restart began.
+    pub fn restart_backend(self: &Arc<Self>, hostname: &str) -> bool {
+        if self.get_state(hostname) == BackendState::Stopped {
+            return false;
+        }
+        info!(hostname, "Restarting backend");
+        let manager = Arc::clone(self);
+        let hostname_owned = hostname.to_string();
+        self.restarting.insert(hostname_owned.clone());
+        tokio::spawn(async move {
+            manager.stop_backend(&hostname_owned).await;
+            let started = manager.start_backend(&hostname_owned).await;
+            manager.restarting.remove(&hostname_owned);
+            if let Err(e) = started {
+                error!(hostname = %hostname_owned, error = %e, "Failed to restart backend");
+            }
+        });
+        true
     }
 
     /// Spawn an auto-restart for an unhealthy backend
@@ -432,15 +1476,29 @@ impl ProcessManager {
         defaults: &BackendDefaults,
     ) {
         let health_path = config.health_path(defaults);
-        let health_url = format!("http://127.0.0.1:{}{}", config.port, health_path);
+        let scheme = if config.backend_tls { "https" } else { "http" };
+        let health_url = format!("{}://127.0.0.1:{}{}", scheme, config.port, health_path);
         let startup_interval = config.health_check_interval(defaults);
         let ready_interval = config.ready_health_check_interval(defaults);
         let timeout = config.startup_timeout(defaults);
         let unhealthy_threshold = config.unhealthy_threshold(defaults);
+        let max_lifetime = config.max_lifetime(defaults);
+        let drain_timeout = config.drain_timeout(defaults);
         let start = Instant::now();
 
         debug!(hostname, %health_url, "Starting health check polling");
 
+        // Dependencies are started alongside so their gates can clear
+        for dependency in &config.readiness.depends_on {
+            if self.get_state(dependency) == BackendState::Stopped {
+                info!(hostname, dependency, "Starting dependency");
+                self.spawn_start(dependency);
+            }
+        }
+
+        // Waiting on a human is not a startup failure, so the timeout is paused
+        let mut awaiting_approval = false;
+
         // Phase 1: Wait for backend to become ready
         loop {
             let state = self.get_state(hostname);
@@ -453,24 +1511,32 @@ impl ProcessManager {
             }
 
             // Check startup timeout
-            if start.elapsed() > timeout {
+            if start.elapsed() > timeout && !awaiting_approval {
                 error!(hostname, "Backend startup timeout exceeded");
                 self.stop_backend(hostname).await;
                 return;
             }
 
             // Try to connect to the health endpoint
-            match self.check_health(&health_url).await {
-                Ok(true) => {
-                    if self.mark_ready(hostname) {
-                        break; // Continue to phase 2
+            match self.probe_health(hostname, &health_url, config.signing_secret.as_deref()).await {
+                Ok(true) => match self.pending_gate(hostname) {
+                    None => {
+                        if self.mark_ready(hostname) {
+                            break; // Continue to phase 2
+                        }
                     }
-                }
+                    Some(gate) => {
+                        debug!(hostname, %gate, "Health check passed, waiting for readiness gate");
+                        awaiting_approval = gate == PendingGate::ManualApproval;
+                    }
+                },
                 Ok(false) => {
                     debug!(hostname, "Health check returned unhealthy");
+                    awaiting_approval = false;
                 }
                 Err(e) => {
                     debug!(hostname, error = %e, "Health check failed");
+                    awaiting_approval = false;
                 }
             }
 
@@ -503,8 +1569,20 @@ impl ProcessManager {
                 }
             }
 
+            if self.memory_limit_exceeded(hostname) {
+                warn!(hostname, memory = ?config.memory, "Backend exceeded its memory limit, restarting");
+                self.spawn_auto_restart(hostname);
+                return; // New poll_health task will be spawned by start_backend
+            }
+
+            if max_lifetime.is_some_and(|lifetime| self.lifetime_exceeded(hostname, lifetime, drain_timeout)) {
+                info!(hostname, ?max_lifetime, "Backend reached its maximum lifetime");
+                self.restart_backend(hostname);
+                return; // New poll_health task will be spawned by start_backend
+            }
+
             // Perform health check
-            match self.check_health(&health_url).await {
+            match self.probe_health(hostname, &health_url, config.signing_secret.as_deref()).await {
                 Ok(true) => {
                     // Health check passed
                     self.reset_health_failures(hostname);
@@ -524,10 +1602,22 @@ impl ProcessManager {
         }
     }
 
-    /// Check the health endpoint with actual HTTP request
-    async fn check_health(&self, url: &str) -> anyhow::Result<bool> {
+    /// Check a backend's health with the probe if one is set, over HTTP otherwise
+    async fn probe_health(&self, hostname: &str, url: &str, signing_secret: Option<&str>) -> anyhow::Result<bool> {
+        let probe = self.health_probe.read().clone();
+        match probe {
+            Some(probe) => probe.check(hostname, url).await,
+            None => self.check_health(url, signing_secret).await,
+        }
+    }
+
+    /// Check the health endpoint with actual HTTP request, signed if the backend has a secret
+    async fn check_health(&self, url: &str, signing_secret: Option<&str>) -> anyhow::Result<bool> {
         // Parse URL to extract host:port and path
-        let url_without_scheme = url.strip_prefix("http://").unwrap_or(url);
+        let (tls, url_without_scheme) = match url.strip_prefix("https://") {
+            Some(rest) => (true, rest),
+            None => (false, url.strip_prefix("http://").unwrap_or(url)),
+        };
         let (host_port, path) = url_without_scheme
             .split_once('/')
             .map(|(h, p)| (h, format!("/{}", p)))
@@ -540,27 +1630,27 @@ impl ProcessManager {
         )
         .await;
 
-        let mut stream = match connect_result {
+        let stream = match connect_result {
             Ok(Ok(s)) => s,
             Ok(Err(_)) | Err(_) => return Ok(false),
         };
 
         // Send HTTP GET request
-        let request = format!(
-            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
-            path, host_port
-        );
-
-        if stream.write_all(request.as_bytes()).await.is_err() {
-            return Ok(false);
+        let mut request = format!("GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n", path, host_port);
+        if let Some(secret) = signing_secret {
+            for (name, value) in signing::headers(secret, "GET", &path) {
+                request.push_str(&format!("{}: {}\r\n", name, value));
+            }
         }
+        request.push_str("\r\n");
 
-        // Read response with timeout
+        // Send the request and read the status line with timeout
         let read_result = tokio::time::timeout(Duration::from_secs(2), async {
-            let mut reader = BufReader::new(stream);
-            let mut status_line = String::new();
-            reader.read_line(&mut status_line).await?;
-            Ok::<_, std::io::Error>(status_line)
+            if tls {
+                read_status_line(pool::backend_tls(stream).await?, &request).await
+            } else {
+                read_status_line(stream, &request).await
+            }
         })
         .await;
 
@@ -597,58 +1687,67 @@ impl ProcessManager {
                 Duration::from_secs(defaults.shutdown_grace_period_secs),
             ));
 
-        // Get the in-flight counter before removing the process
-        let in_flight_counter = self.processes.get(hostname).map(|p| {
-            let guard = p.lock();
-            guard.in_flight.clone()
-        });
-
-        // Mark as stopping (if present)
-        if let Some(process) = self.processes.get(hostname) {
-            process.lock().state = BackendState::Stopping;
-        }
+        // Mark as stopping and get the in-flight counter before removing the process
+        let Some((counter, instance)) = self.processes.get(hostname).map(|p| {
+            let mut guard = p.lock();
+            guard.state = BackendState::Stopping;
+            (guard.in_flight.clone(), guard.instance.id.clone())
+        }) else {
+            return;
+        };
 
         // Wait for in-flight requests to drain
-        if let Some(counter) = in_flight_counter {
-            let drain_start = Instant::now();
-            while counter.load(Ordering::SeqCst) > 0 {
-                if drain_start.elapsed() > drain_timeout {
-                    let remaining = counter.load(Ordering::SeqCst);
-                    warn!(
-                        hostname,
-                        remaining,
-                        "Drain timeout exceeded, proceeding with shutdown"
-                    );
-                    break;
-                }
-                tokio::time::sleep(Duration::from_millis(DRAIN_POLL_INTERVAL_MS)).await;
-            }
-            let drained_in = drain_start.elapsed();
-            if drained_in > Duration::from_millis(100) {
-                info!(hostname, drained_in_ms = drained_in.as_millis(), "Drained in-flight requests");
+        let drain_start = Instant::now();
+        while counter.load(Ordering::SeqCst) > 0 {
+            if drain_start.elapsed() > drain_timeout {
+                let remaining = counter.load(Ordering::SeqCst);
+                warn!(
+                    hostname,
+                    remaining,
+                    "Drain timeout exceeded, proceeding with shutdown"
+                );
+                break;
             }
+            tokio::time::sleep(Duration::from_millis(DRAIN_POLL_INTERVAL_MS)).await;
+        }
+        let drained_in = drain_start.elapsed();
+        if drained_in > Duration::from_millis(100) {
+            info!(hostname, drained_in_ms = drained_in.as_millis(), "Drained in-flight requests");
         }
 
-        // Remove and extract the process handle
+        // Remove and extract the process handle, unless a concurrent stop got to it first
         let backend = {
-            let Some((_, process)) = self.processes.remove(hostname) else {
+            let removed = self.processes.remove_if(hostname, |_, p| p.lock().instance.id == instance);
+            let Some((_, process)) = removed else {
                 return;
             };
             process.into_inner()
         };
+        self.shutting_down.insert(hostname.to_string());
 
         match backend.handle {
-            ProcessHandle::Local(mut child) => {
+            ProcessHandle::Local { mut child, cgroup } => {
                 self.stop_local_process(hostname, &mut child, grace_period).await;
+                if let Some(cgroup) = cgroup {
+                    cgroup.remove().await;
+                }
             }
-            ProcessHandle::Docker { container_id, docker, log_shutdown } => {
+            ProcessHandle::Docker { container_id, runtime, log_shutdown } => {
                 // Stop log streaming first
                 if let Some(shutdown) = log_shutdown {
                     let _ = shutdown.send(true);
                 }
-                self.stop_docker_container(hostname, &container_id, &docker, grace_period).await;
+                self.stop_docker_container(hostname, &container_id, runtime.as_ref(), grace_period).await;
+            }
+            ProcessHandle::Custom { id, spawner } => {
+                info!(hostname, id = %id, "Stopping custom backend");
+                spawner.stop(hostname, &id, grace_period).await;
             }
         }
+        self.shutting_down.remove(hostname);
+        self.ports.release(hostname);
+        self.uptime.stopped(hostname);
+        self.emit(BackendEvent::Stopped { hostname: hostname.to_string() });
     }
 
     /// Stop a local process
@@ -694,42 +1793,51 @@ impl ProcessManager {
         &self,
         hostname: &str,
         container_id: &str,
-        docker: &DockerManager,
+        runtime: &dyn ContainerRuntime,
         grace_period: Duration,
     ) {
-        info!(hostname, container_id, "Stopping Docker container");
+        info!(hostname, container_id, runtime = runtime.name(), "Stopping Docker container");
 
         // docker stop sends SIGTERM and waits
-        if let Err(e) = docker.stop_container(container_id, grace_period).await {
+        if let Err(e) = runtime.stop_container(container_id, grace_period).await {
             warn!(hostname, container_id, error = %e, "Error stopping container, forcing kill");
-            let _ = docker.kill_container(container_id).await;
+            let _ = runtime.kill_container(container_id).await;
         }
 
         // Remove the container
-        if let Err(e) = docker.remove_container(container_id).await {
+        if let Err(e) = runtime.remove_container(container_id).await {
             warn!(hostname, container_id, error = %e, "Error removing container");
         }
     }
 
     /// Check for idle backends and stop them
+    ///
+    /// Backends removed from the configuration while they were starting are
+    /// stopped as well.
     pub async fn cleanup_idle_backends(&self) {
         let mut to_stop = Vec::new();
         let defaults = self.get_defaults();
 
         for entry in self.processes.iter() {
             let hostname = entry.key();
-            let guard = entry.value().lock();
+            let mut guard = entry.value().lock();
 
             if guard.state != BackendState::Ready {
                 continue;
             }
 
             let config = match self.get_config(hostname) {
-                Some(c) => c,
-                None => continue,
+                Some(c) if !c.keep_warm() => c,
+                Some(_) => continue,
+                None => {
+                    info!(hostname, "Stopping backend that is no longer configured");
+                    guard.state = BackendState::Stopping;
+                    to_stop.push(hostname.clone());
+                    continue;
+                }
             };
 
-            let idle_timeout = config.idle_timeout(&defaults);
+            let idle_timeout = self.effective_idle_timeout(hostname, &config, &defaults);
             let idle_duration = guard.last_activity.elapsed();
 
             if idle_duration > idle_timeout {
@@ -738,15 +1846,125 @@ impl ProcessManager {
                     idle_secs = idle_duration.as_secs(),
                     "Backend idle timeout reached"
                 );
+                // Marked under the same lock as the check, so no request is
+                // admitted between deciding to stop the backend and stopping it
+                guard.state = BackendState::Stopping;
                 to_stop.push(hostname.clone());
             }
         }
 
+        to_stop.sort();
         for hostname in to_stop {
             self.stop_backend(&hostname).await;
         }
     }
 
+    /// Stop idle backends and start `keep_warm` ones every `interval` until shutdown
+    pub async fn run_idle_cleanup(self: Arc<Self>, interval: Duration, mut shutdown_rx: watch::Receiver<bool>) {
+        self.spawn_warm_backends();
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {
+                    self.cleanup_idle_backends().await;
+                    self.spawn_warm_backends();
+                }
+                _ = shutdown_rx.changed() => {
+                    if *shutdown_rx.borrow() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Start `keep_warm` backends in the background, so slow spawns hold up
+    /// neither the next idle check nor shutdown
+    fn spawn_warm_backends(self: &Arc<Self>) {
+        let manager = Arc::clone(self);
+        tokio::spawn(async move { manager.start_warm_backends().await });
+    }
+
+    /// Start every `keep_warm` backend that isn't running, all at once
+    ///
+    /// Called at startup and with every idle check, so a warm backend that
+    /// crashed, was stopped or was added on reload is started again.
+    pub async fn start_warm_backends(self: &Arc<Self>) {
+        let mut warm: Vec<String> = self
+            .configs
+            .read()
+            .iter()
+            .filter(|(_, config)| config.keep_warm())
+            .map(|(hostname, _)| hostname.clone())
+            .collect();
+        warm.sort();
+
+        let starts = warm
+            .into_iter()
+            .filter(|hostname| self.get_state(hostname) == BackendState::Stopped)
+            .map(|hostname| async move {
+                info!(hostname, "Starting warm backend");
+                if let Err(e) = self.start_backend(&hostname).await {
+                    error!(hostname, error = %e, "Failed to start warm backend");
+                }
+            });
+        join_all(starts).await;
+    }
+
+    /// Idle timeout applied to a backend: the learned one when adaptive idle
+    /// is enabled and enough traffic was seen, the configured one otherwise
+    fn effective_idle_timeout(&self, hostname: &str, config: &BackendConfig, defaults: &BackendDefaults) -> Duration {
+        config
+            .adaptive_idle(defaults)
+            .then(|| self.idle_predictor.learned(hostname, &defaults.adaptive_idle))
+            .flatten()
+            .unwrap_or_else(|| config.idle_timeout(defaults))
+    }
+
+    /// Configured, learned and applied idle timeout of every backend
+    pub fn idle_timeouts(&self) -> Vec<IdleTimeoutStatus> {
+        let defaults = self.get_defaults();
+        let configs = self.configs.read();
+        let mut timeouts: Vec<IdleTimeoutStatus> = configs
+            .iter()
+            .map(|(hostname, config)| {
+                let adaptive = config.adaptive_idle(&defaults);
+                IdleTimeoutStatus {
+                    hostname: hostname.clone(),
+                    adaptive,
+                    configured_secs: config.idle_timeout(&defaults).as_secs(),
+                    learned_secs: adaptive
+                        .then(|| self.idle_predictor.learned(hostname, &defaults.adaptive_idle))
+                        .flatten()
+                        .map(|t| t.as_secs()),
+                    effective_secs: self.effective_idle_timeout(hostname, config, &defaults).as_secs(),
+                    samples: self.idle_predictor.samples(hostname),
+                    keep_warm: config.keep_warm(),
+                }
+            })
+            .collect();
+        timeouts.sort_by(|a, b| a.hostname.cmp(&b.hostname));
+        timeouts
+    }
+
+    /// The last `count` output lines of a backend, oldest first
+    pub fn logs(&self, hostname: &str, count: usize) -> Vec<LogLine> {
+        self.logs.tail(hostname, count)
+    }
+
+    /// Compute saved by keeping backends stopped while they were idle
+    pub fn savings_report(&self) -> SavingsReport {
+        let defaults = self.get_defaults();
+        let configs = self.configs.read();
+        let backends = configs
+            .iter()
+            .filter_map(|(hostname, config)| {
+                let usage = self.uptime.usage(hostname)?;
+                Some(BackendSavings::new(hostname, usage, config, &defaults.cost))
+            })
+            .collect();
+        SavingsReport::new(backends)
+    }
+
     /// Stop all backends
     pub async fn stop_all(&self) {
         let hostnames: Vec<String> = self.processes.iter().map(|e| e.key().clone()).collect();
@@ -756,13 +1974,31 @@ impl ProcessManager {
     }
 
     /// Get the port for a backend
+    ///
+    /// For backends with `port = 0`, the port assigned to the running
+    /// instance, or None while stopped.
     pub fn get_backend_port(&self, hostname: &str) -> Option<u16> {
-        self.get_config(hostname).map(|c| c.port)
+        let config = self.get_config(hostname)?;
+        Some(self.backend_port(hostname, &config)).filter(|&port| port != 0)
+    }
+
+    /// The configured port, or the one assigned to the running instance (0 while stopped)
+    fn backend_port(&self, hostname: &str, config: &BackendConfig) -> u16 {
+        match config.port {
+            0 => self.ports.assigned(hostname).unwrap_or(0),
+            port => port,
+        }
+    }
+
+    /// Fixed ports of all backends, which are never assigned to others
+    fn configured_ports(&self) -> HashSet<u16> {
+        self.configs.read().values().map(|config| config.port).filter(|&port| port != 0).collect()
     }
 
     /// List all backends and their current status
     pub fn list_backends(&self) -> Vec<BackendStatus> {
         let configs = self.configs.read();
+        let defaults = self.defaults.read();
         configs
             .keys()
             .map(|hostname| {
@@ -779,13 +2015,166 @@ impl ProcessManager {
                 BackendStatus {
                     hostname: hostname.clone(),
                     state,
-                    port: config.port,
+                    port: self.backend_port(hostname, config),
                     in_flight,
+                    queued: self.get_queued(hostname),
+                    queued_by_class: self.queue_stats(hostname),
+                    max_queue_depth: config.max_queue_depth(&defaults),
+                    cancelled: self.get_cancelled(hostname),
+                    labels: config.labels.clone(),
                 }
             })
             .collect()
     }
 
+    /// Whether a backend's labels match a selector
+    pub fn backend_matches(&self, hostname: &str, selector: &Selector) -> bool {
+        self.configs
+            .read()
+            .get(hostname)
+            .is_some_and(|config| selector.matches(&config.labels))
+    }
+
+    /// Hostnames of the backends whose labels match a selector, sorted
+    pub fn select_backends(&self, selector: &Selector) -> Vec<String> {
+        let mut hostnames: Vec<String> = self
+            .configs
+            .read()
+            .iter()
+            .filter(|(_, config)| selector.matches(&config.labels))
+            .map(|(hostname, _)| hostname.clone())
+            .collect();
+        hostnames.sort();
+        hostnames
+    }
+
+    /// Describe what promoting `source` to `target` would change
+    pub fn review_promotion(&self, source: &str, target: &str) -> Result<PromotionReview, String> {
+        let configs = self.configs.read();
+        let source_config = configs
+            .get(source)
+            .ok_or_else(|| format!("Unknown backend '{}'", source))?;
+        let target_config = configs
+            .get(target)
+            .ok_or_else(|| format!("Unknown backend '{}'", target))?;
+        promotion::review(source, source_config, target, target_config)
+    }
+
+    /// Copy `source`'s artifact to `target` and restart `target` if it is running
+    ///
+    /// The change applies to the running configuration; it does not survive a
+    /// config reload or restart unless the config file is updated as well.
+    pub async fn promote(&self, source: &str, target: &str) -> Result<PromotionRecord, String> {
+        let review = {
+            let mut configs = self.configs.write();
+            let source_config = configs
+                .get(source)
+                .ok_or_else(|| format!("Unknown backend '{}'", source))?;
+            let target_config = configs
+                .get(target)
+                .ok_or_else(|| format!("Unknown backend '{}'", target))?;
+            let review = promotion::review(source, source_config, target, target_config)?;
+            if let Some(target_config) = configs.get_mut(target) {
+                review.proposed.apply_to(target_config);
+            }
+            review
+        };
+
+        let record = self.promotions.record(&review);
+        info!(source, target, id = record.id, "Promoted backend artifact");
+
+        // Next request cold-starts the target with the promoted artifact
+        if self.get_state(target) != BackendState::Stopped {
+            self.stop_backend(target).await;
+        }
+
+        Ok(record)
+    }
+
+    /// Recent promotions, oldest first
+    pub fn promotion_history(&self) -> Vec<PromotionRecord> {
+        self.promotions.list()
+    }
+
+    /// Whether the backend was defined via the admin API
+    pub fn is_api_backend(&self, hostname: &str) -> bool {
+        self.api_backends.contains(hostname)
+    }
+
+    /// Add or replace a backend defined via the admin API, returning the
+    /// previous definition
+    ///
+    /// `precondition` is checked against the current definition under the
+    /// same lock as the change, so of two requests with the same `If-Match`
+    /// only one succeeds. An identical definition is left as it is. Like on
+    /// a reload, a changed backend keeps running with its old settings until
+    /// it next starts. Reloads keep the backend until it's removed with
+    /// [`remove_api_backend`](Self::remove_api_backend).
+    pub fn put_api_backend(
+        &self,
+        hostname: &str,
+        config: BackendConfig,
+        precondition: &Precondition,
+    ) -> Result<Option<BackendConfig>, BackendChangeError> {
+        let mut configs = self.configs.write();
+        let current = configs.get(hostname);
+        if !precondition.holds(current.map(BackendConfig::etag).as_deref()) {
+            return Err(BackendChangeError::PreconditionFailed);
+        }
+        if current.is_some() && !self.api_backends.contains(hostname) {
+            return Err(BackendChangeError::Configured);
+        }
+        let definition = serde_json::to_value(&config).unwrap_or_default();
+        if current.is_some_and(|current| serde_json::to_value(current).unwrap_or_default() == definition) {
+            return Ok(current.cloned());
+        }
+
+        let previous = configs.insert(hostname.to_string(), config);
+        self.api_backends.insert(hostname.to_string());
+        drop(configs);
+        if previous.is_some() {
+            self.approvals.remove(hostname);
+            self.faults.disable(hostname);
+            info!(hostname, "Updating backend (admin API)");
+        } else {
+            self.uptime.track(hostname);
+            info!(hostname, "Adding backend (admin API)");
+        }
+        Ok(previous)
+    }
+
+    /// Stop and remove a backend defined via the admin API, returning its
+    /// definition
+    ///
+    /// `precondition` is checked like for [`put_api_backend`](Self::put_api_backend).
+    pub async fn remove_api_backend(
+        &self,
+        hostname: &str,
+        precondition: &Precondition,
+    ) -> Result<Option<BackendConfig>, BackendChangeError> {
+        {
+            let configs = self.configs.write();
+            let current = configs.get(hostname);
+            if !precondition.holds(current.map(BackendConfig::etag).as_deref()) {
+                return Err(BackendChangeError::PreconditionFailed);
+            }
+            if self.api_backends.remove(hostname).is_none() {
+                return match current {
+                    Some(_) => Err(BackendChangeError::Configured),
+                    None => Ok(None),
+                };
+            }
+        }
+        info!(hostname, "Removing backend (admin API)");
+        self.stop_backend(hostname).await;
+        self.idle_predictor.forget(hostname);
+        self.uptime.forget(hostname);
+        self.logs.forget(hostname);
+        self.approvals.remove(hostname);
+        self.faults.disable(hostname);
+        Ok(self.configs.write().remove(hostname))
+    }
+
     /// Reload configuration from a file
     ///
     /// This updates backend configurations without restarting the proxy.
@@ -805,8 +2194,26 @@ impl ProcessManager {
         new_backends: HashMap<String, BackendConfig>,
         new_defaults: BackendDefaults,
     ) -> anyhow::Result<ReloadResult> {
+        let _reload = self.reload_lock.lock().await;
         let mut result = ReloadResult::default();
 
+        // Backends defined via the admin API stay, unless the configuration
+        // now defines them itself
+        let mut new_backends = new_backends;
+        {
+            let configs = self.configs.read();
+            self.api_backends.retain(|hostname| {
+                if new_backends.contains_key(hostname) {
+                    warn!(hostname, "Backend defined via the admin API is now configured; using the configuration");
+                    return false;
+                }
+                if let Some(config) = configs.get(hostname) {
+                    new_backends.insert(hostname.clone(), config.clone());
+                }
+                true
+            });
+        }
+
         // Get current backend hostnames
         let current_hostnames: Vec<String> = {
             let configs = self.configs.read();
@@ -816,16 +2223,20 @@ impl ProcessManager {
         let new_hostnames: std::collections::HashSet<&String> = new_backends.keys().collect();
 
         // Find backends to remove (in current but not in new)
-        let to_remove: Vec<String> = current_hostnames
+        let mut to_remove: Vec<String> = current_hostnames
             .iter()
             .filter(|h| !new_hostnames.contains(h))
             .cloned()
             .collect();
+        to_remove.sort();
 
         // Stop removed backends
         for hostname in &to_remove {
             info!(hostname, "Removing backend (config reload)");
             self.stop_backend(hostname).await;
+            self.idle_predictor.forget(hostname);
+            self.uptime.forget(hostname);
+            self.logs.forget(hostname);
             result.removed.push(hostname.clone());
         }
 
@@ -833,12 +2244,20 @@ impl ProcessManager {
         for hostname in new_backends.keys() {
             if !current_hostnames.contains(hostname) {
                 result.added.push(hostname.clone());
+                self.uptime.track(hostname);
                 info!(hostname, "Adding backend (config reload)");
             } else {
                 result.updated.push(hostname.clone());
             }
         }
 
+        // A reload is a new deploy: manual gates must be approved again and
+        // faults enabled against the old config stop
+        for hostname in result.updated.iter().chain(&result.removed) {
+            self.approvals.remove(hostname);
+            self.faults.disable(hostname);
+        }
+
         // Update configs atomically
         {
             let mut configs = self.configs.write();
@@ -862,6 +2281,53 @@ impl ProcessManager {
     }
 }
 
+/// Send a raw HTTP/1.1 request and read the response's status line
+async fn read_status_line<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, request: &str) -> std::io::Result<String> {
+    stream.write_all(request.as_bytes()).await?;
+    let mut reader = BufReader::new(stream);
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line).await?;
+    Ok(status_line)
+}
+
+/// `If-Match` and `If-None-Match` conditions on a backend's definition
+#[derive(Debug, Clone, Default)]
+pub struct Precondition {
+    pub if_match: Option<String>,
+    pub if_none_match: Option<String>,
+}
+
+impl Precondition {
+    /// Whether the conditions hold for a definition with `etag`, `None` if
+    /// there is none; `*` matches any definition
+    pub fn holds(&self, etag: Option<&str>) -> bool {
+        let listed = |header: &str| {
+            etag.is_some_and(|etag| header.split(',').map(str::trim).any(|tag| tag == "*" || tag == etag))
+        };
+        self.if_match.as_deref().is_none_or(listed) && !self.if_none_match.as_deref().is_some_and(listed)
+    }
+}
+
+/// Why a backend defined via the admin API was left unchanged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendChangeError {
+    /// The [`Precondition`] doesn't hold for the current definition
+    PreconditionFailed,
+    /// The backend is defined by the configuration
+    Configured,
+}
+
+impl fmt::Display for BackendChangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendChangeError::PreconditionFailed => write!(f, "precondition failed"),
+            BackendChangeError::Configured => write!(f, "backend is defined by the configuration"),
+        }
+    }
+}
+
+impl std::error::Error for BackendChangeError {}
+
 /// Result of a configuration reload operation
 #[derive(Debug, Clone, Default)]
 pub struct ReloadResult {
@@ -873,6 +2339,20 @@ pub struct ReloadResult {
     pub updated: Vec<String>,
 }
 
+/// Where and how the upstream stage forwards requests for a backend
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpstreamTarget {
+    pub port: u16,
+    pub request_timeout: Duration,
+    /// Root for internal redirects if enabled (`Some(None)` allows only
+    /// re-dispatching to other backends)
+    pub redirect_root: Option<Option<PathBuf>>,
+    pub deadline_headers: bool,
+    pub protocol: BackendProtocol,
+    /// Rewrites applied to the response, if `response_rewrites` are configured
+    pub rewriter: Option<Rewriter>,
+}
+
 /// Status information for a backend
 #[derive(Debug, Clone)]
 pub struct BackendStatus {
@@ -884,11 +2364,22 @@ pub struct BackendStatus {
     pub port: u16,
     /// Number of in-flight requests
     pub in_flight: usize,
+    /// Requests waiting for the backend to become ready
+    pub queued: usize,
+    /// Queue counts per priority class
+    pub queued_by_class: BTreeMap<PriorityClass, ClassStats>,
+    /// Most requests that may wait, if limited
+    pub max_queue_depth: Option<usize>,
+    /// Requests cancelled by client disconnects since startup
+    pub cancelled: u64,
+    /// Labels from the backend configuration
+    pub labels: HashMap<String, String>,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::atomic::AtomicBool;
 
     fn create_test_config() -> BackendConfig {
         BackendConfig::local("echo", 3000).with_args(vec!["hello".to_string()])
@@ -909,6 +2400,77 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_instance_env() {
+        let manager = create_test_manager();
+        let config = manager.get_config("example.com").unwrap();
+        let instance = InstanceMetadata::new(3);
+
+        let env: HashMap<String, String> = manager
+            .instance_env("example.com", &config, &instance)
+            .into_iter()
+            .collect();
+        assert_eq!(env["PORT"], "3000");
+        assert_eq!(env["INSTANCE_ID"], instance.id);
+        assert_eq!(env["INSTANCE_INDEX"], "3");
+        assert_eq!(env["SPAWNGATE_BACKEND"], "example.com");
+        assert_eq!(env["PUBLIC_URL"], "http://example.com");
+        assert_eq!(
+            env["ADMIN_CALLBACK_URL"],
+            format!("http://127.0.0.1:9999/ready/example.com?token={}", instance.token)
+        );
+        assert_eq!(env["SERVERLESS_PROXY_READY_URL"], env["ADMIN_CALLBACK_URL"]);
+        assert!(!env.contains_key("SPAWNGATE_SIGNING_SECRET"));
+    }
+
+    #[tokio::test]
+    async fn test_signed_health_check() {
+        let manager = create_test_manager();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/health", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 4096];
+            let n = tokio::io::AsyncReadExt::read(&mut stream, &mut request).await.unwrap();
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await.unwrap();
+            String::from_utf8_lossy(&request[..n]).into_owned()
+        });
+
+        assert!(manager.check_health(&url, Some("s3cret")).await.unwrap());
+        let request = server.await.unwrap();
+        let header = |name: &str| {
+            request
+                .lines()
+                .find_map(|line| line.strip_prefix(&format!("{}: ", name)))
+                .unwrap()
+                .to_string()
+        };
+        assert!(signing::verify(
+            "s3cret",
+            "GET",
+            "/health",
+            &header(signing::TIMESTAMP_HEADER),
+            &header(signing::SIGNATURE_HEADER),
+            std::time::SystemTime::now(),
+            Duration::from_secs(60),
+        ));
+    }
+
+    #[test]
+    fn test_public_url() {
+        let manager = create_test_manager();
+        let mut config = manager.get_config("example.com").unwrap();
+
+        manager.set_public_endpoint("https", 443);
+        assert_eq!(manager.public_url("example.com", &config), "https://example.com");
+
+        manager.set_public_endpoint("http", 8080);
+        assert_eq!(manager.public_url("example.com", &config), "http://example.com:8080");
+
+        config.public_url = Some("https://cdn.example.net/app".to_string());
+        assert_eq!(manager.public_url("example.com", &config), "https://cdn.example.net/app");
+    }
+
     #[test]
     fn test_has_backend() {
         let manager = create_test_manager();
@@ -933,6 +2495,49 @@ mod tests {
         assert!(manager.get_config("unknown.com").is_none());
     }
 
+    #[tokio::test]
+    async fn test_api_backend_preconditions() {
+        let manager = create_test_manager();
+        let create = Precondition {
+            if_none_match: Some("*".to_string()),
+            ..Default::default()
+        };
+        let backend = BackendConfig::local("echo", 5000);
+        assert!(manager.put_api_backend("new.example.com", backend.clone(), &create).unwrap().is_none());
+        assert_eq!(
+            manager.put_api_backend("new.example.com", backend.clone(), &create).err(),
+            Some(BackendChangeError::PreconditionFailed)
+        );
+
+        // Two changes made against the same ETag: only the first applies
+        let seen = Precondition {
+            if_match: Some(backend.etag()),
+            ..Default::default()
+        };
+        let first = BackendConfig::local("echo", 5001);
+        assert!(manager.put_api_backend("new.example.com", first, &seen).unwrap().is_some());
+        assert_eq!(
+            manager.put_api_backend("new.example.com", BackendConfig::local("echo", 5002), &seen).err(),
+            Some(BackendChangeError::PreconditionFailed)
+        );
+        assert_eq!(manager.get_config("new.example.com").unwrap().port, 5001);
+        assert_eq!(
+            manager.remove_api_backend("new.example.com", &seen).await.err(),
+            Some(BackendChangeError::PreconditionFailed)
+        );
+
+        assert_eq!(
+            manager.put_api_backend("example.com", backend, &Precondition::default()).err(),
+            Some(BackendChangeError::Configured)
+        );
+        assert_eq!(
+            manager.remove_api_backend("example.com", &Precondition::default()).await.err(),
+            Some(BackendChangeError::Configured)
+        );
+        assert!(manager.remove_api_backend("new.example.com", &Precondition::default()).await.unwrap().is_some());
+        assert!(!manager.has_backend("new.example.com"));
+    }
+
     #[test]
     fn test_get_backend_port() {
         let manager = create_test_manager();
@@ -1008,6 +2613,173 @@ mod tests {
         assert_eq!(manager.get_state("test.com"), BackendState::Stopped);
     }
 
+    #[tokio::test]
+    async fn test_lifecycle_events() {
+        let mut configs = HashMap::new();
+        let mut cfg = BackendConfig::local("sleep", 5003);
+        cfg.args = vec!["60".to_string()];
+        cfg.health_check_interval_ms = Some(50);
+        cfg.shutdown_grace_period_secs = Some(1);
+        cfg.drain_timeout_secs = Some(1);
+        configs.insert("test.com".to_string(), cfg);
+        configs.insert("missing.com".to_string(), BackendConfig::local("/nonexistent/command", 5004));
+
+        let manager = ProcessManager::new(configs, BackendDefaults::default(), String::new());
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&events);
+        manager.on_event(move |event| recorded.lock().push(event.clone()));
+
+        manager.start_backend("test.com").await.unwrap();
+        assert!(manager.mark_ready("test.com"));
+        manager.mark_unhealthy("test.com");
+        manager.stop_backend("test.com").await;
+        assert!(manager.start_backend("missing.com").await.is_err());
+
+        let hostname = "test.com".to_string();
+        let events = events.lock().clone();
+        assert_eq!(
+            events[..4],
+            [
+                BackendEvent::Started { hostname: hostname.clone() },
+                BackendEvent::Ready { hostname: hostname.clone() },
+                BackendEvent::Unhealthy { hostname: hostname.clone() },
+                BackendEvent::Stopped { hostname },
+            ]
+        );
+        assert!(matches!(&events[4], BackendEvent::StartFailed { hostname, .. } if hostname == "missing.com"));
+    }
+
+    /// Records what it was asked to do instead of launching anything
+    #[derive(Default)]
+    struct RecordingSpawner {
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl Spawner for RecordingSpawner {
+        fn start<'a>(
+            &'a self,
+            hostname: &'a str,
+            _config: &'a BackendConfig,
+            env: &'a [(String, String)],
+        ) -> BoxFuture<'a, anyhow::Result<String>> {
+            let port = env.iter().find(|(k, _)| k == "PORT").map(|(_, v)| v.clone());
+            self.calls.lock().push(format!("start {} {:?}", hostname, port));
+            async { Ok("vm-1".to_string()) }.boxed()
+        }
+
+        fn stop<'a>(&'a self, hostname: &'a str, id: &'a str, _grace_period: Duration) -> BoxFuture<'a, ()> {
+            self.calls.lock().push(format!("stop {} {}", hostname, id));
+            async {}.boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_spawner() {
+        let mut configs = HashMap::new();
+        let mut cfg = BackendConfig::custom("vm", 5005);
+        cfg.health_check_interval_ms = Some(50);
+        cfg.drain_timeout_secs = Some(1);
+        configs.insert("vm.com".to_string(), cfg);
+        configs.insert("other.com".to_string(), BackendConfig::custom("unregistered", 5006));
+
+        let manager = ProcessManager::new(configs, BackendDefaults::default(), String::new());
+        let spawner = Arc::new(RecordingSpawner::default());
+        manager.register_spawner("vm", spawner.clone());
+
+        manager.start_backend("vm.com").await.unwrap();
+        assert_eq!(manager.get_state("vm.com"), BackendState::Starting);
+        assert!(manager.mark_ready("vm.com"));
+        manager.stop_backend("vm.com").await;
+        assert_eq!(manager.get_state("vm.com"), BackendState::Stopped);
+        assert_eq!(
+            *spawner.calls.lock(),
+            ["start vm.com Some(\"5005\")", "stop vm.com vm-1"]
+        );
+
+        let err = manager.start_backend("other.com").await.unwrap_err();
+        assert!(err.to_string().contains("No spawner registered as 'unregistered'"), "{}", err);
+    }
+
+    /// Never finishes starting an instance
+    struct HangingSpawner;
+
+    impl Spawner for HangingSpawner {
+        fn start<'a>(
+            &'a self,
+            _hostname: &'a str,
+            _config: &'a BackendConfig,
+            _env: &'a [(String, String)],
+        ) -> BoxFuture<'a, anyhow::Result<String>> {
+            futures::future::pending().boxed()
+        }
+
+        fn stop<'a>(&'a self, _hostname: &'a str, _id: &'a str, _grace_period: Duration) -> BoxFuture<'a, ()> {
+            async {}.boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_warm_backends_start_in_background() {
+        let mut hanging = BackendConfig::custom("hanging", 5007);
+        hanging.keep_warm = Some(true);
+        let mut vm = BackendConfig::custom("vm", 5008);
+        vm.keep_warm = Some(true);
+        let configs = HashMap::from([("a.com".to_string(), hanging), ("b.com".to_string(), vm)]);
+        let manager = ProcessManager::new(configs, BackendDefaults::default(), String::new());
+        manager.register_spawner("hanging", Arc::new(HangingSpawner));
+        manager.register_spawner("vm", Arc::new(RecordingSpawner::default()));
+
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let cleanup = tokio::spawn(Arc::clone(&manager).run_idle_cleanup(Duration::from_millis(10), shutdown_rx));
+
+        // A hung spawn holds up neither the other warm backend nor shutdown
+        let started = async {
+            while manager.get_state("b.com") == BackendState::Stopped {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), started).await.unwrap();
+        shutdown_tx.send(true).unwrap();
+        tokio::time::timeout(Duration::from_secs(1), cleanup).await.unwrap().unwrap();
+        assert_eq!(manager.spawning(), ["a.com"]);
+    }
+
+    /// Healthy while the flag is set
+    struct SwitchedProbe(Arc<AtomicBool>);
+
+    impl HealthProbe for SwitchedProbe {
+        fn check<'a>(&'a self, _hostname: &'a str, _url: &'a str) -> BoxFuture<'a, anyhow::Result<bool>> {
+            let healthy = self.0.load(Ordering::SeqCst);
+            async move { Ok(healthy) }.boxed()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failing_health_ends_wait_for_approval() {
+        let mut cfg = BackendConfig::custom("vm", 5009);
+        cfg.health_check_interval_ms = Some(50);
+        cfg.startup_timeout_secs = Some(1);
+        cfg.readiness.manual_approval = true;
+        let manager = ProcessManager::new(
+            HashMap::from([("vm.com".to_string(), cfg)]),
+            BackendDefaults::default(),
+            String::new(),
+        );
+        manager.register_spawner("vm", Arc::new(RecordingSpawner::default()));
+        let healthy = Arc::new(AtomicBool::new(true));
+        manager.set_health_probe(Arc::new(SwitchedProbe(Arc::clone(&healthy))));
+
+        // Waiting for approval outlasts the startup timeout while the backend is healthy
+        manager.start_backend("vm.com").await.unwrap();
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(manager.get_state("vm.com"), BackendState::Starting);
+
+        // but not once its health checks fail
+        healthy.store(false, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(manager.get_state("vm.com"), BackendState::Stopped);
+    }
+
     #[tokio::test]
     async fn test_stop_all_backends() {
         let mut configs = HashMap::new();
@@ -0,0 +1,255 @@
+//! CPU and heap profiles for the admin API
+//!
+//! With `[server.profiling]` enabled, `GET /debug/pprof/profile?seconds=N`
+//! samples the stacks of all threads for `N` seconds and returns an SVG
+//! flamegraph, and `GET /debug/pprof/heap?seconds=N` returns a jemalloc heap
+//! profile (for `jeprof`) of the allocations made in that time that are
+//! still live. `N` defaults to 30 and may not exceed `max_seconds`.
+//!
+//! The profilers are compiled in with the `pprof` and `jemalloc` build
+//! features, on unix only. Both are process-wide, so one profile of each kind
+//! runs at a time.
+
+use crate::config::ProfilingConfig;
+use hyper::StatusCode;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Profile length when `?seconds=` is not given
+pub const DEFAULT_SECONDS: u64 = 30;
+
+/// Stack samples per second of a CPU profile
+#[cfg(all(unix, feature = "pprof"))]
+const CPU_FREQUENCY: i32 = 99;
+
+/// Why a profile could not be taken
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProfileError {
+    /// The build lacks the feature the profile needs, or isn't for unix
+    Unsupported(&'static str),
+    /// `?seconds=` isn't a whole number between 1 and `max_seconds`
+    InvalidSeconds(u64),
+    /// A profile of the same kind is being taken
+    Busy,
+    /// The process spent no CPU time while it was profiled
+    NoSamples,
+    Failed(String),
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileError::Unsupported(feature) => {
+                write!(f, "profile not available: spawngate was built without the '{}' feature (unix only)", feature)
+            }
+            ProfileError::InvalidSeconds(max) => write!(f, "'seconds' must be between 1 and {}", max),
+            ProfileError::Busy => write!(f, "a profile is already being taken"),
+            ProfileError::NoSamples => write!(f, "no samples were taken; the proxy was idle"),
+            ProfileError::Failed(error) => write!(f, "profiling failed: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {}
+
+impl ProfileError {
+    /// Status of the admin API response
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ProfileError::Unsupported(_) => StatusCode::NOT_IMPLEMENTED,
+            ProfileError::InvalidSeconds(_) => StatusCode::BAD_REQUEST,
+            ProfileError::Busy => StatusCode::CONFLICT,
+            ProfileError::NoSamples => StatusCode::NOT_FOUND,
+            ProfileError::Failed(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Takes bounded CPU and heap profiles, one of each kind at a time
+pub struct Profiler {
+    max_seconds: u64,
+    cpu_running: AtomicBool,
+    heap_running: AtomicBool,
+}
+
+impl Profiler {
+    pub fn new(config: &ProfilingConfig) -> Self {
+        Self {
+            max_seconds: config.max_seconds,
+            cpu_running: AtomicBool::new(false),
+            heap_running: AtomicBool::new(false),
+        }
+    }
+
+    /// Profile length from the `seconds` query parameter
+    pub fn duration(&self, seconds: Option<&str>) -> Result<Duration, ProfileError> {
+        let seconds = match seconds {
+            None => DEFAULT_SECONDS.min(self.max_seconds),
+            Some(value) => value.parse().map_err(|_| ProfileError::InvalidSeconds(self.max_seconds))?,
+        };
+        if seconds == 0 || seconds > self.max_seconds {
+            return Err(ProfileError::InvalidSeconds(self.max_seconds));
+        }
+        Ok(Duration::from_secs(seconds))
+    }
+
+    /// SVG flamegraph of the CPU time spent during `duration`
+    pub async fn cpu(&self, duration: Duration) -> Result<Vec<u8>, ProfileError> {
+        let _running = Running::claim(&self.cpu_running)?;
+        cpu_flamegraph(duration).await
+    }
+
+    /// jemalloc heap profile of the live allocations made during `duration`
+    pub async fn heap(&self, duration: Duration) -> Result<Vec<u8>, ProfileError> {
+        let _running = Running::claim(&self.heap_running)?;
+        heap_profile(duration).await
+    }
+}
+
+/// Marks a profile as running until dropped, including when the request is cancelled
+struct Running<'a>(&'a AtomicBool);
+
+impl<'a> Running<'a> {
+    fn claim(flag: &'a AtomicBool) -> Result<Self, ProfileError> {
+        flag.compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .map_err(|_| ProfileError::Busy)?;
+        Ok(Self(flag))
+    }
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(all(unix, feature = "pprof"))]
+async fn cpu_flamegraph(duration: Duration) -> Result<Vec<u8>, ProfileError> {
+    let failed = |e: pprof::Error| ProfileError::Failed(e.to_string());
+    let guard = pprof::ProfilerGuardBuilder::default()
+        .frequency(CPU_FREQUENCY)
+        .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+        .build()
+        .map_err(failed)?;
+    tokio::time::sleep(duration).await;
+
+    let report = guard.report().build().map_err(failed)?;
+    if report.data.is_empty() {
+        return Err(ProfileError::NoSamples);
+    }
+    let mut svg = Vec::new();
+    report.flamegraph(&mut svg).map_err(failed)?;
+    Ok(svg)
+}
+
+#[cfg(not(all(unix, feature = "pprof")))]
+async fn cpu_flamegraph(_duration: Duration) -> Result<Vec<u8>, ProfileError> {
+    Err(ProfileError::Unsupported("pprof"))
+}
+
+#[cfg(all(unix, feature = "jemalloc"))]
+async fn heap_profile(duration: Duration) -> Result<Vec<u8>, ProfileError> {
+    use std::os::unix::ffi::OsStrExt;
+    use tikv_jemalloc_ctl::{profiling, raw};
+
+    let failed = |e: tikv_jemalloc_ctl::Error| ProfileError::Failed(e.to_string());
+    if !profiling::prof::read().map_err(failed)? {
+        return Err(ProfileError::Failed("jemalloc was started without 'prof:true'".to_string()));
+    }
+
+    /// Stops sampling allocations when dropped, including when the request is cancelled
+    struct Active;
+
+    impl Drop for Active {
+        fn drop(&mut self) {
+            // SAFETY: `prof.active` takes a bool
+            let _ = unsafe { raw::write(b"prof.active\0", false) };
+        }
+    }
+
+    // SAFETY: `prof.active` takes a bool
+    unsafe { raw::write(b"prof.active\0", true) }.map_err(failed)?;
+    let active = Active;
+    tokio::time::sleep(duration).await;
+
+    let path = std::env::temp_dir().join(format!("spawngate-heap-{}.prof", std::process::id()));
+    let file_name = std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| ProfileError::Failed(format!("invalid dump path {}", path.display())))?;
+    // SAFETY: `prof.dump` takes a NUL-terminated file name, which outlives the call
+    let dumped = unsafe { raw::write(b"prof.dump\0", file_name.as_ptr()) };
+    drop(active);
+    dumped.map_err(failed)?;
+
+    let profile = tokio::fs::read(&path).await;
+    let _ = tokio::fs::remove_file(&path).await;
+    profile.map_err(|e| ProfileError::Failed(format!("reading {}: {}", path.display(), e)))
+}
+
+#[cfg(not(all(unix, feature = "jemalloc")))]
+async fn heap_profile(_duration: Duration) -> Result<Vec<u8>, ProfileError> {
+    Err(ProfileError::Unsupported("jemalloc"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profiler(max_seconds: u64) -> Profiler {
+        Profiler::new(&ProfilingConfig {
+            enabled: true,
+            max_seconds,
+        })
+    }
+
+    #[test]
+    fn test_duration_is_bounded() {
+        let bounded = profiler(60);
+        assert_eq!(bounded.duration(None), Ok(Duration::from_secs(DEFAULT_SECONDS)));
+        assert_eq!(bounded.duration(Some("5")), Ok(Duration::from_secs(5)));
+        assert_eq!(bounded.duration(Some("60")), Ok(Duration::from_secs(60)));
+        for invalid in ["0", "61", "-1", "1.5", "forever"] {
+            assert_eq!(bounded.duration(Some(invalid)), Err(ProfileError::InvalidSeconds(60)), "{}", invalid);
+        }
+
+        // The default is capped as well
+        assert_eq!(profiler(10).duration(None), Ok(Duration::from_secs(10)));
+    }
+
+    #[tokio::test]
+    async fn test_one_profile_of_a_kind_at_a_time() {
+        let profiler = profiler(60);
+        let running = Running::claim(&profiler.cpu_running).unwrap();
+        assert_eq!(profiler.cpu(Duration::from_secs(1)).await, Err(ProfileError::Busy));
+        drop(running);
+        assert!(!profiler.cpu_running.load(Ordering::Acquire));
+        assert!(Running::claim(&profiler.heap_running).is_ok());
+    }
+
+    #[cfg(not(all(unix, feature = "pprof")))]
+    #[tokio::test]
+    async fn test_cpu_profile_needs_feature() {
+        let err = profiler(60).cpu(Duration::from_secs(1)).await.unwrap_err();
+        assert_eq!(err, ProfileError::Unsupported("pprof"));
+        assert_eq!(err.status(), StatusCode::NOT_IMPLEMENTED);
+    }
+
+    #[cfg(all(unix, feature = "pprof"))]
+    #[tokio::test]
+    async fn test_cpu_flamegraph() {
+        let profiler = profiler(60);
+        let profile = profiler.cpu(Duration::from_millis(300));
+        tokio::pin!(profile);
+        assert!(futures::poll!(&mut profile).is_pending());
+
+        // Something to sample, on the thread the profile was started from
+        let started = std::time::Instant::now();
+        let mut n = 0u64;
+        while started.elapsed() < Duration::from_millis(500) {
+            n = std::hint::black_box(n.wrapping_mul(31).wrapping_add(7));
+        }
+
+        let svg = String::from_utf8(profile.await.unwrap()).unwrap();
+        assert!(svg.contains("<svg"), "{}", svg);
+    }
+}
@@ -0,0 +1,259 @@
+//! Promoting a backend's artifact (image or command) to another backend
+//!
+//! A backend lists the backends it may be promoted to in `promote_to`, e.g.
+//! staging → production. Promotion copies the artifact only; environment,
+//! ports and other settings stay with the target.
+
+use crate::config::{BackendConfig, BackendType};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of promotions kept in history
+const HISTORY_LIMIT: usize = 100;
+
+/// What a backend runs: the part of its config a promotion copies
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Artifact {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kernel: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rootfs: Option<String>,
+}
+
+impl Artifact {
+    pub fn of(config: &BackendConfig) -> Self {
+        let artifact = Self {
+            image: None,
+            command: None,
+            args: config.args.clone(),
+            working_dir: None,
+            kernel: None,
+            rootfs: None,
+        };
+        match config.backend_type {
+            BackendType::Docker => Self {
+                image: config.image.clone(),
+                ..artifact
+            },
+            BackendType::Local => Self {
+                command: config.command.clone(),
+                working_dir: config.working_dir.clone(),
+                ..artifact
+            },
+            // Spawners may launch from either, so both are promoted
+            BackendType::Custom => Self {
+                image: config.image.clone(),
+                command: config.command.clone(),
+                working_dir: config.working_dir.clone(),
+                ..artifact
+            },
+            BackendType::Firecracker => Self {
+                kernel: config.firecracker.as_ref().map(|vm| vm.kernel.clone()),
+                rootfs: config.firecracker.as_ref().map(|vm| vm.rootfs.clone()),
+                ..artifact
+            },
+            // The job, the VM's disk or the remote machine defines what runs
+            BackendType::Nomad | BackendType::CloudVm | BackendType::SshTunnel => artifact,
+        }
+    }
+
+    /// Overwrite the artifact fields of `config`
+    pub fn apply_to(&self, config: &mut BackendConfig) {
+        match config.backend_type {
+            BackendType::Docker => config.image = self.image.clone(),
+            BackendType::Local => {
+                config.command = self.command.clone();
+                config.working_dir = self.working_dir.clone();
+            }
+            BackendType::Custom => {
+                config.image = self.image.clone();
+                config.command = self.command.clone();
+                config.working_dir = self.working_dir.clone();
+            }
+            BackendType::Firecracker => {
+                if let (Some(vm), Some(kernel), Some(rootfs)) = (config.firecracker.as_mut(), &self.kernel, &self.rootfs) {
+                    vm.kernel.clone_from(kernel);
+                    vm.rootfs.clone_from(rootfs);
+                }
+            }
+            BackendType::Nomad | BackendType::CloudVm | BackendType::SshTunnel => {}
+        }
+        config.args = self.args.clone();
+    }
+}
+
+/// What a promotion would change, for review before applying it
+#[derive(Debug, Clone, Serialize)]
+pub struct PromotionReview {
+    pub source: String,
+    pub target: String,
+    /// Artifact currently deployed on the target
+    pub current: Artifact,
+    /// Artifact the target would run after promotion
+    pub proposed: Artifact,
+    /// Whether applying the promotion changes anything
+    pub changed: bool,
+    /// Env keys whose values differ between the backends (values are not shown)
+    pub env_differences: Vec<String>,
+}
+
+/// Check that `source` may be promoted to `target` and describe the change
+pub fn review(
+    source_name: &str,
+    source: &BackendConfig,
+    target_name: &str,
+    target: &BackendConfig,
+) -> Result<PromotionReview, String> {
+    if !source.promote_to.iter().any(|t| t == target_name) {
+        return Err(format!(
+            "Backend '{}' is not configured to promote to '{}'",
+            source_name, target_name
+        ));
+    }
+    if source.backend_type != target.backend_type {
+        return Err(format!(
+            "Backends '{}' and '{}' have different types",
+            source_name, target_name
+        ));
+    }
+
+    let current = Artifact::of(target);
+    let proposed = Artifact::of(source);
+
+    let mut env_differences: Vec<String> = source
+        .env
+        .keys()
+        .chain(target.env.keys())
+        .filter(|key| source.env.get(*key) != target.env.get(*key))
+        .cloned()
+        .collect();
+    env_differences.sort();
+    env_differences.dedup();
+
+    Ok(PromotionReview {
+        source: source_name.to_string(),
+        target: target_name.to_string(),
+        changed: current != proposed,
+        current,
+        proposed,
+        env_differences,
+    })
+}
+
+/// A completed promotion
+#[derive(Debug, Clone, Serialize)]
+pub struct PromotionRecord {
+    pub id: u64,
+    pub source: String,
+    pub target: String,
+    pub from: Artifact,
+    pub to: Artifact,
+    /// Unix timestamp of the promotion
+    pub promoted_at: u64,
+}
+
+/// Recent promotions, newest last
+#[derive(Debug, Default)]
+pub struct PromotionHistory {
+    inner: Mutex<(u64, VecDeque<PromotionRecord>)>,
+}
+
+impl PromotionHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a promotion and return its record
+    pub fn record(&self, review: &PromotionReview) -> PromotionRecord {
+        let mut inner = self.inner.lock();
+        inner.0 += 1;
+        let record = PromotionRecord {
+            id: inner.0,
+            source: review.source.clone(),
+            target: review.target.clone(),
+            from: review.current.clone(),
+            to: review.proposed.clone(),
+            promoted_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        };
+        if inner.1.len() == HISTORY_LIMIT {
+            inner.1.pop_front();
+        }
+        inner.1.push_back(record.clone());
+        record
+    }
+
+    pub fn list(&self) -> Vec<PromotionRecord> {
+        self.inner.lock().1.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn docker(image: &str) -> BackendConfig {
+        BackendConfig::docker(image, 8080)
+    }
+
+    #[test]
+    fn test_review() {
+        let mut staging = docker("app:v2");
+        staging.promote_to = vec!["app.local".to_string()];
+        staging.env.insert("DATABASE_URL".to_string(), "postgres://staging".to_string());
+        staging.env.insert("LOG".to_string(), "debug".to_string());
+        let mut production = docker("app:v1");
+        production.env.insert("DATABASE_URL".to_string(), "postgres://prod".to_string());
+        production.env.insert("LOG".to_string(), "debug".to_string());
+
+        let review = review("staging.local", &staging, "app.local", &production).unwrap();
+        assert!(review.changed);
+        assert_eq!(review.current.image.as_deref(), Some("app:v1"));
+        assert_eq!(review.proposed.image.as_deref(), Some("app:v2"));
+        assert_eq!(review.env_differences, vec!["DATABASE_URL".to_string()]);
+
+        review.proposed.apply_to(&mut production);
+        assert_eq!(production.image.as_deref(), Some("app:v2"));
+        assert_eq!(production.env["DATABASE_URL"], "postgres://prod");
+    }
+
+    #[test]
+    fn test_review_rejects_unlisted_or_mismatched_targets() {
+        let staging = docker("app:v2");
+        assert!(review("staging.local", &staging, "app.local", &docker("app:v1")).is_err());
+
+        let mut staging = docker("app:v2");
+        staging.promote_to = vec!["app.local".to_string()];
+        let local = BackendConfig::local("./app", 3000);
+        let err = review("staging.local", &staging, "app.local", &local).unwrap_err();
+        assert!(err.contains("different types"));
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let mut staging = docker("app:v2");
+        staging.promote_to = vec!["app.local".to_string()];
+        let review = review("staging.local", &staging, "app.local", &docker("app:v1")).unwrap();
+
+        let history = PromotionHistory::new();
+        for _ in 0..HISTORY_LIMIT + 5 {
+            history.record(&review);
+        }
+        let records = history.list();
+        assert_eq!(records.len(), HISTORY_LIMIT);
+        assert_eq!(records[0].id, 6);
+        assert_eq!(records.last().unwrap().id, (HISTORY_LIMIT + 5) as u64);
+    }
+}
@@ -1,22 +1,44 @@
 use crate::acme::Http01Challenges;
-use crate::error::{json_error_response, ProxyErrorCode};
-use crate::pool::{ConnectionPool, PoolConfig};
-use crate::process::{BackendState, ProcessManager, SharedDefaults};
+use crate::coldstart::{QueueFull, SpawnQueue, StillStarting};
+use crate::config::{EarlyDataConfig, FingerprintBlock, PathNormalizationConfig, PriorityClass, WafMode};
+use crate::early_data::{self, HandshakeState};
+use crate::error::{self, json_error_response, json_error_response_with_status, ProxyErrorCode};
+use crate::experiments::ExperimentMetrics;
+use crate::faults::{DropConnection, FaultAction};
+use crate::files;
+use crate::fingerprint::{self, Fingerprinter, TlsFingerprint};
+use crate::h2c::{self, Rewind};
+use crate::metrics::{RequestMetrics, TlsHandshakeMetrics};
+use crate::normalize;
+use crate::pool::{ConnectTiming, ConnectionPool, PoolConfig};
+use crate::process::{BackendState, ProcessManager, SharedDefaults, UpstreamTarget};
+use crate::quota::QuotaDecision;
+use crate::registry::{ClientConnection, DebugRegistry};
+use crate::routing::{self, Route};
+use crate::sampling::TraceSampler;
+use crate::slo::ColdStartSlo;
+use crate::slowlog::{RequestTimings, SlowRequestLog, TrackedRequest};
+use crate::throttle::{ColdStartThrottle, ThrottleDecision};
+use crate::waf;
+use futures::future::BoxFuture;
 use http_body_util::combinators::BoxBody;
 use http_body_util::{BodyExt, Empty, Full};
 use hyper::body::{Bytes, Incoming};
-use hyper::header::HeaderValue;
-use hyper::service::service_fn;
+use hyper::header::{HeaderMap, HeaderValue};
+use hyper::http::request::Parts;
+use hyper::service::{service_fn, Service};
 use hyper::upgrade::Upgraded;
 use hyper::{Request, Response, StatusCode};
 use hyper_util::rt::{TokioExecutor, TokioIo};
 use hyper_util::server::conn::auto::Builder as AutoBuilder;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::watch;
+use tokio::task::JoinSet;
 use tokio_rustls::TlsAcceptor;
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
@@ -31,21 +53,1002 @@ const X_FORWARDED_FOR: &str = "x-forwarded-for";
 const X_FORWARDED_HOST: &str = "x-forwarded-host";
 /// Header name for forwarded proto
 const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+/// Header name for the time the proxy gives up on a request (Unix milliseconds)
+const X_REQUEST_DEADLINE: &str = "x-request-deadline";
+/// Header name for the gRPC request timeout
+const GRPC_TIMEOUT: &str = "grpc-timeout";
+/// Header name marking requests forwarded from TLS early data (RFC 8470)
+const EARLY_DATA: &str = "early-data";
+/// Header name for the client's JA3 TLS fingerprint
+const X_JA3_FINGERPRINT: &str = "x-ja3-fingerprint";
+/// Header name for the client's JA4 TLS fingerprint
+const X_JA4_FINGERPRINT: &str = "x-ja4-fingerprint";
+/// Header name for internal redirects (file under internal_root or @backend)
+const X_ACCEL_REDIRECT: &str = "x-accel-redirect";
+/// Header name for internal redirects to an absolute file path
+const X_SENDFILE: &str = "x-sendfile";
+
+/// Body type used for requests and responses inside the pipeline
+pub type ProxyBody = BoxBody<Bytes, hyper::Error>;
+
+/// Response type produced by the proxy and its pipeline stages
+pub type ProxyResponse = Response<ProxyBody>;
+
+/// Per-request information shared between pipeline stages
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    /// Request ID (propagated from the client or generated)
+    pub request_id: String,
+    /// Normalized hostname from the Host header
+    pub hostname: String,
+    /// Address of the connecting client
+    pub client_addr: SocketAddr,
+    /// Whether the request arrived over TLS
+    pub is_tls: bool,
+    /// When the proxy received the request
+    pub received: Instant,
+    /// Fingerprints of the TLS client, when fingerprinting is enabled
+    pub tls_fingerprint: Option<Arc<TlsFingerprint>>,
+}
+
+/// Route stage: picks the backend that should handle a request
+///
+/// The default [`HostRouter`] picks the backend configured for the Host
+/// header, following its `routes`. Custom routers can decide on the path, headers or outside state
+/// (a tenant database, feature flags), and delegate to a `HostRouter` for
+/// everything else. Closures with the same signature are routers too.
+pub trait Router: Send + Sync {
+    /// Return the backend hostname for this request, or `None` if nothing matches
+    fn route(&self, ctx: &RequestContext, parts: &Parts) -> Option<String>;
+
+    /// Like `route`, also saying how to adjust the request for the backend
+    ///
+    /// Only needs implementing to strip a path prefix; routers delegating to
+    /// a `HostRouter` should delegate this too.
+    fn route_request(&self, ctx: &RequestContext, parts: &Parts) -> Option<Route> {
+        self.route(ctx, parts).map(Route::to)
+    }
+}
+
+impl<F> Router for F
+where
+    F: Fn(&RequestContext, &Parts) -> Option<String> + Send + Sync,
+{
+    fn route(&self, ctx: &RequestContext, parts: &Parts) -> Option<String> {
+        self(ctx, parts)
+    }
+}
+
+/// Outcome of the admission stage
+pub enum AdmissionDecision {
+    /// Let the request continue down the pipeline
+    Admit,
+    /// Reject the request with this response
+    Reject(ProxyResponse),
+}
+
+/// Admission stage: decides whether a routed request may proceed
+pub trait Admission: Send + Sync {
+    fn admit(&self, ctx: &RequestContext, hostname: &str, parts: &Parts) -> AdmissionDecision;
+}
+
+/// Spawn-wait stage: makes sure the backend is running and ready
+pub trait SpawnWait: Send + Sync {
+    /// Start the backend if needed and wait until it is ready, queued
+    /// according to the request's priority class
+    fn ensure_ready<'a>(&'a self, hostname: &'a str, class: PriorityClass) -> BoxFuture<'a, anyhow::Result<()>>;
+}
+
+/// Upstream stage: forwards the request to a ready backend
+pub trait Upstream: Send + Sync {
+    /// Forward the request and produce the response for the client
+    fn forward<'a>(
+        &'a self,
+        ctx: &'a RequestContext,
+        hostname: &'a str,
+        req: Request<ProxyBody>,
+    ) -> BoxFuture<'a, ProxyResponse>;
+}
+
+/// Default router: the backend configured for the hostname, or the target of
+/// the matching rule among its `routes`
+pub struct HostRouter {
+    process_manager: Arc<ProcessManager>,
+}
+
+impl HostRouter {
+    pub fn new(process_manager: Arc<ProcessManager>) -> Self {
+        Self { process_manager }
+    }
+}
+
+impl Router for HostRouter {
+    fn route(&self, ctx: &RequestContext, parts: &Parts) -> Option<String> {
+        self.route_request(ctx, parts).map(|route| route.backend)
+    }
+
+    fn route_request(&self, ctx: &RequestContext, parts: &Parts) -> Option<Route> {
+        self.process_manager.route(&ctx.hostname, parts, ctx.client_addr.ip())
+    }
+}
+
+/// Default admission: rejects paths outside `allowed_paths`, requests
+/// for draining or unhealthy backends, and blocklisted cold starts
+pub struct StateAdmission {
+    process_manager: Arc<ProcessManager>,
+}
+
+impl StateAdmission {
+    pub fn new(process_manager: Arc<ProcessManager>) -> Self {
+        Self { process_manager }
+    }
+}
+
+impl Admission for StateAdmission {
+    fn admit(&self, ctx: &RequestContext, hostname: &str, parts: &Parts) -> AdmissionDecision {
+        // Checked before anything else so probes for unknown paths never spawn the backend
+        if !self.process_manager.is_path_allowed(hostname, parts.uri.path()) {
+            return AdmissionDecision::Reject(json_error_response(
+                ProxyErrorCode::PathNotAllowed,
+                "Not found",
+            ));
+        }
+
+        match self.process_manager.get_state(hostname) {
+            // Held in the cold-start queue until the new instance is ready
+            BackendState::Stopping if self.process_manager.is_restarting(hostname) => AdmissionDecision::Admit,
+            // Backend is in draining mode (stopping)
+            BackendState::Stopping => AdmissionDecision::Reject(json_error_response(
+                ProxyErrorCode::BackendShuttingDown,
+                "Backend is shutting down, please retry later",
+            )),
+            BackendState::Unhealthy => AdmissionDecision::Reject(json_error_response(
+                ProxyErrorCode::BackendUnhealthy,
+                "Backend is currently unhealthy, auto-restart in progress",
+            )),
+            // Blocklisted TLS clients reach running backends only
+            BackendState::Stopped if ctx.tls_fingerprint.as_ref().is_some_and(|f| f.blocked) => {
+                debug!(
+                    request_id = ctx.request_id,
+                    client = %ctx.client_addr.ip(),
+                    hostname,
+                    "Cold start blocked by TLS fingerprint"
+                );
+                AdmissionDecision::Reject(json_error_response(ProxyErrorCode::FingerprintBlocked, "Request blocked"))
+            }
+            _ => AdmissionDecision::Admit,
+        }
+    }
+}
+
+/// Default spawn-wait: starts backends through the process manager
+pub struct ProcessSpawnWait {
+    process_manager: Arc<ProcessManager>,
+    defaults: SharedDefaults,
+    queue: Arc<SpawnQueue>,
+}
+
+impl ProcessSpawnWait {
+    pub fn new(process_manager: Arc<ProcessManager>, defaults: SharedDefaults) -> Self {
+        Self {
+            process_manager,
+            defaults,
+            queue: Arc::new(SpawnQueue::new()),
+        }
+    }
+}
+
+impl SpawnWait for ProcessSpawnWait {
+    /// Fails with [`StillStarting`] if the backend's `spawn_wait_secs` runs out first
+    fn ensure_ready<'a>(&'a self, hostname: &'a str, class: PriorityClass) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let limit = (!self.process_manager.is_ready(hostname))
+                .then(|| self.process_manager.get_config(hostname))
+                .flatten()
+                .and_then(|config| config.spawn_wait(&self.defaults.read()));
+            let ready = ensure_backend_ready(hostname, class, &self.process_manager, &self.defaults);
+            let Some(limit) = limit else {
+                return ready.await;
+            };
+            let ticket = self.queue.join(hostname, class);
+            match tokio::time::timeout(limit, ready).await {
+                Ok(result) => result,
+                Err(_) => Err(StillStarting {
+                    position: ticket.position(),
+                    estimated_ready: self.process_manager.estimated_ready(hostname),
+                }
+                .into()),
+            }
+        })
+    }
+}
+
+/// Default upstream: forwards through the connection pool with in-flight tracking
+pub struct PoolUpstream {
+    process_manager: Arc<ProcessManager>,
+    defaults: SharedDefaults,
+    pool: Arc<ConnectionPool>,
+}
+
+impl PoolUpstream {
+    pub fn new(
+        process_manager: Arc<ProcessManager>,
+        defaults: SharedDefaults,
+        pool: Arc<ConnectionPool>,
+    ) -> Self {
+        Self {
+            process_manager,
+            defaults,
+            pool,
+        }
+    }
+}
+
+impl Upstream for PoolUpstream {
+    fn forward<'a>(
+        &'a self,
+        ctx: &'a RequestContext,
+        hostname: &'a str,
+        mut req: Request<ProxyBody>,
+    ) -> BoxFuture<'a, ProxyResponse> {
+        Box::pin(async move {
+            // Update activity timestamp
+            self.process_manager.touch(hostname);
+
+            // Get the backend port, request timeout and internal redirect root
+            let target = self.process_manager.upstream_target(hostname, &self.defaults.read());
+            let Some(UpstreamTarget {
+                port,
+                request_timeout,
+                redirect_root,
+                deadline_headers,
+                protocol,
+                rewriter,
+            }) = target
+            else {
+                return json_error_response(
+                    ProxyErrorCode::BackendConfigError,
+                    "Backend configuration not found",
+                );
+            };
+
+            // Check for WebSocket/HTTP upgrade request
+            if is_upgrade_request(&req) {
+                return handle_upgrade(
+                    req,
+                    Arc::clone(&self.process_manager),
+                    hostname.to_string(),
+                    port,
+                    ctx.request_id.clone(),
+                )
+                .await;
+            }
+
+            // Track in-flight request - also atomically verifies backend is still Ready
+            if !self.process_manager.increment_in_flight(hostname) {
+                // Backend state changed between spawn-wait and now
+                return json_error_response(
+                    ProxyErrorCode::BackendShuttingDown,
+                    "Backend state changed, please retry",
+                );
+            }
+            let mut in_flight = InFlightGuard {
+                process_manager: &self.process_manager,
+                hostname,
+                request_id: &ctx.request_id,
+                completed: false,
+            };
+
+            if deadline_headers {
+                set_deadline_headers(req.headers_mut(), request_timeout, SystemTime::now());
+            }
+            // Ask for an uncompressed response so the body can be rewritten
+            if rewriter.is_some() {
+                req.headers_mut().remove(hyper::header::ACCEPT_ENCODING);
+            }
+
+            // Keep the request headers in case the backend re-dispatches elsewhere
+            let request_headers = redirect_root.is_some().then(|| req.headers().clone());
+
+            // Forward the request through the connection pool with timeout
+            let sent = Instant::now();
+            let result =
+                tokio::time::timeout(request_timeout, self.pool.send_request(req, port, protocol)).await;
+
+            // Decrement in-flight counter when done
+            in_flight.completed = true;
+            drop(in_flight);
+
+            match result {
+                Ok(Ok(mut response)) => {
+                    // A connection established after sending was opened for this request
+                    let connect = response
+                        .extensions()
+                        .get::<ConnectTiming>()
+                        .filter(|timing| timing.established >= sent)
+                        .map(|timing| timing.duration)
+                        .unwrap_or_default();
+                    response.extensions_mut().insert(RequestTimings {
+                        connect,
+                        ttfb: sent.elapsed().saturating_sub(connect),
+                        ..Default::default()
+                    });
+                    if let Some(ref rewriter) = rewriter {
+                        response = rewriter.rewrite_response(response);
+                    }
+
+                    let (Some(root), Some(request_headers)) = (redirect_root, request_headers) else {
+                        return response;
+                    };
+                    match parse_internal_redirect(response.headers(), root, request_headers) {
+                        None => response,
+                        Some(Ok(InternalRedirect::Backend { hostname: target, .. }))
+                            if !self.process_manager.has_backend(&target) =>
+                        {
+                            warn!(hostname, target, "Internal redirect to unknown backend");
+                            json_error_response(
+                                ProxyErrorCode::BackendConfigError,
+                                "Internal redirect to unknown backend",
+                            )
+                        }
+                        Some(Ok(redirect)) => {
+                            response.extensions_mut().insert(redirect);
+                            response
+                        }
+                        Some(Err(message)) => {
+                            warn!(hostname, message, "Invalid internal redirect");
+                            json_error_response(ProxyErrorCode::BackendConfigError, message)
+                        }
+                    }
+                }
+                Ok(Err(e)) => {
+                    // Log detailed error internally, return generic message externally
+                    error!(hostname, port, error = %e, "Failed to forward request via pool");
+                    json_error_response(
+                        ProxyErrorCode::ConnectionFailed,
+                        "Failed to connect to backend",
+                    )
+                }
+                Err(_) => {
+                    warn!(
+                        hostname,
+                        port,
+                        timeout_secs = request_timeout.as_secs(),
+                        "Request timed out"
+                    );
+                    json_error_response(
+                        ProxyErrorCode::RequestTimeout,
+                        format!(
+                            "Request timed out after {} seconds",
+                            request_timeout.as_secs()
+                        ),
+                    )
+                }
+            }
+        })
+    }
+}
+
+/// Releases a forwarded request's in-flight slot
+///
+/// If the client disconnects, hyper drops the request future before the
+/// backend responds. Dropping the pooled request closes its backend
+/// connection, and the guard counts the request as cancelled.
+struct InFlightGuard<'a> {
+    process_manager: &'a ProcessManager,
+    hostname: &'a str,
+    request_id: &'a str,
+    completed: bool,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.process_manager.decrement_in_flight(self.hostname);
+        if !self.completed {
+            self.process_manager.record_cancelled(self.hostname);
+            debug!(request_id = self.request_id, hostname = self.hostname, "Client disconnected, upstream request cancelled");
+        }
+    }
+}
+
+/// The request pipeline: route -> admission -> spawn-wait -> upstream
+///
+/// Each stage is a trait object so it can be replaced independently, e.g. to
+/// exercise the proxy in tests without spawning real backends.
+#[derive(Clone)]
+pub struct Pipeline {
+    process_manager: Arc<ProcessManager>,
+    router: Arc<dyn Router>,
+    admission: Arc<dyn Admission>,
+    spawn_wait: Arc<dyn SpawnWait>,
+    upstream: Arc<dyn Upstream>,
+    /// Whether `upstream` is the default [`PoolUpstream`], which `with_pool` rebuilds
+    default_upstream: bool,
+    cold_start_throttle: Option<Arc<ColdStartThrottle>>,
+    metrics: Option<Arc<RequestMetrics>>,
+    trace_sampler: Option<Arc<TraceSampler>>,
+    slow_log: Option<Arc<SlowRequestLog>>,
+    cold_start_slo: Option<Arc<ColdStartSlo>>,
+    experiment_metrics: Option<Arc<ExperimentMetrics>>,
+}
+
+impl Pipeline {
+    /// Create a pipeline with the default stages backed by the process manager and pool
+    pub fn new(
+        process_manager: Arc<ProcessManager>,
+        defaults: SharedDefaults,
+        pool: Arc<ConnectionPool>,
+    ) -> Self {
+        Self {
+            process_manager: Arc::clone(&process_manager),
+            router: Arc::new(HostRouter::new(Arc::clone(&process_manager))),
+            admission: Arc::new(StateAdmission::new(Arc::clone(&process_manager))),
+            spawn_wait: Arc::new(ProcessSpawnWait::new(
+                Arc::clone(&process_manager),
+                Arc::clone(&defaults),
+            )),
+            upstream: Arc::new(PoolUpstream::new(process_manager, defaults, pool)),
+            default_upstream: true,
+            cold_start_throttle: None,
+            metrics: None,
+            trace_sampler: None,
+            slow_log: None,
+            cold_start_slo: None,
+            experiment_metrics: None,
+        }
+    }
+
+    /// Replace the route stage
+    pub fn with_router(mut self, router: Arc<dyn Router>) -> Self {
+        self.router = router;
+        self
+    }
+
+    /// Replace the admission stage
+    pub fn with_admission(mut self, admission: Arc<dyn Admission>) -> Self {
+        self.admission = admission;
+        self
+    }
+
+    /// Replace the spawn-wait stage
+    pub fn with_spawn_wait(mut self, spawn_wait: Arc<dyn SpawnWait>) -> Self {
+        self.spawn_wait = spawn_wait;
+        self
+    }
+
+    /// Replace the upstream stage
+    pub fn with_upstream(mut self, upstream: Arc<dyn Upstream>) -> Self {
+        self.upstream = upstream;
+        self.default_upstream = false;
+        self
+    }
+
+    /// Forward through `pool`; a custom upstream stage is kept as it is
+    pub fn with_pool(mut self, pool: Arc<ConnectionPool>) -> Self {
+        if self.default_upstream {
+            let defaults = self.process_manager.shared_defaults();
+            self.upstream = Arc::new(PoolUpstream::new(Arc::clone(&self.process_manager), defaults, pool));
+        }
+        self
+    }
+
+    /// Limit how often each client may wake a stopped backend
+    ///
+    /// Checked after the admission stage, for requests it admitted.
+    pub fn with_cold_start_throttle(mut self, throttle: Arc<ColdStartThrottle>) -> Self {
+        self.cold_start_throttle = Some(throttle);
+        self
+    }
+
+    /// Record per-backend request counts, status classes and latency
+    ///
+    /// Latency is measured until the response headers are ready.
+    pub fn with_metrics(mut self, metrics: Arc<RequestMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Emit a trace event for sampled requests
+    pub fn with_trace_sampler(mut self, sampler: Arc<TraceSampler>) -> Self {
+        self.trace_sampler = Some(sampler);
+        self
+    }
+
+    /// Log requests exceeding the slow request threshold
+    ///
+    /// Unlike metrics, this includes the time spent sending the response body.
+    pub fn with_slow_log(mut self, slow_log: Arc<SlowRequestLog>) -> Self {
+        self.slow_log = Some(slow_log);
+        self
+    }
+
+    /// Track how much latency cold starts add against the SLO
+    pub fn with_cold_start_slo(mut self, slo: Arc<ColdStartSlo>) -> Self {
+        self.cold_start_slo = Some(slo);
+        self
+    }
+
+    /// Record requests, errors and latency per experiment variant
+    pub fn with_experiment_metrics(mut self, metrics: Arc<ExperimentMetrics>) -> Self {
+        self.experiment_metrics = Some(metrics);
+        self
+    }
+
+    /// Run a request through all stages
+    pub async fn handle(&self, ctx: RequestContext, req: Request<Incoming>) -> ProxyResponse {
+        let (mut parts, body) = req.into_parts();
+
+        let Some(route) = self.router.route_request(&ctx, &parts) else {
+            // Don't reveal whether host exists - use generic message
+            return json_error_response(
+                ProxyErrorCode::UnknownHost,
+                "Unknown or unconfigured host",
+            );
+        };
+        if let Some(ref prefix) = route.strip_prefix {
+            routing::strip_prefix(&mut parts, prefix);
+        }
+        let read_your_writes = route.read_your_writes;
+        let experiment = route.experiment;
+        let hostname = route.backend;
+
+        let trace = self.trace_sampler.as_ref().map(|sampler| sampler.candidate(&parts));
+        let tracked = self.slow_log.as_ref().map(|_| TrackedRequest {
+            request_id: ctx.request_id.clone(),
+            hostname: hostname.clone(),
+            method: parts.method.clone(),
+            path: parts.uri.path().to_string(),
+            received: ctx.received,
+        });
+        let start = Instant::now();
+        let mut response = self.handle_routed(&ctx, hostname.clone(), parts, body).await;
+        if let Some(secs) = read_your_writes {
+            response.headers_mut().append(hyper::header::SET_COOKIE, routing::wrote_cookie(secs));
+        }
+        if let Some(assignment) = experiment {
+            if let Some(ref metrics) = self.experiment_metrics {
+                metrics.record(&assignment, response.status(), start.elapsed());
+            }
+            if let Some(cookie) = assignment.cookie {
+                response.headers_mut().append(hyper::header::SET_COOKIE, cookie);
+            }
+        }
+        if let Some(ref metrics) = self.metrics {
+            metrics.record(&hostname, response.status(), start.elapsed());
+        }
+        if let (Some(sampler), Some(trace)) = (&self.trace_sampler, trace) {
+            let fingerprint = ctx.tls_fingerprint.as_deref();
+            sampler.finish(trace, &ctx.request_id, &hostname, response.status(), start.elapsed(), fingerprint);
+        }
+        // Before the slow log wraps the body
+        let response = error::with_request_details(response, &ctx.request_id);
+        match (&self.slow_log, tracked) {
+            (Some(slow_log), Some(tracked)) => slow_log.track(tracked, response),
+            _ => response,
+        }
+    }
+
+    /// Admission stage, then the cold-start throttle
+    fn admit(&self, ctx: &RequestContext, hostname: &str, parts: &Parts) -> AdmissionDecision {
+        if let AdmissionDecision::Reject(response) = self.admission.admit(ctx, hostname, parts) {
+            return AdmissionDecision::Reject(response);
+        }
+        // Only requests that would spawn the backend count against the client
+        let Some(ref throttle) = self.cold_start_throttle else {
+            return AdmissionDecision::Admit;
+        };
+        if self.process_manager.get_state(hostname) != BackendState::Stopped {
+            return AdmissionDecision::Admit;
+        }
+        match throttle.check(ctx.client_addr.ip()) {
+            ThrottleDecision::Allow => AdmissionDecision::Admit,
+            ThrottleDecision::Banned(remaining) => {
+                debug!(request_id = ctx.request_id, client = %ctx.client_addr.ip(), hostname, "Cold start throttled");
+                let mut response = json_error_response(
+                    ProxyErrorCode::ColdStartThrottled,
+                    "Too many requests, please retry later",
+                );
+                response
+                    .headers_mut()
+                    .insert(hyper::header::RETRY_AFTER, HeaderValue::from(remaining.as_secs().max(1)));
+                AdmissionDecision::Reject(response)
+            }
+        }
+    }
+
+    /// Admission, quotas, dispatch and internal redirects for a routed request
+    async fn handle_routed(&self, ctx: &RequestContext, hostname: String, parts: Parts, body: Incoming) -> ProxyResponse {
+        // An unhealthy primary's traffic goes to its fallback until health checks recover it.
+        // Fallbacks are followed one level deep, so the fallback's own isn't used then.
+        let (hostname, fallback) = match self.process_manager.fallback_for(&hostname) {
+            Some(fallback) if self.process_manager.get_state(&hostname) == BackendState::Unhealthy => {
+                debug!(request_id = ctx.request_id, hostname, fallback, "Primary unhealthy, using fallback backend");
+                (fallback, None)
+            }
+            fallback => (hostname, fallback),
+        };
+
+        // Range and validator headers, in case the response becomes a file
+        let file_request_headers = files::file_request_headers(&parts.headers);
+
+        if let AdmissionDecision::Reject(response) = self.admit(ctx, &hostname, &parts) {
+            return response;
+        }
+        if let Some(response) = self.inspect(ctx, &hostname, &parts) {
+            return response;
+        }
+
+        let quota = self.process_manager.quota(&hostname);
+        let decision = quota.as_ref().map(|config| self.process_manager.quotas().check(&hostname, config));
+        if let Some(QuotaDecision::Block(retry_after)) = decision {
+            debug!(request_id = ctx.request_id, hostname, "Backend over its traffic quota");
+            let mut response = json_error_response(ProxyErrorCode::QuotaExceeded, "Traffic quota exceeded");
+            response
+                .headers_mut()
+                .insert(hyper::header::RETRY_AFTER, HeaderValue::from(retry_after.as_secs().max(1)));
+            return response;
+        }
+        let body = match quota {
+            Some(ref config) => self.process_manager.quotas().meter(&hostname, config.clone(), body.boxed(), None),
+            None => body.boxed(),
+        };
+
+        let request = Request::from_parts(parts, body);
+        let mut response = self.dispatch(ctx, &hostname, fallback.as_deref(), request).await;
+
+        let response = match response.extensions_mut().remove::<InternalRedirect>() {
+            Some(redirect) => {
+                self.follow_internal_redirect(ctx, response, redirect, &file_request_headers)
+                    .await
+            }
+            None => response,
+        };
+        match (quota, decision) {
+            (Some(config), Some(decision)) => {
+                let rate = decision.rate();
+                response.map(|body| self.process_manager.quotas().meter(&hostname, config, body, rate))
+            }
+            _ => response,
+        }
+    }
+
+    /// Spawn-wait and upstream for an admitted request
+    ///
+    /// If the backend fails to start and a `fallback` is given, the request is
+    /// served from the fallback instead. The primary is retried on the next request.
+    async fn dispatch(
+        &self,
+        ctx: &RequestContext,
+        hostname: &str,
+        fallback: Option<&str>,
+        req: Request<ProxyBody>,
+    ) -> ProxyResponse {
+        let queue = ctx.received.elapsed();
+        let spawn_started = Instant::now();
+        let class = self.process_manager.priority_class(hostname, req.method(), req.uri().path(), req.headers());
+
+        // Ensure backend is running and ready
+        let Err(e) = self.spawn_wait.ensure_ready(hostname, class).await else {
+            let spawn = spawn_started.elapsed();
+            self.record_spawn(hostname, Some(spawn));
+            let response = self.forward(ctx, hostname, req).await;
+            return with_wait_timings(response, queue, spawn);
+        };
+
+        if let Some(starting) = e.downcast_ref::<StillStarting>() {
+            debug!(request_id = ctx.request_id, hostname, position = starting.position, "Backend still starting");
+            self.record_spawn(hostname, None);
+            return starting.response();
+        }
+        if let Some(full) = e.downcast_ref::<QueueFull>() {
+            warn!(
+                request_id = ctx.request_id,
+                hostname,
+                depth = full.depth,
+                class = class.as_str(),
+                "Cold-start queue full"
+            );
+            self.record_spawn(hostname, None);
+            return full.response();
+        }
+
+        // Log detailed error internally, return generic message externally
+        error!(hostname, error = %e, "Failed to start backend");
+
+        if let Some(fallback) = fallback {
+            match self.spawn_wait.ensure_ready(fallback, class).await {
+                Ok(()) => {
+                    warn!(request_id = ctx.request_id, hostname, fallback, "Serving request from fallback backend");
+                    let spawn = spawn_started.elapsed();
+                    self.record_spawn(hostname, Some(spawn));
+                    let response = self.forward(ctx, fallback, req).await;
+                    return with_wait_timings(response, queue, spawn);
+                }
+                Err(e) => error!(hostname = fallback, error = %e, "Failed to start fallback backend"),
+            }
+        }
+
+        self.record_spawn(hostname, None);
+        json_error_response(ProxyErrorCode::BackendStartFailed, "Backend unavailable")
+    }
+
+    /// Score the request against the WAF rules if enabled for the backend,
+    /// returning the rejection if it is blocked
+    fn inspect(&self, ctx: &RequestContext, hostname: &str, parts: &Parts) -> Option<ProxyResponse> {
+        let (threshold, mode) = self.process_manager.waf(hostname)?;
+        let inspection = waf::inspect(parts);
+        if inspection.score < threshold {
+            if inspection.score > 0 {
+                debug!(
+                    request_id = ctx.request_id,
+                    hostname,
+                    score = inspection.score,
+                    rules = %inspection,
+                    "WAF rules matched below threshold"
+                );
+            }
+            return None;
+        }
+
+        warn!(
+            request_id = ctx.request_id,
+            client = %ctx.client_addr.ip(),
+            hostname,
+            score = inspection.score,
+            rules = %inspection,
+            blocked = mode == WafMode::Block,
+            "Request matched WAF rules"
+        );
+        (mode == WafMode::Block).then(|| json_error_response(ProxyErrorCode::RequestBlocked, "Request blocked"))
+    }
+
+    /// Count the spawn wait against the cold-start SLO (`None`: start failed)
+    fn record_spawn(&self, hostname: &str, spawn: Option<Duration>) {
+        if let Some(ref slo) = self.cold_start_slo {
+            slo.record(hostname, spawn);
+        }
+    }
+
+    /// Upstream stage, with any faults enabled for the backend
+    async fn forward(&self, ctx: &RequestContext, hostname: &str, req: Request<ProxyBody>) -> ProxyResponse {
+        let faults = self.process_manager.faults().request_faults(hostname);
+        if let Some(delay) = faults.delay {
+            tokio::time::sleep(delay).await;
+        }
+        match faults.action {
+            None => self.upstream.forward(ctx, hostname, req).await,
+            Some(FaultAction::Error(status)) => {
+                debug!(request_id = ctx.request_id, hostname, status = status.as_u16(), "Injecting error");
+                json_error_response_with_status(ProxyErrorCode::FaultInjected, status, "Injected fault")
+            }
+            Some(FaultAction::Drop) => {
+                debug!(request_id = ctx.request_id, hostname, "Injecting dropped connection");
+                let mut response = json_error_response(ProxyErrorCode::FaultInjected, "Injected fault");
+                response.extensions_mut().insert(DropConnection);
+                response
+            }
+        }
+    }
+
+    /// Serve a file or re-dispatch as instructed by X-Accel-Redirect / X-Sendfile
+    ///
+    /// Only one level is followed; redirects in the re-dispatched response are ignored.
+    async fn follow_internal_redirect(
+        &self,
+        ctx: &RequestContext,
+        response: ProxyResponse,
+        redirect: InternalRedirect,
+        file_request_headers: &HeaderMap,
+    ) -> ProxyResponse {
+        match redirect {
+            InternalRedirect::File { root, path } => {
+                debug!(request_id = ctx.request_id, path = %path.display(), "Serving file via internal redirect");
+                let (parts, _body) = response.into_parts();
+                files::serve_file(&root, &path, parts.headers, file_request_headers).await
+            }
+            InternalRedirect::Backend {
+                hostname,
+                path_and_query,
+                mut headers,
+            } => {
+                debug!(request_id = ctx.request_id, hostname, path_and_query, "Re-dispatching via internal redirect");
+                headers.remove(hyper::header::CONTENT_LENGTH);
+                headers.remove(hyper::header::TRANSFER_ENCODING);
+                if let Ok(value) = HeaderValue::from_str(&hostname) {
+                    headers.insert(hyper::header::HOST, value);
+                }
+
+                let mut req = Request::new(Empty::<Bytes>::new().map_err(|never| match never {}).boxed());
+                *req.method_mut() = hyper::Method::GET;
+                *req.headers_mut() = headers;
+                match path_and_query.parse() {
+                    Ok(uri) => *req.uri_mut() = uri,
+                    Err(_) => {
+                        return json_error_response(
+                            ProxyErrorCode::BackendConfigError,
+                            "Invalid internal redirect path",
+                        );
+                    }
+                }
+
+                let (parts, body) = req.into_parts();
+                if let AdmissionDecision::Reject(response) = self.admit(ctx, &hostname, &parts) {
+                    return response;
+                }
+
+                let fallback = self.process_manager.fallback_for(&hostname);
+                let request = Request::from_parts(parts, body);
+                let mut response = self.dispatch(ctx, &hostname, fallback.as_deref(), request).await;
+                response.extensions_mut().remove::<InternalRedirect>();
+                response
+            }
+        }
+    }
+}
+
+/// Tell the backend when the proxy stops waiting for its response
+///
+/// A shorter `grpc-timeout` set by the client is kept; the proxy's own
+/// timeout still applies.
+fn set_deadline_headers(headers: &mut HeaderMap, timeout: Duration, now: SystemTime) {
+    let client_timeout = headers
+        .get(GRPC_TIMEOUT)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_grpc_timeout);
+    let timeout = client_timeout.map_or(timeout, |client| client.min(timeout));
+
+    let deadline_ms = (now + timeout)
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    headers.insert(X_REQUEST_DEADLINE, HeaderValue::from(deadline_ms as u64));
+
+    let is_grpc = headers
+        .get(hyper::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/grpc"));
+    if is_grpc || client_timeout.is_some() {
+        if let Ok(value) = HeaderValue::from_str(&format_grpc_timeout(timeout)) {
+            headers.insert(GRPC_TIMEOUT, value);
+        }
+    }
+}
+
+/// Parse a `grpc-timeout` value: up to 8 digits followed by a unit
+fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    if value.len() < 2 || value.len() > 9 {
+        return None;
+    }
+    let (digits, unit) = value.split_at(value.len() - 1);
+    let amount: u64 = digits.parse().ok()?;
+    match unit {
+        "H" => Some(Duration::from_secs(amount * 3600)),
+        "M" => Some(Duration::from_secs(amount * 60)),
+        "S" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_millis(amount)),
+        "u" => Some(Duration::from_micros(amount)),
+        "n" => Some(Duration::from_nanos(amount)),
+        _ => None,
+    }
+}
+
+/// Format a timeout as `grpc-timeout`, in milliseconds while they fit in 8 digits
+fn format_grpc_timeout(timeout: Duration) -> String {
+    const MAX: u128 = 99_999_999;
+    let millis = timeout.as_millis();
+    if millis <= MAX {
+        format!("{}m", millis)
+    } else {
+        format!("{}S", timeout.as_secs().min(MAX as u64))
+    }
+}
+
+/// Add the time spent before the upstream stage to the response's timings
+fn with_wait_timings(mut response: ProxyResponse, queue: Duration, spawn: Duration) -> ProxyResponse {
+    let timings = response.extensions_mut().get_or_insert_default::<RequestTimings>();
+    timings.queue = queue;
+    timings.spawn = spawn;
+    response
+}
+
+/// Instruction from a backend response to let the proxy produce the body
+#[derive(Debug, Clone)]
+pub enum InternalRedirect {
+    /// Serve `path` from disk; it must resolve inside `root`
+    File { root: PathBuf, path: PathBuf },
+    /// Re-dispatch a GET for `path_and_query` to another backend
+    Backend {
+        hostname: String,
+        path_and_query: String,
+        headers: HeaderMap,
+    },
+}
+
+/// Parse X-Accel-Redirect / X-Sendfile from a backend response
+///
+/// `X-Accel-Redirect: /path` serves `path` relative to `root`;
+/// `X-Accel-Redirect: @host/path` re-dispatches to backend `host`;
+/// `X-Sendfile: /abs/path` serves an absolute path that must lie inside `root`.
+fn parse_internal_redirect(
+    response_headers: &HeaderMap,
+    root: Option<PathBuf>,
+    request_headers: HeaderMap,
+) -> Option<Result<InternalRedirect, &'static str>> {
+    if let Some(value) = response_headers.get(X_ACCEL_REDIRECT) {
+        let Ok(value) = value.to_str() else {
+            return Some(Err("Invalid X-Accel-Redirect header"));
+        };
+
+        if let Some(target) = value.strip_prefix('@') {
+            let (hostname, path_and_query) = match target.find('/') {
+                Some(i) => target.split_at(i),
+                None => (target, "/"),
+            };
+            if hostname.is_empty() {
+                return Some(Err("Invalid X-Accel-Redirect header"));
+            }
+            return Some(Ok(InternalRedirect::Backend {
+                hostname: hostname.to_ascii_lowercase(),
+                path_and_query: path_and_query.to_string(),
+                headers: request_headers,
+            }));
+        }
+
+        let Some(root) = root else {
+            return Some(Err("X-Accel-Redirect file target requires 'internal_root'"));
+        };
+        let relative = value.split('?').next().unwrap_or("").trim_start_matches('/');
+        let path = root.join(relative);
+        return Some(Ok(InternalRedirect::File { root, path }));
+    }
+
+    if let Some(value) = response_headers.get(X_SENDFILE) {
+        let Ok(value) = value.to_str() else {
+            return Some(Err("Invalid X-Sendfile header"));
+        };
+        let Some(root) = root else {
+            return Some(Err("X-Sendfile requires 'internal_root'"));
+        };
+        return Some(Ok(InternalRedirect::File {
+            root,
+            path: PathBuf::from(value),
+        }));
+    }
+
+    None
+}
 
 /// The main reverse proxy server
 pub struct ProxyServer {
     bind_addr: SocketAddr,
     process_manager: Arc<ProcessManager>,
-    defaults: SharedDefaults,
     shutdown_rx: watch::Receiver<bool>,
     pool: Arc<ConnectionPool>,
+    pipeline: Pipeline,
     tls_acceptor: Option<TlsAcceptor>,
-    /// If set, redirect all HTTP requests to this HTTPS port
+    tls_metrics: Option<Arc<TlsHandshakeMetrics>>,
+    /// Fingerprints TLS clients, when enabled
+    fingerprinter: Option<Arc<Fingerprinter>>,
+    /// Methods processed from TLS early data, when 0-RTT is enabled
+    early_data: Option<EarlyDataConfig>,
+    /// How request paths are normalized before routing
+    path_normalization: PathNormalizationConfig,
+    /// If set, redirect HTTP requests to this HTTPS port
     https_redirect_port: Option<u16>,
+    https_redirect_status: StatusCode,
     /// ACME HTTP-01 challenges
     acme_challenges: Option<Http01Challenges>,
+    /// How long open connections may finish after shutdown
+    drain_timeout: Duration,
+    /// Close keep-alive connections after their in-flight requests on shutdown
+    drain_close_keep_alive: bool,
+    /// Lists the server's open client connections
+    registry: Arc<DebugRegistry>,
 }
 
+/// Default time open connections get to finish after shutdown
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
 impl ProxyServer {
     pub fn new(
         bind_addr: SocketAddr,
@@ -70,35 +1073,180 @@ impl ProxyServer {
         pool_config: PoolConfig,
     ) -> Self {
         let pool = Arc::new(ConnectionPool::new(pool_config));
+        let pipeline = Pipeline::new(Arc::clone(&process_manager), defaults, Arc::clone(&pool));
         Self {
             bind_addr,
             process_manager,
-            defaults,
             shutdown_rx,
             pool,
+            pipeline,
             tls_acceptor: None,
+            tls_metrics: None,
+            fingerprinter: None,
+            early_data: None,
+            path_normalization: PathNormalizationConfig::default(),
             https_redirect_port: None,
+            https_redirect_status: StatusCode::MOVED_PERMANENTLY,
             acme_challenges: None,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            drain_close_keep_alive: true,
+            registry: Arc::new(DebugRegistry::new()),
         }
     }
 
-    pub fn with_tls(mut self, acceptor: TlsAcceptor) -> Self {
-        self.tls_acceptor = Some(acceptor);
+    pub fn with_tls(mut self, acceptor: TlsAcceptor) -> Self {
+        self.tls_acceptor = Some(acceptor);
+        self
+    }
+
+    /// Count TLS handshakes by protocol version, cipher, SNI, ALPN and resumption
+    pub fn with_tls_metrics(mut self, metrics: Arc<TlsHandshakeMetrics>) -> Self {
+        self.tls_metrics = Some(metrics);
+        self
+    }
+
+    /// Fingerprint TLS clients, passing the fingerprints to requests and closing blocklisted connections
+    ///
+    /// Client-sent `X-JA3-Fingerprint` and `X-JA4-Fingerprint` headers are removed
+    /// on every listener this is set for, TLS or not.
+    pub fn with_tls_fingerprint(mut self, fingerprinter: Arc<Fingerprinter>) -> Self {
+        self.fingerprinter = Some(fingerprinter);
+        self
+    }
+
+    /// Hand TLS early data to requests before the handshake completes
+    ///
+    /// The acceptor's config must allow early data (see [`early_data::configure`]).
+    /// Requests received before the handshake completes are forwarded with
+    /// `Early-Data: 1` if their method is allowed and rejected with 425 otherwise.
+    pub fn with_early_data(mut self, config: EarlyDataConfig) -> Self {
+        self.early_data = Some(config);
+        self
+    }
+
+    /// Set how request paths are normalized (enabled with defaults unless changed)
+    pub fn with_path_normalization(mut self, config: PathNormalizationConfig) -> Self {
+        self.path_normalization = config;
+        self
+    }
+
+    /// Enable HTTPS redirect: HTTP requests will be redirected to HTTPS on the given port,
+    /// except for backends and paths exempted in their config
+    pub fn with_https_redirect(mut self, port: u16) -> Self {
+        self.https_redirect_port = Some(port);
+        self
+    }
+
+    /// Status code for HTTPS redirects (default: 301)
+    pub fn with_https_redirect_status(mut self, status: StatusCode) -> Self {
+        self.https_redirect_status = status;
+        self
+    }
+
+    /// Set ACME HTTP-01 challenge handler
+    pub fn with_acme_challenges(mut self, challenges: Http01Challenges) -> Self {
+        self.acme_challenges = Some(challenges);
+        self
+    }
+
+    /// Throttle cold starts per client IP
+    pub fn with_cold_start_throttle(mut self, throttle: Arc<ColdStartThrottle>) -> Self {
+        self.pipeline = self.pipeline.with_cold_start_throttle(throttle);
+        self
+    }
+
+    /// Pick backends with a custom router (replaces the route stage)
+    pub fn with_router(mut self, router: Arc<dyn Router>) -> Self {
+        self.pipeline = self.pipeline.with_router(router);
+        self
+    }
+
+    /// Record per-backend request metrics
+    pub fn with_request_metrics(mut self, metrics: Arc<RequestMetrics>) -> Self {
+        self.pipeline = self.pipeline.with_metrics(metrics);
+        self
+    }
+
+    /// Emit sampled per-request trace events
+    pub fn with_trace_sampler(mut self, sampler: Arc<TraceSampler>) -> Self {
+        self.pipeline = self.pipeline.with_trace_sampler(sampler);
+        self
+    }
+
+    /// Log slow requests with a timing breakdown
+    pub fn with_slow_log(mut self, slow_log: Arc<SlowRequestLog>) -> Self {
+        self.pipeline = self.pipeline.with_slow_log(slow_log);
+        self
+    }
+
+    /// Track cold-start latency against the SLO
+    pub fn with_cold_start_slo(mut self, slo: Arc<ColdStartSlo>) -> Self {
+        self.pipeline = self.pipeline.with_cold_start_slo(slo);
+        self
+    }
+
+    /// Record per-variant metrics of A/B experiments
+    pub fn with_experiment_metrics(mut self, metrics: Arc<ExperimentMetrics>) -> Self {
+        self.pipeline = self.pipeline.with_experiment_metrics(metrics);
+        self
+    }
+
+    /// Forward through a pool shared with other listeners
+    ///
+    /// Idle connection limits and pool statistics then cover every listener
+    /// using the pool. A custom upstream stage is kept.
+    pub fn with_pool(mut self, pool: Arc<ConnectionPool>) -> Self {
+        self.pipeline = self.pipeline.with_pool(Arc::clone(&pool));
+        self.pool = pool;
         self
     }
 
-    /// Enable HTTPS redirect: all HTTP requests will be redirected to HTTPS on the given port
-    pub fn with_https_redirect(mut self, port: u16) -> Self {
-        self.https_redirect_port = Some(port);
+    /// How long open connections may finish after shutdown (default: 30s)
+    ///
+    /// On shutdown the server stops accepting, asks open connections to close
+    /// once their in-flight requests complete and waits up to this long.
+    pub fn with_drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
         self
     }
 
-    /// Set ACME HTTP-01 challenge handler
-    pub fn with_acme_challenges(mut self, challenges: Http01Challenges) -> Self {
-        self.acme_challenges = Some(challenges);
+    /// Whether draining closes keep-alive connections (default: true)
+    ///
+    /// HTTP/1.1 responses sent after shutdown carry `Connection: close` and
+    /// HTTP/2 clients get a GOAWAY. When disabled, open connections keep
+    /// serving requests until the client closes them or the drain timeout
+    /// ends, e.g. while a load balancer is still sending traffic.
+    pub fn with_drain_close_keep_alive(mut self, close: bool) -> Self {
+        self.drain_close_keep_alive = close;
+        self
+    }
+
+    /// List open client connections in `registry` rather than a registry of
+    /// this server's own
+    ///
+    /// Backend connections are listed in the registry of the connection pool
+    /// (see [`ConnectionPool::with_registry`]).
+    pub fn with_debug_registry(mut self, registry: Arc<DebugRegistry>) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Get the registry listing the server's client connections
+    pub fn debug_registry(&self) -> &Arc<DebugRegistry> {
+        &self.registry
+    }
+
+    /// Replace the request pipeline (e.g. to swap individual stages)
+    pub fn with_pipeline(mut self, pipeline: Pipeline) -> Self {
+        self.pipeline = pipeline;
         self
     }
 
+    /// Get the request pipeline
+    pub fn pipeline(&self) -> &Pipeline {
+        &self.pipeline
+    }
+
     /// Get the connection pool (for statistics)
     pub fn pool(&self) -> &Arc<ConnectionPool> {
         &self.pool
@@ -110,39 +1258,128 @@ impl ProxyServer {
 
     pub async fn run(self) -> anyhow::Result<()> {
         let listener = TcpListener::bind(self.bind_addr).await?;
+        self.serve(listener).await
+    }
+
+    /// Serve connections from an already-bound listener
+    ///
+    /// Lets callers bind port 0 and learn the actual address before serving.
+    pub async fn serve(self, listener: TcpListener) -> anyhow::Result<()> {
+        self.serve_shared(Arc::new(listener)).await
+    }
+
+    /// Serve connections from a listener that may outlive this server
+    ///
+    /// A replacement server can take over the same listener, so no connection
+    /// is refused while one server hands over to the next.
+    pub async fn serve_shared(self, listener: Arc<TcpListener>) -> anyhow::Result<()> {
+        let local_addr = listener.local_addr()?;
         let protocol = if self.tls_acceptor.is_some() { "HTTPS" } else { "HTTP" };
-        info!(addr = %self.bind_addr, protocol, "Proxy server listening (HTTP/1.1 and HTTP/2)");
+        info!(addr = %local_addr, protocol, "Proxy server listening (HTTP/1.1 and HTTP/2)");
 
         let mut shutdown_rx = self.shutdown_rx.clone();
         let tls_acceptor = self.tls_acceptor.clone();
-        let https_redirect_port = self.https_redirect_port;
-        let acme_challenges = self.acme_challenges.clone();
+        let tls_metrics = self.tls_metrics.clone();
+        let fingerprinter = self.fingerprinter.clone();
+        let handler = Arc::new(RequestHandler {
+            pipeline: self.pipeline.clone(),
+            is_tls: tls_acceptor.is_some(),
+            process_manager: Arc::clone(&self.process_manager),
+            https_redirect_port: self.https_redirect_port,
+            https_redirect_status: self.https_redirect_status,
+            acme_challenges: self.acme_challenges.clone(),
+            early_data: self.early_data.clone(),
+            path_normalization: self.path_normalization.clone(),
+            drain_close_keep_alive: self.drain_close_keep_alive,
+            forward_fingerprints: self.fingerprinter.as_ref().map(|f| f.forward_headers()),
+        });
+        let mut connections = JoinSet::new();
 
         loop {
             tokio::select! {
                 result = listener.accept() => {
                     match result {
-                        Ok((stream, addr)) => {
-                            let process_manager = Arc::clone(&self.process_manager);
-                            let defaults = Arc::clone(&self.defaults);
-                            let pool = Arc::clone(&self.pool);
+                        Ok((mut stream, addr)) => {
                             let tls_acceptor = tls_acceptor.clone();
-                            let acme_challenges = acme_challenges.clone();
+                            let tls_metrics = tls_metrics.clone();
+                            let fingerprinter = fingerprinter.clone();
+                            let handler = Arc::clone(&handler);
+                            let shutdown = self.shutdown_rx.clone();
+                            let connection = Arc::new(self.registry.client(addr, local_addr, handler.is_tls));
+
+                            connections.spawn(async move {
+                                let Some(acceptor) = tls_acceptor else {
+                                    if let Err(e) = handle_connection(stream, addr, handler, None, None, shutdown, connection).await {
+                                        debug!(addr = %addr, error = %e, "Connection error");
+                                    }
+                                    return;
+                                };
+
+                                // The ClientHello is read for fingerprinting, then replayed to rustls
+                                let (client_hello, fingerprint) = match &fingerprinter {
+                                    Some(fingerprinter) => match fingerprint::read_client_hello(&mut stream).await {
+                                        Ok((read, hello)) => {
+                                            (read, hello.map(|hello| Arc::new(fingerprinter.fingerprint(&hello))))
+                                        }
+                                        Err(e) => {
+                                            debug!(addr = %addr, error = %e, "Failed to read TLS ClientHello");
+                                            return;
+                                        }
+                                    },
+                                    None => (Vec::new(), None),
+                                };
+                                if let (Some(fingerprinter), Some(fingerprint)) = (&fingerprinter, &fingerprint) {
+                                    let close =
+                                        fingerprint.blocked && fingerprinter.block() == FingerprintBlock::Connection;
+                                    if let Some(metrics) = &tls_metrics {
+                                        metrics.record_fingerprint(&fingerprint.ja4, close);
+                                    }
+                                    if close {
+                                        debug!(
+                                            addr = %addr,
+                                            ja3 = fingerprint.ja3,
+                                            ja4 = fingerprint.ja4,
+                                            "Closing connection from blocklisted TLS client"
+                                        );
+                                        return;
+                                    }
+                                }
+                                let stream = Rewind::new(client_hello, stream);
 
-                            tokio::spawn(async move {
-                                if let Some(acceptor) = tls_acceptor {
+                                let result = if handler.early_data.is_some() {
+                                    match early_data::accept(Arc::clone(acceptor.config()), stream).await {
+                                        Ok(tls_stream) => {
+                                            if let Some(metrics) = &tls_metrics {
+                                                metrics.record_connection(tls_stream.get_ref().1);
+                                            }
+                                            let handshake = tls_stream.handshake_state();
+                                            Ok(handle_connection(tls_stream, addr, handler, Some(handshake), fingerprint, shutdown, connection).await)
+                                        }
+                                        Err(e) => Err(e),
+                                    }
+                                } else {
                                     match acceptor.accept(stream).await {
                                         Ok(tls_stream) => {
-                                            if let Err(e) = handle_connection(tls_stream, addr, process_manager, defaults, pool, true, None, None).await {
-                                                debug!(addr = %addr, error = %e, "TLS connection error");
+                                            if let Some(metrics) = &tls_metrics {
+                                                metrics.record_connection(tls_stream.get_ref().1);
                                             }
+                                            Ok(handle_connection(tls_stream, addr, handler, None, fingerprint, shutdown, connection).await)
                                         }
-                                        Err(e) => {
-                                            debug!(addr = %addr, error = %e, "TLS handshake failed");
+                                        Err(e) => Err(e),
+                                    }
+                                };
+
+                                match result {
+                                    Ok(Ok(())) => {}
+                                    Ok(Err(e)) => {
+                                        debug!(addr = %addr, error = %e, "TLS connection error");
+                                    }
+                                    Err(e) => {
+                                        if let Some(metrics) = &tls_metrics {
+                                            metrics.record_failure();
                                         }
+                                        debug!(addr = %addr, error = %e, "TLS handshake failed");
                                     }
-                                } else if let Err(e) = handle_connection(stream, addr, process_manager, defaults, pool, false, https_redirect_port, acme_challenges).await {
-                                    debug!(addr = %addr, error = %e, "Connection error");
                                 }
                             });
                         }
@@ -151,248 +1388,334 @@ impl ProxyServer {
                         }
                     }
                 }
+                // Reap finished connections so the set only holds open ones
+                Some(_) = connections.join_next(), if !connections.is_empty() => {}
                 _ = shutdown_rx.changed() => {
                     if *shutdown_rx.borrow() {
-                        info!("Proxy server shutting down");
+                        info!(addr = %local_addr, protocol, "Proxy server shutting down");
                         break;
                     }
                 }
             }
         }
 
+        // Stop accepting before draining, unless another server took the listener over
+        drop(listener);
+        if !connections.is_empty() {
+            info!(addr = %local_addr, connections = connections.len(), "Draining open connections");
+            let drained = tokio::time::timeout(self.drain_timeout, async {
+                while connections.join_next().await.is_some() {}
+            })
+            .await;
+            match drained {
+                Ok(()) => info!(addr = %local_addr, protocol, "Open connections drained"),
+                Err(_) => warn!(
+                    addr = %local_addr,
+                    connections = connections.len(),
+                    "Drain timeout reached, closing remaining connections"
+                ),
+            }
+        }
+
         Ok(())
     }
 }
 
-#[allow(clippy::too_many_arguments)]
-async fn handle_connection<S>(
-    stream: S,
-    addr: SocketAddr,
-    process_manager: Arc<ProcessManager>,
-    defaults: SharedDefaults,
-    pool: Arc<ConnectionPool>,
+/// Listener-level request handling that runs before the pipeline
+struct RequestHandler {
+    pipeline: Pipeline,
     is_tls: bool,
+    process_manager: Arc<ProcessManager>,
     https_redirect_port: Option<u16>,
+    https_redirect_status: StatusCode,
     acme_challenges: Option<Http01Challenges>,
+    early_data: Option<EarlyDataConfig>,
+    path_normalization: PathNormalizationConfig,
+    drain_close_keep_alive: bool,
+    /// Set when fingerprinting: whether fingerprints are forwarded to backends
+    forward_fingerprints: Option<bool>,
+}
+
+/// Serve HTTP on an accepted connection
+///
+/// `handshake` is set for TLS connections that may carry early data, and
+/// `fingerprint` for TLS connections when fingerprinting is enabled. Once
+/// `shutdown` fires the connection is closed after its in-flight requests
+/// complete: HTTP/1.1 responses carry `Connection: close` and HTTP/2 clients
+/// get a GOAWAY. Without `drain_close_keep_alive` the connection is left open.
+async fn handle_connection<S>(
+    stream: S,
+    addr: SocketAddr,
+    handler: Arc<RequestHandler>,
+    handshake: Option<HandshakeState>,
+    fingerprint: Option<Arc<TlsFingerprint>>,
+    mut shutdown: watch::Receiver<bool>,
+    connection: Arc<ClientConnection>,
 ) -> anyhow::Result<()>
 where
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
     let io = TokioIo::new(stream);
-
-    let service = service_fn(move |req: Request<Incoming>| {
-        let pm = Arc::clone(&process_manager);
-        let defs = Arc::clone(&defaults);
-        let pool = Arc::clone(&pool);
-        let client_addr = addr;
-        let acme = acme_challenges.clone();
-        async move { handle_request(req, pm, defs, pool, client_addr, is_tls, https_redirect_port, acme).await }
+    let upgrade_shutdown = shutdown.clone();
+    let plaintext = !handler.is_tls;
+    let close_keep_alive = handler.drain_close_keep_alive;
+    let draining = shutdown.clone();
+
+    let proxied = service_fn(move |req: Request<Incoming>| {
+        connection.record_request();
+        let handler = Arc::clone(&handler);
+        let handshake = handshake.clone();
+        let fingerprint = fingerprint.clone();
+        let draining = draining.clone();
+        let version = req.version();
+        async move {
+            let mut response = handler
+                .handle_request(req, addr, handshake, fingerprint)
+                .await
+                .map_err(std::io::Error::other)?;
+            if response.extensions().get::<DropConnection>().is_some() {
+                // Failing the service makes hyper close the connection (or reset the HTTP/2 stream)
+                return Err(std::io::Error::other("connection dropped by fault injection"));
+            }
+            // Responses finishing after shutdown tell keep-alive clients to reconnect
+            if close_keep_alive
+                && version == hyper::Version::HTTP_11
+                && response.status() != StatusCode::SWITCHING_PROTOCOLS
+                && *draining.borrow()
+            {
+                response
+                    .headers_mut()
+                    .insert(hyper::header::CONNECTION, HeaderValue::from_static("close"));
+            }
+            Ok(response)
+        }
     });
 
+    // Plaintext HTTP/1.1 clients may upgrade to HTTP/2; the task serving the
+    // upgraded connection is awaited so draining covers it
+    let h2c_task = Arc::new(parking_lot::Mutex::new(None));
+    let service = {
+        let h2c_task = Arc::clone(&h2c_task);
+        service_fn(move |mut req: Request<Incoming>| {
+            let upgrade = plaintext
+                .then(|| h2c::upgrade(&mut req, proxied.clone(), upgrade_shutdown.clone()))
+                .flatten();
+            let proxied = proxied.clone();
+            let h2c_task = Arc::clone(&h2c_task);
+            async move {
+                match upgrade {
+                    Some((response, task)) => {
+                        *h2c_task.lock() = Some(task);
+                        Ok(response)
+                    }
+                    None => proxied.call(req).await,
+                }
+            }
+        })
+    };
+
     // Use auto::Builder to support both HTTP/1.1 and HTTP/2
-    // HTTP/2 uses h2c (HTTP/2 cleartext) or h2 over TLS
+    // HTTP/2 uses h2c (HTTP/2 cleartext, prior knowledge or upgrade) or h2 over TLS (ALPN)
     // HTTP/1.1 connections can still use WebSocket upgrades
-    AutoBuilder::new(TokioExecutor::new())
-        .http1()
-        .preserve_header_case(true)
-        .http2()
-        .max_concurrent_streams(250)
-        .serve_connection_with_upgrades(io, service)
-        .await
-        .map_err(|e| anyhow::anyhow!("Connection error: {}", e))?;
-
-    Ok(())
-}
-
-#[allow(clippy::too_many_arguments)]
-async fn handle_request(
-    mut req: Request<Incoming>,
-    process_manager: Arc<ProcessManager>,
-    defaults: SharedDefaults,
-    pool: Arc<ConnectionPool>,
-    client_addr: SocketAddr,
-    is_tls: bool,
-    https_redirect_port: Option<u16>,
-    acme_challenges: Option<Http01Challenges>,
-) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
-    // Handle ACME HTTP-01 challenges first (before HTTPS redirect)
-    if let Some(ref challenges) = acme_challenges {
-        let path = req.uri().path();
-        if let Some(token) = path.strip_prefix(ACME_CHALLENGE_PREFIX) {
-            if let Some(key_auth) = challenges.get(token).await {
-                debug!(token, "Responding to ACME HTTP-01 challenge");
-                return Ok(Response::builder()
-                    .status(StatusCode::OK)
-                    .header(hyper::header::CONTENT_TYPE, "text/plain")
-                    .body(Full::new(Bytes::from(key_auth)).map_err(|never| match never {}).boxed())
-                    .expect("valid response builder"));
+    let mut builder = AutoBuilder::new(TokioExecutor::new());
+    builder.http1().preserve_header_case(true);
+    builder.http2().max_concurrent_streams(250);
+    let conn = builder.serve_connection_with_upgrades(io, service);
+    tokio::pin!(conn);
+
+    let result = tokio::select! {
+        result = conn.as_mut() => result,
+        _ = async {
+            if !close_keep_alive || shutdown.wait_for(|stop| *stop).await.is_err() {
+                std::future::pending::<()>().await;
             }
-        }
-    }
-
-    // Handle HTTPS redirect if configured (for non-TLS connections)
-    if let Some(redirect_port) = https_redirect_port {
-        if !is_tls {
-            return Ok(build_https_redirect(&req, redirect_port));
-        }
-    }
-
-    // Generate or propagate request ID
-    let request_id = req
-        .headers()
-        .get(X_REQUEST_ID)
-        .and_then(|v| v.to_str().ok())
-        .map(String::from)
-        .unwrap_or_else(|| Uuid::new_v4().to_string());
-
-    // Extract hostname from Host header
-    let hostname = match extract_hostname(&req) {
-        Some(h) => h,
-        None => {
-            return Ok(json_error_response(
-                ProxyErrorCode::MissingHostHeader,
-                "Missing or invalid Host header",
-            ));
+        } => {
+            conn.as_mut().graceful_shutdown();
+            conn.await
         }
     };
+    result.map_err(|e| anyhow::anyhow!("Connection error: {}", e))?;
 
-    // Add proxy headers
-    // Security: We overwrite X-Forwarded-* headers rather than appending to prevent
-    // client spoofing. This proxy is assumed to be the first trusted hop.
-    let headers = req.headers_mut();
-
-    // Set X-Request-ID
-    if let Ok(value) = HeaderValue::from_str(&request_id) {
-        headers.insert(X_REQUEST_ID, value);
-    }
-
-    // Set X-Forwarded-For to the actual client IP (overwrites any client-provided value)
-    if let Ok(value) = HeaderValue::from_str(&client_addr.ip().to_string()) {
-        headers.insert(X_FORWARDED_FOR, value);
+    let h2c_task = h2c_task.lock().take();
+    if let Some(task) = h2c_task {
+        let _ = task.await;
     }
 
-    // Set X-Forwarded-Host (original Host header, overwrites any client-provided value)
-    if let Some(host) = headers.get(hyper::header::HOST).cloned() {
-        headers.insert(X_FORWARDED_HOST, host);
-    }
-
-    // Set X-Forwarded-Proto (overwrites any client-provided value)
-    let proto = if is_tls { "https" } else { "http" };
-    headers.insert(X_FORWARDED_PROTO, HeaderValue::from_static(proto));
-
-    debug!(hostname, method = %req.method(), uri = %req.uri(), request_id, "Incoming request");
-
-    // Check if we have a backend configured for this host
-    if !process_manager.has_backend(&hostname) {
-        // Don't reveal whether host exists - use generic message
-        return Ok(json_error_response(
-            ProxyErrorCode::UnknownHost,
-            "Unknown or unconfigured host",
-        ));
-    }
-
-    // Check if backend is in draining mode (stopping)
-    let state = process_manager.get_state(&hostname);
-    if state == BackendState::Stopping {
-        return Ok(json_error_response(
-            ProxyErrorCode::BackendShuttingDown,
-            "Backend is shutting down, please retry later",
-        ));
-    }
+    Ok(())
+}
 
-    // Check if backend is unhealthy
-    if state == BackendState::Unhealthy {
-        return Ok(json_error_response(
-            ProxyErrorCode::BackendUnhealthy,
-            "Backend is currently unhealthy, auto-restart in progress",
-        ));
+impl RequestHandler {
+    async fn handle_request(
+        &self,
+        req: Request<Incoming>,
+        client_addr: SocketAddr,
+        handshake: Option<HandshakeState>,
+        fingerprint: Option<Arc<TlsFingerprint>>,
+    ) -> Result<ProxyResponse, hyper::Error> {
+        // Generate or propagate request ID
+        let request_id = req
+            .headers()
+            .get(X_REQUEST_ID)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from)
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+        let response = self
+            .process_request(req, client_addr, handshake, fingerprint, request_id.clone())
+            .await?;
+        Ok(error::with_request_details(response, &request_id))
     }
 
-    // Ensure backend is running and ready
-    match ensure_backend_ready(&hostname, &process_manager, &defaults).await {
-        Ok(()) => {}
-        Err(e) => {
-            // Log detailed error internally, return generic message externally
-            error!(hostname, error = %e, "Failed to start backend");
-            return Ok(json_error_response(
-                ProxyErrorCode::BackendStartFailed,
-                "Backend unavailable",
-            ));
+    async fn process_request(
+        &self,
+        mut req: Request<Incoming>,
+        client_addr: SocketAddr,
+        handshake: Option<HandshakeState>,
+        fingerprint: Option<Arc<TlsFingerprint>>,
+        request_id: String,
+    ) -> Result<ProxyResponse, hyper::Error> {
+        let received = Instant::now();
+
+        // Requests received in TLS early data may be replays: only allowed methods
+        // are processed before the handshake completes, marked for the backend
+        if handshake.is_some_and(|h| !h.is_complete()) {
+            let method = req.method().as_str();
+            if !self.early_data.as_ref().is_some_and(|c| c.allows(method)) {
+                debug!(method, uri = %req.uri(), "Rejecting request received in TLS early data");
+                return Ok(json_error_response(
+                    ProxyErrorCode::TooEarly,
+                    "Request method not allowed in TLS early data; retry after the handshake",
+                ));
+            }
+            req.headers_mut().insert(EARLY_DATA, HeaderValue::from_static("1"));
         }
-    }
 
-    // Update activity timestamp
-    process_manager.touch(&hostname);
+        // Normalize the path before anything matches on it
+        if self.path_normalization.enabled {
+            match normalize::normalize_uri(req.uri(), &self.path_normalization) {
+                Ok(Some(uri)) => {
+                    debug!(uri = %req.uri(), normalized = %uri, "Normalized request path");
+                    *req.uri_mut() = uri;
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    debug!(uri = %req.uri(), error = %e, "Rejecting request path");
+                    return Ok(json_error_response(ProxyErrorCode::InvalidPath, e.to_string()));
+                }
+            }
+        }
 
-    // Get the backend port and request timeout
-    let (port, request_timeout) = match process_manager.get_config(&hostname) {
-        Some(config) => {
-            let defaults_ref = defaults.read();
-            (config.port, config.request_timeout(&defaults_ref))
+        // Handle ACME HTTP-01 challenges first (before HTTPS redirect)
+        if let Some(ref challenges) = self.acme_challenges {
+            let path = req.uri().path();
+            if let Some(token) = path.strip_prefix(ACME_CHALLENGE_PREFIX) {
+                if let Some(key_auth) = challenges.get(token).await {
+                    debug!(token, "Responding to ACME HTTP-01 challenge");
+                    return Ok(Response::builder()
+                        .status(StatusCode::OK)
+                        .header(hyper::header::CONTENT_TYPE, "text/plain")
+                        .body(Full::new(Bytes::from(key_auth)).map_err(|never| match never {}).boxed())
+                        .expect("valid response builder"));
+                }
+            }
         }
-        None => {
-            return Ok(json_error_response(
-                ProxyErrorCode::BackendConfigError,
-                "Backend configuration not found",
-            ));
+
+        // Handle HTTPS redirect if configured (for non-TLS connections)
+        if let Some(redirect_port) = self.https_redirect_port {
+            let exempt = extract_hostname(&req)
+                .is_some_and(|hostname| !self.process_manager.redirects_to_https(&hostname, req.uri().path()));
+            if !self.is_tls && !exempt {
+                return Ok(build_https_redirect(&req, redirect_port, self.https_redirect_status));
+            }
         }
-    };
 
-    // Check for WebSocket/HTTP upgrade request
-    if is_upgrade_request(&req) {
-        return handle_upgrade(req, process_manager, hostname, port, request_id).await;
-    }
+        // Extract hostname from Host header
+        let hostname = match extract_hostname(&req) {
+            Some(h) => h,
+            None => {
+                return Ok(json_error_response(
+                    ProxyErrorCode::MissingHostHeader,
+                    "Missing or invalid Host header",
+                ));
+            }
+        };
 
-    // Track in-flight request - also atomically verifies backend is still Ready
-    if !process_manager.increment_in_flight(&hostname) {
-        // Backend state changed between ensure_backend_ready and now
-        return Ok(json_error_response(
-            ProxyErrorCode::BackendShuttingDown,
-            "Backend state changed, please retry",
-        ));
-    }
+        // Add proxy headers
+        // Security: We overwrite X-Forwarded-* headers rather than appending to prevent
+        // client spoofing. This proxy is assumed to be the first trusted hop.
+        let forwarded_host = request_host(&req).and_then(|h| HeaderValue::from_str(h).ok());
+        let headers = req.headers_mut();
 
-    // Forward the request through the connection pool with timeout
-    let result = tokio::time::timeout(request_timeout, pool.send_request(req, port)).await;
+        // Set X-Request-ID
+        if let Ok(value) = HeaderValue::from_str(&request_id) {
+            headers.insert(X_REQUEST_ID, value);
+        }
 
-    // Decrement in-flight counter when done
-    process_manager.decrement_in_flight(&hostname);
+        // Set X-Forwarded-For to the actual client IP (overwrites any client-provided value)
+        if let Ok(value) = HeaderValue::from_str(&client_addr.ip().to_string()) {
+            headers.insert(X_FORWARDED_FOR, value);
+        }
 
-    match result {
-        Ok(Ok(response)) => Ok(response),
-        Ok(Err(e)) => {
-            // Log detailed error internally, return generic message externally
-            error!(hostname, port, error = %e, "Failed to forward request via pool");
-            Ok(json_error_response(
-                ProxyErrorCode::ConnectionFailed,
-                "Failed to connect to backend",
-            ))
+        // Set X-Forwarded-Host (original Host or :authority, overwrites any client-provided value)
+        if let Some(host) = forwarded_host {
+            headers.insert(X_FORWARDED_HOST, host);
         }
-        Err(_) => {
-            warn!(
-                hostname,
-                port,
-                timeout_secs = request_timeout.as_secs(),
-                "Request timed out"
-            );
-            Ok(json_error_response(
-                ProxyErrorCode::RequestTimeout,
-                format!(
-                    "Request timed out after {} seconds",
-                    request_timeout.as_secs()
-                ),
-            ))
+
+        // Set X-Forwarded-Proto (overwrites any client-provided value)
+        let proto = if self.is_tls { "https" } else { "http" };
+        headers.insert(X_FORWARDED_PROTO, HeaderValue::from_static(proto));
+
+        // Set TLS fingerprint headers (removing any client-provided values)
+        if let Some(forward) = self.forward_fingerprints {
+            headers.remove(X_JA3_FINGERPRINT);
+            headers.remove(X_JA4_FINGERPRINT);
+            if let (true, Some(fingerprint)) = (forward, &fingerprint) {
+                for (name, value) in [(X_JA3_FINGERPRINT, &fingerprint.ja3), (X_JA4_FINGERPRINT, &fingerprint.ja4)] {
+                    if let Ok(value) = HeaderValue::from_str(value) {
+                        headers.insert(name, value);
+                    }
+                }
+            }
         }
+
+        debug!(
+            hostname,
+            method = %req.method(),
+            uri = %req.uri(),
+            request_id,
+            ja3 = fingerprint.as_ref().map(|f| f.ja3.as_str()),
+            ja4 = fingerprint.as_ref().map(|f| f.ja4.as_str()),
+            "Incoming request"
+        );
+
+        let ctx = RequestContext {
+            request_id,
+            hostname,
+            client_addr,
+            is_tls: self.is_tls,
+            received,
+            tls_fingerprint: fingerprint,
+        };
+
+        Ok(self.pipeline.handle(ctx, req).await)
     }
 }
 
 /// Maximum hostname length per DNS specification
 const MAX_HOSTNAME_LEN: usize = 253;
 
+/// The host a request is for, with any port
+///
+/// HTTP/2 clients send it in the `:authority` pseudo-header instead of `Host`.
+fn request_host<B>(req: &Request<B>) -> Option<&str> {
+    match req.headers().get(hyper::header::HOST) {
+        Some(host) => host.to_str().ok(),
+        None => req.uri().authority().map(|a| a.as_str()),
+    }
+}
+
 fn extract_hostname(req: &Request<Incoming>) -> Option<String> {
-    req.headers()
-        .get(hyper::header::HOST)
-        .and_then(|h| h.to_str().ok())
+    request_host(req)
         .and_then(|h| {
             // Strip port if present
             let hostname = h.split(':').next()?;
@@ -412,12 +1735,9 @@ fn extract_hostname(req: &Request<Incoming>) -> Option<String> {
         })
 }
 
-/// Build an HTTPS redirect response (301 Moved Permanently)
-fn build_https_redirect(req: &Request<Incoming>, https_port: u16) -> Response<BoxBody<Bytes, hyper::Error>> {
-    let host = req
-        .headers()
-        .get(hyper::header::HOST)
-        .and_then(|h| h.to_str().ok())
+/// Build an HTTPS redirect response with the given status (e.g. 301 Moved Permanently)
+fn build_https_redirect(req: &Request<Incoming>, https_port: u16, status: StatusCode) -> ProxyResponse {
+    let host = request_host(req)
         .map(|h| h.split(':').next().unwrap_or(h))
         .unwrap_or("localhost");
 
@@ -430,7 +1750,7 @@ fn build_https_redirect(req: &Request<Incoming>, https_port: u16) -> Response<Bo
     };
 
     Response::builder()
-        .status(StatusCode::MOVED_PERMANENTLY)
+        .status(status)
         .header(hyper::header::LOCATION, location)
         .header(hyper::header::CONTENT_TYPE, "text/plain")
         .body(
@@ -443,11 +1763,43 @@ fn build_https_redirect(req: &Request<Incoming>, https_port: u16) -> Response<Bo
 
 async fn ensure_backend_ready(
     hostname: &str,
+    class: PriorityClass,
     process_manager: &Arc<ProcessManager>,
     defaults: &SharedDefaults,
 ) -> anyhow::Result<()> {
     let state = process_manager.get_state(hostname);
+    if state == BackendState::Ready {
+        return Ok(());
+    }
+
+    let limit = process_manager
+        .get_config(hostname)
+        .and_then(|config| config.max_queue_depth(&defaults.read()));
+    let queue_full = || QueueFull {
+        depth: process_manager.get_queued(hostname),
+        estimated_ready: process_manager.estimated_ready(hostname),
+    };
+    let mut queued = match process_manager.queue_request(hostname, class, limit) {
+        Ok(queued) => queued,
+        Err(depth) => return Err(QueueFull { depth, ..queue_full() }.into()),
+    };
+
+    // A higher-class request may take this one's place while it waits
+    tokio::select! {
+        result = start_and_wait(hostname, state, process_manager, defaults) => result,
+        _ = queued.preempted() => {
+            debug!(hostname, class = class.as_str(), "Queued request preempted by a higher-priority one");
+            Err(queue_full().into())
+        }
+    }
+}
 
+async fn start_and_wait(
+    hostname: &str,
+    state: BackendState,
+    process_manager: &Arc<ProcessManager>,
+    defaults: &SharedDefaults,
+) -> anyhow::Result<()> {
     match state {
         BackendState::Ready => {
             // Already running and ready
@@ -458,8 +1810,7 @@ async fn ensure_backend_ready(
             return wait_for_ready(hostname, process_manager, defaults).await;
         }
         BackendState::Stopping => {
-            // Wait a bit and then try to start
-            tokio::time::sleep(Duration::from_millis(500)).await;
+            // The spawn waits for the previous instance to stop
         }
         BackendState::Unhealthy => {
             // Backend is unhealthy - auto-restart should be in progress
@@ -527,7 +1878,7 @@ async fn wait_for_ready(
 }
 
 /// Check if a request is a WebSocket upgrade request
-fn is_upgrade_request(req: &Request<Incoming>) -> bool {
+fn is_upgrade_request<B>(req: &Request<B>) -> bool {
     // Check for Connection: Upgrade header (case-insensitive value check)
     let has_upgrade_connection = req
         .headers()
@@ -543,7 +1894,7 @@ fn is_upgrade_request(req: &Request<Incoming>) -> bool {
 }
 
 /// Get the value of the Upgrade header
-fn get_upgrade_type(req: &Request<Incoming>) -> Option<String> {
+fn get_upgrade_type<B>(req: &Request<B>) -> Option<String> {
     req.headers()
         .get(hyper::header::UPGRADE)
         .and_then(|v| v.to_str().ok())
@@ -578,7 +1929,7 @@ async fn forward_bidirectional(
 }
 
 /// Build the raw HTTP upgrade request to send to the backend
-fn build_upgrade_request(req: &Request<Incoming>, port: u16) -> Vec<u8> {
+fn build_upgrade_request<B>(req: &Request<B>, port: u16) -> Vec<u8> {
     let path = req.uri().path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
     let mut request = format!(
         "{} {} HTTP/1.1\r\n",
@@ -631,12 +1982,12 @@ fn parse_upgrade_response(data: &[u8]) -> Option<(StatusCode, Vec<(String, Strin
 
 /// Handle a WebSocket upgrade request
 async fn handle_upgrade(
-    req: Request<Incoming>,
+    req: Request<ProxyBody>,
     process_manager: Arc<ProcessManager>,
     hostname: String,
     port: u16,
     request_id: String,
-) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+) -> ProxyResponse {
     let upgrade_type = get_upgrade_type(&req).unwrap_or_else(|| "unknown".to_string());
     debug!(hostname, request_id, upgrade_type, "Handling upgrade request");
 
@@ -649,20 +2000,20 @@ async fn handle_upgrade(
         Ok(stream) => stream,
         Err(e) => {
             error!(hostname, port, error = %e, "Failed to connect to backend for upgrade");
-            return Ok(json_error_response(
+            return json_error_response(
                 ProxyErrorCode::ConnectionFailed,
                 format!("Failed to connect to backend: {}", e),
-            ));
+            );
         }
     };
 
     // Send the upgrade request to the backend
     if let Err(e) = backend_stream.write_all(&raw_request).await {
         error!(hostname, error = %e, "Failed to send upgrade request to backend");
-        return Ok(json_error_response(
+        return json_error_response(
             ProxyErrorCode::ConnectionFailed,
             format!("Failed to send upgrade request: {}", e),
-        ));
+        );
     }
 
     // Read the backend's response
@@ -671,17 +2022,17 @@ async fn handle_upgrade(
         Ok(n) if n > 0 => n,
         Ok(_) => {
             error!(hostname, "Backend closed connection before responding to upgrade");
-            return Ok(json_error_response(
+            return json_error_response(
                 ProxyErrorCode::ConnectionFailed,
                 "Backend closed connection",
-            ));
+            );
         }
         Err(e) => {
             error!(hostname, error = %e, "Failed to read upgrade response from backend");
-            return Ok(json_error_response(
+            return json_error_response(
                 ProxyErrorCode::ConnectionFailed,
                 format!("Failed to read backend response: {}", e),
-            ));
+            );
         }
     };
 
@@ -690,10 +2041,10 @@ async fn handle_upgrade(
         Some(parsed) => parsed,
         None => {
             error!(hostname, "Failed to parse backend upgrade response");
-            return Ok(json_error_response(
+            return json_error_response(
                 ProxyErrorCode::ConnectionFailed,
                 "Invalid upgrade response from backend",
-            ));
+            );
         }
     };
 
@@ -707,19 +2058,19 @@ async fn handle_upgrade(
                 response = response.header(name.as_str(), hv);
             }
         }
-        return Ok(response
+        return response
             .body(Empty::<Bytes>::new().map_err(|never| match never {}).boxed())
-            .expect("valid response builder"));
+            .expect("valid response builder");
     }
 
     info!(hostname, request_id, upgrade_type, "WebSocket upgrade successful");
 
     // Track the WebSocket connection as in-flight - atomically verifies backend is Ready
     if !process_manager.increment_in_flight(&hostname) {
-        return Ok(json_error_response(
+        return json_error_response(
             ProxyErrorCode::BackendShuttingDown,
             "Backend state changed, please retry",
-        ));
+        );
     }
 
     // Build the 101 response to send to the client
@@ -759,5 +2110,389 @@ async fn handle_upgrade(
         debug!(hostname = hostname_clone, request_id = request_id_clone, "WebSocket connection closed");
     });
 
-    Ok(response)
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BackendDefaults;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Spawn-wait stage that never starts anything
+    struct NoopSpawnWait;
+
+    impl SpawnWait for NoopSpawnWait {
+        fn ensure_ready<'a>(&'a self, _hostname: &'a str, _class: PriorityClass) -> BoxFuture<'a, anyhow::Result<()>> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    /// Spawn-wait stage that always fails
+    struct FailingSpawnWait;
+
+    impl SpawnWait for FailingSpawnWait {
+        fn ensure_ready<'a>(&'a self, _hostname: &'a str, _class: PriorityClass) -> BoxFuture<'a, anyhow::Result<()>> {
+            Box::pin(async { Err(anyhow::anyhow!("boom")) })
+        }
+    }
+
+    /// Spawn-wait stage that fails for one hostname only
+    struct FailingFor(&'static str);
+
+    impl SpawnWait for FailingFor {
+        fn ensure_ready<'a>(&'a self, hostname: &'a str, _class: PriorityClass) -> BoxFuture<'a, anyhow::Result<()>> {
+            let failed = hostname == self.0;
+            Box::pin(async move {
+                if failed {
+                    Err(anyhow::anyhow!("boom"))
+                } else {
+                    Ok(())
+                }
+            })
+        }
+    }
+
+    /// Upstream stage that answers with the routed hostname and counts calls
+    #[derive(Default)]
+    struct EchoUpstream {
+        calls: AtomicUsize,
+    }
+
+    impl Upstream for EchoUpstream {
+        fn forward<'a>(
+            &'a self,
+            ctx: &'a RequestContext,
+            hostname: &'a str,
+            _req: Request<ProxyBody>,
+        ) -> BoxFuture<'a, ProxyResponse> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let body = format!("{} {}", hostname, ctx.request_id);
+            Box::pin(async move {
+                Response::new(Full::new(Bytes::from(body)).map_err(|never| match never {}).boxed())
+            })
+        }
+    }
+
+    /// Router that sends every request to a single backend
+    struct FixedRouter(&'static str);
+
+    impl Router for FixedRouter {
+        fn route(&self, _ctx: &RequestContext, _parts: &Parts) -> Option<String> {
+            Some(self.0.to_string())
+        }
+    }
+
+    /// Proxy running on an OS-assigned port with the given pipeline
+    struct Harness {
+        addr: SocketAddr,
+        shutdown_tx: watch::Sender<bool>,
+    }
+
+    impl Harness {
+        async fn start(pipeline: impl FnOnce(Pipeline) -> Pipeline) -> Self {
+            Self::start_server(|server| {
+                let stages = pipeline(server.pipeline().clone());
+                server.with_pipeline(stages)
+            })
+            .await
+        }
+
+        async fn start_server(setup: impl FnOnce(ProxyServer) -> ProxyServer) -> Self {
+            let mut backends = HashMap::new();
+            backends.insert(
+                "app.test".to_string(),
+                crate::config::BackendConfig::local("true", 1),
+            );
+            let mut primary = crate::config::BackendConfig::local("true", 2);
+            primary.fallback = Some("app.test".to_string());
+            backends.insert("primary.test".to_string(), primary);
+            let pm = ProcessManager::new(backends, BackendDefaults::default(), "http://127.0.0.1:0".into());
+            let defaults = pm.shared_defaults();
+            let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
+            let server = setup(ProxyServer::new("127.0.0.1:0".parse().unwrap(), pm, defaults, shutdown_rx));
+
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(server.serve(listener));
+
+            Self { addr, shutdown_tx }
+        }
+
+        async fn get(&self, host: &str) -> String {
+            self.get_with_headers(host, "").await
+        }
+
+        /// GET with extra header lines, each ending in CRLF
+        async fn get_with_headers(&self, host: &str, headers: &str) -> String {
+            let mut stream = TcpStream::connect(self.addr).await.unwrap();
+            let request = format!(
+                "GET / HTTP/1.1\r\nHost: {}\r\nX-Request-ID: req-1\r\n{}Connection: close\r\n\r\n",
+                host, headers
+            );
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        }
+    }
+
+    impl Drop for Harness {
+        fn drop(&mut self) {
+            let _ = self.shutdown_tx.send(true);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_routes_to_upstream() {
+        let upstream = Arc::new(EchoUpstream::default());
+        let stage = Arc::clone(&upstream);
+        let harness = Harness::start(|p| {
+            p.with_spawn_wait(Arc::new(NoopSpawnWait))
+                .with_upstream(stage)
+        })
+        .await;
+
+        let response = harness.get("app.test").await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("app.test req-1"));
+        assert_eq!(upstream.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_unknown_host_skips_upstream() {
+        let upstream = Arc::new(EchoUpstream::default());
+        let stage = Arc::clone(&upstream);
+        let harness = Harness::start(|p| p.with_upstream(stage)).await;
+
+        let response = harness.get("other.test").await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+        assert!(response.contains("UNKNOWN_HOST"));
+        assert_eq!(upstream.calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_custom_router() {
+        let harness = Harness::start(|p| {
+            p.with_router(Arc::new(FixedRouter("app.test")))
+                .with_spawn_wait(Arc::new(NoopSpawnWait))
+                .with_upstream(Arc::new(EchoUpstream::default()))
+        })
+        .await;
+
+        let response = harness.get("anything.test").await;
+        assert!(response.ends_with("app.test req-1"));
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_closure_router_on_headers() {
+        let harness = Harness::start(|p| {
+            let hosts = HostRouter::new(Arc::clone(&p.process_manager));
+            let router = move |ctx: &RequestContext, parts: &Parts| match parts.headers.get("x-tenant") {
+                Some(tenant) if tenant == "beta" => Some("primary.test".to_string()),
+                _ => hosts.route(ctx, parts),
+            };
+            p.with_router(Arc::new(router))
+                .with_spawn_wait(Arc::new(NoopSpawnWait))
+                .with_upstream(Arc::new(EchoUpstream::default()))
+        })
+        .await;
+
+        let response = harness.get_with_headers("app.test", "X-Tenant: beta\r\n").await;
+        assert!(response.ends_with("primary.test req-1"), "{}", response);
+        let response = harness.get("app.test").await;
+        assert!(response.ends_with("app.test req-1"), "{}", response);
+        let response = harness.get("other.test").await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_spawn_failure() {
+        let upstream = Arc::new(EchoUpstream::default());
+        let stage = Arc::clone(&upstream);
+        let harness = Harness::start(|p| {
+            p.with_spawn_wait(Arc::new(FailingSpawnWait))
+                .with_upstream(stage)
+        })
+        .await;
+
+        let response = harness.get("app.test").await;
+        assert!(response.starts_with("HTTP/1.1 503"));
+        assert!(response.contains("BACKEND_START_FAILED"));
+        assert_eq!(upstream.calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_spawn_failure_uses_fallback() {
+        let upstream = Arc::new(EchoUpstream::default());
+        let stage = Arc::clone(&upstream);
+        let harness = Harness::start(|p| {
+            p.with_spawn_wait(Arc::new(FailingFor("primary.test")))
+                .with_upstream(stage)
+        })
+        .await;
+
+        let response = harness.get("primary.test").await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("app.test req-1"));
+        assert_eq!(upstream.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_records_metrics() {
+        let metrics = Arc::new(RequestMetrics::new());
+        let stage = Arc::clone(&metrics);
+        let harness = Harness::start(|p| {
+            p.with_spawn_wait(Arc::new(FailingFor("primary.test")))
+                .with_upstream(Arc::new(EchoUpstream::default()))
+                .with_metrics(stage)
+        })
+        .await;
+
+        harness.get("app.test").await;
+        harness.get("other.test").await; // Unrouted requests are not counted
+
+        let taken = metrics.take();
+        assert_eq!(taken.len(), 1);
+        assert_eq!(taken[0].0, "app.test");
+        assert_eq!(taken[0].1.requests, 1);
+        assert_eq!(taken[0].1.status_2xx, 1);
+    }
+
+    /// Admission stage that admits everything, skipping the backend state checks
+    struct AdmitAll;
+
+    impl Admission for AdmitAll {
+        fn admit(&self, _ctx: &RequestContext, _hostname: &str, _parts: &Parts) -> AdmissionDecision {
+            AdmissionDecision::Admit
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_cold_start_throttle_keeps_custom_stages() {
+        let upstream = Arc::new(EchoUpstream::default());
+        let stage = Arc::clone(&upstream);
+        let throttle = Arc::new(ColdStartThrottle::new(&crate::config::ColdStartThrottleConfig {
+            enabled: true,
+            burst: 1,
+            window_secs: 60,
+            ban_secs: 60,
+        }));
+        let harness = Harness::start(|p| {
+            p.with_admission(Arc::new(AdmitAll))
+                .with_spawn_wait(Arc::new(NoopSpawnWait))
+                .with_upstream(stage)
+                .with_cold_start_throttle(throttle)
+        })
+        .await;
+
+        // The backend stays stopped, so every request would wake it
+        let response = harness.get("app.test").await;
+        assert!(response.ends_with("app.test req-1"), "{}", response);
+        let response = harness.get("app.test").await;
+        assert!(response.starts_with("HTTP/1.1 429"), "{}", response);
+        assert!(response.contains("COLD_START_THROTTLED"), "{}", response);
+        assert_eq!(upstream.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_shared_pool_keeps_custom_upstream() {
+        let upstream = Arc::new(EchoUpstream::default());
+        let stage = Arc::clone(&upstream);
+        let pool = Arc::new(ConnectionPool::new(PoolConfig::default()));
+        let shared = Arc::clone(&pool);
+        let harness = Harness::start_server(|server| {
+            let stages = server
+                .pipeline()
+                .clone()
+                .with_spawn_wait(Arc::new(NoopSpawnWait))
+                .with_upstream(stage);
+            let server = server.with_pipeline(stages).with_pool(shared);
+            assert!(Arc::ptr_eq(server.pool(), &pool));
+            server
+        })
+        .await;
+
+        let response = harness.get("app.test").await;
+        assert!(response.ends_with("app.test req-1"), "{}", response);
+        assert_eq!(upstream.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_grpc_timeout_format() {
+        assert_eq!(parse_grpc_timeout("250m"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_grpc_timeout("2H"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_grpc_timeout("10u"), Some(Duration::from_micros(10)));
+        assert_eq!(parse_grpc_timeout("123456789S"), None);
+        assert_eq!(parse_grpc_timeout("S"), None);
+        assert_eq!(parse_grpc_timeout("5x"), None);
+
+        assert_eq!(format_grpc_timeout(Duration::from_secs(30)), "30000m");
+        assert_eq!(format_grpc_timeout(Duration::from_secs(200_000)), "200000S");
+    }
+
+    #[test]
+    fn test_deadline_headers() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000);
+
+        let mut headers = HeaderMap::new();
+        set_deadline_headers(&mut headers, Duration::from_secs(30), now);
+        assert_eq!(headers[X_REQUEST_DEADLINE], "1030000");
+        assert!(headers.get(GRPC_TIMEOUT).is_none());
+
+        // gRPC requests get a timeout; a shorter client timeout wins
+        let mut headers = HeaderMap::new();
+        headers.insert(hyper::header::CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+        set_deadline_headers(&mut headers, Duration::from_secs(30), now);
+        assert_eq!(headers[GRPC_TIMEOUT], "30000m");
+
+        headers.insert(GRPC_TIMEOUT, HeaderValue::from_static("5S"));
+        set_deadline_headers(&mut headers, Duration::from_secs(30), now);
+        assert_eq!(headers[GRPC_TIMEOUT], "5000m");
+        assert_eq!(headers[X_REQUEST_DEADLINE], "1005000");
+    }
+
+    #[test]
+    fn test_parse_internal_redirect() {
+        let root = Some(PathBuf::from("/srv/protected"));
+        let mut request_headers = HeaderMap::new();
+        request_headers.insert("cookie", HeaderValue::from_static("session=1"));
+
+        let mut headers = HeaderMap::new();
+        assert!(parse_internal_redirect(&headers, root.clone(), HeaderMap::new()).is_none());
+
+        headers.insert(X_ACCEL_REDIRECT, HeaderValue::from_static("/files/a.zip?x=1"));
+        match parse_internal_redirect(&headers, root.clone(), HeaderMap::new()) {
+            Some(Ok(InternalRedirect::File { root, path })) => {
+                assert_eq!(root, PathBuf::from("/srv/protected"));
+                assert_eq!(path, PathBuf::from("/srv/protected/files/a.zip"));
+            }
+            other => panic!("unexpected: {:?}", other),
+        }
+
+        headers.insert(X_ACCEL_REDIRECT, HeaderValue::from_static("@Media.local/v1/blob?id=7"));
+        match parse_internal_redirect(&headers, None, request_headers) {
+            Some(Ok(InternalRedirect::Backend { hostname, path_and_query, headers })) => {
+                assert_eq!(hostname, "media.local");
+                assert_eq!(path_and_query, "/v1/blob?id=7");
+                assert_eq!(headers["cookie"], "session=1");
+            }
+            other => panic!("unexpected: {:?}", other),
+        }
+
+        // File targets need a root
+        headers.insert(X_ACCEL_REDIRECT, HeaderValue::from_static("/files/a.zip"));
+        assert!(matches!(parse_internal_redirect(&headers, None, HeaderMap::new()), Some(Err(_))));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(X_SENDFILE, HeaderValue::from_static("/srv/protected/b.bin"));
+        match parse_internal_redirect(&headers, root, HeaderMap::new()) {
+            Some(Ok(InternalRedirect::File { path, .. })) => {
+                assert_eq!(path, PathBuf::from("/srv/protected/b.bin"));
+            }
+            other => panic!("unexpected: {:?}", other),
+        }
+    }
 }
@@ -0,0 +1,327 @@
+//! Per-backend traffic quotas
+//!
+//! Requests and body bytes in both directions are counted per backend in
+//! fixed windows, starting with each clock hour or UTC day. Once a backend
+//! has used up its quota, `on_exceed` decides what happens until the window
+//! starts over: `block` rejects requests, `throttle` slows response bodies
+//! down, `notify` lets everything through. Exceeding the quota is logged once
+//! per window in every mode.
+
+use crate::config::{QuotaAction, QuotaConfig, QuotaWindow};
+use crate::proxy::ProxyBody;
+use dashmap::DashMap;
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use serde::Serialize;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Sleep;
+use tracing::warn;
+
+/// What to do with a request to a backend with a quota
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaDecision {
+    /// Serve the request normally
+    Allow,
+    /// Serve the request, sending the response body at this many bytes per second
+    Throttle(u64),
+    /// Reject the request; the window starts over after this long
+    Block(Duration),
+}
+
+impl QuotaDecision {
+    /// Response body rate, if the response should be slowed down
+    pub fn rate(self) -> Option<u64> {
+        match self {
+            QuotaDecision::Throttle(rate) => Some(rate),
+            _ => None,
+        }
+    }
+}
+
+/// Usage of a backend with a quota, as returned by `GET /quotas`
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct QuotaStatus {
+    pub hostname: String,
+    pub window: QuotaWindow,
+    pub requests: u64,
+    pub max_requests: Option<u64>,
+    pub bytes: u64,
+    pub max_bytes: Option<u64>,
+    pub exceeded: bool,
+    pub on_exceed: QuotaAction,
+    /// Seconds until the window starts over
+    pub resets_in_secs: u64,
+}
+
+/// Counts of one backend in the current window
+#[derive(Debug, Default)]
+struct Usage {
+    /// Unix time the window started
+    window_start: u64,
+    requests: u64,
+    bytes: u64,
+    /// Whether exceeding the quota was logged in this window
+    logged: bool,
+}
+
+impl Usage {
+    /// Start a new window if the current one is over
+    fn roll(&mut self, window: QuotaWindow, now: u64) {
+        let length = window.duration().as_secs();
+        let start = now - now % length;
+        if self.window_start != start {
+            *self = Usage {
+                window_start: start,
+                ..Default::default()
+            };
+        }
+    }
+
+    fn exceeded(&self, config: &QuotaConfig) -> bool {
+        config.max_requests.is_some_and(|max| self.requests >= max)
+            || config.max_bytes.is_some_and(|max| self.bytes >= max)
+    }
+
+    fn remaining(&self, window: QuotaWindow, now: u64) -> Duration {
+        Duration::from_secs((self.window_start + window.duration().as_secs()).saturating_sub(now))
+    }
+}
+
+/// Traffic counted against quotas, per backend
+#[derive(Debug, Default)]
+pub struct QuotaTracker {
+    usage: DashMap<String, Usage>,
+}
+
+impl QuotaTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decide on a request to `hostname` and count it unless it is blocked
+    pub fn check(&self, hostname: &str, config: &QuotaConfig) -> QuotaDecision {
+        self.check_at(hostname, config, unix_now())
+    }
+
+    fn check_at(&self, hostname: &str, config: &QuotaConfig, now: u64) -> QuotaDecision {
+        let mut usage = self.usage.entry(hostname.to_string()).or_default();
+        usage.roll(config.window, now);
+        if usage.exceeded(config) {
+            match config.on_exceed {
+                QuotaAction::Block => return QuotaDecision::Block(usage.remaining(config.window, now)),
+                QuotaAction::Throttle => {
+                    usage.requests += 1;
+                    return QuotaDecision::Throttle(config.throttle_bytes_per_sec);
+                }
+                QuotaAction::Notify => {}
+            }
+        }
+        usage.requests += 1;
+        log_exceeded(hostname, config, &mut usage);
+        QuotaDecision::Allow
+    }
+
+    /// Count body bytes sent to or received from `hostname`
+    ///
+    /// Bytes of backends without a checked request in the current window are
+    /// ignored, e.g. after a reset.
+    pub fn add_bytes(&self, hostname: &str, bytes: u64, config: &QuotaConfig) {
+        if let Some(mut usage) = self.usage.get_mut(hostname) {
+            usage.bytes += bytes;
+            log_exceeded(hostname, config, &mut usage);
+        }
+    }
+
+    /// Start `hostname`'s window over, returns true if it had any usage
+    pub fn reset(&self, hostname: &str) -> bool {
+        self.usage.remove(hostname).is_some()
+    }
+
+    /// Usage of a backend in its current window
+    pub fn status(&self, hostname: &str, config: &QuotaConfig) -> QuotaStatus {
+        let now = unix_now();
+        let mut usage = self.usage.entry(hostname.to_string()).or_default();
+        usage.roll(config.window, now);
+        QuotaStatus {
+            hostname: hostname.to_string(),
+            window: config.window,
+            requests: usage.requests,
+            max_requests: config.max_requests,
+            bytes: usage.bytes,
+            max_bytes: config.max_bytes,
+            exceeded: usage.exceeded(config),
+            on_exceed: config.on_exceed,
+            resets_in_secs: usage.remaining(config.window, now).as_secs(),
+        }
+    }
+
+    /// Count a body's bytes against `hostname`'s quota, sending it at `rate` bytes per second if set
+    pub fn meter(
+        self: &Arc<Self>,
+        hostname: &str,
+        config: QuotaConfig,
+        body: ProxyBody,
+        rate: Option<u64>,
+    ) -> ProxyBody {
+        MeteredBody {
+            inner: body,
+            tracker: Arc::clone(self),
+            hostname: hostname.to_string(),
+            config,
+            rate,
+            delay: None,
+        }
+        .boxed()
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+fn log_exceeded(hostname: &str, config: &QuotaConfig, usage: &mut Usage) {
+    if usage.logged || !usage.exceeded(config) {
+        return;
+    }
+    usage.logged = true;
+    warn!(
+        hostname,
+        requests = usage.requests,
+        bytes = usage.bytes,
+        window = ?config.window,
+        on_exceed = ?config.on_exceed,
+        "Backend exceeded its traffic quota"
+    );
+}
+
+/// Body counting its data frames against a quota, optionally paced
+struct MeteredBody {
+    inner: ProxyBody,
+    tracker: Arc<QuotaTracker>,
+    hostname: String,
+    config: QuotaConfig,
+    rate: Option<u64>,
+    /// Wait before the next frame, to keep to `rate`
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl Body for MeteredBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, hyper::Error>>> {
+        if let Some(delay) = self.delay.as_mut() {
+            if delay.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.delay = None;
+        }
+
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(ref frame))) = poll {
+            let len = frame.data_ref().map_or(0, |data| data.len() as u64);
+            if len > 0 {
+                self.tracker.add_bytes(&self.hostname, len, &self.config);
+                if let Some(rate) = self.rate {
+                    let wait = Duration::from_secs_f64(len as f64 / rate as f64);
+                    self.delay = Some(Box::pin(tokio::time::sleep(wait)));
+                }
+            }
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::Full;
+
+    fn quota(on_exceed: QuotaAction) -> QuotaConfig {
+        QuotaConfig {
+            window: QuotaWindow::Hour,
+            max_requests: Some(2),
+            max_bytes: Some(100),
+            on_exceed,
+            throttle_bytes_per_sec: 1000,
+        }
+    }
+
+    #[test]
+    fn test_quota_actions() {
+        let tracker = QuotaTracker::new();
+        let block = quota(QuotaAction::Block);
+        let now = 7200 + 600;
+        assert_eq!(tracker.check_at("app", &block, now), QuotaDecision::Allow);
+        assert_eq!(tracker.check_at("app", &block, now), QuotaDecision::Allow);
+        assert_eq!(
+            tracker.check_at("app", &block, now),
+            QuotaDecision::Block(Duration::from_secs(3000))
+        );
+        // The next hour starts over
+        assert_eq!(tracker.check_at("app", &block, 10800), QuotaDecision::Allow);
+
+        let throttle = quota(QuotaAction::Throttle);
+        assert_eq!(tracker.check_at("throttled", &throttle, now), QuotaDecision::Allow);
+        tracker.add_bytes("throttled", 100, &throttle);
+        assert_eq!(tracker.check_at("throttled", &throttle, now), QuotaDecision::Throttle(1000));
+
+        let notify = quota(QuotaAction::Notify);
+        for _ in 0..3 {
+            assert_eq!(tracker.check_at("notified", &notify, now), QuotaDecision::Allow);
+        }
+
+        assert!(tracker.reset("app"));
+        assert!(!tracker.reset("app"));
+        // Bytes without a request in the window are not counted
+        tracker.add_bytes("app", 10, &block);
+        assert!(tracker.usage.get("app").is_none());
+    }
+
+    #[test]
+    fn test_quota_status() {
+        let tracker = QuotaTracker::new();
+        let config = QuotaConfig {
+            window: QuotaWindow::Day,
+            ..quota(QuotaAction::Notify)
+        };
+        tracker.check("app", &config);
+        tracker.add_bytes("app", 150, &config);
+
+        let status = tracker.status("app", &config);
+        assert_eq!(status.requests, 1);
+        assert_eq!(status.bytes, 150);
+        assert!(status.exceeded);
+        assert!(status.resets_in_secs <= 86400);
+    }
+
+    #[tokio::test]
+    async fn test_metered_body() {
+        let tracker = Arc::new(QuotaTracker::new());
+        let config = quota(QuotaAction::Throttle);
+        tracker.check("app", &config);
+
+        let body = Full::new(Bytes::from(vec![0u8; 50])).map_err(|never| match never {}).boxed();
+        let started = tokio::time::Instant::now();
+        let body = tracker.meter("app", config.clone(), body, Some(1000));
+        assert_eq!(body.collect().await.unwrap().to_bytes().len(), 50);
+        assert_eq!(tracker.status("app", &config).bytes, 50);
+        // 50 bytes at 1000 bytes per second
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,338 @@
+//! Registry of running tasks and open connections, for debugging leaks
+//!
+//! Long-running tasks are spawned through the registry under a name, and
+//! client and pooled backend connections register themselves while open.
+//! Entries are removed when the task ends or the connection closes, so an
+//! entry that keeps growing older points at a leak.
+
+use dashmap::DashMap;
+use serde::Serialize;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::task::JoinHandle;
+
+#[derive(Debug)]
+struct TaskEntry {
+    name: String,
+    started: Instant,
+}
+
+#[derive(Debug)]
+struct ClientEntry {
+    client_addr: SocketAddr,
+    local_addr: SocketAddr,
+    tls: bool,
+    opened: Instant,
+    requests: AtomicU64,
+}
+
+#[derive(Debug)]
+struct UpstreamEntry {
+    backend: String,
+    established: Instant,
+    /// Milliseconds after `established` of the last read or write
+    last_io_ms: AtomicU64,
+}
+
+/// Tasks and connections currently registered
+#[derive(Debug, Default)]
+pub struct DebugRegistry {
+    next_id: AtomicU64,
+    tasks: DashMap<u64, TaskEntry>,
+    clients: DashMap<u64, Arc<ClientEntry>>,
+    upstreams: DashMap<u64, Arc<UpstreamEntry>>,
+}
+
+impl DebugRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Spawn a task that is listed under `name` until it ends or is aborted
+    pub fn spawn<F>(self: &Arc<Self>, name: impl Into<String>, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let id = self.id();
+        self.tasks.insert(
+            id,
+            TaskEntry {
+                name: name.into(),
+                started: Instant::now(),
+            },
+        );
+        let guard = Registration {
+            registry: Arc::clone(self),
+            id,
+            kind: Kind::Task,
+        };
+        tokio::spawn(async move {
+            let _guard = guard;
+            future.await
+        })
+    }
+
+    /// Register an accepted client connection until the returned handle is dropped
+    pub fn client(self: &Arc<Self>, client_addr: SocketAddr, local_addr: SocketAddr, tls: bool) -> ClientConnection {
+        let id = self.id();
+        let entry = Arc::new(ClientEntry {
+            client_addr,
+            local_addr,
+            tls,
+            opened: Instant::now(),
+            requests: AtomicU64::new(0),
+        });
+        self.clients.insert(id, Arc::clone(&entry));
+        ClientConnection {
+            entry,
+            _registration: Registration {
+                registry: Arc::clone(self),
+                id,
+                kind: Kind::Client,
+            },
+        }
+    }
+
+    /// Register a backend connection until the returned handle is dropped
+    pub fn upstream(self: &Arc<Self>, backend: impl Into<String>) -> UpstreamConnection {
+        let id = self.id();
+        let entry = Arc::new(UpstreamEntry {
+            backend: backend.into(),
+            established: Instant::now(),
+            last_io_ms: AtomicU64::new(0),
+        });
+        self.upstreams.insert(id, Arc::clone(&entry));
+        UpstreamConnection {
+            entry,
+            _registration: Registration {
+                registry: Arc::clone(self),
+                id,
+                kind: Kind::Upstream,
+            },
+        }
+    }
+
+    /// Registered tasks, oldest first, with the runtime's task counts
+    pub fn tasks(&self) -> TaskSnapshot {
+        let mut tasks: Vec<TaskInfo> = self
+            .tasks
+            .iter()
+            .map(|entry| TaskInfo {
+                id: *entry.key(),
+                name: entry.name.clone(),
+                age_ms: entry.started.elapsed().as_millis() as u64,
+            })
+            .collect();
+        tasks.sort_by_key(|task| task.id);
+
+        let runtime = tokio::runtime::Handle::try_current().ok().map(|handle| {
+            let metrics = handle.metrics();
+            RuntimeInfo {
+                workers: metrics.num_workers(),
+                alive_tasks: metrics.num_alive_tasks(),
+                global_queue_depth: metrics.global_queue_depth(),
+            }
+        });
+
+        TaskSnapshot { runtime, tasks }
+    }
+
+    /// Open client and backend connections, oldest first
+    pub fn connections(&self) -> ConnectionSnapshot {
+        let mut clients: Vec<ClientInfo> = self
+            .clients
+            .iter()
+            .map(|entry| ClientInfo {
+                id: *entry.key(),
+                client_addr: entry.client_addr,
+                local_addr: entry.local_addr,
+                tls: entry.tls,
+                age_ms: entry.opened.elapsed().as_millis() as u64,
+                requests: entry.requests.load(Ordering::Relaxed),
+            })
+            .collect();
+        clients.sort_by_key(|client| client.id);
+
+        let mut upstreams: Vec<UpstreamInfo> = self
+            .upstreams
+            .iter()
+            .map(|entry| {
+                let age_ms = entry.established.elapsed().as_millis() as u64;
+                UpstreamInfo {
+                    id: *entry.key(),
+                    backend: entry.backend.clone(),
+                    age_ms,
+                    idle_ms: age_ms.saturating_sub(entry.last_io_ms.load(Ordering::Relaxed)),
+                }
+            })
+            .collect();
+        upstreams.sort_by_key(|upstream| upstream.id);
+
+        ConnectionSnapshot { clients, upstreams }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Task,
+    Client,
+    Upstream,
+}
+
+/// Removes a registry entry when dropped
+#[derive(Debug)]
+struct Registration {
+    registry: Arc<DebugRegistry>,
+    id: u64,
+    kind: Kind,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        match self.kind {
+            Kind::Task => {
+                self.registry.tasks.remove(&self.id);
+            }
+            Kind::Client => {
+                self.registry.clients.remove(&self.id);
+            }
+            Kind::Upstream => {
+                self.registry.upstreams.remove(&self.id);
+            }
+        }
+    }
+}
+
+/// A registered client connection
+#[derive(Debug)]
+pub struct ClientConnection {
+    entry: Arc<ClientEntry>,
+    _registration: Registration,
+}
+
+impl ClientConnection {
+    /// Count a request received on the connection
+    pub fn record_request(&self) {
+        self.entry.requests.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// A registered backend connection
+#[derive(Debug)]
+pub struct UpstreamConnection {
+    entry: Arc<UpstreamEntry>,
+    _registration: Registration,
+}
+
+impl UpstreamConnection {
+    /// Note that data was read or written
+    pub fn record_io(&self) {
+        let ms = self.entry.established.elapsed().as_millis() as u64;
+        self.entry.last_io_ms.store(ms, Ordering::Relaxed);
+    }
+}
+
+/// Runtime-wide task counts
+#[derive(Debug, Clone, Serialize)]
+pub struct RuntimeInfo {
+    pub workers: usize,
+    /// Every task alive in the runtime, registered or not
+    pub alive_tasks: usize,
+    pub global_queue_depth: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskInfo {
+    pub id: u64,
+    pub name: String,
+    pub age_ms: u64,
+}
+
+/// Registered tasks as returned by `GET /debug/tasks`
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskSnapshot {
+    pub runtime: Option<RuntimeInfo>,
+    pub tasks: Vec<TaskInfo>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientInfo {
+    pub id: u64,
+    pub client_addr: SocketAddr,
+    pub local_addr: SocketAddr,
+    pub tls: bool,
+    pub age_ms: u64,
+    pub requests: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UpstreamInfo {
+    pub id: u64,
+    /// Backend address (host:port)
+    pub backend: String,
+    pub age_ms: u64,
+    /// Time since the last read or write
+    pub idle_ms: u64,
+}
+
+/// Open connections as returned by `GET /debug/connections`
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionSnapshot {
+    pub clients: Vec<ClientInfo>,
+    pub upstreams: Vec<UpstreamInfo>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_tasks_listed_until_they_end() {
+        let registry = Arc::new(DebugRegistry::new());
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let waiting = registry.spawn("waiting", async move {
+            let _ = rx.await;
+        });
+        let aborted = registry.spawn("aborted", std::future::pending::<()>());
+
+        let snapshot = registry.tasks();
+        let names: Vec<&str> = snapshot.tasks.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["waiting", "aborted"]);
+        assert!(snapshot.runtime.unwrap().alive_tasks >= 2);
+
+        tx.send(()).unwrap();
+        waiting.await.unwrap();
+        aborted.abort();
+        let _ = aborted.await;
+        assert!(registry.tasks().tasks.is_empty());
+    }
+
+    #[test]
+    fn test_connections_listed_while_open() {
+        let registry = Arc::new(DebugRegistry::new());
+        let client = registry.client("10.0.0.1:5000".parse().unwrap(), "127.0.0.1:80".parse().unwrap(), false);
+        client.record_request();
+        client.record_request();
+        let upstream = registry.upstream("127.0.0.1:3000");
+        upstream.record_io();
+
+        let snapshot = registry.connections();
+        assert_eq!(snapshot.clients.len(), 1);
+        assert_eq!(snapshot.clients[0].requests, 2);
+        assert_eq!(snapshot.upstreams[0].backend, "127.0.0.1:3000");
+
+        drop(client);
+        drop(upstream);
+        let snapshot = registry.connections();
+        assert!(snapshot.clients.is_empty());
+        assert!(snapshot.upstreams.is_empty());
+    }
+}
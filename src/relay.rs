@@ -0,0 +1,74 @@
+//! Loopback relays to backends running elsewhere
+//!
+//! The proxy reaches every backend on `127.0.0.1:{port}`. Backends with their
+//! own address, like microVMs and cloud instances, get a relay listening there
+//! that forwards each connection to the backend, much like Docker publishing
+//! a container's port.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tracing::{debug, warn};
+
+/// Forward connections accepted on `listener` to the current `target`
+///
+/// Connections arriving while the target is unknown (e.g. a VM still waiting
+/// for its address) are closed, so health checks keep failing until then.
+pub async fn relay(listener: TcpListener, hostname: String, target: watch::Receiver<Option<SocketAddr>>) {
+    loop {
+        let (mut client, _) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!(hostname, error = %e, "Failed to accept connection to relay");
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let Some(target) = *target.borrow() else {
+            continue;
+        };
+        let hostname = hostname.clone();
+        tokio::spawn(async move {
+            match TcpStream::connect(target).await {
+                Ok(mut backend) => {
+                    let _ = tokio::io::copy_bidirectional(&mut client, &mut backend).await;
+                }
+                Err(e) => debug!(hostname, %target, error = %e, "Failed to connect to relayed backend"),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_relay() {
+        let backend = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let backend_addr = backend.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = backend.accept().await {
+                let _ = stream.write_all(b"hello").await;
+            }
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (target_tx, target_rx) = watch::channel(None);
+        tokio::spawn(relay(listener, "vm.local".to_string(), target_rx));
+
+        // Closed while the target is unknown
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let mut buf = Vec::new();
+        assert_eq!(stream.read_to_end(&mut buf).await.unwrap_or(0), 0);
+
+        target_tx.send_replace(Some(backend_addr));
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+}
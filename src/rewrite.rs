@@ -0,0 +1,272 @@
+//! Response body rewriting
+//!
+//! Backends that build absolute URLs from their own address (e.g. links to
+//! `http://localhost:3000`) can have them fixed up by the proxy with
+//! `response_rewrites`. The rewrite is applied while the body streams through:
+//! each chunk is passed on as soon as it has been rewritten, except for its
+//! last few bytes that could be the start of a match continuing in the next
+//! chunk, so matches split across chunk boundaries are replaced too.
+//!
+//! Only bodies with one of the `rewrite_content_types` are rewritten, and only
+//! uncompressed ones; requests to these backends are forwarded without
+//! `Accept-Encoding` so they usually are. The `Location` header is rewritten
+//! as well.
+
+use crate::config::ResponseRewrite;
+use crate::proxy::{ProxyBody, ProxyResponse};
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes, Frame};
+use hyper::header::{HeaderMap, HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, LOCATION};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// Rewrites responses from one backend
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rewriter {
+    rules: Arc<[(Vec<u8>, Vec<u8>)]>,
+    content_types: Vec<String>,
+}
+
+impl Rewriter {
+    /// Create a rewriter, or `None` if there is nothing to rewrite
+    pub fn new(rewrites: &[ResponseRewrite], content_types: &[String]) -> Option<Self> {
+        if rewrites.is_empty() {
+            return None;
+        }
+        Some(Self {
+            rules: rewrites
+                .iter()
+                .map(|r| (r.find.clone().into_bytes(), r.replace.clone().into_bytes()))
+                .collect(),
+            content_types: content_types.iter().map(|t| t.to_ascii_lowercase()).collect(),
+        })
+    }
+
+    /// Rewrite the `Location` header and, for matching content types, the body
+    pub fn rewrite_response(&self, response: ProxyResponse) -> ProxyResponse {
+        let (mut parts, body) = response.into_parts();
+
+        if let Some(location) = parts.headers.get(LOCATION) {
+            let mut stream = self.stream();
+            let mut rewritten = stream.push(location.as_bytes()).to_vec();
+            rewritten.extend_from_slice(&stream.finish());
+            if let Ok(value) = HeaderValue::from_bytes(&rewritten) {
+                parts.headers.insert(LOCATION, value);
+            }
+        }
+
+        if !self.rewrites_body(&parts.headers) {
+            return ProxyResponse::from_parts(parts, body);
+        }
+        parts.headers.remove(CONTENT_LENGTH);
+        let body = RewriteBody {
+            inner: body,
+            stream: self.stream(),
+            pending: None,
+            finished: false,
+        };
+        ProxyResponse::from_parts(parts, body.boxed())
+    }
+
+    fn rewrites_body(&self, headers: &HeaderMap) -> bool {
+        let identity = headers
+            .get(CONTENT_ENCODING)
+            .is_none_or(|v| v.as_bytes().eq_ignore_ascii_case(b"identity"));
+        let media_type = headers
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(';').next())
+            .map(|v| v.trim().to_ascii_lowercase());
+        identity && media_type.is_some_and(|t| self.content_types.contains(&t))
+    }
+
+    fn stream(&self) -> StreamRewriter {
+        StreamRewriter {
+            rules: Arc::clone(&self.rules),
+            keep: self.rules.iter().map(|(find, _)| find.len()).max().unwrap_or(1) - 1,
+            carry: Vec::new(),
+        }
+    }
+}
+
+/// Applies the rules to a byte stream delivered in chunks
+struct StreamRewriter {
+    rules: Arc<[(Vec<u8>, Vec<u8>)]>,
+    /// Bytes at the end of a chunk that may start a match (longest `find` - 1)
+    keep: usize,
+    carry: Vec<u8>,
+}
+
+impl StreamRewriter {
+    /// Rewrite a chunk, holding back a tail that may continue in the next one
+    fn push(&mut self, chunk: &[u8]) -> Bytes {
+        let mut input = std::mem::take(&mut self.carry);
+        input.extend_from_slice(chunk);
+        let limit = input.len().saturating_sub(self.keep);
+        let (out, consumed) = self.replace(&input, limit);
+        self.carry = input[consumed..].to_vec();
+        out.into()
+    }
+
+    /// Rewrite whatever is still held back at the end of the stream
+    fn finish(&mut self) -> Bytes {
+        let input = std::mem::take(&mut self.carry);
+        self.replace(&input, input.len()).0.into()
+    }
+
+    /// Replace matches starting before `limit`, returning the output and the
+    /// number of input bytes consumed
+    fn replace(&self, input: &[u8], limit: usize) -> (Vec<u8>, usize) {
+        let mut out = Vec::with_capacity(input.len());
+        let mut i = 0;
+        while i < limit {
+            let found = self.rules.iter().find(|(find, _)| input[i..].starts_with(find));
+            match found {
+                Some((find, replace)) => {
+                    out.extend_from_slice(replace);
+                    i += find.len();
+                }
+                None => {
+                    out.push(input[i]);
+                    i += 1;
+                }
+            }
+        }
+        (out, i)
+    }
+}
+
+/// Response body passed through a `StreamRewriter`
+struct RewriteBody {
+    inner: ProxyBody,
+    stream: StreamRewriter,
+    /// Trailers held until the rewritten tail has been sent
+    pending: Option<Frame<Bytes>>,
+    finished: bool,
+}
+
+impl Body for RewriteBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, hyper::Error>>> {
+        if let Some(frame) = self.pending.take() {
+            return Poll::Ready(Some(Ok(frame)));
+        }
+        loop {
+            if self.finished {
+                return Poll::Ready(None);
+            }
+            match Pin::new(&mut self.inner).poll_frame(cx) {
+                Poll::Ready(Some(Ok(frame))) => {
+                    let frame = match frame.into_data() {
+                        Ok(data) => {
+                            let out = self.stream.push(&data);
+                            if out.is_empty() {
+                                continue;
+                            }
+                            return Poll::Ready(Some(Ok(Frame::data(out))));
+                        }
+                        Err(frame) => frame,
+                    };
+                    // Trailers end the body: flush the tail before them
+                    let tail = self.stream.finish();
+                    if tail.is_empty() {
+                        return Poll::Ready(Some(Ok(frame)));
+                    }
+                    self.pending = Some(frame);
+                    return Poll::Ready(Some(Ok(Frame::data(tail))));
+                }
+                Poll::Ready(None) => {
+                    self.finished = true;
+                    let tail = self.stream.finish();
+                    if !tail.is_empty() {
+                        return Poll::Ready(Some(Ok(Frame::data(tail))));
+                    }
+                }
+                poll => return poll,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::{Full, StreamBody};
+    use hyper::Response;
+
+    fn rewriter() -> Rewriter {
+        let rewrites = [
+            ResponseRewrite {
+                find: "http://localhost:3000".to_string(),
+                replace: "https://app.example.com".to_string(),
+            },
+            ResponseRewrite {
+                find: "localhost".to_string(),
+                replace: "app.example.com".to_string(),
+            },
+        ];
+        Rewriter::new(&rewrites, &["text/html".to_string()]).unwrap()
+    }
+
+    #[test]
+    fn test_matches_across_chunk_boundaries() {
+        let input = "<a href=\"http://localhost:3000/a\">localhost</a> http://localhost:3000";
+        let expected = "<a href=\"https://app.example.com/a\">app.example.com</a> https://app.example.com";
+
+        // Every way of splitting the input into two chunks, and byte by byte
+        for split in 0..=input.len() {
+            let mut stream = rewriter().stream();
+            let mut out = stream.push(&input.as_bytes()[..split]).to_vec();
+            out.extend_from_slice(&stream.push(&input.as_bytes()[split..]));
+            out.extend_from_slice(&stream.finish());
+            assert_eq!(String::from_utf8(out).unwrap(), expected, "split at {}", split);
+        }
+
+        let mut stream = rewriter().stream();
+        let mut out = Vec::new();
+        for byte in input.as_bytes() {
+            out.extend_from_slice(&stream.push(&[*byte]));
+        }
+        out.extend_from_slice(&stream.finish());
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_response() {
+        let chunks: Vec<Result<Frame<Bytes>, hyper::Error>> = vec![
+            Ok(Frame::data(Bytes::from("{\"url\":\"http://local"))),
+            Ok(Frame::data(Bytes::from("host:3000/x\"}"))),
+        ];
+        let body = StreamBody::new(futures::stream::iter(chunks)).boxed();
+        let response = Response::builder()
+            .header(CONTENT_TYPE, "text/html; charset=utf-8")
+            .header(CONTENT_LENGTH, "33")
+            .header(LOCATION, "http://localhost:3000/next")
+            .body(body)
+            .unwrap();
+
+        let response = rewriter().rewrite_response(response);
+        assert_eq!(response.headers()[LOCATION], "https://app.example.com/next");
+        assert!(response.headers().get(CONTENT_LENGTH).is_none());
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "{\"url\":\"https://app.example.com/x\"}");
+
+        // Other content types and compressed bodies pass through untouched
+        let full = |body: &'static str| Full::new(Bytes::from(body)).map_err(|never| match never {}).boxed();
+        for (content_type, encoding) in [("image/png", None), ("text/html", Some("gzip"))] {
+            let mut builder = Response::builder().header(CONTENT_TYPE, content_type);
+            if let Some(encoding) = encoding {
+                builder = builder.header(CONTENT_ENCODING, encoding);
+            }
+            let response = rewriter().rewrite_response(builder.body(full("localhost")).unwrap());
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(body, "localhost", "{}", content_type);
+        }
+    }
+}
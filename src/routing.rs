@@ -0,0 +1,390 @@
+//! Routing rules for backends sharing a hostname
+//!
+//! A backend's `routes` send some of its hostname's requests to other
+//! backends, e.g. `/v2` to a new API version, gRPC calls to a gRPC service,
+//! browser page loads to a server-side rendering app, one tenant's requests
+//! to its own instance or beta testers (by cookie) to a canary, while the
+//! backend itself serves the rest. Of the rules whose conditions all match, the one
+//! with the highest priority wins, and the first of those on a tie.
+//!
+//! Rules on `methods` split reads from writes, e.g. GET and HEAD to a read
+//! replica. Replicas lag behind, so with `read_your_writes_secs` a client's
+//! writes get a [`WROTE_COOKIE`] in the response, and while it lasts the
+//! client's reads skip the rule and are served with the writes.
+
+use crate::config::{is_safe_method, RouteRule};
+use crate::experiments::Assignment;
+use hyper::header::{HeaderMap, HeaderValue, ACCEPT, CONTENT_TYPE, COOKIE};
+use std::collections::HashMap;
+use hyper::http::request::Parts;
+use hyper::Method;
+use hyper::http::uri::{PathAndQuery, Uri};
+
+/// Header telling the backend which prefix was stripped from the path
+pub const X_FORWARDED_PREFIX: &str = "x-forwarded-prefix";
+
+/// Cookie marking a client that recently sent a write
+pub const WROTE_COOKIE: &str = "spawngate_wrote";
+
+/// Backend chosen for a request, and how to adjust the request for it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub backend: String,
+    /// Path prefix to remove before forwarding
+    pub strip_prefix: Option<String>,
+    /// For writes: seconds to set [`WROTE_COOKIE`] for in the response
+    pub read_your_writes: Option<u64>,
+    /// Experiment variant the request was assigned to
+    pub experiment: Option<Assignment>,
+}
+
+impl Route {
+    /// Route to `backend` without changing the request
+    pub fn to(backend: impl Into<String>) -> Self {
+        Self {
+            backend: backend.into(),
+            strip_prefix: None,
+            read_your_writes: None,
+            experiment: None,
+        }
+    }
+}
+
+impl From<&RouteRule> for Route {
+    fn from(rule: &RouteRule) -> Self {
+        Self {
+            backend: rule.backend.clone(),
+            strip_prefix: rule.path_prefix.clone().filter(|_| rule.strip_prefix),
+            read_your_writes: None,
+            experiment: None,
+        }
+    }
+}
+
+/// The highest-priority rule matching a request, if any
+pub fn select<'a>(rules: &'a [RouteRule], parts: &Parts) -> Option<&'a RouteRule> {
+    rules
+        .iter()
+        .filter(|rule| matches(rule, parts))
+        .fold(None, |best: Option<&RouteRule>, rule| match best {
+            Some(best) if best.priority >= rule.priority => Some(best),
+            _ => Some(rule),
+        })
+}
+
+/// How long a write with `method` should keep the client's reads off `rules`
+/// with `read_your_writes_secs`, if it's a write and there are any
+pub fn read_your_writes(rules: &[RouteRule], method: &Method) -> Option<u64> {
+    if is_safe_method(method.as_str()) {
+        return None;
+    }
+    rules.iter().map(|rule| rule.read_your_writes_secs).max().filter(|secs| *secs > 0)
+}
+
+/// `Set-Cookie` value marking a client as having written, for `secs` seconds
+pub fn wrote_cookie(secs: u64) -> HeaderValue {
+    HeaderValue::from_str(&format!("{}=1; Max-Age={}; Path=/; HttpOnly; SameSite=Lax", WROTE_COOKIE, secs))
+        .expect("valid cookie")
+}
+
+/// Remove `prefix` from the request's path, keeping the query
+///
+/// The prefix is passed on in `X-Forwarded-Prefix` so the backend can build
+/// links that work through the proxy.
+pub fn strip_prefix(parts: &mut Parts, prefix: &str) {
+    let path = parts.uri.path();
+    let Some(rest) = path.strip_prefix(prefix.trim_end_matches('/')) else {
+        return;
+    };
+    let mut stripped = if rest.starts_with('/') { rest.to_string() } else { format!("/{}", rest) };
+    if let Some(query) = parts.uri.query() {
+        stripped = format!("{}?{}", stripped, query);
+    }
+    let mut uri = parts.uri.clone().into_parts();
+    let Ok(path_and_query) = stripped.parse::<PathAndQuery>() else {
+        return;
+    };
+    uri.path_and_query = Some(path_and_query);
+    if let Ok(uri) = Uri::from_parts(uri) {
+        parts.uri = uri;
+    }
+    if let Ok(value) = HeaderValue::from_str(prefix.trim_end_matches('/')) {
+        parts.headers.insert(X_FORWARDED_PREFIX, value);
+    }
+}
+
+fn matches(rule: &RouteRule, parts: &Parts) -> bool {
+    rule.matches_path(parts.uri.path())
+        && (rule.content_type.is_empty() || content_type_matches(&rule.content_type, &parts.headers))
+        && (rule.accept.is_empty() || accept_matches(&rule.accept, &parts.headers))
+        && headers_match(&rule.headers, &parts.headers)
+        && cookies_match(&rule.cookies, &parts.headers)
+        && (rule.methods.is_empty() || rule.methods.iter().any(|m| m == parts.method.as_str()))
+        && !(rule.read_your_writes_secs > 0 && cookies(&parts.headers).any(|(name, _)| name == WROTE_COOKIE))
+}
+
+/// Whether `value` satisfies an expected header or cookie value
+fn value_matches(expected: &str, value: &str) -> bool {
+    expected == "*" || expected == value
+}
+
+/// Whether the request has every header in `expected`, one of its values matching
+pub(crate) fn headers_match(expected: &HashMap<String, String>, headers: &HeaderMap) -> bool {
+    expected.iter().all(|(name, expected)| {
+        headers
+            .get_all(name.as_str())
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .any(|value| value_matches(expected, value.trim()))
+    })
+}
+
+/// Whether the request's `Cookie` headers have every cookie in `expected`
+fn cookies_match(expected: &HashMap<String, String>, headers: &HeaderMap) -> bool {
+    if expected.is_empty() {
+        return true;
+    }
+    let cookies: Vec<(&str, &str)> = cookies(headers).collect();
+    expected.iter().all(|(name, expected)| {
+        cookies
+            .iter()
+            .any(|(cookie, value)| cookie == name && value_matches(expected, value))
+    })
+}
+
+/// Name and value of each cookie in the request's `Cookie` headers
+pub(crate) fn cookies(headers: &HeaderMap) -> impl Iterator<Item = (&str, &str)> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .map(|(name, value)| (name.trim(), value.trim().trim_matches('"')))
+}
+
+/// Whether the request's `Content-Type` is one of `patterns`
+fn content_type_matches(patterns: &[String], headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+    let media_type = essence(content_type);
+    patterns.iter().any(|pattern| media_type_matches(pattern, &media_type))
+}
+
+/// Whether the request's `Accept` header names one of `patterns` with a non-zero quality
+///
+/// Wildcard ranges in the header (`*/*`, `text/*`) are ignored: nearly every
+/// client sends `*/*`, and a rule for `text/html` shouldn't catch API calls.
+fn accept_matches(patterns: &[String], headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter(|range| !rejected(range))
+        .map(essence)
+        .filter(|media_type| !media_type.ends_with("/*"))
+        .any(|media_type| patterns.iter().any(|pattern| media_type_matches(pattern, &media_type)))
+}
+
+/// Media type without parameters, lowercased
+fn essence(value: &str) -> String {
+    value.split(';').next().unwrap_or_default().trim().to_ascii_lowercase()
+}
+
+/// Whether an `Accept` media range has `q=0`
+fn rejected(range: &str) -> bool {
+    range.split(';').skip(1).any(|param| {
+        param
+            .trim()
+            .strip_prefix("q=")
+            .and_then(|q| q.trim().parse::<f32>().ok())
+            .is_some_and(|q| q == 0.0)
+    })
+}
+
+/// Whether `media_type` matches `pattern`
+///
+/// `type/*` matches any subtype, and a pattern also matches its structured
+/// syntax variants (`application/grpc` matches `application/grpc+proto`).
+fn media_type_matches(pattern: &str, media_type: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    if let Some(top) = pattern.strip_suffix("/*") {
+        return media_type.split('/').next() == Some(top);
+    }
+    media_type == pattern
+        || media_type
+            .strip_prefix(pattern.as_str())
+            .is_some_and(|rest| rest.starts_with('+'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::Request;
+
+    fn rule(backend: &str, content_type: &[&str], accept: &[&str]) -> RouteRule {
+        RouteRule {
+            backend: backend.to_string(),
+            path_prefix: None,
+            strip_prefix: false,
+            priority: 0,
+            content_type: content_type.iter().map(|s| s.to_string()).collect(),
+            accept: accept.iter().map(|s| s.to_string()).collect(),
+            headers: HashMap::new(),
+            cookies: HashMap::new(),
+            methods: Vec::new(),
+            read_your_writes_secs: 0,
+        }
+    }
+
+    fn path_rule(backend: &str, prefix: &str, priority: i32) -> RouteRule {
+        RouteRule {
+            path_prefix: Some(prefix.to_string()),
+            strip_prefix: true,
+            priority,
+            ..rule(backend, &[], &[])
+        }
+    }
+
+    fn parts(headers: &[(&str, &str)]) -> Parts {
+        parts_for("/", headers)
+    }
+
+    fn parts_for(uri: &str, headers: &[(&str, &str)]) -> Parts {
+        let mut builder = Request::get(uri);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(()).unwrap().into_parts().0
+    }
+
+    #[test]
+    fn test_select_by_media_type() {
+        let rules = [
+            rule("grpc.internal", &["application/grpc"], &[]),
+            rule("ssr.internal", &[], &["text/html"]),
+            rule("images.internal", &[], &["image/*"]),
+        ];
+        let backend = |headers: &[(&str, &str)]| select(&rules, &parts(headers)).map(|r| r.backend.as_str());
+
+        assert_eq!(backend(&[("content-type", "application/grpc")]), Some("grpc.internal"));
+        assert_eq!(backend(&[("content-type", "Application/GRPC+proto")]), Some("grpc.internal"));
+        assert_eq!(backend(&[("content-type", "application/grpc-web")]), None);
+        assert_eq!(
+            backend(&[("accept", "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8")]),
+            Some("ssr.internal")
+        );
+        assert_eq!(backend(&[("accept", "image/avif,image/webp")]), Some("images.internal"));
+
+        // API clients
+        assert_eq!(backend(&[("accept", "*/*")]), None);
+        assert_eq!(backend(&[("accept", "application/json, text/*")]), None);
+        assert_eq!(backend(&[("accept", "application/json, text/html;q=0")]), None);
+        assert_eq!(backend(&[("content-type", "application/json")]), None);
+        assert_eq!(backend(&[]), None);
+    }
+
+    #[test]
+    fn test_conditions_combine() {
+        let rules = [rule("upload.internal", &["multipart/form-data"], &["text/html"])];
+        let upload = parts(&[("content-type", "multipart/form-data; boundary=x"), ("accept", "text/html")]);
+        assert!(select(&rules, &upload).is_some());
+        let api_upload = parts(&[("content-type", "multipart/form-data; boundary=x"), ("accept", "application/json")]);
+        assert!(select(&rules, &api_upload).is_none());
+    }
+
+    #[test]
+    fn test_select_by_path_and_priority() {
+        let rules = [
+            path_rule("v2.internal", "/v2", 0),
+            path_rule("admin.internal", "/v2/admin", 10),
+            rule("grpc.internal", &["application/grpc"], &[]),
+        ];
+        let backend = |uri: &str| select(&rules, &parts_for(uri, &[])).map(|r| r.backend.as_str());
+
+        assert_eq!(backend("/v2"), Some("v2.internal"));
+        assert_eq!(backend("/v2/users?page=2"), Some("v2.internal"));
+        assert_eq!(backend("/v2beta"), None);
+        assert_eq!(backend("/v1/users"), None);
+        // Listed later, but a higher priority
+        assert_eq!(backend("/v2/admin/users"), Some("admin.internal"));
+
+        let grpc = parts_for("/v2/pkg.Service/Call", &[("content-type", "application/grpc")]);
+        assert_eq!(select(&rules, &grpc).unwrap().backend, "v2.internal");
+    }
+
+    #[test]
+    fn test_strip_prefix() {
+        let route = Route::from(&path_rule("v2.internal", "/v2", 0));
+        assert_eq!(route.strip_prefix.as_deref(), Some("/v2"));
+        let mut kept = path_rule("v2.internal", "/v2", 0);
+        kept.strip_prefix = false;
+        assert_eq!(Route::from(&kept), Route::to("v2.internal"));
+
+        let mut parts = parts_for("/v2/users?page=2", &[]);
+        strip_prefix(&mut parts, "/v2");
+        assert_eq!(parts.uri, "/users?page=2");
+        assert_eq!(parts.headers[X_FORWARDED_PREFIX], "/v2");
+
+        let mut parts = parts_for("http://app.local/v2", &[]);
+        strip_prefix(&mut parts, "/v2/");
+        assert_eq!(parts.uri, "http://app.local/");
+        assert_eq!(parts.headers[X_FORWARDED_PREFIX], "/v2");
+    }
+
+    #[test]
+    fn test_select_by_headers_and_cookies() {
+        let mut tenant = path_rule("acme.internal", "/api", 0);
+        tenant.headers.insert("X-Tenant".to_string(), "acme".to_string());
+        let mut canary = rule("canary.internal", &[], &[]);
+        canary.cookies.insert("beta".to_string(), "1".to_string());
+        let mut debug = rule("debug.internal", &[], &[]);
+        debug.headers.insert("x-debug".to_string(), "*".to_string());
+        let rules = [tenant, canary, debug];
+        let backend = |uri: &str, headers: &[(&str, &str)]| {
+            select(&rules, &parts_for(uri, headers)).map(|r| r.backend.as_str())
+        };
+
+        assert_eq!(backend("/api/orders", &[("x-tenant", "acme")]), Some("acme.internal"));
+        // Header and path must both match
+        assert_eq!(backend("/web", &[("x-tenant", "acme")]), None);
+        assert_eq!(backend("/api/orders", &[("x-tenant", "globex")]), None);
+
+        assert_eq!(backend("/", &[("cookie", "session=abc; beta=1")]), Some("canary.internal"));
+        assert_eq!(backend("/", &[("cookie", "session=abc"), ("cookie", "beta=\"1\"")]), Some("canary.internal"));
+        assert_eq!(backend("/", &[("cookie", "beta=0")]), None);
+        assert_eq!(backend("/", &[("cookie", "notbeta=1")]), None);
+
+        assert_eq!(backend("/", &[("X-Debug", "yes")]), Some("debug.internal"));
+    }
+
+    #[test]
+    fn test_select_by_method() {
+        let mut reads = rule("replica.internal", &[], &[]);
+        reads.methods = vec!["GET".to_string(), "HEAD".to_string()];
+        reads.read_your_writes_secs = 5;
+        let rules = [reads];
+        let request = |method: &str, headers: &[(&str, &str)]| {
+            let mut parts = parts(headers);
+            parts.method = method.parse().unwrap();
+            parts
+        };
+        let backend = |method: &str, headers: &[(&str, &str)]| {
+            select(&rules, &request(method, headers)).map(|r| r.backend.as_str())
+        };
+
+        assert_eq!(backend("GET", &[]), Some("replica.internal"));
+        assert_eq!(backend("HEAD", &[]), Some("replica.internal"));
+        assert_eq!(backend("POST", &[]), None);
+        assert_eq!(backend("DELETE", &[]), None);
+        // Recent writers read from the primary
+        assert_eq!(backend("GET", &[("cookie", "session=abc; spawngate_wrote=1")]), None);
+
+        assert_eq!(read_your_writes(&rules, &Method::POST), Some(5));
+        assert_eq!(read_your_writes(&rules, &Method::GET), None);
+        assert_eq!(read_your_writes(&[rule("grpc.internal", &["application/grpc"], &[])], &Method::POST), None);
+        assert_eq!(wrote_cookie(5), "spawngate_wrote=1; Max-Age=5; Path=/; HttpOnly; SameSite=Lax");
+    }
+}
@@ -0,0 +1,198 @@
+//! Sampled per-request trace events
+//!
+//! A sampled request produces one `Request trace` event with its method,
+//! path, status and latency, plus the client's TLS fingerprint when
+//! fingerprinting is enabled. Whether a request is sampled depends on its
+//! status class, using the backend's rate for that class if it sets one and
+//! the server-wide rate otherwise. The decision is derived from the request
+//! ID, so a request keeps the same decision on every proxy it passes through.
+//! Requests carrying the force header are always traced.
+
+use crate::config::TraceSamplingConfig;
+use crate::fingerprint::TlsFingerprint;
+use crate::process::ProcessManager;
+use hyper::header::{HeaderMap, HeaderName};
+use hyper::http::request::Parts;
+use hyper::{Method, StatusCode};
+use ring::digest;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::info;
+
+/// Request details captured before the request is dispatched
+#[derive(Debug)]
+pub struct TraceCandidate {
+    forced: bool,
+    method: Method,
+    path: String,
+}
+
+/// Decides which requests are traced and emits their trace events
+pub struct TraceSampler {
+    force_header: HeaderName,
+    rates: HashMap<String, f64>,
+    default_rate: f64,
+    process_manager: Arc<ProcessManager>,
+}
+
+impl TraceSampler {
+    pub fn new(config: &TraceSamplingConfig, process_manager: Arc<ProcessManager>) -> Self {
+        Self {
+            // Validated with the config
+            force_header: HeaderName::from_bytes(config.force_header.as_bytes())
+                .unwrap_or_else(|_| HeaderName::from_static("x-debug-trace")),
+            rates: config.rates.clone(),
+            default_rate: config.default_rate,
+            process_manager,
+        }
+    }
+
+    /// Capture what the trace event needs from the request
+    pub fn candidate(&self, parts: &Parts) -> TraceCandidate {
+        TraceCandidate {
+            forced: self.forced(&parts.headers),
+            method: parts.method.clone(),
+            path: parts.uri.path().to_string(),
+        }
+    }
+
+    /// Emit a trace event for the request if it is sampled
+    pub fn finish(
+        &self,
+        candidate: TraceCandidate,
+        request_id: &str,
+        hostname: &str,
+        status: StatusCode,
+        latency: Duration,
+        fingerprint: Option<&TlsFingerprint>,
+    ) {
+        if !candidate.forced && !self.sampled(request_id, hostname, status) {
+            return;
+        }
+        info!(
+            request_id,
+            hostname,
+            method = %candidate.method,
+            path = candidate.path,
+            status = status.as_u16(),
+            latency_ms = latency.as_millis() as u64,
+            forced = candidate.forced,
+            ja3 = fingerprint.map(|f| f.ja3.as_str()),
+            ja4 = fingerprint.map(|f| f.ja4.as_str()),
+            "Request trace"
+        );
+    }
+
+    fn forced(&self, headers: &HeaderMap) -> bool {
+        headers.contains_key(&self.force_header)
+    }
+
+    /// Sample rate for a response from `hostname`
+    pub fn rate(&self, hostname: &str, status: StatusCode) -> f64 {
+        let class = status_class(status);
+        self.process_manager
+            .trace_sample_rate(hostname, class)
+            .or_else(|| self.rates.get(class).copied())
+            .unwrap_or(self.default_rate)
+    }
+
+    /// Whether the request is sampled, ignoring the force header
+    pub fn sampled(&self, request_id: &str, hostname: &str, status: StatusCode) -> bool {
+        let rate = self.rate(hostname, status);
+        rate >= 1.0 || (rate > 0.0 && sample_point(request_id) < rate)
+    }
+}
+
+fn status_class(status: StatusCode) -> &'static str {
+    match status.as_u16() {
+        100..=199 => "1xx",
+        200..=299 => "2xx",
+        300..=399 => "3xx",
+        400..=499 => "4xx",
+        _ => "5xx",
+    }
+}
+
+/// Map a request ID uniformly onto [0, 1)
+fn sample_point(request_id: &str) -> f64 {
+    // A stable hash, unlike std's hashers, so every instance and release agrees
+    let hash = digest::digest(&digest::SHA256, request_id.as_bytes());
+    let bits = u64::from_be_bytes(hash.as_ref()[..8].try_into().expect("8 bytes"));
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{BackendConfig, BackendDefaults};
+    use hyper::Request;
+
+    fn sampler() -> TraceSampler {
+        let mut backend = BackendConfig::local("node", 3000);
+        backend.trace_sample_rates.insert("4xx".to_string(), 1.0);
+        let configs = HashMap::from([("api.local".to_string(), backend)]);
+        let process_manager = ProcessManager::new(configs, BackendDefaults::default(), String::new());
+
+        let config = TraceSamplingConfig {
+            enabled: true,
+            rates: HashMap::from([("5xx".to_string(), 1.0), ("2xx".to_string(), 0.25)]),
+            ..Default::default()
+        };
+        TraceSampler::new(&config, process_manager)
+    }
+
+    #[test]
+    fn test_rates_fall_back_from_backend_to_server() {
+        let sampler = sampler();
+        assert_eq!(sampler.rate("api.local", StatusCode::NOT_FOUND), 1.0);
+        assert_eq!(sampler.rate("api.local", StatusCode::BAD_GATEWAY), 1.0);
+        assert_eq!(sampler.rate("other.local", StatusCode::NOT_FOUND), 0.0);
+        assert_eq!(sampler.rate("other.local", StatusCode::OK), 0.25);
+        assert_eq!(sampler.rate("other.local", StatusCode::FOUND), 0.0);
+    }
+
+    #[test]
+    fn test_sampling_follows_rate_and_is_stable() {
+        let sampler = sampler();
+        let ids: Vec<String> = (0..4000).map(|i| format!("req-{}", i)).collect();
+
+        assert!(ids.iter().all(|id| sampler.sampled(id, "other.local", StatusCode::INTERNAL_SERVER_ERROR)));
+        assert!(!ids.iter().any(|id| sampler.sampled(id, "other.local", StatusCode::MOVED_PERMANENTLY)));
+
+        let sampled = ids
+            .iter()
+            .filter(|id| sampler.sampled(id, "other.local", StatusCode::OK))
+            .count();
+        assert!((800..1200).contains(&sampled), "sampled {} of 4000", sampled);
+
+        for id in &ids[..50] {
+            assert_eq!(
+                sampler.sampled(id, "other.local", StatusCode::OK),
+                sampler.sampled(id, "other.local", StatusCode::NO_CONTENT)
+            );
+        }
+    }
+
+    #[test]
+    fn test_sample_point_is_fixed() {
+        // Every instance and release samples a request ID alike
+        assert_eq!(sample_point("req-1"), 0.5794485793621023);
+        assert_eq!(sample_point("req-2"), 0.6507564023143245);
+    }
+
+    #[test]
+    fn test_force_header() {
+        let sampler = sampler();
+        let req = Request::builder()
+            .uri("/debug")
+            .header("x-debug-trace", "1")
+            .body(())
+            .unwrap();
+        let (parts, _) = req.into_parts();
+        assert!(sampler.candidate(&parts).forced);
+
+        let (parts, _) = Request::new(()).into_parts();
+        assert!(!sampler.candidate(&parts).forced);
+    }
+}
@@ -0,0 +1,264 @@
+//! Estimated savings from scale-to-zero
+//!
+//! Each backend's running time is recorded from spawn until it is stopped.
+//! The rest of the time it was configured is what scale-to-zero saved over
+//! keeping it up all the time. Weighted by the backend's size (its `cpus` and
+//! `memory`, or the sizes in `[defaults.cost]`) this gives the vCPU-hours and
+//! GB-hours saved, and with prices configured an estimated amount.
+//!
+//! Usage is kept in memory, so the report covers the time since the proxy
+//! started (or since the backend was added by a reload).
+
+use crate::config::{BackendConfig, CostModel};
+use crate::docker::parse_memory_limit;
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+const GB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+/// How long a backend was tracked and running
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Usage {
+    pub tracked: Duration,
+    pub running: Duration,
+    pub starts: u64,
+}
+
+#[derive(Debug)]
+struct Uptime {
+    since: Instant,
+    running_since: Option<Instant>,
+    running: Duration,
+    starts: u64,
+}
+
+/// Running time of every backend
+#[derive(Debug, Default)]
+pub struct UptimeLedger {
+    backends: DashMap<String, Mutex<Uptime>>,
+}
+
+impl UptimeLedger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start tracking a backend (no-op if it is already tracked)
+    pub fn track(&self, hostname: &str) {
+        self.track_at(hostname, Instant::now());
+    }
+
+    fn track_at(&self, hostname: &str, now: Instant) {
+        self.backends.entry(hostname.to_string()).or_insert_with(|| {
+            Mutex::new(Uptime {
+                since: now,
+                running_since: None,
+                running: Duration::ZERO,
+                starts: 0,
+            })
+        });
+    }
+
+    /// The backend was spawned
+    pub fn started(&self, hostname: &str) {
+        self.started_at(hostname, Instant::now());
+    }
+
+    fn started_at(&self, hostname: &str, now: Instant) {
+        self.track_at(hostname, now);
+        if let Some(uptime) = self.backends.get(hostname) {
+            let mut uptime = uptime.lock();
+            if uptime.running_since.is_none() {
+                uptime.running_since = Some(now);
+                uptime.starts += 1;
+            }
+        }
+    }
+
+    /// The backend was stopped
+    pub fn stopped(&self, hostname: &str) {
+        self.stopped_at(hostname, Instant::now());
+    }
+
+    fn stopped_at(&self, hostname: &str, now: Instant) {
+        if let Some(uptime) = self.backends.get(hostname) {
+            let mut uptime = uptime.lock();
+            if let Some(since) = uptime.running_since.take() {
+                uptime.running += now.saturating_duration_since(since);
+            }
+        }
+    }
+
+    /// Stop tracking a removed backend
+    pub fn forget(&self, hostname: &str) {
+        self.backends.remove(hostname);
+    }
+
+    /// Usage of a backend up to now
+    pub fn usage(&self, hostname: &str) -> Option<Usage> {
+        self.usage_at(hostname, Instant::now())
+    }
+
+    fn usage_at(&self, hostname: &str, now: Instant) -> Option<Usage> {
+        let uptime = self.backends.get(hostname)?;
+        let uptime = uptime.lock();
+        let current = uptime
+            .running_since
+            .map_or(Duration::ZERO, |since| now.saturating_duration_since(since));
+        Some(Usage {
+            tracked: now.saturating_duration_since(uptime.since),
+            running: uptime.running + current,
+            starts: uptime.starts,
+        })
+    }
+}
+
+/// Savings of one backend, as reported by the admin API
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct BackendSavings {
+    pub hostname: String,
+    pub tracked_secs: u64,
+    pub running_secs: u64,
+    pub stopped_secs: u64,
+    /// Share of the tracked time the backend was stopped
+    pub stopped_ratio: f64,
+    pub starts: u64,
+    pub cpus: f64,
+    pub memory_gb: f64,
+    pub cpu_hours_saved: f64,
+    pub gb_hours_saved: f64,
+    /// Amount saved, if prices are configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_savings: Option<f64>,
+}
+
+impl BackendSavings {
+    pub fn new(hostname: &str, usage: Usage, config: &BackendConfig, model: &CostModel) -> Self {
+        let stopped = usage.tracked.saturating_sub(usage.running);
+        let stopped_hours = stopped.as_secs_f64() / 3600.0;
+
+        // Unparsable sizes are rejected by Docker; fall back to the defaults here
+        let cpus = config
+            .cpus
+            .as_deref()
+            .and_then(|cpus| cpus.parse().ok())
+            .unwrap_or(model.cpus);
+        let memory_gb = config
+            .memory
+            .as_deref()
+            .and_then(|memory| parse_memory_limit(memory).ok())
+            .or_else(|| parse_memory_limit(&model.memory).ok())
+            .unwrap_or(0) as f64
+            / GB;
+
+        let cpu_hours_saved = stopped_hours * cpus;
+        let gb_hours_saved = stopped_hours * memory_gb;
+        let estimated_savings = (model.cpu_hour_price.is_some() || model.gb_hour_price.is_some()).then(|| {
+            cpu_hours_saved * model.cpu_hour_price.unwrap_or(0.0) + gb_hours_saved * model.gb_hour_price.unwrap_or(0.0)
+        });
+
+        Self {
+            hostname: hostname.to_string(),
+            tracked_secs: usage.tracked.as_secs(),
+            running_secs: usage.running.as_secs(),
+            stopped_secs: stopped.as_secs(),
+            stopped_ratio: if usage.tracked.is_zero() {
+                0.0
+            } else {
+                stopped.as_secs_f64() / usage.tracked.as_secs_f64()
+            },
+            starts: usage.starts,
+            cpus,
+            memory_gb,
+            cpu_hours_saved,
+            gb_hours_saved,
+            estimated_savings,
+        }
+    }
+}
+
+/// Savings of every backend and their sum
+#[derive(Debug, Clone, Serialize)]
+pub struct SavingsReport {
+    pub backends: Vec<BackendSavings>,
+    pub cpu_hours_saved: f64,
+    pub gb_hours_saved: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_savings: Option<f64>,
+}
+
+impl SavingsReport {
+    pub fn new(mut backends: Vec<BackendSavings>) -> Self {
+        backends.sort_by(|a, b| a.hostname.cmp(&b.hostname));
+        Self {
+            cpu_hours_saved: backends.iter().map(|b| b.cpu_hours_saved).sum(),
+            gb_hours_saved: backends.iter().map(|b| b.gb_hours_saved).sum(),
+            estimated_savings: backends
+                .iter()
+                .map(|b| b.estimated_savings)
+                .reduce(|a, b| Some(a? + b?))
+                .flatten(),
+            backends,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ledger_tracks_running_time() {
+        let ledger = UptimeLedger::new();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        ledger.track_at("app.local", start);
+        ledger.started_at("app.local", at(100));
+        ledger.started_at("app.local", at(150));
+        ledger.stopped_at("app.local", at(200));
+        ledger.started_at("app.local", at(1000));
+
+        let usage = ledger.usage_at("app.local", at(1100)).unwrap();
+        assert_eq!(usage.tracked, Duration::from_secs(1100));
+        assert_eq!(usage.running, Duration::from_secs(200));
+        assert_eq!(usage.starts, 2);
+
+        ledger.forget("app.local");
+        assert!(ledger.usage("app.local").is_none());
+    }
+
+    #[test]
+    fn test_savings_weighted_by_size() {
+        let usage = Usage {
+            tracked: Duration::from_secs(10 * 3600),
+            running: Duration::from_secs(2 * 3600),
+            starts: 3,
+        };
+        let model = CostModel {
+            cpu_hour_price: Some(0.05),
+            ..Default::default()
+        };
+
+        let mut config = BackendConfig::local("app", 3000);
+        config.cpus = Some("2".to_string());
+        config.memory = Some("1g".to_string());
+        let sized = BackendSavings::new("sized.local", usage, &config, &model);
+        assert_eq!(sized.stopped_secs, 8 * 3600);
+        assert_eq!(sized.stopped_ratio, 0.8);
+        assert_eq!(sized.cpu_hours_saved, 16.0);
+        assert_eq!(sized.gb_hours_saved, 8.0);
+        assert_eq!(sized.estimated_savings, Some(0.8));
+
+        // Defaults: 1 vCPU and 512 MB
+        let default = BackendSavings::new("default.local", usage, &BackendConfig::local("app", 3001), &model);
+        assert_eq!((default.cpu_hours_saved, default.gb_hours_saved), (8.0, 4.0));
+
+        let report = SavingsReport::new(vec![sized, default]);
+        assert_eq!(report.backends[0].hostname, "default.local");
+        assert_eq!(report.cpu_hours_saved, 24.0);
+        assert!((report.estimated_savings.unwrap() - 1.2).abs() < 1e-9);
+    }
+}
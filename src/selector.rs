@@ -0,0 +1,247 @@
+//! Label selectors for referring to groups of backends
+//!
+//! Syntax follows Kubernetes set-based selectors. Requirements are separated
+//! by commas and must all match:
+//!
+//! | Expression | Matches when |
+//! |------------|--------------|
+//! | `env=prod` / `env==prod` | label `env` is `prod` |
+//! | `env!=prod` | label `env` is missing or not `prod` |
+//! | `tier in (web, api)` | label `tier` is one of the values |
+//! | `tier notin (db)` | label `tier` is missing or none of the values |
+//! | `team` | label `team` exists |
+//! | `!legacy` | label `legacy` does not exist |
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Requirement {
+    Equals(String, String),
+    NotEquals(String, String),
+    In(String, Vec<String>),
+    NotIn(String, Vec<String>),
+    Exists(String),
+    NotExists(String),
+}
+
+impl Requirement {
+    fn matches(&self, labels: &HashMap<String, String>) -> bool {
+        match self {
+            Requirement::Equals(key, value) => labels.get(key) == Some(value),
+            Requirement::NotEquals(key, value) => labels.get(key) != Some(value),
+            Requirement::In(key, values) => labels.get(key).is_some_and(|v| values.contains(v)),
+            Requirement::NotIn(key, values) => !labels.get(key).is_some_and(|v| values.contains(v)),
+            Requirement::Exists(key) => labels.contains_key(key),
+            Requirement::NotExists(key) => !labels.contains_key(key),
+        }
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Requirement::Equals(key, value) => write!(f, "{}={}", key, value),
+            Requirement::NotEquals(key, value) => write!(f, "{}!={}", key, value),
+            Requirement::In(key, values) => write!(f, "{} in ({})", key, values.join(", ")),
+            Requirement::NotIn(key, values) => write!(f, "{} notin ({})", key, values.join(", ")),
+            Requirement::Exists(key) => write!(f, "{}", key),
+            Requirement::NotExists(key) => write!(f, "!{}", key),
+        }
+    }
+}
+
+/// A parsed label selector; the empty selector matches everything
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Selector {
+    requirements: Vec<Requirement>,
+}
+
+/// Error parsing a selector expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelectorError(String);
+
+impl fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid selector: {}", self.0)
+    }
+}
+
+impl std::error::Error for SelectorError {}
+
+impl Selector {
+    /// Parse a selector expression
+    pub fn parse(expr: &str) -> Result<Self, SelectorError> {
+        let requirements = split_requirements(expr)?
+            .into_iter()
+            .map(parse_requirement)
+            .collect::<Result<_, _>>()?;
+        Ok(Self { requirements })
+    }
+
+    /// Whether the selector matches a label set
+    pub fn matches(&self, labels: &HashMap<String, String>) -> bool {
+        self.requirements.iter().all(|r| r.matches(labels))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requirements.is_empty()
+    }
+}
+
+impl FromStr for Selector {
+    type Err = SelectorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+/// The selector's expression, in canonical form
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let requirements: Vec<String> = self.requirements.iter().map(|r| r.to_string()).collect();
+        write!(f, "{}", requirements.join(","))
+    }
+}
+
+impl serde::Serialize for Selector {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Selector {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let expr = String::deserialize(deserializer)?;
+        Self::parse(&expr).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Split on commas that are not inside a value list
+fn split_requirements(expr: &str) -> Result<Vec<&str>, SelectorError> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in expr.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Err(SelectorError("unbalanced ')'".into())),
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&expr[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err(SelectorError("unbalanced '('".into()));
+    }
+    parts.push(&expr[start..]);
+
+    let parts: Vec<&str> = parts.into_iter().map(str::trim).collect();
+    if parts == [""] {
+        return Ok(Vec::new());
+    }
+    if parts.iter().any(|p| p.is_empty()) {
+        return Err(SelectorError("empty requirement".into()));
+    }
+    Ok(parts)
+}
+
+fn parse_requirement(part: &str) -> Result<Requirement, SelectorError> {
+    if let Some((key, value)) = part.split_once("!=") {
+        return Ok(Requirement::NotEquals(label_key(key)?, label_value(value)?));
+    }
+    if let Some((key, value)) = part.split_once("==").or_else(|| part.split_once('=')) {
+        return Ok(Requirement::Equals(label_key(key)?, label_value(value)?));
+    }
+    if let Some((key, values)) = split_set(part, " notin ") {
+        return Ok(Requirement::NotIn(label_key(key)?, value_list(values)?));
+    }
+    if let Some((key, values)) = split_set(part, " in ") {
+        return Ok(Requirement::In(label_key(key)?, value_list(values)?));
+    }
+    if let Some(key) = part.strip_prefix('!') {
+        return Ok(Requirement::NotExists(label_key(key)?));
+    }
+    Ok(Requirement::Exists(label_key(part)?))
+}
+
+fn split_set<'a>(part: &'a str, operator: &str) -> Option<(&'a str, &'a str)> {
+    let (key, values) = part.split_once(operator)?;
+    Some((key, values.trim()))
+}
+
+fn value_list(values: &str) -> Result<Vec<String>, SelectorError> {
+    let inner = values
+        .strip_prefix('(')
+        .and_then(|v| v.strip_suffix(')'))
+        .ok_or_else(|| SelectorError(format!("expected '(...)' value list, got '{}'", values)))?;
+    inner.split(',').map(label_value).collect()
+}
+
+fn label_key(key: &str) -> Result<String, SelectorError> {
+    let key = key.trim();
+    let valid = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/'));
+    if valid {
+        Ok(key.to_string())
+    } else {
+        Err(SelectorError(format!("invalid label key '{}'", key)))
+    }
+}
+
+fn label_value(value: &str) -> Result<String, SelectorError> {
+    let value = value.trim();
+    if value.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')) {
+        Ok(value.to_string())
+    } else {
+        Err(SelectorError(format!("invalid label value '{}'", value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_matching() {
+        let prod_web = labels(&[("env", "prod"), ("tier", "web"), ("team", "core")]);
+        let staging_db = labels(&[("env", "staging"), ("tier", "db"), ("legacy", "")]);
+
+        let cases = [
+            ("", true, true),
+            ("env=prod", true, false),
+            ("env==prod", true, false),
+            ("env!=prod", false, true),
+            ("tier in (web, api)", true, false),
+            ("tier notin (db)", true, false),
+            ("team", true, false),
+            ("!legacy", true, false),
+            ("env=prod, tier in (web,api), !legacy", true, false),
+            ("owner!=alice", true, true),
+        ];
+        for (expr, a, b) in cases {
+            let selector = Selector::parse(expr).unwrap();
+            assert_eq!(selector.matches(&prod_web), a, "{} on prod_web", expr);
+            assert_eq!(selector.matches(&staging_db), b, "{} on staging_db", expr);
+            assert_eq!(Selector::parse(&selector.to_string()).unwrap(), selector, "{} round trip", expr);
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for expr in ["env=prod,", "tier in web", "tier in (web", "a b", "env=pr od", ",", "x)"] {
+            assert!(Selector::parse(expr).is_err(), "{} should fail", expr);
+        }
+    }
+}
@@ -0,0 +1,239 @@
+//! Shutdown hooks and the state snapshot file
+//!
+//! On shutdown the configured hook commands run one after the other, before
+//! the listeners stop accepting, each killed once its `timeout_secs` runs out.
+//! They get `SPAWNGATE_STATE_FILE` with the path of the state snapshot, if
+//! one is configured.
+//!
+//! The snapshot lists the backends that are running and the requests waiting
+//! for a backend to become ready:
+//!
+//! ```json
+//! {"timestamp":1760572800,"pid":4242,"reason":"shutdown","queued_requests":3,
+//!  "backends":[{"hostname":"app.local","state":"ready","port":3000,"in_flight":1,"queued":0}]}
+//! ```
+//!
+//! It is written on shutdown and every `state_interval_secs` while running,
+//! replacing the file atomically, so a crash leaves the last one behind.
+
+use crate::config::ShutdownHook;
+use crate::process::{BackendState, ProcessManager};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::process::Command;
+use tokio::sync::watch;
+use tracing::{debug, error, info, warn};
+
+/// Env var with the state snapshot path, set for hook commands
+pub const STATE_FILE_ENV: &str = "SPAWNGATE_STATE_FILE";
+
+/// A backend that wasn't stopped when the snapshot was taken
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct RunningBackend {
+    pub hostname: String,
+    pub state: BackendState,
+    pub port: u16,
+    pub in_flight: usize,
+    pub queued: usize,
+}
+
+/// What the proxy was doing when the snapshot was taken
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct StateSnapshot {
+    /// Unix time in seconds
+    pub timestamp: u64,
+    pub pid: u32,
+    /// `shutdown` for the final snapshot, `periodic` otherwise
+    pub reason: &'static str,
+    /// Requests waiting for any backend to become ready
+    pub queued_requests: usize,
+    /// Running backends, by hostname
+    pub backends: Vec<RunningBackend>,
+}
+
+impl StateSnapshot {
+    pub fn take(manager: &ProcessManager, reason: &'static str) -> Self {
+        let mut backends: Vec<RunningBackend> = manager
+            .list_backends()
+            .into_iter()
+            .filter(|b| b.state != BackendState::Stopped || b.queued > 0)
+            .map(|b| RunningBackend {
+                hostname: b.hostname,
+                state: b.state,
+                port: b.port,
+                in_flight: b.in_flight,
+                queued: b.queued,
+            })
+            .collect();
+        backends.sort_by(|a, b| a.hostname.cmp(&b.hostname));
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            pid: std::process::id(),
+            reason,
+            queued_requests: backends.iter().map(|b| b.queued).sum(),
+            backends,
+        }
+    }
+
+    /// Replace `path` with the snapshot, via a temporary file next to it
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let json = serde_json::to_vec_pretty(self).map_err(std::io::Error::other)?;
+        std::fs::write(&tmp, json)?;
+        std::fs::rename(&tmp, path)
+    }
+}
+
+/// Take a snapshot and write it to `path`, logging failures
+pub async fn export_state(manager: &ProcessManager, path: &Path, reason: &'static str) {
+    let snapshot = StateSnapshot::take(manager, reason);
+    let backends = snapshot.backends.len();
+    let path = path.to_path_buf();
+    match tokio::task::spawn_blocking(move || snapshot.write(&path)).await {
+        Ok(Ok(())) => debug!(backends, reason, "Wrote state snapshot"),
+        Ok(Err(e)) => error!(error = %e, "Failed to write state snapshot"),
+        Err(e) => error!(error = %e, "State snapshot task failed"),
+    }
+}
+
+/// Rewrite the state snapshot every `interval` until shutdown
+///
+/// The final snapshot is left to the shutdown sequence, which takes it
+/// before anything stops.
+pub async fn run_state_export(
+    manager: Arc<ProcessManager>,
+    path: PathBuf,
+    interval: Duration,
+    mut shutdown_rx: watch::Receiver<bool>,
+) {
+    info!(path = %path.display(), interval_secs = interval.as_secs(), "State snapshots enabled");
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => export_state(&manager, &path, "periodic").await,
+            _ = shutdown_rx.changed() => {
+                if *shutdown_rx.borrow() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Run the shutdown hooks in order
+///
+/// A hook that fails or times out is logged and the next one still runs.
+pub async fn run_hooks(hooks: &[ShutdownHook], state_file: Option<&Path>) {
+    for hook in hooks {
+        info!(command = %hook.command, "Running shutdown hook");
+        let mut cmd = Command::new(&hook.command);
+        cmd.args(&hook.args);
+        cmd.stdin(Stdio::null());
+        cmd.kill_on_drop(true);
+        if let Some(path) = state_file {
+            cmd.env(STATE_FILE_ENV, path);
+        }
+
+        let status = match cmd.spawn() {
+            Ok(mut child) => tokio::time::timeout(Duration::from_secs(hook.timeout_secs), child.wait()).await,
+            Err(e) => {
+                error!(command = %hook.command, error = %e, "Failed to start shutdown hook");
+                continue;
+            }
+        };
+        match status {
+            Ok(Ok(status)) if status.success() => debug!(command = %hook.command, "Shutdown hook finished"),
+            Ok(Ok(status)) => warn!(command = %hook.command, %status, "Shutdown hook failed"),
+            Ok(Err(e)) => error!(command = %hook.command, error = %e, "Shutdown hook failed"),
+            Err(_) => warn!(
+                command = %hook.command,
+                timeout_secs = hook.timeout_secs,
+                "Shutdown hook timed out, killing it"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{BackendConfig, BackendDefaults, PriorityClass};
+    use std::collections::HashMap;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("spawngate-shutdown-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn hook(command: &str, args: &[&str], timeout_secs: u64) -> ShutdownHook {
+        ShutdownHook {
+            command: command.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+            timeout_secs,
+        }
+    }
+
+    #[test]
+    fn test_snapshot_lists_waiting_backends() {
+        let mut configs = HashMap::new();
+        configs.insert("app.local".to_string(), BackendConfig::local("./app", 3000));
+        configs.insert("idle.local".to_string(), BackendConfig::local("./idle", 3001));
+        let manager = ProcessManager::new(configs, BackendDefaults::default(), "http://127.0.0.1:9999".to_string());
+
+        let queued = (
+            manager.queue_request("app.local", PriorityClass::Normal, None),
+            manager.queue_request("app.local", PriorityClass::Low, None),
+        );
+        let snapshot = StateSnapshot::take(&manager, "shutdown");
+        assert_eq!(snapshot.queued_requests, 2);
+        assert_eq!(snapshot.backends.len(), 1);
+        assert_eq!(snapshot.backends[0].hostname, "app.local");
+        assert_eq!(snapshot.backends[0].queued, 2);
+
+        drop(queued);
+        assert!(StateSnapshot::take(&manager, "periodic").backends.is_empty());
+
+        let dir = temp_dir("snapshot");
+        let path = dir.join("state.json");
+        snapshot.write(&path).unwrap();
+        let written: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(written["reason"], "shutdown");
+        assert_eq!(written["queued_requests"], 2);
+        assert_eq!(written["backends"][0]["state"], "stopped");
+        assert!(!dir.join("state.json.tmp").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_hooks_run_in_order() {
+        let dir = temp_dir("hooks");
+        let out = dir.join("hooks.log");
+        let state = dir.join("state.json");
+        let append = format!("echo \"$1 $SPAWNGATE_STATE_FILE\" >> {}", out.display());
+        let hooks = vec![
+            hook("sh", &["-c", &append, "sh", "first"], 5),
+            hook("/nonexistent/hook", &[], 5),
+            hook("sleep", &["10"], 1),
+            hook("sh", &["-c", &append, "sh", "second"], 5),
+        ];
+
+        let started = std::time::Instant::now();
+        run_hooks(&hooks, Some(&state)).await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let log = std::fs::read_to_string(&out).unwrap();
+        let expected = format!("first {}\nsecond {}\n", state.display(), state.display());
+        assert_eq!(log, expected);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,114 @@
+//! Signing of requests the proxy sends to backends
+//!
+//! With `signing_secret` set on a backend, the proxy's own requests to it
+//! (health probes) carry an HMAC-SHA256 signature, so the backend can tell
+//! them apart from probes spoofed by anyone else who can reach its port:
+//!
+//! ```text
+//! X-Spawngate-Timestamp: 1700000000
+//! X-Spawngate-Signature: v1=<hex HMAC-SHA256 of "{timestamp}.{method}.{path}">
+//! ```
+//!
+//! The path includes the query string. Backends should recompute the
+//! signature, compare it in constant time and reject timestamps older than a
+//! few minutes, so a captured probe can't be replayed later.
+
+use ring::hmac;
+use std::fmt::Write as _;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Header with the Unix time the request was signed at
+pub const TIMESTAMP_HEADER: &str = "x-spawngate-timestamp";
+
+/// Header with the request's signature
+pub const SIGNATURE_HEADER: &str = "x-spawngate-signature";
+
+/// Signature of a request, as sent in [`SIGNATURE_HEADER`]
+pub fn signature(secret: &str, timestamp: u64, method: &str, path: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let tag = hmac::sign(&key, message(timestamp, method, path).as_bytes());
+    tag.as_ref().iter().fold("v1=".to_string(), |mut out, b| {
+        let _ = write!(out, "{:02x}", b);
+        out
+    })
+}
+
+fn message(timestamp: u64, method: &str, path: &str) -> String {
+    format!("{}.{}.{}", timestamp, method, path)
+}
+
+/// Headers signing a request sent now
+pub fn headers(secret: &str, method: &str, path: &str) -> [(&'static str, String); 2] {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    [
+        (TIMESTAMP_HEADER, timestamp.to_string()),
+        (SIGNATURE_HEADER, signature(secret, timestamp, method, path)),
+    ]
+}
+
+/// Check a signed request the way a backend would
+///
+/// Fails for a wrong signature or a timestamp more than `tolerance` away from `now`.
+pub fn verify(
+    secret: &str,
+    method: &str,
+    path: &str,
+    timestamp: &str,
+    signature: &str,
+    now: SystemTime,
+    tolerance: Duration,
+) -> bool {
+    let Ok(timestamp) = timestamp.parse::<u64>() else {
+        return false;
+    };
+    let now = now.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+    if now.abs_diff(timestamp) > tolerance.as_secs() {
+        return false;
+    }
+    let Some(tag) = signature.strip_prefix("v1=").and_then(decode_hex) else {
+        return false;
+    };
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    // Compares in constant time
+    hmac::verify(&key, message(timestamp, method, path).as_bytes(), &tag).is_ok()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let tolerance = Duration::from_secs(300);
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let signed = signature("s3cret", 1_700_000_000, "GET", "/health?deep=1");
+        assert!(signed.starts_with("v1="));
+        assert_eq!(signed.len(), 3 + 64);
+
+        assert!(verify("s3cret", "GET", "/health?deep=1", "1700000000", &signed, now, tolerance));
+        assert!(!verify("other", "GET", "/health?deep=1", "1700000000", &signed, now, tolerance));
+        assert!(!verify("s3cret", "GET", "/health", "1700000000", &signed, now, tolerance));
+        assert!(!verify("s3cret", "GET", "/health?deep=1", "1700000001", &signed, now, tolerance));
+        assert!(!verify("s3cret", "GET", "/health?deep=1", "1700000000", "v1=zz", now, tolerance));
+
+        // Replayed after the tolerance
+        let later = now + Duration::from_secs(301);
+        assert!(!verify("s3cret", "GET", "/health?deep=1", "1700000000", &signed, later, tolerance));
+
+        let [(_, timestamp), (_, signed)] = headers("s3cret", "GET", "/health");
+        assert!(verify("s3cret", "GET", "/health", &timestamp, &signed, SystemTime::now(), tolerance));
+    }
+}
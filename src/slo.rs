@@ -0,0 +1,284 @@
+//! Cold-start latency SLO tracking
+//!
+//! An objective such as "99% of requests see less than 2s of added cold-start
+//! latency" is checked against the time each request spent waiting for its
+//! backend to start (the `spawn` timing, 0 when the backend was ready).
+//! Requests whose backend failed to start count as violations.
+//!
+//! Attainment is tracked per backend over a rolling window. The burn rate is
+//! the share of violating requests divided by the error budget (`1 - target`):
+//! at 1.0 the budget lasts exactly one window, at 10 it is gone in a tenth of
+//! it. An alert is logged when the burn rate over both the whole window and
+//! its last twelfth exceed `alert_burn_rate`, so a burst of slow cold starts
+//! alerts quickly and the alert clears once cold starts are fast again.
+
+use crate::config::ColdStartSloConfig;
+use crate::process::ProcessManager;
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Buckets the window is divided into
+const BUCKETS: u64 = 60;
+
+/// Buckets in the short window used to confirm alerts (a twelfth of the window)
+const SHORT_BUCKETS: u64 = BUCKETS / 12;
+
+#[derive(Debug)]
+struct Bucket {
+    index: u64,
+    requests: u64,
+    violations: u64,
+}
+
+#[derive(Debug, Default)]
+struct Attainment {
+    buckets: VecDeque<Bucket>,
+    alerting: bool,
+}
+
+impl Attainment {
+    fn prune(&mut self, current: u64) {
+        while self.buckets.front().is_some_and(|b| b.index + BUCKETS <= current) {
+            self.buckets.pop_front();
+        }
+    }
+
+    /// Requests and violations in the last `buckets` buckets
+    fn totals(&self, current: u64, buckets: u64) -> (u64, u64) {
+        self.buckets
+            .iter()
+            .filter(|b| b.index + buckets > current)
+            .fold((0, 0), |(r, v), b| (r + b.requests, v + b.violations))
+    }
+}
+
+/// SLO attainment of one backend within the window
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct SloStatus {
+    pub hostname: String,
+    pub threshold_ms: u64,
+    pub target: f64,
+    pub requests: u64,
+    /// Requests over the threshold or whose backend failed to start
+    pub violations: u64,
+    /// Share of requests within the threshold (1.0 without requests)
+    pub attainment: f64,
+    /// Error budget burn rate over the window
+    pub burn_rate: f64,
+    /// Error budget burn rate over the last twelfth of the window
+    pub short_burn_rate: f64,
+    pub alerting: bool,
+}
+
+/// Attainment of every backend that saw requests, as returned by the admin API
+#[derive(Debug, Clone, Serialize)]
+pub struct SloReport {
+    pub window_secs: u64,
+    pub alert_burn_rate: f64,
+    pub backends: Vec<SloStatus>,
+}
+
+/// Tracks cold-start SLO attainment per backend
+pub struct ColdStartSlo {
+    threshold: Duration,
+    target: f64,
+    window: Duration,
+    alert_burn_rate: f64,
+    started: Instant,
+    backends: DashMap<String, Mutex<Attainment>>,
+    process_manager: Arc<ProcessManager>,
+}
+
+impl ColdStartSlo {
+    pub fn new(config: &ColdStartSloConfig, process_manager: Arc<ProcessManager>) -> Self {
+        Self {
+            threshold: Duration::from_millis(config.threshold_ms),
+            target: config.target,
+            window: Duration::from_secs(config.window_secs),
+            alert_burn_rate: config.alert_burn_rate,
+            started: Instant::now(),
+            backends: DashMap::new(),
+            process_manager,
+        }
+    }
+
+    /// Record a request that waited `spawn` for its backend, or `None` if
+    /// the backend failed to start
+    pub fn record(&self, hostname: &str, spawn: Option<Duration>) {
+        self.record_at(hostname, spawn, Instant::now());
+    }
+
+    fn record_at(&self, hostname: &str, spawn: Option<Duration>, now: Instant) {
+        let (threshold, target) = self.objective(hostname);
+        let violation = spawn.is_none_or(|spawn| spawn > threshold);
+        let current = self.bucket(now);
+
+        let entry = self.backends.entry(hostname.to_string()).or_default();
+        let mut attainment = entry.lock();
+        attainment.prune(current);
+        match attainment.buckets.back_mut() {
+            Some(bucket) if bucket.index == current => {
+                bucket.requests += 1;
+                bucket.violations += u64::from(violation);
+            }
+            _ => attainment.buckets.push_back(Bucket {
+                index: current,
+                requests: 1,
+                violations: u64::from(violation),
+            }),
+        }
+        self.evaluate(hostname, &mut attainment, current, target);
+    }
+
+    /// Attainment and burn rates of every backend, sorted by hostname
+    pub fn report(&self) -> SloReport {
+        self.report_at(Instant::now())
+    }
+
+    fn report_at(&self, now: Instant) -> SloReport {
+        let current = self.bucket(now);
+        let mut backends: Vec<SloStatus> = self
+            .backends
+            .iter()
+            .map(|entry| {
+                let hostname = entry.key();
+                let (threshold, target) = self.objective(hostname);
+                let mut attainment = entry.value().lock();
+                attainment.prune(current);
+                let (burn_rate, short_burn_rate) = self.evaluate(hostname, &mut attainment, current, target);
+                let (requests, violations) = attainment.totals(current, BUCKETS);
+                SloStatus {
+                    hostname: hostname.clone(),
+                    threshold_ms: threshold.as_millis() as u64,
+                    target,
+                    requests,
+                    violations,
+                    attainment: if requests == 0 {
+                        1.0
+                    } else {
+                        (requests - violations) as f64 / requests as f64
+                    },
+                    burn_rate,
+                    short_burn_rate,
+                    alerting: attainment.alerting,
+                }
+            })
+            .collect();
+        backends.sort_by(|a, b| a.hostname.cmp(&b.hostname));
+
+        SloReport {
+            window_secs: self.window.as_secs(),
+            alert_burn_rate: self.alert_burn_rate,
+            backends,
+        }
+    }
+
+    /// Threshold and target for a backend, with its overrides applied
+    fn objective(&self, hostname: &str) -> (Duration, f64) {
+        let (threshold, target) = self.process_manager.cold_start_slo(hostname);
+        (threshold.unwrap_or(self.threshold), target.unwrap_or(self.target))
+    }
+
+    fn bucket(&self, now: Instant) -> u64 {
+        let width = (self.window.as_millis() as u64 / BUCKETS).max(1);
+        now.saturating_duration_since(self.started).as_millis() as u64 / width
+    }
+
+    /// Update the alert state, returning the long and short burn rates
+    fn evaluate(&self, hostname: &str, attainment: &mut Attainment, current: u64, target: f64) -> (f64, f64) {
+        let burn_rate = |(requests, violations): (u64, u64)| {
+            if requests == 0 {
+                0.0
+            } else {
+                violations as f64 / requests as f64 / (1.0 - target)
+            }
+        };
+        let long = burn_rate(attainment.totals(current, BUCKETS));
+        let short = burn_rate(attainment.totals(current, SHORT_BUCKETS));
+
+        let alerting = long >= self.alert_burn_rate && short >= self.alert_burn_rate;
+        if alerting && !attainment.alerting {
+            warn!(hostname, burn_rate = long, short_burn_rate = short, "Cold-start SLO burn rate alert");
+        } else if !alerting && attainment.alerting {
+            info!(hostname, burn_rate = long, short_burn_rate = short, "Cold-start SLO burn rate alert resolved");
+        }
+        attainment.alerting = alerting;
+        (long, short)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{BackendConfig, BackendDefaults};
+    use std::collections::HashMap;
+
+    fn slo() -> ColdStartSlo {
+        let mut strict = BackendConfig::local("node", 3001);
+        strict.cold_start_slo_ms = Some(100);
+        let configs = HashMap::from([("strict.local".to_string(), strict)]);
+        let process_manager = ProcessManager::new(configs, BackendDefaults::default(), String::new());
+        ColdStartSlo::new(
+            &ColdStartSloConfig {
+                enabled: true,
+                threshold_ms: 2000,
+                target: 0.9,
+                window_secs: 600,
+                alert_burn_rate: 2.0,
+            },
+            process_manager,
+        )
+    }
+
+    #[test]
+    fn test_attainment_and_overrides() {
+        let slo = slo();
+        let now = Instant::now();
+        for _ in 0..9 {
+            slo.record_at("app.local", Some(Duration::ZERO), now);
+        }
+        slo.record_at("app.local", Some(Duration::from_millis(2500)), now);
+        slo.record_at("strict.local", Some(Duration::from_millis(500)), now);
+
+        let report = slo.report_at(now);
+        let app = &report.backends[0];
+        assert_eq!((app.requests, app.violations), (10, 1));
+        assert!((app.attainment - 0.9).abs() < 1e-9);
+        assert!((app.burn_rate - 1.0).abs() < 1e-9);
+        assert!(!app.alerting);
+
+        let strict = &report.backends[1];
+        assert_eq!(strict.threshold_ms, 100);
+        assert_eq!(strict.violations, 1);
+    }
+
+    #[test]
+    fn test_alert_fires_and_clears() {
+        let slo = slo();
+        let now = Instant::now();
+        slo.record_at("app.local", Some(Duration::ZERO), now);
+        slo.record_at("app.local", None, now);
+        // Half the requests violate: 5x the budget
+        assert!(slo.report_at(now).backends[0].alerting);
+
+        // Fast requests after the short window clear the alert while the
+        // window still remembers the violation
+        let later = now + Duration::from_secs(60);
+        for _ in 0..3 {
+            slo.record_at("app.local", Some(Duration::ZERO), later);
+        }
+        let app = slo.report_at(later).backends[0].clone();
+        assert!(app.burn_rate >= 2.0);
+        assert_eq!(app.short_burn_rate, 0.0);
+        assert!(!app.alerting);
+
+        // Everything expires after the window
+        let app = slo.report_at(now + Duration::from_secs(700)).backends[0].clone();
+        assert_eq!((app.requests, app.attainment), (0, 1.0));
+    }
+}
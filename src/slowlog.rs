@@ -0,0 +1,332 @@
+//! Slow request log and tail-latency summary
+//!
+//! Requests taking longer than the threshold, measured from arrival until the
+//! last byte of the response body, are logged with a timing breakdown:
+//!
+//! - `queue`: from arrival until the spawn wait starts (routing, admission)
+//! - `spawn`: waiting for the backend to start and become ready
+//! - `connect`: opening a new backend connection (0 when one was reused)
+//! - `ttfb`: from sending the request until the response headers arrive
+//! - `transfer`: streaming the response body to the client
+//!
+//! Slow requests are kept for a window so the admin API can list the slowest
+//! endpoints per backend.
+
+use crate::config::SlowRequestConfig;
+use crate::proxy::{ProxyBody, ProxyResponse};
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use hyper::Method;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use http_body_util::BodyExt;
+use tracing::warn;
+
+/// Slow requests kept for the summary, regardless of the window
+const MAX_ENTRIES: usize = 10_000;
+
+/// Where a request spent its time before the response body
+///
+/// Set in the response extensions by the pipeline stages.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RequestTimings {
+    pub queue: Duration,
+    pub spawn: Duration,
+    pub connect: Duration,
+    pub ttfb: Duration,
+}
+
+/// A request that may turn out to be slow
+#[derive(Debug)]
+pub struct TrackedRequest {
+    pub request_id: String,
+    pub hostname: String,
+    pub method: Method,
+    pub path: String,
+    /// When the proxy received the request
+    pub received: Instant,
+}
+
+#[derive(Debug)]
+struct SlowRequest {
+    at: Instant,
+    hostname: String,
+    method: Method,
+    path: String,
+    total: Duration,
+}
+
+/// Slow requests of one endpoint within the window
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct SlowEndpoint {
+    pub method: String,
+    pub path: String,
+    pub count: u64,
+    pub avg_ms: u64,
+    pub max_ms: u64,
+}
+
+/// Slowest endpoints per backend, as returned by the admin API
+#[derive(Debug, Clone, Serialize)]
+pub struct SlowRequestSummary {
+    pub threshold_ms: u64,
+    pub window_secs: u64,
+    pub backends: BTreeMap<String, Vec<SlowEndpoint>>,
+}
+
+/// Logs slow requests and keeps them for the summary
+#[derive(Debug)]
+pub struct SlowRequestLog {
+    threshold: Duration,
+    window: Duration,
+    top: usize,
+    entries: Mutex<VecDeque<SlowRequest>>,
+}
+
+impl SlowRequestLog {
+    pub fn new(config: &SlowRequestConfig) -> Self {
+        Self {
+            threshold: Duration::from_millis(config.threshold_ms),
+            window: Duration::from_secs(config.window_secs),
+            top: config.top,
+            entries: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Record the request once the client has received the response body
+    ///
+    /// Requests whose body is not fully sent (e.g. the client went away) are
+    /// recorded when the body is dropped.
+    pub fn track(self: &Arc<Self>, request: TrackedRequest, mut response: ProxyResponse) -> ProxyResponse {
+        let timings = response.extensions_mut().remove::<RequestTimings>().unwrap_or_default();
+        let status = response.status().as_u16();
+        let headers_sent = Instant::now();
+        let log = Arc::clone(self);
+
+        response.map(|body| {
+            TimedBody {
+                inner: body,
+                on_done: Some(Box::new(move || {
+                    log.record(&request, status, timings, headers_sent.elapsed(), request.received.elapsed());
+                })),
+            }
+            .boxed()
+        })
+    }
+
+    /// Log the request and keep it for the summary if it is slow
+    pub fn record(
+        &self,
+        request: &TrackedRequest,
+        status: u16,
+        timings: RequestTimings,
+        transfer: Duration,
+        total: Duration,
+    ) {
+        if total < self.threshold {
+            return;
+        }
+        warn!(
+            request_id = request.request_id,
+            hostname = request.hostname,
+            method = %request.method,
+            path = request.path,
+            status,
+            total_ms = total.as_millis() as u64,
+            queue_ms = timings.queue.as_millis() as u64,
+            spawn_ms = timings.spawn.as_millis() as u64,
+            connect_ms = timings.connect.as_millis() as u64,
+            ttfb_ms = timings.ttfb.as_millis() as u64,
+            transfer_ms = transfer.as_millis() as u64,
+            "Slow request"
+        );
+
+        let now = Instant::now();
+        let mut entries = self.entries.lock();
+        self.prune(&mut entries, now);
+        if entries.len() == MAX_ENTRIES {
+            entries.pop_front();
+        }
+        entries.push_back(SlowRequest {
+            at: now,
+            hostname: request.hostname.clone(),
+            method: request.method.clone(),
+            path: request.path.clone(),
+            total,
+        });
+    }
+
+    fn prune(&self, entries: &mut VecDeque<SlowRequest>, now: Instant) {
+        while entries.front().is_some_and(|e| now.duration_since(e.at) >= self.window) {
+            entries.pop_front();
+        }
+    }
+
+    /// Slowest endpoints per backend within the window, by maximum latency
+    pub fn summary(&self) -> SlowRequestSummary {
+        let mut entries = self.entries.lock();
+        self.prune(&mut entries, Instant::now());
+
+        let mut endpoints: HashMap<(&str, &Method, &str), (u64, Duration, Duration)> = HashMap::new();
+        for entry in entries.iter() {
+            let stats = endpoints
+                .entry((&entry.hostname, &entry.method, &entry.path))
+                .or_default();
+            stats.0 += 1;
+            stats.1 += entry.total;
+            stats.2 = stats.2.max(entry.total);
+        }
+
+        let mut backends: BTreeMap<String, Vec<SlowEndpoint>> = BTreeMap::new();
+        for ((hostname, method, path), (count, total, max)) in endpoints {
+            backends.entry(hostname.to_string()).or_default().push(SlowEndpoint {
+                method: method.to_string(),
+                path: path.to_string(),
+                count,
+                avg_ms: (total / count as u32).as_millis() as u64,
+                max_ms: max.as_millis() as u64,
+            });
+        }
+        for list in backends.values_mut() {
+            list.sort_by(|a, b| b.max_ms.cmp(&a.max_ms).then_with(|| a.path.cmp(&b.path)));
+            list.truncate(self.top);
+        }
+
+        SlowRequestSummary {
+            threshold_ms: self.threshold.as_millis() as u64,
+            window_secs: self.window.as_secs(),
+            backends,
+        }
+    }
+}
+
+type OnDone = Box<dyn FnOnce() + Send + Sync>;
+
+/// Response body that runs a callback when it ends or is dropped
+struct TimedBody {
+    inner: ProxyBody,
+    on_done: Option<OnDone>,
+}
+
+impl TimedBody {
+    fn finish(&mut self) {
+        if let Some(on_done) = self.on_done.take() {
+            on_done();
+        }
+    }
+}
+
+impl Body for TimedBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, hyper::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+        if let Poll::Ready(None) = poll {
+            self.finish();
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for TimedBody {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::Full;
+    use hyper::Response;
+
+    fn slow_log(threshold_ms: u64) -> Arc<SlowRequestLog> {
+        Arc::new(SlowRequestLog::new(&SlowRequestConfig {
+            enabled: true,
+            threshold_ms,
+            window_secs: 3600,
+            top: 2,
+        }))
+    }
+
+    fn request(hostname: &str, path: &str) -> TrackedRequest {
+        TrackedRequest {
+            request_id: "req-1".to_string(),
+            hostname: hostname.to_string(),
+            method: Method::GET,
+            path: path.to_string(),
+            received: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn test_summary_lists_slowest_endpoints_per_backend() {
+        let log = slow_log(100);
+        let ms = Duration::from_millis;
+        let timings = RequestTimings::default();
+
+        log.record(&request("a.local", "/fast"), 200, timings, ms(0), ms(50));
+        log.record(&request("a.local", "/report"), 200, timings, ms(0), ms(300));
+        log.record(&request("a.local", "/report"), 200, timings, ms(0), ms(500));
+        log.record(&request("a.local", "/search"), 200, timings, ms(0), ms(200));
+        log.record(&request("a.local", "/export"), 200, timings, ms(0), ms(150));
+        log.record(&request("b.local", "/"), 502, timings, ms(0), ms(1000));
+
+        let summary = log.summary();
+        assert_eq!(summary.threshold_ms, 100);
+        let a = &summary.backends["a.local"];
+        assert_eq!(a.len(), 2);
+        assert_eq!(
+            a[0],
+            SlowEndpoint {
+                method: "GET".to_string(),
+                path: "/report".to_string(),
+                count: 2,
+                avg_ms: 400,
+                max_ms: 500,
+            }
+        );
+        assert_eq!(a[1].path, "/search");
+        assert_eq!(summary.backends["b.local"][0].max_ms, 1000);
+    }
+
+    #[tokio::test]
+    async fn test_track_records_after_body_is_sent() {
+        let log = slow_log(1);
+        let mut response: ProxyResponse = Response::new(
+            Full::new(Bytes::from_static(b"done")).map_err(|never| match never {}).boxed(),
+        );
+        response.extensions_mut().insert(RequestTimings {
+            ttfb: Duration::from_millis(5),
+            ..Default::default()
+        });
+
+        let mut tracked = request("a.local", "/download");
+        tracked.received -= Duration::from_millis(20);
+        let response = log.track(tracked, response);
+        assert!(response.extensions().get::<RequestTimings>().is_none());
+        assert!(log.summary().backends.is_empty());
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "done");
+        let summary = log.summary();
+        assert_eq!(summary.backends["a.local"][0].path, "/download");
+        assert!(summary.backends["a.local"][0].max_ms >= 20);
+    }
+}
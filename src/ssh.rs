@@ -0,0 +1,247 @@
+//! SSH tunnel backends
+//!
+//! A backend with `type = "ssh-tunnel"` is a service on a machine without
+//! public ingress, reached through a local forward of `ssh`: the proxy runs
+//! `ssh -N -L 127.0.0.1:{port}:{remote_host}:{remote_port}` when traffic
+//! arrives, so routing, health checks and idle shutdown work as for any other
+//! backend, and closes the tunnel again when the backend is stopped.
+//!
+//! While the backend runs, the tunnel is kept open: when `ssh` exits (network
+//! outage, server restart, keepalive timeout) it is started again after a
+//! delay that backs off up to [`MAX_RECONNECT_DELAY`]. Requests in the
+//! meantime fail to connect like they would to a crashed process.
+//!
+//! `ssh` runs non-interactively, so the server's host key must be in the
+//! known_hosts file and the key must not need a passphrase.
+
+use crate::config::{BackendConfig, SshTunnelConfig};
+use crate::logs::{self, BackendLogs, LogStream};
+use crate::process::Spawner;
+use dashmap::DashMap;
+use futures::future::{BoxFuture, FutureExt};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::process::{Child, Command};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// Delay before the first reconnect attempt
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Longest delay between reconnect attempts
+pub const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// A tunnel open this long counts as stable, resetting the reconnect delay
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+/// Opens, maintains and closes the tunnels of ssh-tunnel backends
+pub struct SshTunnelSpawner {
+    logs: Arc<BackendLogs>,
+    /// Task keeping each backend's tunnel open, by hostname
+    tunnels: DashMap<String, JoinHandle<()>>,
+}
+
+impl SshTunnelSpawner {
+    pub fn new(logs: Arc<BackendLogs>) -> Self {
+        Self {
+            logs,
+            tunnels: DashMap::new(),
+        }
+    }
+
+    async fn open(&self, hostname: &str, config: &BackendConfig) -> anyhow::Result<String> {
+        let tunnel = config
+            .ssh_tunnel
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("SSH tunnel backend requires an 'ssh_tunnel' table"))?;
+        let args = ssh_args(tunnel, config.port);
+        info!(hostname, host = %tunnel.host, port = config.port, "Opening SSH tunnel");
+
+        // A missing binary fails the start; later failures are retried
+        let child = connect(hostname, &tunnel.binary, &args, &self.logs)?;
+        let maintain = maintain(child, hostname.to_string(), tunnel.binary.clone(), args, Arc::clone(&self.logs));
+        if let Some(previous) = self.tunnels.insert(hostname.to_string(), tokio::spawn(maintain)) {
+            previous.abort();
+        }
+        Ok(hostname.to_string())
+    }
+
+    async fn close(&self, hostname: &str, id: &str) {
+        let Some((_, task)) = self.tunnels.remove(id) else {
+            debug!(hostname, "SSH tunnel not open");
+            return;
+        };
+        // Dropping the task's child kills ssh, which closes the connection
+        task.abort();
+        let _ = task.await;
+        info!(hostname, "Closed SSH tunnel");
+    }
+}
+
+impl Spawner for SshTunnelSpawner {
+    fn start<'a>(
+        &'a self,
+        hostname: &'a str,
+        config: &'a BackendConfig,
+        _env: &'a [(String, String)],
+    ) -> BoxFuture<'a, anyhow::Result<String>> {
+        self.open(hostname, config).boxed()
+    }
+
+    fn stop<'a>(&'a self, hostname: &'a str, id: &'a str, _grace_period: Duration) -> BoxFuture<'a, ()> {
+        self.close(hostname, id).boxed()
+    }
+}
+
+/// Run ssh, keeping its diagnostics with the backend's logs
+fn connect(hostname: &str, binary: &str, args: &[String], logs: &Arc<BackendLogs>) -> anyhow::Result<Child> {
+    let mut child = Command::new(binary)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("Failed to run '{}': {}", binary, e))?;
+    if let Some(stderr) = child.stderr.take() {
+        let (hostname, logs) = (hostname.to_string(), Arc::clone(logs));
+        tokio::spawn(async move { logs::capture(stderr, hostname, LogStream::Stderr, &logs).await });
+    }
+    Ok(child)
+}
+
+/// Restart ssh whenever it exits, until the task is aborted
+async fn maintain(mut child: Child, hostname: String, binary: String, args: Vec<String>, logs: Arc<BackendLogs>) {
+    let mut delay = RECONNECT_DELAY;
+    loop {
+        let opened = Instant::now();
+        let status = child.wait().await;
+        if opened.elapsed() >= STABLE_AFTER {
+            delay = RECONNECT_DELAY;
+        }
+        match status {
+            Ok(status) => warn!(hostname, %status, retry_in_ms = delay.as_millis() as u64, "SSH tunnel closed"),
+            Err(e) => warn!(hostname, error = %e, retry_in_ms = delay.as_millis() as u64, "SSH tunnel failed"),
+        }
+
+        loop {
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+            match connect(&hostname, &binary, &args, &logs) {
+                Ok(reconnected) => {
+                    info!(hostname, "Reopened SSH tunnel");
+                    child = reconnected;
+                    break;
+                }
+                Err(e) => warn!(hostname, error = %e, "Failed to reopen SSH tunnel"),
+            }
+        }
+    }
+}
+
+/// Arguments for an ssh local forward from `127.0.0.1:{port}` to the remote service
+fn ssh_args(tunnel: &SshTunnelConfig, port: u16) -> Vec<String> {
+    let remote_host = if tunnel.remote_host.contains(':') {
+        format!("[{}]", tunnel.remote_host)
+    } else {
+        tunnel.remote_host.clone()
+    };
+    let remote_port = tunnel.remote_port.unwrap_or(port);
+
+    let mut args: Vec<String> = [
+        "-N",
+        // Exit (and be restarted) instead of running without the forward
+        "-o",
+        "ExitOnForwardFailure=yes",
+        "-o",
+        "BatchMode=yes",
+        // Notice a dead connection within 45 seconds
+        "-o",
+        "ServerAliveInterval=15",
+        "-o",
+        "ServerAliveCountMax=3",
+    ]
+    .map(String::from)
+    .to_vec();
+    args.push("-L".to_string());
+    args.push(format!("127.0.0.1:{}:{}:{}", port, remote_host, remote_port));
+    args.push("-p".to_string());
+    args.push(tunnel.port.to_string());
+    if let Some(key) = &tunnel.identity_file {
+        args.extend(["-i".to_string(), key.clone(), "-o".to_string(), "IdentitiesOnly=yes".to_string()]);
+    }
+    if let Some(known_hosts) = &tunnel.known_hosts_file {
+        args.extend(["-o".to_string(), format!("UserKnownHostsFile={}", known_hosts)]);
+    }
+    if let Some(user) = &tunnel.user {
+        args.extend(["-l".to_string(), user.clone()]);
+    }
+    args.extend(["--".to_string(), tunnel.host.clone()]);
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tunnel() -> SshTunnelConfig {
+        SshTunnelConfig {
+            host: "bastion.internal".to_string(),
+            port: 2222,
+            user: Some("deploy".to_string()),
+            identity_file: Some("/keys/id_ed25519".to_string()),
+            known_hosts_file: None,
+            remote_host: "127.0.0.1".to_string(),
+            remote_port: Some(8080),
+            binary: "ssh".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_ssh_args() {
+        let args = ssh_args(&tunnel(), 9000).join(" ");
+        assert!(args.starts_with("-N -o ExitOnForwardFailure=yes -o BatchMode=yes"));
+        assert!(args.contains("-L 127.0.0.1:9000:127.0.0.1:8080 -p 2222"));
+        assert!(args.contains("-i /keys/id_ed25519 -o IdentitiesOnly=yes"));
+        assert!(args.ends_with("-l deploy -- bastion.internal"));
+        assert!(!args.contains("UserKnownHostsFile"));
+
+        let mut ipv6 = tunnel();
+        ipv6.remote_host = "::1".to_string();
+        ipv6.remote_port = None;
+        assert!(ssh_args(&ipv6, 9000).contains(&"127.0.0.1:9000:[::1]:9000".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_reconnects_until_closed() {
+        let dir = std::env::temp_dir().join(format!("spawngate-ssh-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let marker = dir.join("runs");
+        let script = dir.join("ssh");
+        std::fs::write(&script, format!("#!/bin/sh\necho run >> {}\nexit 1\n", marker.display())).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let spawner = SshTunnelSpawner::new(Arc::new(BackendLogs::new()));
+        let mut config = BackendConfig::local("unused", 9000);
+        config.ssh_tunnel = Some(SshTunnelConfig {
+            binary: script.display().to_string(),
+            ..tunnel()
+        });
+        let id = spawner.open("legacy.local", &config).await.unwrap();
+
+        // First run, then a reconnect after RECONNECT_DELAY
+        tokio::time::sleep(RECONNECT_DELAY + Duration::from_millis(500)).await;
+        spawner.close("legacy.local", &id).await;
+        let runs = std::fs::read_to_string(&marker).unwrap().lines().count();
+        assert_eq!(runs, 2);
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(std::fs::read_to_string(&marker).unwrap().lines().count(), runs);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,164 @@
+//! Restartable long-running subsystems
+//!
+//! Subsystems such as the ACME manager or the certificate watcher run as
+//! tasks that are started from a factory, so the admin API can restart one
+//! that got stuck (`POST /subsystems/{name}/restart`, or `POST /acme/restart`)
+//! without restarting the proxy and dropping its connections. A restart
+//! aborts the running task, if any, and starts a fresh one; a task that has
+//! ended, e.g. after an error, is started again the same way.
+
+use crate::registry::DebugRegistry;
+use dashmap::DashMap;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::task::JoinHandle;
+use tracing::info;
+
+type MakeTask = Box<dyn Fn() -> BoxFuture<'static, ()> + Send + Sync>;
+
+struct Subsystem {
+    /// Name listed in the debug registry
+    task_name: String,
+    make: MakeTask,
+    task: Mutex<(JoinHandle<()>, Instant)>,
+    restarts: u64,
+}
+
+/// State of a subsystem, as returned by the admin API
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct SubsystemStatus {
+    pub name: String,
+    /// False once the task has ended, e.g. after an error
+    pub running: bool,
+    pub restarts: u64,
+    /// Milliseconds since the task was last started
+    pub uptime_ms: u64,
+}
+
+/// Runs subsystems and restarts them on request
+pub struct Supervisor {
+    registry: Arc<DebugRegistry>,
+    subsystems: DashMap<String, Subsystem>,
+}
+
+impl Supervisor {
+    /// Spawn the subsystems' tasks through `registry`
+    pub fn new(registry: Arc<DebugRegistry>) -> Self {
+        Self {
+            registry,
+            subsystems: DashMap::new(),
+        }
+    }
+
+    /// Start a subsystem called `name` from `make`, which is called again on
+    /// every restart
+    pub fn spawn<F, Fut>(&self, name: &str, task_name: &str, make: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let make: MakeTask = Box::new(move || make().boxed());
+        let task = self.registry.spawn(task_name, make());
+        self.subsystems.insert(
+            name.to_string(),
+            Subsystem {
+                task_name: task_name.to_string(),
+                make,
+                task: Mutex::new((task, Instant::now())),
+                restarts: 0,
+            },
+        );
+    }
+
+    /// Abort the subsystem's task and start a new one
+    ///
+    /// Returns `None` for unknown subsystems.
+    pub fn restart(&self, name: &str) -> Option<SubsystemStatus> {
+        let mut subsystem = self.subsystems.get_mut(name)?;
+        let task = self.registry.spawn(subsystem.task_name.clone(), (subsystem.make)());
+        let (old, _) = std::mem::replace(&mut *subsystem.task.lock(), (task, Instant::now()));
+        old.abort();
+        subsystem.restarts += 1;
+        info!(subsystem = name, restarts = subsystem.restarts, "Subsystem restarted");
+        Some(status(name, &subsystem))
+    }
+
+    /// Every subsystem, by name
+    pub fn list(&self) -> Vec<SubsystemStatus> {
+        let mut list: Vec<SubsystemStatus> = self
+            .subsystems
+            .iter()
+            .map(|entry| status(entry.key(), entry.value()))
+            .collect();
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        list
+    }
+
+    /// Stop the subsystem's task without waiting for it to end
+    pub fn abort(&self, name: &str) {
+        if let Some((_, subsystem)) = self.subsystems.remove(name) {
+            subsystem.task.into_inner().0.abort();
+        }
+    }
+
+    /// Wait for the subsystem's task to end, e.g. after shutdown
+    pub async fn join(&self, name: &str) {
+        if let Some((_, subsystem)) = self.subsystems.remove(name) {
+            let _ = subsystem.task.into_inner().0.await;
+        }
+    }
+}
+
+fn status(name: &str, subsystem: &Subsystem) -> SubsystemStatus {
+    let task = subsystem.task.lock();
+    SubsystemStatus {
+        name: name.to_string(),
+        running: !task.0.is_finished(),
+        restarts: subsystem.restarts,
+        uptime_ms: task.1.elapsed().as_millis() as u64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_restart_replaces_task() {
+        let registry = Arc::new(DebugRegistry::new());
+        let supervisor = Supervisor::new(Arc::clone(&registry));
+        let starts = Arc::new(AtomicU64::new(0));
+        let counted = Arc::clone(&starts);
+        supervisor.spawn("stuck", "stuck loop", move || {
+            counted.fetch_add(1, Ordering::SeqCst);
+            std::future::pending()
+        });
+        supervisor.spawn("failed", "failing task", || async {});
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let list = supervisor.list();
+        assert_eq!(list.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(), vec!["failed", "stuck"]);
+        assert!(!list[0].running);
+        assert!(list[1].running);
+
+        let status = supervisor.restart("stuck").unwrap();
+        assert!(status.running);
+        assert_eq!(status.restarts, 1);
+        assert_eq!(starts.load(Ordering::SeqCst), 2);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        // The old task was aborted, so only the new one is registered
+        assert_eq!(registry.tasks().tasks.iter().filter(|t| t.name == "stuck loop").count(), 1);
+
+        assert!(supervisor.restart("missing").is_none());
+        supervisor.abort("stuck");
+        supervisor.join("failed").await;
+        assert!(supervisor.list().is_empty());
+    }
+}
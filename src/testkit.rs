@@ -0,0 +1,408 @@
+//! In-process proxy for integration tests (`testkit` feature)
+//!
+//! [`TestProxy`] runs a proxy and admin API on loopback ports, configured
+//! from a TOML file written to a temporary directory. Backends can be the
+//! bundled mock server, which answers `/health`, `/echo`, `/headers`,
+//! `/slow` and WebSocket upgrades on `/ws`:
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! use spawngate::testkit::TestProxy;
+//!
+//! let proxy = TestProxy::builder()
+//!     .mock_backend("app.local")?
+//!     .start()
+//!     .await?;
+//! let response = proxy.get("app.local", "/echo").await?;
+//! assert_eq!(response.status, 200);
+//! proxy.shutdown().await;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Call [`TestProxy::shutdown`] at the end of each test: it stops the
+//! backend processes, which would otherwise outlive the test.
+
+use crate::admin::AdminServer;
+use crate::config::Config;
+use crate::process::{ProcessManager, ReloadResult};
+use crate::proxy::ProxyServer;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::header::{HeaderMap, HeaderValue, AUTHORIZATION, HOST};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// Admin API token used by test proxies
+pub const ADMIN_TOKEN: &str = "testkit-token";
+
+/// Environment variable overriding the mock server binary
+pub const MOCK_SERVER_ENV: &str = "SPAWNGATE_MOCK_SERVER";
+
+/// Path to the bundled mock server, building it on first use
+///
+/// The binary can be provided instead through `SPAWNGATE_MOCK_SERVER`. One
+/// built in the mock server's own directory, as for spawngate's integration
+/// tests, is used as is. Otherwise it is built into `spawngate-mock-server`
+/// under `CARGO_TARGET_DIR` or the test binary's target directory, leaving
+/// the package sources untouched.
+pub fn mock_server() -> anyhow::Result<PathBuf> {
+    if let Some(path) = std::env::var_os(MOCK_SERVER_ENV) {
+        return Ok(PathBuf::from(path));
+    }
+    let prebuilt = prebuilt_mock_server();
+    if prebuilt.is_file() {
+        return Ok(prebuilt);
+    }
+
+    let target_dir = std::env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .or_else(|| Some(std::env::current_exe().ok()?.ancestors().nth(3)?.to_path_buf()))
+        .unwrap_or_else(std::env::temp_dir)
+        .join("spawngate-mock-server");
+    let binary = target_dir.join("release").join(mock_server_file_name());
+
+    // Tests run concurrently; only one of them builds
+    static BUILD: Mutex<()> = Mutex::new(());
+    let _guard = BUILD.lock().unwrap_or_else(|e| e.into_inner());
+    if !binary.is_file() {
+        let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+        let status = Command::new(cargo)
+            .args(["build", "--release", "--manifest-path"])
+            .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/mock_server/Cargo.toml"))
+            .arg("--target-dir")
+            .arg(&target_dir)
+            .status()
+            .map_err(|e| anyhow::anyhow!("Failed to run cargo to build the mock server: {}", e))?;
+        if !status.success() {
+            anyhow::bail!("Building the mock server failed: {}", status);
+        }
+    }
+    Ok(binary)
+}
+
+/// The mock server as built by `cargo build --release` in its own directory
+fn prebuilt_mock_server() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/mock_server/target/release")
+        .join(mock_server_file_name())
+}
+
+fn mock_server_file_name() -> String {
+    format!("mock-server{}", std::env::consts::EXE_SUFFIX)
+}
+
+/// A free port on the loopback interface
+pub fn free_port() -> anyhow::Result<u16> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    Ok(listener.local_addr()?.port())
+}
+
+/// A response received from the proxy or admin API
+#[derive(Debug, Clone)]
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl TestResponse {
+    /// Body as text (lossy for invalid UTF-8)
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    /// Body parsed as JSON
+    pub fn json(&self) -> anyhow::Result<serde_json::Value> {
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+
+    /// Value of the `X-Proxy-Error` header, for responses generated by the proxy
+    pub fn proxy_error(&self) -> Option<&str> {
+        self.headers.get("x-proxy-error").and_then(|v| v.to_str().ok())
+    }
+}
+
+type ServerHook = Box<dyn FnOnce(ProxyServer) -> ProxyServer + Send>;
+
+/// Configures a [`TestProxy`]
+pub struct TestProxyBuilder {
+    config: String,
+    customize: Option<ServerHook>,
+}
+
+impl TestProxyBuilder {
+    /// Append TOML to the config file, e.g. `[defaults]` or `[backends."..."]` tables
+    ///
+    /// `[server]` listener settings are ignored: the test proxy always
+    /// listens on free loopback ports.
+    pub fn config(mut self, toml: &str) -> Self {
+        self.config.push_str(toml);
+        self.config.push('\n');
+        self
+    }
+
+    /// Add a backend running the mock server on a free port
+    ///
+    /// Timeouts are short so tests don't wait on idle backends.
+    pub fn mock_backend(self, hostname: &str) -> anyhow::Result<Self> {
+        let command = mock_server()?;
+        let toml = format!(
+            r#"
+[backends."{hostname}"]
+command = "{command}"
+port = {port}
+health_path = "/health"
+idle_timeout_secs = 5
+startup_timeout_secs = 10
+health_check_interval_ms = 50
+shutdown_grace_period_secs = 2
+drain_timeout_secs = 5
+"#,
+            hostname = hostname,
+            command = command.to_string_lossy().replace('\\', "\\\\").replace('"', "\\\""),
+            port = free_port()?,
+        );
+        Ok(self.config(&toml))
+    }
+
+    /// Adjust the proxy server before it starts, e.g. to replace pipeline stages
+    pub fn with_proxy<F>(mut self, customize: F) -> Self
+    where
+        F: FnOnce(ProxyServer) -> ProxyServer + Send + 'static,
+    {
+        self.customize = Some(Box::new(customize));
+        self
+    }
+
+    /// Write the config file and start the proxy and admin API
+    pub async fn start(self) -> anyhow::Result<TestProxy> {
+        let dir = std::env::temp_dir().join(format!("spawngate-testkit-{}", uuid::Uuid::new_v4().simple()));
+        std::fs::create_dir_all(&dir)?;
+        let config_path = dir.join("spawngate.toml");
+        std::fs::write(&config_path, &self.config)?;
+        let config = Config::load(&config_path)?;
+
+        let admin_listener = TcpListener::bind("127.0.0.1:0").await?;
+        let proxy_listener = TcpListener::bind("127.0.0.1:0").await?;
+        let admin_addr = admin_listener.local_addr()?;
+        let proxy_addr = proxy_listener.local_addr()?;
+
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let process_manager = ProcessManager::new(config.backends, config.defaults, format!("http://{}", admin_addr));
+        process_manager.set_public_endpoint("http", proxy_addr.port());
+
+        let admin_server = AdminServer::new(
+            admin_addr,
+            Arc::clone(&process_manager),
+            shutdown_rx.clone(),
+            ADMIN_TOKEN.to_string(),
+        );
+        let mut proxy_server = ProxyServer::new(
+            proxy_addr,
+            Arc::clone(&process_manager),
+            process_manager.shared_defaults(),
+            shutdown_rx,
+        );
+        if let Some(customize) = self.customize {
+            proxy_server = customize(proxy_server);
+        }
+
+        let tasks = vec![
+            tokio::spawn(async move {
+                let _ = admin_server.serve(admin_listener).await;
+            }),
+            tokio::spawn(async move {
+                let _ = proxy_server.serve(proxy_listener).await;
+            }),
+        ];
+
+        Ok(TestProxy {
+            proxy_addr,
+            admin_addr,
+            dir,
+            config_path,
+            process_manager,
+            client: Client::builder(TokioExecutor::new()).build_http(),
+            shutdown_tx,
+            tasks,
+        })
+    }
+}
+
+/// A proxy and admin API running in the test's runtime
+pub struct TestProxy {
+    proxy_addr: SocketAddr,
+    admin_addr: SocketAddr,
+    dir: PathBuf,
+    config_path: PathBuf,
+    process_manager: Arc<ProcessManager>,
+    client: Client<HttpConnector, Full<Bytes>>,
+    shutdown_tx: watch::Sender<bool>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl TestProxy {
+    pub fn builder() -> TestProxyBuilder {
+        TestProxyBuilder {
+            config: String::new(),
+            customize: None,
+        }
+    }
+
+    pub fn proxy_addr(&self) -> SocketAddr {
+        self.proxy_addr
+    }
+
+    pub fn admin_addr(&self) -> SocketAddr {
+        self.admin_addr
+    }
+
+    /// The config file the proxy was started from
+    pub fn config_path(&self) -> &Path {
+        &self.config_path
+    }
+
+    pub fn process_manager(&self) -> &Arc<ProcessManager> {
+        &self.process_manager
+    }
+
+    /// Send a request through the proxy
+    ///
+    /// A relative URI is sent to the proxy's address; set the `Host` header
+    /// to pick the backend.
+    pub async fn send(&self, req: Request<Full<Bytes>>) -> anyhow::Result<TestResponse> {
+        self.send_to(self.proxy_addr, req).await
+    }
+
+    /// GET `path` from the backend serving `host`
+    pub async fn get(&self, host: &str, path: &str) -> anyhow::Result<TestResponse> {
+        let req = Request::builder()
+            .uri(path)
+            .header(HOST, host)
+            .body(Full::default())?;
+        self.send(req).await
+    }
+
+    /// Call the admin API with the test token
+    pub async fn admin(&self, method: Method, path: &str) -> anyhow::Result<TestResponse> {
+        let req = Request::builder()
+            .method(method)
+            .uri(path)
+            .header(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", ADMIN_TOKEN))?)
+            .body(Full::default())?;
+        self.send_to(self.admin_addr, req).await
+    }
+
+    async fn send_to(&self, addr: SocketAddr, mut req: Request<Full<Bytes>>) -> anyhow::Result<TestResponse> {
+        if req.uri().authority().is_none() {
+            let path = req.uri().path_and_query().map_or("/", |p| p.as_str());
+            *req.uri_mut() = format!("http://{}{}", addr, path).parse()?;
+        }
+        let response = self.client.request(req).await?;
+        let (parts, body) = response.into_parts();
+        Ok(TestResponse {
+            status: parts.status,
+            headers: parts.headers,
+            body: body.collect().await?.to_bytes(),
+        })
+    }
+
+    /// Replace the config file with `toml` and reload backends and defaults
+    pub async fn reload(&self, toml: &str) -> anyhow::Result<ReloadResult> {
+        std::fs::write(&self.config_path, toml)?;
+        self.process_manager.reload_config(&self.config_path).await
+    }
+
+    /// Stop the backends, the proxy and the admin API, and remove the config
+    pub async fn shutdown(self) {
+        self.process_manager.stop_all().await;
+        let _ = self.shutdown_tx.send(true);
+        for task in self.tasks {
+            let _ = task.await;
+        }
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::{Pipeline, RequestContext, Router};
+    use hyper::http::request::Parts;
+
+    /// Routes every request to one backend, whatever its Host header
+    struct FixedRouter(String);
+
+    impl Router for FixedRouter {
+        fn route(&self, _ctx: &RequestContext, _parts: &Parts) -> Option<String> {
+            Some(self.0.clone())
+        }
+    }
+
+    /// Tests don't build the mock server themselves, like the integration tests
+    fn mock_server_built() -> bool {
+        std::env::var_os(MOCK_SERVER_ENV).is_some_and(|path| Path::new(&path).is_file())
+            || prebuilt_mock_server().is_file()
+    }
+
+    #[tokio::test]
+    async fn test_proxy_with_mock_backend() {
+        if !mock_server_built() {
+            eprintln!("Skipping test: mock server not built");
+            return;
+        }
+
+        let proxy = TestProxy::builder()
+            .config("[defaults]\nrequest_timeout_secs = 10")
+            .mock_backend("app.local")
+            .unwrap()
+            .start()
+            .await
+            .unwrap();
+
+        let response = proxy.get("app.local", "/echo").await.unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        let response = proxy.get("missing.local", "/").await.unwrap();
+        assert_eq!(response.proxy_error(), Some("UNKNOWN_HOST"));
+
+        let response = proxy.admin(Method::GET, "/backends").await.unwrap();
+        assert_eq!(response.json().unwrap()["backends"][0]["state"], "ready");
+
+        let result = proxy.reload("").await.unwrap();
+        assert_eq!(result.removed, ["app.local"]);
+        proxy.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_customized_pipeline() {
+        if !mock_server_built() {
+            eprintln!("Skipping test: mock server not built");
+            return;
+        }
+
+        let proxy = TestProxy::builder()
+            .mock_backend("app.local")
+            .unwrap()
+            .with_proxy(|server| {
+                let pipeline: Pipeline = server.pipeline().clone();
+                server.with_pipeline(pipeline.with_router(Arc::new(FixedRouter("app.local".to_string()))))
+            })
+            .start()
+            .await
+            .unwrap();
+
+        let response = proxy.get("anything.local", "/echo").await.unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        proxy.shutdown().await;
+    }
+}
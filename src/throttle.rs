@@ -0,0 +1,204 @@
+//! Per-client limiter for requests that would cold-start a backend
+//!
+//! Crawlers and vulnerability scanners tend to hit every hostname they know
+//! about, waking idle backends only for them to time out again. Requests to
+//! running backends are never counted; only requests that would trigger a
+//! spawn consume a client's budget.
+
+use crate::config::ColdStartThrottleConfig;
+use dashmap::DashMap;
+use serde::Serialize;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Tracked clients above which expired entries are pruned on insert
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Outcome of a throttle check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThrottleDecision {
+    /// The client may trigger a spawn
+    Allow,
+    /// The client is banned for this much longer
+    Banned(Duration),
+}
+
+/// Counters exposed through the admin API
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ThrottleStats {
+    /// Spawn-triggering requests let through
+    pub allowed: u64,
+    /// Spawn-triggering requests rejected
+    pub throttled: u64,
+    /// Bans issued since startup
+    pub bans: u64,
+    /// Clients currently banned
+    pub banned_clients: usize,
+}
+
+#[derive(Debug)]
+struct ClientWindow {
+    started: Instant,
+    count: u32,
+    banned_until: Option<Instant>,
+}
+
+/// Fixed-window limiter keyed by client IP
+#[derive(Debug)]
+pub struct ColdStartThrottle {
+    burst: u32,
+    window: Duration,
+    ban: Duration,
+    clients: DashMap<IpAddr, ClientWindow>,
+    allowed: AtomicU64,
+    throttled: AtomicU64,
+    bans: AtomicU64,
+}
+
+impl ColdStartThrottle {
+    pub fn new(config: &ColdStartThrottleConfig) -> Self {
+        Self {
+            burst: config.burst,
+            window: Duration::from_secs(config.window_secs),
+            ban: Duration::from_secs(config.ban_secs),
+            clients: DashMap::new(),
+            allowed: AtomicU64::new(0),
+            throttled: AtomicU64::new(0),
+            bans: AtomicU64::new(0),
+        }
+    }
+
+    /// Record a spawn-triggering request from `ip` and decide whether to let it through
+    pub fn check(&self, ip: IpAddr) -> ThrottleDecision {
+        self.check_at(ip, Instant::now())
+    }
+
+    fn check_at(&self, ip: IpAddr, now: Instant) -> ThrottleDecision {
+        if self.clients.len() > PRUNE_THRESHOLD {
+            self.prune(now);
+        }
+
+        let mut entry = self.clients.entry(ip).or_insert_with(|| ClientWindow {
+            started: now,
+            count: 0,
+            banned_until: None,
+        });
+
+        if let Some(until) = entry.banned_until {
+            if now < until {
+                self.throttled.fetch_add(1, Ordering::Relaxed);
+                return ThrottleDecision::Banned(until - now);
+            }
+            entry.banned_until = None;
+            entry.started = now;
+            entry.count = 0;
+        }
+
+        if now.duration_since(entry.started) >= self.window {
+            entry.started = now;
+            entry.count = 0;
+        }
+
+        entry.count += 1;
+        if entry.count > self.burst {
+            entry.banned_until = Some(now + self.ban);
+            self.bans.fetch_add(1, Ordering::Relaxed);
+            self.throttled.fetch_add(1, Ordering::Relaxed);
+            warn!(client = %ip, ban_secs = self.ban.as_secs(), "Client exceeded cold-start burst, banning");
+            return ThrottleDecision::Banned(self.ban);
+        }
+
+        self.allowed.fetch_add(1, Ordering::Relaxed);
+        ThrottleDecision::Allow
+    }
+
+    /// Drop clients whose window and ban have both expired
+    fn prune(&self, now: Instant) {
+        self.clients.retain(|_, client| {
+            client.banned_until.is_some_and(|until| now < until)
+                || now.duration_since(client.started) < self.window
+        });
+    }
+
+    pub fn stats(&self) -> ThrottleStats {
+        let now = Instant::now();
+        ThrottleStats {
+            allowed: self.allowed.load(Ordering::Relaxed),
+            throttled: self.throttled.load(Ordering::Relaxed),
+            bans: self.bans.load(Ordering::Relaxed),
+            banned_clients: self
+                .clients
+                .iter()
+                .filter(|c| c.banned_until.is_some_and(|until| now < until))
+                .count(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle(burst: u32) -> ColdStartThrottle {
+        ColdStartThrottle::new(&ColdStartThrottleConfig {
+            enabled: true,
+            burst,
+            window_secs: 60,
+            ban_secs: 300,
+        })
+    }
+
+    #[test]
+    fn test_burst_then_ban() {
+        let throttle = throttle(2);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+        let now = Instant::now();
+
+        assert_eq!(throttle.check_at(ip, now), ThrottleDecision::Allow);
+        assert_eq!(throttle.check_at(ip, now), ThrottleDecision::Allow);
+        assert_eq!(throttle.check_at(ip, now), ThrottleDecision::Banned(Duration::from_secs(300)));
+        assert_eq!(
+            throttle.check_at(ip, now + Duration::from_secs(100)),
+            ThrottleDecision::Banned(Duration::from_secs(200))
+        );
+
+        // Other clients are unaffected
+        assert_eq!(throttle.check_at(other, now), ThrottleDecision::Allow);
+
+        // Ban expires with a fresh window
+        assert_eq!(throttle.check_at(ip, now + Duration::from_secs(301)), ThrottleDecision::Allow);
+
+        let stats = throttle.stats();
+        assert_eq!(stats.allowed, 4);
+        assert_eq!(stats.throttled, 2);
+        assert_eq!(stats.bans, 1);
+    }
+
+    #[test]
+    fn test_window_resets() {
+        let throttle = throttle(1);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let now = Instant::now();
+
+        assert_eq!(throttle.check_at(ip, now), ThrottleDecision::Allow);
+        assert_eq!(throttle.check_at(ip, now + Duration::from_secs(60)), ThrottleDecision::Allow);
+        assert_eq!(throttle.stats().banned_clients, 0);
+    }
+
+    #[test]
+    fn test_prune_keeps_active_clients() {
+        let throttle = throttle(0);
+        let now = Instant::now();
+        let banned: IpAddr = "10.0.0.1".parse().unwrap();
+        throttle.check_at(banned, now);
+
+        throttle.prune(now + Duration::from_secs(120));
+        assert_eq!(throttle.clients.len(), 1);
+
+        throttle.prune(now + Duration::from_secs(301));
+        assert!(throttle.clients.is_empty());
+    }
+}
@@ -0,0 +1,351 @@
+//! Stateless TLS session tickets with persistent, shareable keys
+//!
+//! rustls' built-in ticketer keeps its keys in memory, so every restart
+//! invalidates all outstanding tickets and each node of a cluster issues
+//! tickets the others can't decrypt. A key file fixes both: every node
+//! loading the same file can resume sessions started on any other.
+//!
+//! The file holds one base64-encoded 32-byte key per line (`#` comments
+//! allowed). The first key encrypts new tickets; all keys decrypt. Rotate by
+//! adding a new key at the top and dropping the oldest; changes are picked
+//! up without a restart. Without a key file, keys are random per process
+//! and rotated every ticket lifetime.
+
+use base64::Engine;
+use parking_lot::{Mutex, RwLock};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use rustls::server::ProducesTickets;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, warn};
+
+/// Length of a ticket key in bytes
+pub const KEY_LEN: usize = 32;
+
+/// Length of the key identifier prefixed to each ticket
+const KEY_NAME_LEN: usize = 16;
+
+/// How often the key file is checked for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+struct TicketKey {
+    name: [u8; KEY_NAME_LEN],
+    key: LessSafeKey,
+}
+
+impl TicketKey {
+    fn new(secret: &[u8; KEY_LEN]) -> Self {
+        let digest = ring::digest::digest(&ring::digest::SHA256, secret);
+        let mut name = [0u8; KEY_NAME_LEN];
+        name.copy_from_slice(&digest.as_ref()[..KEY_NAME_LEN]);
+        let key = UnboundKey::new(&AES_256_GCM, secret).expect("32-byte AES-256-GCM key");
+        Self {
+            name,
+            key: LessSafeKey::new(key),
+        }
+    }
+}
+
+enum KeySource {
+    /// Keys read from a file, re-read when its modification time changes
+    File {
+        path: PathBuf,
+        modified: Option<SystemTime>,
+        checked: Instant,
+    },
+    /// Random in-memory keys, rotated every `lifetime`
+    Random { created: Instant },
+}
+
+/// Ticket encrypter backed by a key file or rotating random keys
+pub struct TicketKeyring {
+    lifetime: u32,
+    /// Current key first; later keys only decrypt
+    keys: RwLock<Vec<TicketKey>>,
+    source: Mutex<KeySource>,
+    rng: SystemRandom,
+}
+
+impl std::fmt::Debug for TicketKeyring {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TicketKeyring")
+            .field("lifetime", &self.lifetime)
+            .field("keys", &self.keys.read().len())
+            .finish()
+    }
+}
+
+impl TicketKeyring {
+    /// Keyring with random keys rotated every `lifetime` seconds
+    pub fn random(lifetime: u32) -> anyhow::Result<Self> {
+        let rng = SystemRandom::new();
+        let key = TicketKey::new(&random_key(&rng)?);
+        Ok(Self {
+            lifetime,
+            keys: RwLock::new(vec![key]),
+            source: Mutex::new(KeySource::Random { created: Instant::now() }),
+            rng,
+        })
+    }
+
+    /// Keyring loading keys from `path`, creating the file with one random key if missing
+    pub fn from_file(path: &Path, lifetime: u32) -> anyhow::Result<Self> {
+        let rng = SystemRandom::new();
+        if !path.exists() {
+            write_key_file(path, &random_key(&rng)?)?;
+            info!(path = %path.display(), "Created TLS session ticket key file");
+        }
+        let keys = read_key_file(path)?;
+        info!(path = %path.display(), keys = keys.len(), "Loaded TLS session ticket keys");
+        Ok(Self {
+            lifetime,
+            keys: RwLock::new(keys),
+            source: Mutex::new(KeySource::File {
+                path: path.to_path_buf(),
+                modified: modified(path),
+                checked: Instant::now(),
+            }),
+            rng,
+        })
+    }
+
+    /// Number of keys currently accepted for decryption
+    pub fn key_count(&self) -> usize {
+        self.keys.read().len()
+    }
+
+    /// Reload the key file or rotate random keys when due
+    fn refresh(&self) {
+        let Some(mut source) = self.source.try_lock() else {
+            return;
+        };
+        match &mut *source {
+            KeySource::File { path, modified: last, checked } => {
+                if checked.elapsed() < RELOAD_INTERVAL {
+                    return;
+                }
+                *checked = Instant::now();
+                let current = modified(path);
+                if current == *last {
+                    return;
+                }
+                match read_key_file(path) {
+                    Ok(keys) => {
+                        info!(path = %path.display(), keys = keys.len(), "Reloaded TLS session ticket keys");
+                        *self.keys.write() = keys;
+                        *last = current;
+                    }
+                    Err(e) => {
+                        warn!(path = %path.display(), error = %e, "Invalid ticket key file, keeping previous keys");
+                        *last = current;
+                    }
+                }
+            }
+            KeySource::Random { created } => {
+                if created.elapsed() < Duration::from_secs(self.lifetime as u64) {
+                    return;
+                }
+                let Ok(secret) = random_key(&self.rng) else {
+                    return;
+                };
+                // Keep the previous key so tickets issued just before rotation stay usable
+                let mut keys = self.keys.write();
+                keys.insert(0, TicketKey::new(&secret));
+                keys.truncate(2);
+                *created = Instant::now();
+            }
+        }
+    }
+}
+
+impl ProducesTickets for TicketKeyring {
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    fn lifetime(&self) -> u32 {
+        self.lifetime
+    }
+
+    fn encrypt(&self, plain: &[u8]) -> Option<Vec<u8>> {
+        self.refresh();
+        let keys = self.keys.read();
+        let current = keys.first()?;
+
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng.fill(&mut nonce).ok()?;
+
+        let mut in_out = plain.to_vec();
+        current
+            .key
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(&current.name), &mut in_out)
+            .ok()?;
+
+        let mut ticket = Vec::with_capacity(KEY_NAME_LEN + NONCE_LEN + in_out.len());
+        ticket.extend_from_slice(&current.name);
+        ticket.extend_from_slice(&nonce);
+        ticket.extend_from_slice(&in_out);
+        Some(ticket)
+    }
+
+    fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>> {
+        self.refresh();
+        if cipher.len() < KEY_NAME_LEN + NONCE_LEN {
+            return None;
+        }
+        let (name, rest) = cipher.split_at(KEY_NAME_LEN);
+        let (nonce, sealed) = rest.split_at(NONCE_LEN);
+
+        let keys = self.keys.read();
+        let key = keys.iter().find(|k| k.name == name)?;
+        let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+        let mut in_out = sealed.to_vec();
+        let plain_len = key.key.open_in_place(nonce, Aad::from(&key.name), &mut in_out).ok()?.len();
+        in_out.truncate(plain_len);
+        Some(in_out)
+    }
+}
+
+fn random_key(rng: &SystemRandom) -> anyhow::Result<[u8; KEY_LEN]> {
+    let mut key = [0u8; KEY_LEN];
+    rng.fill(&mut key)
+        .map_err(|_| anyhow::anyhow!("Failed to generate ticket key"))?;
+    Ok(key)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Parse a key file: one base64 key per line, blank lines and `#` comments ignored
+fn parse_keys(contents: &str) -> anyhow::Result<Vec<TicketKey>> {
+    let mut keys = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(line)
+            .map_err(|e| anyhow::anyhow!("line {}: invalid base64: {}", index + 1, e))?;
+        let secret: [u8; KEY_LEN] = bytes.try_into().map_err(|bytes: Vec<u8>| {
+            anyhow::anyhow!("line {}: key is {} bytes, expected {}", index + 1, bytes.len(), KEY_LEN)
+        })?;
+        keys.push(TicketKey::new(&secret));
+    }
+    if keys.is_empty() {
+        anyhow::bail!("no keys found");
+    }
+    Ok(keys)
+}
+
+fn read_key_file(path: &Path) -> anyhow::Result<Vec<TicketKey>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read ticket key file {}: {}", path.display(), e))?;
+    parse_keys(&contents).map_err(|e| anyhow::anyhow!("Invalid ticket key file {}: {}", path.display(), e))
+}
+
+/// Write a new key file readable only by the owner
+fn write_key_file(path: &Path, key: &[u8; KEY_LEN]) -> anyhow::Result<()> {
+    let contents = format!(
+        "# TLS session ticket keys: first line encrypts, all lines decrypt\n{}\n",
+        base64::engine::general_purpose::STANDARD.encode(key)
+    );
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(path)?;
+        std::io::Write::write_all(&mut file, contents.as_bytes())?;
+    }
+    #[cfg(not(unix))]
+    {
+        std::fs::write(path, contents)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(byte: u8) -> String {
+        base64::engine::general_purpose::STANDARD.encode([byte; KEY_LEN])
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("spawngate-tickets-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_round_trip_and_tampering() {
+        let keyring = TicketKeyring::random(3600).unwrap();
+        let ticket = keyring.encrypt(b"session state").unwrap();
+        assert_eq!(keyring.decrypt(&ticket).unwrap(), b"session state");
+
+        let mut tampered = ticket.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(keyring.decrypt(&tampered).is_none());
+        assert!(keyring.decrypt(&ticket[..10]).is_none());
+
+        // Another process' random keys can't decrypt it
+        assert!(TicketKeyring::random(3600).unwrap().decrypt(&ticket).is_none());
+    }
+
+    #[test]
+    fn test_shared_key_file() {
+        let path = temp_path("shared");
+        std::fs::write(&path, format!("# keys\n{}\n\n{}\n", encoded(1), encoded(2))).unwrap();
+
+        let node_a = TicketKeyring::from_file(&path, 3600).unwrap();
+        let node_b = TicketKeyring::from_file(&path, 3600).unwrap();
+        assert_eq!(node_a.key_count(), 2);
+        let ticket = node_a.encrypt(b"state").unwrap();
+        assert_eq!(node_b.decrypt(&ticket).unwrap(), b"state");
+
+        // After rotation the old key still decrypts
+        let rotated = TicketKeyring::from_file(&path, 3600).unwrap();
+        *rotated.keys.write() = parse_keys(&format!("{}\n{}\n", encoded(3), encoded(1))).unwrap();
+        assert_eq!(rotated.decrypt(&ticket).unwrap(), b"state");
+        assert_ne!(&rotated.encrypt(b"x").unwrap()[..KEY_NAME_LEN], &ticket[..KEY_NAME_LEN]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_creates_missing_key_file() {
+        let path = temp_path("create");
+        let keyring = TicketKeyring::from_file(&path, 3600).unwrap();
+        assert_eq!(keyring.key_count(), 1);
+        let reloaded = TicketKeyring::from_file(&path, 3600).unwrap();
+        let ticket = keyring.encrypt(b"state").unwrap();
+        assert_eq!(reloaded.decrypt(&ticket).unwrap(), b"state");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse_keys("# only comments\n").is_err());
+        assert!(parse_keys("not base64!\n").is_err());
+        let short = base64::engine::general_purpose::STANDARD.encode([0u8; 16]);
+        let err = parse_keys(&short).err().unwrap().to_string();
+        assert!(err.contains("16 bytes"), "{}", err);
+    }
+}
@@ -0,0 +1,304 @@
+//! TLS certificate loading and per-host certificate selection
+
+use crate::config::TlsSessionConfig;
+use crate::tickets::TicketKeyring;
+use parking_lot::RwLock;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, NoServerSessionStorage, ResolvesServerCert, ServerConfig, ServerSessionMemoryCache};
+use rustls::sign::CertifiedKey;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Load a PEM certificate chain from a file
+pub fn load_certs(path: &str) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let file = File::open(path)
+        .map_err(|e| anyhow::anyhow!("Failed to open certificate file {}: {}", path, e))?;
+    let mut reader = BufReader::new(file);
+    let certs = rustls_pemfile::certs(&mut reader)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| anyhow::anyhow!("Failed to parse certificates from {}: {}", path, e))?;
+
+    if certs.is_empty() {
+        anyhow::bail!("No certificates found in {}", path);
+    }
+
+    Ok(certs)
+}
+
+/// Load the first PEM private key (PKCS#1, PKCS#8 or SEC1) from a file
+pub fn load_key(path: &str) -> anyhow::Result<PrivateKeyDer<'static>> {
+    let file = File::open(path)
+        .map_err(|e| anyhow::anyhow!("Failed to open key file {}: {}", path, e))?;
+    let mut reader = BufReader::new(file);
+
+    loop {
+        match rustls_pemfile::read_one(&mut reader)
+            .map_err(|e| anyhow::anyhow!("Failed to parse key from {}: {}", path, e))?
+        {
+            Some(rustls_pemfile::Item::Pkcs1Key(key)) => return Ok(key.into()),
+            Some(rustls_pemfile::Item::Pkcs8Key(key)) => return Ok(key.into()),
+            Some(rustls_pemfile::Item::Sec1Key(key)) => return Ok(key.into()),
+            None => break,
+            _ => continue,
+        }
+    }
+
+    anyhow::bail!("No private key found in {}", path)
+}
+
+/// Build a rustls signing key from a certificate chain and private key
+pub fn certified_key(
+    certs: Vec<CertificateDer<'static>>,
+    key: &PrivateKeyDer<'static>,
+) -> anyhow::Result<Arc<CertifiedKey>> {
+    let signing_key = rustls::crypto::ring::sign::any_supported_type(key)
+        .map_err(|e| anyhow::anyhow!("Failed to create signing key: {}", e))?;
+    Ok(Arc::new(CertifiedKey::new(certs, signing_key)))
+}
+
+/// Load a certificate chain and key from files, checking that they belong together
+pub fn load_certified_key(cert_path: &str, key_path: &str) -> anyhow::Result<Arc<CertifiedKey>> {
+    let certs = load_certs(cert_path)?;
+    let key = load_key(key_path)?;
+    let certified = certified_key(certs, &key)?;
+    certified
+        .keys_match()
+        .map_err(|e| anyhow::anyhow!("Key {} does not match certificate {}: {}", key_path, cert_path, e))?;
+    Ok(certified)
+}
+
+/// Expiry (notAfter) of a certificate as a Unix timestamp
+pub fn not_after(cert: &CertificateDer<'_>) -> Option<i64> {
+    use x509_parser::prelude::*;
+
+    let (_, parsed) = X509Certificate::from_der(cert.as_ref()).ok()?;
+    Some(parsed.validity().not_after.timestamp())
+}
+
+/// Apply session resumption settings to a server config
+pub fn configure_session_resumption(config: &mut ServerConfig, settings: &TlsSessionConfig) -> anyhow::Result<()> {
+    config.session_storage = if settings.cache_size > 0 {
+        ServerSessionMemoryCache::new(settings.cache_size)
+    } else {
+        Arc::new(NoServerSessionStorage {})
+    };
+    config.send_tls13_tickets = settings.tickets_per_handshake;
+
+    if settings.tickets {
+        let lifetime = settings.ticket_lifetime_secs;
+        let keyring = match &settings.ticket_key_file {
+            Some(path) => TicketKeyring::from_file(Path::new(path), lifetime)?,
+            None => TicketKeyring::random(lifetime)?,
+        };
+        config.ticketer = Arc::new(keyring);
+    }
+    Ok(())
+}
+
+/// Resolver that serves pinned certificates for specific hostnames
+/// and delegates everything else (e.g. to ACME or the global certificate)
+///
+/// Certificates can be replaced while serving, so renewed files take
+/// effect without a restart. Uses synchronous locks because rustls resolves
+/// certificates from inside the handshake.
+#[derive(Debug)]
+pub struct PinnedCertResolver {
+    pinned: RwLock<HashMap<String, Arc<CertifiedKey>>>,
+    fallback: Fallback,
+}
+
+#[derive(Debug)]
+enum Fallback {
+    Cert(RwLock<Arc<CertifiedKey>>),
+    Resolver(Arc<dyn ResolvesServerCert>),
+}
+
+impl PinnedCertResolver {
+    /// Fall back to a single certificate for unpinned hostnames
+    pub fn with_default_cert(cert: Arc<CertifiedKey>) -> Self {
+        Self {
+            pinned: RwLock::new(HashMap::new()),
+            fallback: Fallback::Cert(RwLock::new(cert)),
+        }
+    }
+
+    /// Fall back to another resolver for unpinned hostnames
+    pub fn with_fallback(resolver: Arc<dyn ResolvesServerCert>) -> Self {
+        Self {
+            pinned: RwLock::new(HashMap::new()),
+            fallback: Fallback::Resolver(resolver),
+        }
+    }
+
+    /// Serve `cert` for `hostname`, replacing any certificate pinned before
+    pub fn pin(&self, hostname: &str, cert: Arc<CertifiedKey>) {
+        self.pinned.write().insert(hostname.to_ascii_lowercase(), cert);
+    }
+
+    /// Load certificate files and serve them for `hostname`
+    pub fn pin_files(&self, hostname: &str, cert_path: &str, key_path: &str) -> anyhow::Result<()> {
+        self.pin(hostname, load_certified_key(cert_path, key_path)?);
+        Ok(())
+    }
+
+    /// Replace the default certificate; returns false if unpinned hostnames
+    /// are delegated to another resolver
+    pub fn set_default_cert(&self, cert: Arc<CertifiedKey>) -> bool {
+        match &self.fallback {
+            Fallback::Cert(current) => {
+                *current.write() = cert;
+                true
+            }
+            Fallback::Resolver(_) => false,
+        }
+    }
+
+    /// Number of pinned hostnames
+    pub fn pinned_count(&self) -> usize {
+        self.pinned.read().len()
+    }
+
+    pub(crate) fn lookup(&self, server_name: Option<&str>) -> Option<Arc<CertifiedKey>> {
+        server_name.and_then(|name| self.pinned.read().get(&name.to_ascii_lowercase()).cloned())
+    }
+}
+
+impl ResolvesServerCert for PinnedCertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        if let Some(cert) = self.lookup(client_hello.server_name()) {
+            return Some(cert);
+        }
+
+        match &self.fallback {
+            Fallback::Cert(cert) => Some(Arc::clone(&cert.read())),
+            Fallback::Resolver(resolver) => resolver.resolve(client_hello),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_cert(name: &str) -> Arc<CertifiedKey> {
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        let key = PrivateKeyDer::try_from(key_pair.serialize_der()).unwrap();
+        certified_key(vec![CertificateDer::from(cert.der().to_vec())], &key).unwrap()
+    }
+
+    #[test]
+    fn test_pinned_lookup() {
+        let default = test_cert("default.local");
+        let pinned = test_cert("cdn.example.com");
+
+        let resolver = PinnedCertResolver::with_default_cert(Arc::clone(&default));
+        resolver.pin("CDN.example.com", Arc::clone(&pinned));
+
+        assert_eq!(resolver.pinned_count(), 1);
+        assert!(Arc::ptr_eq(&resolver.lookup(Some("cdn.example.com")).unwrap(), &pinned));
+        assert!(resolver.lookup(Some("other.example.com")).is_none());
+        assert!(resolver.lookup(None).is_none());
+    }
+
+    #[test]
+    fn test_load_missing_files() {
+        let resolver = PinnedCertResolver::with_default_cert(test_cert("default.local"));
+        let err = resolver
+            .pin_files("a.local", "/nonexistent/cert.pem", "/nonexistent/key.pem")
+            .unwrap_err();
+        assert!(err.to_string().contains("Failed to open certificate file"));
+    }
+
+    #[test]
+    fn test_replace_default_cert() {
+        let first = test_cert("default.local");
+        let second = test_cert("default.local");
+        let resolver = PinnedCertResolver::with_default_cert(Arc::clone(&first));
+        assert!(resolver.set_default_cert(Arc::clone(&second)));
+        match &resolver.fallback {
+            Fallback::Cert(cert) => assert!(Arc::ptr_eq(&cert.read(), &second)),
+            Fallback::Resolver(_) => unreachable!(),
+        }
+
+        let delegating = PinnedCertResolver::with_fallback(resolver_arc(resolver));
+        assert!(!delegating.set_default_cert(first));
+    }
+
+    fn resolver_arc(resolver: PinnedCertResolver) -> Arc<dyn ResolvesServerCert> {
+        Arc::new(resolver)
+    }
+
+    /// Move pending TLS records from one connection to the other
+    fn pump(from: &mut rustls::Connection, to: &mut rustls::Connection) {
+        let mut buf = Vec::new();
+        while from.wants_write() {
+            from.write_tls(&mut buf).unwrap();
+        }
+        let mut pending = &buf[..];
+        while !pending.is_empty() {
+            to.read_tls(&mut pending).unwrap();
+            to.process_new_packets().unwrap();
+        }
+    }
+
+    fn connect(server: Arc<ServerConfig>, client: Arc<rustls::ClientConfig>) -> rustls::HandshakeKind {
+        let name = rustls::pki_types::ServerName::try_from("resume.local").unwrap();
+        let mut client = rustls::Connection::Client(rustls::ClientConnection::new(client, name).unwrap());
+        let mut server = rustls::Connection::Server(rustls::ServerConnection::new(server).unwrap());
+        for _ in 0..4 {
+            pump(&mut client, &mut server);
+            pump(&mut server, &mut client);
+        }
+        assert!(!client.is_handshaking());
+        client.handshake_kind().unwrap()
+    }
+
+    #[test]
+    fn test_tickets_resume_across_servers_sharing_a_key_file() {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec!["resume.local".to_string()]).unwrap();
+        let cert_der = CertificateDer::from(cert.der().to_vec());
+        let key = PrivateKeyDer::try_from(key_pair.serialize_der()).unwrap();
+
+        let key_file = std::env::temp_dir().join(format!("spawngate-tls-tickets-{}", std::process::id()));
+        let _ = std::fs::remove_file(&key_file);
+        let settings = TlsSessionConfig {
+            cache_size: 0,
+            tickets: true,
+            ticket_key_file: Some(key_file.display().to_string()),
+            ..TlsSessionConfig::default()
+        };
+        let server = || {
+            let mut config = ServerConfig::builder_with_provider(Arc::clone(&provider))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_no_client_auth()
+                .with_single_cert(vec![cert_der.clone()], key.clone_key())
+                .unwrap();
+            configure_session_resumption(&mut config, &settings).unwrap();
+            Arc::new(config)
+        };
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(cert_der.clone()).unwrap();
+        let client = Arc::new(
+            rustls::ClientConfig::builder_with_provider(Arc::clone(&provider))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots)
+                .with_no_client_auth(),
+        );
+
+        // A ticket issued by one node resumes on another (no shared session cache)
+        let (node_a, node_b) = (server(), server());
+        assert_eq!(connect(node_a, Arc::clone(&client)), rustls::HandshakeKind::Full);
+        assert_eq!(connect(node_b, Arc::clone(&client)), rustls::HandshakeKind::Resumed);
+
+        std::fs::remove_file(&key_file).unwrap();
+    }
+}
@@ -0,0 +1,342 @@
+//! Lightweight request inspection (a small OWASP CRS-style rule set)
+//!
+//! Requests for backends with `waf` enabled are checked against a handful of
+//! SQL injection and XSS patterns and some header anomalies before the backend
+//! is spawned. Like the CRS anomaly scoring mode, every matching rule adds its
+//! severity to the request's score (critical 5, error 4, warning 3, notice 2)
+//! and the request is logged, or blocked with 403, once the score reaches the
+//! backend's threshold. The default threshold of 5 blocks on a single critical
+//! match, while notices only add up.
+//!
+//! The path, query parameter names and values, and the `User-Agent`, `Referer`
+//! and `Cookie` headers are inspected after decoding, lowercasing, replacing
+//! SQL comments with spaces and dropping whitespace around punctuation, so
+//! `UNION/**/SELECT` and `' OR '1'='1` match the same rules as their plain
+//! forms. Request bodies are not inspected.
+//!
+//! This is a basic defense for apps that can't run behind a full WAF, not a
+//! replacement for one.
+
+use hyper::header::{HeaderName, ACCEPT, CONTENT_LENGTH, COOKIE, REFERER, TRANSFER_ENCODING, USER_AGENT};
+use hyper::http::request::Parts;
+use hyper::Method;
+use std::borrow::Cow;
+use std::fmt;
+
+/// Rule severity, which is also the score it adds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Critical = 5,
+    Error = 4,
+    Warning = 3,
+    Notice = 2,
+}
+
+/// A rule that matched the request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleMatch {
+    pub rule: &'static str,
+    pub severity: Severity,
+    /// Where it matched: `path`, `query`, `headers` or a header name
+    pub location: &'static str,
+}
+
+/// Result of inspecting a request
+#[derive(Debug, Default)]
+pub struct Inspection {
+    /// Sum of the severities of the matched rules
+    pub score: u32,
+    /// Matched rules, each counted once
+    pub matches: Vec<RuleMatch>,
+}
+
+impl Inspection {
+    fn add(&mut self, rule: &'static str, severity: Severity, location: &'static str) {
+        if self.matches.iter().all(|m| m.rule != rule) {
+            self.score += severity as u32;
+            self.matches.push(RuleMatch { rule, severity, location });
+        }
+    }
+}
+
+impl fmt::Display for Inspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, m) in self.matches.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}@{}", m.rule, m.location)?;
+        }
+        Ok(())
+    }
+}
+
+struct Rule {
+    id: &'static str,
+    severity: Severity,
+    /// Patterns in normalized form (see `normalize`)
+    patterns: &'static [&'static str],
+}
+
+const RULES: &[Rule] = &[
+    Rule {
+        id: "sqli-union",
+        severity: Severity::Critical,
+        patterns: &["union select", "union all select", "union distinct select", "union(select"],
+    },
+    Rule {
+        id: "sqli-tautology",
+        severity: Severity::Critical,
+        patterns: &["'or'", "'or 1", "'or true", "\"or\"", "\"or 1", "\"or true", " or 1=1"],
+    },
+    Rule {
+        id: "sqli-comment",
+        severity: Severity::Critical,
+        patterns: &["'--", "\"--", "';--"],
+    },
+    Rule {
+        id: "sqli-stacked-query",
+        severity: Severity::Critical,
+        patterns: &[";drop table", ";drop database", ";delete from", ";insert into", ";truncate table", ";shutdown"],
+    },
+    Rule {
+        id: "sqli-function",
+        severity: Severity::Critical,
+        patterns: &[
+            "sleep(",
+            "benchmark(",
+            "waitfor delay",
+            "load_file(",
+            "into outfile",
+            "into dumpfile",
+            "xp_cmdshell",
+            "information_schema",
+            "@@version",
+            "extractvalue(",
+            "updatexml(",
+        ],
+    },
+    Rule {
+        id: "xss-script",
+        severity: Severity::Critical,
+        patterns: &["<script", "</script"],
+    },
+    Rule {
+        id: "xss-uri",
+        severity: Severity::Critical,
+        patterns: &["javascript:", "vbscript:", "data:text/html"],
+    },
+    Rule {
+        id: "xss-tag",
+        severity: Severity::Critical,
+        patterns: &["<iframe", "<object", "<embed", "<svg", "<math", "<base"],
+    },
+    Rule {
+        id: "xss-dom",
+        severity: Severity::Critical,
+        patterns: &["document.cookie", "document.write(", "document.location", "alert(", "eval(", "fromcharcode("],
+    },
+];
+
+/// Headers whose values are checked against the rules
+const INSPECTED_HEADERS: &[(HeaderName, &str)] = &[(USER_AGENT, "user-agent"), (REFERER, "referer"), (COOKIE, "cookie")];
+
+/// Score a request against the rule set
+pub fn inspect(parts: &Parts) -> Inspection {
+    let mut inspection = Inspection::default();
+
+    let path = percent_decode(parts.uri.path());
+    check(&mut inspection, &path, "path");
+
+    if let Some(query) = parts.uri.query() {
+        for (name, value) in form_urlencoded::parse(query.as_bytes()) {
+            check(&mut inspection, &name, "query");
+            check(&mut inspection, &value, "query");
+        }
+    }
+
+    for (header, location) in INSPECTED_HEADERS {
+        for value in parts.headers.get_all(header) {
+            check(&mut inspection, &String::from_utf8_lossy(value.as_bytes()), location);
+        }
+    }
+
+    check_headers(&mut inspection, parts);
+    inspection
+}
+
+fn check(inspection: &mut Inspection, input: &str, location: &'static str) {
+    if input.is_empty() {
+        return;
+    }
+    let normalized = normalize(input);
+    for rule in RULES {
+        if rule.patterns.iter().any(|pattern| normalized.contains(pattern)) {
+            inspection.add(rule.id, rule.severity, location);
+        }
+    }
+    if has_event_handler(&normalized) {
+        inspection.add("xss-event-handler", Severity::Critical, location);
+    }
+}
+
+fn check_headers(inspection: &mut Inspection, parts: &Parts) {
+    let headers = &parts.headers;
+    if headers.get(USER_AGENT).is_none_or(|v| v.is_empty()) {
+        inspection.add("header-missing-user-agent", Severity::Notice, "headers");
+    }
+    if parts.method != Method::OPTIONS && !headers.contains_key(ACCEPT) {
+        inspection.add("header-missing-accept", Severity::Notice, "headers");
+    }
+
+    let has_body = headers.contains_key(TRANSFER_ENCODING)
+        || headers
+            .get(CONTENT_LENGTH)
+            .is_some_and(|v| v.as_bytes() != b"0");
+    if matches!(parts.method, Method::GET | Method::HEAD) && has_body {
+        inspection.add("header-body-on-get", Severity::Critical, "headers");
+    }
+    if headers.contains_key(TRANSFER_ENCODING) && headers.contains_key(CONTENT_LENGTH) {
+        inspection.add("header-content-length-with-transfer-encoding", Severity::Critical, "headers");
+    }
+}
+
+/// Percent-decode a path, keeping invalid escapes as they are
+fn percent_decode(input: &str) -> Cow<'_, str> {
+    if !input.contains('%') {
+        return Cow::Borrowed(input);
+    }
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                out.push(byte);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    Cow::Owned(String::from_utf8_lossy(&out).into_owned())
+}
+
+/// Lowercase, replace `/* */` comments with a space, collapse whitespace and
+/// drop it next to punctuation
+fn normalize(input: &str) -> String {
+    let lower = input.to_lowercase();
+    let mut uncommented = String::with_capacity(lower.len());
+    let mut rest = lower.as_str();
+    while let Some(start) = rest.find("/*") {
+        uncommented.push_str(&rest[..start]);
+        uncommented.push(' ');
+        rest = rest[start + 2..].find("*/").map_or("", |end| &rest[start + 2 + end + 2..]);
+    }
+    uncommented.push_str(rest);
+
+    let tight = |c: char| matches!(c, '=' | '(' | ')' | ';' | ',' | '\'' | '"' | '<' | '>');
+    let mut out = String::with_capacity(uncommented.len());
+    let mut space = false;
+    for c in uncommented.chars() {
+        if c.is_whitespace() {
+            space = true;
+            continue;
+        }
+        if space && !out.is_empty() && !tight(c) && !out.ends_with(tight) {
+            out.push(' ');
+        }
+        space = false;
+        out.push(c);
+    }
+    out
+}
+
+/// An HTML event handler attribute such as ` onerror=` or `/onload=`
+fn has_event_handler(normalized: &str) -> bool {
+    let bytes = normalized.as_bytes();
+    normalized.match_indices("on").any(|(i, _)| {
+        let boundary = i > 0 && matches!(bytes[i - 1], b' ' | b'"' | b'\'' | b'/' | b'`');
+        let name_len = bytes[i + 2..].iter().take_while(|b| b.is_ascii_lowercase()).count();
+        boundary && name_len >= 3 && bytes.get(i + 2 + name_len) == Some(&b'=')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::Request;
+
+    fn inspect_uri(uri: &str) -> Inspection {
+        let (parts, ()) = Request::get(uri)
+            .header(USER_AGENT, "test")
+            .header(ACCEPT, "*/*")
+            .body(())
+            .unwrap()
+            .into_parts();
+        inspect(&parts)
+    }
+
+    fn rules(inspection: &Inspection) -> Vec<&'static str> {
+        inspection.matches.iter().map(|m| m.rule).collect()
+    }
+
+    #[test]
+    fn test_sqli_and_xss_rules() {
+        let cases = [
+            ("/items?id=1%20UNION%2F**%2FSELECT%20password", "sqli-union"),
+            ("/login?user=admin'%20OR%20'1'%3D'1", "sqli-tautology"),
+            ("/login?user=admin'--", "sqli-comment"),
+            ("/items?id=1;%20DROP%20TABLE%20users", "sqli-stacked-query"),
+            ("/items?id=1%20AND%20SLEEP%20(5)", "sqli-function"),
+            ("/search?q=%3Cscript%3Ealert(1)%3C/script%3E", "xss-script"),
+            ("/search?q=%3Cimg%20src=x%20OnError%20=alert(1)%3E", "xss-event-handler"),
+            ("/go?next=JavaScript:void(0)", "xss-uri"),
+            ("/%3Csvg/onload=x%3E", "xss-tag"),
+        ];
+        for (uri, rule) in cases {
+            let inspection = inspect_uri(uri);
+            assert!(rules(&inspection).contains(&rule), "{}: {:?}", uri, inspection);
+            assert!(inspection.score >= 5, "{}", uri);
+        }
+
+        for uri in [
+            "/",
+            "/api/users?sort=name&order=desc&page=2",
+            "/search?q=union+station+opening+hours",
+            "/blog/it's-or-isn't?tag=select",
+            "/events?q=online+conference&location=london",
+        ] {
+            let inspection = inspect_uri(uri);
+            assert_eq!(inspection.score, 0, "{}: {}", uri, inspection);
+        }
+    }
+
+    #[test]
+    fn test_header_anomalies() {
+        let (parts, ()) = Request::get("/").body(()).unwrap().into_parts();
+        let inspection = inspect(&parts);
+        assert_eq!(rules(&inspection), ["header-missing-user-agent", "header-missing-accept"]);
+        assert_eq!(inspection.score, 4);
+
+        let (parts, ()) = Request::get("/")
+            .header(USER_AGENT, "curl/8.0")
+            .header(ACCEPT, "*/*")
+            .header(CONTENT_LENGTH, "12")
+            .header(REFERER, "https://example.com/?q=<script>")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let inspection = inspect(&parts);
+        assert_eq!(rules(&inspection), ["xss-script", "header-body-on-get"]);
+        assert_eq!(inspection.score, 10);
+        assert_eq!(inspection.to_string(), "xss-script@referer,header-body-on-get@headers");
+    }
+}
@@ -6,11 +6,26 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 
+use hyper::http::request::Parts;
 use spawngate::admin::AdminServer;
-use spawngate::config::{BackendConfig, BackendDefaults, Config};
+use spawngate::config::{
+    AdaptiveIdleConfig, BackendConfig, BackendDefaults, BackendType, ColdStartSloConfig, Config,
+    ConsulConfig, ExperimentConfig, ExperimentVariant, FaultConfig, FileSdConfig, FingerprintBlock,
+    KvConfig, KvStore, MetricsExportConfig, NomadConfig, PortAllocationConfig, PriorityClass,
+    PriorityRule, ProfilingConfig, QuotaAction, QuotaConfig, QuotaWindow, RouteRule, ServerConfig,
+    SidecarConfig, TlsFingerprintConfig,
+};
+use spawngate::fingerprint::Fingerprinter;
+use spawngate::listeners::{ListenerSettings, Listeners, ProxyServers};
+use spawngate::lockout::AuthLockout;
 use spawngate::pool::PoolConfig;
-use spawngate::process::{BackendState, ProcessManager};
-use spawngate::proxy::ProxyServer;
+use spawngate::process::{BackendEvent, BackendState, PendingGate, ProcessManager};
+use spawngate::profiling::Profiler;
+use spawngate::proxy::{ProxyServer, RequestContext, Router};
+use spawngate::registry::DebugRegistry;
+use spawngate::slowlog::SlowRequestLog;
+use spawngate::throttle::ColdStartThrottle;
+use spawngate::Spawngate;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::sync::watch;
@@ -123,6 +138,115 @@ async fn http_get_with_host(
     Ok(response)
 }
 
+/// Send a request without a body to the admin API with the test token
+async fn admin_request(port: u16, method: &str, path: &str) -> String {
+    admin_request_with_body(port, method, path, "").await
+}
+
+/// Send a request to the admin API with the test token
+async fn admin_request_with_body(port: u16, method: &str, path: &str, body: &str) -> String {
+    admin_request_with_headers(port, method, path, &[], body).await
+}
+
+/// Send a request to the admin API with the test token and extra headers
+async fn admin_request_with_headers(
+    port: u16,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+) -> String {
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).await.unwrap();
+    let headers: String = headers.iter().map(|(name, value)| format!("{}: {}\r\n", name, value)).collect();
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: 127.0.0.1\r\nAuthorization: Bearer test-token\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        headers,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+/// Ask the OS for a free local port
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// Full proxy + admin stack bound to OS-assigned ports
+///
+/// Avoids hardcoded listener ports so tests can run in parallel without collisions.
+struct TestHarness {
+    proxy_port: u16,
+    admin_port: u16,
+    manager: Arc<ProcessManager>,
+    shutdown_tx: watch::Sender<bool>,
+    handles: Vec<tokio::task::JoinHandle<()>>,
+}
+
+impl TestHarness {
+    async fn start(configs: HashMap<String, BackendConfig>) -> Self {
+        Self::start_with_throttle(configs, None).await
+    }
+
+    async fn start_with_throttle(
+        configs: HashMap<String, BackendConfig>,
+        throttle: Option<Arc<ColdStartThrottle>>,
+    ) -> Self {
+        let admin_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let admin_addr = admin_listener.local_addr().unwrap();
+        let proxy_addr = proxy_listener.local_addr().unwrap();
+
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let manager = ProcessManager::new(
+            configs,
+            BackendDefaults::default(),
+            format!("http://{}", admin_addr),
+        );
+
+        let mut admin_server = AdminServer::new(admin_addr, Arc::clone(&manager), shutdown_rx.clone(), "test-token".to_string());
+        let mut proxy_server = ProxyServer::new(proxy_addr, Arc::clone(&manager), manager.shared_defaults(), shutdown_rx);
+        if let Some(throttle) = throttle {
+            admin_server = admin_server.with_cold_start_throttle(Arc::clone(&throttle));
+            proxy_server = proxy_server.with_cold_start_throttle(throttle);
+        }
+
+        let handles = vec![
+            tokio::spawn(async move {
+                let _ = admin_server.serve(admin_listener).await;
+            }),
+            tokio::spawn(async move {
+                let _ = proxy_server.serve(proxy_listener).await;
+            }),
+        ];
+
+        Self {
+            proxy_port: proxy_addr.port(),
+            admin_port: admin_addr.port(),
+            manager,
+            shutdown_tx,
+            handles,
+        }
+    }
+
+    async fn stop(self) {
+        self.manager.stop_all().await;
+        let _ = self.shutdown_tx.send(true);
+        for handle in self.handles {
+            let _ = handle.await;
+        }
+    }
+}
+
 // ============================================================================
 // Basic Configuration Tests
 // ============================================================================
@@ -175,6 +299,9 @@ fn test_backend_config_default_fallback() {
     let defaults = BackendDefaults {
         idle_timeout_secs: 300,
         startup_timeout_secs: 45,
+        spawn_wait_secs: None,
+        max_queue_depth: None,
+        max_lifetime_secs: None,
         health_check_interval_ms: 200,
         health_path: "/health".to_string(),
         shutdown_grace_period_secs: 10,
@@ -182,6 +309,14 @@ fn test_backend_config_default_fallback() {
         request_timeout_secs: 30,
         ready_health_check_interval_ms: 5000,
         unhealthy_threshold: 3,
+        timezone: None,
+        locale: None,
+        adaptive_idle: Default::default(),
+        cost: Default::default(),
+        waf: Default::default(),
+        registries: Default::default(),
+        cgroups: Default::default(),
+        ports: Default::default(),
     };
 
     let mut backend = BackendConfig::local("node", 3000);
@@ -851,6 +986,232 @@ async fn test_concurrent_requests_while_starting() {
     let _ = proxy_handle.await;
 }
 
+/// Load the test certificate as server and client TLS configs
+fn test_tls_configs() -> Option<(rustls::ServerConfig, rustls::ClientConfig)> {
+    use std::fs::File;
+    use std::io::BufReader;
+
+    let cert_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/certs/cert.pem");
+    let key_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/certs/key.pem");
+    if !cert_path.exists() || !key_path.exists() {
+        return None;
+    }
+
+    let certs: Vec<_> = rustls_pemfile::certs(&mut BufReader::new(File::open(&cert_path).unwrap()))
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(&key_path).unwrap()))
+        .unwrap()
+        .unwrap();
+
+    let mut root_store = rustls::RootCertStore::empty();
+    for cert in &certs {
+        root_store.add(cert.clone()).unwrap();
+    }
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let server = rustls::ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .unwrap();
+    let client = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(root_store)
+        .with_no_client_auth();
+    Some((server, client))
+}
+
+/// Cold-start requests on the HTTP and HTTPS listeners at once must spawn the backend only once
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_cold_start_across_listeners_spawns_once() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+    let Some((server_config, client_config)) = test_tls_configs() else {
+        eprintln!("Skipping test: test certificates not found");
+        return;
+    };
+
+    let admin_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let http_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let https_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let admin_addr = admin_listener.local_addr().unwrap();
+    let http_port = http_listener.local_addr().unwrap().port();
+    let https_port = https_listener.local_addr().unwrap().port();
+
+    let mut configs = HashMap::new();
+    configs.insert("race.local".to_string(), mock_backend_config_with_delay(free_port(), 300));
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let manager = ProcessManager::new(configs, BackendDefaults::default(), format!("http://{}", admin_addr));
+
+    let admin_server = AdminServer::new(admin_addr, Arc::clone(&manager), shutdown_rx.clone(), "test-token".to_string());
+    let http_proxy = ProxyServer::new(
+        http_listener.local_addr().unwrap(),
+        Arc::clone(&manager),
+        manager.shared_defaults(),
+        shutdown_rx.clone(),
+    );
+    let https_proxy = ProxyServer::new(
+        https_listener.local_addr().unwrap(),
+        Arc::clone(&manager),
+        manager.shared_defaults(),
+        shutdown_rx,
+    )
+    .with_tls(tokio_rustls::TlsAcceptor::from(Arc::new(server_config)));
+    let server_handles = vec![
+        tokio::spawn(async move {
+            let _ = admin_server.serve(admin_listener).await;
+        }),
+        tokio::spawn(async move {
+            let _ = http_proxy.serve(http_listener).await;
+        }),
+        tokio::spawn(async move {
+            let _ = https_proxy.serve(https_listener).await;
+        }),
+    ];
+
+    let connector = tokio_rustls::TlsConnector::from(Arc::new(client_config));
+    let start = Arc::new(tokio::sync::Barrier::new(32));
+    let mut requests = Vec::new();
+    for i in 0..32 {
+        let start = Arc::clone(&start);
+        let connector = connector.clone();
+        requests.push(tokio::spawn(async move {
+            start.wait().await;
+            if i % 2 == 0 {
+                return http_get_with_host(http_port, "/echo", "race.local").await.unwrap();
+            }
+            let stream = TcpStream::connect(format!("127.0.0.1:{}", https_port)).await.unwrap();
+            let domain = rustls::pki_types::ServerName::try_from("localhost").unwrap();
+            let mut tls_stream = connector.connect(domain, stream).await.unwrap();
+            tls_stream
+                .write_all(b"GET /echo HTTP/1.1\r\nHost: race.local\r\nConnection: close\r\n\r\n")
+                .await
+                .unwrap();
+            let mut response = String::new();
+            let _ = tls_stream.read_to_string(&mut response).await;
+            response
+        }));
+    }
+
+    for request in requests {
+        let response = request.await.unwrap();
+        assert!(response.contains("200 OK"), "Request failed: {}", response);
+    }
+    assert_eq!(manager.spawn_count("race.local"), 1);
+
+    manager.stop_all().await;
+    let _ = shutdown_tx.send(true);
+    for handle in server_handles {
+        let _ = handle.await;
+    }
+}
+
+/// Send a request over TLS, returning None if the connection is refused
+///
+/// Each request uses a new client config, so no session is resumed and the
+/// client hello, and with it the fingerprint, stays the same.
+async fn https_get(port: u16, request: &str) -> Option<String> {
+    let (_, client_config) = test_tls_configs().unwrap();
+    let connector = tokio_rustls::TlsConnector::from(Arc::new(client_config));
+    let stream = TcpStream::connect(format!("127.0.0.1:{}", port)).await.unwrap();
+    let domain = rustls::pki_types::ServerName::try_from("localhost").unwrap();
+    let mut tls_stream = connector.connect(domain, stream).await.ok()?;
+    tls_stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    let _ = tls_stream.read_to_string(&mut response).await;
+    Some(response)
+}
+
+/// TLS clients are fingerprinted, fingerprints are forwarded and blocklisted clients refused
+#[tokio::test]
+async fn test_tls_fingerprint() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+    let Some((server_config, _)) = test_tls_configs() else {
+        eprintln!("Skipping test: test certificates not found");
+        return;
+    };
+    let server_config = Arc::new(server_config);
+
+    let mut configs = HashMap::new();
+    configs.insert("fingerprint.local".to_string(), mock_backend_config(free_port()));
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let manager = ProcessManager::new(configs, BackendDefaults::default(), "http://127.0.0.1:1".to_string());
+
+    let start = |fingerprint: TlsFingerprintConfig| {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let proxy = ProxyServer::new(
+            listener.local_addr().unwrap(),
+            Arc::clone(&manager),
+            manager.shared_defaults(),
+            shutdown_rx.clone(),
+        )
+        .with_tls(tokio_rustls::TlsAcceptor::from(Arc::clone(&server_config)))
+        .with_tls_fingerprint(Arc::new(Fingerprinter::new(&fingerprint)));
+        let handle = tokio::spawn(async move {
+            let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+            let _ = proxy.serve(listener).await;
+        });
+        (port, handle)
+    };
+
+    // Client-sent fingerprint headers are replaced
+    let (port, first) = start(TlsFingerprintConfig {
+        enabled: true,
+        ..Default::default()
+    });
+    let request = "GET /headers HTTP/1.1\r\nHost: fingerprint.local\r\nX-JA4-Fingerprint: spoofed\r\n\
+                   Connection: close\r\n\r\n";
+    let response = https_get(port, request).await.unwrap();
+    assert!(response.contains("200 OK"), "Request failed: {}", response);
+    assert!(!response.contains("spoofed"));
+    let ja4 = response
+        .split("\"x-ja4-fingerprint\":\"")
+        .nth(1)
+        .and_then(|rest| rest.split('"').next())
+        .expect("JA4 fingerprint forwarded")
+        .to_string();
+    assert!(ja4.starts_with("t13d"), "Unexpected JA4: {}", ja4);
+    assert!(response.contains("\"x-ja3-fingerprint\":\""));
+
+    // Blocked on the connection level, the TLS handshake never completes
+    let (port, second) = start(TlsFingerprintConfig {
+        enabled: true,
+        blocklist: vec![ja4.clone()],
+        ..Default::default()
+    });
+    let request = "GET /echo HTTP/1.1\r\nHost: fingerprint.local\r\nConnection: close\r\n\r\n";
+    assert!(https_get(port, request).await.is_none());
+
+    // Blocked from cold starts, the running backend is still served
+    let (port, third) = start(TlsFingerprintConfig {
+        enabled: true,
+        blocklist: vec![ja4],
+        block: FingerprintBlock::ColdStart,
+        ..Default::default()
+    });
+    let response = https_get(port, request).await.unwrap();
+    assert!(response.contains("200 OK"), "Request failed: {}", response);
+    manager.stop_all().await;
+    let response = https_get(port, request).await.unwrap();
+    assert!(response.contains("403"), "Expected 403: {}", response);
+    assert!(response.contains("FINGERPRINT_BLOCKED"));
+
+    let _ = shutdown_tx.send(true);
+    for handle in [first, second, third] {
+        let _ = handle.await;
+    }
+}
+
 // ============================================================================
 // Multiple Backend Tests
 // ============================================================================
@@ -1520,6 +1881,7 @@ port = 3000
     // Should use defaults
     assert_eq!(config.server.pool_max_idle_per_host, 10);
     assert_eq!(config.server.pool_idle_timeout_secs, 90);
+    assert!(!config.server.pool_shared);
 }
 
 #[test]
@@ -1605,6 +1967,137 @@ async fn test_proxy_with_custom_pool_config() {
     let _ = proxy_handle.await;
 }
 
+#[tokio::test]
+async fn test_listeners_share_pool() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let admin_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let first_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let second_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let admin_addr = admin_listener.local_addr().unwrap();
+    let first_port = first_listener.local_addr().unwrap().port();
+    let second_port = second_listener.local_addr().unwrap().port();
+
+    let mut configs = HashMap::new();
+    configs.insert("shared.local".to_string(), mock_backend_config(free_port()));
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let manager = ProcessManager::new(configs, BackendDefaults::default(), format!("http://{}", admin_addr));
+
+    let pool = Arc::new(spawngate::pool::ConnectionPool::new(PoolConfig::default()));
+    let admin_server = AdminServer::new(admin_addr, Arc::clone(&manager), shutdown_rx.clone(), "test-token".to_string());
+    let first = ProxyServer::new(
+        first_listener.local_addr().unwrap(),
+        Arc::clone(&manager),
+        manager.shared_defaults(),
+        shutdown_rx.clone(),
+    )
+    .with_pool(Arc::clone(&pool));
+    let second = ProxyServer::new(
+        second_listener.local_addr().unwrap(),
+        Arc::clone(&manager),
+        manager.shared_defaults(),
+        shutdown_rx,
+    )
+    .with_pool(Arc::clone(&pool));
+    assert!(Arc::ptr_eq(first.pool(), second.pool()));
+
+    let handles = vec![
+        tokio::spawn(async move {
+            let _ = admin_server.serve(admin_listener).await;
+        }),
+        tokio::spawn(async move {
+            let _ = first.serve(first_listener).await;
+        }),
+        tokio::spawn(async move {
+            let _ = second.serve(second_listener).await;
+        }),
+    ];
+
+    for port in [first_port, second_port, first_port] {
+        let response = http_get_with_host(port, "/echo", "shared.local").await.unwrap();
+        assert!(response.contains("200 OK"), "Response: {}", response);
+    }
+    assert_eq!(pool.stats().get_total_requests(), 3);
+
+    manager.stop_all().await;
+    let _ = shutdown_tx.send(true);
+    for handle in handles {
+        let _ = handle.await;
+    }
+}
+
+/// Listener settings serving HTTP on `port` of the loopback address
+fn loopback_listener_settings(port: u16) -> ListenerSettings {
+    ListenerSettings::new(&spawngate::config::ServerConfig {
+        bind: "127.0.0.1".to_string(),
+        port,
+        ..Default::default()
+    })
+}
+
+#[tokio::test]
+async fn test_listeners_replaced_without_dropping_requests() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut configs = HashMap::new();
+    configs.insert("reload.local".to_string(), mock_backend_config(free_port()));
+    let manager = ProcessManager::new(configs, BackendDefaults::default(), "http://127.0.0.1:1".to_string());
+    let build = |settings: &ListenerSettings, shutdown_rx| {
+        let addr = settings.http_addr()?.expect("HTTP enabled");
+        Ok(ProxyServers {
+            http: Some(ProxyServer::new(addr, Arc::clone(&manager), manager.shared_defaults(), shutdown_rx)),
+            https: None,
+        })
+    };
+
+    let old_port = free_port();
+    let mut listeners = Listeners::start(loopback_listener_settings(old_port), build).await.unwrap();
+    let response = http_get_with_host(old_port, "/echo", "reload.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Response: {}", response);
+
+    // A request in flight on the old listener while it is replaced
+    let in_flight = tokio::spawn(async move {
+        http_get_with_host(old_port, "/slow", "reload.local").await.unwrap()
+    });
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    // A port that is taken fails the reload and keeps the current listener
+    let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let taken_port = taken.local_addr().unwrap().port();
+    assert!(listeners.replace(loopback_listener_settings(taken_port), build).await.is_err());
+    assert_eq!(listeners.http_addr().unwrap().port(), old_port);
+    drop(taken);
+
+    let new_port = free_port();
+    assert!(listeners.replace(loopback_listener_settings(new_port), build).await.unwrap());
+    assert!(!listeners.replace(loopback_listener_settings(new_port), build).await.unwrap());
+    let response = http_get_with_host(new_port, "/echo", "reload.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Response: {}", response);
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(TcpStream::connect(("127.0.0.1", old_port)).await.is_err());
+    let response = in_flight.await.unwrap();
+    assert!(response.contains("slow response"), "Response: {}", response);
+
+    // Settings changing without the address reuse the bound listener
+    let mut settings = loopback_listener_settings(new_port);
+    settings.https_redirect_status = 308;
+    assert!(listeners.replace(settings, build).await.unwrap());
+    assert_eq!(listeners.http_addr().unwrap().port(), new_port);
+    let response = http_get_with_host(new_port, "/echo", "reload.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Response: {}", response);
+
+    listeners.shutdown().await;
+    assert!(TcpStream::connect(("127.0.0.1", new_port)).await.is_err());
+    manager.stop_all().await;
+}
+
 #[tokio::test]
 async fn test_connection_pool_stats() {
     if !mock_server_path().exists() {
@@ -1903,6 +2396,7 @@ async fn test_json_error_response_unknown_host() {
         response
     );
     assert!(response.contains("UNKNOWN_HOST"), "Response: {}", response);
+    assert!(response.contains(r#""request_id":""#), "Response: {}", response);
 
     // Cleanup
     manager.stop_all().await;
@@ -2525,6 +3019,88 @@ async fn test_x_forwarded_for_appends() {
     let _ = proxy_handle.await;
 }
 
+#[tokio::test]
+async fn test_deadline_headers() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut backend = mock_backend_config(free_port());
+    backend.request_timeout_secs = Some(20);
+    backend.deadline_headers = true;
+    let mut configs = HashMap::new();
+    configs.insert("deadline.local".to_string(), backend);
+    configs.insert("plain.local".to_string(), mock_backend_config(free_port()));
+    let harness = TestHarness::start(configs).await;
+
+    let before = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    let response = http_get_with_timeout(harness.proxy_port, "/headers", "deadline.local", &[("grpc-timeout", "2S")])
+        .await
+        .unwrap();
+    assert!(response.contains("200 OK"), "Response: {}", response);
+
+    let deadline: u64 = response
+        .split("\"x-request-deadline\":\"")
+        .nth(1)
+        .and_then(|rest| rest.split('"').next())
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| panic!("Missing x-request-deadline: {}", response));
+    assert!(deadline >= before + 2000 && deadline < before + 20_000, "Unexpected deadline: {}", deadline);
+    assert!(response.contains("\"grpc-timeout\":\"2000m\""), "Response: {}", response);
+
+    let response = http_get_with_timeout(harness.proxy_port, "/headers", "plain.local", &[])
+        .await
+        .unwrap();
+    assert!(!response.contains("x-request-deadline"), "Response: {}", response);
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn test_client_disconnect_cancels_upstream_request() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut configs = HashMap::new();
+    configs.insert("app.local".to_string(), mock_backend_config(free_port()));
+    let harness = TestHarness::start(configs).await;
+
+    // Start the backend so the slow request is in flight right away
+    let response = http_get_with_host(harness.proxy_port, "/echo", "app.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Request failed: {}", response);
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", harness.proxy_port)).await.unwrap();
+    stream
+        .write_all(b"GET /slow HTTP/1.1\r\nHost: app.local\r\n\r\n")
+        .await
+        .unwrap();
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(1);
+    while harness.manager.get_in_flight("app.local") == 0 && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(harness.manager.get_in_flight("app.local"), 1);
+    drop(stream);
+
+    // The backend takes 2s to respond; cancellation must happen well before that
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(1);
+    while harness.manager.get_cancelled("app.local") == 0 && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(harness.manager.get_cancelled("app.local"), 1);
+    assert_eq!(harness.manager.get_in_flight("app.local"), 0);
+
+    let response = admin_request(harness.admin_port, "GET", "/backends").await;
+    assert!(response.contains("\"cancelled\":1"), "Unexpected response: {}", response);
+
+    harness.stop().await;
+}
+
 // ============================================================================
 // Admin API Tests
 // ============================================================================
@@ -2576,6 +3152,47 @@ async fn test_admin_version_endpoint() {
     let _ = admin_handle.await;
 }
 
+#[tokio::test]
+async fn test_admin_profiling_endpoints() {
+    let admin_port = free_port();
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let admin_url = format!("http://127.0.0.1:{}", admin_port);
+    let manager = ProcessManager::new(HashMap::new(), BackendDefaults::default(), admin_url);
+
+    let admin_addr: SocketAddr = format!("127.0.0.1:{}", admin_port).parse().unwrap();
+    let profiler = Arc::new(Profiler::new(&ProfilingConfig { enabled: true, max_seconds: 5 }));
+    let admin_server = AdminServer::new(admin_addr, Arc::clone(&manager), shutdown_rx, "test-token".to_string())
+        .with_profiler(profiler);
+    let admin_handle = tokio::spawn(async move {
+        let _ = admin_server.run().await;
+    });
+    assert!(wait_for_port(admin_port, Duration::from_secs(2)).await);
+
+    let response = http_get(admin_port, "/debug/pprof/profile?seconds=1").await.unwrap();
+    assert!(response.contains("401"), "Unexpected response: {}", response);
+
+    // The duration is bounded by max_seconds
+    for path in ["/debug/pprof/profile?seconds=6", "/debug/pprof/heap?seconds=0"] {
+        let response = admin_request(admin_port, "GET", path).await;
+        assert!(response.contains("400 Bad Request"), "Unexpected response: {}", response);
+        assert!(response.contains("between 1 and 5"), "Unexpected response: {}", response);
+    }
+
+    // Builds without the profilers say so
+    if !cfg!(all(unix, feature = "pprof")) {
+        let response = admin_request(admin_port, "GET", "/debug/pprof/profile?seconds=1").await;
+        assert!(response.contains("501"), "Unexpected response: {}", response);
+        assert!(response.contains("'pprof' feature"), "Unexpected response: {}", response);
+    }
+    if !cfg!(all(unix, feature = "jemalloc")) {
+        let response = admin_request(admin_port, "GET", "/debug/pprof/heap?seconds=1").await;
+        assert!(response.contains("501"), "Unexpected response: {}", response);
+    }
+
+    let _ = shutdown_tx.send(true);
+    let _ = admin_handle.await;
+}
+
 // ============================================================================
 // WebSocket Proxy Tests
 // ============================================================================
@@ -3177,24 +3794,133 @@ async fn test_http2_admin_api() {
     let _ = admin_handle.await;
 }
 
-/// Test the /backends endpoint returns JSON with all configured backends
+/// HTTP/2 clients send the host as :authority rather than a Host header
 #[tokio::test]
-async fn test_backends_endpoint() {
-    // Start admin server with test configuration
-    let admin_port = 19370;
+async fn test_http2_request_without_host_header() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
 
-    let mut backends = HashMap::new();
-    backends.insert("app1.test".to_string(), mock_backend_config(19371));
-    backends.insert("app2.test".to_string(), mock_backend_config(19372));
+    let mut configs = HashMap::new();
+    configs.insert("h2-authority.local".to_string(), mock_backend_config(free_port()));
+    let harness = TestHarness::start(configs).await;
 
-    let process_manager = ProcessManager::new(
-        backends,
-        BackendDefaults::default(),
-        format!("http://127.0.0.1:{}", admin_port),
-    );
+    let stream = TcpStream::connect(format!("127.0.0.1:{}", harness.proxy_port)).await.unwrap();
+    let (h2, connection) = h2::client::handshake(stream).await.unwrap();
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("HTTP/2 connection error: {}", e);
+        }
+    });
 
-    let (shutdown_tx, shutdown_rx) = watch::channel(false);
-    let admin = AdminServer::new(
+    let mut h2 = h2.ready().await.unwrap();
+    let request = http::Request::builder()
+        .uri("http://h2-authority.local/env/SPAWNGATE_BACKEND")
+        .body(())
+        .unwrap();
+    let (response, _send_stream) = h2.send_request(request, true).unwrap();
+    let response = response.await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let mut body_stream = response.into_body();
+    let mut body = String::new();
+    while let Some(chunk) = body_stream.data().await {
+        let chunk = chunk.unwrap();
+        body.push_str(&String::from_utf8_lossy(&chunk));
+        body_stream.flow_control().release_capacity(chunk.len()).unwrap();
+    }
+    assert_eq!(body, "h2-authority.local");
+
+    harness.stop().await;
+}
+
+/// Test an HTTP/1.1 request upgraded to HTTP/2 with `Upgrade: h2c`
+#[tokio::test]
+async fn test_h2c_upgrade() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut configs = HashMap::new();
+    configs.insert("h2c.local".to_string(), mock_backend_config(free_port()));
+    let harness = TestHarness::start(configs).await;
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", harness.proxy_port)).await.unwrap();
+    stream
+        .write_all(
+            b"GET /env/SPAWNGATE_BACKEND HTTP/1.1\r\n\
+              Host: h2c.local\r\n\
+              Connection: Upgrade, HTTP2-Settings\r\n\
+              Upgrade: h2c\r\n\
+              HTTP2-Settings: AAMAAABkAARAAAAAAAIAAAAA\r\n\r\n",
+        )
+        .await
+        .unwrap();
+
+    // Read the 101 response byte by byte so no HTTP/2 frames are consumed
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        head.push(stream.read_u8().await.unwrap());
+    }
+    let head = String::from_utf8_lossy(&head).to_lowercase();
+    assert!(head.starts_with("http/1.1 101"), "Unexpected response: {}", head);
+    assert!(head.contains("upgrade: h2c"), "Unexpected response: {}", head);
+
+    // Connection preface and an empty SETTINGS frame
+    stream.write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n").await.unwrap();
+    stream.write_all(&[0, 0, 0, 4, 0, 0, 0, 0, 0]).await.unwrap();
+
+    // The upgrade request is answered on stream 1
+    let body = tokio::time::timeout(Duration::from_secs(10), async {
+        let mut body = Vec::new();
+        loop {
+            let mut header = [0u8; 9];
+            stream.read_exact(&mut header).await.unwrap();
+            let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+            let (kind, flags) = (header[3], header[4]);
+            let stream_id = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff;
+            let mut payload = vec![0; length];
+            stream.read_exact(&mut payload).await.unwrap();
+            if stream_id != 1 {
+                continue;
+            }
+            if kind == 0 {
+                body.extend_from_slice(&payload);
+            }
+            if flags & 0x1 != 0 {
+                return body;
+            }
+        }
+    })
+    .await
+    .expect("response on stream 1");
+    assert_eq!(String::from_utf8_lossy(&body), "h2c.local");
+
+    // Graceful shutdown would wait for a PING acknowledgement this client never sends
+    drop(stream);
+    harness.stop().await;
+}
+
+/// Test the /backends endpoint returns JSON with all configured backends
+#[tokio::test]
+async fn test_backends_endpoint() {
+    // Start admin server with test configuration
+    let admin_port = 19370;
+
+    let mut backends = HashMap::new();
+    backends.insert("app1.test".to_string(), mock_backend_config(19371));
+    backends.insert("app2.test".to_string(), mock_backend_config(19372));
+
+    let process_manager = ProcessManager::new(
+        backends,
+        BackendDefaults::default(),
+        format!("http://127.0.0.1:{}", admin_port),
+    );
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let admin = AdminServer::new(
         SocketAddr::from(([127, 0, 0, 1], admin_port)),
         Arc::clone(&process_manager),
         shutdown_rx,
@@ -3557,6 +4283,51 @@ async fn test_https_redirect_standard_port() {
     let _ = http_proxy_handle.await;
 }
 
+/// Test per-backend and per-path HTTPS redirect exemptions and the redirect status
+#[tokio::test]
+async fn test_https_redirect_exemptions() {
+    let http_port = 30610;
+    let admin_port = 30611;
+
+    let mut exempt = BackendConfig::local("unused", 30612);
+    exempt.https_redirect = false;
+    let mut partial = BackendConfig::local("unused", 30613);
+    partial.https_redirect_exclude = vec!["/internal".to_string()];
+    let mut configs = HashMap::new();
+    configs.insert("exempt.local".to_string(), exempt);
+    configs.insert("partial.local".to_string(), partial);
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let manager = ProcessManager::new(configs, BackendDefaults::default(), format!("http://127.0.0.1:{}", admin_port));
+
+    let http_addr: SocketAddr = format!("127.0.0.1:{}", http_port).parse().unwrap();
+    let http_proxy = ProxyServer::new(http_addr, Arc::clone(&manager), manager.shared_defaults(), shutdown_rx)
+        .with_https_redirect(443)
+        .with_https_redirect_status(hyper::StatusCode::PERMANENT_REDIRECT);
+    let http_proxy_handle = tokio::spawn(async move {
+        let _ = http_proxy.run().await;
+    });
+    assert!(wait_for_port(http_port, Duration::from_secs(2)).await);
+
+    async fn status_line(port: u16, host: &str, path: &str) -> String {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", path, host);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response.lines().next().unwrap_or_default().to_string()
+    }
+
+    // Redirected with the configured status
+    assert!(status_line(http_port, "partial.local", "/app").await.contains("308"));
+    // Exempted requests reach the backend (which fails to start here)
+    assert!(!status_line(http_port, "partial.local", "/internal/status").await.contains("308"));
+    assert!(!status_line(http_port, "exempt.local", "/").await.contains("308"));
+
+    let _ = shutdown_tx.send(true);
+    let _ = http_proxy_handle.await;
+}
+
 // ============================================================================
 // Docker Backend Integration Tests
 // ============================================================================
@@ -4397,6 +5168,64 @@ async fn test_docker_restart_after_stop() {
     cleanup_docker_container(container_name).await;
 }
 
+#[tokio::test]
+async fn test_docker_sidecars() {
+    if !docker_available().await {
+        eprintln!("Skipping test: Docker not available");
+        return;
+    }
+
+    let port = 31105;
+    let container_name = "spawngate-test-sidecars";
+    let sidecar_name = "spawngate-test-sidecars-echo";
+
+    cleanup_docker_container(container_name).await;
+
+    let mut config = docker_backend_config(port);
+    config.container_name = Some(container_name.to_string());
+    config.args = vec!["-text=main".to_string(), format!("-listen=:{}", port)];
+    config.sidecars = vec![SidecarConfig {
+        name: "echo".to_string(),
+        image: "hashicorp/http-echo:latest".to_string(),
+        args: vec!["-text=sidecar".to_string()],
+        env: HashMap::new(),
+        memory: Some("64m".to_string()),
+        cpus: None,
+        registry_auth: None,
+    }];
+
+    let mut configs = HashMap::new();
+    configs.insert("docker.sidecars.local".to_string(), config);
+    let manager = ProcessManager::new(
+        configs,
+        BackendDefaults::default(),
+        "http://127.0.0.1:9999".to_string(),
+    );
+
+    manager.start_backend("docker.sidecars.local").await.unwrap();
+    let start = std::time::Instant::now();
+    while start.elapsed() < Duration::from_secs(30) {
+        if manager.get_state("docker.sidecars.local") == BackendState::Ready {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(manager.get_state("docker.sidecars.local"), BackendState::Ready);
+    assert!(http_get(port, "/").await.unwrap().contains("main"));
+
+    // The sidecar runs alongside, without a published port
+    let docker = DockerManager::new(None).await.unwrap();
+    assert!(docker.is_running(sidecar_name).await);
+
+    // Stopping the backend stops its sidecars too
+    manager.stop_backend("docker.sidecars.local").await;
+    assert_eq!(manager.get_state("docker.sidecars.local"), BackendState::Stopped);
+    assert!(!docker.is_running(sidecar_name).await);
+
+    cleanup_docker_container(sidecar_name).await;
+    cleanup_docker_container(container_name).await;
+}
+
 #[tokio::test]
 async fn test_docker_concurrent_starts() {
     if !docker_available().await {
@@ -4661,3 +5490,2330 @@ async fn test_hot_reload_running_backend_continues() {
     let _ = admin_handle.await;
     let _ = proxy_handle.await;
 }
+
+// ============================================================================
+// Pipeline Harness Tests
+// ============================================================================
+
+#[tokio::test]
+async fn test_harness_proxies_to_mock_server() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut configs = HashMap::new();
+    configs.insert("harness.local".to_string(), mock_backend_config(free_port()));
+    let harness = TestHarness::start(configs).await;
+
+    let response = http_get_with_host(harness.proxy_port, "/echo", "harness.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Request failed: {}", response);
+    assert_eq!(harness.manager.get_state("harness.local"), BackendState::Ready);
+
+    let response = http_get_with_auth(harness.admin_port, "/backends", "test-token").await.unwrap();
+    assert!(response.contains("harness.local"));
+    // Resource usage only on request, since it may have to ask the container runtime
+    assert!(!response.contains("\"resources\""), "Unexpected response: {}", response);
+    let response = http_get_with_auth(harness.admin_port, "/backends?resources=true", "test-token").await.unwrap();
+    assert!(response.contains("\"resources\":null"), "Unexpected response: {}", response);
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn test_harness_unknown_host() {
+    let harness = TestHarness::start(HashMap::new()).await;
+
+    let response = http_get_with_host(harness.proxy_port, "/", "nobody.local").await.unwrap();
+    assert!(response.contains("404"));
+    assert!(response.contains("UNKNOWN_HOST"));
+
+    harness.stop().await;
+}
+
+// ============================================================================
+// Internal Redirect Tests
+// ============================================================================
+
+#[tokio::test]
+async fn test_internal_redirect_serves_file() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let root = std::env::temp_dir().join(format!("spawngate-accel-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("report.txt"), "protected contents").unwrap();
+
+    let mut config = mock_backend_config(free_port());
+    config.internal_redirect = true;
+    config.internal_root = Some(root.to_string_lossy().to_string());
+
+    let mut configs = HashMap::new();
+    configs.insert("files.local".to_string(), config);
+    let harness = TestHarness::start(configs).await;
+
+    // X-Accel-Redirect relative to internal_root
+    let response = http_get_with_host(harness.proxy_port, "/accel/report.txt", "files.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Request failed: {}", response);
+    assert!(response.to_lowercase().contains("content-disposition: attachment"));
+    assert!(response.ends_with("protected contents"));
+    assert!(!response.to_lowercase().contains("x-accel-redirect"));
+
+    // X-Sendfile with an absolute path
+    let path = format!("/sendfile{}", root.join("report.txt").display());
+    let response = http_get_with_host(harness.proxy_port, &path, "files.local").await.unwrap();
+    assert!(response.ends_with("protected contents"), "Unexpected response: {}", response);
+
+    // Traversal outside the root is refused
+    let response = http_get_with_host(harness.proxy_port, "/sendfile/etc/hostname", "files.local").await.unwrap();
+    assert!(response.contains("404"));
+    assert!(response.contains("FILE_NOT_FOUND"));
+
+    harness.stop().await;
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn test_internal_redirect_to_backend() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut app = mock_backend_config(free_port());
+    app.internal_redirect = true;
+
+    let mut configs = HashMap::new();
+    configs.insert("app.local".to_string(), app);
+    configs.insert("media.local".to_string(), mock_backend_config(free_port()));
+    let harness = TestHarness::start(configs).await;
+
+    let response = http_get_with_host(harness.proxy_port, "/accel/@media.local/echo", "app.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Request failed: {}", response);
+    assert!(response.ends_with("echo response"));
+    assert_eq!(harness.manager.get_state("media.local"), BackendState::Ready);
+
+    let response = http_get_with_host(harness.proxy_port, "/accel/@nobody.local/echo", "app.local").await.unwrap();
+    assert!(response.contains("BACKEND_CONFIG_ERROR"));
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn test_internal_redirect_ignored_when_disabled() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut configs = HashMap::new();
+    configs.insert("plain.local".to_string(), mock_backend_config(free_port()));
+    let harness = TestHarness::start(configs).await;
+
+    let response = http_get_with_host(harness.proxy_port, "/accel/report.txt", "plain.local").await.unwrap();
+    assert!(response.contains("200 OK"));
+    assert!(response.to_lowercase().contains("x-accel-redirect: /report.txt"));
+
+    harness.stop().await;
+}
+
+// ============================================================================
+// Path Allowlist Tests
+// ============================================================================
+
+#[tokio::test]
+async fn test_disallowed_path_does_not_spawn() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut config = mock_backend_config(free_port());
+    config.allowed_paths = vec!["/echo".to_string(), "/health".to_string()];
+
+    let mut configs = HashMap::new();
+    configs.insert("guarded.local".to_string(), config);
+    let harness = TestHarness::start(configs).await;
+
+    let response = http_get_with_host(harness.proxy_port, "/wp-login.php", "guarded.local").await.unwrap();
+    assert!(response.contains("404"), "Unexpected response: {}", response);
+    assert!(response.contains("PATH_NOT_ALLOWED"));
+    assert_eq!(harness.manager.get_state("guarded.local"), BackendState::Stopped);
+
+    let response = http_get_with_host(harness.proxy_port, "/echo", "guarded.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Request failed: {}", response);
+    assert_eq!(harness.manager.get_state("guarded.local"), BackendState::Ready);
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn test_normalized_path_cannot_bypass_allowlist() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut config = mock_backend_config(free_port());
+    config.allowed_paths = vec!["/health".to_string()];
+
+    let mut configs = HashMap::new();
+    configs.insert("guarded.local".to_string(), config);
+    let harness = TestHarness::start(configs).await;
+
+    for path in ["/health/../wp-login.php", "/health/%2e%2e/admin", "/health//..//admin"] {
+        let response = http_get_with_host(harness.proxy_port, path, "guarded.local").await.unwrap();
+        assert!(response.contains("PATH_NOT_ALLOWED"), "Unexpected response for {}: {}", path, response);
+    }
+    assert_eq!(harness.manager.get_state("guarded.local"), BackendState::Stopped);
+
+    let response = http_get_with_host(harness.proxy_port, "/health%zz", "guarded.local").await.unwrap();
+    assert!(response.contains("400 Bad Request"), "Unexpected response: {}", response);
+    assert!(response.contains("INVALID_PATH"));
+
+    // The backend receives the normalized path
+    let response = http_get_with_host(harness.proxy_port, "//%68ealth", "guarded.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Unexpected response: {}", response);
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn test_waf_blocks_or_logs_matching_requests() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut blocking = mock_backend_config(free_port());
+    blocking.waf = Some(true);
+    let mut logging = mock_backend_config(free_port());
+    logging.waf = Some(true);
+    logging.waf_mode = Some(spawngate::config::WafMode::Log);
+
+    let mut configs = HashMap::new();
+    configs.insert("blocking.local".to_string(), blocking);
+    configs.insert("logging.local".to_string(), logging);
+    configs.insert("open.local".to_string(), mock_backend_config(free_port()));
+    let harness = TestHarness::start(configs).await;
+
+    let attack = "/echo?id=1%27%20OR%20%271%27=%271";
+    let response = http_get_with_host(harness.proxy_port, attack, "blocking.local").await.unwrap();
+    assert!(response.contains("403 Forbidden"), "Unexpected response: {}", response);
+    assert!(response.contains("REQUEST_BLOCKED"));
+    assert_eq!(harness.manager.get_state("blocking.local"), BackendState::Stopped);
+
+    // Missing User-Agent and Accept headers alone stay below the threshold
+    let response = http_get_with_host(harness.proxy_port, "/echo?id=1", "blocking.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Unexpected response: {}", response);
+
+    for host in ["logging.local", "open.local"] {
+        let response = http_get_with_host(harness.proxy_port, attack, host).await.unwrap();
+        assert!(response.contains("200 OK"), "Unexpected response for {}: {}", host, response);
+    }
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn test_response_rewrites() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut config = mock_backend_config(free_port());
+    config.response_rewrites = vec![spawngate::config::ResponseRewrite {
+        find: "rewrite.local".to_string(),
+        replace: "app.example.com".to_string(),
+    }];
+
+    let mut configs = HashMap::new();
+    configs.insert("rewrite.local".to_string(), config);
+    let harness = TestHarness::start(configs).await;
+
+    // The mock echoes the request headers as JSON
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", harness.proxy_port)).await.unwrap();
+    let request = "GET /headers HTTP/1.1\r\nHost: rewrite.local\r\nAccept-Encoding: gzip\r\nConnection: close\r\n\r\n";
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    assert!(response.contains("200 OK"), "Unexpected response: {}", response);
+    assert!(response.contains("\"host\":\"app.example.com\""), "Body not rewritten: {}", response);
+    assert!(!response.contains("rewrite.local"));
+    assert!(!response.contains("accept-encoding"), "Accept-Encoding forwarded: {}", response);
+
+    // Plain text is left alone
+    let response = http_get_with_host(harness.proxy_port, "/echo", "rewrite.local").await.unwrap();
+    assert!(response.contains("echo response"));
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn test_spawn_wait_retry_hints() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut config = mock_backend_config_with_delay(free_port(), 600);
+    config.spawn_wait_secs = Some(0);
+    let mut configs = HashMap::new();
+    configs.insert("slow.local".to_string(), config);
+    let harness = TestHarness::start(configs).await;
+
+    let wait_ready = || async {
+        for _ in 0..100 {
+            if harness.manager.get_state("slow.local") == BackendState::Ready {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("Backend did not become ready");
+    };
+
+    // The first cold start has no history to estimate from
+    let response = http_get_with_host(harness.proxy_port, "/echo", "slow.local").await.unwrap().to_lowercase();
+    assert!(response.contains("503"), "Unexpected response: {}", response);
+    assert!(response.contains("backend_starting"));
+    assert!(response.contains("retry-after: 1\r\n"), "Unexpected response: {}", response);
+    assert!(response.contains("x-queue-position: 1\r\n"));
+    assert!(!response.contains("x-estimated-ready-ms"));
+    wait_ready().await;
+
+    // Later cold starts are estimated from the earlier ones
+    harness.manager.stop_backend("slow.local").await;
+    let response = http_get_with_host(harness.proxy_port, "/echo", "slow.local").await.unwrap().to_lowercase();
+    assert!(response.contains("503"), "Unexpected response: {}", response);
+    let estimate: u64 = response
+        .lines()
+        .find_map(|line| line.strip_prefix("x-estimated-ready-ms: "))
+        .expect("estimate header")
+        .trim()
+        .parse()
+        .unwrap();
+    assert!((1..10_000).contains(&estimate), "Unexpected estimate: {}", estimate);
+    wait_ready().await;
+
+    let response = http_get_with_host(harness.proxy_port, "/echo", "slow.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Request failed: {}", response);
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn test_cold_start_queue_depth() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut config = mock_backend_config_with_delay(free_port(), 1000);
+    config.max_queue_depth = Some(2);
+    let mut configs = HashMap::new();
+    configs.insert("slow.local".to_string(), config);
+    let harness = TestHarness::start(configs).await;
+
+    // Two requests wait for the cold start
+    let port = harness.proxy_port;
+    let waiting: Vec<_> = (0..2)
+        .map(|_| tokio::spawn(async move { http_get_with_host(port, "/echo", "slow.local").await.unwrap() }))
+        .collect();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let backends = admin_request(harness.admin_port, "GET", "/backends").await;
+    assert!(backends.contains(r#""queued":2"#), "Unexpected response: {}", backends);
+    assert!(backends.contains(r#""max_queue_depth":2"#), "Unexpected response: {}", backends);
+
+    // A third is turned away instead of joining them
+    let response = http_get_with_host(port, "/echo", "slow.local").await.unwrap().to_lowercase();
+    assert!(response.contains("503"), "Unexpected response: {}", response);
+    assert!(response.contains("cold_start_queue_full"), "Unexpected response: {}", response);
+    assert!(response.contains("retry-after: 1\r\n"), "Unexpected response: {}", response);
+
+    // The queued requests are released once the backend is ready
+    for request in waiting {
+        let response = request.await.unwrap();
+        assert!(response.contains("200 OK"), "Request failed: {}", response);
+    }
+    assert_eq!(harness.manager.get_queued("slow.local"), 0);
+    let response = http_get_with_host(port, "/echo", "slow.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Request failed: {}", response);
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn test_cold_start_queue_priority() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut config = mock_backend_config_with_delay(free_port(), 1000);
+    config.max_queue_depth = Some(1);
+    config.priority_rules = vec![PriorityRule {
+        class: PriorityClass::Low,
+        path_prefix: None,
+        headers: HashMap::from([("x-background".to_string(), "*".to_string())]),
+        methods: Vec::new(),
+    }];
+    let mut configs = HashMap::new();
+    configs.insert("slow.local".to_string(), config);
+    let harness = TestHarness::start(configs).await;
+    let port = harness.proxy_port;
+
+    // A background request waits for the cold start
+    let background = [("X-Background", "1")];
+    let low = tokio::spawn(async move {
+        http_get_with_timeout(port, "/echo", "slow.local", &background).await.unwrap()
+    });
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    // A normal request takes its place, and the background one is turned away
+    let normal = tokio::spawn(async move { http_get_with_host(port, "/echo", "slow.local").await.unwrap() });
+    let response = low.await.unwrap().to_lowercase();
+    assert!(response.contains("503"), "Unexpected response: {}", response);
+    assert!(response.contains("cold_start_queue_full"), "Unexpected response: {}", response);
+
+    // Further background requests are shed while the queue is full
+    let response = http_get_with_timeout(port, "/echo", "slow.local", &background).await.unwrap();
+    assert!(response.contains("COLD_START_QUEUE_FULL"), "Unexpected response: {}", response);
+
+    let response = normal.await.unwrap();
+    assert!(response.contains("200 OK"), "Request failed: {}", response);
+
+    let stats = harness.manager.queue_stats("slow.local");
+    assert_eq!((stats[&PriorityClass::Low].admitted, stats[&PriorityClass::Low].shed), (1, 1));
+    assert_eq!(stats[&PriorityClass::Low].preempted, 1);
+    assert_eq!((stats[&PriorityClass::Normal].admitted, stats[&PriorityClass::Normal].queued), (1, 0));
+    let backends = admin_request(harness.admin_port, "GET", "/backends").await;
+    assert!(backends.contains(r#""queued_by_class":{"#), "Unexpected response: {}", backends);
+
+    harness.stop().await;
+}
+
+// ============================================================================
+// Cold-Start Throttle Tests
+// ============================================================================
+
+#[tokio::test]
+async fn test_cold_start_throttle_bans_client() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let throttle = Arc::new(ColdStartThrottle::new(&spawngate::config::ColdStartThrottleConfig {
+        enabled: true,
+        burst: 1,
+        window_secs: 60,
+        ban_secs: 120,
+    }));
+
+    let mut configs = HashMap::new();
+    configs.insert("first.local".to_string(), mock_backend_config(free_port()));
+    configs.insert("second.local".to_string(), mock_backend_config(free_port()));
+    let harness = TestHarness::start_with_throttle(configs, Some(throttle)).await;
+
+    // First cold start is within the burst
+    let response = http_get_with_host(harness.proxy_port, "/echo", "first.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Request failed: {}", response);
+
+    // Second cold start exceeds it and the backend stays down
+    let response = http_get_with_timeout(harness.proxy_port, "/echo", "second.local", &[("X-Request-ID", "throttled-1")])
+        .await
+        .unwrap();
+    assert!(response.contains("429"), "Unexpected response: {}", response);
+    assert!(response.contains("COLD_START_THROTTLED"));
+    assert!(response.to_lowercase().contains("retry-after: 120"));
+    // The body carries the request ID and delay for API clients
+    assert!(response.contains(r#""request_id":"throttled-1""#), "Unexpected response: {}", response);
+    assert!(response.contains(r#""retry_after":120"#), "Unexpected response: {}", response);
+    assert_eq!(harness.manager.get_state("second.local"), BackendState::Stopped);
+
+    // Running backends are not affected by the ban
+    let response = http_get_with_host(harness.proxy_port, "/echo", "first.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Request failed: {}", response);
+
+    let response = http_get_with_auth(harness.admin_port, "/throttle", "test-token").await.unwrap();
+    assert!(response.contains("\"allowed\":1"), "Unexpected stats: {}", response);
+    assert!(response.contains("\"bans\":1"));
+    assert!(response.contains("\"banned_clients\":1"));
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn test_admin_lockout_after_failed_authentication() {
+    let admin_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let admin_addr = admin_listener.local_addr().unwrap();
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let mut sleeper = BackendConfig::local("sleep", free_port());
+    sleeper.args = vec!["60".to_string()];
+    sleeper.startup_timeout_secs = Some(60);
+    sleeper.health_check_interval_ms = Some(1000);
+    sleeper.shutdown_grace_period_secs = Some(1);
+    let configs = HashMap::from([("sleeper.local".to_string(), sleeper)]);
+    let manager = ProcessManager::new(configs, BackendDefaults::default(), format!("http://{}", admin_addr));
+
+    let lockout = Arc::new(AuthLockout::new(&spawngate::config::AdminLockoutConfig {
+        enabled: true,
+        max_failures: 2,
+        window_secs: 60,
+        lockout_secs: 30,
+        max_lockout_secs: 300,
+    }));
+    let admin_server = AdminServer::new(admin_addr, Arc::clone(&manager), shutdown_rx, "test-token".to_string())
+        .with_auth_lockout(lockout);
+    let handle = tokio::spawn(async move {
+        let _ = admin_server.serve(admin_listener).await;
+    });
+    let port = admin_addr.port();
+
+    for _ in 0..3 {
+        let response = http_get_with_auth(port, "/backends", "wrong-token").await.unwrap();
+        assert!(response.contains("401"), "Unexpected response: {}", response);
+    }
+
+    // Locked out, even with the right token
+    let response = http_get_with_auth(port, "/backends", "test-token").await.unwrap();
+    assert!(response.contains("429"), "Unexpected response: {}", response);
+    assert!(response.to_lowercase().contains("retry-after: 30"));
+
+    // Unauthenticated endpoints stay available
+    let response = http_get(port, "/health").await.unwrap();
+    assert!(response.contains("200 OK"));
+
+    // So do ready callbacks from backends sharing the locked out IP
+    manager.start_backend("sleeper.local").await.unwrap();
+    let token = manager.instance("sleeper.local").unwrap().token;
+    for (token, expected) in [("wrong", "429"), (token.as_str(), "200 OK")] {
+        let mut stream = TcpStream::connect(admin_addr).await.unwrap();
+        let request = format!(
+            "POST /ready/sleeper.local?token={} HTTP/1.1\r\nHost: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            token, admin_addr
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.contains(expected), "Unexpected response: {}", response);
+    }
+    assert_eq!(manager.get_state("sleeper.local"), BackendState::Ready);
+
+    manager.stop_backend("sleeper.local").await;
+    let _ = shutdown_tx.send(true);
+    let _ = handle.await;
+}
+
+// ============================================================================
+// Fallback Backend Tests
+// ============================================================================
+
+#[tokio::test]
+async fn test_spawn_failure_serves_fallback() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut primary = BackendConfig::local("/nonexistent/spawngate-app", free_port());
+    primary.fallback = Some("status.local".to_string());
+
+    let mut configs = HashMap::new();
+    configs.insert("app.local".to_string(), primary);
+    configs.insert("status.local".to_string(), mock_backend_config(free_port()));
+    let harness = TestHarness::start(configs).await;
+
+    let response = http_get_with_host(harness.proxy_port, "/echo", "app.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Request failed: {}", response);
+    assert!(response.ends_with("echo response"));
+    assert_eq!(harness.manager.get_state("status.local"), BackendState::Ready);
+    assert_eq!(harness.manager.get_state("app.local"), BackendState::Stopped);
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn test_unhealthy_primary_follows_one_fallback() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut primary = mock_backend_config(free_port());
+    primary.fallback = Some("status.local".to_string());
+    let mut status = BackendConfig::local("/nonexistent/spawngate-status", free_port());
+    status.fallback = Some("last.local".to_string());
+
+    let mut configs = HashMap::new();
+    configs.insert("app.local".to_string(), primary);
+    configs.insert("status.local".to_string(), status);
+    configs.insert("last.local".to_string(), mock_backend_config(free_port()));
+    let harness = TestHarness::start(configs).await;
+
+    let response = http_get_with_host(harness.proxy_port, "/echo", "app.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Request failed: {}", response);
+    harness.manager.mark_unhealthy("app.local");
+
+    // The fallback fails to start, and its own fallback isn't followed
+    let response = http_get_with_host(harness.proxy_port, "/echo", "app.local").await.unwrap();
+    assert!(response.contains("BACKEND_START_FAILED"), "Unexpected response: {}", response);
+    assert_eq!(harness.manager.get_state("last.local"), BackendState::Stopped);
+
+    // Requested directly, the fallback has a fallback of its own
+    let response = http_get_with_host(harness.proxy_port, "/echo", "status.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Request failed: {}", response);
+    assert_eq!(harness.manager.get_state("last.local"), BackendState::Ready);
+
+    harness.stop().await;
+}
+
+// ============================================================================
+// Routing Rule Tests
+// ============================================================================
+
+#[tokio::test]
+async fn test_routes_by_media_type() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut api = mock_backend_config(free_port());
+    api.routes = vec![RouteRule {
+        backend: "ssr.local".to_string(),
+        path_prefix: None,
+        strip_prefix: false,
+        priority: 0,
+        content_type: Vec::new(),
+        accept: vec!["text/html".to_string()],
+        headers: HashMap::new(),
+        cookies: HashMap::new(),
+        methods: Vec::new(),
+        read_your_writes_secs: 0,
+    }];
+    let mut configs = HashMap::new();
+    configs.insert("app.local".to_string(), api);
+    configs.insert("ssr.local".to_string(), mock_backend_config(free_port()));
+    let harness = TestHarness::start(configs).await;
+
+    let page = [("Accept", "text/html,*/*;q=0.8")];
+    let response = http_get_with_timeout(harness.proxy_port, "/env/SPAWNGATE_BACKEND", "app.local", &page)
+        .await
+        .unwrap();
+    assert!(response.ends_with("\r\n\r\nssr.local"), "Unexpected response: {}", response);
+    assert_eq!(harness.manager.get_state("app.local"), BackendState::Stopped);
+
+    let api_call = [("Accept", "application/json")];
+    let response = http_get_with_timeout(harness.proxy_port, "/env/SPAWNGATE_BACKEND", "app.local", &api_call)
+        .await
+        .unwrap();
+    assert!(response.ends_with("\r\n\r\napp.local"), "Unexpected response: {}", response);
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn test_routes_by_path_prefix() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut api = mock_backend_config(free_port());
+    api.routes = vec![RouteRule {
+        backend: "v2.local".to_string(),
+        path_prefix: Some("/v2".to_string()),
+        strip_prefix: true,
+        priority: 0,
+        content_type: Vec::new(),
+        accept: Vec::new(),
+        headers: HashMap::new(),
+        cookies: HashMap::new(),
+        methods: Vec::new(),
+        read_your_writes_secs: 0,
+    }];
+    let mut configs = HashMap::new();
+    configs.insert("api.local".to_string(), api);
+    configs.insert("v2.local".to_string(), mock_backend_config(free_port()));
+    let harness = TestHarness::start(configs).await;
+
+    let response = http_get_with_host(harness.proxy_port, "/v2/env/SPAWNGATE_BACKEND", "api.local")
+        .await
+        .unwrap();
+    assert!(response.ends_with("\r\n\r\nv2.local"), "Unexpected response: {}", response);
+
+    let response = http_get_with_host(harness.proxy_port, "/env/SPAWNGATE_BACKEND", "api.local")
+        .await
+        .unwrap();
+    assert!(response.ends_with("\r\n\r\napi.local"), "Unexpected response: {}", response);
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn test_routes_by_path_prefix_and_cookie() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut api = mock_backend_config(free_port());
+    api.routes = vec![RouteRule {
+        backend: "v2.local".to_string(),
+        path_prefix: Some("/v2".to_string()),
+        strip_prefix: true,
+        priority: 0,
+        content_type: Vec::new(),
+        accept: Vec::new(),
+        headers: HashMap::new(),
+        cookies: HashMap::from([("beta".to_string(), "1".to_string())]),
+        methods: Vec::new(),
+        read_your_writes_secs: 0,
+    }];
+    let mut configs = HashMap::new();
+    configs.insert("api.local".to_string(), api);
+    configs.insert("v2.local".to_string(), mock_backend_config(free_port()));
+    let harness = TestHarness::start(configs).await;
+
+    let beta = [("Cookie", "beta=1")];
+    let response = http_get_with_timeout(harness.proxy_port, "/v2/env/SPAWNGATE_BACKEND", "api.local", &beta)
+        .await
+        .unwrap();
+    assert!(response.ends_with("\r\n\r\nv2.local"), "Unexpected response: {}", response);
+
+    // Path and cookie must both match: api.local serves the unknown path
+    let response = http_get_with_host(harness.proxy_port, "/v2/env/SPAWNGATE_BACKEND", "api.local")
+        .await
+        .unwrap();
+    assert!(response.contains("Hello!"), "Unexpected response: {}", response);
+    assert_eq!(harness.manager.get_state("v2.local"), BackendState::Ready);
+    let response = http_get_with_timeout(harness.proxy_port, "/env/SPAWNGATE_BACKEND", "api.local", &beta)
+        .await
+        .unwrap();
+    assert!(response.ends_with("\r\n\r\napi.local"), "Unexpected response: {}", response);
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn test_routes_reads_to_replica() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut primary = mock_backend_config(free_port());
+    primary.routes = vec![RouteRule {
+        backend: "replica.local".to_string(),
+        path_prefix: None,
+        strip_prefix: false,
+        priority: 0,
+        content_type: Vec::new(),
+        accept: Vec::new(),
+        headers: HashMap::new(),
+        cookies: HashMap::new(),
+        methods: vec!["GET".to_string(), "HEAD".to_string()],
+        read_your_writes_secs: 5,
+    }];
+    let mut configs = HashMap::new();
+    configs.insert("db.local".to_string(), primary);
+    configs.insert("replica.local".to_string(), mock_backend_config(free_port()));
+    let harness = TestHarness::start(configs).await;
+
+    let response = http_get_with_host(harness.proxy_port, "/env/SPAWNGATE_BACKEND", "db.local")
+        .await
+        .unwrap();
+    assert!(response.ends_with("\r\n\r\nreplica.local"), "Unexpected response: {}", response);
+    assert!(!response.to_lowercase().contains("set-cookie"), "Unexpected response: {}", response);
+
+    // Writes go to the primary and mark the client
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", harness.proxy_port)).await.unwrap();
+    stream
+        .write_all(b"POST /env/SPAWNGATE_BACKEND HTTP/1.1\r\nHost: db.local\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.ends_with("\r\n\r\ndb.local"), "Unexpected response: {}", response);
+    assert!(
+        response.contains("spawngate_wrote=1; Max-Age=5; Path=/; HttpOnly; SameSite=Lax"),
+        "Unexpected response: {}",
+        response
+    );
+
+    // The client then reads its own write from the primary
+    let wrote = [("Cookie", "spawngate_wrote=1")];
+    let response = http_get_with_timeout(harness.proxy_port, "/env/SPAWNGATE_BACKEND", "db.local", &wrote)
+        .await
+        .unwrap();
+    assert!(response.ends_with("\r\n\r\ndb.local"), "Unexpected response: {}", response);
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn test_experiment_assignment() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut shop = mock_backend_config(free_port());
+    shop.experiment = Some(ExperimentConfig {
+        name: "checkout".to_string(),
+        key_cookie: Some("session".to_string()),
+        cookie_max_age_secs: 60,
+        variants: vec![
+            ExperimentVariant {
+                name: "control".to_string(),
+                backend: None,
+                weight: 1,
+            },
+            ExperimentVariant {
+                name: "redesign".to_string(),
+                backend: Some("shop-b.local".to_string()),
+                weight: 1,
+            },
+        ],
+    });
+    let mut configs = HashMap::new();
+    configs.insert("shop.local".to_string(), shop);
+    configs.insert("shop-b.local".to_string(), mock_backend_config(free_port()));
+    let harness = TestHarness::start(configs).await;
+
+    // New clients are bucketed and told their variant
+    let session = [("Cookie", "session=abc")];
+    let response = http_get_with_timeout(harness.proxy_port, "/env/SPAWNGATE_BACKEND", "shop.local", &session)
+        .await
+        .unwrap();
+    let served = response.rsplit("\r\n\r\n").next().unwrap().to_string();
+    let variant = if served == "shop-b.local" { "redesign" } else { "control" };
+    assert!(
+        response.contains(&format!("spawngate_exp_checkout={}; Max-Age=60", variant)),
+        "Unexpected response: {}",
+        response
+    );
+
+    // The same session lands in the same variant
+    let response = http_get_with_timeout(harness.proxy_port, "/env/SPAWNGATE_BACKEND", "shop.local", &session)
+        .await
+        .unwrap();
+    assert!(response.ends_with(&format!("\r\n\r\n{}", served)), "Unexpected response: {}", response);
+
+    // Assigned clients keep their variant, without a new cookie
+    for (variant, backend) in [("redesign", "shop-b.local"), ("control", "shop.local")] {
+        let cookie = format!("session=abc; spawngate_exp_checkout={}", variant);
+        let response = http_get_with_timeout(harness.proxy_port, "/env/SPAWNGATE_BACKEND", "shop.local", &[("Cookie", &cookie)])
+            .await
+            .unwrap();
+        assert!(response.ends_with(&format!("\r\n\r\n{}", backend)), "Unexpected response: {}", response);
+        assert!(!response.contains("spawngate_exp_checkout="), "Unexpected response: {}", response);
+    }
+
+    harness.stop().await;
+}
+
+// ============================================================================
+// Instance Environment Tests
+// ============================================================================
+
+#[tokio::test]
+async fn test_instance_environment_injected() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut configs = HashMap::new();
+    configs.insert("meta.local".to_string(), mock_backend_config(free_port()));
+    let harness = TestHarness::start(configs).await;
+
+    let response = http_get_with_host(harness.proxy_port, "/env/SPAWNGATE_BACKEND", "meta.local").await.unwrap();
+    assert!(response.ends_with("\r\n\r\nmeta.local"), "Unexpected response: {}", response);
+
+    let instance = harness.manager.instance("meta.local").unwrap();
+    let response = http_get_with_host(harness.proxy_port, "/env/INSTANCE_ID", "meta.local").await.unwrap();
+    assert!(response.ends_with(&instance.id));
+    let response = http_get_with_host(harness.proxy_port, "/env/INSTANCE_INDEX", "meta.local").await.unwrap();
+    assert!(response.ends_with("\r\n\r\n0"));
+    let response = http_get_with_host(harness.proxy_port, "/env/PUBLIC_URL", "meta.local").await.unwrap();
+    assert!(response.ends_with("http://meta.local"));
+
+    let response = http_get_with_host(harness.proxy_port, "/env/ADMIN_CALLBACK_URL", "meta.local").await.unwrap();
+    let expected = format!(
+        "http://127.0.0.1:{}/ready/meta.local?token={}",
+        harness.admin_port, instance.token
+    );
+    assert!(response.ends_with(&expected), "Unexpected response: {}", response);
+
+    // The instance token only authorizes its own backend
+    assert!(harness.manager.verify_instance_token("meta.local", &instance.token));
+    assert!(!harness.manager.verify_instance_token("other.local", &instance.token));
+    assert!(!harness.manager.verify_instance_token("meta.local", "wrong"));
+
+    harness.stop().await;
+}
+
+// ============================================================================
+// Readiness Gate Tests
+// ============================================================================
+
+#[tokio::test]
+async fn test_readiness_gates() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let marker = std::env::temp_dir().join(format!("spawngate-migrated-{}", std::process::id()));
+    let _ = std::fs::remove_file(&marker);
+
+    let mut app = mock_backend_config(free_port());
+    app.startup_timeout_secs = Some(2);
+    app.health_check_interval_ms = Some(50);
+    app.readiness.depends_on = vec!["db.local".to_string()];
+    app.readiness.marker_file = Some(marker.to_string_lossy().to_string());
+    app.readiness.manual_approval = true;
+
+    let mut configs = HashMap::new();
+    configs.insert("app.local".to_string(), app);
+    configs.insert("db.local".to_string(), mock_backend_config(free_port()));
+    let harness = TestHarness::start(configs).await;
+
+    harness.manager.start_backend("app.local").await.unwrap();
+
+    // Dependency is started alongside and becomes ready on its own
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(harness.manager.get_state("db.local"), BackendState::Ready);
+    assert_eq!(harness.manager.get_state("app.local"), BackendState::Starting);
+    assert_eq!(
+        harness.manager.pending_gate("app.local"),
+        Some(PendingGate::MarkerFile(marker.to_string_lossy().to_string()))
+    );
+
+    // Ready callbacks can't skip the gates
+    let response = admin_request(harness.admin_port, "POST", "/ready/app.local").await;
+    assert!(response.contains("409"), "Unexpected response: {}", response);
+
+    std::fs::write(&marker, "done").unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(harness.manager.pending_gate("app.local"), Some(PendingGate::ManualApproval));
+
+    // Waiting for approval outlasts the startup timeout
+    tokio::time::sleep(Duration::from_millis(2200)).await;
+    assert_eq!(harness.manager.get_state("app.local"), BackendState::Starting);
+
+    let response = admin_request(harness.admin_port, "POST", "/approve/app.local").await;
+    assert!(response.contains("200 OK"), "Unexpected response: {}", response);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(harness.manager.get_state("app.local"), BackendState::Ready);
+
+    let response = admin_request(harness.admin_port, "DELETE", "/approve/app.local").await;
+    assert!(response.contains("200 OK"));
+    assert_eq!(harness.manager.pending_gate("app.local"), Some(PendingGate::ManualApproval));
+
+    harness.stop().await;
+    std::fs::remove_file(&marker).unwrap();
+}
+
+// ============================================================================
+// Label Selector Tests
+// ============================================================================
+
+#[tokio::test]
+async fn test_admin_label_selectors() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut configs = HashMap::new();
+    for (hostname, env) in [("a.local", "prod"), ("b.local", "staging"), ("c.local", "staging")] {
+        let mut config = mock_backend_config(free_port());
+        config.labels.insert("env".to_string(), env.to_string());
+        configs.insert(hostname.to_string(), config);
+    }
+    let harness = TestHarness::start(configs).await;
+
+    let response = admin_request(harness.admin_port, "GET", "/backends?selector=env%3Dstaging").await;
+    assert!(response.contains("\"count\":2"), "Unexpected response: {}", response);
+    assert!(response.contains("\"labels\":{\"env\":\"staging\"}"));
+    assert!(!response.contains("a.local"));
+
+    let response = admin_request(harness.admin_port, "GET", "/backends?selector=env+in+(prod").await;
+    assert!(response.contains("400 Bad Request"));
+
+    for hostname in ["a.local", "b.local", "c.local"] {
+        harness.manager.start_backend(hostname).await.unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    // Bulk operations require an explicit selector
+    let response = admin_request(harness.admin_port, "POST", "/backends/stop").await;
+    assert!(response.contains("400 Bad Request"));
+
+    let response = admin_request(harness.admin_port, "POST", "/backends/stop?selector=env!%3Dprod").await;
+    assert!(response.contains("\"stopped\":[\"b.local\",\"c.local\"]"), "Unexpected response: {}", response);
+    assert_eq!(harness.manager.get_state("a.local"), BackendState::Ready);
+    assert_eq!(harness.manager.get_state("b.local"), BackendState::Stopped);
+    assert_eq!(harness.manager.get_state("c.local"), BackendState::Stopped);
+
+    harness.stop().await;
+}
+
+// ============================================================================
+// Promotion Tests
+// ============================================================================
+
+#[tokio::test]
+async fn test_promote_backend() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut staging = mock_backend_config(free_port());
+    staging.args = vec!["--release".to_string(), "v2".to_string()];
+    staging.promote_to = vec!["app.local".to_string()];
+    let mut production = mock_backend_config(free_port());
+    production.args = vec!["--release".to_string(), "v1".to_string()];
+
+    let mut configs = HashMap::new();
+    configs.insert("staging.local".to_string(), staging);
+    configs.insert("app.local".to_string(), production);
+    let harness = TestHarness::start(configs).await;
+
+    let response = http_get_with_host(harness.proxy_port, "/echo", "app.local").await.unwrap();
+    assert!(response.contains("200 OK"));
+
+    // Review first
+    let response = admin_request(harness.admin_port, "GET", "/promote/staging.local/app.local").await;
+    assert!(response.contains("\"changed\":true"), "Unexpected response: {}", response);
+    assert!(response.contains("\"proposed\":{\"args\":[\"--release\",\"v2\"]"));
+
+    // Only configured directions are allowed
+    let response = admin_request(harness.admin_port, "POST", "/promote/app.local/staging.local").await;
+    assert!(response.contains("400 Bad Request"));
+
+    let response = admin_request(harness.admin_port, "POST", "/promote/staging.local/app.local").await;
+    assert!(response.contains("200 OK"), "Unexpected response: {}", response);
+    assert!(response.contains("\"id\":1"));
+    assert_eq!(harness.manager.get_config("app.local").unwrap().args, vec!["--release", "v2"]);
+    assert_eq!(harness.manager.get_state("app.local"), BackendState::Stopped);
+
+    // Target cold-starts with the promoted artifact
+    let response = http_get_with_host(harness.proxy_port, "/echo", "app.local").await.unwrap();
+    assert!(response.contains("200 OK"));
+
+    let response = admin_request(harness.admin_port, "GET", "/promotions").await;
+    assert!(response.contains("\"count\":1"));
+    assert!(response.contains("\"source\":\"staging.local\""));
+
+    harness.stop().await;
+}
+
+// ============================================================================
+// Slow Request Tests
+// ============================================================================
+
+#[tokio::test]
+async fn test_slow_requests_summary() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let admin_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let admin_addr = admin_listener.local_addr().unwrap();
+    let proxy_port = proxy_listener.local_addr().unwrap().port();
+
+    let mut configs = HashMap::new();
+    configs.insert("app.local".to_string(), mock_backend_config(free_port()));
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let manager = ProcessManager::new(configs, BackendDefaults::default(), format!("http://{}", admin_addr));
+
+    let slow_log = Arc::new(SlowRequestLog::new(&spawngate::config::SlowRequestConfig {
+        enabled: true,
+        threshold_ms: 1000,
+        window_secs: 3600,
+        top: 10,
+    }));
+    let admin_server = AdminServer::new(admin_addr, Arc::clone(&manager), shutdown_rx.clone(), "test-token".to_string())
+        .with_slow_log(Arc::clone(&slow_log));
+    let proxy_server = ProxyServer::new(
+        proxy_listener.local_addr().unwrap(),
+        Arc::clone(&manager),
+        manager.shared_defaults(),
+        shutdown_rx,
+    )
+    .with_slow_log(slow_log);
+    let handles = vec![
+        tokio::spawn(async move {
+            let _ = admin_server.serve(admin_listener).await;
+        }),
+        tokio::spawn(async move {
+            let _ = proxy_server.serve(proxy_listener).await;
+        }),
+    ];
+
+    let response = http_get_with_host(proxy_port, "/echo", "app.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Request failed: {}", response);
+    let response = http_get_with_host(proxy_port, "/slow", "app.local").await.unwrap();
+    assert!(response.contains("slow response"), "Request failed: {}", response);
+
+    let response = admin_request(admin_addr.port(), "GET", "/slow-requests").await;
+    assert!(response.contains("200 OK"), "Unexpected response: {}", response);
+    assert!(response.contains("\"threshold_ms\":1000"));
+    assert!(response.contains("\"path\":\"/slow\""));
+    assert!(!response.contains("/echo"));
+
+    manager.stop_all().await;
+    let _ = shutdown_tx.send(true);
+    for handle in handles {
+        let _ = handle.await;
+    }
+}
+
+#[tokio::test]
+async fn test_debug_tasks_and_connections() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let admin_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let admin_addr = admin_listener.local_addr().unwrap();
+    let proxy_port = proxy_listener.local_addr().unwrap().port();
+    let backend_port = free_port();
+
+    let mut configs = HashMap::new();
+    configs.insert("app.local".to_string(), mock_backend_config(backend_port));
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let manager = ProcessManager::new(configs, BackendDefaults::default(), format!("http://{}", admin_addr));
+
+    let registry = Arc::new(DebugRegistry::new());
+    let admin_server = AdminServer::new(admin_addr, Arc::clone(&manager), shutdown_rx.clone(), "test-token".to_string())
+        .with_debug_registry(Arc::clone(&registry));
+    let proxy_server = ProxyServer::new(
+        proxy_listener.local_addr().unwrap(),
+        Arc::clone(&manager),
+        manager.shared_defaults(),
+        shutdown_rx,
+    )
+    .with_pool(Arc::new(spawngate::pool::ConnectionPool::with_registry(
+        PoolConfig::default(),
+        Arc::clone(&registry),
+    )))
+    .with_debug_registry(Arc::clone(&registry));
+    let handles = vec![
+        tokio::spawn(async move {
+            let _ = admin_server.serve(admin_listener).await;
+        }),
+        registry.spawn("test proxy server", async move {
+            let _ = proxy_server.serve(proxy_listener).await;
+        }),
+    ];
+
+    let response = http_get_with_host(proxy_port, "/echo", "app.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Request failed: {}", response);
+
+    // Client and backend connections are listed while a request is in flight
+    let mut client = TcpStream::connect(("127.0.0.1", proxy_port)).await.unwrap();
+    client
+        .write_all(b"GET /slow HTTP/1.1\r\nHost: app.local\r\n\r\n")
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let response = admin_request(admin_addr.port(), "GET", "/debug/connections").await;
+    assert!(response.contains("200 OK"), "Unexpected response: {}", response);
+    let body = &response[response.find("\r\n\r\n").unwrap() + 4..];
+    let json: serde_json::Value = serde_json::from_str(body).expect("Valid JSON response");
+    let clients = json["clients"].as_array().unwrap();
+    assert_eq!(clients.len(), 1, "Connections: {}", body);
+    assert_eq!(clients[0]["client_addr"], client.local_addr().unwrap().to_string());
+    assert_eq!(clients[0]["requests"], 1);
+    assert_eq!(json["upstreams"][0]["backend"], format!("127.0.0.1:{}", backend_port));
+
+    let response = admin_request(admin_addr.port(), "GET", "/debug/tasks").await;
+    let body = &response[response.find("\r\n\r\n").unwrap() + 4..];
+    let json: serde_json::Value = serde_json::from_str(body).expect("Valid JSON response");
+    assert_eq!(json["tasks"][0]["name"], "test proxy server");
+    assert!(json["runtime"]["alive_tasks"].as_u64().unwrap() >= 2);
+    assert_eq!(json["spawning"], serde_json::json!([]));
+
+    let mut response = String::new();
+    client.read_to_string(&mut response).await.unwrap();
+    assert!(response.contains("slow response"), "Response: {}", response);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let connections = registry.connections();
+    assert!(connections.clients.is_empty());
+    assert!(connections.upstreams.is_empty());
+
+    manager.stop_all().await;
+    let _ = shutdown_tx.send(true);
+    for handle in handles {
+        let _ = handle.await;
+    }
+    assert!(registry.tasks().tasks.is_empty());
+}
+
+// ============================================================================
+// Fault Injection Tests
+// ============================================================================
+
+#[tokio::test]
+async fn test_fault_injection_via_admin_api() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut app = mock_backend_config(free_port());
+    app.faults = Some(FaultConfig {
+        error_rate: 1.0,
+        error_status: 503,
+        ..Default::default()
+    });
+    let mut configs = HashMap::new();
+    configs.insert("app.local".to_string(), app);
+    configs.insert("plain.local".to_string(), mock_backend_config(free_port()));
+    let harness = TestHarness::start(configs).await;
+
+    let response = http_get_with_host(harness.proxy_port, "/echo", "app.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Request failed: {}", response);
+
+    let response = admin_request(harness.admin_port, "POST", "/faults/plain.local").await;
+    assert!(response.contains("400"), "Unexpected response: {}", response);
+    let response = admin_request(harness.admin_port, "POST", "/faults/missing.local").await;
+    assert!(response.contains("404"), "Unexpected response: {}", response);
+
+    // A requested duration longer than the configured one is capped
+    let response = admin_request(harness.admin_port, "POST", "/faults/app.local?duration_secs=3600").await;
+    assert!(response.contains("200 OK"), "Unexpected response: {}", response);
+    assert!(response.contains(r#""duration_secs":300"#), "Unexpected response: {}", response);
+
+    let response = http_get_with_host(harness.proxy_port, "/echo", "app.local").await.unwrap();
+    assert!(response.contains("503"), "Expected injected error: {}", response);
+    assert!(response.to_lowercase().contains("x-proxy-error: fault_injected"));
+    let response = http_get_with_host(harness.proxy_port, "/echo", "plain.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Request failed: {}", response);
+
+    let response = admin_request(harness.admin_port, "GET", "/faults").await;
+    let body = &response[response.find("\r\n\r\n").unwrap() + 4..];
+    let json: serde_json::Value = serde_json::from_str(body).expect("Valid JSON response");
+    assert_eq!(json["count"], 1);
+    assert_eq!(json["faults"][0]["hostname"], "app.local");
+    assert_eq!(json["faults"][0]["error_status"], 503);
+
+    let response = admin_request(harness.admin_port, "DELETE", "/faults/app.local").await;
+    assert!(response.contains("200 OK"), "Unexpected response: {}", response);
+    let response = http_get_with_host(harness.proxy_port, "/echo", "app.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Request failed: {}", response);
+
+    // Faults switch off by themselves
+    let response = admin_request(harness.admin_port, "POST", "/faults/app.local?duration_secs=1").await;
+    assert!(response.contains("200 OK"), "Unexpected response: {}", response);
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let response = http_get_with_host(harness.proxy_port, "/echo", "app.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Request failed: {}", response);
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn test_config_vars_api() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut app = mock_backend_config(free_port());
+    app.env.insert("GREETING".to_string(), "from-config".to_string());
+    let mut configs = HashMap::new();
+    configs.insert("app.local".to_string(), app);
+    let harness = TestHarness::start(configs).await;
+
+    let response = http_get_with_host(harness.proxy_port, "/env/GREETING", "app.local").await.unwrap();
+    assert!(response.ends_with("from-config"), "Unexpected response: {}", response);
+
+    let body = r#"{"GREETING":"from-api","FEATURE":"on"}"#;
+    let response = admin_request_with_body(harness.admin_port, "PUT", "/apps/app.local/config?restart=true", body).await;
+    assert!(response.contains("200 OK"), "Unexpected response: {}", response);
+    assert!(response.ends_with(r#"{"FEATURE":"on","GREETING":"from-api"}"#), "Unexpected response: {}", response);
+
+    // The restart picks up the new vars
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+    loop {
+        let response = http_get_with_host(harness.proxy_port, "/env/GREETING", "app.local").await;
+        if response.as_ref().is_ok_and(|r| r.ends_with("from-api")) {
+            break;
+        }
+        assert!(tokio::time::Instant::now() < deadline, "Restart did not apply vars: {:?}", response);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(harness.manager.spawn_count("app.local"), 2);
+
+    let response =
+        admin_request_with_body(harness.admin_port, "PUT", "/apps/app.local/config", r#"{"FEATURE":null}"#).await;
+    assert!(response.ends_with(r#"{"GREETING":"from-api"}"#), "Unexpected response: {}", response);
+    let response = admin_request(harness.admin_port, "GET", "/apps/app.local/config").await;
+    assert!(response.ends_with(r#"{"GREETING":"from-api"}"#), "Unexpected response: {}", response);
+
+    let response = admin_request_with_body(harness.admin_port, "PUT", "/apps/app.local/config", "[1]").await;
+    assert!(response.contains("400"), "Unexpected response: {}", response);
+    let response = admin_request(harness.admin_port, "GET", "/apps/missing.local/config").await;
+    assert!(response.contains("404"), "Unexpected response: {}", response);
+
+    let response = admin_request(harness.admin_port, "DELETE", "/apps/app.local/config").await;
+    assert!(response.ends_with("{}"), "Unexpected response: {}", response);
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn test_adaptive_idle_timeout() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut configs = HashMap::new();
+    configs.insert("app.local".to_string(), mock_backend_config(free_port()));
+    let harness = TestHarness::start(configs).await;
+    harness.manager.shared_defaults().write().adaptive_idle = AdaptiveIdleConfig {
+        enabled: true,
+        min_samples: 2,
+        min_timeout_secs: 1,
+        max_timeout_secs: 60,
+        ..Default::default()
+    };
+
+    let response = admin_request(harness.admin_port, "GET", "/idle").await;
+    assert!(response.contains(r#""configured_secs":5"#), "Unexpected response: {}", response);
+    assert!(response.contains(r#""learned_secs":null"#), "Unexpected response: {}", response);
+
+    for i in 0..3 {
+        if i > 0 {
+            tokio::time::sleep(Duration::from_millis(1100)).await;
+        }
+        let response = http_get_with_host(harness.proxy_port, "/echo", "app.local").await.unwrap();
+        assert!(response.contains("200 OK"));
+    }
+
+    // Requests ~1.1s apart: the 5s timeout shrinks to 2s
+    let response = admin_request(harness.admin_port, "GET", "/idle").await;
+    assert!(response.contains(r#""learned_secs":2"#), "Unexpected response: {}", response);
+    assert!(response.contains(r#""effective_secs":2"#), "Unexpected response: {}", response);
+    assert!(response.contains(r#""samples":2"#), "Unexpected response: {}", response);
+
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    harness.manager.cleanup_idle_backends().await;
+    assert_eq!(harness.manager.get_state("app.local"), BackendState::Stopped);
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn test_savings_report() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut app = mock_backend_config(free_port());
+    app.cpus = Some("2".to_string());
+    let mut configs = HashMap::new();
+    configs.insert("app.local".to_string(), app);
+    configs.insert("idle.local".to_string(), mock_backend_config(free_port()));
+    let harness = TestHarness::start(configs).await;
+
+    let response = http_get_with_host(harness.proxy_port, "/echo", "app.local").await.unwrap();
+    assert!(response.contains("200 OK"));
+    harness.manager.stop_backend("app.local").await;
+
+    let report = harness.manager.savings_report();
+    let app = &report.backends[0];
+    assert_eq!((app.hostname.as_str(), app.starts, app.cpus), ("app.local", 1, 2.0));
+    assert!(app.running_secs <= app.tracked_secs);
+    let idle = &report.backends[1];
+    assert_eq!((idle.starts, idle.running_secs, idle.stopped_ratio), (0, 0, 1.0));
+
+    let response = admin_request(harness.admin_port, "GET", "/savings").await;
+    assert!(response.contains("200 OK"), "Unexpected response: {}", response);
+    assert!(response.contains(r#""cpu_hours_saved""#), "Unexpected response: {}", response);
+    assert!(!response.contains("estimated_savings"), "Unexpected response: {}", response);
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn test_backend_logs() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut configs = HashMap::new();
+    configs.insert("app.local".to_string(), mock_backend_config(free_port()));
+    let harness = TestHarness::start(configs).await;
+
+    let response = http_get_with_host(harness.proxy_port, "/echo", "app.local").await.unwrap();
+    assert!(response.contains("200 OK"));
+
+    // The mock server logs each request to stderr
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while !harness.manager.logs("app.local", 100).iter().any(|l| l.line.contains("GET /echo")) {
+        assert!(std::time::Instant::now() < deadline, "Request not logged: {:?}", harness.manager.logs("app.local", 100));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let response = admin_request(harness.admin_port, "GET", "/logs/app.local?lines=1").await;
+    assert!(response.contains("200 OK"), "Unexpected response: {}", response);
+    assert!(response.contains(r#""count":1"#), "Unexpected response: {}", response);
+    assert!(response.contains(r#""stream":"stderr""#), "Unexpected response: {}", response);
+
+    let response = admin_request(harness.admin_port, "GET", "/logs/unknown.local").await;
+    assert!(response.contains("404"), "Unexpected response: {}", response);
+    let response = admin_request(harness.admin_port, "GET", "/logs/app.local?lines=all").await;
+    assert!(response.contains("400"), "Unexpected response: {}", response);
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn test_fault_injection_drops_connections() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut app = mock_backend_config(free_port());
+    app.faults = Some(FaultConfig {
+        drop_rate: 1.0,
+        ..Default::default()
+    });
+    let mut configs = HashMap::new();
+    configs.insert("app.local".to_string(), app);
+    let harness = TestHarness::start(configs).await;
+
+    harness.manager.enable_faults("app.local", None).unwrap();
+    let response = http_get_with_host(harness.proxy_port, "/echo", "app.local").await;
+    assert!(
+        response.as_ref().map_or(true, |r| r.is_empty()),
+        "Expected a dropped connection: {:?}",
+        response
+    );
+
+    harness.stop().await;
+}
+
+// ============================================================================
+// Traffic Quota Tests
+// ============================================================================
+
+#[tokio::test]
+async fn test_traffic_quota() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut app = mock_backend_config(free_port());
+    app.quota = Some(QuotaConfig {
+        window: QuotaWindow::Day,
+        max_requests: Some(2),
+        max_bytes: None,
+        on_exceed: QuotaAction::Block,
+        throttle_bytes_per_sec: 65536,
+    });
+    let mut configs = HashMap::new();
+    configs.insert("app.local".to_string(), app);
+    configs.insert("plain.local".to_string(), mock_backend_config(free_port()));
+    let harness = TestHarness::start(configs).await;
+
+    for _ in 0..2 {
+        let response = http_get_with_host(harness.proxy_port, "/echo", "app.local").await.unwrap();
+        assert!(response.contains("200 OK"), "Request failed: {}", response);
+    }
+    let response = http_get_with_host(harness.proxy_port, "/echo", "app.local").await.unwrap();
+    assert!(response.contains("429"), "Expected quota rejection: {}", response);
+    assert!(response.to_lowercase().contains("x-proxy-error: quota_exceeded"));
+    assert!(response.to_lowercase().contains("retry-after:"));
+    let response = http_get_with_host(harness.proxy_port, "/echo", "plain.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Request failed: {}", response);
+
+    let response = admin_request(harness.admin_port, "GET", "/quotas").await;
+    let body = &response[response.find("\r\n\r\n").unwrap() + 4..];
+    let json: serde_json::Value = serde_json::from_str(body).expect("Valid JSON response");
+    assert_eq!(json["count"], 1);
+    assert_eq!(json["quotas"][0]["hostname"], "app.local");
+    assert_eq!(json["quotas"][0]["requests"], 2);
+    assert_eq!(json["quotas"][0]["exceeded"], true);
+    assert_eq!(json["quotas"][0]["on_exceed"], "block");
+    assert!(json["quotas"][0]["bytes"].as_u64().unwrap() > 0);
+
+    let response = admin_request(harness.admin_port, "DELETE", "/quotas/plain.local").await;
+    assert!(response.contains("404"), "Unexpected response: {}", response);
+    let response = admin_request(harness.admin_port, "DELETE", "/quotas/app.local").await;
+    assert!(response.contains("200 OK"), "Unexpected response: {}", response);
+    let response = http_get_with_host(harness.proxy_port, "/echo", "app.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Request failed: {}", response);
+
+    harness.stop().await;
+}
+
+// ============================================================================
+// Port Allocation Tests
+// ============================================================================
+
+/// Start and stop a backend with `port = 0` through the proxy, returning the port of each instance
+async fn cycle_backend(harness: &TestHarness, hostname: &str, cycles: usize) -> Vec<u16> {
+    let mut ports = Vec::new();
+    for cycle in 0..cycles {
+        let response = http_get_with_host(harness.proxy_port, "/echo", hostname).await.unwrap();
+        assert!(response.contains("200 OK"), "Cycle {} failed: {}", cycle, response);
+        ports.push(harness.manager.get_backend_port(hostname).unwrap());
+        harness.manager.stop_backend(hostname).await;
+        assert_eq!(harness.manager.get_backend_port(hostname), None);
+    }
+    ports
+}
+
+/// A rapidly cycled backend gets its previous port back every time
+#[tokio::test]
+async fn test_port_reuse_across_rapid_cycles() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut configs = HashMap::new();
+    configs.insert("reuse.local".to_string(), mock_backend_config(0));
+    let harness = TestHarness::start(configs).await;
+    harness.manager.shared_defaults().write().ports = PortAllocationConfig {
+        range_start: 22000,
+        range_end: 22999,
+        reuse: true,
+        cooldown_secs: 60,
+    };
+
+    let ports = cycle_backend(&harness, "reuse.local", 200).await;
+    assert!((22000..=22999).contains(&ports[0]));
+    assert!(ports.iter().all(|&port| port == ports[0]), "Port changed: {:?}", ports);
+    assert_eq!(harness.manager.spawn_count("reuse.local"), 200);
+
+    harness.stop().await;
+}
+
+/// Without reuse, a cycled backend rotates through the range and no port comes back within its cooldown
+#[tokio::test]
+async fn test_port_rotation_across_rapid_cycles() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let mut configs = HashMap::new();
+    configs.insert("rotate.local".to_string(), mock_backend_config(0));
+    let harness = TestHarness::start(configs).await;
+    harness.manager.shared_defaults().write().ports = PortAllocationConfig {
+        range_start: 23000,
+        range_end: 23999,
+        reuse: false,
+        cooldown_secs: 60,
+    };
+
+    let ports = cycle_backend(&harness, "rotate.local", 200).await;
+    let distinct: std::collections::HashSet<u16> = ports.iter().copied().collect();
+    assert_eq!(distinct.len(), ports.len(), "Port reused during its cooldown: {:?}", ports);
+    assert!(ports.iter().all(|port| (23000..=23999).contains(port)));
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn test_cold_start_slo() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let server = ServerConfig {
+        bind: "127.0.0.1".to_string(),
+        port: free_port(),
+        admin_port: 0,
+        admin_token: Some("test-token".to_string()),
+        cold_start_slo: ColdStartSloConfig {
+            enabled: true,
+            threshold_ms: 1,
+            target: 0.9,
+            alert_burn_rate: 2.0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let gate = Spawngate::builder()
+        .server(server)
+        .backend("app.local", mock_backend_config(free_port()))
+        .start()
+        .await
+        .unwrap();
+    let proxy_port = gate.http_addr().unwrap().port();
+
+    // The first request waits for the cold start, the second does not
+    for _ in 0..2 {
+        let response = http_get_with_host(proxy_port, "/echo", "app.local").await.unwrap();
+        assert!(response.contains("200 OK"), "Unexpected response: {}", response);
+    }
+
+    let response = admin_request(gate.admin_addr().port(), "GET", "/slo").await;
+    assert!(response.contains("200 OK"), "Unexpected response: {}", response);
+    assert!(response.contains(r#""requests":2"#), "Unexpected response: {}", response);
+    assert!(response.contains(r#""violations":1"#), "Unexpected response: {}", response);
+    assert!(response.contains(r#""attainment":0.5"#), "Unexpected response: {}", response);
+    assert!(response.contains(r#""alerting":true"#), "Unexpected response: {}", response);
+
+    gate.stop().await;
+}
+
+#[tokio::test]
+async fn test_embedded_spawngate() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let server = ServerConfig {
+        bind: "127.0.0.1".to_string(),
+        port: free_port(),
+        admin_port: 0,
+        admin_token: Some("embedded-token".to_string()),
+        ..Default::default()
+    };
+
+    let events = Arc::new(parking_lot::Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&events);
+    let gate = Spawngate::builder()
+        .server(server)
+        .backend("app.local", mock_backend_config(free_port()))
+        .on_event(move |event| recorded.lock().push(event.clone()))
+        .router(|hosts| {
+            Arc::new(move |ctx: &RequestContext, parts: &Parts| {
+                if ctx.hostname == "tenant.test" {
+                    Some("app.local".to_string())
+                } else {
+                    hosts.route(ctx, parts)
+                }
+            })
+        })
+        .start()
+        .await
+        .unwrap();
+
+    assert_ne!(gate.admin_addr().port(), 0);
+    assert_eq!(gate.admin_token(), "embedded-token");
+    let proxy_port = gate.http_addr().unwrap().port();
+
+    let response = http_get_with_host(proxy_port, "/echo", "app.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Unexpected response: {}", response);
+    assert_eq!(gate.process_manager().get_state("app.local"), BackendState::Ready);
+    let response = http_get_with_host(proxy_port, "/echo", "tenant.test").await.unwrap();
+    assert!(response.contains("200 OK"), "Unexpected response: {}", response);
+
+    gate.stop().await;
+    let events = events.lock().clone();
+    assert_eq!(
+        events,
+        vec![
+            BackendEvent::Started { hostname: "app.local".to_string() },
+            BackendEvent::Ready { hostname: "app.local".to_string() },
+            BackendEvent::Stopped { hostname: "app.local".to_string() },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_keep_warm_backend() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let server = ServerConfig {
+        bind: "127.0.0.1".to_string(),
+        port: free_port(),
+        admin_port: 0,
+        admin_token: Some("test-token".to_string()),
+        ..Default::default()
+    };
+    let mut warm = mock_backend_config(free_port());
+    warm.keep_warm = Some(true);
+    warm.idle_timeout_secs = Some(1);
+    let mut cold = mock_backend_config(free_port());
+    cold.idle_timeout_secs = Some(1);
+    let gate = Spawngate::builder()
+        .server(server)
+        .backend("warm.local", warm)
+        .backend("cold.local", cold)
+        .start()
+        .await
+        .unwrap();
+    let manager = Arc::clone(gate.process_manager());
+
+    // Started without any traffic
+    for _ in 0..100 {
+        if manager.get_state("warm.local") == BackendState::Ready {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(manager.get_state("warm.local"), BackendState::Ready);
+    assert_eq!(manager.get_state("cold.local"), BackendState::Stopped);
+
+    // Only the other backend is stopped once idle
+    let proxy_port = gate.http_addr().unwrap().port();
+    let response = http_get_with_host(proxy_port, "/echo", "cold.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Unexpected response: {}", response);
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    manager.cleanup_idle_backends().await;
+    assert_eq!(manager.get_state("warm.local"), BackendState::Ready);
+    assert_eq!(manager.get_state("cold.local"), BackendState::Stopped);
+
+    // Restarted after being stopped
+    manager.stop_backend("warm.local").await;
+    manager.start_warm_backends().await;
+    assert_ne!(manager.get_state("warm.local"), BackendState::Stopped);
+
+    let response = admin_request(gate.admin_addr().port(), "GET", "/idle").await;
+    assert!(response.contains(r#""keep_warm":true"#), "Unexpected response: {}", response);
+
+    gate.stop().await;
+}
+
+#[tokio::test]
+async fn test_restart_subsystem() {
+    let dir = std::env::temp_dir().join(format!("spawngate-subsystems-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let server = ServerConfig {
+        bind: "127.0.0.1".to_string(),
+        port: free_port(),
+        admin_port: 0,
+        admin_token: Some("test-token".to_string()),
+        metrics_export: MetricsExportConfig {
+            enabled: true,
+            path: dir.join("metrics.csv").to_string_lossy().into_owned(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let gate = Spawngate::builder().server(server).start().await.unwrap();
+    let admin_port = gate.admin_addr().port();
+
+    let response = admin_request(admin_port, "GET", "/subsystems").await;
+    assert!(response.contains("200 OK"), "Unexpected response: {}", response);
+    assert!(response.contains(r#""name":"metrics-export""#), "Unexpected response: {}", response);
+    assert!(response.contains(r#""running":true"#), "Unexpected response: {}", response);
+
+    let response = admin_request(admin_port, "POST", "/subsystems/metrics-export/restart").await;
+    assert!(response.contains("200 OK"), "Unexpected response: {}", response);
+    assert!(response.contains(r#""restarts":1"#), "Unexpected response: {}", response);
+    let tasks = admin_request(admin_port, "GET", "/debug/tasks").await;
+    assert_eq!(tasks.matches(r#""name":"metrics export""#).count(), 1, "Unexpected tasks: {}", tasks);
+
+    // ACME isn't configured
+    let response = admin_request(admin_port, "POST", "/acme/restart").await;
+    assert!(response.contains("404"), "Unexpected response: {}", response);
+    assert!(response.contains("subsystem 'acme' not running"), "Unexpected response: {}", response);
+
+    gate.stop().await;
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_file_discovery() {
+    let dir = std::env::temp_dir().join(format!("spawngate-file-sd-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("api.json"), r#"{"api.local": {"command": "./api", "port": 3001}}"#).unwrap();
+
+    let server = ServerConfig {
+        bind: "127.0.0.1".to_string(),
+        port: free_port(),
+        admin_port: 0,
+        admin_token: Some("test-token".to_string()),
+        file_sd: FileSdConfig {
+            dir: Some(dir.to_string_lossy().into_owned()),
+            interval_secs: 1,
+        },
+        ..Default::default()
+    };
+    let mut gate = Spawngate::builder()
+        .server(server.clone())
+        .backend("static.local", BackendConfig::local("./static", 3000))
+        .start()
+        .await
+        .unwrap();
+    let manager = Arc::clone(gate.process_manager());
+    assert!(manager.has_backend("api.local"));
+    assert!(manager.has_backend("static.local"));
+
+    // Changes are picked up without a reload
+    std::fs::write(dir.join("jobs.toml"), "[\"jobs.local\"]\ncommand = \"./jobs\"\nport = 3002\n").unwrap();
+    std::fs::remove_file(dir.join("api.json")).unwrap();
+    for _ in 0..60 {
+        if manager.has_backend("jobs.local") && !manager.has_backend("api.local") {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert!(manager.has_backend("jobs.local"));
+    assert!(!manager.has_backend("api.local"));
+
+    // A reload of the main config keeps the discovered backends
+    let mut config = Config {
+        server,
+        ..Default::default()
+    };
+    config
+        .backends
+        .insert("other.local".to_string(), BackendConfig::local("./other", 3003));
+    let result = gate.reload(config).await.unwrap();
+    assert_eq!(result.removed, vec!["static.local"]);
+    assert!(manager.has_backend("jobs.local"));
+    assert!(manager.has_backend("other.local"));
+
+    gate.stop().await;
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_backend_definition_api() {
+    let server = ServerConfig {
+        bind: "127.0.0.1".to_string(),
+        port: free_port(),
+        admin_port: 0,
+        admin_token: Some("test-token".to_string()),
+        ..Default::default()
+    };
+    let mut gate = Spawngate::builder()
+        .server(server.clone())
+        .backend("static.local", BackendConfig::local("./static", 3000))
+        .start()
+        .await
+        .unwrap();
+    let admin_port = gate.admin_addr().port();
+    let manager = Arc::clone(gate.process_manager());
+    let etag = |response: &str| {
+        response
+            .lines()
+            .find_map(|line| line.strip_prefix("etag: "))
+            .expect("ETag header")
+            .to_string()
+    };
+    let put = |headers: &'static [(&'static str, &'static str)], body: &'static str| {
+        admin_request_with_headers(admin_port, "PUT", "/backends/api.local", headers, body)
+    };
+
+    // Created, then putting the same definition again changes nothing
+    let created = put(&[], r#"{"command": "./api", "port": 3001}"#).await;
+    assert!(created.starts_with("HTTP/1.1 201"), "Unexpected response: {}", created);
+    assert!(created.contains(r#""action":"created""#), "Unexpected response: {}", created);
+    assert!(created.contains(r#"{"field":"port","new":3001,"old":null}"#), "Unexpected response: {}", created);
+    assert!(manager.has_backend("api.local"));
+    let unchanged = put(&[], r#"{"command": "./api", "port": 3001}"#).await;
+    assert!(unchanged.starts_with("HTTP/1.1 200"), "Unexpected response: {}", unchanged);
+    assert!(unchanged.contains(r#""action":"unchanged""#), "Unexpected response: {}", unchanged);
+    assert!(unchanged.contains(r#""diff":[]"#), "Unexpected response: {}", unchanged);
+    assert_eq!(etag(&unchanged), etag(&created));
+
+    // Optimistic concurrency
+    let stale = put(&[("If-Match", "\"0000000000000000\"")], r#"{"command": "./api", "port": 3002}"#).await;
+    assert!(stale.starts_with("HTTP/1.1 412"), "Unexpected response: {}", stale);
+    let exists = put(&[("If-None-Match", "*")], r#"{"command": "./api", "port": 3002}"#).await;
+    assert!(exists.starts_with("HTTP/1.1 412"), "Unexpected response: {}", exists);
+    let current = etag(&created);
+    let updated = admin_request_with_headers(
+        admin_port,
+        "PUT",
+        "/backends/api.local",
+        &[("If-Match", &current)],
+        r#"{"command": "./api", "port": 3002}"#,
+    )
+    .await;
+    assert!(updated.starts_with("HTTP/1.1 200"), "Unexpected response: {}", updated);
+    assert!(updated.contains(r#""diff":[{"field":"port","new":3002,"old":3001}]"#), "Unexpected response: {}", updated);
+    assert_eq!(manager.get_config("api.local").unwrap().port, 3002);
+
+    let read = admin_request(admin_port, "GET", "/backends/api.local").await;
+    assert!(read.contains(r#""source":"api""#), "Unexpected response: {}", read);
+    assert_eq!(etag(&read), etag(&updated));
+    let not_modified =
+        admin_request_with_headers(admin_port, "GET", "/backends/api.local", &[("If-None-Match", &etag(&read))], "")
+            .await;
+    assert!(not_modified.starts_with("HTTP/1.1 304"), "Unexpected response: {}", not_modified);
+
+    // Secrets are redacted, and a redacted secret sent back is kept
+    let secret = put(&[], r#"{"command": "./api", "port": 3002, "env": {"API_TOKEN": "hunter2"}}"#).await;
+    assert!(secret.starts_with("HTTP/1.1 200"), "Unexpected response: {}", secret);
+    let read = admin_request(admin_port, "GET", "/backends/api.local").await;
+    for response in [&secret, &read] {
+        assert!(!response.contains("hunter2"), "Unexpected response: {}", response);
+        assert!(response.contains(r#""API_TOKEN":"<redacted>""#), "Unexpected response: {}", response);
+    }
+    let resent = put(&[], r#"{"command": "./api", "port": 3002, "env": {"API_TOKEN": "<redacted>"}}"#).await;
+    assert!(resent.contains(r#""action":"unchanged""#), "Unexpected response: {}", resent);
+    assert_eq!(manager.get_config("api.local").unwrap().env["API_TOKEN"], "hunter2");
+
+    // Invalid definitions and backends from the configuration are rejected
+    let invalid = put(&[], r#"{"command": "./api", "port": "http"}"#).await;
+    assert!(invalid.starts_with("HTTP/1.1 400"), "Unexpected response: {}", invalid);
+    let response = admin_request(admin_port, "DELETE", "/backends/static.local").await;
+    assert!(response.starts_with("HTTP/1.1 409"), "Unexpected response: {}", response);
+    let response = admin_request(admin_port, "GET", "/backends/static.local").await;
+    assert!(response.contains(r#""source":"config""#), "Unexpected response: {}", response);
+
+    // Validated together with the other backends, which it may refer to
+    let canary = |body: &'static str| admin_request_with_body(admin_port, "PUT", "/backends/canary.local", body);
+    let response = canary(r#"{"command": "./api", "port": 3003, "fallback": "static.local"}"#).await;
+    assert!(response.starts_with("HTTP/1.1 201"), "Unexpected response: {}", response);
+    let response = canary(r#"{"command": "./api", "port": 3003, "fallback": "missing.local"}"#).await;
+    assert!(response.starts_with("HTTP/1.1 400"), "Unexpected response: {}", response);
+    assert!(response.contains("unknown fallback backend 'missing.local'"), "Unexpected response: {}", response);
+    let response = canary(r#"{"command": "./api", "port": 3003, "readiness": {"depends_on": ["api.local"]}}"#).await;
+    assert!(response.starts_with("HTTP/1.1 200"), "Unexpected response: {}", response);
+    let cycle = put(&[], r#"{"command": "./api", "port": 3002, "readiness": {"depends_on": ["canary.local"]}}"#).await;
+    assert!(cycle.starts_with("HTTP/1.1 400"), "Unexpected response: {}", cycle);
+    assert!(cycle.contains("depends_on forms a cycle"), "Unexpected response: {}", cycle);
+    let response = admin_request(admin_port, "DELETE", "/backends/canary.local").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "Unexpected response: {}", response);
+
+    // Kept by a reload of the configuration
+    gate.reload(Config {
+        server,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    assert!(manager.has_backend("api.local"));
+    assert!(!manager.has_backend("static.local"));
+
+    let deleted = admin_request(admin_port, "DELETE", "/backends/api.local").await;
+    assert!(deleted.starts_with("HTTP/1.1 200"), "Unexpected response: {}", deleted);
+    assert!(deleted.contains(r#""action":"deleted""#), "Unexpected response: {}", deleted);
+    assert!(!manager.has_backend("api.local"));
+    let response = admin_request(admin_port, "DELETE", "/backends/api.local").await;
+    assert!(response.starts_with("HTTP/1.1 404"), "Unexpected response: {}", response);
+
+    gate.stop().await;
+}
+
+#[tokio::test]
+async fn test_effective_config() {
+    let server = ServerConfig {
+        bind: "127.0.0.1".to_string(),
+        port: free_port(),
+        admin_port: 0,
+        admin_token: Some("test-token".to_string()),
+        ..Default::default()
+    };
+    let mut web = BackendConfig::local("./web", 3000);
+    web.idle_timeout_secs = Some(600);
+    let gate = Spawngate::builder()
+        .server(server)
+        .defaults(BackendDefaults {
+            request_timeout_secs: 45,
+            ..Default::default()
+        })
+        .backend("web.local", web)
+        .start()
+        .await
+        .unwrap();
+    let admin_port = gate.admin_addr().port();
+
+    let body = r#"{"command": "./api", "port": 3001}"#;
+    let response = admin_request_with_body(admin_port, "PUT", "/backends/api.local", body).await;
+    assert!(response.starts_with("HTTP/1.1 201"), "Unexpected response: {}", response);
+    let body = r#"{"DATABASE_PASSWORD":"hunter2","MODE":"fast"}"#;
+    let response = admin_request_with_body(admin_port, "PUT", "/apps/web.local/config", body).await;
+    assert!(response.contains("200 OK"), "Unexpected response: {}", response);
+
+    let response = admin_request(admin_port, "GET", "/config/effective").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "Unexpected response: {}", response);
+    let body = response.split("\r\n\r\n").nth(1).unwrap();
+    let effective: serde_json::Value = serde_json::from_str(body).unwrap();
+    let web = &effective["backends"]["web.local"];
+    assert_eq!(web["idle_timeout_secs"], 600);
+    assert_eq!(web["request_timeout_secs"], 45);
+    assert_eq!(web["env"]["MODE"], "fast");
+    assert_eq!(web["env"]["DATABASE_PASSWORD"], "<redacted>");
+    assert_eq!(effective["backends"]["api.local"]["request_timeout_secs"], 45);
+    assert_eq!(effective["server"]["admin_token"], "<redacted>");
+
+    gate.stop().await;
+}
+
+#[tokio::test]
+async fn test_apply_api() {
+    let server = ServerConfig {
+        bind: "127.0.0.1".to_string(),
+        port: free_port(),
+        admin_port: 0,
+        admin_token: Some("test-token".to_string()),
+        ..Default::default()
+    };
+    let gate = Spawngate::builder()
+        .server(server)
+        .backend("web.local", BackendConfig::local("./web", 3000))
+        .backend("old.local", BackendConfig::local("./old", 3001))
+        .start()
+        .await
+        .unwrap();
+    let admin_port = gate.admin_addr().port();
+    let manager = Arc::clone(gate.process_manager());
+    let body = r#"{"command": "./api", "port": 3002}"#;
+    let response = admin_request_with_body(admin_port, "PUT", "/backends/api.local", body).await;
+    assert!(response.starts_with("HTTP/1.1 201"), "Unexpected response: {}", response);
+
+    let desired = r#"
+[defaults]
+request_timeout_secs = 45
+
+[backends."web.local"]
+command = "./web"
+port = 3000
+
+[backends."new.local"]
+command = "./new"
+port = 3003
+"#;
+
+    // A dry run only shows the plan
+    let planned = admin_request_with_body(admin_port, "POST", "/apply?dry_run=true", desired).await;
+    assert!(planned.starts_with("HTTP/1.1 200"), "Unexpected response: {}", planned);
+    let plan: serde_json::Value = serde_json::from_str(planned.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+    assert_eq!(plan["applied"], false);
+    assert_eq!(plan["plan"]["add"], serde_json::json!(["new.local"]));
+    assert_eq!(plan["plan"]["remove"], serde_json::json!(["api.local", "old.local"]));
+    assert_eq!(
+        plan["plan"]["update"],
+        serde_json::json!([{
+            "hostname": "web.local",
+            "changes": [{"field": "request_timeout_secs", "old": 30, "new": 45}],
+            "restart": false
+        }])
+    );
+    assert!(manager.has_backend("old.local"));
+    let etag = planned
+        .lines()
+        .find_map(|line| line.strip_prefix("etag: "))
+        .expect("ETag header")
+        .to_string();
+
+    // Applied only while the plan is the one reviewed
+    let stale_etag = [("If-Match", "\"0000000000000000\"")];
+    let stale = admin_request_with_headers(admin_port, "POST", "/apply", &stale_etag, desired).await;
+    assert!(stale.starts_with("HTTP/1.1 412"), "Unexpected response: {}", stale);
+    assert!(manager.has_backend("old.local"));
+    let applied = admin_request_with_headers(admin_port, "POST", "/apply", &[("If-Match", &etag)], desired).await;
+    assert!(applied.starts_with("HTTP/1.1 200"), "Unexpected response: {}", applied);
+    assert!(applied.contains(r#""applied":true"#), "Unexpected response: {}", applied);
+    assert!(manager.has_backend("new.local"));
+    assert!(!manager.has_backend("old.local"));
+    assert!(!manager.has_backend("api.local"));
+    assert_eq!(manager.get_defaults().request_timeout_secs, 45);
+
+    let again = admin_request_with_body(admin_port, "POST", "/apply?dry_run=true", desired).await;
+    assert!(again.contains(r#""add":[],"defaults":[],"remove":[],"update":[]"#), "Unexpected response: {}", again);
+    let invalid = admin_request_with_body(admin_port, "POST", "/apply", "[backends.x]\nport = \"http\"").await;
+    assert!(invalid.starts_with("HTTP/1.1 400"), "Unexpected response: {}", invalid);
+
+    gate.stop().await;
+}
+
+/// Fake Consul/etcd KV API answering every request with the current body,
+/// recording `METHOD path` for each
+async fn fake_kv_store(body: Arc<parking_lot::Mutex<String>>) -> (u16, Arc<parking_lot::Mutex<Vec<String>>>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let requests = Arc::new(parking_lot::Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&requests);
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let body = body.lock().clone();
+            let recorded = Arc::clone(&recorded);
+            tokio::spawn(async move {
+                let mut buf = [0u8; 4096];
+                let n = stream.read(&mut buf).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..n]).to_string();
+                let mut parts = request.split(' ');
+                recorded
+                    .lock()
+                    .push(format!("{} {}", parts.next().unwrap_or_default(), parts.next().unwrap_or_default()));
+                let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+                let _ = stream.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+    (port, requests)
+}
+
+#[tokio::test]
+async fn test_kv_discovery() {
+    let encode = |value: &str| base64::Engine::encode(&base64::engine::general_purpose::STANDARD, value);
+    let api = encode(r#"{"command": "./api", "port": 3001}"#);
+
+    // Consul lists the keys under the prefix, folders without a value
+    let consul_body = Arc::new(parking_lot::Mutex::new(format!(
+        r#"[{{"Key":"spawngate/backends/","Value":null}},{{"Key":"spawngate/backends/api.local","Value":"{}"}}]"#,
+        api
+    )));
+    let (consul_port, consul_requests) = fake_kv_store(Arc::clone(&consul_body)).await;
+    let server = ServerConfig {
+        bind: "127.0.0.1".to_string(),
+        port: free_port(),
+        admin_port: 0,
+        kv: KvConfig {
+            enabled: true,
+            address: Some(format!("http://127.0.0.1:{}", consul_port)),
+            interval_secs: 1,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let gate = Spawngate::builder().server(server).start().await.unwrap();
+    let manager = Arc::clone(gate.process_manager());
+    assert!(manager.has_backend("api.local"));
+    assert_eq!(consul_requests.lock()[0], "GET /v1/kv/spawngate/backends/?recurse=true");
+
+    // Removing the key stops routing to the backend
+    *consul_body.lock() = "[]".to_string();
+    for _ in 0..60 {
+        if !manager.has_backend("api.local") {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert!(!manager.has_backend("api.local"));
+    gate.stop().await;
+
+    // etcd returns base64 keys and values for the prefix's range
+    let etcd_body = Arc::new(parking_lot::Mutex::new(format!(
+        r#"{{"header":{{"revision":"7"}},"kvs":[{{"key":"{}","value":"{}"}},{{"key":"{}","value":"{}"}}]}}"#,
+        encode("apps/jobs.local"),
+        encode(r#"{"command": "./jobs", "port": 3002}"#),
+        encode("apps/old/api.local"),
+        api
+    )));
+    let (etcd_port, etcd_requests) = fake_kv_store(etcd_body).await;
+    let server = ServerConfig {
+        bind: "127.0.0.1".to_string(),
+        port: free_port(),
+        admin_port: 0,
+        kv: KvConfig {
+            enabled: true,
+            store: KvStore::Etcd,
+            address: Some(format!("http://127.0.0.1:{}", etcd_port)),
+            prefix: "apps/".to_string(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let gate = Spawngate::builder().server(server).start().await.unwrap();
+    let manager = Arc::clone(gate.process_manager());
+    assert!(manager.has_backend("jobs.local"));
+    assert!(!manager.has_backend("api.local"));
+    assert_eq!(etcd_requests.lock()[0], "POST /v3/kv/range");
+    gate.stop().await;
+}
+
+/// Shutdown must let in-flight requests finish before stopping backends,
+/// and tell keep-alive clients the connection is closing
+#[tokio::test]
+async fn test_shutdown_drains_before_stopping_backends() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    let server = ServerConfig {
+        bind: "127.0.0.1".to_string(),
+        port: free_port(),
+        admin_port: 0,
+        admin_token: Some("test-token".to_string()),
+        listener_drain_timeout_secs: 10,
+        ..Default::default()
+    };
+    let gate = Spawngate::builder()
+        .server(server)
+        .backend("app.local", mock_backend_config(free_port()))
+        .start()
+        .await
+        .unwrap();
+    let proxy_port = gate.http_addr().unwrap().port();
+    let manager = Arc::clone(gate.process_manager());
+
+    let response = http_get_with_host(proxy_port, "/echo", "app.local").await.unwrap();
+    assert!(response.contains("200 OK"), "Unexpected response: {}", response);
+
+    // A keep-alive request still waiting on the backend when shutdown starts
+    let mut stream = TcpStream::connect(("127.0.0.1", proxy_port)).await.unwrap();
+    stream
+        .write_all(b"GET /slow HTTP/1.1\r\nHost: app.local\r\n\r\n")
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let stopping = tokio::spawn(gate.stop());
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(manager.get_state("app.local"), BackendState::Ready);
+
+    // The connection closes once the response is sent
+    let mut response = String::new();
+    tokio::time::timeout(Duration::from_secs(5), stream.read_to_string(&mut response))
+        .await
+        .expect("connection left open")
+        .unwrap();
+    assert!(response.contains("200 OK"), "Unexpected response: {}", response);
+    assert!(response.contains("slow response"), "Unexpected response: {}", response);
+    assert!(response.to_lowercase().contains("connection: close"), "Unexpected response: {}", response);
+
+    stopping.await.unwrap();
+    assert_eq!(manager.get_state("app.local"), BackendState::Stopped);
+}
+
+/// Fake Consul/Nomad agent recording `METHOD path body` for every request
+///
+/// Nomad reports a single running allocation of the `web` group, on `task_port`.
+async fn fake_hashicorp_agent(task_port: u16) -> (u16, Arc<parking_lot::Mutex<Vec<String>>>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let requests = Arc::new(parking_lot::Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&requests);
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let recorded = Arc::clone(&recorded);
+            tokio::spawn(async move {
+                let mut buf = Vec::new();
+                let mut chunk = [0u8; 4096];
+                // Read the head, then as much body as Content-Length says
+                let request = loop {
+                    let n = stream.read(&mut chunk).await.unwrap_or(0);
+                    if n == 0 {
+                        return;
+                    }
+                    buf.extend_from_slice(&chunk[..n]);
+                    let text = String::from_utf8_lossy(&buf).to_string();
+                    let Some((head, body)) = text.split_once("\r\n\r\n") else {
+                        continue;
+                    };
+                    let length = head
+                        .lines()
+                        .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length: ").map(str::to_string))
+                        .and_then(|len| len.trim().parse::<usize>().ok())
+                        .unwrap_or(0);
+                    if body.len() >= length {
+                        let request_line = head.lines().next().unwrap_or_default();
+                        let mut parts = request_line.split(' ');
+                        break format!("{} {} {}", parts.next().unwrap_or_default(), parts.next().unwrap_or_default(), body);
+                    }
+                };
+                let body = if request.starts_with("GET /v1/job/") {
+                    r#"[{"ID":"old","TaskGroup":"web","ClientStatus":"complete"},
+                        {"ID":"alloc 1","TaskGroup":"web","ClientStatus":"running"}]"#
+                        .to_string()
+                } else if request.starts_with("GET /v1/allocation/") {
+                    let ports = format!(r#"[{{"Label":"http","Value":{},"HostIP":"127.0.0.1"}}]"#, task_port);
+                    format!(r#"{{"AllocatedResources":{{"Shared":{{"Ports":{}}}}}}}"#, ports)
+                } else {
+                    "{}".to_string()
+                };
+                recorded.lock().push(request);
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+    (port, requests)
+}
+
+#[tokio::test]
+async fn test_consul_registration_and_nomad_backend() {
+    if !mock_server_path().exists() {
+        eprintln!("Skipping test: mock server not built");
+        return;
+    }
+
+    // Stands in for the task Nomad places once the group is scaled up
+    let task_port = free_port();
+    let mut task = tokio::process::Command::new(mock_server_path())
+        .env("PORT", task_port.to_string())
+        .kill_on_drop(true)
+        .spawn()
+        .unwrap();
+    assert!(wait_for_port(task_port, Duration::from_secs(5)).await);
+    let (agent_port, requests) = fake_hashicorp_agent(task_port).await;
+
+    // The proxy relays its own port to the allocation's
+    let mut backend = mock_backend_config(free_port());
+    backend.backend_type = BackendType::Nomad;
+    backend.command = None;
+    backend.nomad = Some(NomadConfig {
+        address: format!("http://127.0.0.1:{}", agent_port),
+        job: "app".to_string(),
+        group: Some("web".to_string()),
+        namespace: Some("team/a".to_string()),
+        port_label: Some("http".to_string()),
+        token: Some("nomad-token".to_string()),
+    });
+    let server = ServerConfig {
+        bind: "127.0.0.1".to_string(),
+        port: free_port(),
+        admin_port: 0,
+        consul: ConsulConfig {
+            enabled: true,
+            address: format!("http://127.0.0.1:{}", agent_port),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let gate = Spawngate::builder().server(server).backend("app.local", backend).start().await.unwrap();
+    let proxy_port = gate.http_addr().unwrap().port();
+
+    {
+        let requests = requests.lock();
+        assert_eq!(requests.len(), 1, "{:?}", requests);
+        assert!(requests[0].starts_with("PUT /v1/agent/service/register "), "{:?}", requests);
+        assert!(requests[0].contains(r#""ID":"spawngate-app.local""#), "{:?}", requests);
+        assert!(requests[0].contains(&format!(r#""Port":{}"#, proxy_port)), "{:?}", requests);
+    }
+
+    let response = http_get_with_host(proxy_port, "/echo", "app.local").await.unwrap();
+    assert!(response.contains("echo response"), "Unexpected response: {}", response);
+    {
+        let requests = requests.lock();
+        assert!(requests[1].starts_with("POST /v1/job/app/scale?namespace=team%2Fa "), "{:?}", requests);
+        assert!(requests[1].contains(r#""Count":1"#), "{:?}", requests);
+        assert!(requests[1].contains(r#""Group":"web""#), "{:?}", requests);
+        assert!(requests[2].starts_with("GET /v1/job/app/allocations?namespace=team%2Fa "), "{:?}", requests);
+        assert!(requests[3].starts_with("GET /v1/allocation/alloc%201?namespace=team%2Fa "), "{:?}", requests);
+    }
+
+    gate.stop().await;
+    let requests: Vec<String> = requests.lock().iter().filter(|r| !r.starts_with("GET ")).cloned().collect();
+    assert_eq!(requests.len(), 4, "{:?}", requests);
+    assert!(requests[2].starts_with("PUT /v1/agent/service/deregister/spawngate-app.local "), "{:?}", requests);
+    assert!(requests[3].starts_with("POST /v1/job/app/scale?namespace=team%2Fa "), "{:?}", requests);
+    assert!(requests[3].contains(r#""Count":0"#), "{:?}", requests);
+    let _ = task.kill().await;
+}
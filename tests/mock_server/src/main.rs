@@ -183,6 +183,11 @@ async fn handle_connection(mut stream: tokio::net::TcpStream) {
             ("200 OK", "slow response".to_string())
         }
         "/error" => ("500 Internal Server Error", "error".to_string()),
+        // /env/<NAME> returns an environment variable of this process
+        _ if path.starts_with("/env/") => {
+            let name = &path["/env/".len()..];
+            ("200 OK", std::env::var(name).unwrap_or_default())
+        }
         _ => {
             let uptime = get_uptime();
             ("200 OK", format!("Hello! Uptime: {:.1}s", uptime.as_secs_f64()))
@@ -195,17 +200,33 @@ async fn handle_connection(mut stream: tokio::net::TcpStream) {
         "text/plain"
     };
 
+    // /accel/<path> and /accel/@<host>/<path> answer with X-Accel-Redirect,
+    // /sendfile/<abs path> with X-Sendfile
+    let extra_headers = if let Some(target) = path.strip_prefix("/accel") {
+        let target = match target.strip_prefix("/@") {
+            Some(backend) => format!("@{}", backend),
+            None => target.to_string(),
+        };
+        format!("X-Accel-Redirect: {}\r\nContent-Disposition: attachment\r\n", target)
+    } else if let Some(file) = path.strip_prefix("/sendfile") {
+        format!("X-Sendfile: {}\r\n", file)
+    } else {
+        String::new()
+    };
+
     let response = format!(
         "HTTP/1.1 {}\r\n\
          Content-Type: {}\r\n\
          Content-Length: {}\r\n\
          Connection: close\r\n\
          X-Mock-Server: true\r\n\
+         {}\
          \r\n\
          {}",
         status,
         content_type,
         body.len(),
+        extra_headers,
         body
     );
 
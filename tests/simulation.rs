@@ -0,0 +1,586 @@
+//! Deterministic simulations of the backend lifecycle
+//!
+//! The process manager runs on a paused tokio clock against simulated
+//! backends: a spawner that only keeps track of instances and a health probe
+//! that answers from the simulation's state. Idle timeouts, health checks and
+//! restarts play out in virtual time, so an hour of traffic takes
+//! milliseconds, and a seed makes the same choices on every run. Each test
+//! runs many seeds and checks the lifecycle's invariants after each.
+//!
+//! Set `SPAWNGATE_SIM_SEED` to run a single seed, e.g. one a failure reported.
+
+use futures::future::{BoxFuture, FutureExt};
+use parking_lot::Mutex;
+use spawngate::config::{BackendConfig, BackendDefaults};
+use spawngate::process::{BackendEvent, BackendState, HealthProbe, ProcessManager, ReloadResult, Spawner};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::{sleep, Instant};
+
+/// Seeds each simulation runs unless `SPAWNGATE_SIM_SEED` is set
+const SEEDS: u64 = 24;
+
+/// Spawner name of simulated backends
+const SPAWNER: &str = "sim";
+
+/// Failed health checks before a ready backend is unhealthy
+const UNHEALTHY_THRESHOLD: u32 = 3;
+
+/// Backends a reload configured and what it reported
+type Reload = (BTreeSet<String>, ReloadResult);
+
+/// Small xorshift generator, so a seed makes the same choices on every run
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Uniform in `low..high`
+    fn range(&mut self, low: u64, high: u64) -> u64 {
+        low + self.next() % (high - low)
+    }
+
+    fn millis(&mut self, low: u64, high: u64) -> Duration {
+        Duration::from_millis(self.range(low, high))
+    }
+
+    fn chance(&mut self, percent: u64) -> bool {
+        self.range(0, 100) < percent
+    }
+}
+
+/// Something that happened during a simulation
+#[derive(Debug, Clone)]
+enum Entry {
+    /// A health check and whether it passed
+    Probe { hostname: String, healthy: bool },
+    Event(BackendEvent),
+}
+
+/// A running instance of a simulated backend
+struct Instance {
+    hostname: String,
+    /// When it starts passing health checks
+    booted: Instant,
+    /// Simulated requests it is serving
+    requests: usize,
+}
+
+/// State of the simulated backends, shared by the spawner and the probe
+struct World {
+    epoch: Instant,
+    /// Running instances by instance ID
+    instances: HashMap<String, Instance>,
+    /// Backends whose health checks fail even once booted
+    failing: HashSet<String>,
+    /// Health checks and lifecycle events, in order
+    log: Vec<(Duration, Entry)>,
+    violations: Vec<String>,
+}
+
+impl World {
+    fn new() -> Self {
+        Self {
+            epoch: Instant::now(),
+            instances: HashMap::new(),
+            failing: HashSet::new(),
+            log: Vec::new(),
+            violations: Vec::new(),
+        }
+    }
+
+    fn record(&mut self, entry: Entry) {
+        self.log.push((self.epoch.elapsed(), entry));
+    }
+
+    fn violation(&mut self, message: String) {
+        let at = self.epoch.elapsed();
+        self.violations.push(format!("at {:?}: {}", at, message));
+    }
+
+    /// ID of the backend's running instance
+    fn running(&self, hostname: &str) -> Option<String> {
+        self.instances
+            .iter()
+            .find(|(_, instance)| instance.hostname == hostname)
+            .map(|(id, _)| id.clone())
+    }
+
+    /// Lifecycle events of a backend, oldest first
+    fn events(&self, hostname: &str) -> Vec<(Duration, BackendEvent)> {
+        self.log
+            .iter()
+            .filter_map(|(at, entry)| match entry {
+                Entry::Event(event) if event.hostname() == hostname => Some((*at, event.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Launches instances that pass health checks once booted
+struct SimSpawner {
+    world: Arc<Mutex<World>>,
+    rng: Mutex<Rng>,
+}
+
+impl Spawner for SimSpawner {
+    fn start<'a>(
+        &'a self,
+        hostname: &'a str,
+        _config: &'a BackendConfig,
+        env: &'a [(String, String)],
+    ) -> BoxFuture<'a, anyhow::Result<String>> {
+        let id = env.iter().find(|(k, _)| k == "INSTANCE_ID").map(|(_, v)| v.clone()).unwrap_or_default();
+        let (launch, boot) = {
+            let mut rng = self.rng.lock();
+            (rng.millis(0, 1000), rng.millis(200, 3000))
+        };
+        async move {
+            sleep(launch).await;
+            let mut world = self.world.lock();
+            if let Some(other) = world.running(hostname) {
+                world.violation(format!("{} started while instance {} is still running", hostname, other));
+            }
+            let instance = Instance {
+                hostname: hostname.to_string(),
+                booted: Instant::now() + boot,
+                requests: 0,
+            };
+            world.instances.insert(id.clone(), instance);
+            Ok(id)
+        }
+        .boxed()
+    }
+
+    fn stop<'a>(&'a self, hostname: &'a str, id: &'a str, _grace_period: Duration) -> BoxFuture<'a, ()> {
+        async move {
+            {
+                let mut world = self.world.lock();
+                match world.instances.get(id).map(|instance| instance.requests) {
+                    None => world.violation(format!("{} stopped unknown instance {}", hostname, id)),
+                    Some(0) => {}
+                    Some(requests) => {
+                        world.violation(format!("{} stopped with {} requests in flight", hostname, requests))
+                    }
+                }
+            }
+            // Still holds its port while shutting down
+            sleep(Duration::from_millis(200)).await;
+            self.world.lock().instances.remove(id);
+        }
+        .boxed()
+    }
+}
+
+/// Passes health checks of booted instances of backends that aren't failing
+struct SimProbe {
+    world: Arc<Mutex<World>>,
+}
+
+impl HealthProbe for SimProbe {
+    fn check<'a>(&'a self, hostname: &'a str, _url: &'a str) -> BoxFuture<'a, anyhow::Result<bool>> {
+        let mut world = self.world.lock();
+        let booted = world
+            .running(hostname)
+            .is_some_and(|id| world.instances[&id].booted <= Instant::now());
+        let healthy = booted && !world.failing.contains(hostname);
+        world.record(Entry::Probe { hostname: hostname.to_string(), healthy });
+        async move { Ok(healthy) }.boxed()
+    }
+}
+
+/// A process manager driving simulated backends
+struct Sim {
+    seed: u64,
+    manager: Arc<ProcessManager>,
+    world: Arc<Mutex<World>>,
+    /// Whether requests turned away by a backend shutting down are retried,
+    /// as clients do after a 503, rather than failed
+    retry_shutting_down: bool,
+}
+
+impl Sim {
+    fn new(seed: u64, hostnames: &[&str]) -> Arc<Self> {
+        Self::with_configs(seed, configs(hostnames, 30), true)
+    }
+
+    fn with_configs(seed: u64, configs: HashMap<String, BackendConfig>, retry_shutting_down: bool) -> Arc<Self> {
+        let world = Arc::new(Mutex::new(World::new()));
+        let manager = ProcessManager::new(configs, BackendDefaults::default(), String::new());
+        manager.register_spawner(
+            SPAWNER,
+            Arc::new(SimSpawner {
+                world: Arc::clone(&world),
+                rng: Mutex::new(Rng::new(!seed)),
+            }),
+        );
+        manager.set_health_probe(Arc::new(SimProbe { world: Arc::clone(&world) }));
+        let events = Arc::clone(&world);
+        manager.on_event(move |event| events.lock().record(Entry::Event(event.clone())));
+        Arc::new(Self {
+            seed,
+            manager,
+            world,
+            retry_shutting_down,
+        })
+    }
+
+    /// Send a request the way the proxy does: start the backend if needed,
+    /// wait until it is ready, then keep it busy for `duration`
+    async fn request(&self, hostname: &str, duration: Duration) -> Result<(), String> {
+        let deadline = Instant::now() + Duration::from_secs(120);
+        self.manager.touch(hostname);
+        while Instant::now() < deadline {
+            match self.manager.get_state(hostname) {
+                BackendState::Ready if self.manager.increment_in_flight(hostname) => {
+                    let id = self.manager.instance(hostname).map(|instance| instance.id).unwrap_or_default();
+                    self.serve(&id, true);
+                    sleep(duration).await;
+                    self.serve(&id, false);
+                    self.manager.decrement_in_flight(hostname);
+                    return Ok(());
+                }
+                // Held until the new instance is ready
+                BackendState::Stopping if self.manager.is_restarting(hostname) => {
+                    self.manager.start_backend(hostname).await.map_err(|e| e.to_string())?;
+                }
+                BackendState::Stopping if self.retry_shutting_down => {
+                    sleep(Duration::from_millis(500)).await;
+                    self.manager.start_backend(hostname).await.map_err(|e| e.to_string())?;
+                }
+                BackendState::Stopping => return Err(format!("{} is shutting down", hostname)),
+                BackendState::Stopped => self.manager.start_backend(hostname).await.map_err(|e| e.to_string())?,
+                BackendState::Unhealthy => return Err(format!("{} is unhealthy", hostname)),
+                _ => sleep(Duration::from_millis(100)).await,
+            }
+        }
+        Err(format!("{} did not become ready", hostname))
+    }
+
+    /// Count a request against the instance it was admitted to
+    fn serve(&self, id: &str, started: bool) {
+        let mut world = self.world.lock();
+        match world.instances.get_mut(id) {
+            Some(instance) if started => instance.requests += 1,
+            Some(instance) => instance.requests -= 1,
+            None => world.violation(format!("instance {} is gone while serving a request", id)),
+        }
+    }
+
+    fn violation(&self, message: String) {
+        self.world.lock().violation(message);
+    }
+
+    /// Stop every backend, then fail with the seed if an invariant was violated
+    async fn finish(&self) {
+        self.manager.stop_all().await;
+        let world = self.world.lock();
+        let mut violations = world.violations.clone();
+        violations.extend(
+            world
+                .instances
+                .iter()
+                .map(|(id, instance)| format!("instance {} of {} still running after stop_all", id, instance.hostname)),
+        );
+        assert!(violations.is_empty(), "seed {}:\n{}", self.seed, violations.join("\n"));
+    }
+}
+
+/// A simulated backend with lifecycle timings small enough to exercise often
+fn backend(port: u16, idle_timeout_secs: u64) -> BackendConfig {
+    let mut config = BackendConfig::custom(SPAWNER, port);
+    config.health_check_interval_ms = Some(100);
+    config.ready_health_check_interval_ms = Some(1000);
+    config.unhealthy_threshold = Some(UNHEALTHY_THRESHOLD);
+    config.startup_timeout_secs = Some(30);
+    config.idle_timeout_secs = Some(idle_timeout_secs);
+    config.drain_timeout_secs = Some(10);
+    config.shutdown_grace_period_secs = Some(1);
+    config
+}
+
+fn configs(hostnames: &[&str], idle_timeout_secs: u64) -> HashMap<String, BackendConfig> {
+    hostnames
+        .iter()
+        .enumerate()
+        .map(|(i, hostname)| (hostname.to_string(), backend(7001 + i as u16, idle_timeout_secs)))
+        .collect()
+}
+
+/// Run `simulation` for every seed, each on a fresh runtime with a paused clock
+fn simulate<F, Fut>(simulation: F)
+where
+    F: Fn(u64) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let seeds = match std::env::var("SPAWNGATE_SIM_SEED") {
+        Ok(seed) => {
+            let seed: u64 = seed.parse().expect("SPAWNGATE_SIM_SEED must be a number");
+            seed..seed + 1
+        }
+        Err(_) => 0..SEEDS,
+    };
+    for seed in seeds {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .start_paused(true)
+            .build()
+            .unwrap();
+        runtime.block_on(simulation(seed));
+    }
+}
+
+/// Health alternates between passing and failing for stretches both shorter
+/// and longer than the unhealthy threshold
+#[test]
+fn test_flapping_health() {
+    simulate(|seed| async move {
+        let hostname = "app.local";
+        let sim = Sim::new(seed, &[hostname]);
+        let mut rng = Rng::new(seed);
+
+        let end = Instant::now() + Duration::from_secs(600);
+        while Instant::now() < end {
+            // A backend that timed out starting is started again by the next request
+            if sim.manager.get_state(hostname) == BackendState::Stopped {
+                sim.manager.start_backend(hostname).await.unwrap();
+            }
+            sleep(rng.millis(500, 8000)).await;
+            let mut world = sim.world.lock();
+            if !world.failing.remove(hostname) {
+                world.failing.insert(hostname.to_string());
+            }
+        }
+
+        // Once health is stable again the backend ends up ready
+        sim.world.lock().failing.clear();
+        sleep(Duration::from_secs(40)).await;
+        if sim.manager.get_state(hostname) == BackendState::Stopped {
+            sim.manager.start_backend(hostname).await.unwrap();
+        }
+        sleep(Duration::from_secs(10)).await;
+        if sim.manager.get_state(hostname) != BackendState::Ready {
+            sim.violation(format!("{} is {:?} after health recovered", hostname, sim.manager.get_state(hostname)));
+        }
+
+        let mut violations = Vec::new();
+        {
+            let world = sim.world.lock();
+            let mut probes: Vec<bool> = Vec::new();
+            for (at, entry) in &world.log {
+                match entry {
+                    Entry::Probe { hostname: probed, healthy } if probed == hostname => probes.push(*healthy),
+                    Entry::Probe { .. } => {}
+                    // Only after enough consecutive failures
+                    Entry::Event(BackendEvent::Unhealthy { .. }) => {
+                        let last = &probes[probes.len().saturating_sub(UNHEALTHY_THRESHOLD as usize)..];
+                        if last.len() < UNHEALTHY_THRESHOLD as usize || last.iter().any(|healthy| *healthy) {
+                            violations.push(format!("at {:?}: unhealthy after health checks {:?}", at, last));
+                        }
+                    }
+                    // Only right after a passing health check
+                    Entry::Event(BackendEvent::Ready { .. }) => {
+                        if probes.last() != Some(&true) {
+                            violations.push(format!("at {:?}: ready after a failed health check", at));
+                        }
+                    }
+                    Entry::Event(_) => {}
+                }
+            }
+
+            // Every unhealthy backend is restarted
+            let events = world.events(hostname);
+            let unhealthy = events.iter().filter(|(_, e)| matches!(e, BackendEvent::Unhealthy { .. })).count();
+            if unhealthy == 0 {
+                violations.push("never became unhealthy".to_string());
+            }
+            for (i, (at, event)) in events.iter().enumerate() {
+                if !matches!(event, BackendEvent::Unhealthy { .. }) {
+                    continue;
+                }
+                let next: Vec<&BackendEvent> = events[i + 1..].iter().take(2).map(|(_, e)| e).collect();
+                if !matches!(next[..], [BackendEvent::Stopped { .. }, BackendEvent::Started { .. }]) {
+                    violations.push(format!("at {:?}: unhealthy backend not restarted, followed by {:?}", at, next));
+                }
+            }
+        }
+        for violation in violations {
+            sim.violation(violation);
+        }
+        sim.finish().await;
+    });
+}
+
+/// Reloads adding, removing and changing backends, some at the same time,
+/// while requests start them
+#[test]
+fn test_concurrent_reloads() {
+    simulate(|seed| async move {
+        let hostnames = ["a.local", "b.local", "c.local", "d.local"];
+        let sim = Sim::new(seed, &hostnames);
+        let mut rng = Rng::new(seed);
+        let reloads: Arc<Mutex<Vec<Reload>>> = Arc::default();
+
+        let mut tasks = Vec::new();
+        for _ in 0..40 {
+            for _ in 0..rng.range(0, 4) {
+                let (hostname, duration) = (hostnames[rng.range(0, 4) as usize], rng.millis(0, 3000));
+                let sim = Arc::clone(&sim);
+                tasks.push(tokio::spawn(async move {
+                    // Fails for backends removed in the meantime
+                    let _ = sim.request(hostname, duration).await;
+                }));
+            }
+            for _ in 0..rng.range(1, 3) {
+                let configured: Vec<&str> = hostnames.iter().copied().filter(|_| rng.chance(70)).collect();
+                let idle_timeout_secs = rng.range(5, 60);
+                let (sim, reloads) = (Arc::clone(&sim), Arc::clone(&reloads));
+                tasks.push(tokio::spawn(async move {
+                    let configs = configs(&configured, idle_timeout_secs);
+                    let result = sim.manager.apply_config(configs, BackendDefaults::default()).await.unwrap();
+                    let configured = configured.iter().map(|h| h.to_string()).collect();
+                    reloads.lock().push((configured, result));
+                }));
+            }
+            sleep(rng.millis(0, 5000)).await;
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        // Each reload saw the backends the one before it left, in the order they finished
+        let mut configured: BTreeSet<String> = hostnames.iter().map(|h| h.to_string()).collect();
+        for (i, (next, result)) in reloads.lock().iter().enumerate() {
+            let before: BTreeSet<String> = result.removed.iter().chain(&result.updated).cloned().collect();
+            let after: BTreeSet<String> = result.added.iter().chain(&result.updated).cloned().collect();
+            if before != configured || after != *next {
+                sim.violation(format!("reload {} went from {:?} to {:?}, expected {:?}", i, before, after, configured));
+            }
+            configured = next.clone();
+        }
+        let current: BTreeSet<String> = sim.manager.get_configs().into_keys().collect();
+        if current != configured {
+            sim.violation(format!("configured {:?} after the last reload applied {:?}", current, configured));
+        }
+
+        // Backends removed while starting time out or are stopped by the idle check
+        sleep(Duration::from_secs(60)).await;
+        sim.manager.cleanup_idle_backends().await;
+        let running: Vec<String> = sim.world.lock().instances.values().map(|i| i.hostname.clone()).collect();
+        for hostname in running.iter().filter(|hostname| !current.contains(*hostname)) {
+            sim.violation(format!("{} still running after it was removed", hostname));
+        }
+        sim.finish().await;
+    });
+}
+
+/// Requests arriving around the idle timeout, while the idle check stops the
+/// backend they are for
+#[test]
+fn test_idle_shutdown_races_requests() {
+    simulate(|seed| async move {
+        let hostnames = ["a.local", "b.local"];
+        let sim = Sim::new(seed, &hostnames);
+        let mut rng = Rng::new(seed);
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let cleanup = tokio::spawn(Arc::clone(&sim.manager).run_idle_cleanup(Duration::from_secs(10), shutdown_rx));
+
+        let mut tasks = Vec::new();
+        let end = Instant::now() + Duration::from_secs(3600);
+        while Instant::now() < end {
+            sleep(rng.millis(0, 25_000)).await;
+            let hostname = hostnames[rng.range(0, 2) as usize];
+            for _ in 0..rng.range(1, 4) {
+                let (sim, duration) = (Arc::clone(&sim), rng.millis(0, 8000));
+                tasks.push(tokio::spawn(async move { sim.request(hostname, duration).await }));
+            }
+        }
+        for task in tasks {
+            if let Err(e) = task.await.unwrap() {
+                sim.violation(format!("request failed: {}", e));
+            }
+        }
+
+        // Without traffic every backend is stopped
+        sleep(Duration::from_secs(60)).await;
+        let running: Vec<String> = sim.world.lock().instances.values().map(|i| i.hostname.clone()).collect();
+        for hostname in running {
+            sim.violation(format!("{} still running after it went idle", hostname));
+        }
+        let idle_stops = hostnames
+            .iter()
+            .flat_map(|hostname| sim.world.lock().events(hostname))
+            .filter(|(_, event)| matches!(event, BackendEvent::Stopped { .. }))
+            .count();
+        if idle_stops < 10 {
+            sim.violation(format!("only {} idle shutdowns", idle_stops));
+        }
+
+        shutdown_tx.send(true).unwrap();
+        cleanup.await.unwrap();
+        sim.finish().await;
+    });
+}
+
+/// Backends under constant traffic are recycled once they reach their
+/// maximum lifetime, without failing requests
+#[test]
+fn test_max_lifetime_recycles_busy_backends() {
+    simulate(|seed| async move {
+        let hostname = "app.local";
+        let mut config = backend(7001, 30);
+        config.max_lifetime_secs = Some(120);
+        let sim = Sim::with_configs(seed, HashMap::from([(hostname.to_string(), config)]), false);
+        let mut rng = Rng::new(seed);
+
+        let mut tasks = Vec::new();
+        let end = Instant::now() + Duration::from_secs(1800);
+        while Instant::now() < end {
+            let (sim, duration) = (Arc::clone(&sim), rng.millis(0, 8000));
+            tasks.push(tokio::spawn(async move { sim.request(hostname, duration).await }));
+            sleep(rng.millis(0, 5000)).await;
+        }
+        for task in tasks {
+            if let Err(e) = task.await.unwrap() {
+                sim.violation(format!("request failed: {}", e));
+            }
+        }
+
+        // Recycled after the lifetime, waiting at most the drain timeout for a
+        // quiet moment, then draining for at most as long again
+        let limit = Duration::from_secs(120 + 10 + 10 + 2);
+        let events = sim.world.lock().events(hostname);
+        let mut started = None;
+        let mut recycled = 0;
+        for (at, event) in events {
+            match event {
+                BackendEvent::Started { .. } => started = Some(at),
+                BackendEvent::Stopped { .. } => {
+                    let lifetime = at - started.take().unwrap_or_default();
+                    if lifetime > limit {
+                        sim.violation(format!("instance stopped at {:?} ran for {:?}", at, lifetime));
+                    }
+                    recycled += 1;
+                }
+                _ => {}
+            }
+        }
+        if recycled < 10 {
+            sim.violation(format!("recycled only {} times", recycled));
+        }
+        sim.finish().await;
+    });
+}